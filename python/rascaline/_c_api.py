@@ -45,6 +45,7 @@ class rascal_pair_t(ctypes.Structure):
         ("second", c_uintptr_t),
         ("distance", ctypes.c_double),
         ("vector", ctypes.c_double * 3),
+        ("cell_shift", ctypes.c_int32 * 3),
     ]
 
 
@@ -76,6 +77,7 @@ class rascal_calculation_options_t(ctypes.Structure):
         ("selected_samples", rascal_labels_selection_t),
         ("selected_properties", rascal_labels_selection_t),
         ("selected_keys", POINTER(eqs_labels_t)),
+        ("selected_gradient_atoms", POINTER(eqs_labels_t)),
     ]
 
 
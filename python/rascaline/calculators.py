@@ -28,6 +28,7 @@ def _options_to_c(
     selected_samples,
     selected_properties,
     selected_keys,
+    selected_gradient_atoms,
 ):
     if gradients is None:
         gradients = []
@@ -94,6 +95,20 @@ def _options_to_c(
         selected_keys = selected_keys._as_eqs_labels_t()
         c_options.selected_keys = ctypes.pointer(selected_keys)
         c_options.__keepalive["selected_keys"] = selected_keys
+
+    if selected_gradient_atoms is None:
+        # nothing to do, all pointers are already NULL
+        pass
+    elif isinstance(selected_gradient_atoms, Labels):
+        selected_gradient_atoms = selected_gradient_atoms._as_eqs_labels_t()
+        c_options.selected_gradient_atoms = ctypes.pointer(selected_gradient_atoms)
+        c_options.__keepalive["selected_gradient_atoms"] = selected_gradient_atoms
+    else:
+        raise ValueError(
+            "expected selected gradient atoms to be an `equistore.Labels` "
+            f"instance, got {type(selected_gradient_atoms)} instead"
+        )
+
     return c_options
 
 
@@ -149,6 +164,7 @@ class CalculatorBase:
         selected_samples: Optional[Union[Labels, TensorMap]] = None,
         selected_properties: Optional[Union[Labels, TensorMap]] = None,
         selected_keys: Optional[Labels] = None,
+        selected_gradient_atoms: Optional[Labels] = None,
     ) -> TensorMap:
         r"""Runs a calculation with this calculator on the given ``systems``.
 
@@ -167,8 +183,8 @@ class CalculatorBase:
             an empty list ``[]``, no gradients are computed. Gradients are
             stored inside the different blocks, and can be accessed with
             ``descriptor.block(...).gradient(<parameter>)``, where
-            ``<parameter>`` is ``"positions"`` or ``"cell"``. The following
-            gradients are available:
+            ``<parameter>`` is ``"positions"``, ``"cell"``, ``"strain"`` or
+            ``"atomic_gaussian_width"``. The following gradients are available:
 
             - ``"positions"``, for gradients of the representation with respect to
               atomic positions. Positions gradients are computed as
@@ -206,6 +222,24 @@ class CalculatorBase:
                    = -\frac{\partial \langle q \vert A \rangle}
                            {\partial \mathbf{h}} \cdot \mathbf{h}
 
+            - ``"strain"``, for gradients of the representation with respect to
+              an homogeneous strain :math:`\epsilon` applied to both the atomic
+              positions and the cell. These gradients directly give the virial,
+              and are computed as
+
+              .. math::
+                  \frac{\partial \langle q \vert A_i \rangle}
+                       {\partial\epsilon}
+                   = \frac{\partial \langle q \vert A_i \rangle}
+                           {\partial \mathbf{h}} \cdot \mathbf{h}
+
+              Strain gradients are only available for some calculators.
+
+            - ``"atomic_gaussian_width"``, for gradients of the representation
+              with respect to the width of the atomic gaussian densities. This
+              is useful for hyper-parameters optimization, and only available
+              for some calculators.
+
         :param selected_samples: Set of samples on which to run the calculation.
             Use ``None`` to run the calculation on all samples in the
             ``systems`` (this is the default).
@@ -247,6 +281,14 @@ class CalculatorBase:
             If this is ``None``, the default set of keys (as determined by the
             calculator) will be used. Note that this default set of keys can
             depend on which systems we are running the calculation on.
+
+        :param selected_gradient_atoms: Selection of atoms for which to compute
+            the gradients with respect to positions, as
+            :py:class:`equistore.Labels` with the ``["structure", "atom"]``
+            names. If this is ``None``, the gradients are computed with respect
+            to all atoms (this is the default). Only the gradient samples
+            involving one of the selected atoms are created. This is only
+            supported by some calculators.
         """
 
         c_systems = _convert_systems(systems)
@@ -258,6 +300,7 @@ class CalculatorBase:
             selected_samples=selected_samples,
             selected_properties=selected_properties,
             selected_keys=selected_keys,
+            selected_gradient_atoms=selected_gradient_atoms,
         )
         self._lib.rascal_calculator_compute(
             self, tensor_map_ptr, c_systems, c_systems._length_, c_options
@@ -284,12 +327,14 @@ class AtomicComposition(CalculatorBase):
 
 
 class DummyCalculator(CalculatorBase):
-    def __init__(self, cutoff, delta, name):
+    def __init__(self, cutoff, delta, name, gradients=False):
         parameters = {
             "cutoff": cutoff,
             "delta": delta,
             "name": name,
         }
+        if gradients:
+            parameters["gradients"] = True
         super().__init__("dummy_calculator", parameters)
 
 
@@ -330,9 +375,11 @@ class SortedDistances(CalculatorBase):
     """Sorted distances vector representation of an atomic environment.
 
     Each atomic center is represented by a vector of distance to its neighbors
-    within the spherical ``cutoff``, sorted from smallest to largest. If there
-    are less neighbors than ``max_neighbors``, the remaining entries are filled
-    with ``cutoff`` instead.
+    within the spherical ``cutoff``, sorted from smallest to largest. Centers
+    with more than ``max_neighbors`` neighbors only keep the closest ones, and
+    if there are less neighbors than ``max_neighbors``, the remaining entries
+    are filled with ``pad_value`` (or ``cutoff`` if ``pad_value`` is ``None``)
+    instead.
 
     Separate species for neighbors are represented separately, meaning that the
     ``max_neighbors`` parameter only apply to a single species.
@@ -341,12 +388,18 @@ class SortedDistances(CalculatorBase):
     :ref:`documentation <sorted-distances>`.
     """
 
-    def __init__(self, cutoff, max_neighbors, separate_neighbor_species):
+    def __init__(
+        self, cutoff, max_neighbors, separate_neighbor_species, pad_value=None
+    ):
         parameters = {
             "cutoff": cutoff,
             "max_neighbors": max_neighbors,
             "separate_neighbor_species": separate_neighbor_species,
         }
+
+        if pad_value is not None:
+            parameters["pad_value"] = pad_value
+
         super().__init__("sorted_distances", parameters)
 
 
@@ -379,6 +432,8 @@ class SphericalExpansion(CalculatorBase):
         center_atom_weight,
         cutoff_function,
         radial_scaling=None,
+        all_species=None,
+        cached_directions=None,
     ):
         parameters = {
             "cutoff": cutoff,
@@ -393,6 +448,12 @@ class SphericalExpansion(CalculatorBase):
         if radial_scaling is not None:
             parameters["radial_scaling"] = radial_scaling
 
+        if all_species is not None:
+            parameters["all_species"] = list(all_species)
+
+        if cached_directions is not None:
+            parameters["cached_directions"] = cached_directions
+
         super().__init__("spherical_expansion", parameters)
 
 
@@ -525,6 +586,7 @@ class SoapPowerSpectrum(CalculatorBase):
         radial_basis,
         cutoff_function,
         radial_scaling=None,
+        all_species=None,
     ):
         parameters = {
             "cutoff": cutoff,
@@ -539,6 +601,9 @@ class SoapPowerSpectrum(CalculatorBase):
         if radial_scaling is not None:
             parameters["radial_scaling"] = radial_scaling
 
+        if all_species is not None:
+            parameters["all_species"] = list(all_species)
+
         super().__init__("soap_power_spectrum", parameters)
 
 
@@ -140,11 +140,7 @@ class SystemBase:
             """
             self = get_self(user_data)
 
-            pairs = np.asarray(
-                self.pairs(),
-                order="C",
-                dtype=rascal_pair_t,
-            )
+            pairs = _pairs_to_array(self.pairs())
 
             count[0] = c_uintptr_t(len(pairs))
             data[0] = pairs.ctypes.data
@@ -160,11 +156,7 @@ class SystemBase:
             """
             self = get_self(user_data)
 
-            pairs = np.asarray(
-                self.pairs_containing(center),
-                order="C",
-                dtype=rascal_pair_t,
-            )
+            pairs = _pairs_to_array(self.pairs_containing(center))
 
             count[0] = c_uintptr_t(len(pairs))
             data[0] = pairs.ctypes.data
@@ -230,10 +222,12 @@ class SystemBase:
         :py:func:`SystemBase.compute_neighbors`
 
         Get all neighbor pairs in this system as a list of tuples ``(int, int,
-        float, (float, float, float))`` containing the indexes of the first and
-        second atom in the pair, the distance between the atoms, and the wrapped
-        between them. Alternatively, this function can return a 1D numpy array
-        with ``dtype=rascal_pair_t``.
+        float, (float, float, float), (int, int, int))`` containing the indexes
+        of the first and second atom in the pair, the distance between the
+        atoms, the wrapped vector between them, and the number of shifts along
+        each cell vector used to wrap this vector. The cell shift can be
+        omitted, in which case it is set to ``(0, 0, 0)``. Alternatively, this
+        function can return a 1D numpy array with ``dtype=rascal_pair_t``.
 
         The list of pair should only contain each pair once (and not twice as
         ``i-j`` and ``j-i``), should not contain self pairs (``i-i``); and
@@ -260,3 +254,15 @@ class SystemBase:
         """
 
         raise NotImplementedError("System.pairs_containing method is not implemented")
+
+
+def _pairs_to_array(pairs):
+    """
+    Convert the ``pairs`` returned by :py:func:`SystemBase.pairs` or
+    :py:func:`SystemBase.pairs_containing` to a numpy array with
+    ``dtype=rascal_pair_t``, setting the cell shift to zero if it is missing.
+    """
+    if not isinstance(pairs, np.ndarray):
+        pairs = [pair if len(pair) == 5 else (*pair, (0, 0, 0)) for pair in pairs]
+
+    return np.asarray(pairs, order="C", dtype=rascal_pair_t)
@@ -145,6 +145,30 @@ class TestDummyCalculator(unittest.TestCase):
         O_block = descriptor.block(species_center=8)
         self.assertEqual(O_block.values.shape, (6, 2))
 
+    def test_selected_gradient_atoms(self):
+        system = TestSystem()
+        calculator = DummyCalculator(cutoff=3.2, delta=2, name="")
+
+        atoms = Labels(
+            names=["structure", "atom"],
+            values=np.array([[0, 1]], dtype=np.int32),
+        )
+
+        with self.assertRaises(RascalError) as cm:
+            calculator.compute(
+                system,
+                use_native_system=False,
+                gradients=["positions"],
+                selected_gradient_atoms=atoms,
+            )
+
+        self.assertEqual(
+            str(cm.exception),
+            "invalid parameter: the dummy test calculator with cutoff: 3.2 - "
+            "delta: 2 - name:  calculator does not support selecting atoms for "
+            "gradients with respect to positions",
+        )
+
 
 class TestComputePartialSamples(unittest.TestCase):
     def test_selection(self):
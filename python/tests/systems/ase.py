@@ -39,6 +39,7 @@ class TestAseSystem(unittest.TestCase):
         self.assertEqual(pairs[0][:2], (0, 1))
         self.assertEqual(pairs[0][2], 1.4)
         self.assertTrue(np.all(pairs[0][3] == [0, 0, 1.4]))
+        self.assertTrue(np.all(pairs[0][4] == [0, 0, 0]))
 
         self.system.compute_neighbors(2.5)
         pairs = self.system.pairs()
@@ -135,6 +135,13 @@ typedef struct rascal_pair_t {
    * cell as required by periodic boundary conditions.
    */
   double vector[3];
+  /**
+   * number of shifts along each of the unit cell vectors for this pair,
+   * such that `vector = positions[second] - positions[first] + cell_shift[0]
+   * * a + cell_shift[1] * b + cell_shift[2] * c`, where `a`, `b` and `c`
+   * are the unit cell vectors.
+   */
+  int32_t cell_shift[3];
 } rascal_pair_t;
 
 /**
@@ -305,6 +312,24 @@ typedef struct rascal_calculation_options_t {
    *             {\partial\epsilon}
    *         = -\frac{\partial \langle q \vert A \rangle}
    *                 {\partial \mathbf{h}} \cdot \mathbf{h}
+   *
+   * - ``"strain"``, for gradients of the representation with respect to an
+   *   homogeneous strain :math:`\epsilon` applied to both the atomic
+   *   positions and the cell. These gradients directly give the virial, and
+   *   are computed as
+   *
+   *   .. math::
+   *       \frac{\partial \langle q \vert A_i \rangle}
+   *             {\partial\epsilon}
+   *         = \frac{\partial \langle q \vert A_i \rangle}
+   *                 {\partial \mathbf{h}} \cdot \mathbf{h}
+   *
+   *   Strain gradients are only available for some calculators.
+   *
+   * - ``"atomic_gaussian_width"``, for gradients of the representation with
+   *   respect to the width of the atomic gaussian densities. This is
+   *   useful for hyper-parameters optimization, and only available for
+   *   some calculators.
    * @endverbatim
    */
   const char *const *gradients;
@@ -332,6 +357,13 @@ typedef struct rascal_calculation_options_t {
    * running the calculation on.
    */
   const eqs_labels_t *selected_keys;
+  /**
+   * Selection of atoms for which to compute the gradients with respect to
+   * positions, as labels with the `["structure", "atom"]` names. Set this
+   * parameter to `NULL` to compute gradients with respect to all atoms.
+   * This is only supported by some calculators.
+   */
+  const eqs_labels_t *selected_gradient_atoms;
 } rascal_calculation_options_t;
 
 #ifdef __cplusplus
@@ -4,7 +4,7 @@ use std::ops::{Deref, DerefMut};
 
 use equistore::{Labels, TensorMap};
 use equistore::c_api::{eqs_tensormap_t, eqs_labels_t};
-use rascaline::{Calculator, System, CalculationOptions, LabelsSelection};
+use rascaline::{Calculator, System, CalculationOptions, CellInference, LabelsSelection};
 
 use super::utils::copy_str_to_c;
 use super::{catch_unwind, rascal_status_t};
@@ -309,6 +309,24 @@ pub struct rascal_calculation_options_t {
     ///             {\partial\epsilon}
     ///         = -\frac{\partial \langle q \vert A \rangle}
     ///                 {\partial \mathbf{h}} \cdot \mathbf{h}
+    ///
+    /// - ``"strain"``, for gradients of the representation with respect to an
+    ///   homogeneous strain :math:`\epsilon` applied to both the atomic
+    ///   positions and the cell. These gradients directly give the virial, and
+    ///   are computed as
+    ///
+    ///   .. math::
+    ///       \frac{\partial \langle q \vert A_i \rangle}
+    ///             {\partial\epsilon}
+    ///         = \frac{\partial \langle q \vert A_i \rangle}
+    ///                 {\partial \mathbf{h}} \cdot \mathbf{h}
+    ///
+    ///   Strain gradients are only available for some calculators.
+    ///
+    /// - ``"atomic_gaussian_width"``, for gradients of the representation with
+    ///   respect to the width of the atomic gaussian densities. This is
+    ///   useful for hyper-parameters optimization, and only available for
+    ///   some calculators.
     /// @endverbatim
     gradients: *const *const c_char,
     /// Size of the `gradients` array
@@ -325,6 +343,11 @@ pub struct rascal_calculation_options_t {
     /// Note that this default set of keys can depend on which systems we are
     /// running the calculation on.
     selected_keys: *const eqs_labels_t,
+    /// Selection of atoms for which to compute the gradients with respect to
+    /// positions, as labels with the `["structure", "atom"]` names. Set this
+    /// parameter to `NULL` to compute gradients with respect to all atoms.
+    /// This is only supported by some calculators.
+    selected_gradient_atoms: *const eqs_labels_t,
 }
 
 #[allow(clippy::doc_markdown)]
@@ -391,12 +414,22 @@ pub unsafe extern fn rascal_calculator_compute(
         let mut selected_keys = None;
         let selected_keys = key_selection(options.selected_keys, &mut selected_keys)?;
 
+        let mut selected_gradient_atoms = None;
+        let selected_gradient_atoms = key_selection(options.selected_gradient_atoms, &mut selected_gradient_atoms)?;
+
         let rust_options = CalculationOptions {
             gradients: &gradients,
             use_native_system: options.use_native_system,
             selected_samples,
             selected_properties,
             selected_keys,
+            cell_inference: CellInference::Keep,
+            velocities: None,
+            post_process: None,
+            thread_pool: None,
+            progress_callback: None,
+            selected_gradient_atoms,
+            aggregate: None,
         };
 
         let tensor = (*calculator).compute(&mut systems, rust_options)?;
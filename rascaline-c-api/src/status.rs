@@ -58,10 +58,22 @@ pub const RASCAL_INTERNAL_ERROR: i32 = 255;
 impl From<Error> for rascal_status_t {
     #[allow(clippy::match_same_arms)]
     fn from(error: Error) -> rascal_status_t {
+        // `Display` only includes the outermost context, so we add the
+        // messages of all the wrapped errors here
+        let mut full_message = String::new();
+        let mut inner = &error;
+        while let Error::Context { context, source } = inner {
+            full_message += context;
+            full_message += ": ";
+            inner = source;
+        }
+        full_message += &inner.to_string();
+
         LAST_ERROR_MESSAGE.with(|message| {
-            *message.borrow_mut() = CString::new(format!("{}", error)).expect("error message contains a null byte");
+            *message.borrow_mut() = CString::new(full_message).expect("error message contains a null byte");
         });
-        match error {
+        // use the status of the innermost error when context was added
+        match *error.root_cause() {
             Error::InvalidParameter(_) => rascal_status_t(RASCAL_INVALID_PARAMETER_ERROR),
             Error::Json(_) => rascal_status_t(RASCAL_JSON_ERROR),
             Error::Utf8(_) => rascal_status_t(RASCAL_UTF8_ERROR),
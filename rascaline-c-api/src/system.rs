@@ -21,6 +21,11 @@ pub struct rascal_pair_t {
     /// vector from the first atom to the second atom, wrapped inside the unit
     /// cell as required by periodic boundary conditions.
     pub vector: [f64; 3],
+    /// number of shifts along each of the unit cell vectors for this pair,
+    /// such that `vector = positions[second] - positions[first] + cell_shift[0]
+    /// * a + cell_shift[1] * b + cell_shift[2] * c`, where `a`, `b` and `c`
+    /// are the unit cell vectors.
+    pub cell_shift: [i32; 3],
 }
 
 /// A `rascal_system_t` deals with the storage of atoms and related information,
@@ -0,0 +1,140 @@
+#![allow(clippy::needless_return)]
+use std::convert::TryFrom;
+
+use rascaline::System;
+use rascaline::systems::{SimpleSystem, SoaSystem, NeighborsList};
+
+use criterion::{BenchmarkGroup, Criterion, measurement::WallTime, SamplingMode};
+use criterion::{criterion_group, criterion_main};
+
+fn load_systems(path: &str) -> Vec<SimpleSystem> {
+    return rascaline::systems::read_from_file(format!("benches/data/{}", path))
+        .expect("failed to read file");
+}
+
+fn run_neighbors(mut group: BenchmarkGroup<WallTime>, path: &str, test_mode: bool) {
+    let mut systems = load_systems(path);
+    if test_mode {
+        systems.truncate(1);
+    }
+
+    let n_atoms = systems.iter().map(|s| s.size().unwrap()).sum::<usize>();
+
+    for &cutoff in &[4.0, 6.0] {
+        group.bench_function(&format!("SimpleSystem, cutoff = {}", cutoff), |b| b.iter_custom(|repeat| {
+            let start = std::time::Instant::now();
+            for _ in 0..repeat {
+                for system in &systems {
+                    // use a fresh system to prevent re-using the cached neighbors list
+                    let mut system = system.clone();
+                    system.compute_neighbors(cutoff).unwrap();
+                }
+            }
+            start.elapsed() / n_atoms as u32
+        }));
+
+        let soa_systems = systems.iter()
+            .map(|system| SoaSystem::try_from(system as &dyn System).unwrap())
+            .collect::<Vec<_>>();
+
+        group.bench_function(&format!("SoaSystem, cutoff = {}", cutoff), |b| b.iter_custom(|repeat| {
+            let start = std::time::Instant::now();
+            for _ in 0..repeat {
+                for system in &soa_systems {
+                    let mut system = system.clone();
+                    system.compute_neighbors(cutoff).unwrap();
+                }
+            }
+            start.elapsed() / n_atoms as u32
+        }));
+    }
+}
+
+fn run_supercell_neighbors(mut group: BenchmarkGroup<WallTime>, path: &str, test_mode: bool) {
+    let systems = load_systems(path);
+    let repetitions = if test_mode { [1, 1, 1] } else { [4, 4, 4] };
+    let system = systems[0].supercell(repetitions).unwrap();
+    let soa_system = SoaSystem::try_from(&system as &dyn System).unwrap();
+
+    let n_atoms = system.size().unwrap();
+
+    for &cutoff in &[4.0, 6.0] {
+        group.bench_function(&format!("SimpleSystem, cutoff = {}", cutoff), |b| b.iter_custom(|repeat| {
+            let start = std::time::Instant::now();
+            for _ in 0..repeat {
+                // use a fresh system to prevent re-using the cached neighbors list
+                let mut system = system.clone();
+                system.compute_neighbors(cutoff).unwrap();
+            }
+            start.elapsed() / n_atoms as u32
+        }));
+
+        group.bench_function(&format!("SoaSystem, cutoff = {}", cutoff), |b| b.iter_custom(|repeat| {
+            let start = std::time::Instant::now();
+            for _ in 0..repeat {
+                let mut system = soa_system.clone();
+                system.compute_neighbors(cutoff).unwrap();
+            }
+            start.elapsed() / n_atoms as u32
+        }));
+    }
+}
+
+fn run_parallel_neighbors(mut group: BenchmarkGroup<WallTime>, path: &str, test_mode: bool) {
+    let systems = load_systems(path);
+    let repetitions = if test_mode { [1, 1, 1] } else { [4, 4, 4] };
+    let system = systems[0].supercell(repetitions).unwrap();
+
+    let n_atoms = system.size().unwrap();
+    let positions = system.positions().unwrap();
+    let cell = system.cell().unwrap();
+
+    for &cutoff in &[4.0, 6.0] {
+        for parallel in [false, true] {
+            let name = if parallel { "parallel" } else { "serial" };
+            group.bench_function(&format!("{}, cutoff = {}", name, cutoff), |b| b.iter_custom(|repeat| {
+                let start = std::time::Instant::now();
+                for _ in 0..repeat {
+                    NeighborsList::with_parallel(positions, cell, cutoff, parallel);
+                }
+                start.elapsed() / n_atoms as u32
+            }));
+        }
+    }
+}
+
+fn neighbors(c: &mut Criterion) {
+    let test_mode = std::env::args().any(|arg| arg == "--test");
+
+    let mut group = c.benchmark_group("Neighbors list (per atom)/Bulk Silicon");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_neighbors(group, "silicon_bulk.xyz", test_mode);
+
+    let mut group = c.benchmark_group("Neighbors list (per atom)/Molecular crystals");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_neighbors(group, "molecular_crystals.xyz", test_mode);
+
+    // the structure-of-arrays layout matters most for systems with many atoms
+    let mut group = c.benchmark_group("Neighbors list (per atom)/Bulk Silicon supercell");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_supercell_neighbors(group, "silicon_bulk.xyz", test_mode);
+
+    let mut group = c.benchmark_group("Neighbors list construction (per atom)/Bulk Silicon supercell");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_parallel_neighbors(group, "silicon_bulk.xyz", test_mode);
+}
+
+criterion_group!(all, neighbors);
+criterion_main!(all);
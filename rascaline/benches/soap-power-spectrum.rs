@@ -36,7 +36,7 @@ fn run_soap_power_spectrum(
         system.compute_neighbors(cutoff).unwrap();
     }
 
-    for &(max_radial, max_angular) in &[(2, 1), (8, 7), (15, 14)] {
+    for &(max_radial, max_angular) in &[(2, 1), (8, 7), (10, 6), (15, 14)] {
         // keep the memory requirements under control
         if max_radial == 15 {
             systems.truncate(10);
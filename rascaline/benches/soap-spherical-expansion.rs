@@ -1,5 +1,6 @@
 #![allow(clippy::needless_return)]
-use rascaline::{Calculator, System, CalculationOptions};
+use rascaline::{Calculator, System, SimpleSystem, CalculationOptions, Vector3D};
+use rascaline::systems::UnitCell;
 
 use criterion::{BenchmarkGroup, Criterion, measurement::WallTime, SamplingMode};
 use criterion::{criterion_group, criterion_main};
@@ -13,12 +14,25 @@ fn load_systems(path: &str) -> Vec<Box<dyn System>> {
         .collect()
 }
 
+/// Create a 4x4x4 supercell of a FCC crystal, where all atoms have the same
+/// environment and many pairs share the same direction
+fn fcc_systems() -> Vec<Box<dyn System>> {
+    let mut system = SimpleSystem::new(UnitCell::cubic(3.6));
+    system.add_atom_fractional(29, Vector3D::new(0.0, 0.0, 0.0)).expect("failed to add atom");
+    system.add_atom_fractional(29, Vector3D::new(0.5, 0.5, 0.0)).expect("failed to add atom");
+    system.add_atom_fractional(29, Vector3D::new(0.5, 0.0, 0.5)).expect("failed to add atom");
+    system.add_atom_fractional(29, Vector3D::new(0.0, 0.5, 0.5)).expect("failed to add atom");
+
+    let system = system.supercell([4, 4, 4]).expect("failed to create supercell");
+    return vec![Box::new(system) as Box<dyn System>];
+}
+
 fn run_spherical_expansion(mut group: BenchmarkGroup<WallTime>,
-    path: &str,
+    mut systems: Vec<Box<dyn System>>,
     gradients: bool,
+    cached_directions: Option<usize>,
     test_mode: bool,
 ) {
-    let mut systems = load_systems(path);
 
     if test_mode {
         // Reduce the time/RAM required to test the benchmarks code.
@@ -43,7 +57,13 @@ fn run_spherical_expansion(mut group: BenchmarkGroup<WallTime>,
             }
         }
 
+        let cached_directions = match cached_directions {
+            Some(cached_directions) => format!(r#""cached_directions": {cached_directions},"#),
+            None => String::new(),
+        };
+
         let parameters = format!(r#"{{
+            {cached_directions}
             "max_radial": {max_radial},
             "max_angular": {max_angular},
             "cutoff": {cutoff},
@@ -78,28 +98,56 @@ fn spherical_expansion(c: &mut Criterion) {
     group.sampling_mode(SamplingMode::Flat);
     group.sample_size(10);
 
-    run_spherical_expansion(group, "silicon_bulk.xyz", false, test_mode);
+    run_spherical_expansion(group, load_systems("silicon_bulk.xyz"), false, None, test_mode);
 
     let mut group = c.benchmark_group("SOAP spherical expansion (per atom) with gradients/Bulk Silicon");
     group.noise_threshold(0.05);
     group.sampling_mode(SamplingMode::Flat);
     group.sample_size(10);
 
-    run_spherical_expansion(group, "silicon_bulk.xyz", true, test_mode);
+    run_spherical_expansion(group, load_systems("silicon_bulk.xyz"), true, None, test_mode);
 
     let mut group = c.benchmark_group("SOAP spherical expansion (per atom)/Molecular crystals");
     group.noise_threshold(0.05);
     group.sampling_mode(SamplingMode::Flat);
     group.sample_size(10);
 
-    run_spherical_expansion(group, "molecular_crystals.xyz", false, test_mode);
+    run_spherical_expansion(group, load_systems("molecular_crystals.xyz"), false, None, test_mode);
 
     let mut group = c.benchmark_group("SOAP spherical expansion (per atom) with gradients/Molecular crystals");
     group.noise_threshold(0.05);
     group.sampling_mode(SamplingMode::Flat);
     group.sample_size(10);
 
-    run_spherical_expansion(group, "molecular_crystals.xyz", true, test_mode);
+    run_spherical_expansion(group, load_systems("molecular_crystals.xyz"), true, None, test_mode);
+
+    let mut group = c.benchmark_group("SOAP spherical expansion (per atom)/FCC crystal");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_spherical_expansion(group, fcc_systems(), false, None, test_mode);
+
+    let mut group = c.benchmark_group("SOAP spherical expansion (per atom) with gradients/FCC crystal");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_spherical_expansion(group, fcc_systems(), true, None, test_mode);
+
+    let mut group = c.benchmark_group("SOAP spherical expansion (per atom)/FCC crystal, cached directions");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_spherical_expansion(group, fcc_systems(), false, Some(256), test_mode);
+
+    let mut group = c.benchmark_group("SOAP spherical expansion (per atom) with gradients/FCC crystal, cached directions");
+    group.noise_threshold(0.05);
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    run_spherical_expansion(group, fcc_systems(), true, Some(256), test_mode);
 }
 
 criterion_group!(all, spherical_expansion);
@@ -0,0 +1,109 @@
+use std::collections::{BTreeMap, HashMap};
+
+use equistore::{TensorMap, LabelValue};
+
+/// Assign an integer id to each sample (i.e. each atomic environment) in
+/// `features`, such that samples with the same features (up to `tolerance`)
+/// get the same id.
+///
+/// The features of a sample are spread over all the blocks of `features`, and
+/// a sample missing from a block is considered to have all its features set
+/// to zero in this block. Two samples are equivalent if the absolute
+/// difference between all their features is smaller than `tolerance`.
+///
+/// The ids are assigned in the order of the samples, starting at 0. Each new
+/// sample is compared with the first sample of every id seen so far, so the
+/// result depends on the order of the samples when `tolerance` is larger than
+/// the distance between distinct environments.
+pub fn environment_ids(features: &TensorMap, tolerance: f64) -> HashMap<Vec<LabelValue>, usize> {
+    // features of each sample, indexed by the block they are coming from
+    let mut all_features: BTreeMap<Vec<LabelValue>, BTreeMap<usize, Vec<f64>>> = BTreeMap::new();
+    for (block_i, (_, block)) in features.iter().enumerate() {
+        let values = block.values().to_array();
+        for (sample, row) in block.samples().iter().zip(values.outer_iter()) {
+            all_features.entry(sample.to_vec())
+                .or_default()
+                .insert(block_i, row.iter().copied().collect());
+        }
+    }
+
+    let mut references: Vec<&BTreeMap<usize, Vec<f64>>> = Vec::new();
+    let mut ids = HashMap::new();
+    for (sample, sample_features) in &all_features {
+        let existing = references.iter().position(|reference| {
+            same_features(reference, sample_features, tolerance)
+        });
+
+        let id = if let Some(id) = existing {
+            id
+        } else {
+            references.push(sample_features);
+            references.len() - 1
+        };
+
+        ids.insert(sample.clone(), id);
+    }
+
+    return ids;
+}
+
+/// Check if two sets of features are the same up to `tolerance`, treating
+/// missing blocks as containing only zeros
+fn same_features(first: &BTreeMap<usize, Vec<f64>>, second: &BTreeMap<usize, Vec<f64>>, tolerance: f64) -> bool {
+    let is_small = |values: &Vec<f64>| values.iter().all(|v| f64::abs(*v) <= tolerance);
+
+    for (block_i, first) in first {
+        let matches = match second.get(block_i) {
+            Some(second) => first.iter().zip(second).all(|(a, b)| f64::abs(a - b) <= tolerance),
+            None => is_small(first),
+        };
+
+        if !matches {
+            return false;
+        }
+    }
+
+    for (block_i, second) in second {
+        if !first.contains_key(block_i) && !is_small(second) {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use equistore::LabelValue;
+
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{System, Vector3D};
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::environment_ids;
+
+    #[test]
+    fn methane() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+
+        // perfectly symmetric methane molecule
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(6, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.63, 0.63, 0.63));
+        system.add_atom(1, Vector3D::new(0.63, -0.63, -0.63));
+        system.add_atom(1, Vector3D::new(-0.63, 0.63, -0.63));
+        system.add_atom(1, Vector3D::new(-0.63, -0.63, 0.63));
+
+        let mut systems = [Box::new(system) as Box<dyn System>];
+        let features = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let ids = environment_ids(&features, 1e-8);
+        assert_eq!(ids.len(), 5);
+
+        let id = |center: i32| ids[&vec![LabelValue::new(0), LabelValue::new(center)]];
+        assert_eq!(id(0), 0);
+        for center in 1..5 {
+            assert_eq!(id(center), 1);
+        }
+    }
+}
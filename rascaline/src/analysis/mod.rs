@@ -0,0 +1,5 @@
+//! Functions to analyze the atomic environments described by the features
+//! computed with rascaline.
+
+mod environments;
+pub use self::environments::environment_ids;
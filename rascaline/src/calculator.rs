@@ -3,17 +3,25 @@ use std::convert::TryFrom;
 
 use once_cell::sync::Lazy;
 
-use equistore::{Labels, LabelsBuilder};
-use equistore::{TensorBlockRef, TensorBlock, TensorMap};
-use ndarray::ArrayD;
+use equistore::{Labels, LabelsBuilder, LabelValue};
+use equistore::{TensorBlockRef, TensorBlock, TensorMap, EmptyArray};
+use ndarray::{ArrayD, ArrayViewMut2, Axis};
 
-use crate::{SimpleSystem, System, Error};
+use crate::{SimpleSystem, System, Error, ErrorContext, Vector3D};
 
 use crate::calculators::CalculatorBase;
+use crate::utils::{Aggregation, aggregate_centers};
+
+/// All the gradient parameters that can be requested in
+/// `CalculationOptions::gradients`
+pub(crate) const ALL_GRADIENT_PARAMETERS: [&str; 4] = [
+    "positions", "cell", "strain", "atomic_gaussian_width"
+];
 
 pub struct Calculator {
     implementation: Box<dyn CalculatorBase>,
     parameters: String,
+    registered_name: Option<&'static str>,
 }
 
 /// Rules to select labels (either samples or properties) on which the user
@@ -159,6 +167,58 @@ impl<'a> LabelsSelection<'a> {
     }
 }
 
+/// How to deal with systems without a periodic unit cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellInference {
+    /// Default, use the cell of the systems as-is. Systems with an infinite
+    /// cell are computed in vacuum, without periodic boundary conditions.
+    Keep,
+    /// Replace the infinite cell of systems by an orthorhombic bounding box
+    /// containing all atoms, with `padding` empty space on each side (see
+    /// [`SimpleSystem::infer_bounding_box`]). Systems with a periodic cell are
+    /// not modified.
+    ///
+    /// This allows to run the periodic code paths on non-periodic systems.
+    /// When `padding` is larger than the cutoff radius of the calculator, no
+    /// atom can see the periodic images of the others, and the results are
+    /// the same as the computation in vacuum (up to floating point rounding
+    /// errors).
+    ///
+    /// Gradients with respect to the cell are not available with an inferred
+    /// cell.
+    BoundingBox {
+        padding: f64,
+    },
+}
+
+impl Default for CellInference {
+    fn default() -> CellInference {
+        CellInference::Keep
+    }
+}
+
+/// Callback used to transform the values of each block at the end of a
+/// calculation, see [`CalculationOptions::post_process`].
+#[derive(Clone, Copy)]
+pub struct PostProcess<'a>(pub &'a (dyn Fn(&[LabelValue], &mut ArrayViewMut2<'_, f64>) + Send + Sync));
+
+impl<'a> std::fmt::Debug for PostProcess<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PostProcess(..)")
+    }
+}
+
+/// Callback used to report the progress of a calculation, see
+/// [`CalculationOptions::progress_callback`].
+#[derive(Clone, Copy)]
+pub struct ProgressCallback<'a>(pub &'a (dyn Fn(usize, usize) + Send + Sync));
+
+impl<'a> std::fmt::Debug for ProgressCallback<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ProgressCallback(..)")
+    }
+}
+
 /// Parameters specific to a single call to `compute`
 #[derive(Debug, Clone, Copy)]
 pub struct CalculationOptions<'a> {
@@ -199,6 +259,23 @@ pub struct CalculationOptions<'a> {
     ///            {\partial\epsilon}
     ///        = -\frac{\partial \langle q \vert A \rangle}
     ///                {\partial \mathbf{h}} \cdot \mathbf{h} $$
+    ///
+    /// - ``"strain"``, for gradients of the representation with respect to an
+    ///   homogeneous strain $\epsilon$ applied to both the atomic positions
+    ///   and the cell. These gradients directly give the virial, and are
+    ///   computed as
+    ///
+    ///   $$ \frac{\partial \langle q \vert A_i \rangle}
+    ///            {\partial\epsilon}
+    ///        = \frac{\partial \langle q \vert A_i \rangle}
+    ///                {\partial \mathbf{h}} \cdot \mathbf{h} $$
+    ///
+    ///   Strain gradients are only available for some calculators.
+    ///
+    /// - ``"atomic_gaussian_width"``, for gradients of the representation with
+    ///   respect to the width of the atomic gaussian densities. This is
+    ///   useful for hyper-parameters optimization, and only available for
+    ///   some calculators.
     pub gradients: &'a[&'a str],
     /// Copy the data from systems into native `SimpleSystem`. This can be
     /// faster than having to cross the FFI boundary too often.
@@ -212,6 +289,56 @@ pub struct CalculationOptions<'a> {
     /// that this default set of keys can depend on which systems we are running
     /// the calculation on.
     pub selected_keys: Option<&'a Labels>,
+    /// How to deal with systems without a periodic unit cell. Using anything
+    /// else than `CellInference::Keep` implies `use_native_system`.
+    pub cell_inference: CellInference,
+    /// **Experimental**: velocities of all atoms, with one entry per system
+    /// containing one velocity per atom. This is only used by calculators
+    /// with velocity dependent densities (see
+    /// [`crate::calculators::VelocityWeight`]), other calculators will return
+    /// an error if velocities are given.
+    pub velocities: Option<&'a [Vec<Vector3D>]>,
+    /// Optional callback applied to the values of each block once the
+    /// calculation is finished, for example to normalize or clamp the values.
+    ///
+    /// The callback is called once per block with the key of the block and
+    /// the values reshaped to a 2-dimensional `(samples, components x
+    /// properties)` array. It runs after both values and gradients have been
+    /// computed, and gradients are not modified: if the transformation changes
+    /// the values, the gradients will no longer match them.
+    pub post_process: Option<PostProcess<'a>>,
+    /// Thread pool used to run the parallel parts of the calculation. If this
+    /// is `None`, rayon's global thread pool is used. This allows to limit
+    /// the number of threads used by a calculation, for example when running
+    /// multiple calculations in parallel.
+    pub thread_pool: Option<&'a rayon::ThreadPool>,
+    /// Optional callback used to report the progress of the calculation. The
+    /// callback is called with `(systems_done, systems_total)` each time the
+    /// calculation for one system is finished, always from the thread which
+    /// called [`Calculator::compute`].
+    ///
+    /// When this is set, the systems are computed one at the time instead of
+    /// all together, which can be slower for calculators parallelizing the
+    /// calculation over systems.
+    pub progress_callback: Option<ProgressCallback<'a>>,
+    /// Selection of atoms for which to compute gradients with respect to
+    /// positions, as `Labels` with the `["structure", "atom"]` names. If this
+    /// is `None`, gradients are computed with respect to all atoms.
+    ///
+    /// Only the gradient samples involving one of the selected atoms are
+    /// created, which reduces the memory and time required when only the
+    /// forces acting on a few atoms are needed. This is only available for
+    /// some calculators, and only affects the ``"positions"`` gradients.
+    pub selected_gradient_atoms: Option<&'a Labels>,
+    /// Combine all the samples associated with the same structure once the
+    /// calculation is finished (and after `post_process`), producing a
+    /// single sample per structure in each block. Gradients are combined in
+    /// the same way as the values. See [`crate::utils::aggregate_centers`]
+    /// for more information.
+    ///
+    /// The full per-center descriptor is still computed first, so this does
+    /// not reduce the peak memory usage of the calculation.
+    pub aggregate: Option<Aggregation>,
 }
 
 impl<'a> Default for CalculationOptions<'a> {
@@ -222,6 +349,13 @@ impl<'a> Default for CalculationOptions<'a> {
             selected_samples: LabelsSelection::All,
             selected_properties: LabelsSelection::All,
             selected_keys: None,
+            cell_inference: CellInference::Keep,
+            velocities: None,
+            post_process: None,
+            thread_pool: None,
+            progress_callback: None,
+            selected_gradient_atoms: None,
+            aggregate: None,
         }
     }
 }
@@ -232,6 +366,7 @@ impl From<Box<dyn CalculatorBase>> for Calculator {
         Calculator {
             implementation: implementation,
             parameters: parameters,
+            registered_name: None,
         }
     }
 }
@@ -247,8 +382,8 @@ impl Calculator {
     /// This function returns an error if there is no registered calculator with
     /// the given `name`, or if the parameters are invalid for this calculator.
     pub fn new(name: &str, parameters: String) -> Result<Calculator, Error> {
-        let creator = match REGISTERED_CALCULATORS.get(name) {
-            Some(creator) => creator,
+        let (registered_name, (creator, _)) = match REGISTERED_CALCULATORS.get_key_value(name) {
+            Some(entry) => entry,
             None => {
                 return Err(Error::InvalidParameter(
                     format!("unknown calculator with name '{}'", name)
@@ -259,23 +394,70 @@ impl Calculator {
         return Ok(Calculator {
             implementation: creator(&parameters)?,
             parameters: parameters,
+            registered_name: Some(*registered_name),
         })
     }
 
+    /// Get the JSON schema describing the parameters of the calculator
+    /// registered with the given `name`, formatted as JSON.
+    ///
+    /// This can be used to validate parameters or to generate user interfaces
+    /// for them before creating a calculator with [`Calculator::new`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if there is no registered calculator with
+    /// the given `name`.
+    pub fn parameters_schema(name: &str) -> Result<String, Error> {
+        let (_, schema) = match REGISTERED_CALCULATORS.get(name) {
+            Some(entry) => entry,
+            None => {
+                return Err(Error::InvalidParameter(
+                    format!("unknown calculator with name '{}'", name)
+                ));
+            }
+        };
+
+        return Ok(serde_json::to_string_pretty(&schema())?);
+    }
+
     /// Get the name of this calculator
     pub fn name(&self) -> String {
         self.implementation.name()
     }
 
+    /// Get the name used to create this calculator with [`Calculator::new`],
+    /// or `None` if the calculator was created directly from a
+    /// `CalculatorBase` implementation.
+    ///
+    /// Together with [`Calculator::parameters`], this allows to re-create the
+    /// same calculator later, for example after storing both strings in a
+    /// configuration file.
+    pub fn registered_name(&self) -> Option<&str> {
+        self.registered_name
+    }
+
     /// Get the parameters used to create this calculator in a string, formatted
     /// as JSON.
     pub fn parameters(&self) -> &str {
         &self.parameters
     }
 
+    /// Get the number of properties this calculator would produce for each
+    /// of the given `keys`, without running a calculation.
+    ///
+    /// This can be used to pre-allocate memory for the features, and does not
+    /// account for any property selection.
+    pub fn properties_count(&self, keys: &Labels) -> Vec<usize> {
+        return self.implementation.properties_count(keys);
+    }
 
-    #[time_graph::instrument(name="Calculator::prepare")]
-    fn prepare(&mut self, systems: &mut [Box<dyn System>], options: CalculationOptions) -> Result<TensorMap, Error> {
+
+    /// Get the keys, samples, components, properties and gradient samples of
+    /// the descriptor produced by a calculation on the given `systems` with
+    /// the given `options`, without allocating the corresponding arrays.
+    #[time_graph::instrument(name="Calculator::prepare_metadata")]
+    fn prepare_metadata(&mut self, systems: &mut [Box<dyn System>], options: CalculationOptions) -> Result<DescriptorMetadata, Error> {
         let default_keys = self.implementation.keys(systems)?;
         let keys = match options.selected_keys {
             Some(keys) if keys.is_empty() => {
@@ -304,12 +486,12 @@ impl Calculator {
         )?;
 
         for &parameter in options.gradients {
-            if parameter == "positions" || parameter == "cell" {
+            if ALL_GRADIENT_PARAMETERS.contains(&parameter) {
                 continue;
             }
 
             return Err(Error::InvalidParameter(format!(
-                "unexpected gradient \"{}\", should be one of \"positions\" or \"cell\"",
+                "unexpected gradient \"{}\", should be one of \"positions\", \"cell\", \"strain\" or \"atomic_gaussian_width\"",
                 parameter
             )));
         }
@@ -322,19 +504,55 @@ impl Calculator {
                 )));
             }
 
-            Some(self.implementation.positions_gradient_samples(&keys, &samples, systems)?)
+            let gradient_samples = self.implementation.positions_gradient_samples(&keys, &samples, systems)?;
+            if let Some(atoms) = options.selected_gradient_atoms {
+                if atoms.names() != ["structure", "atom"] {
+                    return Err(Error::InvalidParameter(format!(
+                        "selected gradient atoms should have [structure, atom] names, got [{}]",
+                        atoms.names().join(", ")
+                    )));
+                }
+
+                if !self.implementation.supports_gradient_atoms_selection() {
+                    return Err(Error::InvalidParameter(format!(
+                        "the {} calculator does not support selecting atoms for gradients with respect to positions",
+                        self.name()
+                    )));
+                }
+
+                Some(gradient_samples.iter().map(|samples| select_gradient_atoms(samples, atoms)).collect())
+            } else {
+                Some(gradient_samples)
+            }
         } else {
             None
         };
 
-        let cell_gradient_samples = if options.gradients.contains(&"cell") {
-            if !self.implementation.supports_gradient("cell") {
+        if options.gradients.contains(&"strain") && !self.implementation.supports_gradient("strain") {
+            return Err(Error::InvalidParameter(format!(
+                "the {} calculator does not support gradients with respect to strain",
+                self.name()
+            )));
+        }
+
+        if options.gradients.contains(&"atomic_gaussian_width") && !self.implementation.supports_gradient("atomic_gaussian_width") {
+            return Err(Error::InvalidParameter(format!(
+                "the {} calculator does not support gradients with respect to the atomic gaussian width",
+                self.name()
+            )));
+        }
+
+        let cell_like_gradients = ["cell", "strain", "atomic_gaussian_width"];
+        let cell_gradient_samples = if cell_like_gradients.iter().any(|parameter| options.gradients.contains(parameter)) {
+            if options.gradients.contains(&"cell") && !self.implementation.supports_gradient("cell") {
                 return Err(Error::InvalidParameter(format!(
                     "the {} calculator does not support gradients with respect to the cell",
                     self.name()
                 )));
             }
 
+            // the same samples (one gradient sample for each sample) are used
+            // for gradients w.r.t. cell, strain and atomic gaussian width
             let mut cell_gradient_samples = Vec::new();
             for samples in &samples {
                 let mut builder = LabelsBuilder::new(vec!["sample"]);
@@ -363,62 +581,65 @@ impl Calculator {
         assert_eq!(keys.count(), components.len());
         assert_eq!(keys.count(), properties.len());
 
-        let direction = Labels::new(["direction"], &[[0], [1], [2]]);
-        let direction_1 = Labels::new(["direction_1"], &[[0], [1], [2]]);
-        let direction_2 = Labels::new(["direction_2"], &[[0], [1], [2]]);
+        let mut gradients = Vec::new();
+        if let Some(gradient_samples) = positions_gradient_samples {
+            for samples in &gradient_samples {
+                assert_eq!(samples.names(), ["sample", "structure", "atom"]);
+            }
+            gradients.push(("positions", gradient_samples));
+        }
+
+        if let Some(gradient_samples) = cell_gradient_samples {
+            for parameter in ["atomic_gaussian_width", "cell", "strain"] {
+                if options.gradients.contains(&parameter) {
+                    gradients.push((parameter, gradient_samples.clone()));
+                }
+            }
+        }
+
+        return Ok(DescriptorMetadata {
+            keys: keys,
+            samples: samples,
+            components: components,
+            properties: properties,
+            gradients: gradients,
+        });
+    }
+
+    /// Allocate the descriptor for a calculation on the given `systems` with
+    /// the given `options`, with all values and gradients set to zero.
+    #[time_graph::instrument(name="Calculator::prepare")]
+    fn prepare(&mut self, systems: &mut [Box<dyn System>], options: CalculationOptions) -> Result<TensorMap, Error> {
+        let metadata = self.prepare_metadata(systems, options)?;
 
         let mut blocks = Vec::new();
-        for (block_i, ((samples, components), properties)) in samples.into_iter().zip(components).zip(properties).enumerate() {
-            let shape = shape_from_labels(
-                &samples, &components, &properties
-            );
+        for block_i in 0..metadata.keys.count() {
+            let samples = &metadata.samples[block_i];
+            let components = &metadata.components[block_i];
+            let properties = &metadata.properties[block_i];
+
+            let shape = shape_from_labels(samples, components, properties);
             let mut new_block = TensorBlock::new(
                 ArrayD::from_elem(shape, 0.0),
-                &samples,
-                &components,
-                &properties,
+                samples,
+                components,
+                properties,
             )?;
 
-            if let Some(ref gradient_samples) = positions_gradient_samples {
+            for (parameter, gradient_samples) in &metadata.gradients {
                 let gradient_samples = &gradient_samples[block_i];
-                assert_eq!(gradient_samples.names(), ["sample", "structure", "atom"]);
-
-                // add the x/y/z component for gradients
-                let mut components = components.clone();
-                components.insert(0, direction.clone());
+                let components = gradient_components(parameter, components);
                 let shape = shape_from_labels(
-                    gradient_samples, &components, &properties
+                    gradient_samples, &components, properties
                 );
 
                 new_block.add_gradient(
-                    "positions",
+                    parameter,
                     TensorBlock::new(
                         ArrayD::from_elem(shape, 0.0),
                         gradient_samples,
                         &components,
-                        &properties
-                    ).expect("generated invalid gradient")
-                ).expect("generated invalid gradient");
-            }
-
-            if let Some(ref gradient_samples) = cell_gradient_samples {
-                let gradient_samples = &gradient_samples[block_i];
-
-                // add the components for cell gradients
-                let mut components = components;
-                components.insert(0, direction_2.clone());
-                components.insert(0, direction_1.clone());
-                let shape = shape_from_labels(
-                    gradient_samples, &components, &properties
-                );
-
-                new_block.add_gradient(
-                    "cell",
-                    TensorBlock::new(
-                        ArrayD::from_elem(shape, 0.0),
-                        gradient_samples,
-                        &components,
-                        &properties
+                        properties
                     ).expect("generated invalid gradient")
                 ).expect("generated invalid gradient");
             }
@@ -426,7 +647,7 @@ impl Calculator {
             blocks.push(new_block);
         }
 
-        return Ok(TensorMap::new(keys, blocks)?);
+        return Ok(TensorMap::new(metadata.keys, blocks)?);
     }
 
     /// Compute the descriptor for all the given `systems` and store it in
@@ -439,23 +660,816 @@ impl Calculator {
         systems: &mut [Box<dyn System>],
         options: CalculationOptions,
     ) -> Result<TensorMap, Error> {
-        let mut native_systems;
-        let systems = if options.use_native_system {
-            native_systems = Vec::with_capacity(systems.len());
-            for system in systems {
-                native_systems.push(Box::new(SimpleSystem::try_from(&**system)?) as Box<dyn System>);
+        let mut native_systems = native_systems(systems, options)?;
+        let systems: &mut [Box<dyn System>] = match native_systems {
+            Some(ref mut native_systems) => native_systems,
+            None => systems,
+        };
+
+        check_velocities(systems, options)?;
+
+        let mut tensor = self.prepare(systems, options)?;
+        self.compute_prepared(systems, &mut tensor, options)?;
+
+        if let Some(aggregation) = options.aggregate {
+            return aggregate_centers(&tensor, aggregation);
+        }
+
+        return Ok(tensor);
+    }
+
+    /// Compute the descriptor for all the given `systems`, re-using the
+    /// allocations of an existing `descriptor`.
+    ///
+    /// This is useful when running the same calculation many times on systems
+    /// with the same topology, for example in molecular dynamics where only
+    /// the positions change between calls. The `descriptor` must have been
+    /// produced by a previous calculation with the same systems and options:
+    /// its keys, samples, components, properties and gradients must match the
+    /// ones this calculation would produce, otherwise this function returns
+    /// an [`Error::InvalidParameter`]. All values and gradients in
+    /// `descriptor` are then reset to zero and overwritten with the results
+    /// of the new calculation.
+    pub fn compute_into(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        descriptor: &mut TensorMap,
+        options: CalculationOptions,
+    ) -> Result<(), Error> {
+        let mut native_systems = native_systems(systems, options)?;
+        let systems: &mut [Box<dyn System>] = match native_systems {
+            Some(ref mut native_systems) => native_systems,
+            None => systems,
+        };
+
+        if options.aggregate.is_some() {
+            return Err(Error::InvalidParameter(
+                "aggregation of the samples is not supported when re-using a descriptor".into()
+            ));
+        }
+
+        check_velocities(systems, options)?;
+
+        let metadata = self.prepare_metadata(systems, options)?;
+        check_descriptor_metadata(descriptor, &metadata)?;
+
+        for (_, mut block) in descriptor.iter_mut() {
+            block.values_mut().to_array_mut().fill(0.0);
+            for parameter in ALL_GRADIENT_PARAMETERS {
+                if let Some(mut gradient) = block.gradient_mut(parameter) {
+                    gradient.values_mut().to_array_mut().fill(0.0);
+                }
             }
-            &mut native_systems
+        }
+
+        return self.compute_prepared(systems, descriptor, options);
+    }
+
+    /// Run the calculation for the given `systems` in an already prepared
+    /// `descriptor`, containing only zeros, and apply the post-processing
+    /// from the `options`.
+    fn compute_prepared(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        descriptor: &mut TensorMap,
+        options: CalculationOptions,
+    ) -> Result<(), Error> {
+        if let Some(progress_callback) = options.progress_callback {
+            self.compute_by_system(systems, descriptor, options, progress_callback)?;
         } else {
-            systems
+            self.implementation.set_velocities(options.velocities)?;
+            self.compute_in_thread_pool(systems, descriptor, options.thread_pool)?;
+        }
+
+        apply_post_process(descriptor, options);
+
+        return Ok(());
+    }
+
+    /// Compute a descriptor for the given `systems`, with the same keys,
+    /// samples, properties and gradients as [`Calculator::compute`] would
+    /// produce, but using `fill` instead of the calculator implementation to
+    /// set the values and gradients.
+    ///
+    /// This is used by calculators which can produce their output from some
+    /// already computed data. `fill` runs in the thread pool from `options`,
+    /// and the post-processing and aggregation from `options` are applied to
+    /// the result. `options.progress_callback` is not used.
+    pub(crate) fn compute_with<F>(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        options: CalculationOptions,
+        fill: F,
+    ) -> Result<TensorMap, Error> where F: FnOnce(&mut TensorMap) -> Result<(), Error> + Send {
+        let mut native_systems = native_systems(systems, options)?;
+        let systems: &mut [Box<dyn System>] = match native_systems {
+            Some(ref mut native_systems) => native_systems,
+            None => systems,
         };
 
+        check_velocities(systems, options)?;
+
         let mut tensor = self.prepare(systems, options)?;
+        match options.thread_pool {
+            Some(thread_pool) => thread_pool.install(|| fill(&mut tensor))?,
+            None => fill(&mut tensor)?,
+        }
 
-        self.implementation.compute(systems, &mut tensor)?;
+        apply_post_process(&mut tensor, options);
+
+        if let Some(aggregation) = options.aggregate {
+            return aggregate_centers(&tensor, aggregation);
+        }
 
         return Ok(tensor);
     }
+
+    /// Run the calculation for the given `systems` in `descriptor`, using the
+    /// given `thread_pool` or rayon's global thread pool if it is `None`.
+    fn compute_in_thread_pool(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        descriptor: &mut TensorMap,
+        thread_pool: Option<&rayon::ThreadPool>,
+    ) -> Result<(), Error> {
+        return time_graph::spanned!("Calculator::compute", {
+            match thread_pool {
+                Some(thread_pool) => thread_pool.install(|| self.implementation.compute(systems, descriptor)),
+                None => self.implementation.compute(systems, descriptor),
+            }
+        });
+    }
+
+    /// Run the calculation one system at the time, copying the results for
+    /// each system in `descriptor` and calling `progress_callback` after each
+    /// one of them.
+    fn compute_by_system(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        descriptor: &mut TensorMap,
+        options: CalculationOptions,
+        progress_callback: ProgressCallback,
+    ) -> Result<(), Error> {
+        let n_systems = systems.len();
+        for system_i in 0..n_systems {
+            let system = &mut systems[system_i..=system_i];
+
+            // compute exactly the samples of `descriptor` for this system,
+            // with the same keys and properties
+            let selection = system_selection(descriptor, system_i)?;
+            let velocities = options.velocities.map(|velocities| &velocities[system_i..=system_i]);
+            let gradient_atoms = options.selected_gradient_atoms.map(|atoms| system_gradient_atoms(atoms, system_i));
+            let system_options = CalculationOptions {
+                gradients: options.gradients,
+                use_native_system: false,
+                selected_samples: LabelsSelection::Predefined(&selection),
+                selected_properties: LabelsSelection::Predefined(&selection),
+                selected_keys: Some(descriptor.keys()),
+                cell_inference: CellInference::Keep,
+                velocities: velocities,
+                post_process: None,
+                thread_pool: options.thread_pool,
+                progress_callback: None,
+                selected_gradient_atoms: gradient_atoms.as_ref(),
+                aggregate: None,
+            };
+
+            let mut partial = self.prepare(system, system_options)?;
+            self.implementation.set_velocities(velocities)?;
+            self.compute_in_thread_pool(system, &mut partial, options.thread_pool)?;
+
+            copy_system_data(&partial, descriptor, system_i);
+
+            (progress_callback.0)(system_i + 1, n_systems);
+        }
+
+        return Ok(());
+    }
+
+    /// Get the number of features that [`Calculator::compute`] would produce
+    /// for the given `systems` and `options`, without computing them.
+    ///
+    /// This accounts for the selected keys, samples and properties in
+    /// `options`, and is the sum of the number of properties over all the
+    /// blocks containing at least one sample.
+    pub fn effective_feature_count(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        options: CalculationOptions,
+    ) -> Result<usize, Error> {
+        let mut native_systems = native_systems(systems, options)?;
+        let systems: &mut [Box<dyn System>] = match native_systems {
+            Some(ref mut native_systems) => native_systems,
+            None => systems,
+        };
+
+        // gradients do not change the number of features
+        let options = CalculationOptions {
+            gradients: &[],
+            ..options
+        };
+        let tensor = self.prepare(systems, options)?;
+
+        let mut count = 0;
+        for (_, block) in tensor.iter() {
+            if block.samples().count() != 0 {
+                count += block.properties().count();
+            }
+        }
+
+        return Ok(count);
+    }
+
+    /// Compute the descriptor for all the given `systems` one block at the
+    /// time, returning an iterator over the keys and corresponding blocks.
+    ///
+    /// Each block is only computed when the iterator is advanced, limiting the
+    /// peak memory usage to roughly a single block. The blocks are yielded in
+    /// the same order as the keys of the `TensorMap` that would be returned by
+    /// [`Calculator::compute`], and contain the same data. All the `options`
+    /// apply to each block.
+    pub fn compute_blocks<'a>(
+        &'a mut self,
+        systems: &'a mut [Box<dyn System>],
+        options: CalculationOptions<'a>,
+    ) -> impl Iterator<Item = Result<(Vec<LabelValue>, TensorBlock), Error>> + 'a {
+        let (keys, mut error) = match options.selected_keys {
+            Some(keys) => (keys.clone(), None),
+            None => match self.implementation.keys(systems) {
+                Ok(keys) => (keys, None),
+                Err(error) => (Labels::empty(vec!["_"]), Some(error)),
+            }
+        };
+
+        let mut key_i = 0;
+        return std::iter::from_fn(move || {
+            if let Some(error) = error.take() {
+                return Some(Err(error));
+            }
+
+            if key_i >= keys.count() {
+                return None;
+            }
+
+            let key = keys[key_i].to_vec();
+            key_i += 1;
+
+            let mut selected_key = LabelsBuilder::new(keys.names());
+            selected_key.add(&key);
+            let selected_key = selected_key.finish();
+
+            let options = CalculationOptions {
+                selected_keys: Some(&selected_key),
+                ..options
+            };
+
+            let block = self.compute(systems, options).and_then(|tensor| {
+                debug_assert_eq!(tensor.keys().count(), 1);
+                return Ok(tensor.block_by_id(0).try_clone()?);
+            });
+
+            return Some(block.map(|block| (key, block)));
+        });
+    }
+
+    /// Compute the descriptor for a stream of `systems`, `chunk_size` systems
+    /// at the time, calling `callback` with the descriptor of each chunk.
+    ///
+    /// This allows to process datasets which do not fit in memory, since only
+    /// the systems and descriptor of a single chunk are kept around at any
+    /// time. All the chunks use the same set of keys, taken from
+    /// `options.selected_keys` if given, or from the first chunk otherwise.
+    /// In the latter case, a later chunk containing additional keys results
+    /// in an error. The `"structure"` in the samples refers to the position of
+    /// the system in the full stream, so the chunks can be joined together
+    /// along the samples to get the same data as [`Calculator::compute`].
+    ///
+    /// Samples selection, velocities and progress callbacks are not supported
+    /// by this function.
+    pub fn compute_streaming<I, F>(
+        &mut self,
+        systems: I,
+        chunk_size: usize,
+        options: CalculationOptions,
+        mut callback: F,
+    ) -> Result<(), Error>
+        where I: IntoIterator<Item = Box<dyn System>>,
+              F: FnMut(TensorMap) -> Result<(), Error>,
+    {
+        if chunk_size == 0 {
+            return Err(Error::InvalidParameter(
+                "chunk size must be at least 1 in streaming calculations".into()
+            ));
+        }
+
+        if !matches!(options.selected_samples, LabelsSelection::All) || options.selected_gradient_atoms.is_some() {
+            return Err(Error::InvalidParameter(
+                "samples selection is not supported in streaming calculations".into()
+            ));
+        }
+
+        if options.velocities.is_some() {
+            return Err(Error::InvalidParameter(
+                "velocities are not supported in streaming calculations".into()
+            ));
+        }
+
+        if options.progress_callback.is_some() {
+            return Err(Error::InvalidParameter(
+                "progress callbacks are not supported in streaming calculations".into()
+            ));
+        }
+
+        let mut keys = options.selected_keys.cloned();
+        let mut systems = systems.into_iter();
+        let mut offset = 0;
+        loop {
+            let mut chunk = systems.by_ref().take(chunk_size).collect::<Vec<_>>();
+            if chunk.is_empty() {
+                break;
+            }
+
+            match keys {
+                Some(ref keys) => {
+                    if options.selected_keys.is_none() {
+                        let chunk_keys = self.implementation.keys(&mut chunk)?;
+                        for key in chunk_keys.iter() {
+                            if !keys.contains(key) {
+                                return Err(Error::InvalidParameter(format!(
+                                    "the systems starting at index {} contain keys which are not in the first chunk, \
+                                    use `selected_keys` to specify the keys of all chunks",
+                                    offset
+                                )));
+                            }
+                        }
+                    }
+                }
+                None => keys = Some(self.implementation.keys(&mut chunk)?),
+            }
+
+            let chunk_options = CalculationOptions {
+                selected_keys: keys.as_ref(),
+                ..options
+            };
+
+            let tensor = self.compute(&mut chunk, chunk_options).with_context(|| format!(
+                "failed to compute the systems starting at index {}", offset
+            ))?;
+            callback(offset_tensor_structure(&tensor, offset)?)?;
+
+            offset += chunk.len();
+        }
+
+        return Ok(());
+    }
+
+    /// Compute the descriptor for all the given `systems`, storing the values
+    /// in single precision.
+    ///
+    /// The calculation itself is still done in double precision, one system
+    /// at the time, and the values for each system are converted to `f32`
+    /// before moving on to the next one. This halves the memory used by the
+    /// output, and limits the additional double precision storage to the
+    /// descriptor of a single system. The output contains the same samples
+    /// as [`Calculator::compute`], in the same order.
+    ///
+    /// Gradients and samples selection are not supported by this function.
+    pub fn compute_f32(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        options: CalculationOptions,
+    ) -> Result<SinglePrecisionTensorMap, Error> {
+        if !options.gradients.is_empty() {
+            return Err(Error::InvalidParameter(
+                "gradients are not supported in single precision calculations".into()
+            ));
+        }
+
+        if !matches!(options.selected_samples, LabelsSelection::All) {
+            return Err(Error::InvalidParameter(
+                "samples selection is not supported in single precision calculations".into()
+            ));
+        }
+
+        if systems.is_empty() {
+            return Err(Error::InvalidParameter(
+                "can not run a single precision calculation without systems".into()
+            ));
+        }
+
+        if let Some(velocities) = options.velocities {
+            if velocities.len() != systems.len() {
+                return Err(Error::InvalidParameter(format!(
+                    "expected velocities for {} systems, got {}",
+                    systems.len(), velocities.len()
+                )));
+            }
+        }
+
+        // use the same keys for all systems
+        let keys = match options.selected_keys {
+            Some(keys) => keys.clone(),
+            None => self.implementation.keys(systems)?,
+        };
+
+        // the samples for all systems are used to allocate the output once,
+        // and the values are then copied into it one system at the time
+        let all_samples = self.implementation.samples(&keys, systems)?;
+
+        let mut samples = all_samples.iter()
+            .map(|samples| LabelsBuilder::new(samples.names()))
+            .collect::<Vec<_>>();
+        let mut outputs = (0..keys.count()).map(|_| None).collect::<Vec<Option<SinglePrecisionBlock>>>();
+        let mut offsets = vec![0; keys.count()];
+        for system_i in 0..systems.len() {
+            let system_options = CalculationOptions {
+                selected_keys: Some(&keys),
+                velocities: options.velocities.map(|velocities| &velocities[system_i..=system_i]),
+                progress_callback: None,
+                ..options
+            };
+
+            // the double precision descriptor for this system is dropped at
+            // the end of the loop, before computing the next system
+            let tensor = self.compute(&mut systems[system_i..=system_i], system_options)?;
+            for (block_i, (_, block)) in tensor.iter().enumerate() {
+                let values = block.values().to_array();
+                let output = outputs[block_i].get_or_insert_with(|| {
+                    let mut shape = values.shape().to_vec();
+                    shape[0] = all_samples[block_i].count();
+                    SinglePrecisionBlock {
+                        // the samples are set once all systems are computed
+                        samples: Labels::empty(vec!["structure"]),
+                        components: block.components().to_vec(),
+                        properties: block.properties(),
+                        values: ArrayD::zeros(shape),
+                    }
+                });
+
+                let start = offsets[block_i];
+                let stop = start + values.shape()[0];
+                if stop > output.values.shape()[0] {
+                    return Err(Error::Internal(format!(
+                        "got more samples than expected for block {} in single precision calculation",
+                        block_i
+                    )));
+                }
+
+                output.values.slice_axis_mut(Axis(0), (start..stop).into())
+                    .zip_mut_with(&values, |output, &value| *output = value as f32);
+                offsets[block_i] = stop;
+
+                for entry in offset_structure(&block.samples(), system_i).iter() {
+                    samples[block_i].add(entry);
+                }
+            }
+
+            if let Some(progress_callback) = options.progress_callback {
+                (progress_callback.0)(system_i + 1, systems.len());
+            }
+        }
+
+        let mut blocks = Vec::new();
+        for ((output, samples), offset) in outputs.into_iter().zip(samples).zip(offsets) {
+            let mut output = output.expect("there should be at least one system");
+            if offset != output.values.shape()[0] {
+                return Err(Error::Internal(format!(
+                    "expected {} samples in single precision calculation, got {}",
+                    output.values.shape()[0], offset
+                )));
+            }
+
+            output.samples = samples.finish();
+            blocks.push(output);
+        }
+
+        return Ok(SinglePrecisionTensorMap {
+            keys: keys,
+            blocks: blocks,
+        });
+    }
+}
+
+/// Single precision version of a `TensorBlock`, produced by
+/// [`Calculator::compute_f32`]. Gradients are not included.
+#[derive(Debug, Clone)]
+pub struct SinglePrecisionBlock {
+    /// samples of this block
+    pub samples: Labels,
+    /// components of this block
+    pub components: Vec<Labels>,
+    /// properties of this block
+    pub properties: Labels,
+    /// values of this block, with one dimension for the samples, each
+    /// component and the properties
+    pub values: ArrayD<f32>,
+}
+
+/// Single precision version of a `TensorMap`, produced by
+/// [`Calculator::compute_f32`].
+#[derive(Debug, Clone)]
+pub struct SinglePrecisionTensorMap {
+    /// keys of this tensor map
+    pub keys: Labels,
+    /// blocks of this tensor map, in the same order as the keys
+    pub blocks: Vec<SinglePrecisionBlock>,
+}
+
+/// Add `offset` to the `structure` variable of the `samples`, if it exists
+fn offset_structure(samples: &Labels, offset: usize) -> Labels {
+    let names = samples.names();
+    let structure = names.iter().position(|&name| name == "structure");
+
+    let mut builder = LabelsBuilder::new(names.clone());
+    for entry in samples.iter() {
+        let mut entry = entry.to_vec();
+        if let Some(structure) = structure {
+            entry[structure] = LabelValue::new(entry[structure].i32() + offset as i32);
+        }
+        builder.add(&entry);
+    }
+
+    return builder.finish();
+}
+
+/// Create a copy of `tensor` where the `"structure"` in the samples (and
+/// gradient samples) is shifted by `offset`.
+fn offset_tensor_structure(tensor: &TensorMap, offset: usize) -> Result<TensorMap, Error> {
+    let mut blocks = Vec::new();
+    for (_, block) in tensor.iter() {
+        let mut new_block = TensorBlock::new(
+            block.values().to_array().to_owned(),
+            &offset_structure(&block.samples(), offset),
+            &block.components(),
+            &block.properties(),
+        )?;
+
+        for parameter in ALL_GRADIENT_PARAMETERS {
+            if let Some(gradient) = block.gradient(parameter) {
+                new_block.add_gradient(parameter, TensorBlock::new(
+                    gradient.values().to_array().to_owned(),
+                    &offset_structure(&gradient.samples(), offset),
+                    &gradient.components(),
+                    &gradient.properties(),
+                )?)?;
+            }
+        }
+
+        blocks.push(new_block);
+    }
+
+    return Ok(TensorMap::new(tensor.keys().clone(), blocks)?);
+}
+
+/// Build a `TensorMap` containing the samples and properties of `descriptor`
+/// associated with the system at index `system`, renumbered as if this system
+/// was the only one in the calculation. This is used as a predefined selection
+/// when computing systems one at the time.
+fn system_selection(descriptor: &TensorMap, system: usize) -> Result<TensorMap, Error> {
+    let mut blocks = Vec::new();
+    for (_, block) in descriptor.iter() {
+        let samples = block.samples();
+        let names = samples.names();
+        let structure = names.iter().position(|&name| name == "structure").ok_or_else(|| Error::InvalidParameter(
+            "computing systems one at the time requires a 'structure' variable in the samples".into()
+        ))?;
+
+        let mut builder = LabelsBuilder::new(names.clone());
+        for entry in samples.iter() {
+            if entry[structure].usize() == system {
+                let mut entry = entry.to_vec();
+                entry[structure] = LabelValue::new(0);
+                builder.add(&entry);
+            }
+        }
+        let samples = builder.finish();
+        let properties = block.properties();
+
+        blocks.push(TensorBlock::new(
+            EmptyArray::new(vec![samples.count(), properties.count()]),
+            &samples,
+            &[],
+            &properties,
+        )?);
+    }
+
+    return Ok(TensorMap::new(descriptor.keys().clone(), blocks)?);
+}
+
+/// Only keep the entries of the positions `gradient_samples` corresponding to
+/// one of the selected `atoms`
+fn select_gradient_atoms(gradient_samples: &Labels, atoms: &Labels) -> Labels {
+    debug_assert_eq!(gradient_samples.names(), ["sample", "structure", "atom"]);
+
+    let mut builder = LabelsBuilder::new(gradient_samples.names());
+    for entry in gradient_samples.iter() {
+        if atoms.contains(&entry[1..]) {
+            builder.add(entry);
+        }
+    }
+
+    return builder.finish();
+}
+
+/// Get the selected gradient `atoms` in the system at index `system`,
+/// renumbered as if this system was the only one in the calculation.
+fn system_gradient_atoms(atoms: &Labels, system: usize) -> Labels {
+    let mut builder = LabelsBuilder::new(atoms.names());
+    for &[structure, atom] in atoms.iter_fixed_size() {
+        if structure.usize() == system {
+            builder.add(&[LabelValue::new(0), atom]);
+        }
+    }
+
+    return builder.finish();
+}
+
+/// Copy the values and gradients in `partial`, computed for the system at
+/// index `system` only, to the corresponding samples of `descriptor`.
+fn copy_system_data(partial: &TensorMap, descriptor: &mut TensorMap, system: usize) {
+    for ((_, mut block), (_, partial_block)) in descriptor.iter_mut().zip(partial.iter()) {
+        let partial_samples = offset_structure(&partial_block.samples(), system);
+        let partial_values = partial_block.values().to_array();
+
+        let mut samples_mapping = Vec::with_capacity(partial_samples.count());
+        {
+            let mut block_data = block.data_mut();
+            let values = block_data.values.as_array_mut();
+            for (partial_sample_i, entry) in partial_samples.iter().enumerate() {
+                let sample_i = block_data.samples.position(entry).expect("missing sample in descriptor");
+                values.index_axis_mut(Axis(0), sample_i).assign(&partial_values.index_axis(Axis(0), partial_sample_i));
+                samples_mapping.push(sample_i);
+            }
+        }
+
+        for parameter in ALL_GRADIENT_PARAMETERS {
+            if let Some(mut gradient) = block.gradient_mut(parameter) {
+                let partial_gradient = partial_block.gradient(parameter).expect("missing gradient in partial calculation");
+                let partial_gradient_samples = offset_structure(&partial_gradient.samples(), system);
+                let partial_gradient_values = partial_gradient.values().to_array();
+
+                let gradient_data = gradient.data_mut();
+                let values = gradient_data.values.to_array_mut();
+                for (partial_sample_i, entry) in partial_gradient_samples.iter().enumerate() {
+                    // the first variable of gradient samples is the index of
+                    // the corresponding sample in the values
+                    let mut entry = entry.to_vec();
+                    entry[0] = LabelValue::from(samples_mapping[entry[0].usize()]);
+
+                    let sample_i = gradient_data.samples.position(&entry).expect("missing gradient sample in descriptor");
+                    values.index_axis_mut(Axis(0), sample_i).assign(&partial_gradient_values.index_axis(Axis(0), partial_sample_i));
+                }
+            }
+        }
+    }
+}
+
+/// Convert `systems` to `SimpleSystem` if requested by `options`, returning
+/// `None` if the systems should be used directly.
+fn native_systems(systems: &mut [Box<dyn System>], options: CalculationOptions) -> Result<Option<Vec<Box<dyn System>>>, Error> {
+    if !options.use_native_system && options.cell_inference == CellInference::Keep {
+        return Ok(None);
+    }
+
+    let mut native_systems = Vec::with_capacity(systems.len());
+    for system in systems {
+        let mut native = SimpleSystem::try_from(&**system)?;
+        if let CellInference::BoundingBox { padding } = options.cell_inference {
+            if native.cell.is_infinite() && (options.gradients.contains(&"cell") || options.gradients.contains(&"strain")) {
+                return Err(Error::InvalidParameter(
+                    "can not compute cell or strain gradients with an inferred bounding box cell".into()
+                ));
+            }
+            native.infer_bounding_box(padding)?;
+        }
+        native_systems.push(Box::new(native) as Box<dyn System>);
+    }
+
+    return Ok(Some(native_systems));
+}
+
+/// Check that `velocities` in the `options` (if any) contain one velocity for
+/// each atom in each of the `systems`.
+fn check_velocities(systems: &[Box<dyn System>], options: CalculationOptions) -> Result<(), Error> {
+    if let Some(velocities) = options.velocities {
+        if velocities.len() != systems.len() {
+            return Err(Error::InvalidParameter(format!(
+                "expected velocities for {} systems, got {}",
+                systems.len(), velocities.len()
+            )));
+        }
+
+        for (system, velocities) in systems.iter().zip(velocities) {
+            if velocities.len() != system.size()? {
+                return Err(Error::InvalidParameter(format!(
+                    "expected {} velocities for a system, got {}",
+                    system.size()?, velocities.len()
+                )));
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// Apply the `options.post_process` function (if any) to all the blocks of
+/// the `descriptor`
+fn apply_post_process(descriptor: &mut TensorMap, options: CalculationOptions) {
+    if let Some(post_process) = options.post_process {
+        for (key, mut block) in descriptor.iter_mut() {
+            let array = block.values_mut().to_array_mut();
+            let n_samples = array.shape()[0];
+            let n_features = array.shape()[1..].iter().product::<usize>();
+
+            let mut values = array.view_mut()
+                .into_shape((n_samples, n_features))
+                .expect("values should be contiguous");
+            (post_process.0)(key, &mut values);
+        }
+    }
+}
+
+/// Metadata of the descriptor produced by a calculation, see
+/// `Calculator::prepare_metadata`.
+struct DescriptorMetadata {
+    keys: Labels,
+    /// samples for each block
+    samples: Vec<Labels>,
+    /// components for each block
+    components: Vec<Vec<Labels>>,
+    /// properties for each block
+    properties: Vec<Labels>,
+    /// requested gradient parameters, together with the gradient samples for
+    /// each block
+    gradients: Vec<(&'static str, Vec<Labels>)>,
+}
+
+/// Get the components of the gradients with respect to `parameter` for a
+/// block with the given `components`
+fn gradient_components(parameter: &str, components: &[Labels]) -> Vec<Labels> {
+    let mut gradient_components = match parameter {
+        "positions" => vec![Labels::new(["direction"], &[[0], [1], [2]])],
+        "cell" | "strain" => vec![
+            Labels::new(["direction_1"], &[[0], [1], [2]]),
+            Labels::new(["direction_2"], &[[0], [1], [2]]),
+        ],
+        // gradients w.r.t. a scalar parameter use the same components as
+        // the values
+        "atomic_gaussian_width" => Vec::new(),
+        _ => unreachable!("unknown gradient parameter {}", parameter),
+    };
+    gradient_components.extend(components.iter().cloned());
+    return gradient_components;
+}
+
+/// Check that the metadata of an existing `descriptor` matches the
+/// `expected` metadata for a new calculation
+fn check_descriptor_metadata(descriptor: &TensorMap, expected: &DescriptorMetadata) -> Result<(), Error> {
+    if descriptor.keys() != &expected.keys {
+        return Err(Error::InvalidParameter(
+            "the keys of the descriptor do not match the keys of this calculation".into()
+        ));
+    }
+
+    for (block_i, (_, block)) in descriptor.iter().enumerate() {
+        if block.samples() != expected.samples[block_i] {
+            return Err(Error::InvalidParameter(
+                "the samples of the descriptor do not match the samples of this calculation".into()
+            ));
+        }
+
+        if block.components() != expected.components[block_i] || block.properties() != expected.properties[block_i] {
+            return Err(Error::InvalidParameter(
+                "the components or properties of the descriptor do not match the ones of this calculation".into()
+            ));
+        }
+
+        for parameter in ALL_GRADIENT_PARAMETERS {
+            let expected_samples = expected.gradients.iter()
+                .find(|(expected_parameter, _)| *expected_parameter == parameter)
+                .map(|(_, samples)| &samples[block_i]);
+
+            match (block.gradient(parameter), expected_samples) {
+                (None, None) => {}
+                (Some(gradient), Some(expected_samples)) => {
+                    if &gradient.samples() != expected_samples || gradient.components() != gradient_components(parameter, &expected.components[block_i]) {
+                        return Err(Error::InvalidParameter(format!(
+                            "the {} gradients of the descriptor do not match the ones of this calculation",
+                            parameter
+                        )));
+                    }
+                }
+                _ => {
+                    return Err(Error::InvalidParameter(format!(
+                        "the descriptor and this calculation do not request the same {} gradients",
+                        parameter
+                    )));
+                }
+            }
+        }
+    }
+
+    return Ok(());
 }
 
 fn shape_from_labels(samples: &Labels, components: &[Labels], properties: &Labels) -> Vec<usize> {
@@ -476,44 +1490,553 @@ use crate::calculators::AtomicComposition;
 use crate::calculators::DummyCalculator;
 use crate::calculators::SortedDistances;
 use crate::calculators::NeighborList;
+use crate::calculators::CoulombMatrix;
+use crate::calculators::{CoordinationNumber, CoordinationNumberParameters};
+use crate::calculators::{SteinhardtOrderParameters, SteinhardtParameters};
+use crate::calculators::{PairDistanceHistogram, PairDistanceHistogramParameters};
 use crate::calculators::{SphericalExpansionByPair, SphericalExpansionParameters};
 use crate::calculators::SphericalExpansion;
 use crate::calculators::{SoapPowerSpectrum, PowerSpectrumParameters};
 use crate::calculators::{SoapRadialSpectrum, RadialSpectrumParameters};
+use crate::calculators::{SoapBispectrum, BispectrumParameters};
+use crate::calculators::{LambdaSoap, LambdaSoapParameters};
 use crate::calculators::{LodeSphericalExpansion, LodeSphericalExpansionParameters};
+use crate::calculators::{BehlerG2, BehlerG2Parameters};
+use crate::calculators::{BehlerG4, BehlerG4Parameters};
 type CalculatorCreator = fn(&str) -> Result<Box<dyn CalculatorBase>, Error>;
+type ParametersSchema = fn() -> schemars::schema::RootSchema;
 
 macro_rules! add_calculator {
     ($map :expr, $name :literal, $type :ty) => (
-        $map.insert($name, (|json| {
+        $map.insert($name, ((|json| {
             let value = serde_json::from_str::<$type>(json)?;
             Ok(Box::new(value))
-        }) as CalculatorCreator);
+        }) as CalculatorCreator, (|| schemars::schema_for!($type)) as ParametersSchema));
     );
     ($map :expr, $name :literal, $type :ty, $parameters :ty) => (
-        $map.insert($name, (|json| {
+        $map.insert($name, ((|json| {
             let parameters = serde_json::from_str::<$parameters>(json)?;
             Ok(Box::new(<$type>::new(parameters)?))
-        }) as CalculatorCreator);
+        }) as CalculatorCreator, (|| schemars::schema_for!($parameters)) as ParametersSchema));
     );
 }
 
 // this code is included in the calculator tutorial, the tags below indicate the
 // first/last line to include
 // [calculator-registration]
-static REGISTERED_CALCULATORS: Lazy<BTreeMap<&'static str, CalculatorCreator>> = Lazy::new(|| {
+static REGISTERED_CALCULATORS: Lazy<BTreeMap<&'static str, (CalculatorCreator, ParametersSchema)>> = Lazy::new(|| {
     let mut map = BTreeMap::new();
     add_calculator!(map, "atomic_composition", AtomicComposition);
     add_calculator!(map, "dummy_calculator", DummyCalculator);
     add_calculator!(map, "neighbor_list", NeighborList);
     add_calculator!(map, "sorted_distances", SortedDistances);
+    add_calculator!(map, "coulomb_matrix", CoulombMatrix);
+    add_calculator!(map, "coordination_number", CoordinationNumber, CoordinationNumberParameters);
+    add_calculator!(map, "steinhardt_order_parameters", SteinhardtOrderParameters, SteinhardtParameters);
+    add_calculator!(map, "pair_distance_histogram", PairDistanceHistogram, PairDistanceHistogramParameters);
 
     add_calculator!(map, "spherical_expansion_by_pair", SphericalExpansionByPair, SphericalExpansionParameters);
     add_calculator!(map, "spherical_expansion", SphericalExpansion, SphericalExpansionParameters);
     add_calculator!(map, "soap_radial_spectrum", SoapRadialSpectrum, RadialSpectrumParameters);
     add_calculator!(map, "soap_power_spectrum", SoapPowerSpectrum, PowerSpectrumParameters);
+    add_calculator!(map, "soap_bispectrum", SoapBispectrum, BispectrumParameters);
+    add_calculator!(map, "lambda_soap", LambdaSoap, LambdaSoapParameters);
 
     add_calculator!(map, "lode_spherical_expansion", LodeSphericalExpansion, LodeSphericalExpansionParameters);
+
+    add_calculator!(map, "behler_g2", BehlerG2, BehlerG2Parameters);
+    add_calculator!(map, "behler_g4", BehlerG4, BehlerG4Parameters);
     return map;
 });
 // [calculator-registration]
+
+
+#[cfg(test)]
+mod tests {
+    use approx::{assert_ulps_eq, assert_relative_eq};
+    use equistore::Labels;
+    use ndarray::Axis;
+
+    use crate::systems::test_utils::test_systems;
+    use crate::systems::UnitCell;
+    use crate::calculators::tests_utils::soap_calculator;
+    use crate::{SimpleSystem, System, Vector3D};
+    use super::{Calculator, CalculationOptions, CellInference, LabelsSelection, PostProcess, ProgressCallback};
+
+    #[test]
+    fn compute_f32() {
+        let parameters = r#"{
+            "cutoff": 3.5,
+            "max_radial": 6,
+            "max_angular": 4,
+            "atomic_gaussian_width": 0.3,
+            "center_atom_weight": 1.0,
+            "radial_basis": {"Gto": {}},
+            "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+        }"#;
+
+        for name in ["spherical_expansion", "soap_power_spectrum"] {
+            let mut calculator = Calculator::new(name, parameters.into()).unwrap();
+            let mut systems = test_systems(&["water", "methane"]);
+
+            let reference = calculator.compute(&mut systems, Default::default()).unwrap();
+            let single = calculator.compute_f32(&mut systems, Default::default()).unwrap();
+
+            assert_eq!(&single.keys, reference.keys());
+            assert_eq!(single.blocks.len(), reference.keys().count());
+            for ((_, block), single) in reference.iter().zip(&single.blocks) {
+                assert_eq!(single.samples, block.samples());
+                assert_eq!(single.properties, block.properties());
+
+                let expected = block.values().to_array().mapv(|value| value as f32);
+                assert_relative_eq!(single.values, expected, max_relative=1e-6);
+
+                let values = single.values.mapv(f64::from);
+                assert_relative_eq!(values, block.values().to_array().to_owned(), epsilon=1e-6, max_relative=1e-6);
+            }
+        }
+
+        let mut calculator = Calculator::new(
+            "dummy_calculator",
+            r#"{"cutoff": 1.5, "delta": 3, "name": ""}"#.into()
+        ).unwrap();
+        let mut systems = test_systems(&["water"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let error = calculator.compute_f32(&mut systems, options).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: gradients are not supported in single precision calculations");
+    }
+
+    #[test]
+    fn compute_blocks() {
+        let mut calculator = Calculator::new(
+            "dummy_calculator",
+            r#"{"cutoff": 1.5, "delta": 3, "name": ""}"#.into()
+        ).unwrap();
+        let mut systems = test_systems(&["water", "methane"]);
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        let blocks = calculator.compute_blocks(&mut systems, options)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(blocks.len(), descriptor.keys().count());
+        for ((key, block), (expected_key, expected)) in blocks.iter().zip(descriptor.iter()) {
+            assert_eq!(key, expected_key);
+
+            let block = block.as_ref();
+            assert_eq!(block.samples(), expected.samples());
+            assert_eq!(block.components(), expected.components());
+            assert_eq!(block.properties(), expected.properties());
+            assert_ulps_eq!(block.values().to_array(), expected.values().to_array());
+
+            let gradient = block.gradient("positions").unwrap();
+            let expected_gradient = expected.gradient("positions").unwrap();
+            assert_eq!(gradient.samples(), expected_gradient.samples());
+            assert_ulps_eq!(gradient.values().to_array(), expected_gradient.values().to_array());
+        }
+    }
+
+    #[test]
+    fn effective_feature_count() {
+        let mut calculator = soap_calculator("spherical_expansion");
+        let mut systems = test_systems(&["water"]);
+
+        let properties = Labels::new(["n"], &[[0], [2]]);
+        // only keep the oxygen center, making the blocks centered on
+        // hydrogen empty
+        let samples = Labels::new(["center"], &[[0]]);
+        let options = CalculationOptions {
+            selected_properties: LabelsSelection::Subset(&properties),
+            selected_samples: LabelsSelection::Subset(&samples),
+            ..Default::default()
+        };
+
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+        let mut expected = 0;
+        let mut empty_blocks = 0;
+        for (_, block) in descriptor.iter() {
+            if block.samples().count() == 0 {
+                empty_blocks += 1;
+            } else {
+                assert_eq!(block.properties().count(), 2);
+                expected += block.properties().count();
+            }
+        }
+        assert_ne!(empty_blocks, 0);
+        assert_ne!(expected, 0);
+
+        let count = calculator.effective_feature_count(&mut systems, options).unwrap();
+        assert_eq!(count, expected);
+    }
+
+    #[test]
+    fn bounding_box_cell_inference() {
+        let mut calculator = soap_calculator("spherical_expansion");
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, 0.75545, -0.58895));
+        system.add_atom(1, Vector3D::new(0.0, -0.75545, -0.58895));
+        system.add_atom(6, Vector3D::new(2.0, 0.5, 1.0));
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let vacuum = calculator.compute(&mut systems, options).unwrap();
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            cell_inference: CellInference::BoundingBox { padding: 3.5 },
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(descriptor.keys(), vacuum.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(vacuum.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-14
+            );
+
+            let gradient = block.gradient("positions").unwrap();
+            let expected_gradient = expected.gradient("positions").unwrap();
+            assert_eq!(gradient.samples(), expected_gradient.samples());
+            assert_relative_eq!(
+                gradient.values().to_array(), expected_gradient.values().to_array(),
+                max_relative=1e-12, epsilon=1e-14
+            );
+        }
+
+        // cell gradients are not defined for the inferred cell
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            cell_inference: CellInference::BoundingBox { padding: 3.5 },
+            ..Default::default()
+        };
+        assert!(calculator.compute(&mut systems, options).is_err());
+    }
+
+    #[test]
+    fn post_process() {
+        // only scale the blocks for hydrogen centers
+        fn scale(key: &[equistore::LabelValue], values: &mut ndarray::ArrayViewMut2<'_, f64>) {
+            if key[0].i32() == 1 {
+                *values *= 2.0;
+            }
+        }
+
+        let mut calculator = Calculator::new(
+            "dummy_calculator",
+            r#"{"cutoff": 1.5, "delta": 3, "name": ""}"#.into()
+        ).unwrap();
+        let mut systems = test_systems(&["water", "methane"]);
+
+        let reference = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let options = CalculationOptions {
+            post_process: Some(PostProcess(&scale)),
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(descriptor.keys(), reference.keys());
+        for ((key, block), (_, expected)) in descriptor.iter().zip(reference.iter()) {
+            let factor = if key[0].i32() == 1 { 2.0 } else { 1.0 };
+            assert_ulps_eq!(
+                block.values().to_array(),
+                &(expected.values().to_array() * factor),
+            );
+        }
+    }
+
+    #[test]
+    fn compute_into() {
+        let mut calculator = Calculator::new("spherical_expansion", r#"{
+            "cutoff": 3.5,
+            "max_radial": 6,
+            "max_angular": 4,
+            "atomic_gaussian_width": 0.3,
+            "center_atom_weight": 1.0,
+            "radial_basis": {"Gto": {}},
+            "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+        }"#.into()).unwrap();
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+
+        let water = crate::systems::test_utils::test_system("water");
+        let mut systems = vec![Box::new(water.clone()) as Box<dyn System>];
+        let mut descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        let mut displaced = water;
+        displaced.positions_mut()[1][0] += 0.1;
+        displaced.positions_mut()[2][2] -= 0.05;
+        let mut systems = vec![Box::new(displaced) as Box<dyn System>];
+        let expected = calculator.compute(&mut systems, options).unwrap();
+
+        calculator.compute_into(&mut systems, &mut descriptor, options).unwrap();
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_ulps_eq!(block.values().to_array(), expected.values().to_array());
+
+            let gradient = block.gradient("positions").unwrap();
+            let expected = expected.gradient("positions").unwrap();
+            assert_eq!(gradient.samples(), expected.samples());
+            assert_ulps_eq!(gradient.values().to_array(), expected.values().to_array());
+        }
+
+        // different gradients
+        let error = calculator.compute_into(&mut systems, &mut descriptor, Default::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: the descriptor and this calculation do not request the same positions gradients"
+        );
+
+        // different systems
+        let error = calculator.compute_into(&mut test_systems(&["methane"]), &mut descriptor, options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: the keys of the descriptor do not match the keys of this calculation"
+        );
+    }
+
+    #[test]
+    fn thread_pool() {
+        let parameters = r#"{
+            "cutoff": 3.5,
+            "max_radial": 6,
+            "max_angular": 4,
+            "atomic_gaussian_width": 0.3,
+            "center_atom_weight": 1.0,
+            "radial_basis": {"Gto": {}},
+            "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+        }"#;
+
+        let mut calculator = Calculator::new("soap_power_spectrum", parameters.into()).unwrap();
+        let mut systems = test_systems(&["water", "methane"]);
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let reference = calculator.compute(&mut systems, options).unwrap();
+
+        let thread_pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            thread_pool: Some(&thread_pool),
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(descriptor.keys(), reference.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(reference.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            let gradient = block.gradient("positions").unwrap();
+            let expected = expected.gradient("positions").unwrap();
+            assert_relative_eq!(
+                gradient.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+        }
+    }
+
+    #[test]
+    fn progress_callback() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let mut systems = test_systems(&["water", "CH", "methane"]);
+
+        let selected_samples = Labels::new(["center"], &[[0], [1]]);
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            selected_samples: LabelsSelection::Subset(&selected_samples),
+            ..Default::default()
+        };
+        let reference = calculator.compute(&mut systems, options).unwrap();
+
+        let progress = std::sync::Mutex::new(Vec::new());
+        let callback = |done: usize, total: usize| progress.lock().unwrap().push((done, total));
+        let options = CalculationOptions {
+            progress_callback: Some(ProgressCallback(&callback)),
+            ..options
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(*progress.lock().unwrap(), [(1, 3), (2, 3), (3, 3)]);
+
+        assert_eq!(descriptor.keys(), reference.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(reference.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            for parameter in ["positions", "cell"] {
+                let gradient = block.gradient(parameter).unwrap();
+                let expected = expected.gradient(parameter).unwrap();
+                assert_eq!(gradient.samples(), expected.samples());
+                assert_relative_eq!(
+                    gradient.values().to_array(), expected.values().to_array(),
+                    max_relative=1e-12, epsilon=1e-16
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compute_streaming() {
+        let mut calculator = Calculator::new(
+            "dummy_calculator",
+            r#"{"cutoff": 1.5, "delta": 3, "name": ""}"#.into()
+        ).unwrap();
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let reference = calculator.compute(&mut test_systems(&["water", "methane"]), options).unwrap();
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            selected_keys: Some(reference.keys()),
+            ..Default::default()
+        };
+        let mut chunks = Vec::new();
+        calculator.compute_streaming(test_systems(&["water", "methane"]), 1, options, |tensor| {
+            chunks.push(tensor);
+            Ok(())
+        }).unwrap();
+        assert_eq!(chunks.len(), 2);
+
+        for (block_i, (_, block)) in reference.iter().enumerate() {
+            let values = block.values().to_array();
+            let gradient = block.gradient("positions").unwrap();
+            let gradient_values = gradient.values().to_array();
+
+            let mut n_samples = 0;
+            let mut n_gradient_samples = 0;
+            for chunk in &chunks {
+                assert_eq!(chunk.keys(), reference.keys());
+
+                let chunk_block = chunk.block_by_id(block_i);
+                let chunk_values = chunk_block.values().to_array();
+                for (chunk_sample_i, sample) in chunk_block.samples().iter().enumerate() {
+                    let sample_i = block.samples().position(sample).unwrap();
+                    assert_eq!(
+                        chunk_values.index_axis(Axis(0), chunk_sample_i),
+                        values.index_axis(Axis(0), sample_i)
+                    );
+                    n_samples += 1;
+                }
+
+                let chunk_gradient = chunk_block.gradient("positions").unwrap();
+                let chunk_gradient_values = chunk_gradient.values().to_array();
+                for (chunk_sample_i, [sample, structure, atom]) in chunk_gradient.samples().iter_fixed_size().enumerate() {
+                    let sample = &chunk_block.samples()[sample.usize()];
+                    let sample_i = block.samples().position(sample).unwrap();
+                    let gradient_i = gradient.samples().position(&[sample_i.into(), *structure, *atom]).unwrap();
+                    assert_eq!(
+                        chunk_gradient_values.index_axis(Axis(0), chunk_sample_i),
+                        gradient_values.index_axis(Axis(0), gradient_i)
+                    );
+                    n_gradient_samples += 1;
+                }
+            }
+            assert_eq!(n_samples, block.samples().count());
+            assert_eq!(n_gradient_samples, gradient.samples().count());
+        }
+
+        // without selected keys, the keys of the first chunk are used for all
+        // chunks, and methane contains carbon which is not in water
+        let error = calculator.compute_streaming(test_systems(&["water", "methane"]), 1, Default::default(), |_| Ok(())).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: the systems starting at index 1 contain keys which are not in the first chunk, \
+            use `selected_keys` to specify the keys of all chunks"
+        );
+    }
+
+    #[test]
+    fn create_from_name() {
+        let parameters = r#"{
+            "cutoff": 3.5,
+            "max_radial": 4,
+            "max_angular": 3,
+            "atomic_gaussian_width": 0.3,
+            "center_atom_weight": 1.0,
+            "radial_basis": {"Gto": {}},
+            "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+        }"#;
+
+        let all_parameters = [
+            ("spherical_expansion", parameters),
+            ("soap_power_spectrum", parameters),
+            ("sorted_distances", r#"{"cutoff": 3.5, "max_neighbors": 5, "separate_neighbor_species": false}"#),
+            ("dummy_calculator", r#"{"cutoff": 1.5, "delta": 3, "name": ""}"#),
+        ];
+
+        let mut systems = test_systems(&["water"]);
+        for (name, parameters) in all_parameters {
+            let mut calculator = Calculator::new(name, parameters.into()).unwrap();
+            assert_eq!(calculator.registered_name(), Some(name));
+            let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+            // round-trip through the registered name and parameters
+            let mut copy = Calculator::new(
+                calculator.registered_name().unwrap(),
+                calculator.parameters().into(),
+            ).unwrap();
+            assert_eq!(copy.name(), calculator.name());
+
+            let copy_descriptor = copy.compute(&mut systems, Default::default()).unwrap();
+            assert_eq!(copy_descriptor.keys(), descriptor.keys());
+            for ((_, block), (_, expected)) in copy_descriptor.iter().zip(descriptor.iter()) {
+                assert_eq!(block.values().to_array(), expected.values().to_array());
+            }
+        }
+
+        let calculator = Calculator::from(Box::new(
+            crate::calculators::DummyCalculator { cutoff: 1.5, delta: 3, name: String::new(), gradients: false },
+        ) as Box<dyn crate::calculators::CalculatorBase>);
+        assert_eq!(calculator.registered_name(), None);
+
+        let error = Calculator::new("not_a_calculator", "{}".into()).err().unwrap();
+        assert_eq!(error.to_string(), "invalid parameter: unknown calculator with name 'not_a_calculator'");
+    }
+
+    #[test]
+    fn parameters_schema() {
+        let schema = Calculator::parameters_schema("soap_power_spectrum").unwrap();
+        let schema: serde_json::Value = serde_json::from_str(&schema).unwrap();
+        assert_eq!(schema["title"], "PowerSpectrumParameters");
+        assert!(schema["properties"].get("radial_scaling").is_some());
+        assert!(schema["properties"].get("cutoff").is_some());
+
+        let schema = Calculator::parameters_schema("sorted_distances").unwrap();
+        let schema: serde_json::Value = serde_json::from_str(&schema).unwrap();
+        assert!(schema["properties"].get("max_neighbors").is_some());
+
+        let error = Calculator::parameters_schema("not_a_calculator").unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: unknown calculator with name 'not_a_calculator'");
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+
+use equistore::{Labels, LabelsBuilder, TensorMap};
+
+use super::CalculatorBase;
+use super::soap::CutoffFunction;
+
+use crate::{Error, System, Vector3D};
+use crate::systems::CellShape;
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterSingleNeighborsSpeciesKeys};
+
+/// Parameters for the smooth coordination number calculator.
+///
+/// The coordination number of atom `i` with respect to a given neighbor
+/// species is defined as `\sum_j f_c(r_ij)`, where the sum runs over all
+/// neighbors `j` of this species within the cutoff and `f_c` is the cutoff
+/// function. With a smooth cutoff function, this gives a differentiable count
+/// of the neighbors.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct CoordinationNumberParameters {
+    /// Spherical cutoff to use for atomic environments
+    pub cutoff: f64,
+    /// cutoff function `f_c` used to count the neighbors
+    pub cutoff_function: CutoffFunction,
+}
+
+/// Calculator computing the smooth coordination number of each atom
+#[derive(Debug)]
+pub struct CoordinationNumber {
+    parameters: CoordinationNumberParameters,
+}
+
+impl CoordinationNumber {
+    pub fn new(parameters: CoordinationNumberParameters) -> Result<CoordinationNumber, Error> {
+        if !(parameters.cutoff > 0.0 && parameters.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number, got {}", parameters.cutoff
+            )));
+        }
+
+        parameters.cutoff_function.validate()?;
+
+        return Ok(CoordinationNumber { parameters: parameters });
+    }
+}
+
+/// Contribution of a single neighbor to the coordination number of a center
+struct NeighborContribution {
+    /// index of the sample corresponding to the center
+    sample_i: usize,
+    center: usize,
+    neighbor: usize,
+    /// vector from the center to the neighbor
+    vector: Vector3D,
+    /// distance between the center and the neighbor
+    distance: f64,
+}
+
+impl CalculatorBase for CoordinationNumber {
+    fn name(&self) -> String {
+        "coordination number".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        let builder = CenterSingleNeighborsSpeciesKeys {
+            cutoff: self.parameters.cutoff,
+            self_pairs: false,
+            all_species: None,
+        };
+        return builder.keys(systems);
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor"]);
+        let mut result = Vec::new();
+        for [species_center, species_neighbor] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn supports_gradient(&self, parameter: &str) -> bool {
+        match parameter {
+            "positions" | "cell" => true,
+            _ => false,
+        }
+    }
+
+    fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor"]);
+        assert_eq!(keys.count(), samples.len());
+
+        let mut gradient_samples = Vec::new();
+        for ([species_center, species_neighbor], samples) in keys.iter_fixed_size().zip(samples) {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            gradient_samples.push(builder.gradients_for(systems, samples)?);
+        }
+
+        return Ok(gradient_samples);
+    }
+
+    fn components(&self, keys: &Labels) -> Vec<Vec<Labels>> {
+        return vec![Vec::new(); keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        vec!["count"]
+    }
+
+    fn properties(&self, keys: &Labels) -> Vec<Labels> {
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        properties.add(&[0]);
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "CoordinationNumber::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["species_center", "species_neighbor"]);
+
+        let cutoff = self.parameters.cutoff;
+        let cutoff_function = &self.parameters.cutoff_function;
+
+        for (key, mut block) in descriptor.iter_mut() {
+            let species_neighbor = key[1].i32();
+
+            let samples = block.samples();
+            let n_properties = block.properties().count();
+
+            // mapping from structure to center to sample index
+            let mut centers = BTreeMap::new();
+            for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                centers.entry(structure.usize())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(center.usize(), sample_i);
+            }
+
+            let do_cell_gradients = block.gradient("cell").is_some();
+
+            let mut contributions = Vec::new();
+            let mut inverse_cells = BTreeMap::new();
+            for (&structure, centers) in &centers {
+                let system = &mut *systems[structure];
+                system.compute_neighbors(cutoff)?;
+                let species = system.species()?;
+
+                if do_cell_gradients {
+                    let cell = system.cell()?;
+                    if cell.shape() == CellShape::Infinite {
+                        return Err(Error::InvalidParameter(
+                            "can not compute cell gradients for non periodic systems".into()
+                        ));
+                    }
+                    inverse_cells.insert(structure, cell.matrix().inverse());
+                }
+
+                for pair in system.pairs()? {
+                    if species[pair.second] == species_neighbor {
+                        if let Some(&sample_i) = centers.get(&pair.first) {
+                            contributions.push(NeighborContribution {
+                                sample_i: sample_i,
+                                center: pair.first,
+                                neighbor: pair.second,
+                                vector: pair.vector,
+                                distance: pair.distance,
+                            });
+                        }
+                    }
+
+                    if pair.first == pair.second {
+                        // the pair between an atom and its periodic image is
+                        // already included in both directions
+                        continue;
+                    }
+
+                    if species[pair.first] == species_neighbor {
+                        if let Some(&sample_i) = centers.get(&pair.second) {
+                            contributions.push(NeighborContribution {
+                                sample_i: sample_i,
+                                center: pair.second,
+                                neighbor: pair.first,
+                                vector: -pair.vector,
+                                distance: pair.distance,
+                            });
+                        }
+                    }
+                }
+            }
+
+            let values = block.values_mut().to_array_mut();
+            for contribution in &contributions {
+                let fc = cutoff_function.compute(contribution.distance, cutoff);
+                for property_i in 0..n_properties {
+                    values[[contribution.sample_i, property_i]] += fc;
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("positions") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                let mut gradient_rows = BTreeMap::new();
+                for (grad_sample_i, &[sample_i, _, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    gradient_rows.insert((sample_i.usize(), atom.usize()), grad_sample_i);
+                }
+
+                for contribution in &contributions {
+                    if contribution.neighbor == contribution.center {
+                        // periodic image of the center, the gradients
+                        // w.r.t. the center and neighbor cancel out
+                        continue;
+                    }
+
+                    let center_row = gradient_rows[&(contribution.sample_i, contribution.center)];
+                    let neighbor_row = gradient_rows[&(contribution.sample_i, contribution.neighbor)];
+
+                    let derivative = cutoff_function.derivative(contribution.distance, cutoff);
+                    let gradient = contribution.vector * (derivative / contribution.distance);
+                    for d in 0..3 {
+                        for property_i in 0..n_properties {
+                            array[[neighbor_row, d, property_i]] += gradient[d];
+                            array[[center_row, d, property_i]] -= gradient[d];
+                        }
+                    }
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("cell") {
+                let array = gradient.values_mut().to_array_mut();
+
+                for contribution in &contributions {
+                    let structure = samples[contribution.sample_i][0].usize();
+                    let inverse_cell = inverse_cells[&structure];
+
+                    let vector = contribution.vector;
+                    let inverse_cell_vector = Vector3D::new(
+                        vector[0] * inverse_cell[0][0] + vector[1] * inverse_cell[1][0] + vector[2] * inverse_cell[2][0],
+                        vector[0] * inverse_cell[0][1] + vector[1] * inverse_cell[1][1] + vector[2] * inverse_cell[2][1],
+                        vector[0] * inverse_cell[0][2] + vector[1] * inverse_cell[1][2] + vector[2] * inverse_cell[2][2],
+                    );
+
+                    let derivative = cutoff_function.derivative(contribution.distance, cutoff);
+                    let gradient = vector * (derivative / contribution.distance);
+
+                    // the cell gradients samples are the same as the values
+                    // samples, in the same order
+                    for spatial_1 in 0..3 {
+                        for spatial_2 in 0..3 {
+                            for property_i in 0..n_properties {
+                                array[[contribution.sample_i, spatial_1, spatial_2, property_i]] += inverse_cell_vector[spatial_2] * gradient[spatial_1];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use equistore::LabelValue;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+    use crate::calculators::soap::CutoffFunction;
+
+    use super::{CoordinationNumber, CoordinationNumberParameters};
+
+    fn parameters() -> CoordinationNumberParameters {
+        CoordinationNumberParameters {
+            cutoff: 3.5,
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 1.5 },
+        }
+    }
+
+    #[test]
+    fn values() {
+        let mut calculator = Calculator::from(Box::new(
+            CoordinationNumber::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 1.0));
+        system.add_atom(1, Vector3D::new(2.5, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, 4.0, 0.0));
+
+        let mut systems = [Box::new(system) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let block_i = descriptor.keys().position(&[LabelValue::new(8), LabelValue::new(1)]).unwrap();
+        let block = descriptor.block_by_id(block_i);
+        assert_eq!(block.samples().count(), 1);
+
+        let fc = CutoffFunction::ShiftedCosine { width: 1.5 };
+        let expected = 1.0 + fc.compute(2.5, 3.5);
+        assert_relative_eq!(block.values().to_array()[[0, 0]], expected, max_relative=1e-12);
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-6,
+            epsilon: 1e-16,
+        };
+
+        for name in ["water", "methane"] {
+            let calculator = Calculator::from(Box::new(
+                CoordinationNumber::new(parameters()).unwrap()
+            ) as Box<dyn CalculatorBase>);
+
+            let system = test_system(name);
+            crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+        }
+    }
+
+    #[test]
+    fn finite_differences_cell() {
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-6,
+            epsilon: 1e-16,
+        };
+
+        for name in ["water", "methane"] {
+            let calculator = Calculator::from(Box::new(
+                CoordinationNumber::new(parameters()).unwrap()
+            ) as Box<dyn CalculatorBase>);
+
+            let system = test_system(name);
+            crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
+        }
+    }
+}
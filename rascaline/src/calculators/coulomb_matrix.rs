@@ -0,0 +1,358 @@
+use ndarray::{Array2, s};
+
+use equistore::{Labels, LabelsBuilder, TensorMap};
+
+use super::CalculatorBase;
+
+use crate::{Error, System};
+use crate::labels::{SamplesBuilder, StructureSamples};
+use crate::math::SymmetricEigen;
+
+/// Which representation of the Coulomb matrix should be used as features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub enum CoulombMatrixOutput {
+    /// The full Coulomb matrix, with atoms in the same order as in the system
+    Raw,
+    /// The full Coulomb matrix, with rows and columns sorted by decreasing
+    /// norm of the rows
+    SortedRows,
+    /// The eigenvalues of the Coulomb matrix, sorted in decreasing order. The
+    /// zeros used for padding are always stored after all the eigenvalues.
+    Eigenvalues,
+}
+
+/// Coulomb matrix representation of a full structure.
+///
+/// The Coulomb matrix is defined as `M_ii = 0.5 Z_i^2.4` on the diagonal and
+/// `M_ij = Z_i Z_j / r_ij` outside of the diagonal, where `Z_i` is the nuclear
+/// charge of atom `i` (taken to be the atomic species) and `r_ij` the distance
+/// between atoms `i` and `j`. Since this matrix is defined for isolated
+/// molecules, periodic systems are not supported.
+///
+/// Systems with less than `max_size` atoms are padded with zeros, giving
+/// features of the same size for all systems. There is a single sample for
+/// each structure, and gradients are not supported.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct CoulombMatrix {
+    /// Maximal number of atoms in the systems. This is the size of the matrix
+    /// (or the number of eigenvalues) used as features.
+    pub max_size: usize,
+    /// Which representation of the Coulomb matrix should be used
+    pub output: CoulombMatrixOutput,
+}
+
+impl CoulombMatrix {
+    /// Compute the Coulomb matrix for the given system, padded with zeros to
+    /// `max_size`
+    fn matrix(&self, system: &dyn System) -> Result<Array2<f64>, Error> {
+        if !system.cell()?.is_infinite() {
+            return Err(Error::InvalidParameter(
+                "Coulomb matrix can not be computed for periodic systems".into()
+            ));
+        }
+
+        let species = system.species()?;
+        let positions = system.positions()?;
+
+        let n_atoms = species.len();
+        if n_atoms > self.max_size {
+            return Err(Error::InvalidParameter(format!(
+                "this system contains {} atoms, which is more than the \
+                maximal size of the Coulomb matrix ({})", n_atoms, self.max_size
+            )));
+        }
+
+        let mut charges = Vec::with_capacity(n_atoms);
+        for &species in species {
+            if species <= 0 {
+                return Err(Error::InvalidParameter(format!(
+                    "Coulomb matrix requires species to be atomic numbers, got {}", species
+                )));
+            }
+            charges.push(species as f64);
+        }
+
+        let mut matrix = Array2::zeros((self.max_size, self.max_size));
+        for i in 0..n_atoms {
+            matrix[[i, i]] = 0.5 * charges[i].powf(2.4);
+            for j in (i + 1)..n_atoms {
+                let distance = (positions[j] - positions[i]).norm();
+                let value = charges[i] * charges[j] / distance;
+                matrix[[i, j]] = value;
+                matrix[[j, i]] = value;
+            }
+        }
+
+        return Ok(matrix);
+    }
+}
+
+/// Sort the rows and columns of `matrix` by decreasing norm of the rows
+fn sort_rows(matrix: &Array2<f64>) -> Array2<f64> {
+    let norms = matrix.rows().into_iter()
+        .map(|row| row.dot(&row))
+        .collect::<Vec<_>>();
+
+    let mut order = (0..matrix.nrows()).collect::<Vec<_>>();
+    // stable sort, so the padding rows (with zero norm) stay at the end
+    order.sort_by(|&i, &j| norms[j].partial_cmp(&norms[i]).expect("got NaN in Coulomb matrix"));
+
+    let mut sorted = Array2::zeros(matrix.raw_dim());
+    for (new_i, &i) in order.iter().enumerate() {
+        for (new_j, &j) in order.iter().enumerate() {
+            sorted[[new_i, new_j]] = matrix[[i, j]];
+        }
+    }
+
+    return sorted;
+}
+
+impl CalculatorBase for CoulombMatrix {
+    fn name(&self) -> String {
+        "Coulomb matrix".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, _: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        return Ok(Labels::single());
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        StructureSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.count(), 1);
+        return Ok(vec![StructureSamples.samples(systems)?]);
+    }
+
+    fn supports_gradient(&self, _parameter: &str) -> bool {
+        return false;
+    }
+
+    fn positions_gradient_samples(&self, _: &Labels, _: &[Labels], _: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        return Err(Error::InvalidParameter(
+            "the Coulomb matrix calculator does not support gradients".into()
+        ));
+    }
+
+    fn components(&self, keys: &Labels) -> Vec<Vec<Labels>> {
+        return vec![Vec::new(); keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        match self.output {
+            CoulombMatrixOutput::Raw | CoulombMatrixOutput::SortedRows => vec!["row", "column"],
+            CoulombMatrixOutput::Eigenvalues => vec!["eigenvalue"],
+        }
+    }
+
+    fn properties(&self, keys: &Labels) -> Vec<Labels> {
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        match self.output {
+            CoulombMatrixOutput::Raw | CoulombMatrixOutput::SortedRows => {
+                for row in 0..self.max_size {
+                    for column in 0..self.max_size {
+                        properties.add(&[row, column]);
+                    }
+                }
+            }
+            CoulombMatrixOutput::Eigenvalues => {
+                for eigenvalue in 0..self.max_size {
+                    properties.add(&[eigenvalue]);
+                }
+            }
+        }
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "CoulombMatrix::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        for (_, mut block) in descriptor.iter_mut() {
+            let block_data = block.data_mut();
+            let array = block_data.values.to_array_mut();
+
+            for (sample_i, [structure_i]) in block_data.samples.iter_fixed_size().enumerate() {
+                let matrix = self.matrix(&*systems[structure_i.usize()])?;
+
+                match self.output {
+                    CoulombMatrixOutput::Raw | CoulombMatrixOutput::SortedRows => {
+                        let matrix = if self.output == CoulombMatrixOutput::SortedRows {
+                            sort_rows(&matrix)
+                        } else {
+                            matrix
+                        };
+
+                        for (property_i, [row, column]) in block_data.properties.iter_fixed_size().enumerate() {
+                            array[[sample_i, property_i]] = matrix[[row.usize(), column.usize()]];
+                        }
+                    }
+                    CoulombMatrixOutput::Eigenvalues => {
+                        // only diagonalize the block corresponding to actual
+                        // atoms, and pad after sorting. Otherwise the padding
+                        // zeros would end up between positive and negative
+                        // eigenvalues.
+                        let n_atoms = systems[structure_i.usize()].size()?;
+                        let mut eigenvalues = if n_atoms == 0 {
+                            Vec::new()
+                        } else {
+                            let matrix = matrix.slice(s![..n_atoms, ..n_atoms]).to_owned();
+                            SymmetricEigen::new(matrix).eigenvalues.to_vec()
+                        };
+                        eigenvalues.sort_by(|a, b| b.partial_cmp(a).expect("got NaN in Coulomb matrix eigenvalues"));
+                        eigenvalues.resize(self.max_size, 0.0);
+
+                        for (property_i, [eigenvalue]) in block_data.properties.iter_fixed_size().enumerate() {
+                            array[[sample_i, property_i]] = eigenvalues[eigenvalue.usize()];
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, CalculationOptions, System, Vector3D};
+
+    use super::super::CalculatorBase;
+    use super::{CoulombMatrix, CoulombMatrixOutput};
+
+    fn water() -> Vec<Box<dyn System>> {
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(1, Vector3D::new(0.0, 0.75, -0.5));
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, -0.75, -0.5));
+        return vec![Box::new(system) as Box<dyn System>];
+    }
+
+    fn calculator(max_size: usize, output: CoulombMatrixOutput) -> Calculator {
+        Calculator::from(Box::new(CoulombMatrix {
+            max_size: max_size,
+            output: output,
+        }) as Box<dyn CalculatorBase>)
+    }
+
+    #[test]
+    fn raw() {
+        let mut calculator = calculator(4, CoulombMatrixOutput::Raw);
+        let mut systems = water();
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let block = descriptor.block_by_id(0);
+        assert_eq!(block.samples().count(), 1);
+        assert_eq!(block.properties().count(), 16);
+
+        let values = block.values().to_array();
+        let r_oh = f64::sqrt(0.75 * 0.75 + 0.5 * 0.5);
+        let expected = [
+            0.5, 8.0 / r_oh, 1.0 / 1.5, 0.0,
+            8.0 / r_oh, 0.5 * f64::powf(8.0, 2.4), 8.0 / r_oh, 0.0,
+            1.0 / 1.5, 8.0 / r_oh, 0.5, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+        ];
+
+        for (property_i, &expected) in expected.iter().enumerate() {
+            assert_relative_eq!(values[[0, property_i]], expected, max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn sorted_rows() {
+        let mut calculator = calculator(4, CoulombMatrixOutput::SortedRows);
+        let mut systems = water();
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let values = descriptor.block_by_id(0).values().to_array();
+        let values = values.to_shape((4, 4)).unwrap();
+
+        // the oxygen row comes first
+        assert_relative_eq!(values[[0, 0]], 0.5 * f64::powf(8.0, 2.4), max_relative=1e-12);
+
+        let norms = values.rows().into_iter().map(|row| row.dot(&row)).collect::<Vec<_>>();
+        for i in 1..norms.len() {
+            assert!(norms[i - 1] >= norms[i]);
+        }
+        assert_eq!(norms[3], 0.0);
+
+        // sorting rows gives the same result for all permutations of atoms
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, -0.75, -0.5));
+        system.add_atom(1, Vector3D::new(0.0, 0.75, -0.5));
+        let mut permuted = vec![Box::new(system) as Box<dyn System>];
+        let permuted = calculator.compute(&mut permuted, Default::default()).unwrap();
+
+        assert_relative_eq!(
+            permuted.block_by_id(0).values().to_array(),
+            descriptor.block_by_id(0).values().to_array(),
+            max_relative=1e-12
+        );
+    }
+
+    #[test]
+    fn eigenvalues() {
+        let mut calculator = calculator(5, CoulombMatrixOutput::Eigenvalues);
+        let mut systems = water();
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let block = descriptor.block_by_id(0);
+        assert_eq!(block.properties().count(), 5);
+
+        let values = block.values().to_array();
+        for i in 1..3 {
+            assert!(values[[0, i - 1]] >= values[[0, i]]);
+        }
+
+        // the padding is always at the end, even with negative eigenvalues
+        assert!(values[[0, 2]] < 0.0);
+        assert_eq!(values[[0, 3]], 0.0);
+        assert_eq!(values[[0, 4]], 0.0);
+
+        // the sum of eigenvalues is the trace of the matrix
+        let trace = 0.5 + 0.5 * f64::powf(8.0, 2.4) + 0.5;
+        assert_relative_eq!(values.sum(), trace, max_relative=1e-12);
+
+        // the eigenvalues do not depend on the padding
+        let mut calculator = calculator(3, CoulombMatrixOutput::Eigenvalues);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        let expected = descriptor.block_by_id(0).values().to_array().clone();
+        assert_relative_eq!(values.slice(ndarray::s![.., ..3]), expected, max_relative=1e-12);
+    }
+
+    #[test]
+    fn errors() {
+        let mut systems = water();
+
+        // system too large
+        let mut calculator = calculator(2, CoulombMatrixOutput::Raw);
+        assert!(calculator.compute(&mut systems, Default::default()).is_err());
+
+        // no gradients
+        let mut calculator = self::calculator(3, CoulombMatrixOutput::Eigenvalues);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        assert!(calculator.compute(&mut systems, options).is_err());
+
+        // periodic systems
+        let mut system = SimpleSystem::new(UnitCell::cubic(10.0));
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+        assert!(calculator.compute(&mut systems, Default::default()).is_err());
+    }
+}
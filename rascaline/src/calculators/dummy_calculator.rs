@@ -15,6 +15,12 @@ use crate::{Error, System};
 ///
 /// The calculator has two features: one containing the atom index +
 /// `self.delta`, and the other one containing `x + y + z`.
+///
+/// If `gradients` is `true`, the calculator also produces deterministic,
+/// non-zero gradients with respect to positions and cell, built from the
+/// distances between the center and its neighbors. These are **not** the
+/// actual derivatives of the features, and are only intended to exercise the
+/// code handling gradients.
 #[doc(hidden)]
 #[derive(Debug, Clone)]
 #[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
@@ -25,6 +31,10 @@ pub struct DummyCalculator {
     pub delta: isize,
     /// Unused name parameter, to test passing string values
     pub name: String,
+    /// Produce non-zero gradients based on pair distances, and support
+    /// gradients with respect to the cell
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gradients: bool,
 }
 
 impl CalculatorBase for DummyCalculator {
@@ -56,6 +66,8 @@ impl CalculatorBase for DummyCalculator {
                 species_center: SpeciesFilter::Single(species_center.i32()),
                 species_neighbor: SpeciesFilter::Any,
                 self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             samples.push(builder.samples(systems)?);
@@ -67,6 +79,7 @@ impl CalculatorBase for DummyCalculator {
     fn supports_gradient(&self, parameter: &str) -> bool {
         match parameter {
             "positions" => true,
+            "cell" => self.gradients,
             _ => false,
         }
     }
@@ -80,6 +93,8 @@ impl CalculatorBase for DummyCalculator {
                 species_center: SpeciesFilter::Single(species_center.i32()),
                 species_neighbor: SpeciesFilter::Any,
                 self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             gradient_samples.push(builder.gradients_for(systems, samples)?);
@@ -115,6 +130,7 @@ impl CalculatorBase for DummyCalculator {
 
         for (key, mut block) in descriptor.iter_mut() {
             let species_center = key[0].i32();
+            let samples = block.samples();
 
             let block_data = block.data_mut();
             let array = block_data.values.to_array_mut();
@@ -153,16 +169,52 @@ impl CalculatorBase for DummyCalculator {
                 let gradient = gradient.data_mut();
                 let array = gradient.values.to_array_mut();
 
-                for gradient_sample_i in 0..array.shape()[0] {
+                for (gradient_sample_i, [sample_i, system, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    let value = if self.gradients {
+                        let center = samples[sample_i.usize()][1].usize();
+                        let distances = self.pair_distances(&mut *systems[system.usize()], center)?;
+                        if atom.usize() == center {
+                            distances.iter().map(|(_, distance)| distance).sum()
+                        } else {
+                            distances.iter()
+                                .filter(|(neighbor, _)| *neighbor == atom.usize())
+                                .map(|(_, distance)| distance)
+                                .sum()
+                        }
+                    } else {
+                        1.0
+                    };
+
                     for (property_i, property) in gradient.properties.iter().enumerate() {
                         if property[0].i32() == 1 {
                             array[[gradient_sample_i, 0, property_i]] = 0.0;
                             array[[gradient_sample_i, 1, property_i]] = 0.0;
                             array[[gradient_sample_i, 2, property_i]] = 0.0;
                         } else if property[1].i32() == 1 {
-                            array[[gradient_sample_i, 0, property_i]] = 1.0;
-                            array[[gradient_sample_i, 1, property_i]] = 1.0;
-                            array[[gradient_sample_i, 2, property_i]] = 1.0;
+                            array[[gradient_sample_i, 0, property_i]] = value;
+                            array[[gradient_sample_i, 1, property_i]] = value;
+                            array[[gradient_sample_i, 2, property_i]] = value;
+                        }
+                    }
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("cell") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                for (gradient_sample_i, [sample_i]) in gradient.samples.iter_fixed_size().enumerate() {
+                    let sample = &samples[sample_i.usize()];
+                    let distances = self.pair_distances(&mut *systems[sample[0].usize()], sample[1].usize())?;
+                    let value = distances.iter().map(|(_, distance)| distance).sum::<f64>();
+
+                    for (property_i, property) in gradient.properties.iter().enumerate() {
+                        if property[1].i32() == 1 {
+                            for direction_1 in 0..3 {
+                                for direction_2 in 0..3 {
+                                    array[[gradient_sample_i, direction_1, direction_2, property_i]] = value;
+                                }
+                            }
                         }
                     }
                 }
@@ -173,14 +225,31 @@ impl CalculatorBase for DummyCalculator {
     }
 }
 
+impl DummyCalculator {
+    /// Get the neighbors of `center` in `system` together with the
+    /// corresponding distance, used to create non-zero gradients.
+    fn pair_distances(&self, system: &mut dyn System, center: usize) -> Result<Vec<(usize, f64)>, Error> {
+        system.compute_neighbors(self.cutoff)?;
+
+        let mut distances = Vec::new();
+        for pair in system.pairs_containing(center)? {
+            let neighbor = if pair.first == center { pair.second } else { pair.first };
+            distances.push((neighbor, pair.distance));
+        }
+
+        return Ok(distances);
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use ndarray::{s, aview1};
     use equistore::Labels;
 
+    use approx::assert_relative_eq;
+
     use crate::systems::test_utils::test_systems;
-    use crate::Calculator;
+    use crate::{Calculator, CalculationOptions};
 
     use super::DummyCalculator;
     use super::super::CalculatorBase;
@@ -191,6 +260,7 @@ mod tests {
             cutoff: 1.4,
             delta: 9,
             name: "a long name".into(),
+            gradients: false,
         }) as Box<dyn CalculatorBase>);
 
         assert_eq!(
@@ -210,6 +280,7 @@ mod tests {
             cutoff: 1.0,
             delta: 9,
             name: String::new(),
+            gradients: false,
         }) as Box<dyn CalculatorBase>);
 
         let mut systems = test_systems(&["water"]);
@@ -239,6 +310,7 @@ mod tests {
             cutoff: 1.0,
             delta: 9,
             name: String::new(),
+            gradients: false,
         }) as Box<dyn CalculatorBase>);
         let mut systems = test_systems(&["water"]);
 
@@ -250,4 +322,47 @@ mod tests {
             calculator, &mut systems, &keys, &samples, &properties
         );
     }
+
+    #[test]
+    fn gradients() {
+        let mut calculator = Calculator::from(Box::new(DummyCalculator{
+            cutoff: 1.0,
+            delta: 9,
+            name: String::new(),
+            gradients: true,
+        }) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water"]);
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        // O-H distance in the water test system
+        let distance = f64::sqrt(0.75545 * 0.75545 + 0.58895 * 0.58895);
+
+        let o_block = &descriptor.block_by_id(0);
+        let gradient = o_block.gradient("positions").unwrap();
+        assert_eq!(gradient.samples().count(), 3);
+        let values = gradient.values().to_array();
+        // gradient of the center w.r.t. itself is the sum of all distances
+        assert_relative_eq!(values[[0, 0, 1]], 2.0 * distance);
+        assert_relative_eq!(values[[1, 2, 1]], distance);
+        assert_eq!(values[[1, 2, 0]], 0.0);
+
+        let gradient = o_block.gradient("cell").unwrap();
+        let values = gradient.values().to_array();
+        assert_eq!(values.shape(), [1, 3, 3, 2]);
+        assert_relative_eq!(values[[0, 1, 2, 1]], 2.0 * distance);
+
+        // without the gradients parameter, cell gradients are not available
+        let mut calculator = Calculator::from(Box::new(DummyCalculator{
+            cutoff: 1.0,
+            delta: 9,
+            name: String::new(),
+            gradients: false,
+        }) as Box<dyn CalculatorBase>);
+        assert!(calculator.compute(&mut systems, options).is_err());
+    }
 }
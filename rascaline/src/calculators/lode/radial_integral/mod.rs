@@ -91,6 +91,9 @@ impl LodeRadialIntegralCache {
                     Box::new(gto) as Box<dyn LodeRadialIntegral>
                 }
             }
+            RadialBasis::SphericalBessel {spline_accuracy: _} => {
+                return Err(Error::InvalidParameter("LODE does not support the spherical Bessel radial basis for the moment".into()));
+            }
             RadialBasis::TabulatedRadialIntegral {points: _} => {
                 return Err(Error::InvalidParameter("LODE does not support a tabulated radial integral for the moment".into()));
             }
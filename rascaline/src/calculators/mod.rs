@@ -1,6 +1,6 @@
 use equistore::{TensorMap, Labels};
 
-use crate::{Error, System};
+use crate::{Error, System, Vector3D};
 
 /// The `CalculatorBase` trait is the interface shared by all calculator
 /// implementations; and used by [`crate::Calculator`] to run the calculation.
@@ -9,8 +9,10 @@ use crate::{Error, System};
 /// in [`crate::Calculator`] instead.
 ///
 /// `std::panic::RefUnwindSafe` is a required super-trait to enable passing
-/// calculators across the C API.
-pub trait CalculatorBase: std::panic::RefUnwindSafe {
+/// calculators across the C API, and `Send` is required to run calculations
+/// in a user-provided thread pool (see
+/// [`crate::CalculationOptions::thread_pool`]).
+pub trait CalculatorBase: std::panic::RefUnwindSafe + Send {
     /// Get the name of this Calculator
     fn name(&self) -> String;
 
@@ -28,7 +30,10 @@ pub trait CalculatorBase: std::panic::RefUnwindSafe {
     fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error>;
 
     /// Can this calculator compute gradients with respect to the `parameter`?
-    /// Right now, `parameter` can be either `"positions"` or `"cell"`.
+    /// Right now, `parameter` can be either `"positions"`, `"cell"`,
+    /// `"strain"` or `"atomic_gaussian_width"`.
+    /// Gradients with respect to `"strain"` and `"atomic_gaussian_width"` use
+    /// the same samples as the `"cell"` gradients.
     fn supports_gradient(&self, parameter: &str) -> bool;
 
     /// Get the samples for gradients with respect to positions, corresponding
@@ -40,6 +45,16 @@ pub trait CalculatorBase: std::panic::RefUnwindSafe {
     /// function should return an error.
     fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error>;
 
+    /// Can this calculator compute gradients with respect to positions for a
+    /// subset of the atoms (see `CalculationOptions::selected_gradient_atoms`)?
+    ///
+    /// Calculators returning `true` must only use the gradient samples present
+    /// in the descriptor, instead of assuming that all the samples returned by
+    /// `positions_gradient_samples` are there.
+    fn supports_gradient_atoms_selection(&self) -> bool {
+        return false;
+    }
+
     /// Get the components this calculator computes for each key.
     fn components(&self, keys: &Labels) -> Vec<Vec<Labels>>;
 
@@ -49,6 +64,15 @@ pub trait CalculatorBase: std::panic::RefUnwindSafe {
     /// Get the properties this calculator computes for each key.
     fn properties(&self, keys: &Labels) -> Vec<Labels>;
 
+    /// Get the number of properties this calculator computes for each key.
+    ///
+    /// The default implementation counts the properties returned by
+    /// [`CalculatorBase::properties`], calculators for which this number is
+    /// known in advance can override it to avoid creating the labels.
+    fn properties_count(&self, keys: &Labels) -> Vec<usize> {
+        return self.properties(keys).iter().map(Labels::count).collect();
+    }
+
     /// Actually run the calculation.
     ///
     /// This function is given a pre-allocated descriptor, filled with zeros.
@@ -62,6 +86,22 @@ pub trait CalculatorBase: std::panic::RefUnwindSafe {
     /// [`CalculatorBase::supports_gradient`], and the users requested them as
     /// part of the calculation options.
     fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error>;
+
+    /// Set the per-atom velocities to use in the next call to
+    /// [`CalculatorBase::compute`], as given in
+    /// [`crate::CalculationOptions::velocities`].
+    ///
+    /// This is called before every calculation. The default implementation
+    /// returns an error if `velocities` is not `None`, calculators using
+    /// velocities must override it.
+    fn set_velocities(&mut self, velocities: Option<&[Vec<Vector3D>]>) -> Result<(), Error> {
+        if velocities.is_some() {
+            return Err(Error::InvalidParameter(format!(
+                "the {} calculator does not support velocities", self.name()
+            )));
+        }
+        return Ok(());
+    }
 }
 
 
@@ -80,6 +120,18 @@ pub use self::sorted_distances::SortedDistances;
 mod neighbor_list;
 pub use self::neighbor_list::NeighborList;
 
+mod coulomb_matrix;
+pub use self::coulomb_matrix::{CoulombMatrix, CoulombMatrixOutput};
+
+mod coordination_number;
+pub use self::coordination_number::{CoordinationNumber, CoordinationNumberParameters};
+
+mod steinhardt;
+pub use self::steinhardt::{SteinhardtOrderParameters, SteinhardtParameters};
+
+mod pair_distance_histogram;
+pub use self::pair_distance_histogram::{PairDistanceHistogram, PairDistanceHistogramParameters};
+
 mod radial_basis;
 pub use self::radial_basis::{RadialBasis, GtoRadialBasis};
 
@@ -87,10 +139,17 @@ mod descriptors_by_systems;
 pub(crate) use self::descriptors_by_systems::{array_mut_for_system, split_tensor_map_by_system};
 
 pub mod soap;
-pub use self::soap::{SphericalExpansionByPair, SphericalExpansionParameters};
+pub use self::soap::{SphericalExpansionByPair, SphericalExpansionParameters, SpeciesDistanceWeight, VelocityWeight};
+pub use self::soap::SphericalExpansionParametersBuilder;
 pub use self::soap::SphericalExpansion;
-pub use self::soap::{SoapPowerSpectrum, PowerSpectrumParameters};
+pub use self::soap::{SoapPowerSpectrum, PowerSpectrumParameters, PowerSpectrumParametersBuilder, NeighborContribution};
 pub use self::soap::{SoapRadialSpectrum, RadialSpectrumParameters};
+pub use self::soap::{SoapBispectrum, BispectrumParameters};
+pub use self::soap::{LambdaSoap, LambdaSoapParameters};
 
 pub mod lode;
 pub use self::lode::{LodeSphericalExpansion, LodeSphericalExpansionParameters};
+
+pub mod symmetry_functions;
+pub use self::symmetry_functions::{BehlerG2, BehlerG2Parameters};
+pub use self::symmetry_functions::{BehlerG4, BehlerG4Parameters};
@@ -0,0 +1,427 @@
+use std::collections::BTreeMap;
+
+use equistore::{Labels, LabelsBuilder, TensorMap};
+
+use super::CalculatorBase;
+
+use crate::{Error, System, Vector3D};
+use crate::math::erf;
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterSingleNeighborsSpeciesKeys};
+
+/// Parameters for the pair distance histogram calculator.
+///
+/// For each atom and each neighbor species, this calculator accumulates the
+/// distances `r_ij` to all neighbors `j` within the cutoff in a set of fixed
+/// bins. Bin `i` contains the distances between `bins[i]` and `bins[i + 1]`,
+/// and is centered on `(bins[i] + bins[i + 1]) / 2`.
+///
+/// When `sigma` is given, each neighbor is represented by a gaussian of width
+/// `sigma` centered on `r_ij` instead of a single point, and contributes to
+/// each bin according to the integral of this gaussian over the bin. The
+/// resulting histogram is a smooth function of the atomic positions.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct PairDistanceHistogramParameters {
+    /// Spherical cutoff to use for atomic environments
+    pub cutoff: f64,
+    /// Edges of the histogram bins, in strictly increasing order
+    pub bins: Vec<f64>,
+    /// Width of the gaussian used to smooth the histogram, if any
+    #[serde(default)]
+    pub sigma: Option<f64>,
+}
+
+/// Calculator computing the histogram of pair distances around each atom
+#[derive(Debug)]
+pub struct PairDistanceHistogram {
+    parameters: PairDistanceHistogramParameters,
+}
+
+impl PairDistanceHistogram {
+    pub fn new(parameters: PairDistanceHistogramParameters) -> Result<PairDistanceHistogram, Error> {
+        if !(parameters.cutoff > 0.0 && parameters.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number, got {}", parameters.cutoff
+            )));
+        }
+
+        if parameters.bins.len() < 2 {
+            return Err(Error::InvalidParameter(
+                "expected at least two bin edges for the pair distance histogram".into()
+            ));
+        }
+
+        if parameters.bins.iter().any(|edge| !edge.is_finite()) {
+            return Err(Error::InvalidParameter(
+                "bin edges must be finite numbers".into()
+            ));
+        }
+
+        if parameters.bins.windows(2).any(|edges| edges[0] >= edges[1]) {
+            return Err(Error::InvalidParameter(
+                "bin edges must be sorted in strictly increasing order".into()
+            ));
+        }
+
+        if let Some(sigma) = parameters.sigma {
+            if !(sigma > 0.0 && sigma.is_finite()) {
+                return Err(Error::InvalidParameter(format!(
+                    "sigma must be a positive number, got {}", sigma
+                )));
+            }
+        }
+
+        return Ok(PairDistanceHistogram { parameters: parameters });
+    }
+
+    /// Get the contribution of a neighbor at distance `r` to the bin between
+    /// `start` and `stop`, and the derivative of this contribution with
+    /// respect to `r`.
+    fn bin_contribution(&self, r: f64, start: f64, stop: f64) -> (f64, f64) {
+        match self.parameters.sigma {
+            None => {
+                let value = if start <= r && r < stop { 1.0 } else { 0.0 };
+                (value, 0.0)
+            }
+            Some(sigma) => {
+                let scale = 1.0 / (std::f64::consts::SQRT_2 * sigma);
+                let value = 0.5 * (erf((stop - r) * scale) - erf((start - r) * scale));
+
+                let gaussian = |x: f64| {
+                    f64::exp(-x * x / (2.0 * sigma * sigma)) / (f64::sqrt(2.0 * std::f64::consts::PI) * sigma)
+                };
+                let derivative = gaussian(start - r) - gaussian(stop - r);
+
+                (value, derivative)
+            }
+        }
+    }
+}
+
+/// Contribution of a single neighbor to the histogram of a center
+struct NeighborContribution {
+    /// index of the sample corresponding to the center
+    sample_i: usize,
+    center: usize,
+    neighbor: usize,
+    /// vector from the center to the neighbor
+    vector: Vector3D,
+    /// distance between the center and the neighbor
+    distance: f64,
+}
+
+impl CalculatorBase for PairDistanceHistogram {
+    fn name(&self) -> String {
+        "pair distance histogram".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        let builder = CenterSingleNeighborsSpeciesKeys {
+            cutoff: self.parameters.cutoff,
+            self_pairs: false,
+            all_species: None,
+        };
+        return builder.keys(systems);
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor"]);
+        let mut result = Vec::new();
+        for [species_center, species_neighbor] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn supports_gradient(&self, parameter: &str) -> bool {
+        match parameter {
+            // the histogram is only differentiable with gaussian smoothing
+            "positions" => self.parameters.sigma.is_some(),
+            _ => false,
+        }
+    }
+
+    fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor"]);
+        assert_eq!(keys.count(), samples.len());
+
+        if self.parameters.sigma.is_none() {
+            return Err(Error::InvalidParameter(
+                "gradients of the pair distance histogram are only available with gaussian smoothing".into()
+            ));
+        }
+
+        let mut gradient_samples = Vec::new();
+        for ([species_center, species_neighbor], samples) in keys.iter_fixed_size().zip(samples) {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            gradient_samples.push(builder.gradients_for(systems, samples)?);
+        }
+
+        return Ok(gradient_samples);
+    }
+
+    fn components(&self, keys: &Labels) -> Vec<Vec<Labels>> {
+        return vec![Vec::new(); keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        vec!["bin"]
+    }
+
+    fn properties(&self, keys: &Labels) -> Vec<Labels> {
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        for bin in 0..(self.parameters.bins.len() - 1) {
+            properties.add(&[bin]);
+        }
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "PairDistanceHistogram::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["species_center", "species_neighbor"]);
+
+        let cutoff = self.parameters.cutoff;
+
+        for (key, mut block) in descriptor.iter_mut() {
+            let species_neighbor = key[1].i32();
+
+            let samples = block.samples();
+            let bins = block.properties().iter_fixed_size()
+                .map(|&[bin]| {
+                    let bin = bin.usize();
+                    (self.parameters.bins[bin], self.parameters.bins[bin + 1])
+                })
+                .collect::<Vec<_>>();
+
+            // mapping from structure to center to sample index
+            let mut centers = BTreeMap::new();
+            for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                centers.entry(structure.usize())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(center.usize(), sample_i);
+            }
+
+            let mut contributions = Vec::new();
+            for (&structure, centers) in &centers {
+                let system = &mut *systems[structure];
+                system.compute_neighbors(cutoff)?;
+                let species = system.species()?;
+
+                for pair in system.pairs()? {
+                    if species[pair.second] == species_neighbor {
+                        if let Some(&sample_i) = centers.get(&pair.first) {
+                            contributions.push(NeighborContribution {
+                                sample_i: sample_i,
+                                center: pair.first,
+                                neighbor: pair.second,
+                                vector: pair.vector,
+                                distance: pair.distance,
+                            });
+                        }
+                    }
+
+                    if pair.first == pair.second {
+                        // the pair between an atom and its periodic image is
+                        // already included in both directions
+                        continue;
+                    }
+
+                    if species[pair.first] == species_neighbor {
+                        if let Some(&sample_i) = centers.get(&pair.second) {
+                            contributions.push(NeighborContribution {
+                                sample_i: sample_i,
+                                center: pair.second,
+                                neighbor: pair.first,
+                                vector: -pair.vector,
+                                distance: pair.distance,
+                            });
+                        }
+                    }
+                }
+            }
+
+            let values = block.values_mut().to_array_mut();
+            for contribution in &contributions {
+                for (property_i, &(start, stop)) in bins.iter().enumerate() {
+                    let (value, _) = self.bin_contribution(contribution.distance, start, stop);
+                    values[[contribution.sample_i, property_i]] += value;
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("positions") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                let mut gradient_rows = BTreeMap::new();
+                for (grad_sample_i, &[sample_i, _, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    gradient_rows.insert((sample_i.usize(), atom.usize()), grad_sample_i);
+                }
+
+                for contribution in &contributions {
+                    if contribution.neighbor == contribution.center {
+                        // periodic image of the center, the gradients
+                        // w.r.t. the center and neighbor cancel out
+                        continue;
+                    }
+
+                    let center_row = gradient_rows[&(contribution.sample_i, contribution.center)];
+                    let neighbor_row = gradient_rows[&(contribution.sample_i, contribution.neighbor)];
+
+                    let direction = contribution.vector / contribution.distance;
+                    for (property_i, &(start, stop)) in bins.iter().enumerate() {
+                        let (_, derivative) = self.bin_contribution(contribution.distance, start, stop);
+                        for d in 0..3 {
+                            array[[neighbor_row, d, property_i]] += derivative * direction[d];
+                            array[[center_row, d, property_i]] -= derivative * direction[d];
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use equistore::LabelValue;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, CalculationOptions, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+
+    use super::{PairDistanceHistogram, PairDistanceHistogramParameters};
+
+    fn parameters(sigma: Option<f64>) -> PairDistanceHistogramParameters {
+        PairDistanceHistogramParameters {
+            cutoff: 3.5,
+            bins: vec![0.0, 1.0, 2.0, 3.0, 4.0],
+            sigma: sigma,
+        }
+    }
+
+    fn system() -> SimpleSystem {
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 1.2));
+        system.add_atom(8, Vector3D::new(3.2, 0.0, 0.0));
+        system.add_atom(8, Vector3D::new(0.0, -2.5, 0.0));
+        return system;
+    }
+
+    #[test]
+    fn values() {
+        let mut calculator = Calculator::from(Box::new(
+            PairDistanceHistogram::new(parameters(None)).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut systems = [Box::new(system()) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let block_i = descriptor.keys().position(&[LabelValue::new(1), LabelValue::new(8)]).unwrap();
+        let block = descriptor.block_by_id(block_i);
+        assert_eq!(block.samples().count(), 1);
+
+        let values = block.values().to_array();
+        assert_eq!(values.shape(), [1, 4]);
+        assert_eq!(values[[0, 0]], 0.0);
+        assert_eq!(values[[0, 1]], 1.0);
+        assert_eq!(values[[0, 2]], 1.0);
+        assert_eq!(values[[0, 3]], 1.0);
+    }
+
+    #[test]
+    fn smoothed_values() {
+        let mut calculator = Calculator::from(Box::new(
+            PairDistanceHistogram::new(parameters(Some(0.1))).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut systems = [Box::new(system()) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let block_i = descriptor.keys().position(&[LabelValue::new(1), LabelValue::new(8)]).unwrap();
+        let values = descriptor.block_by_id(block_i).values().to_array();
+
+        // all the neighbors are far from the bin edges
+        assert_relative_eq!(values[[0, 0]], 0.0, epsilon=1e-6);
+        assert_relative_eq!(values[[0, 1]], 1.0, max_relative=1e-6);
+        assert_relative_eq!(values[[0, 2]], 1.0, max_relative=1e-6);
+        assert_relative_eq!(values[[0, 3]], 1.0, max_relative=1e-6);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let mut parameters = self::parameters(None);
+        parameters.bins = vec![1.0];
+        assert!(PairDistanceHistogram::new(parameters).is_err());
+
+        let mut parameters = self::parameters(None);
+        parameters.bins = vec![0.0, 2.0, 1.0];
+        assert!(PairDistanceHistogram::new(parameters).is_err());
+
+        let parameters = self::parameters(Some(-0.3));
+        assert!(PairDistanceHistogram::new(parameters).is_err());
+    }
+
+    #[test]
+    fn gradients_require_smoothing() {
+        let mut calculator = Calculator::from(Box::new(
+            PairDistanceHistogram::new(parameters(None)).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut systems = [Box::new(system()) as Box<dyn System>];
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        assert!(calculator.compute(&mut systems, options).is_err());
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let calculator = Calculator::from(Box::new(
+            PairDistanceHistogram::new(parameters(Some(0.3))).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-6,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+}
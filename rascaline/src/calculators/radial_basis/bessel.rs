@@ -0,0 +1,149 @@
+use ndarray::Array2;
+
+use crate::math::spherical_bessel_first_kind;
+
+#[derive(Debug, Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+/// Use spherical Bessel functions of the first kind as radial basis, with
+/// zero boundary conditions at the cutoff (Laplacian eigenstates).
+///
+/// The basis is defined as `R_nl(r) = N_nl j_l(z_nl r / r_c)`, where `z_nl` is
+/// the `n`-th positive zero of `j_l`, and `N_nl = \sqrt{2 / r_c^3} / |j_{l +
+/// 1}(z_nl)|` makes the functions orthonormal on `[0, r_c]` for each `l`.
+pub struct SphericalBesselRadialBasis {
+    pub max_radial: usize,
+    pub max_angular: usize,
+    pub cutoff: f64,
+}
+
+impl SphericalBesselRadialBasis {
+    /// Get the `max_radial` first positive zeros of the spherical Bessel
+    /// functions `j_l` for `l` up to `max_angular`, in a `(max_angular + 1) x
+    /// max_radial` array.
+    ///
+    /// The zeros of `j_0` are `n π`, and the zeros of `j_l` are found by
+    /// bisection, using the fact that they are interlaced with the zeros of
+    /// `j_{l - 1}`.
+    pub fn zeros(&self) -> Array2<f64> {
+        let mut zeros = Array2::from_elem((self.max_angular + 1, self.max_radial), 0.0);
+
+        let count = self.max_radial + self.max_angular;
+        let mut previous = (1..=count)
+            .map(|n| n as f64 * std::f64::consts::PI)
+            .collect::<Vec<_>>();
+
+        for n in 0..self.max_radial {
+            zeros[[0, n]] = previous[n];
+        }
+
+        for l in 1..=self.max_angular {
+            let current = previous.windows(2)
+                .map(|bounds| find_zero(l, bounds[0], bounds[1]))
+                .collect::<Vec<_>>();
+
+            for n in 0..self.max_radial {
+                zeros[[l, n]] = current[n];
+            }
+            previous = current;
+        }
+
+        return zeros;
+    }
+
+    /// Get the normalization constants `N_nl` for all radial basis functions
+    /// in a `(max_angular + 1) x max_radial` array, given the `zeros` of the
+    /// spherical Bessel functions.
+    pub fn normalization(&self, zeros: &Array2<f64>) -> Array2<f64> {
+        let prefactor = f64::sqrt(2.0 / (self.cutoff * self.cutoff * self.cutoff));
+        let mut normalization = Array2::from_elem(zeros.dim(), 0.0);
+        for ((l, n), value) in normalization.indexed_iter_mut() {
+            let j_l_plus_1 = spherical_bessel_first_kind(l + 1, zeros[[l, n]]);
+            *value = prefactor / j_l_plus_1.abs();
+        }
+        return normalization;
+    }
+}
+
+/// Find the zero of `j_l` in the `[start, stop]` interval with bisection
+fn find_zero(l: usize, mut start: f64, mut stop: f64) -> f64 {
+    let mut f_start = spherical_bessel_first_kind(l, start);
+    debug_assert!(f_start * spherical_bessel_first_kind(l, stop) <= 0.0);
+
+    while stop - start > 4.0 * f64::EPSILON * stop {
+        let middle = 0.5 * (start + stop);
+        let f_middle = spherical_bessel_first_kind(l, middle);
+        if f_middle == 0.0 {
+            return middle;
+        }
+
+        if f_start * f_middle < 0.0 {
+            stop = middle;
+        } else {
+            start = middle;
+            f_start = f_middle;
+        }
+    }
+
+    return 0.5 * (start + stop);
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::math::{gauss_legendre, spherical_bessel_first_kind};
+    use super::*;
+
+    #[test]
+    fn zeros() {
+        let basis = SphericalBesselRadialBasis {
+            max_radial: 3,
+            max_angular: 2,
+            cutoff: 4.5,
+        };
+        let zeros = basis.zeros();
+
+        let pi = std::f64::consts::PI;
+        assert_relative_eq!(zeros[[0, 0]], pi, max_relative=1e-14);
+        assert_relative_eq!(zeros[[0, 1]], 2.0 * pi, max_relative=1e-14);
+        assert_relative_eq!(zeros[[0, 2]], 3.0 * pi, max_relative=1e-14);
+
+        assert_relative_eq!(zeros[[1, 0]], 4.493409457909064, max_relative=1e-14);
+        assert_relative_eq!(zeros[[1, 1]], 7.725251836937707, max_relative=1e-14);
+        assert_relative_eq!(zeros[[1, 2]], 10.904121659428899, max_relative=1e-14);
+
+        assert_relative_eq!(zeros[[2, 0]], 5.763459196894550, max_relative=1e-14);
+        assert_relative_eq!(zeros[[2, 1]], 9.095011330476355, max_relative=1e-14);
+        assert_relative_eq!(zeros[[2, 2]], 12.322940970566582, max_relative=1e-14);
+    }
+
+    #[test]
+    fn orthonormal() {
+        let basis = SphericalBesselRadialBasis {
+            max_radial: 6,
+            max_angular: 4,
+            cutoff: 3.5,
+        };
+        let zeros = basis.zeros();
+        let normalization = basis.normalization(&zeros);
+
+        let (nodes, weights) = gauss_legendre(80);
+        let radial = |l: usize, n: usize, r: f64| {
+            normalization[[l, n]] * spherical_bessel_first_kind(l, zeros[[l, n]] * r / basis.cutoff)
+        };
+
+        for l in 0..=basis.max_angular {
+            for n1 in 0..basis.max_radial {
+                for n2 in 0..basis.max_radial {
+                    let overlap = nodes.iter().zip(&weights).map(|(&x, &w)| {
+                        let r = 0.5 * basis.cutoff * (x + 1.0);
+                        0.5 * basis.cutoff * w * r * r * radial(l, n1, r) * radial(l, n2, r)
+                    }).sum::<f64>();
+
+                    let expected = if n1 == n2 { 1.0 } else { 0.0 };
+                    assert_relative_eq!(overlap, expected, epsilon=1e-10);
+                }
+            }
+        }
+    }
+}
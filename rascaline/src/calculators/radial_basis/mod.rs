@@ -1,8 +1,15 @@
+use std::path::Path;
+
+use crate::Error;
+
 mod gto;
 pub use self::gto::GtoRadialBasis;
 
+mod bessel;
+pub use self::bessel::SphericalBesselRadialBasis;
+
 mod tabulated;
-pub use self::tabulated::SplinePoint;
+pub use self::tabulated::{SplinePoint, JsonArray2};
 
 #[derive(Debug, Clone)]
 #[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
@@ -12,6 +19,17 @@ pub enum RadialBasis {
     ///
     /// The basis is defined as `R_n(r) ∝ r^n e^{- r^2 / (2 σ_n^2)}`, where `σ_n
     /// = cutoff * \sqrt{n} / n_max`
+    ///
+    /// The radial integral for this basis is evaluated analytically (using the
+    /// confluent hypergeometric function `1F1`), not with a numerical
+    /// quadrature; so there is no quadrature order to configure. The only
+    /// accuracy/speed trade-off available is `spline_accuracy` when
+    /// `splined_radial_integral` is true.
+    ///
+    /// Setting `splined_radial_integral` to false evaluates the analytical
+    /// expression for every pair, removing the spline accuracy from
+    /// convergence studies. This makes the full spherical expansion calculation
+    /// roughly 2 times slower.
     Gto {
         /// compute the radial integral using splines. This is much faster than
         /// the base GTO implementation.
@@ -23,11 +41,33 @@ pub enum RadialBasis {
         #[serde(default = "serde_default_spline_accuracy")]
         spline_accuracy: f64,
     },
+    /// Use spherical Bessel functions of the first kind as the radial basis,
+    /// with zero boundary conditions at the cutoff. These are the eigenstates
+    /// of the Laplacian inside a sphere of radius `cutoff`.
+    ///
+    /// The basis is defined as `R_nl(r) ∝ j_l(z_nl r / cutoff)`, where `z_nl`
+    /// is the `n`-th positive zero of `j_l`. Contrary to the GTO basis, the
+    /// radial functions depend on `l`.
+    ///
+    /// The number of radial functions and the cutoff are taken from the
+    /// calculator parameters (`max_radial` and `cutoff`). There is no closed
+    /// form for the radial integral, which is evaluated with a numerical
+    /// quadrature and then always splined.
+    SphericalBessel {
+        /// Accuracy for the spline. The number of control points in the spline
+        /// is automatically determined to ensure the average absolute error is
+        /// close to the requested accuracy.
+        #[serde(default = "serde_default_spline_accuracy")]
+        spline_accuracy: f64,
+    },
     /// Compute the radial integral with user-defined splines.
     ///
     /// The easiest way to create a set of spline points is the
-    /// `rascaline.generate_splines` Python function.
+    /// `rascaline.generate_splines` Python function. The points must span the
+    /// whole `[0, cutoff]` range.
+    #[serde(alias = "Tabulated")]
     TabulatedRadialIntegral {
+        #[serde(alias = "spline_points")]
         points: Vec<SplinePoint>,
     }
 }
@@ -36,7 +76,10 @@ fn serde_default_splined_radial_integral() -> bool { true }
 fn serde_default_spline_accuracy() -> f64 { 1e-8 }
 
 impl RadialBasis {
-    /// Use GTO as the radial basis, and do not spline the radial integral
+    /// Use GTO as the radial basis, and do not spline the radial integral.
+    ///
+    /// The radial integral is computed analytically for every pair, which is
+    /// exact but about 2 times slower than the splined version.
     pub fn gto() -> RadialBasis {
         return RadialBasis::Gto {
             splined_radial_integral: false, spline_accuracy: 0.0
@@ -49,4 +92,61 @@ impl RadialBasis {
             splined_radial_integral: true, spline_accuracy: accuracy
         };
     }
+
+    /// Use a tabulated radial integral, reading the spline points from the
+    /// JSON file at `path`. The file should contain a list of points, each one
+    /// being an object with `position`, `values` and `derivatives` fields.
+    pub fn tabulated_from_json(path: impl AsRef<Path>) -> Result<RadialBasis, Error> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| Error::InvalidParameter(format!(
+            "failed to read tabulated radial integral from '{}': {}", path.display(), e
+        )))?;
+
+        let points = serde_json::from_str(&content)?;
+        return Ok(RadialBasis::TabulatedRadialIntegral { points });
+    }
+
+    /// Use spherical Bessel functions as the radial basis, splining the
+    /// radial integral with the given accuracy
+    pub fn spherical_bessel(accuracy: f64) -> RadialBasis {
+        return RadialBasis::SphericalBessel {
+            spline_accuracy: accuracy
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RadialBasis;
+
+    #[test]
+    fn tabulated_from_json() {
+        let path = std::env::temp_dir().join("rascaline-tabulated-radial-integral.json");
+        std::fs::write(&path, r#"[
+            {"position": 0.0, "values": {"v": 1, "dim": [1, 1], "data": [1.0]}, "derivatives": {"v": 1, "dim": [1, 1], "data": [0.0]}},
+            {"position": 2.0, "values": {"v": 1, "dim": [1, 1], "data": [0.5]}, "derivatives": {"v": 1, "dim": [1, 1], "data": [-0.5]}}
+        ]"#).unwrap();
+
+        let radial_basis = RadialBasis::tabulated_from_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        match radial_basis {
+            RadialBasis::TabulatedRadialIntegral { points } => {
+                assert_eq!(points.len(), 2);
+                assert_eq!(points[1].position, 2.0);
+                assert_eq!(points[1].derivatives[[0, 0]], -0.5);
+            }
+            _ => panic!("expected a tabulated radial integral"),
+        }
+
+        assert!(RadialBasis::tabulated_from_json("this/file/does/not/exist.json").is_err());
+    }
+
+    #[test]
+    fn tabulated_alias() {
+        let radial_basis: RadialBasis = serde_json::from_str(r#"{
+            "Tabulated": {"spline_points": []}
+        }"#).unwrap();
+        assert!(matches!(radial_basis, RadialBasis::TabulatedRadialIntegral { .. }));
+    }
 }
@@ -0,0 +1,505 @@
+use std::collections::BTreeMap;
+
+use ndarray::{s, Array1, Array2, Array3, ArrayView1, Axis, Ix2, Ix3};
+
+use equistore::{TensorMap, Labels, LabelsBuilder};
+
+use crate::calculators::CalculatorBase;
+use crate::{CalculationOptions, Calculator};
+use crate::{Error, System};
+
+use super::SphericalExpansionParameters;
+use super::{SphericalExpansion, CutoffFunction, RadialScaling};
+use super::clebsch_gordan::ClebschGordan;
+use crate::calculators::radial_basis::RadialBasis;
+
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterTwoNeighborsSpeciesKeys};
+
+/// Parameters for SOAP bispectrum calculator.
+///
+/// The SOAP bispectrum represents three-neighbors density correlations,
+/// coupling two spherical expansion coefficients to an angular channel `l`
+/// with Clebsch-Gordan coefficients, and contracting the result with the
+/// expansion of the full neighbors density:
+///
+/// `< l1 l2 l n1 n2 | X_i > = \sum_{m1 m2 m} C^{l1 l2 l}_{m1 m2 m}
+///     < n1 l1 m1 | X_i^{a} > < n2 l2 m2 | X_i^{b} > < l m | X_i >`
+///
+/// where `a` and `b` are the two neighbor species, and `< l m | X_i >` is the
+/// spherical expansion of the density of all neighbor species, summed over
+/// the radial index `n`. Only `l1, l2, l` combinations satisfying `|l1 - l2|
+/// <= l <= l1 + l2` are included.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct BispectrumParameters {
+    /// Spherical cutoff to use for atomic environments
+    pub cutoff: f64,
+    /// Number of radial basis function to use
+    pub max_radial: usize,
+    /// Number of spherical harmonics to use
+    pub max_angular: usize,
+    /// Width of the atom-centered gaussian creating the atomic density
+    pub atomic_gaussian_width: f64,
+    /// Weight of the central atom contribution to the
+    /// features. If `1.0` the center atom contribution is weighted the same
+    /// as any other contribution. If `0.0` the central atom does not
+    /// contribute to the features at all.
+    pub center_atom_weight: f64,
+    /// radial basis to use for the radial integral
+    pub radial_basis: RadialBasis,
+    /// cutoff function used to smooth the behavior around the cutoff radius
+    pub cutoff_function: CutoffFunction,
+    /// radial scaling can be used to reduce the importance of neighbor atoms
+    /// further away from the center, usually improving the performance of the
+    /// model
+    #[serde(default)]
+    pub radial_scaling: RadialScaling,
+}
+
+/// Calculator implementing the SOAP bispectrum representation of atomistic
+/// systems.
+pub struct SoapBispectrum {
+    parameters: BispectrumParameters,
+    spherical_expansion: Calculator,
+    clebsch_gordan: ClebschGordan,
+}
+
+impl std::fmt::Debug for SoapBispectrum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.parameters)
+    }
+}
+
+impl SoapBispectrum {
+    pub fn new(parameters: BispectrumParameters) -> Result<SoapBispectrum, Error> {
+        let expansion_parameters = SphericalExpansionParameters {
+            cutoff: parameters.cutoff,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
+            max_radial: parameters.max_radial,
+            max_angular: parameters.max_angular,
+            angular_channels: None,
+            atomic_gaussian_width: parameters.atomic_gaussian_width,
+            center_atom_weight: parameters.center_atom_weight,
+            radial_basis: parameters.radial_basis.clone(),
+            cutoff_function: parameters.cutoff_function.clone(),
+            radial_scaling: parameters.radial_scaling,
+            species_occupations: Default::default(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
+        };
+        let spherical_expansion = SphericalExpansion::new(expansion_parameters)?;
+
+        return Ok(SoapBispectrum {
+            clebsch_gordan: ClebschGordan::new(parameters.max_angular),
+            parameters: parameters,
+            spherical_expansion: Calculator::from(
+                Box::new(spherical_expansion) as Box<dyn CalculatorBase>
+            ),
+        });
+    }
+}
+
+/// Spherical expansion coefficients around a single center
+#[derive(Default)]
+pub(super) struct CenterExpansion {
+    /// expansion for each `(l, species_neighbor)`, with shape `(2l + 1, n)`
+    pub(super) by_species: BTreeMap<(usize, i32), Array2<f64>>,
+    /// expansion of the density of all neighbors summed over `n`, for each
+    /// `l`, with shape `(2l + 1)`
+    pub(super) total: BTreeMap<usize, Array1<f64>>,
+}
+
+/// Gradients of the spherical expansion around a single center with respect
+/// to the position of a single atom
+#[derive(Default)]
+pub(super) struct CenterGradient {
+    /// gradients for each `(l, species_neighbor)`, with shape `(3, 2l + 1, n)`
+    pub(super) by_species: BTreeMap<(usize, i32), Array3<f64>>,
+    /// gradients of the expansion of the density of all neighbors summed over
+    /// `n`, for each `l`, with shape `(3, 2l + 1)`
+    pub(super) total: BTreeMap<usize, Array2<f64>>,
+}
+
+/// Spherical expansion (and gradients) re-organized by center
+#[derive(Default)]
+pub(super) struct ExpansionsByCenter {
+    /// expansion for each `(structure, center)`
+    pub(super) values: BTreeMap<(usize, usize), CenterExpansion>,
+    /// gradients for each `(structure, center, atom)`
+    pub(super) gradients: BTreeMap<(usize, usize, usize), CenterGradient>,
+}
+
+impl ExpansionsByCenter {
+    pub(super) fn new(spherical_expansion: &TensorMap) -> ExpansionsByCenter {
+        let mut result = ExpansionsByCenter::default();
+        for (key, block) in spherical_expansion.iter() {
+            let spherical_harmonics_l = key[0].usize();
+            let species_neighbor = key[2].i32();
+
+            let samples = block.samples();
+            let values = block.values().to_array();
+            for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                let value = values.index_axis(Axis(0), sample_i)
+                    .into_dimensionality::<Ix2>()
+                    .expect("wrong dimensionality for spherical expansion");
+
+                let expansion = result.values.entry((structure.usize(), center.usize())).or_default();
+                *expansion.total.entry(spherical_harmonics_l)
+                    .or_insert_with(|| Array1::zeros(2 * spherical_harmonics_l + 1)) += &value.sum_axis(Axis(1));
+                expansion.by_species.insert((spherical_harmonics_l, species_neighbor), value.to_owned());
+            }
+
+            if let Some(gradient) = block.gradient("positions") {
+                let gradient_values = gradient.values().to_array();
+                for (grad_sample_i, &[sample_i, structure, atom]) in gradient.samples().iter_fixed_size().enumerate() {
+                    let center = samples[sample_i.usize()][1];
+                    let value = gradient_values.index_axis(Axis(0), grad_sample_i)
+                        .into_dimensionality::<Ix3>()
+                        .expect("wrong dimensionality for spherical expansion gradients");
+
+                    let gradient = result.gradients.entry((structure.usize(), center.usize(), atom.usize())).or_default();
+                    *gradient.total.entry(spherical_harmonics_l)
+                        .or_insert_with(|| Array2::zeros((3, 2 * spherical_harmonics_l + 1))) += &value.sum_axis(Axis(2));
+                    gradient.by_species.insert((spherical_harmonics_l, species_neighbor), value.to_owned());
+                }
+            }
+        }
+
+        return result;
+    }
+}
+
+/// Contract three sets of coefficients with the Clebsch-Gordan coefficients
+/// `cg`
+fn couple(cg: &Array3<f64>, first: ArrayView1<f64>, second: ArrayView1<f64>, third: ArrayView1<f64>) -> f64 {
+    let mut result = 0.0;
+    for (m1, a) in first.iter().enumerate() {
+        for (m2, b) in second.iter().enumerate() {
+            let ab = a * b;
+            for (m, c) in third.iter().enumerate() {
+                result += cg[[m1, m2, m]] * ab * c;
+            }
+        }
+    }
+    return result;
+}
+
+impl CalculatorBase for SoapBispectrum {
+    fn name(&self) -> String {
+        "SOAP bispectrum".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<equistore::Labels, Error> {
+        // the bispectrum is not symmetric with respect to the exchange of the
+        // two neighbor species (unless `l1` and `l2` are also exchanged), so
+        // we keep both orders
+        let builder = CenterTwoNeighborsSpeciesKeys {
+            cutoff: self.parameters.cutoff,
+            self_pairs: true,
+            symmetric: false,
+            all_species: None,
+        };
+        return builder.keys(systems);
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &equistore::Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
+        let mut result = Vec::new();
+        for [species_center, species_neighbor_1, species_neighbor_2] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                // we only want center with both neighbor species present
+                species_neighbor: SpeciesFilter::AllOf(
+                    [
+                        species_neighbor_1.i32(),
+                        species_neighbor_2.i32()
+                    ].iter().copied().collect()
+                ),
+                self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
+        assert_eq!(keys.count(), samples.len());
+
+        let mut gradient_samples = Vec::new();
+        for ([species_center, _, _], samples) in keys.iter_fixed_size().zip(samples) {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                // the density of all neighbors enters the bispectrum, so the
+                // gradients samples should contain all neighbors
+                species_neighbor: SpeciesFilter::Any,
+                self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            gradient_samples.push(builder.gradients_for(systems, samples)?);
+        }
+
+        return Ok(gradient_samples);
+    }
+
+    fn supports_gradient(&self, parameter: &str) -> bool {
+        match parameter {
+            "positions" => true,
+            _ => false,
+        }
+    }
+
+    fn components(&self, keys: &equistore::Labels) -> Vec<Vec<Labels>> {
+        return vec![vec![]; keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        vec!["l1", "l2", "l", "n1", "n2"]
+    }
+
+    fn properties(&self, keys: &equistore::Labels) -> Vec<Labels> {
+        let max_angular = self.parameters.max_angular;
+
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        for l1 in 0..=max_angular {
+            for l2 in 0..=max_angular {
+                for l in 0..=max_angular {
+                    if self.clebsch_gordan.get(l1, l2, l).is_none() {
+                        continue;
+                    }
+
+                    for n1 in 0..self.parameters.max_radial {
+                        for n2 in 0..self.parameters.max_radial {
+                            properties.add(&[l1, l2, l, n1, n2]);
+                        }
+                    }
+                }
+            }
+        }
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "SoapBispectrum::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
+
+        let mut gradients = Vec::new();
+        if descriptor.block_by_id(0).gradient("positions").is_some() {
+            gradients.push("positions");
+        }
+
+        let options = CalculationOptions {
+            gradients: &gradients,
+            ..Default::default()
+        };
+        let spherical_expansion = self.spherical_expansion.compute(systems, options)?;
+        let expansions = ExpansionsByCenter::new(&spherical_expansion);
+
+        for (key, mut block) in descriptor.iter_mut() {
+            let species_neighbor_1 = key[1].i32();
+            let species_neighbor_2 = key[2].i32();
+
+            let samples = block.samples();
+            let properties = block.properties();
+
+            let values = block.values_mut().to_array_mut();
+            for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                let expansion = if let Some(expansion) = expansions.values.get(&(structure.usize(), center.usize())) {
+                    expansion
+                } else {
+                    continue;
+                };
+
+                for (property_i, &[l1, l2, l, n1, n2]) in properties.iter_fixed_size().enumerate() {
+                    let cg = self.clebsch_gordan.get(l1.usize(), l2.usize(), l.usize()).expect("invalid l1, l2, l");
+
+                    let first = expansion.by_species.get(&(l1.usize(), species_neighbor_1));
+                    let second = expansion.by_species.get(&(l2.usize(), species_neighbor_2));
+                    let third = expansion.total.get(&l.usize());
+
+                    if let (Some(first), Some(second), Some(third)) = (first, second, third) {
+                        values[[sample_i, property_i]] = couple(
+                            cg, first.column(n1.usize()), second.column(n2.usize()), third.view()
+                        );
+                    }
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("positions") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                for (grad_sample_i, &[sample_i, structure, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    let center = samples[sample_i.usize()][1];
+                    let expansion = expansions.values.get(&(structure.usize(), center.usize()));
+                    let expansion_gradient = expansions.gradients.get(&(structure.usize(), center.usize(), atom.usize()));
+
+                    let (expansion, expansion_gradient) = if let (Some(e), Some(g)) = (expansion, expansion_gradient) {
+                        (e, g)
+                    } else {
+                        continue;
+                    };
+
+                    for (property_i, &[l1, l2, l, n1, n2]) in properties.iter_fixed_size().enumerate() {
+                        let (l1, l2, l) = (l1.usize(), l2.usize(), l.usize());
+                        let (n1, n2) = (n1.usize(), n2.usize());
+                        let cg = self.clebsch_gordan.get(l1, l2, l).expect("invalid l1, l2, l");
+
+                        let first = expansion.by_species.get(&(l1, species_neighbor_1));
+                        let second = expansion.by_species.get(&(l2, species_neighbor_2));
+                        let third = expansion.total.get(&l);
+
+                        let first_gradient = expansion_gradient.by_species.get(&(l1, species_neighbor_1));
+                        let second_gradient = expansion_gradient.by_species.get(&(l2, species_neighbor_2));
+                        let third_gradient = expansion_gradient.total.get(&l);
+
+                        for d in 0..3 {
+                            let mut sum = 0.0;
+                            if let (Some(first_gradient), Some(second), Some(third)) = (first_gradient, second, third) {
+                                sum += couple(cg, first_gradient.slice(s![d, .., n1]), second.column(n2), third.view());
+                            }
+
+                            if let (Some(first), Some(second_gradient), Some(third)) = (first, second_gradient, third) {
+                                sum += couple(cg, first.column(n1), second_gradient.slice(s![d, .., n2]), third.view());
+                            }
+
+                            if let (Some(first), Some(second), Some(third_gradient)) = (first, second, third_gradient) {
+                                sum += couple(cg, first.column(n1), second.column(n2), third_gradient.row(d));
+                            }
+
+                            array[[grad_sample_i, d, property_i]] = sum;
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+
+    use super::{SoapBispectrum, BispectrumParameters};
+    use super::super::CutoffFunction;
+    use crate::calculators::radial_basis::RadialBasis;
+
+    fn parameters() -> BispectrumParameters {
+        BispectrumParameters {
+            cutoff: 3.5,
+            max_radial: 3,
+            max_angular: 2,
+            atomic_gaussian_width: 0.3,
+            center_atom_weight: 1.0,
+            radial_basis: RadialBasis::splined_gto(1e-8),
+            radial_scaling: Default::default(),
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+        }
+    }
+
+    #[test]
+    fn properties() {
+        let mut calculator = Calculator::from(Box::new(SoapBispectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = [Box::new(test_system("water")) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        for (_, block) in descriptor.iter() {
+            for &[l1, l2, l, _, _] in block.properties().iter_fixed_size() {
+                let (l1, l2, l) = (l1.i32(), l2.i32(), l.i32());
+                assert!((l1 - l2).abs() <= l && l <= l1 + l2);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_invariance() {
+        let mut calculator = Calculator::from(Box::new(SoapBispectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let positions = [
+            (6, Vector3D::new(0.0, 0.0, 0.0)),
+            (1, Vector3D::new(0.5288, 0.1610, 0.9359)),
+            (1, Vector3D::new(0.2051, 0.8240, -0.6786)),
+            (8, Vector3D::new(0.3345, -0.9314, -0.4496)),
+            (1, Vector3D::new(-1.0685, -0.0537, 0.1921)),
+        ];
+
+        // rotation around z, followed by a rotation around x
+        let (cos_1, sin_1) = (f64::cos(0.3), f64::sin(0.3));
+        let (cos_2, sin_2) = (f64::cos(1.1), f64::sin(1.1));
+        let rotate = |v: Vector3D| {
+            let x = cos_1 * v[0] - sin_1 * v[1];
+            let y = sin_1 * v[0] + cos_1 * v[1];
+            let z = v[2];
+            Vector3D::new(x, cos_2 * y - sin_2 * z, sin_2 * y + cos_2 * z)
+        };
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        let mut rotated = SimpleSystem::new(UnitCell::infinite());
+        for &(species, position) in &positions {
+            system.add_atom(species, position);
+            rotated.add_atom(species, rotate(position));
+        }
+
+        let expected = calculator.compute(&mut [Box::new(system) as Box<dyn System>], Default::default()).unwrap();
+        let descriptor = calculator.compute(&mut [Box::new(rotated) as Box<dyn System>], Default::default()).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-9, epsilon=1e-12
+            );
+        }
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let calculator = Calculator::from(Box::new(SoapBispectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 5e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::BTreeMap;
+
+use ndarray::{Array3, ArrayView1};
+
+/// Pre-computed Clebsch-Gordan coefficients for the coupling of real
+/// spherical harmonics, for all `l1, l2, l <= max_angular` with `|l1 - l2| <=
+/// l <= l1 + l2`.
+///
+/// The coefficients are stored as `(2 l1 + 1, 2 l2 + 1, 2 l + 1)` arrays, such
+/// that `\sum_{m1 m2} C[m1, m2, m] A_{l1 m1} B_{l2 m2}` transforms under
+/// rotations like the real spherical harmonics `Y_l^m` when `A` and `B`
+/// transform like `Y_{l1}^{m1}` and `Y_{l2}^{m2}` respectively.
+#[derive(Debug, Clone)]
+pub struct ClebschGordan {
+    coefficients: BTreeMap<(usize, usize, usize), Array3<f64>>,
+}
+
+impl ClebschGordan {
+    /// Compute all the coefficients up to `max_angular`
+    pub fn new(max_angular: usize) -> ClebschGordan {
+        let mut coefficients = BTreeMap::new();
+        for l1 in 0..=max_angular {
+            for l2 in 0..=max_angular {
+                let min_l = if l1 > l2 { l1 - l2 } else { l2 - l1 };
+                let max_l = usize::min(l1 + l2, max_angular);
+                for l in min_l..=max_l {
+                    coefficients.insert((l1, l2, l), real_clebsch_gordan(l1, l2, l));
+                }
+            }
+        }
+
+        return ClebschGordan {
+            coefficients: coefficients,
+        };
+    }
+
+    /// Get the coefficients coupling `l1` and `l2` to `l`, or `None` if this
+    /// coupling is not allowed by the triangle inequality.
+    pub fn get(&self, l1: usize, l2: usize, l: usize) -> Option<&Array3<f64>> {
+        self.coefficients.get(&(l1, l2, l))
+    }
+}
+
+/// Minimal complex number type, only used to build the real coefficients
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+
+    fn conj(self) -> Complex {
+        Complex { re: self.re, im: -self.im }
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+}
+
+fn factorial(n: i64) -> f64 {
+    debug_assert!(n >= 0);
+    return (1..=n).map(|i| i as f64).product();
+}
+
+/// Clebsch-Gordan coefficient `<j1 m1; j2 m2 | j m>` for complex spherical
+/// harmonics, computed with Racah's formula.
+#[allow(clippy::similar_names)]
+fn complex_clebsch_gordan(j1: i64, m1: i64, j2: i64, m2: i64, j: i64, m: i64) -> f64 {
+    if m1 + m2 != m || j < (j1 - j2).abs() || j > j1 + j2 {
+        return 0.0;
+    }
+
+    if m1.abs() > j1 || m2.abs() > j2 || m.abs() > j {
+        return 0.0;
+    }
+
+    let prefactor = f64::sqrt(
+        (2 * j + 1) as f64 * factorial(j + j1 - j2) * factorial(j - j1 + j2) * factorial(j1 + j2 - j)
+        / factorial(j1 + j2 + j + 1)
+    ) * f64::sqrt(
+        factorial(j + m) * factorial(j - m)
+        * factorial(j1 - m1) * factorial(j1 + m1)
+        * factorial(j2 - m2) * factorial(j2 + m2)
+    );
+
+    let mut sum = 0.0;
+    for k in 0..=(j1 + j2 - j) {
+        let others = [j1 + j2 - j - k, j1 - m1 - k, j2 + m2 - k, j - j2 + m1 + k, j - j1 - m2 + k];
+        if others.iter().any(|&x| x < 0) {
+            continue;
+        }
+
+        let denominator = factorial(k) * others.iter().map(|&x| factorial(x)).product::<f64>();
+        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+        sum += sign / denominator;
+    }
+
+    return prefactor * sum;
+}
+
+/// Get the matrix `U` expressing real spherical harmonics as linear
+/// combination of complex spherical harmonics: `Y_{l, μ} = \sum_m U[m, μ]
+/// Y_l^m`, where `Y_{l, μ}` are the real spherical harmonics used in
+/// rascaline.
+fn real_to_complex(l: usize) -> Vec<Vec<Complex>> {
+    let size = 2 * l + 1;
+    let mut result = vec![vec![Complex::ZERO; size]; size];
+
+    let l = l as isize;
+    let inv_sqrt_2 = 1.0 / std::f64::consts::SQRT_2;
+    for m in -l..=l {
+        let sign = if m % 2 == 0 { 1.0 } else { -1.0 };
+        let real = (l + m) as usize;
+        let positive = (l + m) as usize;
+        let negative = (l - m) as usize;
+
+        match m.cmp(&0) {
+            std::cmp::Ordering::Less => {
+                result[positive][real] = Complex { re: 0.0, im: inv_sqrt_2 };
+                result[negative][real] = Complex { re: 0.0, im: -sign * inv_sqrt_2 };
+            }
+            std::cmp::Ordering::Equal => {
+                result[positive][real] = Complex { re: 1.0, im: 0.0 };
+            }
+            std::cmp::Ordering::Greater => {
+                result[negative][real] = Complex { re: inv_sqrt_2, im: 0.0 };
+                result[positive][real] = Complex { re: sign * inv_sqrt_2, im: 0.0 };
+            }
+        }
+    }
+
+    return result;
+}
+
+/// Compute the Clebsch-Gordan coefficients coupling real spherical harmonics
+/// with angular momentum `l1` and `l2` to `l`.
+///
+/// The coefficients expressed in the basis of real spherical harmonics are
+/// either purely real (when `l1 + l2 + l` is even) or purely imaginary (when
+/// `l1 + l2 + l` is odd), this function returns the non-zero part.
+fn real_clebsch_gordan(l1: usize, l2: usize, l: usize) -> Array3<f64> {
+    let u_1 = real_to_complex(l1);
+    let u_2 = real_to_complex(l2);
+    let u = real_to_complex(l);
+
+    let even = (l1 + l2 + l) % 2 == 0;
+
+    let mut result = Array3::zeros((2 * l1 + 1, 2 * l2 + 1, 2 * l + 1));
+    for mu_1 in 0..(2 * l1 + 1) {
+        for mu_2 in 0..(2 * l2 + 1) {
+            for mu in 0..(2 * l + 1) {
+                let mut value = Complex::ZERO;
+                for m1_i in 0..(2 * l1 + 1) {
+                    let c_1 = u_1[m1_i][mu_1];
+                    if c_1 == Complex::ZERO {
+                        continue;
+                    }
+
+                    for m2_i in 0..(2 * l2 + 1) {
+                        let c_2 = u_2[m2_i][mu_2];
+                        if c_2 == Complex::ZERO {
+                            continue;
+                        }
+
+                        let m1 = m1_i as i64 - l1 as i64;
+                        let m2 = m2_i as i64 - l2 as i64;
+                        let m = m1 + m2;
+                        if m.abs() > l as i64 {
+                            continue;
+                        }
+
+                        let cg = complex_clebsch_gordan(l1 as i64, m1, l2 as i64, m2, l as i64, m);
+                        let product = c_1 * c_2 * u[(m + l as i64) as usize][mu].conj();
+                        value.re += cg * product.re;
+                        value.im += cg * product.im;
+                    }
+                }
+
+                result[[mu_1, mu_2, mu]] = if even { value.re } else { value.im };
+            }
+        }
+    }
+
+    return result;
+}
+
+/// Compute the third-order rotational invariant `\sum_{m1 m2 m3} (l l l; m1
+/// m2 m3) q_l^{m1} q_l^{m2} q_l^{m3}`, where `(l l l; m1 m2 m3)` are Wigner 3j
+/// symbols and `q_l^m` are the coefficients on complex spherical harmonics
+/// corresponding to the coefficients `real_q` (of size `2 l + 1`) on the real
+/// spherical harmonics used in rascaline.
+pub(crate) fn third_order_invariant(l: usize, real_q: ArrayView1<'_, f64>) -> f64 {
+    let size = 2 * l + 1;
+    assert_eq!(real_q.len(), size);
+
+    // q^m = \sum_μ U^*[m, μ] q_μ, since U is unitary
+    let u = real_to_complex(l);
+    let mut complex_q = vec![Complex::ZERO; size];
+    for (m_i, q) in complex_q.iter_mut().enumerate() {
+        for (mu, &value) in real_q.iter().enumerate() {
+            let c = u[m_i][mu].conj();
+            q.re += c.re * value;
+            q.im += c.im * value;
+        }
+    }
+
+    let l = l as i64;
+    let normalization = 1.0 / f64::sqrt((2 * l + 1) as f64);
+
+    let mut result = 0.0;
+    for m1 in -l..=l {
+        for m2 in -l..=l {
+            let m3 = -m1 - m2;
+            if m3.abs() > l {
+                continue;
+            }
+
+            // (l l l; m1 m2 m3) = (-1)^m3 / sqrt(2l + 1) <l m1; l m2 | l -m3>
+            let sign = if m3 % 2 == 0 { 1.0 } else { -1.0 };
+            let wigner_3j = sign * normalization * complex_clebsch_gordan(l, m1, l, m2, l, -m3);
+
+            let product = complex_q[(m1 + l) as usize] * complex_q[(m2 + l) as usize] * complex_q[(m3 + l) as usize];
+            result += wigner_3j * product.re;
+        }
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::{ClebschGordan, complex_clebsch_gordan, third_order_invariant};
+
+    #[test]
+    fn complex_values() {
+        assert_relative_eq!(complex_clebsch_gordan(1, 1, 1, -1, 0, 0), f64::sqrt(1.0 / 3.0), max_relative=1e-14);
+        assert_relative_eq!(complex_clebsch_gordan(1, 0, 1, 0, 0, 0), -f64::sqrt(1.0 / 3.0), max_relative=1e-14);
+        assert_relative_eq!(complex_clebsch_gordan(1, 1, 1, 0, 2, 1), f64::sqrt(0.5), max_relative=1e-14);
+        assert_relative_eq!(complex_clebsch_gordan(2, 1, 1, -1, 1, 0), f64::sqrt(0.3), max_relative=1e-14);
+        assert_eq!(complex_clebsch_gordan(1, 1, 1, 1, 1, 1), 0.0);
+    }
+
+    #[test]
+    fn real_scalar_coupling() {
+        // coupling to l=0 is the scalar product
+        let cg = ClebschGordan::new(3);
+        for l in 0..=3 {
+            let coefficients = cg.get(l, l, 0).unwrap();
+            let sign = if l % 2 == 0 { 1.0 } else { -1.0 };
+            for m1 in 0..(2 * l + 1) {
+                for m2 in 0..(2 * l + 1) {
+                    let expected = if m1 == m2 { sign / f64::sqrt((2 * l + 1) as f64) } else { 0.0 };
+                    assert_relative_eq!(coefficients[[m1, m2, 0]], expected, max_relative=1e-14, epsilon=1e-14);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn orthogonality() {
+        let max_angular = 3;
+        let cg = ClebschGordan::new(max_angular);
+        for l1 in 0..=max_angular {
+            for l2 in 0..=max_angular {
+                for l in 0..=max_angular {
+                    for l_prime in 0..=max_angular {
+                        let (first, second) = match (cg.get(l1, l2, l), cg.get(l1, l2, l_prime)) {
+                            (Some(first), Some(second)) => (first, second),
+                            _ => continue,
+                        };
+
+                        for m in 0..(2 * l + 1) {
+                            for m_prime in 0..(2 * l_prime + 1) {
+                                let mut sum = 0.0;
+                                for m1 in 0..(2 * l1 + 1) {
+                                    for m2 in 0..(2 * l2 + 1) {
+                                        sum += first[[m1, m2, m]] * second[[m1, m2, m_prime]];
+                                    }
+                                }
+
+                                let expected = if l == l_prime && m == m_prime { 1.0 } else { 0.0 };
+                                assert_relative_eq!(sum, expected, epsilon=1e-12);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn third_order_invariant_rotations() {
+        // the invariant of q_l = Y_l(r) does not depend on the direction r
+        let mut spherical_harmonics = crate::math::SphericalHarmonicsCache::new(4);
+
+        spherical_harmonics.compute(crate::Vector3D::new(0.0, 0.0, 1.0), false);
+        let reference = third_order_invariant(4, spherical_harmonics.values.slice(4));
+        assert!(reference.abs() > 1e-3);
+
+        let direction = crate::Vector3D::new(0.3, -0.8, 0.4).normalized();
+        spherical_harmonics.compute(direction, false);
+        let value = third_order_invariant(4, spherical_harmonics.values.slice(4));
+        assert_relative_eq!(value, reference, max_relative=1e-12);
+
+        // odd l invariants vanish
+        let value = third_order_invariant(3, spherical_harmonics.values.slice(3));
+        assert_relative_eq!(value, 0.0, epsilon=1e-14);
+    }
+}
@@ -1,16 +1,76 @@
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
 use crate::Error;
 
+/// Signature of user-provided cutoff functions, taking the distance `r` and
+/// returning both the value of the function and its derivative at `r`.
+pub type CustomCutoffFunction = dyn Fn(f64) -> (f64, f64) + Send + Sync + RefUnwindSafe;
+
 /// Possible values for the smoothing cutoff function
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone)]
 #[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
 pub enum CutoffFunction {
-    /// Step function, 1 if `r < cutoff` and 0 if `r >= cutoff`
+    /// Step function, 1 if `r < cutoff` and 0 if `r >= cutoff`.
+    ///
+    /// This is a hard cutoff without any smoothing, mainly useful to compare
+    /// with older reference implementations. The derivative is taken to be 0
+    /// everywhere, which is only correct away from the cutoff: the function
+    /// itself is discontinuous at `r = cutoff`, and so are the resulting
+    /// features and their gradients when an atom crosses the cutoff sphere.
     Step{},
     /// Shifted cosine switching function
     /// `f(r) = 1/2 * (1 + cos(π (r - cutoff + width) / width ))`
     ShiftedCosine {
         width: f64,
     },
+    /// Polynomial switching function, with a continuous first derivative at
+    /// both ends of the switching region. Using `s = (r - cutoff + width) /
+    /// width`, the function is `f(r) = (1 - s)^exponent * (1 + exponent * s)`
+    /// for `0 < s < 1`.
+    Polynomial {
+        width: f64,
+        exponent: i32,
+    },
+    /// Hyperbolic tangent switching function, with infinitely many continuous
+    /// derivatives. Using `s = (r - cutoff + width) / width`, the function is
+    /// `f(r) = 1/2 * (1 - tanh(1 / (1 - s) - 1 / s))` for `0 < s < 1`.
+    Tanh {
+        width: f64,
+    },
+    /// User-provided cutoff function, returning the value and the derivative
+    /// of the function for a given distance `r`.
+    ///
+    /// The function is responsible for going smoothly to zero at the cutoff
+    /// radius, which is not passed to it. Arbitrary functions can not be
+    /// serialized, so this variant is replaced by a placeholder string in the
+    /// calculator parameters, and can only be created from Rust code.
+    #[serde(skip_deserializing, serialize_with = "serialize_custom")]
+    #[schemars(skip)]
+    Custom(Arc<CustomCutoffFunction>),
+}
+
+impl std::fmt::Debug for CutoffFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CutoffFunction::Step {} => f.debug_struct("Step").finish(),
+            CutoffFunction::ShiftedCosine { width } => {
+                f.debug_struct("ShiftedCosine").field("width", width).finish()
+            }
+            CutoffFunction::Polynomial { width, exponent } => {
+                f.debug_struct("Polynomial").field("width", width).field("exponent", exponent).finish()
+            }
+            CutoffFunction::Tanh { width } => {
+                f.debug_struct("Tanh").field("width", width).finish()
+            }
+            CutoffFunction::Custom(_) => f.write_str("Custom(<custom function>)"),
+        }
+    }
+}
+
+/// Serialize a custom cutoff function as a placeholder string
+fn serialize_custom<S: serde::Serializer>(_: &Arc<CustomCutoffFunction>, serializer: S) -> Result<S::Ok, S::Error> {
+    return serializer.serialize_str("<custom function>");
 }
 
 impl CutoffFunction {
@@ -18,17 +78,52 @@ impl CutoffFunction {
         match self {
             CutoffFunction::Step {} => {},
             CutoffFunction::ShiftedCosine { width } => {
-                if *width <= 0.0 {
+                if !(*width > 0.0 && width.is_finite()) {
                     return Err(Error::InvalidParameter(format!(
                         "expected positive width for shifted cosine cutoff function, got {}",
                         width
                     )));
                 }
             }
+            CutoffFunction::Polynomial { width, exponent } => {
+                if !(*width > 0.0 && width.is_finite()) {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected positive width for polynomial cutoff function, got {}",
+                        width
+                    )));
+                }
+
+                if *exponent < 2 {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected exponent of at least 2 for polynomial cutoff function, got {}",
+                        exponent
+                    )));
+                }
+            }
+            CutoffFunction::Tanh { width } => {
+                if !(*width > 0.0 && width.is_finite()) {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected positive width for tanh cutoff function, got {}",
+                        width
+                    )));
+                }
+            }
+            CutoffFunction::Custom(_) => {},
         }
         return Ok(());
     }
 
+    /// Get the width of the switching region of this cutoff function, or
+    /// `None` for the step function and custom functions
+    pub fn width(&self) -> Option<f64> {
+        match *self {
+            CutoffFunction::Step {} | CutoffFunction::Custom(_) => None,
+            CutoffFunction::ShiftedCosine { width } |
+            CutoffFunction::Polynomial { width, .. } |
+            CutoffFunction::Tanh { width } => Some(width),
+        }
+    }
+
     /// Evaluate the cutoff function at the distance `r` for the given `cutoff`
     pub fn compute(&self, r: f64, cutoff: f64) -> f64 {
         match self {
@@ -45,11 +140,36 @@ impl CutoffFunction {
                     0.5 * (1. + f64::cos(s))
                 }
             }
+            CutoffFunction::Polynomial { width, exponent } => {
+                if r <= (cutoff - width) {
+                    1.0
+                } else if r >= cutoff {
+                    0.0
+                } else {
+                    let s = (r - cutoff + width) / width;
+                    (1.0 - s).powi(*exponent) * (1.0 + (*exponent as f64) * s)
+                }
+            }
+            CutoffFunction::Tanh { width } => {
+                if r <= (cutoff - width) {
+                    1.0
+                } else if r >= cutoff {
+                    0.0
+                } else {
+                    let s = (r - cutoff + width) / width;
+                    let x = 1.0 / (1.0 - s) - 1.0 / s;
+                    0.5 * (1.0 - f64::tanh(x))
+                }
+            }
+            CutoffFunction::Custom(function) => function(r).0,
         }
     }
 
     /// Evaluate the derivative of the cutoff function at the distance `r` for the
-    /// given `cutoff`
+    /// given `cutoff`.
+    ///
+    /// For [`CutoffFunction::Step`], this returns 0 everywhere, ignoring the
+    /// discontinuity at `r = cutoff`.
     pub fn derivative(&self, r: f64, cutoff: f64) -> f64 {
         match self {
             CutoffFunction::Step{} => 0.0,
@@ -61,6 +181,29 @@ impl CutoffFunction {
                     return -0.5 * std::f64::consts::PI * f64::sin(s) / width;
                 }
             }
+            CutoffFunction::Polynomial { width, exponent } => {
+                if r <= (cutoff - width) || r >= cutoff {
+                    0.0
+                } else {
+                    let s = (r - cutoff + width) / width;
+                    let n = *exponent as f64;
+                    return -n * (n + 1.0) * s * (1.0 - s).powi(exponent - 1) / width;
+                }
+            }
+            CutoffFunction::Tanh { width } => {
+                if r <= (cutoff - width) || r >= cutoff {
+                    0.0
+                } else {
+                    let s = (r - cutoff + width) / width;
+                    let x = 1.0 / (1.0 - s) - 1.0 / s;
+                    let dx_ds = 1.0 / ((1.0 - s) * (1.0 - s)) + 1.0 / (s * s);
+                    // 1 - tanh(x)^2 = 1 / cosh(x)^2, which goes to 0 instead
+                    // of NaN close to the edges of the switching region
+                    let cosh = f64::cosh(x);
+                    return -0.5 * dx_ds / (cosh * cosh * width);
+                }
+            }
+            CutoffFunction::Custom(function) => function(r).1,
         }
     }
 }
@@ -79,6 +222,14 @@ pub enum RadialScaling {
         rate: f64,
         exponent: i32,
     },
+    /// Logarithmic decay of the contribution of far away neighbors, with a
+    /// smooth behavior at $r \rightarrow 0$:
+    /// `f(r) = 1 / (1 + rate * ln(1 + (r / scale) ^ exponent))`
+    Logarithmic {
+        scale: f64,
+        rate: f64,
+        exponent: i32,
+    },
 }
 
 impl Default for RadialScaling {
@@ -113,21 +264,52 @@ impl RadialScaling {
                     )));
                 }
             }
+            RadialScaling::Logarithmic { scale, rate, exponent } => {
+                if *scale <= 0.0 {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected positive scale for logarithmic radial scaling function, got {}",
+                        scale
+                    )));
+                }
+
+                if *rate <= 0.0 {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected positive rate for logarithmic radial scaling function, got {}",
+                        rate
+                    )));
+                }
+
+                if *exponent <= 0 {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected positive exponent for logarithmic radial scaling function, got {}",
+                        exponent
+                    )));
+                }
+            }
         }
         return Ok(());
     }
 
-    /// Evaluate the radial scaling function at the distance `r`
+    /// Evaluate the radial scaling function at the distance `r`.
+    ///
+    /// This is the same function used by the SOAP calculators to weight the
+    /// contribution of each neighbor, and can be used to inspect the scaling
+    /// before running a calculation. The parameters should be checked with
+    /// [`RadialScaling::validate`] first.
     pub fn compute(&self, r: f64) -> f64 {
         match self {
             RadialScaling::None {} => 1.0,
             RadialScaling::Willatt2018 { rate, scale, exponent } => {
                 rate / (rate + (r / scale).powi(*exponent))
             }
+            RadialScaling::Logarithmic { scale, rate, exponent } => {
+                1.0 / (1.0 + rate * f64::ln_1p((r / scale).powi(*exponent)))
+            }
         }
     }
 
-    /// Evaluate the derivative of the radial scaling function at the distance `r`
+    /// Evaluate the derivative of the radial scaling function with respect to
+    /// the distance, at the distance `r`
     pub fn derivative(&self, r: f64) -> f64 {
         match self {
             RadialScaling::None {} => 0.0,
@@ -139,6 +321,15 @@ impl RadialScaling {
 
                 factor * rs_m1 / ((rate + rs_m) * (rate + rs_m))
             }
+            RadialScaling::Logarithmic { scale, rate, exponent } => {
+                let rs = r / scale;
+                let rs_m1 = rs.powi(exponent - 1);
+                let rs_m = rs * rs_m1;
+                let denominator = 1.0 + rate * f64::ln_1p(rs_m);
+                let factor = - rate * (*exponent as f64) / scale;
+
+                factor * rs_m1 / ((1.0 + rs_m) * denominator * denominator)
+            }
         }
     }
 }
@@ -146,6 +337,8 @@ impl RadialScaling {
 
 #[cfg(test)]
 mod tests {
+    use approx::assert_relative_eq;
+
     use super::*;
     #[test]
     fn step() {
@@ -153,7 +346,10 @@ mod tests {
         let cutoff = 4.0;
 
         assert_eq!(function.compute(2.0, cutoff), 1.0);
+        assert_eq!(function.compute(3.999, cutoff), 1.0);
+        assert_eq!(function.compute(4.0, cutoff), 0.0);
         assert_eq!(function.compute(5.0, cutoff), 0.0);
+        assert!(function.validate().is_ok());
     }
 
     #[test]
@@ -165,6 +361,16 @@ mod tests {
         assert_eq!(function.derivative(5.0, cutoff), 0.0);
     }
 
+    #[test]
+    fn serde() {
+        let function: CutoffFunction = serde_json::from_str(r#"{"Step": {}}"#).unwrap();
+        assert!(matches!(function, CutoffFunction::Step {}));
+        assert_eq!(serde_json::to_string(&function).unwrap(), r#"{"Step":{}}"#);
+
+        let function: CutoffFunction = serde_json::from_str(r#"{"Polynomial": {"width": 0.5, "exponent": 3}}"#).unwrap();
+        assert!(matches!(function, CutoffFunction::Polynomial { exponent: 3, .. }));
+    }
+
     #[test]
     fn shifted_cosine() {
         let function = CutoffFunction::ShiftedCosine { width: 0.5 };
@@ -188,4 +394,153 @@ mod tests {
         assert_eq!(function.derivative(4.0, cutoff), 0.0);
         assert_eq!(function.derivative(5.0, cutoff), 0.0);
     }
+
+    #[test]
+    fn polynomial() {
+        let function = CutoffFunction::Polynomial { width: 0.5, exponent: 3 };
+        let cutoff = 4.0;
+
+        assert_eq!(function.compute(2.0, cutoff), 1.0);
+        assert_eq!(function.compute(3.5, cutoff), 1.0);
+        assert_relative_eq!(function.compute(3.8, cutoff), 0.1792, max_relative=1e-12);
+        assert_eq!(function.compute(4.0, cutoff), 0.0);
+        assert_eq!(function.compute(5.0, cutoff), 0.0);
+    }
+
+    #[test]
+    fn polynomial_gradient() {
+        let function = CutoffFunction::Polynomial { width: 0.5, exponent: 3 };
+        let cutoff = 4.0;
+
+        assert_eq!(function.derivative(2.0, cutoff), 0.0);
+        assert_eq!(function.derivative(3.5, cutoff), 0.0);
+        assert_relative_eq!(function.derivative(3.8, cutoff), -2.304, max_relative=1e-12);
+        assert_eq!(function.derivative(4.0, cutoff), 0.0);
+        assert_eq!(function.derivative(5.0, cutoff), 0.0);
+
+        let delta = 1e-9;
+        for &r in &[3.51, 3.62, 3.77, 3.93, 3.99] {
+            let finite_difference = (function.compute(r + delta, cutoff) - function.compute(r, cutoff)) / delta;
+            assert_relative_eq!(function.derivative(r, cutoff), finite_difference, epsilon=1e-6, max_relative=1e-5);
+        }
+    }
+
+    #[test]
+    fn tanh() {
+        let function = CutoffFunction::Tanh { width: 0.5 };
+        let cutoff = 4.0;
+
+        assert_eq!(function.compute(2.0, cutoff), 1.0);
+        assert_eq!(function.compute(3.5, cutoff), 1.0);
+        assert_eq!(function.compute(3.75, cutoff), 0.5);
+        assert_relative_eq!(function.compute(3.8, cutoff), 0.1588691048809152, max_relative=1e-12);
+        assert_eq!(function.compute(4.0, cutoff), 0.0);
+        assert_eq!(function.compute(5.0, cutoff), 0.0);
+    }
+
+    #[test]
+    fn tanh_gradient() {
+        let function = CutoffFunction::Tanh { width: 0.5 };
+        let cutoff = 4.0;
+
+        assert_eq!(function.derivative(2.0, cutoff), 0.0);
+        assert_eq!(function.derivative(3.5, cutoff), 0.0);
+        assert_relative_eq!(function.derivative(3.8, cutoff), -4.825517392050764, max_relative=1e-12);
+        assert_eq!(function.derivative(4.0, cutoff), 0.0);
+        assert_eq!(function.derivative(5.0, cutoff), 0.0);
+
+        // no NaN close to the edges
+        assert!(function.derivative(3.5 + 1e-10, cutoff).is_finite());
+        assert!(function.derivative(4.0 - 1e-10, cutoff).is_finite());
+
+        let delta = 1e-9;
+        for &r in &[3.51, 3.62, 3.77, 3.93, 3.99] {
+            let finite_difference = (function.compute(r + delta, cutoff) - function.compute(r, cutoff)) / delta;
+            assert_relative_eq!(function.derivative(r, cutoff), finite_difference, epsilon=1e-6, max_relative=1e-5);
+        }
+    }
+
+    #[test]
+    fn custom() {
+        let cutoff = 4.0;
+        let function = CutoffFunction::Custom(Arc::new(move |r: f64| {
+            if r >= cutoff {
+                (0.0, 0.0)
+            } else {
+                (1.0 - r / cutoff, -1.0 / cutoff)
+            }
+        }));
+        assert!(function.validate().is_ok());
+        assert_eq!(function.width(), None);
+
+        assert_eq!(function.compute(0.0, cutoff), 1.0);
+        assert_eq!(function.compute(2.0, cutoff), 0.5);
+        assert_eq!(function.compute(5.0, cutoff), 0.0);
+        assert_eq!(function.derivative(2.0, cutoff), -0.25);
+        assert_eq!(function.derivative(5.0, cutoff), 0.0);
+
+        let delta = 1e-9;
+        for &r in &[0.5, 1.2, 2.7, 3.5, 3.99] {
+            let finite_difference = (function.compute(r + delta, cutoff) - function.compute(r, cutoff)) / delta;
+            assert_relative_eq!(function.derivative(r, cutoff), finite_difference, epsilon=1e-6, max_relative=1e-5);
+        }
+
+        assert_eq!(serde_json::to_string(&function).unwrap(), r#"{"Custom":"<custom function>"}"#);
+        assert!(serde_json::from_str::<CutoffFunction>(r#"{"Custom":"<custom function>"}"#).is_err());
+    }
+
+    #[test]
+    fn radial_scaling_continuity_at_zero() {
+        let all_scalings = [
+            RadialScaling::None {},
+            RadialScaling::Willatt2018 { scale: 1.5, rate: 0.8, exponent: 1 },
+            RadialScaling::Willatt2018 { scale: 1.5, rate: 0.8, exponent: 4 },
+            RadialScaling::Logarithmic { scale: 1.5, rate: 0.8, exponent: 1 },
+            RadialScaling::Logarithmic { scale: 1.5, rate: 0.8, exponent: 4 },
+        ];
+
+        for scaling in all_scalings {
+            scaling.validate().unwrap();
+
+            let value = scaling.compute(0.0);
+            let derivative = scaling.derivative(0.0);
+            assert!(value.is_finite() && derivative.is_finite());
+
+            let r = 1e-8;
+            assert_relative_eq!(scaling.compute(r), value, epsilon=1e-7);
+            assert_relative_eq!(scaling.derivative(r), derivative, epsilon=1e-6);
+
+            let finite_difference = (scaling.compute(r) - value) / r;
+            assert_relative_eq!(finite_difference, derivative, epsilon=1e-6);
+        }
+    }
+
+    #[test]
+    fn logarithmic_scaling() {
+        let scaling = RadialScaling::Logarithmic { scale: 1.5, rate: 0.8, exponent: 2 };
+        assert!(scaling.validate().is_ok());
+
+        assert_eq!(scaling.compute(0.0), 1.0);
+        assert_eq!(scaling.derivative(0.0), 0.0);
+        assert_relative_eq!(scaling.compute(2.0), 0.5502605214414199, max_relative=1e-12);
+        assert_relative_eq!(scaling.derivative(2.0), -0.1550267604259755, max_relative=1e-12);
+
+        let delta = 1e-9;
+        for &r in &[0.1, 0.7, 1.5, 3.2, 6.0] {
+            let finite_difference = (scaling.compute(r + delta) - scaling.compute(r)) / delta;
+            assert_relative_eq!(scaling.derivative(r), finite_difference, epsilon=1e-6, max_relative=1e-5);
+        }
+
+        let scaling = RadialScaling::Logarithmic { scale: 1.5, rate: -0.8, exponent: 2 };
+        assert!(scaling.validate().is_err());
+    }
+
+    #[test]
+    fn polynomial_validation() {
+        let function = CutoffFunction::Polynomial { width: 0.5, exponent: 1 };
+        assert!(function.validate().is_err());
+
+        let function = CutoffFunction::Polynomial { width: -0.5, exponent: 3 };
+        assert!(function.validate().is_err());
+    }
 }
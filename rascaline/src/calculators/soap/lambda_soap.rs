@@ -0,0 +1,513 @@
+use ndarray::{s, Array3, ArrayView1};
+
+use equistore::{TensorMap, Labels, LabelsBuilder, LabelValue};
+
+use crate::calculators::CalculatorBase;
+use crate::{CalculationOptions, Calculator};
+use crate::{Error, System};
+
+use super::SphericalExpansionParameters;
+use super::{SphericalExpansion, CutoffFunction, RadialScaling};
+use super::bispectrum::ExpansionsByCenter;
+use super::clebsch_gordan::ClebschGordan;
+use crate::calculators::radial_basis::RadialBasis;
+
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterTwoNeighborsSpeciesKeys};
+
+/// Parameters for the lambda-SOAP calculator.
+///
+/// The lambda-SOAP representation is an equivariant generalization of the SOAP
+/// power spectrum, where pairs of spherical expansion coefficients are coupled
+/// to an angular momentum `lambda` with Clebsch-Gordan coefficients, instead
+/// of being summed over `m`:
+///
+/// `< l1 l2 n1 n2 | X_i^{lambda mu} > = \sum_{m1 m2} C^{l1 l2 lambda}_{m1 m2 mu}
+///     < n1 l1 m1 | X_i^{a} > < n2 l2 m2 | X_i^{b} >`
+///
+/// The resulting features transform like the real spherical harmonics
+/// `Y_lambda^mu` under rotations. Features with odd `l1 + l2 + lambda` change
+/// sign under inversion (they are pseudo-tensors) while features with even
+/// `l1 + l2 + lambda` are proper tensors.
+///
+/// For `lambda = 0`, `l1 = l2 = l` and a single neighbor species, this is the
+/// same as the power spectrum multiplied by `(-1)^l`.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct LambdaSoapParameters {
+    /// Spherical cutoff to use for atomic environments
+    pub cutoff: f64,
+    /// Number of radial basis function to use
+    pub max_radial: usize,
+    /// Number of spherical harmonics to use, this is also the maximal value
+    /// of `lambda`
+    pub max_angular: usize,
+    /// Width of the atom-centered gaussian creating the atomic density
+    pub atomic_gaussian_width: f64,
+    /// Weight of the central atom contribution to the
+    /// features. If `1.0` the center atom contribution is weighted the same
+    /// as any other contribution. If `0.0` the central atom does not
+    /// contribute to the features at all.
+    pub center_atom_weight: f64,
+    /// radial basis to use for the radial integral
+    pub radial_basis: RadialBasis,
+    /// cutoff function used to smooth the behavior around the cutoff radius
+    pub cutoff_function: CutoffFunction,
+    /// radial scaling can be used to reduce the importance of neighbor atoms
+    /// further away from the center, usually improving the performance of the
+    /// model
+    #[serde(default)]
+    pub radial_scaling: RadialScaling,
+}
+
+/// Calculator implementing the lambda-SOAP equivariant representation of
+/// atomistic systems.
+pub struct LambdaSoap {
+    parameters: LambdaSoapParameters,
+    spherical_expansion: Calculator,
+    clebsch_gordan: ClebschGordan,
+}
+
+impl std::fmt::Debug for LambdaSoap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.parameters)
+    }
+}
+
+impl LambdaSoap {
+    pub fn new(parameters: LambdaSoapParameters) -> Result<LambdaSoap, Error> {
+        let expansion_parameters = SphericalExpansionParameters {
+            cutoff: parameters.cutoff,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
+            max_radial: parameters.max_radial,
+            max_angular: parameters.max_angular,
+            angular_channels: None,
+            atomic_gaussian_width: parameters.atomic_gaussian_width,
+            center_atom_weight: parameters.center_atom_weight,
+            radial_basis: parameters.radial_basis.clone(),
+            cutoff_function: parameters.cutoff_function.clone(),
+            radial_scaling: parameters.radial_scaling,
+            species_occupations: Default::default(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
+        };
+        let spherical_expansion = SphericalExpansion::new(expansion_parameters)?;
+
+        return Ok(LambdaSoap {
+            clebsch_gordan: ClebschGordan::new(parameters.max_angular),
+            parameters: parameters,
+            spherical_expansion: Calculator::from(
+                Box::new(spherical_expansion) as Box<dyn CalculatorBase>
+            ),
+        });
+    }
+}
+
+/// Compute the `m`-th component of the coupling of `first` and `second` with
+/// the Clebsch-Gordan coefficients `cg`
+fn couple(cg: &Array3<f64>, first: ArrayView1<f64>, second: ArrayView1<f64>, m: usize) -> f64 {
+    let mut result = 0.0;
+    for (m1, a) in first.iter().enumerate() {
+        for (m2, b) in second.iter().enumerate() {
+            result += cg[[m1, m2, m]] * a * b;
+        }
+    }
+    return result;
+}
+
+impl CalculatorBase for LambdaSoap {
+    fn name(&self) -> String {
+        "lambda-SOAP".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<equistore::Labels, Error> {
+        let builder = CenterTwoNeighborsSpeciesKeys {
+            cutoff: self.parameters.cutoff,
+            self_pairs: true,
+            symmetric: true,
+            all_species: None,
+        };
+        let species_keys = builder.keys(systems)?;
+
+        let mut keys = LabelsBuilder::new(vec!["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+        for &[species_center, species_neighbor_1, species_neighbor_2] in species_keys.iter_fixed_size() {
+            for spherical_harmonics_l in 0..=self.parameters.max_angular {
+                keys.add(&[spherical_harmonics_l.into(), species_center, species_neighbor_1, species_neighbor_2]);
+            }
+        }
+
+        return Ok(keys.finish());
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &equistore::Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+        let mut result = Vec::new();
+        for [_, species_center, species_neighbor_1, species_neighbor_2] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                // we only want center with both neighbor species present
+                species_neighbor: SpeciesFilter::AllOf(
+                    [
+                        species_neighbor_1.i32(),
+                        species_neighbor_2.i32()
+                    ].iter().copied().collect()
+                ),
+                self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+        assert_eq!(keys.count(), samples.len());
+
+        let mut gradient_samples = Vec::new();
+        for ([_, species_center, species_neighbor_1, species_neighbor_2], samples) in keys.iter_fixed_size().zip(samples) {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                // gradients samples should contain either neighbor species
+                species_neighbor: SpeciesFilter::OneOf(vec![
+                    species_neighbor_1.i32(),
+                    species_neighbor_2.i32()
+                ]),
+                self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            gradient_samples.push(builder.gradients_for(systems, samples)?);
+        }
+
+        return Ok(gradient_samples);
+    }
+
+    fn supports_gradient(&self, parameter: &str) -> bool {
+        match parameter {
+            "positions" => true,
+            _ => false,
+        }
+    }
+
+    fn components(&self, keys: &equistore::Labels) -> Vec<Vec<Labels>> {
+        assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+
+        let mut result = Vec::new();
+        for [spherical_harmonics_l, _, _, _] in keys.iter_fixed_size() {
+            let mut component = LabelsBuilder::new(vec!["spherical_harmonics_m"]);
+            for m in -spherical_harmonics_l.i32()..=spherical_harmonics_l.i32() {
+                component.add(&[LabelValue::new(m)]);
+            }
+            result.push(vec![component.finish()]);
+        }
+
+        return result;
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        vec!["l1", "l2", "n1", "n2"]
+    }
+
+    fn properties(&self, keys: &equistore::Labels) -> Vec<Labels> {
+        assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+        let max_angular = self.parameters.max_angular;
+
+        let mut result = Vec::new();
+        for [spherical_harmonics_l, _, _, _] in keys.iter_fixed_size() {
+            let mut properties = LabelsBuilder::new(self.properties_names());
+            for l1 in 0..=max_angular {
+                for l2 in 0..=max_angular {
+                    if self.clebsch_gordan.get(l1, l2, spherical_harmonics_l.usize()).is_none() {
+                        continue;
+                    }
+
+                    for n1 in 0..self.parameters.max_radial {
+                        for n2 in 0..self.parameters.max_radial {
+                            properties.add(&[l1, l2, n1, n2]);
+                        }
+                    }
+                }
+            }
+            result.push(properties.finish());
+        }
+
+        return result;
+    }
+
+    #[time_graph::instrument(name = "LambdaSoap::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+
+        let mut gradients = Vec::new();
+        if descriptor.block_by_id(0).gradient("positions").is_some() {
+            gradients.push("positions");
+        }
+
+        let options = CalculationOptions {
+            gradients: &gradients,
+            ..Default::default()
+        };
+        let spherical_expansion = self.spherical_expansion.compute(systems, options)?;
+        let expansions = ExpansionsByCenter::new(&spherical_expansion);
+
+        for (key, mut block) in descriptor.iter_mut() {
+            let lambda = key[0].usize();
+            let species_neighbor_1 = key[2].i32();
+            let species_neighbor_2 = key[3].i32();
+
+            let samples = block.samples();
+            let properties = block.properties();
+
+            let values = block.values_mut().to_array_mut();
+            for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                let expansion = if let Some(expansion) = expansions.values.get(&(structure.usize(), center.usize())) {
+                    expansion
+                } else {
+                    continue;
+                };
+
+                for (property_i, &[l1, l2, n1, n2]) in properties.iter_fixed_size().enumerate() {
+                    let cg = self.clebsch_gordan.get(l1.usize(), l2.usize(), lambda).expect("invalid l1, l2, lambda");
+
+                    let first = expansion.by_species.get(&(l1.usize(), species_neighbor_1));
+                    let second = expansion.by_species.get(&(l2.usize(), species_neighbor_2));
+
+                    if let (Some(first), Some(second)) = (first, second) {
+                        for m in 0..(2 * lambda + 1) {
+                            values[[sample_i, m, property_i]] = couple(
+                                cg, first.column(n1.usize()), second.column(n2.usize()), m
+                            );
+                        }
+                    }
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("positions") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                for (grad_sample_i, &[sample_i, structure, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    let center = samples[sample_i.usize()][1];
+                    let expansion = expansions.values.get(&(structure.usize(), center.usize()));
+                    let expansion_gradient = expansions.gradients.get(&(structure.usize(), center.usize(), atom.usize()));
+
+                    let (expansion, expansion_gradient) = if let (Some(e), Some(g)) = (expansion, expansion_gradient) {
+                        (e, g)
+                    } else {
+                        continue;
+                    };
+
+                    for (property_i, &[l1, l2, n1, n2]) in properties.iter_fixed_size().enumerate() {
+                        let (l1, l2) = (l1.usize(), l2.usize());
+                        let (n1, n2) = (n1.usize(), n2.usize());
+                        let cg = self.clebsch_gordan.get(l1, l2, lambda).expect("invalid l1, l2, lambda");
+
+                        let first = expansion.by_species.get(&(l1, species_neighbor_1));
+                        let second = expansion.by_species.get(&(l2, species_neighbor_2));
+
+                        let first_gradient = expansion_gradient.by_species.get(&(l1, species_neighbor_1));
+                        let second_gradient = expansion_gradient.by_species.get(&(l2, species_neighbor_2));
+
+                        for d in 0..3 {
+                            for m in 0..(2 * lambda + 1) {
+                                let mut sum = 0.0;
+                                if let (Some(first_gradient), Some(second)) = (first_gradient, second) {
+                                    sum += couple(cg, first_gradient.slice(s![d, .., n1]), second.column(n2), m);
+                                }
+
+                                if let (Some(first), Some(second_gradient)) = (first, second_gradient) {
+                                    sum += couple(cg, first.column(n1), second_gradient.slice(s![d, .., n2]), m);
+                                }
+
+                                array[[grad_sample_i, d, m, property_i]] = sum;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use equistore::LabelValue;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+
+    use super::{LambdaSoap, LambdaSoapParameters};
+    use super::super::CutoffFunction;
+    use crate::calculators::radial_basis::RadialBasis;
+
+    fn parameters() -> LambdaSoapParameters {
+        LambdaSoapParameters {
+            cutoff: 3.5,
+            max_radial: 3,
+            max_angular: 2,
+            atomic_gaussian_width: 0.3,
+            center_atom_weight: 1.0,
+            radial_basis: RadialBasis::splined_gto(1e-8),
+            radial_scaling: Default::default(),
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+        }
+    }
+
+    #[test]
+    fn components() {
+        let mut calculator = Calculator::from(Box::new(LambdaSoap::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = [Box::new(test_system("water")) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        assert_eq!(descriptor.keys().names(), ["spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"]);
+        for (key, block) in descriptor.iter() {
+            let lambda = key[0].i32();
+
+            let components = block.components();
+            assert_eq!(components.len(), 1);
+            assert_eq!(components[0].names(), ["spherical_harmonics_m"]);
+            assert_eq!(components[0].count(), (2 * lambda + 1) as usize);
+
+            for &[l1, l2, _, _] in block.properties().iter_fixed_size() {
+                let (l1, l2) = (l1.i32(), l2.i32());
+                assert!((l1 - l2).abs() <= lambda && lambda <= l1 + l2);
+            }
+        }
+    }
+
+    #[test]
+    fn power_spectrum() {
+        let mut calculator = Calculator::from(Box::new(LambdaSoap::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut power_spectrum = Calculator::new("soap_power_spectrum", r#"{
+            "cutoff": 3.5,
+            "max_radial": 3,
+            "max_angular": 2,
+            "atomic_gaussian_width": 0.3,
+            "center_atom_weight": 1.0,
+            "radial_basis": {"Gto": {"splined_radial_integral": true, "spline_accuracy": 1e-8}},
+            "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+        }"#.into()).unwrap();
+
+        let mut systems = [Box::new(test_system("water")) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        let expected = power_spectrum.compute(&mut systems, Default::default()).unwrap();
+
+        for (key, expected) in expected.iter() {
+            if key[1] != key[2] {
+                // different normalization for different neighbor species
+                continue;
+            }
+
+            let block_i = descriptor.keys().position(&[LabelValue::new(0), key[0], key[1], key[2]]).unwrap();
+            let block = descriptor.block_by_id(block_i);
+            assert_eq!(block.samples(), expected.samples());
+
+            let values = block.values().to_array();
+            let expected_values = expected.values().to_array();
+            for (expected_i, &[l, n1, n2]) in expected.properties().iter_fixed_size().enumerate() {
+                let property_i = block.properties().position(&[l, l, n1, n2]).unwrap();
+                let sign = if l.usize() % 2 == 0 { 1.0 } else { -1.0 };
+                for sample_i in 0..block.samples().count() {
+                    assert_relative_eq!(
+                        values[[sample_i, 0, property_i]],
+                        sign * expected_values[[sample_i, expected_i]],
+                        max_relative=1e-10, epsilon=1e-14
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_norm() {
+        let mut calculator = Calculator::from(Box::new(LambdaSoap::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let positions = [
+            (6, Vector3D::new(0.0, 0.0, 0.0)),
+            (1, Vector3D::new(0.5288, 0.1610, 0.9359)),
+            (1, Vector3D::new(0.2051, 0.8240, -0.6786)),
+            (8, Vector3D::new(0.3345, -0.9314, -0.4496)),
+            (1, Vector3D::new(-1.0685, -0.0537, 0.1921)),
+        ];
+
+        // rotation around z, followed by a rotation around x
+        let (cos_1, sin_1) = (f64::cos(0.3), f64::sin(0.3));
+        let (cos_2, sin_2) = (f64::cos(1.1), f64::sin(1.1));
+        let rotate = |v: Vector3D| {
+            let x = cos_1 * v[0] - sin_1 * v[1];
+            let y = sin_1 * v[0] + cos_1 * v[1];
+            let z = v[2];
+            Vector3D::new(x, cos_2 * y - sin_2 * z, sin_2 * y + cos_2 * z)
+        };
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        let mut rotated = SimpleSystem::new(UnitCell::infinite());
+        for &(species, position) in &positions {
+            system.add_atom(species, position);
+            rotated.add_atom(species, rotate(position));
+        }
+
+        let expected = calculator.compute(&mut [Box::new(system) as Box<dyn System>], Default::default()).unwrap();
+        let descriptor = calculator.compute(&mut [Box::new(rotated) as Box<dyn System>], Default::default()).unwrap();
+
+        // the features are equivariant, so the norm over the m component
+        // should be invariant under rotation
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+
+            let values = block.values().to_array();
+            let expected = expected.values().to_array();
+            let norm = values.mapv(|v| v * v).sum_axis(ndarray::Axis(1));
+            let expected_norm = expected.mapv(|v| v * v).sum_axis(ndarray::Axis(1));
+            assert_relative_eq!(norm, expected_norm, max_relative=1e-9, epsilon=1e-12);
+        }
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let calculator = Calculator::from(Box::new(LambdaSoap::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 5e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+}
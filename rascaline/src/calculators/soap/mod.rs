@@ -6,17 +6,27 @@ pub use self::radial_integral::{SoapRadialIntegralSpline, SoapRadialIntegralSpli
 pub use self::radial_integral::{SoapRadialIntegralCache, SoapRadialIntegralParameters};
 
 mod cutoff;
-pub use self::cutoff::CutoffFunction;
+pub use self::cutoff::{CutoffFunction, CustomCutoffFunction};
 pub use self::cutoff::RadialScaling;
 
 mod spherical_expansion_pair;
-pub use self::spherical_expansion_pair::{SphericalExpansionByPair, SphericalExpansionParameters};
+pub use self::spherical_expansion_pair::{SphericalExpansionByPair, SphericalExpansionParameters, SpeciesDistanceWeight, VelocityWeight};
+pub use self::spherical_expansion_pair::SphericalExpansionParametersBuilder;
 
 mod spherical_expansion;
 pub use self::spherical_expansion::SphericalExpansion;
 
 mod power_spectrum;
-pub use self::power_spectrum::{SoapPowerSpectrum, PowerSpectrumParameters};
+pub use self::power_spectrum::{SoapPowerSpectrum, PowerSpectrumParameters, PowerSpectrumParametersBuilder, NeighborContribution};
 
 mod radial_spectrum;
 pub use self::radial_spectrum::{SoapRadialSpectrum, RadialSpectrumParameters};
+
+mod clebsch_gordan;
+pub(crate) use self::clebsch_gordan::third_order_invariant;
+
+mod bispectrum;
+pub use self::bispectrum::{SoapBispectrum, BispectrumParameters};
+
+mod lambda_soap;
+pub use self::lambda_soap::{LambdaSoap, LambdaSoapParameters};
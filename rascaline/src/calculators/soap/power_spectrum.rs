@@ -1,16 +1,18 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::btree_map::Entry;
 
 use ndarray::parallel::prelude::*;
+use ndarray::{Array2, ArrayD, ArrayView2, ArrayView3, ArrayView4, ArrayView5, Axis, Ix2, s};
 
-use equistore::{TensorMap, TensorBlock, EmptyArray};
+use equistore::{TensorMap, TensorBlock, TensorBlockRef, EmptyArray};
 use equistore::{LabelsBuilder, Labels, LabelValue};
 
 use crate::calculators::CalculatorBase;
 use crate::{CalculationOptions, Calculator, LabelsSelection};
 use crate::{Error, System};
 
-use super::SphericalExpansionParameters;
-use super::{SphericalExpansion, CutoffFunction, RadialScaling};
+use super::{SphericalExpansionParameters, SphericalExpansionParametersBuilder};
+use super::{SphericalExpansion, SphericalExpansionByPair, CutoffFunction, RadialScaling};
 use crate::calculators::radial_basis::RadialBasis;
 
 use crate::labels::{SpeciesFilter, SamplesBuilder};
@@ -56,6 +58,131 @@ pub struct PowerSpectrumParameters {
     /// model
     #[serde(default)]
     pub radial_scaling: RadialScaling,
+    /// Use a fused computation mode, where the spherical expansion is only
+    /// computed for a small chunk of centers at the time, and immediately
+    /// combined into the power spectrum. This reduces the peak memory usage
+    /// for large systems, at the cost of some repeated work (in particular
+    /// for neighbors lists and pairs filtering).
+    #[serde(default)]
+    pub fused: bool,
+    /// Use this set of species to create the keys instead of the species
+    /// found in the systems. All the species in the systems must be part of
+    /// this set, and the blocks for species absent from the systems are
+    /// empty. This allows to get the same keys when computing the power
+    /// spectrum for different subsets of a dataset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub all_species: Option<Vec<i32>>,
+}
+
+impl PowerSpectrumParameters {
+    /// Create a builder for `PowerSpectrumParameters`, using default values
+    /// for all optional parameters. The `cutoff`, `max_radial`, `max_angular`
+    /// and `atomic_gaussian_width` must be set before calling
+    /// [`PowerSpectrumParametersBuilder::build`].
+    pub fn builder() -> PowerSpectrumParametersBuilder {
+        PowerSpectrumParametersBuilder {
+            expansion: SphericalExpansionParameters::builder(),
+            fused: false,
+        }
+    }
+
+    /// Validate all the parameters
+    pub fn validate(&self) -> Result<(), Error> {
+        SoapPowerSpectrum::expansion_parameters(self).validate()
+    }
+}
+
+/// Builder for [`PowerSpectrumParameters`], created with
+/// [`PowerSpectrumParameters::builder`].
+///
+/// The optional parameters use the same defaults as
+/// [`SphericalExpansionParametersBuilder`], and the `fused` mode is disabled
+/// by default.
+#[derive(Debug, Clone)]
+pub struct PowerSpectrumParametersBuilder {
+    expansion: SphericalExpansionParametersBuilder,
+    fused: bool,
+}
+
+impl PowerSpectrumParametersBuilder {
+    /// Set the spherical cutoff to use for atomic environments
+    pub fn cutoff(mut self, cutoff: f64) -> Self {
+        self.expansion = self.expansion.cutoff(cutoff);
+        self
+    }
+
+    /// Set the number of radial basis functions
+    pub fn max_radial(mut self, max_radial: usize) -> Self {
+        self.expansion = self.expansion.max_radial(max_radial);
+        self
+    }
+
+    /// Set the number of spherical harmonics
+    pub fn max_angular(mut self, max_angular: usize) -> Self {
+        self.expansion = self.expansion.max_angular(max_angular);
+        self
+    }
+
+    /// Set the width of the atom-centered gaussian densities
+    pub fn atomic_gaussian_width(mut self, atomic_gaussian_width: f64) -> Self {
+        self.expansion = self.expansion.atomic_gaussian_width(atomic_gaussian_width);
+        self
+    }
+
+    /// Set the weight of the central atom contribution
+    pub fn center_atom_weight(mut self, center_atom_weight: f64) -> Self {
+        self.expansion = self.expansion.center_atom_weight(center_atom_weight);
+        self
+    }
+
+    /// Set the radial basis
+    pub fn radial_basis(mut self, radial_basis: RadialBasis) -> Self {
+        self.expansion = self.expansion.radial_basis(radial_basis);
+        self
+    }
+
+    /// Set the cutoff function
+    pub fn cutoff_function(mut self, cutoff_function: CutoffFunction) -> Self {
+        self.expansion = self.expansion.cutoff_function(cutoff_function);
+        self
+    }
+
+    /// Set the radial scaling
+    pub fn radial_scaling(mut self, radial_scaling: RadialScaling) -> Self {
+        self.expansion = self.expansion.radial_scaling(radial_scaling);
+        self
+    }
+
+    /// Use the given set of species to create the keys, instead of the
+    /// species found in the systems
+    pub fn all_species(mut self, all_species: Vec<i32>) -> Self {
+        self.expansion = self.expansion.all_species(all_species);
+        self
+    }
+
+    /// Use the fused computation mode
+    pub fn fused(mut self, fused: bool) -> Self {
+        self.fused = fused;
+        self
+    }
+
+    /// Create the parameters, checking that all required parameters are set
+    /// and that all parameters are valid.
+    pub fn build(self) -> Result<PowerSpectrumParameters, Error> {
+        let expansion = self.expansion.build()?;
+        return Ok(PowerSpectrumParameters {
+            cutoff: expansion.cutoff,
+            max_radial: expansion.max_radial,
+            max_angular: expansion.max_angular,
+            atomic_gaussian_width: expansion.atomic_gaussian_width,
+            center_atom_weight: expansion.center_atom_weight,
+            radial_basis: expansion.radial_basis,
+            cutoff_function: expansion.cutoff_function,
+            radial_scaling: expansion.radial_scaling,
+            fused: self.fused,
+            all_species: expansion.all_species,
+        });
+    }
 }
 
 /// Calculator implementing the Smooth Overlap of Atomic Position (SOAP) power
@@ -71,27 +198,555 @@ impl std::fmt::Debug for SoapPowerSpectrum {
     }
 }
 
+/// Maximal number of centers for which the spherical expansion is computed at
+/// once in fused mode
+const FUSED_CENTERS_CHUNK: usize = 64;
+
+/// Samples of a block in the full power spectrum corresponding to the samples
+/// of a block in a chunk, when using fused mode
+struct ChunkSamples {
+    /// index of the block in the full power spectrum
+    block_i: usize,
+    /// index of the samples in the full power spectrum block
+    values: Vec<usize>,
+    /// index of the positions gradient samples in the full power spectrum
+    /// block
+    positions: Vec<usize>,
+    /// index of the cell gradient samples in the full power spectrum block
+    cell: Vec<usize>,
+    /// index of the strain gradient samples in the full power spectrum block
+    strain: Vec<usize>,
+}
+
+/// Sensitivity of the power spectrum around a given center to the presence of
+/// one of its neighbors, as computed by
+/// [`SoapPowerSpectrum::neighbor_sensitivity`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NeighborContribution {
+    /// index of the system containing the center and the neighbor
+    pub structure: usize,
+    /// index of the central atom in the system
+    pub center: usize,
+    /// index of the neighbor atom in the system
+    pub neighbor: usize,
+    /// norm of the power spectrum features for this center, including all
+    /// neighbors
+    pub norm: f64,
+    /// norm of the power spectrum features for this center if the neighbor
+    /// (and all its periodic images) were removed
+    pub norm_without_neighbor: f64,
+}
+
+impl NeighborContribution {
+    /// Change in the norm of the features when removing this neighbor
+    pub fn change(&self) -> f64 {
+        return self.norm - self.norm_without_neighbor;
+    }
+}
+
+/// Spherical expansion around a single center, both in total and split
+/// between the different neighbors. The maps are indexed by
+/// `(spherical_harmonics_l, species_neighbor)`, and the arrays have shape
+/// `(m, n)`.
+#[derive(Default)]
+struct CenterExpansion {
+    total: BTreeMap<(usize, LabelValue), Array2<f64>>,
+    by_neighbor: BTreeMap<usize, BTreeMap<(usize, LabelValue), Array2<f64>>>,
+}
+
+fn accumulate_expansion(
+    expansion: &mut BTreeMap<(usize, LabelValue), Array2<f64>>,
+    channel: (usize, LabelValue),
+    value: ndarray::ArrayView2<f64>,
+) {
+    match expansion.entry(channel) {
+        Entry::Occupied(mut entry) => *entry.get_mut() += &value,
+        Entry::Vacant(entry) => {
+            entry.insert(value.to_owned());
+        }
+    }
+}
+
+/// Compute the squared norm of the full power spectrum (including all pairs
+/// of neighbor species) associated with the given spherical `expansion`.
+///
+/// Storing only `species_neighbor_1 <= species_neighbor_2` with a `sqrt(2)`
+/// factor for different species gives the same norm as summing over all
+/// ordered pairs of species, which is what we do here.
+fn power_spectrum_norm2(expansion: &BTreeMap<(usize, LabelValue), Array2<f64>>) -> f64 {
+    let mut norm2 = 0.0;
+    for ((l_1, _), expansion_1) in expansion {
+        for ((l_2, _), expansion_2) in expansion {
+            if l_1 != l_2 {
+                continue;
+            }
+
+            let product = expansion_1.t().dot(expansion_2);
+            norm2 += product.iter().map(|v| v * v).sum::<f64>() / (2 * l_1 + 1) as f64;
+        }
+    }
+    return norm2;
+}
+
 impl SoapPowerSpectrum {
     pub fn new(parameters: PowerSpectrumParameters) -> Result<SoapPowerSpectrum, Error> {
-        let expansion_parameters = SphericalExpansionParameters {
+        let expansion_parameters = SoapPowerSpectrum::expansion_parameters(&parameters);
+        let spherical_expansion = SphericalExpansion::new(expansion_parameters)?;
+
+        return Ok(SoapPowerSpectrum {
+            parameters: parameters,
+            spherical_expansion: Calculator::from(
+                Box::new(spherical_expansion) as Box<dyn CalculatorBase>
+            ),
+        });
+    }
+
+    /// Get the parameters of the spherical expansion used to compute a power
+    /// spectrum with the given `parameters`
+    fn expansion_parameters(parameters: &PowerSpectrumParameters) -> SphericalExpansionParameters {
+        SphericalExpansionParameters {
             cutoff: parameters.cutoff,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
             max_radial: parameters.max_radial,
             max_angular: parameters.max_angular,
+            angular_channels: None,
             atomic_gaussian_width: parameters.atomic_gaussian_width,
             center_atom_weight: parameters.center_atom_weight,
             radial_basis: parameters.radial_basis.clone(),
-            cutoff_function: parameters.cutoff_function,
+            cutoff_function: parameters.cutoff_function.clone(),
             radial_scaling: parameters.radial_scaling,
-        };
+            species_occupations: Default::default(),
+            all_species: parameters.all_species.clone(),
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
+        }
+    }
 
-        let spherical_expansion = SphericalExpansion::new(expansion_parameters)?;
+    /// Compute how much each neighbor contributes to the norm of the power
+    /// spectrum features around each center, in a leave-one-out fashion.
+    ///
+    /// Since the spherical expansion is a sum of pair contributions, removing
+    /// a neighbor `j` from the environment of center `i` amounts to removing
+    /// the contribution of the `i-j` pair (and all its periodic images) from
+    /// the expansion coefficients. The power spectrum of the modified
+    /// environment is then computed analytically from these coefficients,
+    /// without going through a new calculation.
+    ///
+    /// The norm of the features includes all pairs of neighbor species, and
+    /// all `l, n1, n2` properties. Gradients are not supported, and labels
+    /// selection in `options` are ignored.
+    pub fn neighbor_sensitivity(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        options: CalculationOptions,
+    ) -> Result<Vec<NeighborContribution>, Error> {
+        if !options.gradients.is_empty() {
+            return Err(Error::InvalidParameter(
+                "gradients are not supported when computing neighbor sensitivity".into()
+            ));
+        }
 
-        return Ok(SoapPowerSpectrum {
-            parameters: parameters,
-            spherical_expansion: Calculator::from(
-                Box::new(spherical_expansion) as Box<dyn CalculatorBase>
-            ),
-        });
+        let by_pair = SphericalExpansionByPair::new(
+            SoapPowerSpectrum::expansion_parameters(&self.parameters)
+        )?;
+        let mut by_pair = Calculator::from(Box::new(by_pair) as Box<dyn CalculatorBase>);
+        let by_pair = by_pair.compute(systems, CalculationOptions {
+            use_native_system: options.use_native_system,
+            ..Default::default()
+        })?;
+
+        let mut environments: BTreeMap<(usize, usize), CenterExpansion> = BTreeMap::new();
+        for (key, block) in by_pair.iter() {
+            let spherical_harmonics_l = key[0].usize();
+            let channel = (spherical_harmonics_l, key[2]);
+
+            let samples = block.samples();
+            let values = block.values().to_array();
+            for (sample_i, &[structure, _, center, neighbor]) in samples.iter_fixed_size().enumerate() {
+                let value = values.index_axis(Axis(0), sample_i)
+                    .into_dimensionality::<Ix2>()
+                    .expect("wrong dimensionality for spherical expansion");
+
+                let environment = environments.entry((structure.usize(), center.usize())).or_default();
+                accumulate_expansion(&mut environment.total, channel, value);
+
+                // the center can not be removed from its own environment,
+                // which also applies to its periodic images
+                if neighbor != center {
+                    let by_neighbor = environment.by_neighbor.entry(neighbor.usize()).or_default();
+                    accumulate_expansion(by_neighbor, channel, value);
+                }
+            }
+        }
+
+        let mut result = Vec::new();
+        for ((structure, center), environment) in environments {
+            let norm = f64::sqrt(power_spectrum_norm2(&environment.total));
+
+            for (neighbor, contributions) in environment.by_neighbor {
+                let mut expansion = environment.total.clone();
+                for (channel, contribution) in contributions {
+                    let value = expansion.get_mut(&channel).expect("missing channel in total expansion");
+                    *value -= &contribution;
+                }
+
+                result.push(NeighborContribution {
+                    structure: structure,
+                    center: center,
+                    neighbor: neighbor,
+                    norm: norm,
+                    norm_without_neighbor: f64::sqrt(power_spectrum_norm2(&expansion)),
+                });
+            }
+        }
+
+        return Ok(result);
+    }
+
+    /// Compute the power spectrum without summing over the `m` components of
+    /// the spherical expansion, to look at the contribution of each
+    /// `spherical_harmonics_m` to the final features.
+    ///
+    /// The output contains one block for each `spherical_harmonics_l` and each
+    /// key of the power spectrum, with the same samples as the corresponding
+    /// power spectrum block, `spherical_harmonics_m` as component and `n1, n2`
+    /// as properties. Summing the values over `spherical_harmonics_m` gives
+    /// back the power spectrum feature `(l, n1, n2)`, including the
+    /// normalization factors.
+    ///
+    /// Labels selection in `options` are applied to the power spectrum, and
+    /// only the `(l, n1, n2)` properties selected there are included in the
+    /// output. Gradients are not supported, and post-processing and
+    /// aggregation in `options` are ignored.
+    pub fn compute_m_resolved(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        options: CalculationOptions,
+    ) -> Result<TensorMap, Error> {
+        if !options.gradients.is_empty() {
+            return Err(Error::InvalidParameter(
+                "gradients are not supported when computing m-resolved power spectrum".into()
+            ));
+        }
+
+        let spherical_expansion = self.spherical_expansion.compute(systems, CalculationOptions {
+            use_native_system: options.use_native_system,
+            ..Default::default()
+        })?;
+
+        // this calculator is only used to create the power spectrum metadata
+        // with the selections from `options`
+        let mut power_spectrum = Calculator::from(Box::new(
+            SoapPowerSpectrum::new(self.parameters.clone())?
+        ) as Box<dyn CalculatorBase>);
+
+        let mut m_resolved = None;
+        power_spectrum.compute_with(systems, options, |descriptor| {
+            m_resolved = Some(SoapPowerSpectrum::combine_m_resolved(&spherical_expansion, descriptor)?);
+            Ok(())
+        })?;
+
+        return Ok(m_resolved.expect("compute_with should have called the closure"));
+    }
+
+    /// Combine the spherical expansion coefficients into the m-resolved power
+    /// spectrum, for the keys, samples and properties in `descriptor`.
+    fn combine_m_resolved(spherical_expansion: &TensorMap, descriptor: &TensorMap) -> Result<TensorMap, Error> {
+        let samples_mapping = SoapPowerSpectrum::samples_mapping(descriptor, spherical_expansion)?;
+
+        let mut m_resolved_keys = LabelsBuilder::new(vec![
+            "spherical_harmonics_l", "species_center", "species_neighbor_1", "species_neighbor_2"
+        ]);
+        let mut blocks = Vec::new();
+        for (key, block) in descriptor.iter() {
+            let species_center = key[0];
+            let species_neighbor_1 = key[1];
+            let species_neighbor_2 = key[2];
+
+            let mut properties_by_l = BTreeMap::new();
+            for &[l, n1, n2] in block.properties().iter_fixed_size() {
+                properties_by_l.entry(l).or_insert_with(Vec::new).push([n1, n2]);
+            }
+
+            let samples = block.samples();
+            let mapping = samples_mapping.get(key).ok_or_else(|| Error::Internal(format!(
+                "missing samples mapping for power spectrum block {}", format_key(key)
+            )))?;
+            for (l, properties) in properties_by_l {
+                let spherical_harmonics_l = l.usize();
+
+                let missing_block = |spx_key: &[LabelValue]| Error::Internal(format!(
+                    "missing spherical expansion block {} for power spectrum block {}",
+                    format_key(spx_key), format_key(key)
+                ));
+
+                let spx_key_1 = [l, species_center, species_neighbor_1];
+                let block_id_1 = spherical_expansion.keys().position(&spx_key_1).ok_or_else(|| missing_block(&spx_key_1))?;
+                let spx_block_1 = spherical_expansion.block_by_id(block_id_1);
+                let spx_properties_1 = spx_block_1.properties();
+                let spx_values_1 = spx_block_1.values().to_array();
+
+                let spx_key_2 = [l, species_center, species_neighbor_2];
+                let block_id_2 = spherical_expansion.keys().position(&spx_key_2).ok_or_else(|| missing_block(&spx_key_2))?;
+                let spx_block_2 = spherical_expansion.block_by_id(block_id_2);
+                let spx_properties_2 = spx_block_2.properties();
+                let spx_values_2 = spx_block_2.values().to_array();
+
+                let missing_property = |n: LabelValue, spx_key: &[LabelValue]| Error::Internal(format!(
+                    "missing property n={} in spherical expansion block {} for power spectrum block {}",
+                    n, format_key(spx_key), format_key(key)
+                ));
+
+                // same normalization as in `compute_materialized`
+                let mut factor = 1.0 / f64::sqrt((2 * spherical_harmonics_l + 1) as f64);
+                if species_neighbor_1 != species_neighbor_2 {
+                    factor *= std::f64::consts::SQRT_2;
+                }
+
+                let mut component = LabelsBuilder::new(vec!["spherical_harmonics_m"]);
+                for m in -(spherical_harmonics_l as i32)..=(spherical_harmonics_l as i32) {
+                    component.add(&[LabelValue::new(m)]);
+                }
+
+                let mut properties_builder = LabelsBuilder::new(vec!["n1", "n2"]);
+                let mut spx_properties = Vec::with_capacity(properties.len());
+                for &[n1, n2] in &properties {
+                    properties_builder.add(&[n1, n2]);
+                    spx_properties.push((
+                        spx_properties_1.position(&[n1]).ok_or_else(|| missing_property(n1, &spx_key_1))?,
+                        spx_properties_2.position(&[n2]).ok_or_else(|| missing_property(n2, &spx_key_2))?,
+                    ));
+                }
+
+                let shape = (samples.count(), 2 * spherical_harmonics_l + 1, properties.len());
+                let mut values = ndarray::Array3::from_elem(shape, 0.0);
+                for (sample_i, &(sample_1, sample_2)) in mapping.values.iter().enumerate() {
+                    for m in 0..(2 * spherical_harmonics_l + 1) {
+                        for (property_i, &(n1, n2)) in spx_properties.iter().enumerate() {
+                            values[[sample_i, m, property_i]] = factor
+                                * spx_values_1[[sample_1, m, n1]]
+                                * spx_values_2[[sample_2, m, n2]];
+                        }
+                    }
+                }
+
+                m_resolved_keys.add(&[l, species_center, species_neighbor_1, species_neighbor_2]);
+                blocks.push(TensorBlock::new(
+                    values.into_dyn(),
+                    &samples,
+                    &[component.finish()],
+                    &properties_builder.finish(),
+                )?);
+            }
+        }
+
+        return Ok(TensorMap::new(m_resolved_keys.finish(), blocks)?);
+    }
+
+    /// Compute the power spectrum, and return it together with the spherical
+    /// expansion it is built from. This allows to use the chain rule in
+    /// external automatic differentiation frameworks.
+    ///
+    /// The spherical expansion contains all samples and properties, and the
+    /// same gradients as requested in `options`. The power spectrum can be
+    /// recovered from the spherical expansion as
+    ///
+    /// `<n1 n2 l | X_i> = \sum_m <n1 l m | X_i> <n2 l m | X_i> / \sqrt{2 l + 1}`
+    ///
+    /// where the first expansion coefficient is taken from the block with
+    /// `species_neighbor_1` and the second from the block with
+    /// `species_neighbor_2`, with an additional `\sqrt{2}` factor when the two
+    /// neighbor species are different.
+    ///
+    /// Labels selection, post-processing and aggregation in `options` only
+    /// apply to the power spectrum, and the progress callback only reports on
+    /// the calculation of the spherical expansion.
+    pub fn compute_with_intermediates(
+        &mut self,
+        systems: &mut [Box<dyn System>],
+        options: CalculationOptions,
+    ) -> Result<(TensorMap, TensorMap), Error> {
+        let spherical_expansion = self.spherical_expansion.compute(systems, CalculationOptions {
+            gradients: options.gradients,
+            use_native_system: options.use_native_system,
+            cell_inference: options.cell_inference,
+            velocities: options.velocities,
+            thread_pool: options.thread_pool,
+            progress_callback: options.progress_callback,
+            ..Default::default()
+        })?;
+
+        // this calculator is only used to create the power spectrum metadata
+        // and apply the options, the values come from `spherical_expansion`
+        let mut power_spectrum = Calculator::from(Box::new(
+            SoapPowerSpectrum::new(self.parameters.clone())?
+        ) as Box<dyn CalculatorBase>);
+
+        let power_spectrum = power_spectrum.compute_with(systems, options, |descriptor| {
+            SoapPowerSpectrum::combine_spherical_expansion(&spherical_expansion, descriptor)
+        })?;
+
+        return Ok((power_spectrum, spherical_expansion));
+    }
+
+    /// Compute the power spectrum in `descriptor` from an already computed
+    /// `spherical_expansion`, skipping the calculation of the spherical
+    /// expansion.
+    ///
+    /// `descriptor` must contain the keys, samples, properties and gradients
+    /// of the power spectrum, as created by `Calculator::compute`. The
+    /// spherical expansion must have been computed with the same parameters
+    /// as this calculator, and contain all the blocks, samples, properties and
+    /// gradients needed to compute `descriptor`.
+    pub fn compute_from_spherical_expansion(
+        &self,
+        spherical_expansion: &TensorMap,
+        descriptor: &mut TensorMap,
+    ) -> Result<(), Error> {
+        SoapPowerSpectrum::check_spherical_expansion(spherical_expansion, descriptor)?;
+        return SoapPowerSpectrum::combine_spherical_expansion(spherical_expansion, descriptor);
+    }
+
+    /// Check that `spherical_expansion` contains everything needed to compute
+    /// the power spectrum in `descriptor`.
+    fn check_spherical_expansion(spherical_expansion: &TensorMap, descriptor: &TensorMap) -> Result<(), Error> {
+        if descriptor.keys().names() != ["species_center", "species_neighbor_1", "species_neighbor_2"] {
+            return Err(Error::InvalidParameter(format!(
+                "invalid keys for the power spectrum: expected [species_center, species_neighbor_1, species_neighbor_2], got [{}]",
+                descriptor.keys().names().join(", ")
+            )));
+        }
+
+        if spherical_expansion.keys().names() != ["spherical_harmonics_l", "species_center", "species_neighbor"] {
+            return Err(Error::InvalidParameter(format!(
+                "invalid keys for the spherical expansion: expected [spherical_harmonics_l, species_center, species_neighbor], got [{}]",
+                spherical_expansion.keys().names().join(", ")
+            )));
+        }
+
+        // spherical expansion blocks for which the gradients have already been
+        // checked
+        let mut checked_gradients = BTreeSet::new();
+        for (key, block) in descriptor.iter() {
+            let species_center = key[0];
+            let samples = block.samples();
+
+            for &[l, n1, n2] in block.properties().iter_fixed_size() {
+                for (species_neighbor, n) in [(key[1], n1), (key[2], n2)] {
+                    let spx_key = [l, species_center, species_neighbor];
+                    let block_id = spherical_expansion.keys().position(&spx_key).ok_or_else(|| Error::InvalidParameter(format!(
+                        "missing block for spherical_harmonics_l={}, species_center={}, species_neighbor={} in the spherical expansion",
+                        l.i32(), species_center.i32(), species_neighbor.i32()
+                    )))?;
+                    let spx_block = spherical_expansion.block_by_id(block_id);
+
+                    let components = spx_block.components();
+                    if components.len() != 1 || components[0].count() != 2 * l.usize() + 1 {
+                        return Err(Error::InvalidParameter(format!(
+                            "the spherical expansion block for spherical_harmonics_l={} must have {} components",
+                            l.i32(), 2 * l.usize() + 1
+                        )));
+                    }
+
+                    let spx_properties = spx_block.properties();
+                    if spx_properties.names() != ["n"] || spx_properties.position(&[n]).is_none() {
+                        return Err(Error::InvalidParameter(format!(
+                            "missing property n={} in the spherical expansion block for spherical_harmonics_l={}, species_center={}, species_neighbor={}",
+                            n.i32(), l.i32(), species_center.i32(), species_neighbor.i32()
+                        )));
+                    }
+
+                    let spx_samples = spx_block.samples();
+                    for sample in samples.iter() {
+                        if spx_samples.position(sample).is_none() {
+                            return Err(Error::InvalidParameter(format!(
+                                "missing sample (structure={}, center={}) in the spherical expansion block for spherical_harmonics_l={}, species_center={}, species_neighbor={}",
+                                sample[0].i32(), sample[1].i32(), l.i32(), species_center.i32(), species_neighbor.i32()
+                            )));
+                        }
+                    }
+
+                    for parameter in ["positions", "cell", "strain"] {
+                        if block.gradient(parameter).is_some() && spx_block.gradient(parameter).is_none() {
+                            return Err(Error::InvalidParameter(format!(
+                                "missing {} gradients in the spherical expansion", parameter
+                            )));
+                        }
+                    }
+
+                    if checked_gradients.insert(block_id) {
+                        SoapPowerSpectrum::check_spherical_expansion_gradients(&spx_block, &spx_key)?;
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Check that the gradients in the spherical expansion block `spx_block`
+    /// (associated with `spx_key`) have the expected metadata, so they can be
+    /// used to compute the power spectrum gradients.
+    fn check_spherical_expansion_gradients(spx_block: &TensorBlockRef, spx_key: &[LabelValue]) -> Result<(), Error> {
+        let n_samples = spx_block.samples().count();
+        for parameter in ["positions", "cell", "strain"] {
+            let gradient = match spx_block.gradient(parameter) {
+                Some(gradient) => gradient,
+                None => continue,
+            };
+
+            let (expected_samples, n_directions): (&[&str], usize) = if parameter == "positions" {
+                (&["sample", "structure", "atom"], 1)
+            } else {
+                (&["sample"], 2)
+            };
+
+            let samples = gradient.samples();
+            if samples.names() != expected_samples {
+                return Err(Error::InvalidParameter(format!(
+                    "invalid samples for {} gradients in the spherical expansion block {}: expected [{}], got [{}]",
+                    parameter, format_key(spx_key), expected_samples.join(", "), samples.names().join(", ")
+                )));
+            }
+
+            // one component for each cartesian direction, and then the
+            // spherical harmonics m
+            let components = gradient.components();
+            let n_m = spx_block.components()[0].count();
+            if components.len() != n_directions + 1
+               || components[..n_directions].iter().any(|component| component.count() != 3)
+               || components[n_directions].count() != n_m {
+                return Err(Error::InvalidParameter(format!(
+                    "invalid components for {} gradients in the spherical expansion block {}",
+                    parameter, format_key(spx_key)
+                )));
+            }
+
+            if gradient.properties() != spx_block.properties() {
+                return Err(Error::InvalidParameter(format!(
+                    "the properties of {} gradients in the spherical expansion block {} do not match the properties of the values",
+                    parameter, format_key(spx_key)
+                )));
+            }
+
+            if parameter == "positions" {
+                if samples.iter().any(|sample| sample[0].usize() >= n_samples) {
+                    return Err(Error::InvalidParameter(format!(
+                        "the positions gradients in the spherical expansion block {} refer to samples which do not exist",
+                        format_key(spx_key)
+                    )));
+                }
+            } else if samples.count() != n_samples || samples.iter().enumerate().any(|(i, sample)| sample[0].usize() != i) {
+                return Err(Error::InvalidParameter(format!(
+                    "the {} gradients in the spherical expansion block {} must contain exactly one sample for each sample of the values",
+                    parameter, format_key(spx_key)
+                )));
+            }
+        }
+
+        return Ok(());
     }
 
     /// Construct a `TensorMap` containing the set of samples/properties we want
@@ -104,8 +759,9 @@ impl SoapPowerSpectrum {
         assert_eq!(descriptor.keys().names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
 
         // first, go over the requested power spectrum properties and group them
-        // depending on the species_neighbor
-        let mut requested_by_key = HashMap::new();
+        // depending on the species_neighbor. This uses a `BTreeMap` to get
+        // the keys in a deterministic order.
+        let mut requested_by_key = BTreeMap::new();
         let mut requested_spherical_harmonics_l = BTreeSet::new();
         for (&[center, neighbor_1, neighbor_2], block) in descriptor.keys().iter_fixed_size().zip(descriptor.blocks()) {
             for &[l, n1, n2] in block.properties().iter_fixed_size() {
@@ -225,7 +881,7 @@ impl SoapPowerSpectrum {
     fn samples_mapping(
         descriptor: &TensorMap,
         spherical_expansion: &TensorMap
-    ) -> HashMap<Vec<LabelValue>, SamplesMapping> {
+    ) -> Result<HashMap<Vec<LabelValue>, SamplesMapping>, Error> {
         let mut mapping = HashMap::new();
         for (key, block) in descriptor.iter() {
             let species_center = key[0];
@@ -265,29 +921,42 @@ impl SoapPowerSpectrum {
             // the first one.
             let first_l = block_data.properties[0][0];
 
-            let block_id_1 = spherical_expansion.keys().position(&[
-                first_l, species_center, species_neighbor_1
-            ]).expect("missing block in spherical expansion");
+            let spx_key_1 = [first_l, species_center, species_neighbor_1];
+            let block_id_1 = spherical_expansion.keys().position(&spx_key_1).ok_or_else(|| Error::Internal(format!(
+                "missing spherical expansion block {} for power spectrum block {}",
+                format_key(&spx_key_1), format_key(key)
+            )))?;
             let spx_block_1 = &spherical_expansion.block_by_id(block_id_1);
             let spx_samples_1 = spx_block_1.samples();
 
-            let block_id_2 = spherical_expansion.keys().position(&[
-                first_l, species_center, species_neighbor_2
-            ]).expect("missing block in spherical expansion");
+            let spx_key_2 = [first_l, species_center, species_neighbor_2];
+            let block_id_2 = spherical_expansion.keys().position(&spx_key_2).ok_or_else(|| Error::Internal(format!(
+                "missing spherical expansion block {} for power spectrum block {}",
+                format_key(&spx_key_2), format_key(key)
+            )))?;
             let spx_block_2 = &spherical_expansion.block_by_id(block_id_2);
             let spx_samples_2 = spx_block_2.samples();
 
+            let missing_sample = |sample: &[LabelValue], spx_key: &[LabelValue]| Error::Internal(format!(
+                "missing sample {} in spherical expansion block {} for power spectrum block {}",
+                format_key(sample), format_key(spx_key), format_key(key)
+            ));
+
             values_mapping.reserve(block_data.samples.count());
             for sample in &*block_data.samples {
-                let sample_1 = spx_samples_1.position(sample).expect("missing spherical expansion sample");
-                let sample_2 = spx_samples_2.position(sample).expect("missing spherical expansion sample");
+                let sample_1 = spx_samples_1.position(sample).ok_or_else(|| missing_sample(sample, &spx_key_1))?;
+                let sample_2 = spx_samples_2.position(sample).ok_or_else(|| missing_sample(sample, &spx_key_2))?;
                 values_mapping.push((sample_1, sample_2));
             }
 
             let mut gradient_mapping = Vec::new();
             if let Some(gradient) = block.gradient("positions") {
-                let spx_gradient_1 = spx_block_1.gradient("positions").expect("missing spherical expansion gradients");
-                let spx_gradient_2 = spx_block_2.gradient("positions").expect("missing spherical expansion gradients");
+                let missing_gradients = |spx_key: &[LabelValue]| Error::Internal(format!(
+                    "missing positions gradients in spherical expansion block {} for power spectrum block {}",
+                    format_key(spx_key), format_key(key)
+                ));
+                let spx_gradient_1 = spx_block_1.gradient("positions").ok_or_else(|| missing_gradients(&spx_key_1))?;
+                let spx_gradient_2 = spx_block_2.gradient("positions").ok_or_else(|| missing_gradients(&spx_key_2))?;
 
                 let gradient_samples = gradient.samples();
                 gradient_mapping.reserve(gradient_samples.count());
@@ -295,10 +964,14 @@ impl SoapPowerSpectrum {
                 let spx_gradient_1_samples = spx_gradient_1.samples();
                 let spx_gradient_2_samples = spx_gradient_2.samples();
 
-                for gradient_sample in gradient_samples.iter() {
+                // the first dimension of the gradient samples refers to the
+                // samples of the values, which can be different between the
+                // power spectrum and the spherical expansion
+                for &[sample, structure, atom] in gradient_samples.iter_fixed_size() {
+                    let (sample_1, sample_2) = values_mapping[sample.usize()];
                     gradient_mapping.push((
-                        spx_gradient_1_samples.position(gradient_sample),
-                        spx_gradient_2_samples.position(gradient_sample),
+                        spx_gradient_1_samples.position(&[sample_1.into(), structure, atom]),
+                        spx_gradient_2_samples.position(&[sample_2.into(), structure, atom]),
                     ));
                 }
             }
@@ -309,7 +982,7 @@ impl SoapPowerSpectrum {
             });
         }
 
-        return mapping;
+        return Ok(mapping);
     }
 
     /// Get the list of spherical expansion to combine when computing a single
@@ -318,42 +991,55 @@ impl SoapPowerSpectrum {
         key: &[LabelValue],
         properties: &Labels,
         spherical_expansion: &HashMap<&[LabelValue], SphericalExpansionBlock<'a>>,
-    ) -> Vec<SpxPropertiesToCombine<'a>> {
+    ) -> Result<Vec<SpxPropertiesToCombine<'a>>, Error> {
         let species_center = key[0];
         let species_neighbor_1 = key[1];
         let species_neighbor_2 = key[2];
 
-        return properties.par_iter().map(|property| {
+        return properties.par_iter().map(|property| -> Result<SpxPropertiesToCombine<'a>, Error> {
             let l = property[0];
             let n1 = property[1];
             let n2 = property[2];
 
+            let missing_block = |spx_key: &[LabelValue]| Error::Internal(format!(
+                "missing spherical expansion block {} for power spectrum block {}",
+                format_key(spx_key), format_key(key)
+            ));
+
             let key_1: &[_] = &[l, species_center, species_neighbor_1];
-            let block_1 = spherical_expansion.get(&key_1)
-            .expect("missing first neighbor species block in spherical expansion");
+            let block_1 = spherical_expansion.get(&key_1).ok_or_else(|| missing_block(key_1))?;
 
             let key_2: &[_] = &[l, species_center, species_neighbor_2];
-            let block_2 = spherical_expansion.get(&key_2)
-                .expect("missing first neighbor species block in spherical expansion");
+            let block_2 = spherical_expansion.get(&key_2).ok_or_else(|| missing_block(key_2))?;
 
             // both blocks should had the same number of m components
             debug_assert_eq!(block_1.values.shape()[1], block_2.values.shape()[1]);
 
-            let property_1 = block_1.properties.position(&[n1]).expect("missing n1");
-            let property_2 = block_2.properties.position(&[n2]).expect("missing n2");
+            let missing_property = |n: LabelValue, spx_key: &[LabelValue]| Error::Internal(format!(
+                "missing property n={} in spherical expansion block {} for power spectrum block {}",
+                n, format_key(spx_key), format_key(key)
+            ));
+            let property_1 = block_1.properties.position(&[n1]).ok_or_else(|| missing_property(n1, key_1))?;
+            let property_2 = block_2.properties.position(&[n2]).ok_or_else(|| missing_property(n2, key_2))?;
 
-            SpxPropertiesToCombine {
+            Ok(SpxPropertiesToCombine {
                 spherical_harmonics_l: l.usize(),
                 property_1,
                 property_2,
                 spx_1: block_1.clone(),
                 spx_2: block_2.clone(),
-            }
+            })
         }).collect();
     }
 }
 
 
+/// Format a key or sample for error messages, e.g. `[1, 6, 8]`
+fn format_key(key: &[LabelValue]) -> String {
+    let values = key.iter().map(|value| value.to_string()).collect::<Vec<_>>();
+    return format!("[{}]", values.join(", "));
+}
+
 /// Data about the two spherical expansion block that will get combined to
 /// produce a single (l, n1, n2) property in a single power spectrum block
 struct SpxPropertiesToCombine<'a> {
@@ -373,12 +1059,96 @@ struct SpxPropertiesToCombine<'a> {
 #[derive(Debug, Clone)]
 struct SphericalExpansionBlock<'a> {
     properties: Labels,
-    /// spherical expansion values
-    values: &'a ndarray::ArrayD<f64>,
-    /// spherical expansion position gradients
-    positions_gradients: Option<&'a ndarray::ArrayD<f64>>,
-    /// spherical expansion cell gradients
-    cell_gradients: Option<&'a ndarray::ArrayD<f64>>,
+    /// spherical expansion values, as `(sample, m, n)`
+    values: ArrayView3<'a, f64>,
+    /// spherical expansion position gradients, as `(sample, xyz, m, n)`
+    positions_gradients: Option<ArrayView4<'a, f64>>,
+    /// spherical expansion cell gradients, as `(sample, xyz, xyz, m, n)`
+    cell_gradients: Option<ArrayView5<'a, f64>>,
+    /// spherical expansion strain gradients, as `(sample, xyz, xyz, m, n)`
+    strain_gradients: Option<ArrayView5<'a, f64>>,
+}
+
+impl<'a> SphericalExpansionBlock<'a> {
+    /// Get the data of a block of the spherical expansion, checking the
+    /// dimensionality of the values and gradients once, instead of checking
+    /// it for every sample.
+    fn new(block: &TensorBlockRef<'a>) -> Result<SphericalExpansionBlock<'a>, Error> {
+        let invalid_shape = |_| Error::Internal("invalid shape for the spherical expansion data".into());
+
+        let values = block.values().to_array().view().into_dimensionality().map_err(invalid_shape)?;
+        let positions_gradients = match block.gradient("positions") {
+            Some(gradient) => Some(gradient.values().to_array().view().into_dimensionality().map_err(invalid_shape)?),
+            None => None,
+        };
+        let cell_gradients = match block.gradient("cell") {
+            Some(gradient) => Some(gradient.values().to_array().view().into_dimensionality().map_err(invalid_shape)?),
+            None => None,
+        };
+        let strain_gradients = match block.gradient("strain") {
+            Some(gradient) => Some(gradient.values().to_array().view().into_dimensionality().map_err(invalid_shape)?),
+            None => None,
+        };
+
+        return Ok(SphericalExpansionBlock {
+            properties: block.properties(),
+            values: values,
+            positions_gradients: positions_gradients,
+            cell_gradients: cell_gradients,
+            strain_gradients: strain_gradients,
+        });
+    }
+
+    /// Get the gradients with respect to `parameter`, which should be either
+    /// `"cell"` or `"strain"`. Both are combined in the same way in the power
+    /// spectrum.
+    fn cell_like_gradients(&self, parameter: &str) -> Option<ArrayView5<'a, f64>> {
+        match parameter {
+            "cell" => self.cell_gradients,
+            "strain" => self.strain_gradients,
+            _ => panic!("unexpected gradient parameter {}", parameter),
+        }
+    }
+
+    /// Get the values for a single sample as a `(m, n)` matrix
+    fn sample(&self, sample: usize) -> ArrayView2<'a, f64> {
+        return self.values.index_axis_move(Axis(0), sample);
+    }
+}
+
+/// All the properties with the same `l` in a single power spectrum block,
+/// computed together with matrix multiplications
+struct MatmulGroup<'a> {
+    /// value of l
+    spherical_harmonics_l: usize,
+    /// first spherical expansion block
+    spx_1: SphericalExpansionBlock<'a>,
+    /// second spherical expansion block
+    spx_2: SphericalExpansionBlock<'a>,
+    /// position of the property in the power spectrum block, and of n1 and n2
+    /// in the first and second spherical expansion properties
+    properties: Vec<(usize, usize, usize)>,
+}
+
+impl<'a> MatmulGroup<'a> {
+    /// Normalize the requested entries of the `(n1, n2)` matrix `product` and
+    /// pass them to `store`, together with the corresponding property index.
+    fn store(&self, product: &Array2<f64>, different_species: bool, mut store: impl FnMut(usize, f64)) {
+        let normalization = f64::sqrt((2 * self.spherical_harmonics_l + 1) as f64);
+        for &(property_i, property_1, property_2) in &self.properties {
+            let mut value = product[[property_1, property_2]];
+            if different_species {
+                // We only store values for `species_neighbor_1 <
+                // species_neighbor_2` because the values are the same for
+                // pairs `species_neighbor_1 <-> species_neighbor_2` and
+                // `species_neighbor_2 <-> species_neighbor_1`. To ensure the
+                // final kernels are correct, we have to multiply the
+                // corresponding values.
+                value *= std::f64::consts::SQRT_2;
+            }
+            store(property_i, value / normalization);
+        }
+    }
 }
 
 /// Indexes of the spherical expansion samples/rows corresponding to each power
@@ -409,6 +1179,7 @@ impl CalculatorBase for SoapPowerSpectrum {
             cutoff: self.parameters.cutoff,
             self_pairs: true,
             symmetric: true,
+            all_species: self.parameters.all_species.clone(),
         };
         return builder.keys(systems);
     }
@@ -433,6 +1204,8 @@ impl CalculatorBase for SoapPowerSpectrum {
                     ].iter().copied().collect()
                 ),
                 self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             result.push(builder.samples(systems)?);
@@ -456,6 +1229,8 @@ impl CalculatorBase for SoapPowerSpectrum {
                     species_neighbor_2.i32()
                 ]),
                 self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             gradient_samples.push(builder.gradients_for(systems, samples)?);
@@ -468,10 +1243,17 @@ impl CalculatorBase for SoapPowerSpectrum {
         match parameter {
             "positions" => true,
             "cell" => true,
+            "strain" => true,
             _ => false,
         }
     }
 
+    fn supports_gradient_atoms_selection(&self) -> bool {
+        // the spherical expansion gradients are only computed for the atoms
+        // in the power spectrum gradient samples, see `compute_materialized`
+        return true;
+    }
+
     fn components(&self, keys: &equistore::Labels) -> Vec<Vec<Labels>> {
         return vec![vec![]; keys.count()];
     }
@@ -494,9 +1276,26 @@ impl CalculatorBase for SoapPowerSpectrum {
         return vec![properties; keys.count()];
     }
 
+    fn properties_count(&self, keys: &Labels) -> Vec<usize> {
+        let max_radial = self.parameters.max_radial;
+        let count = (self.parameters.max_angular + 1) * max_radial * max_radial;
+        return vec![count; keys.count()];
+    }
+
     #[time_graph::instrument(name = "SoapPowerSpectrum::compute")]
-    #[allow(clippy::too_many_lines)]
     fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        if self.parameters.fused {
+            return self.compute_fused(systems, descriptor);
+        }
+
+        return self.compute_materialized(systems, descriptor);
+    }
+}
+
+impl SoapPowerSpectrum {
+    /// Compute the power spectrum by computing the full spherical expansion
+    /// for all requested samples, and then combining it.
+    fn compute_materialized(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
         let mut gradients = Vec::new();
         if descriptor.block_by_id(0).gradient("positions").is_some() {
             gradients.push("positions");
@@ -504,33 +1303,64 @@ impl CalculatorBase for SoapPowerSpectrum {
         if descriptor.block_by_id(0).gradient("cell").is_some() {
             gradients.push("cell");
         }
+        if descriptor.block_by_id(0).gradient("strain").is_some() {
+            gradients.push("strain");
+        }
 
         let selected = self.selected_spx_labels(descriptor);
+        let gradient_atoms = SoapPowerSpectrum::gradient_atoms(descriptor);
 
         let options = CalculationOptions {
             gradients: &gradients,
             selected_samples: LabelsSelection::Predefined(&selected),
             selected_properties: LabelsSelection::Predefined(&selected),
             selected_keys: Some(selected.keys()),
+            selected_gradient_atoms: gradient_atoms.as_ref(),
             ..Default::default()
         };
 
         let spherical_expansion = self.spherical_expansion.compute(
             systems,
             options,
-        ).expect("failed to compute spherical expansion");
-        let samples_mapping = SoapPowerSpectrum::samples_mapping(descriptor, &spherical_expansion);
+        )?;
 
-        let spherical_expansion = spherical_expansion.iter().map(|(key, block)| {
-            let spx_block = SphericalExpansionBlock {
-                properties: block.properties(),
-                values: block.values().to_array(),
-                positions_gradients: block.gradient("positions").map(|g| g.values().to_array()),
-                cell_gradients: block.gradient("cell").map(|g| g.values().to_array()),
-            };
+        return SoapPowerSpectrum::combine_spherical_expansion(&spherical_expansion, descriptor);
+    }
 
-            (key, spx_block)
-        }).collect();
+    /// Get all the atoms (as `["structure", "atom"]` labels) appearing in the
+    /// positions gradient samples of `descriptor`, or `None` if there are no
+    /// positions gradients. Only the spherical expansion gradients with
+    /// respect to these atoms are needed to compute the power spectrum
+    /// gradients.
+    fn gradient_atoms(descriptor: &TensorMap) -> Option<Labels> {
+        let mut atoms = BTreeSet::new();
+        for (_, block) in descriptor.iter() {
+            let gradient = block.gradient("positions")?;
+            for &[_, structure, atom] in gradient.samples().iter_fixed_size() {
+                atoms.insert((structure.i32(), atom.i32()));
+            }
+        }
+
+        let mut builder = LabelsBuilder::new(vec!["structure", "atom"]);
+        for (structure, atom) in atoms {
+            builder.add(&[structure, atom]);
+        }
+
+        return Some(builder.finish());
+    }
+
+    /// Combine the spherical expansion coefficients into the power spectrum
+    /// stored in `descriptor`. The spherical expansion must contain all the
+    /// blocks, samples, properties and gradients required by `descriptor`.
+    fn combine_spherical_expansion(
+        spherical_expansion: &TensorMap,
+        descriptor: &mut TensorMap,
+    ) -> Result<(), Error> {
+        let samples_mapping = SoapPowerSpectrum::samples_mapping(descriptor, spherical_expansion)?;
+
+        let spherical_expansion = spherical_expansion.iter().map(|(key, block)| {
+            Ok((key, SphericalExpansionBlock::new(&block)?))
+        }).collect::<Result<HashMap<_, _>, Error>>()?;
 
         for (key, mut block) in descriptor.iter_mut() {
             let species_neighbor_1 = key[1];
@@ -541,192 +1371,341 @@ impl CalculatorBase for SoapPowerSpectrum {
                 key,
                 &block_data.properties,
                 &spherical_expansion,
-            );
+            )?;
 
-            let mapping = samples_mapping.get(key).expect("missing sample mapping");
-
-            block_data.values.as_array_mut()
-                .axis_iter_mut(ndarray::Axis(0))
-                .into_par_iter()
-                .zip_eq(&mapping.values)
-                .for_each(|(mut values, &(spx_sample_1, spx_sample_2))| {
-                    for (property_i, spx) in properties_to_combine.iter().enumerate() {
-                        let SpxPropertiesToCombine { spx_1, spx_2, ..} = spx;
-
-                        let mut sum = 0.0;
-
-                        for m in 0..(2 * spx.spherical_harmonics_l + 1) {
-                            // unsafe is required to remove the bound checking
-                            // in release mode (`uget` still checks bounds in
-                            // debug mode)
-                            unsafe {
-                                sum += spx_1.values.uget([spx_sample_1, m, spx.property_1])
-                                     * spx_2.values.uget([spx_sample_2, m, spx.property_2]);
-                            }
-                        }
+            let mapping = samples_mapping.get(key).ok_or_else(|| Error::Internal(format!(
+                "missing samples mapping for power spectrum block {}", format_key(key)
+            )))?;
 
-                        if species_neighbor_1 != species_neighbor_2 {
-                            // We only store values for `species_neighbor_1 <
-                            // species_neighbor_2` because the values are the
-                            // same for pairs `species_neighbor_1 <->
-                            // species_neighbor_2` and `species_neighbor_2 <->
-                            // species_neighbor_1`. To ensure the final kernels
-                            // are correct, we have to multiply the
-                            // corresponding values.
-                            sum *= std::f64::consts::SQRT_2;
-                        }
+            // all the properties with the same `l` are computed together,
+            // with one `(n1, m) x (m, n2)` matrix multiplication per sample
+            let groups = SoapPowerSpectrum::matmul_groups(&properties_to_combine);
+            let different_species = species_neighbor_1 != species_neighbor_2;
 
-                        unsafe {
-                            *values.uget_mut(property_i) = sum / f64::sqrt((2 * spx.spherical_harmonics_l + 1) as f64);
-                        }
-                    }
-                });
+            SoapPowerSpectrum::combine_values(
+                block_data.values.as_array_mut(), &groups, mapping, different_species
+            );
 
-            // gradients with respect to the atomic positions
             if let Some(mut gradient) = block.gradient_mut("positions") {
                 let gradient = gradient.data_mut();
+                SoapPowerSpectrum::combine_positions_gradients(
+                    gradient.values.to_array_mut(), &gradient.samples, &groups, mapping, different_species
+                )?;
+            }
 
-                gradient.values.to_array_mut()
-                    .axis_iter_mut(ndarray::Axis(0))
-                    .into_par_iter()
-                    .zip_eq(gradient.samples.par_iter())
-                    .zip_eq(&mapping.gradients)
-                    .for_each(|((mut values, gradient_sample), &(spx_grad_sample_1, spx_grad_sample_2))| {
-                        for (property_i, spx) in properties_to_combine.iter().enumerate() {
-                            let SpxPropertiesToCombine { spx_1, spx_2, ..} = spx;
-
-                            let spx_1_gradient = spx_1.positions_gradients.expect("missing spherical expansion gradients");
-                            let spx_2_gradient = spx_2.positions_gradients.expect("missing spherical expansion gradients");
-
-                            let sample_i = gradient_sample[0].usize();
-                            let (spx_sample_1, spx_sample_2) = mapping.values[sample_i];
-
-                            let mut sum = [0.0, 0.0, 0.0];
-                            if let Some(grad_sample_1) = spx_grad_sample_1 {
-                                for m in 0..(2 * spx.spherical_harmonics_l + 1) {
-                                    // SAFETY: see same loop for values
-                                    unsafe {
-                                        let value_2 = spx_2.values.uget([spx_sample_2, m, spx.property_2]);
-                                        for d in 0..3 {
-                                            sum[d] += value_2 * spx_1_gradient.uget([grad_sample_1, d, m, spx.property_1]);
-                                        }
-                                    }
-                                }
-                            }
+            for parameter in ["cell", "strain"] {
+                if let Some(mut gradient) = block.gradient_mut(parameter) {
+                    let gradient = gradient.data_mut();
+                    SoapPowerSpectrum::combine_cell_gradients(
+                        parameter, gradient.values.to_array_mut(), &gradient.samples, &groups, mapping, different_species
+                    )?;
+                }
+            }
+        }
 
-                            if let Some(grad_sample_2) = spx_grad_sample_2 {
-                                for m in 0..(2 * spx.spherical_harmonics_l + 1) {
-                                    // SAFETY: see same loop for values
-                                    unsafe {
-                                        let value_1 = spx_1.values.uget([spx_sample_1, m, spx.property_1]);
-                                        for d in 0..3 {
-                                            sum[d] += value_1 * spx_2_gradient.uget([grad_sample_2, d, m, spx.property_2]);
-                                        }
-                                    }
-                                }
-                            }
+        return Ok(());
+    }
 
-                            if species_neighbor_1 != species_neighbor_2 {
-                                // see above
-                                for d in 0..3 {
-                                    sum[d] *= std::f64::consts::SQRT_2;
-                                }
-                            }
+    /// Group the properties to combine by angular channel, to compute them
+    /// with matrix multiplications
+    fn matmul_groups<'a>(properties_to_combine: &[SpxPropertiesToCombine<'a>]) -> Vec<MatmulGroup<'a>> {
+        let mut groups = BTreeMap::new();
+        for (property_i, spx) in properties_to_combine.iter().enumerate() {
+            let group = groups.entry(spx.spherical_harmonics_l).or_insert_with(|| MatmulGroup {
+                spherical_harmonics_l: spx.spherical_harmonics_l,
+                spx_1: spx.spx_1.clone(),
+                spx_2: spx.spx_2.clone(),
+                properties: Vec::new(),
+            });
+            group.properties.push((property_i, spx.property_1, spx.property_2));
+        }
 
-                            let normalization = f64::sqrt((2 * spx.spherical_harmonics_l + 1) as f64);
-                            for d in 0..3 {
-                                unsafe {
-                                    *values.uget_mut([d, property_i]) = sum[d] / normalization;
-                                }
-                            }
+        return groups.into_values().collect();
+    }
+
+    /// Compute the values of a power spectrum block using one matrix
+    /// multiplication per sample and angular channel
+    fn combine_values(
+        values: &mut ArrayD<f64>,
+        groups: &[MatmulGroup],
+        mapping: &SamplesMapping,
+        different_species: bool,
+    ) {
+        values.axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip_eq(&mapping.values)
+            .for_each(|(mut values, &(spx_sample_1, spx_sample_2))| {
+                for group in groups {
+                    let value_1 = group.spx_1.sample(spx_sample_1);
+                    let value_2 = group.spx_2.sample(spx_sample_2);
+
+                    let product = value_1.t().dot(&value_2);
+                    group.store(&product, different_species, |property_i, value| values[property_i] = value);
+                }
+            });
+    }
+
+    /// Compute the gradients w.r.t. positions of a power spectrum block
+    /// using matrix multiplications
+    fn combine_positions_gradients(
+        gradient: &mut ArrayD<f64>,
+        gradient_samples: &Labels,
+        groups: &[MatmulGroup],
+        mapping: &SamplesMapping,
+        different_species: bool,
+    ) -> Result<(), Error> {
+        let spx_gradients = groups.iter().map(|group| {
+            match (group.spx_1.positions_gradients, group.spx_2.positions_gradients) {
+                (Some(gradient_1), Some(gradient_2)) => Ok((gradient_1, gradient_2)),
+                _ => Err(Error::Internal("missing positions gradients in the spherical expansion".into())),
+            }
+        }).collect::<Result<Vec<_>, Error>>()?;
+
+        gradient.axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip_eq(gradient_samples.par_iter())
+            .zip_eq(&mapping.gradients)
+            .for_each(|((mut values, gradient_sample), &(spx_grad_sample_1, spx_grad_sample_2))| {
+                let sample_i = gradient_sample[0].usize();
+                let (spx_sample_1, spx_sample_2) = mapping.values[sample_i];
+
+                for (group, (spx_1_gradient, spx_2_gradient)) in groups.iter().zip(&spx_gradients) {
+                    let value_1 = group.spx_1.sample(spx_sample_1);
+                    let value_2 = group.spx_2.sample(spx_sample_2);
+
+                    for d in 0..3 {
+                        let mut product = Array2::zeros((value_1.ncols(), value_2.ncols()));
+                        if let Some(grad_sample_1) = spx_grad_sample_1 {
+                            let gradient_1 = spx_1_gradient.slice(s![grad_sample_1, d, .., ..]);
+                            product += &gradient_1.t().dot(&value_2);
+                        }
+
+                        if let Some(grad_sample_2) = spx_grad_sample_2 {
+                            let gradient_2 = spx_2_gradient.slice(s![grad_sample_2, d, .., ..]);
+                            product += &value_1.t().dot(&gradient_2);
                         }
-                    });
+
+                        group.store(&product, different_species, |property_i, value| values[[d, property_i]] = value);
+                    }
+                }
+            });
+
+        return Ok(());
+    }
+
+    /// Compute the gradients w.r.t. cell or strain (depending on `parameter`)
+    /// of a power spectrum block using matrix multiplications
+    fn combine_cell_gradients(
+        parameter: &str,
+        gradient: &mut ArrayD<f64>,
+        gradient_samples: &Labels,
+        groups: &[MatmulGroup],
+        mapping: &SamplesMapping,
+        different_species: bool,
+    ) -> Result<(), Error> {
+        let spx_gradients = groups.iter().map(|group| {
+            match (group.spx_1.cell_like_gradients(parameter), group.spx_2.cell_like_gradients(parameter)) {
+                (Some(gradient_1), Some(gradient_2)) => Ok((gradient_1, gradient_2)),
+                _ => Err(Error::Internal(format!("missing {} gradients in the spherical expansion", parameter))),
             }
+        }).collect::<Result<Vec<_>, Error>>()?;
 
-            // gradients with respect to the cell parameters
-            if let Some(mut gradient) = block.gradient_mut("cell") {
-                let gradient = gradient.data_mut();
+        gradient.axis_iter_mut(Axis(0))
+            .into_par_iter()
+            .zip_eq(gradient_samples.par_iter())
+            .for_each(|(mut values, gradient_sample)| {
+                let sample_i = gradient_sample[0].usize();
+                let (spx_sample_1, spx_sample_2) = mapping.values[sample_i];
 
-                gradient.values.to_array_mut()
-                    .axis_iter_mut(ndarray::Axis(0))
-                    .into_par_iter()
-                    .zip_eq(gradient.samples.par_iter())
-                    .for_each(|(mut values, gradient_sample)| {
-                        for (property_i, spx) in properties_to_combine.iter().enumerate() {
-                            let SpxPropertiesToCombine { spx_1, spx_2, ..} = spx;
-
-                            let spx_1_gradient = spx_1.cell_gradients.expect("missing spherical expansion gradients");
-                            let spx_2_gradient = spx_2.cell_gradients.expect("missing spherical expansion gradients");
-
-                            let sample_i = gradient_sample[0].usize();
-                            let (spx_sample_1, spx_sample_2) = mapping.values[sample_i];
-
-                            let mut sum = [
-                                [0.0, 0.0, 0.0],
-                                [0.0, 0.0, 0.0],
-                                [0.0, 0.0, 0.0],
-                            ];
-                            for m in 0..(2 * spx.spherical_harmonics_l + 1) {
-                                // SAFETY: see same loop for values
-                                unsafe {
-                                    let value_2 = spx_2.values.uget([spx_sample_2, m, spx.property_2]);
-                                    for d1 in 0..3 {
-                                        for d2 in 0..3 {
-                                            // TODO: ensure that gradient samples are 0..nsamples
-                                            sum[d1][d2] += value_2 * spx_1_gradient.uget([spx_sample_1, d1, d2, m, spx.property_1]);
-                                        }
-                                    }
-                                }
-                            }
+                for (group, (spx_1_gradient, spx_2_gradient)) in groups.iter().zip(&spx_gradients) {
+                    let value_1 = group.spx_1.sample(spx_sample_1);
+                    let value_2 = group.spx_2.sample(spx_sample_2);
 
-                            for m in 0..(2 * spx.spherical_harmonics_l + 1) {
-                                // SAFETY: see same loop for values
-                                unsafe {
-                                    let value_1 = spx_1.values.uget([spx_sample_1, m, spx.property_1]);
-                                    for d1 in 0..3 {
-                                        for d2 in 0..3 {
-                                            // TODO: ensure that gradient samples are 0..nsamples
-                                            sum[d1][d2] += value_1 * spx_2_gradient.uget([spx_sample_2, d1, d2, m, spx.property_2]);
-                                        }
-                                    }
-                                }
-                            }
+                    for d1 in 0..3 {
+                        for d2 in 0..3 {
+                            let gradient_1 = spx_1_gradient.slice(s![spx_sample_1, d1, d2, .., ..]);
+                            let gradient_2 = spx_2_gradient.slice(s![spx_sample_2, d1, d2, .., ..]);
 
-                            if species_neighbor_1 != species_neighbor_2 {
-                                // see above
-                                for d1 in 0..3 {
-                                    for d2 in 0..3 {
-                                        sum[d1][d2] *= std::f64::consts::SQRT_2;
-                                    }
-                                }
+                            let mut product = gradient_1.t().dot(&value_2);
+                            product += &value_1.t().dot(&gradient_2);
+
+                            group.store(&product, different_species, |property_i, value| values[[d1, d2, property_i]] = value);
+                        }
+                    }
+                }
+            });
+
+        return Ok(());
+    }
+
+    /// Compute the power spectrum in fused mode, for chunks of at most
+    /// `FUSED_CENTERS_CHUNK` centers in a single system at the time. The
+    /// spherical expansion is only computed for the centers in the current
+    /// chunk, and immediately combined into the power spectrum, without
+    /// storing the spherical expansion for all centers.
+    fn compute_fused(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        let mut centers_by_system = BTreeMap::new();
+        for (_, block) in descriptor.iter() {
+            for &[structure, center] in block.samples().iter_fixed_size() {
+                centers_by_system.entry(structure.usize())
+                    .or_insert_with(BTreeSet::new)
+                    .insert(center.usize());
+            }
+        }
+
+        for (system_i, centers) in centers_by_system {
+            if system_i >= systems.len() {
+                // samples for non-existing systems are left as zeros
+                continue;
+            }
+
+            let centers = centers.into_iter().collect::<Vec<_>>();
+            for chunk in centers.chunks(FUSED_CENTERS_CHUNK) {
+                let chunk = chunk.iter().copied().collect::<BTreeSet<_>>();
+
+                let (mut partial, chunk_samples) = SoapPowerSpectrum::extract_chunk(descriptor, system_i, &chunk)?;
+                self.compute_materialized(&mut systems[system_i..=system_i], &mut partial)?;
+
+                for ((_, partial_block), chunk_samples) in partial.iter().zip(chunk_samples) {
+                    let mut block = descriptor.block_mut_by_id(chunk_samples.block_i);
+
+                    let partial_values = partial_block.values().to_array();
+                    let mut block_data = block.data_mut();
+                    let values = block_data.values.as_array_mut();
+                    for (partial_i, &sample_i) in chunk_samples.values.iter().enumerate() {
+                        values.index_axis_mut(Axis(0), sample_i).assign(&partial_values.index_axis(Axis(0), partial_i));
+                    }
+
+                    let all_gradient_samples = [
+                        ("positions", &chunk_samples.positions),
+                        ("cell", &chunk_samples.cell),
+                        ("strain", &chunk_samples.strain),
+                    ];
+                    for (parameter, gradient_samples) in all_gradient_samples {
+                        if let Some(mut gradient) = block.gradient_mut(parameter) {
+                            let partial_gradient = partial_block.gradient(parameter).expect("missing gradient in partial power spectrum");
+                            let partial_gradient = partial_gradient.values().to_array();
+
+                            let gradient = gradient.data_mut();
+                            let gradient = gradient.values.to_array_mut();
+                            for (partial_i, &gradient_sample_i) in gradient_samples.iter().enumerate() {
+                                gradient.index_axis_mut(Axis(0), gradient_sample_i).assign(
+                                    &partial_gradient.index_axis(Axis(0), partial_i)
+                                );
                             }
+                        }
+                    }
+                }
+            }
+        }
 
-                            let normalization = f64::sqrt((2 * spx.spherical_harmonics_l + 1) as f64);
+        return Ok(());
+    }
 
-                            for d1 in 0..3 {
-                                for d2 in 0..3 {
-                                    unsafe {
-                                        *values.uget_mut([d1, d2, property_i]) = sum[d1][d2] / normalization;
-                                    }
-                                }
+    /// Create a new `TensorMap` containing only the samples corresponding to
+    /// the given `centers` in the system `system_i`, for all blocks in
+    /// `descriptor` with at least one such sample. The samples of the new
+    /// `TensorMap` refer to the system `system_i` as structure 0.
+    ///
+    /// This also returns the position of the samples (and gradient samples)
+    /// of the new `TensorMap` in `descriptor`.
+    fn extract_chunk(
+        descriptor: &TensorMap,
+        system_i: usize,
+        centers: &BTreeSet<usize>,
+    ) -> Result<(TensorMap, Vec<ChunkSamples>), Error> {
+        let mut keys = LabelsBuilder::new(descriptor.keys().names());
+        let mut blocks = Vec::new();
+        let mut chunk_samples = Vec::new();
+
+        for (block_i, (key, block)) in descriptor.iter().enumerate() {
+            let mut samples = LabelsBuilder::new(block.samples().names());
+            let mut new_sample_index = BTreeMap::new();
+            let mut values_samples = Vec::new();
+            for (sample_i, &[structure, center]) in block.samples().iter_fixed_size().enumerate() {
+                if structure.usize() == system_i && centers.contains(&center.usize()) {
+                    new_sample_index.insert(sample_i, values_samples.len());
+                    values_samples.push(sample_i);
+                    samples.add(&[LabelValue::new(0), center]);
+                }
+            }
+
+            if values_samples.is_empty() {
+                continue;
+            }
+
+            let samples = samples.finish();
+            let mut shape = block.values().to_array().shape().to_vec();
+            shape[0] = samples.count();
+
+            let mut new_block = TensorBlock::new(
+                ArrayD::from_elem(shape, 0.0),
+                &samples,
+                &block.components(),
+                &block.properties(),
+            )?;
+
+            let mut positions_samples = Vec::new();
+            let mut cell_samples = Vec::new();
+            let mut strain_samples = Vec::new();
+            let all_gradient_samples = [
+                ("positions", &mut positions_samples),
+                ("cell", &mut cell_samples),
+                ("strain", &mut strain_samples),
+            ];
+            for (parameter, gradient_samples) in all_gradient_samples {
+                if let Some(gradient) = block.gradient(parameter) {
+                    let mut new_samples = LabelsBuilder::new(gradient.samples().names());
+                    for (gradient_sample_i, gradient_sample) in gradient.samples().iter().enumerate() {
+                        if let Some(&new_sample_i) = new_sample_index.get(&gradient_sample[0].usize()) {
+                            let mut new_sample = gradient_sample.to_vec();
+                            new_sample[0] = new_sample_i.into();
+                            if parameter == "positions" {
+                                // the structure is always 0 in the chunk
+                                new_sample[1] = LabelValue::new(0);
                             }
+                            new_samples.add(&new_sample);
+                            gradient_samples.push(gradient_sample_i);
                         }
-                    });
+                    }
+
+                    let new_samples = new_samples.finish();
+                    let mut shape = gradient.values().to_array().shape().to_vec();
+                    shape[0] = new_samples.count();
+
+                    new_block.add_gradient(parameter, TensorBlock::new(
+                        ArrayD::from_elem(shape, 0.0),
+                        &new_samples,
+                        &gradient.components(),
+                        &gradient.properties(),
+                    )?)?;
+                }
             }
 
+            keys.add(key);
+            blocks.push(new_block);
+            chunk_samples.push(ChunkSamples {
+                block_i: block_i,
+                values: values_samples,
+                positions: positions_samples,
+                cell: cell_samples,
+                strain: strain_samples,
+            });
         }
 
-        Ok(())
+        return Ok((TensorMap::new(keys.finish(), blocks)?, chunk_samples));
     }
 }
 
 
 #[cfg(test)]
 mod tests {
+    use approx::assert_relative_eq;
     use equistore::LabelValue;
 
     use crate::systems::test_utils::{test_systems, test_system};
-    use crate::Calculator;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, Vector3D};
 
     use super::*;
     use crate::calculators::CalculatorBase;
@@ -741,9 +1720,53 @@ mod tests {
             radial_basis: RadialBasis::splined_gto(1e-8),
             radial_scaling: RadialScaling::None {},
             cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            fused: false,
+            all_species: None,
         }
     }
 
+    #[test]
+    fn invalid_parameters() {
+        let mut parameters = parameters();
+        parameters.atomic_gaussian_width = -0.3;
+        let error = SoapPowerSpectrum::new(parameters).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: atomic_gaussian_width must be a positive number, got -0.3");
+    }
+
+    #[test]
+    fn builder() {
+        let built = PowerSpectrumParameters::builder()
+            .cutoff(3.5)
+            .max_radial(6)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&built).unwrap(),
+            serde_json::to_value(&parameters()).unwrap(),
+        );
+
+        let built = PowerSpectrumParameters::builder()
+            .cutoff(3.5)
+            .max_radial(6)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .fused(true)
+            .build()
+            .unwrap();
+        assert!(built.fused);
+
+        let error = PowerSpectrumParameters::builder()
+            .cutoff(3.5)
+            .max_radial(6)
+            .atomic_gaussian_width(0.3)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: missing required parameter 'max_angular' for the spherical expansion");
+    }
+
     #[test]
     fn values() {
         let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
@@ -778,6 +1801,768 @@ mod tests {
         // `rascaline/tests/soap-power-spectrum.rs`
     }
 
+    #[test]
+    fn properties_count() {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water"]);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let counts = calculator.properties_count(descriptor.keys());
+        assert_eq!(counts.len(), descriptor.keys().count());
+        for (&count, (_, block)) in counts.iter().zip(descriptor.iter()) {
+            assert_eq!(count, 7 * 6 * 6);
+            assert_eq!(count, block.properties().count());
+        }
+    }
+
+    #[test]
+    fn deterministic_keys_order() {
+        let calculator = SoapPowerSpectrum::new(parameters()).unwrap();
+        let mut calculator = Calculator::from(Box::new(calculator) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water", "methane", "CH"]);
+        let first = calculator.compute(&mut systems, Default::default()).unwrap();
+        let second = calculator.compute(&mut systems, Default::default()).unwrap();
+        assert_eq!(first.keys(), second.keys());
+
+        // the spherical expansion keys used internally are sorted
+        let power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+        let spx_labels = power_spectrum.selected_spx_labels(&first);
+        let keys = spx_labels.keys().iter().map(|key| key.to_vec()).collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(keys, sorted);
+
+        let other = power_spectrum.selected_spx_labels(&second);
+        assert_eq!(spx_labels.keys(), other.keys());
+    }
+
+    #[test]
+    fn missing_spherical_expansion_block() {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        let descriptor = calculator.compute(&mut test_systems(&["water"]), Default::default()).unwrap();
+
+        // the spherical expansion for CH does not contain any block for the
+        // oxygen (species -42) in water
+        let mut spherical_expansion = Calculator::from(Box::new(SphericalExpansion::new(
+            SoapPowerSpectrum::expansion_parameters(&parameters())
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        let spx = spherical_expansion.compute(&mut test_systems(&["CH"]), Default::default()).unwrap();
+
+        let error = SoapPowerSpectrum::samples_mapping(&descriptor, &spx).unwrap_err();
+        assert!(matches!(error, Error::Internal(_)));
+        assert!(error.to_string().contains("missing spherical expansion block [0, -42, "));
+    }
+
+    #[test]
+    fn single_atom_gradients() {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(6, Vector3D::new(0.0, 0.0, 0.0));
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(*descriptor.keys(), Labels::new(
+            ["species_center", "species_neighbor_1", "species_neighbor_2"],
+            &[[6, 6, 6]]
+        ));
+
+        let block = descriptor.block_by_id(0);
+        assert_eq!(block.samples(), Labels::new(["structure", "center"], &[[0, 0]]));
+
+        // the self contribution gives non-zero values for l=0
+        let values = block.values().to_array();
+        assert!(values.iter().any(|&v| v != 0.0));
+
+        // the only gradient is with respect to the position of the atom
+        // itself, and is zero since there are no neighbors
+        let gradient = block.gradient("positions").unwrap();
+        assert_eq!(gradient.samples(), Labels::new(["sample", "structure", "atom"], &[[0, 0, 0]]));
+        assert!(gradient.values().to_array().iter().all(|&v| v == 0.0));
+
+        // cell gradients are not defined for a non-periodic system, this
+        // should be an error and not a panic
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+        assert!(calculator.compute(&mut systems, options).is_err());
+    }
+
+    #[test]
+    fn selected_gradient_atoms() {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        let mut systems = test_systems(&["water", "methane"]);
+
+        let atoms = Labels::new(["structure", "atom"], &[[0, 1], [1, 0]]);
+        check_selected_gradient_atoms(&mut calculator, &mut systems, &atoms);
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            selected_gradient_atoms: Some(&atoms),
+            ..Default::default()
+        };
+
+        // calculators without support for this option should return an error
+        let mut calculator = Calculator::new("soap_radial_spectrum", r#"{
+            "cutoff": 3.5,
+            "max_radial": 6,
+            "atomic_gaussian_width": 0.3,
+            "center_atom_weight": 1.0,
+            "radial_basis": {"Gto": {}},
+            "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+        }"#.into()).unwrap();
+        let error = calculator.compute(&mut systems, options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: the radial spectrum calculator does not support selecting atoms for gradients with respect to positions"
+        );
+    }
+
+    #[test]
+    fn selected_gradient_atoms_fused() {
+        let mut parameters = parameters();
+        parameters.max_radial = 3;
+        parameters.max_angular = 3;
+        parameters.fused = true;
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("methane").supercell([3, 3, 2]).unwrap();
+        assert!(system.size().unwrap() > FUSED_CENTERS_CHUNK);
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+        // one atom in each chunk of centers
+        let atoms = Labels::new(["structure", "atom"], &[[0, 3], [0, 70]]);
+        check_selected_gradient_atoms(&mut calculator, &mut systems, &atoms);
+    }
+
+    /// Check that the positions gradients computed for the selected `atoms`
+    /// are the same as the corresponding full gradients, and that the
+    /// gradients with respect to other atoms are not part of the output.
+    fn check_selected_gradient_atoms(calculator: &mut Calculator, systems: &mut [Box<dyn System>], atoms: &Labels) {
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let full = calculator.compute(systems, options).unwrap();
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            selected_gradient_atoms: Some(atoms),
+            ..Default::default()
+        };
+        let partial = calculator.compute(systems, options).unwrap();
+
+        assert_eq!(full.keys(), partial.keys());
+        for (full, partial) in full.blocks().iter().zip(partial.blocks()) {
+            assert_eq!(full.values().to_array(), partial.values().to_array());
+
+            let full_gradient = full.gradient("positions").unwrap();
+            let partial_gradient = partial.gradient("positions").unwrap();
+
+            let mut n_selected = 0;
+            for (full_i, sample) in full_gradient.samples().iter().enumerate() {
+                if !atoms.contains(&sample[1..]) {
+                    assert!(partial_gradient.samples().position(sample).is_none());
+                    continue;
+                }
+
+                n_selected += 1;
+                let partial_i = partial_gradient.samples().position(sample).unwrap();
+                assert_relative_eq!(
+                    full_gradient.values().to_array().index_axis(Axis(0), full_i),
+                    partial_gradient.values().to_array().index_axis(Axis(0), partial_i),
+                    max_relative=1e-12,
+                );
+            }
+            assert_eq!(partial_gradient.samples().count(), n_selected);
+        }
+    }
+
+    #[test]
+    fn neighbor_sensitivity() {
+        let mut no_center_weight = parameters();
+        no_center_weight.center_atom_weight = 0.0;
+        let mut power_spectrum = SoapPowerSpectrum::new(no_center_weight).unwrap();
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(6, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 1.4));
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+        let sensitivity = power_spectrum.neighbor_sensitivity(&mut systems, Default::default()).unwrap();
+        assert_eq!(sensitivity.len(), 2);
+
+        let mut calculator = Calculator::from(Box::new(power_spectrum) as Box<dyn CalculatorBase>);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        for (contribution, center) in sensitivity.iter().zip([0, 1]) {
+            assert_eq!(contribution.structure, 0);
+            assert_eq!(contribution.center, center);
+            assert_eq!(contribution.neighbor, 1 - center);
+
+            // the norm matches the one from the actual power spectrum
+            let mut expected = 0.0;
+            for (_, block) in descriptor.iter() {
+                let values = block.values().to_array();
+                for (sample_i, &[_, sample_center]) in block.samples().iter_fixed_size().enumerate() {
+                    if sample_center.usize() == center {
+                        expected += values.index_axis(Axis(0), sample_i).iter().map(|v| v * v).sum::<f64>();
+                    }
+                }
+            }
+            assert_relative_eq!(contribution.norm, f64::sqrt(expected), max_relative=1e-12);
+            assert!(contribution.norm > 0.0);
+
+            // removing the only neighbor leaves an empty environment
+            assert_relative_eq!(contribution.norm_without_neighbor, 0.0, epsilon=1e-12);
+            assert_relative_eq!(contribution.change(), contribution.norm, max_relative=1e-12);
+        }
+
+        // gradients are not supported
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+        assert!(power_spectrum.neighbor_sensitivity(&mut systems, options).is_err());
+    }
+
+    #[test]
+    fn m_resolved() {
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let m_resolved = power_spectrum.compute_m_resolved(&mut systems, Default::default()).unwrap();
+
+        let mut calculator = Calculator::from(Box::new(power_spectrum) as Box<dyn CalculatorBase>);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        for (key, block) in descriptor.iter() {
+            let values = block.values().to_array();
+            for (property_i, &[l, n1, n2]) in block.properties().iter_fixed_size().enumerate() {
+                let m_resolved_block = m_resolved.block_by_id(m_resolved.keys().position(
+                    &[l, key[0], key[1], key[2]]
+                ).expect("missing m-resolved block"));
+                assert_eq!(m_resolved_block.samples(), block.samples());
+
+                let m_resolved_values = m_resolved_block.values().to_array();
+                assert_eq!(m_resolved_values.shape()[1], 2 * l.usize() + 1);
+
+                let property = m_resolved_block.properties().position(&[n1, n2]).expect("missing m-resolved property");
+                for sample_i in 0..values.shape()[0] {
+                    let sum = (0..(2 * l.usize() + 1))
+                        .map(|m| m_resolved_values[[sample_i, m, property]])
+                        .sum::<f64>();
+                    assert_relative_eq!(sum, values[[sample_i, property_i]], max_relative=1e-12, epsilon=1e-16);
+                }
+            }
+        }
+
+        // gradients are not supported
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+        assert!(power_spectrum.compute_m_resolved(&mut systems, options).is_err());
+    }
+
+    #[test]
+    fn m_resolved_selection() {
+        let mut systems = test_systems(&["water", "methane"]);
+
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+        let full = power_spectrum.compute_m_resolved(&mut systems, Default::default()).unwrap();
+
+        let samples = Labels::new(["structure", "center"], &[[0, 1], [1, 0], [1, 3]]);
+        let properties = Labels::new(["l", "n1", "n2"], &[[0, 1, 2], [2, 0, 0], [2, 1, 3]]);
+        let options = CalculationOptions {
+            selected_samples: LabelsSelection::Subset(&samples),
+            selected_properties: LabelsSelection::Subset(&properties),
+            ..Default::default()
+        };
+        let partial = power_spectrum.compute_m_resolved(&mut systems, options).unwrap();
+
+        for (key, block) in partial.iter() {
+            assert!(key[0] == 0 || key[0] == 2);
+            for sample in block.samples().iter() {
+                assert!(samples.position(sample).is_some());
+            }
+
+            let expected_properties: &[[i32; 2]] = if key[0] == 0 { &[[1, 2]] } else { &[[0, 0], [1, 3]] };
+            assert_eq!(block.properties(), Labels::new(["n1", "n2"], expected_properties));
+
+            let full_block = full.block_by_id(full.keys().position(key).expect("missing m-resolved block"));
+            let full_values = full_block.values().to_array();
+            let values = block.values().to_array();
+            for (sample_i, sample) in block.samples().iter().enumerate() {
+                let full_sample_i = full_block.samples().position(sample).unwrap();
+                for (property_i, property) in block.properties().iter().enumerate() {
+                    let full_property_i = full_block.properties().position(property).unwrap();
+                    assert_eq!(
+                        values.index_axis(Axis(0), sample_i).index_axis(Axis(1), property_i),
+                        full_values.index_axis(Axis(0), full_sample_i).index_axis(Axis(1), full_property_i),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intermediates() {
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let (descriptor, spherical_expansion) = power_spectrum.compute_with_intermediates(
+            &mut systems, options
+        ).unwrap();
+
+        for (_, block) in spherical_expansion.iter() {
+            assert!(block.gradient("positions").is_some());
+        }
+
+        for (key, block) in descriptor.iter() {
+            let species_center = key[0];
+            let species_neighbor_1 = key[1];
+            let species_neighbor_2 = key[2];
+
+            let values = block.values().to_array();
+            for (property_i, &[l, n1, n2]) in block.properties().iter_fixed_size().enumerate() {
+                let spx_1 = spherical_expansion.block_by_id(spherical_expansion.keys().position(
+                    &[l, species_center, species_neighbor_1]
+                ).unwrap());
+                let spx_2 = spherical_expansion.block_by_id(spherical_expansion.keys().position(
+                    &[l, species_center, species_neighbor_2]
+                ).unwrap());
+
+                let mut factor = 1.0 / f64::sqrt((2 * l.usize() + 1) as f64);
+                if species_neighbor_1 != species_neighbor_2 {
+                    factor *= std::f64::consts::SQRT_2;
+                }
+
+                let spx_values_1 = spx_1.values().to_array();
+                let spx_values_2 = spx_2.values().to_array();
+                for (sample_i, sample) in block.samples().iter().enumerate() {
+                    let sample_1 = spx_1.samples().position(sample).unwrap();
+                    let sample_2 = spx_2.samples().position(sample).unwrap();
+
+                    let mut expected = 0.0;
+                    for m in 0..(2 * l.usize() + 1) {
+                        expected += spx_values_1[[sample_1, m, n1.usize()]] * spx_values_2[[sample_2, m, n2.usize()]];
+                    }
+
+                    assert_relative_eq!(
+                        values[[sample_i, property_i]], factor * expected,
+                        max_relative=1e-12, epsilon=1e-16
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intermediates_options() {
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+        let mut calculator = Calculator::from(Box::new(
+            SoapPowerSpectrum::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let thread_pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            thread_pool: Some(&thread_pool),
+            aggregate: Some(crate::utils::Aggregation::Sum),
+            ..Default::default()
+        };
+
+        let reference = calculator.compute(&mut systems, options).unwrap();
+        let (descriptor, spherical_expansion) = power_spectrum.compute_with_intermediates(
+            &mut systems, options
+        ).unwrap();
+
+        // aggregation only applies to the power spectrum
+        assert_eq!(spherical_expansion.block_by_id(0).samples().names(), ["structure", "center"]);
+
+        assert_eq!(descriptor.keys(), reference.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(reference.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            let gradient = block.gradient("positions").unwrap();
+            let expected = expected.gradient("positions").unwrap();
+            assert_eq!(gradient.samples(), expected.samples());
+            assert_relative_eq!(
+                gradient.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+        }
+    }
+
+    #[test]
+    fn all_species() {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            PowerSpectrumParameters {
+                all_species: Some(vec![1, 6, -42]),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water"]);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        // 3 center species and 6 symmetric pairs of neighbor species
+        assert_eq!(descriptor.keys().count(), 18);
+        for (key, block) in descriptor.iter() {
+            // there is no carbon in water, so the corresponding blocks are empty
+            if key[0] == 6 || key[1] == 6 || key[2] == 6 {
+                assert_eq!(block.samples().count(), 0);
+            } else {
+                assert_ne!(block.samples().count(), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn from_spherical_expansion() {
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let (mut descriptor, spherical_expansion) = power_spectrum.compute_with_intermediates(
+            &mut systems, options
+        ).unwrap();
+
+        let mut expected = Vec::new();
+        for (_, block) in descriptor.iter() {
+            expected.push(block.values().to_array().clone());
+            expected.push(block.gradient("positions").unwrap().values().to_array().clone());
+        }
+
+        for (_, mut block) in descriptor.iter_mut() {
+            block.data_mut().values.to_array_mut().fill(0.0);
+            block.gradient_mut("positions").unwrap().data_mut().values.to_array_mut().fill(0.0);
+        }
+
+        power_spectrum.compute_from_spherical_expansion(&spherical_expansion, &mut descriptor).unwrap();
+
+        let mut expected = expected.iter();
+        for (_, block) in descriptor.iter() {
+            assert_relative_eq!(
+                block.values().to_array(), expected.next().unwrap(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            let gradient = block.gradient("positions").unwrap();
+            assert_relative_eq!(
+                gradient.values().to_array(), expected.next().unwrap(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+        }
+
+        // the spherical expansion does not contain all the required properties
+        let mut small_parameters = parameters();
+        small_parameters.max_radial -= 1;
+        let mut small = SoapPowerSpectrum::new(small_parameters).unwrap();
+        let (_, small_spherical_expansion) = small.compute_with_intermediates(
+            &mut systems, options
+        ).unwrap();
+
+        let error = power_spectrum.compute_from_spherical_expansion(&small_spherical_expansion, &mut descriptor).unwrap_err();
+        assert!(error.to_string().starts_with("invalid parameter: missing property n="));
+
+        // the spherical expansion does not contain gradients
+        let (_, spherical_expansion) = power_spectrum.compute_with_intermediates(
+            &mut systems, Default::default()
+        ).unwrap();
+        let error = power_spectrum.compute_from_spherical_expansion(&spherical_expansion, &mut descriptor).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: missing positions gradients in the spherical expansion");
+    }
+
+    #[test]
+    fn from_spherical_expansion_invalid_gradients() {
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+
+        let mut systems = test_systems(&["water"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let (mut descriptor, spherical_expansion) = power_spectrum.compute_with_intermediates(
+            &mut systems, options
+        ).unwrap();
+
+        // rename the positions gradients samples in the spherical expansion
+        let mut blocks = Vec::new();
+        for (_, block) in spherical_expansion.iter() {
+            let mut new_block = TensorBlock::new(
+                block.values().to_array().clone(),
+                &block.samples(),
+                &block.components(),
+                &block.properties(),
+            ).unwrap();
+
+            let gradient = block.gradient("positions").unwrap();
+            let mut samples = LabelsBuilder::new(vec!["sample", "structure", "neighbor"]);
+            for sample in gradient.samples().iter() {
+                samples.add(sample);
+            }
+
+            new_block.add_gradient("positions", TensorBlock::new(
+                gradient.values().to_array().clone(),
+                &samples.finish(),
+                &gradient.components(),
+                &gradient.properties(),
+            ).unwrap()).unwrap();
+
+            blocks.push(new_block);
+        }
+        let spherical_expansion = TensorMap::new(spherical_expansion.keys().clone(), blocks).unwrap();
+
+        let error = power_spectrum.compute_from_spherical_expansion(&spherical_expansion, &mut descriptor).unwrap_err();
+        assert!(error.to_string().starts_with(
+            "invalid parameter: invalid samples for positions gradients in the spherical expansion block"
+        ));
+        assert!(error.to_string().ends_with(
+            "expected [sample, structure, atom], got [sample, structure, neighbor]"
+        ));
+    }
+
+    #[test]
+    fn intermediates_selected_samples_gradients() {
+        let mut systems = test_systems(&["water", "methane"]);
+
+        // the spherical expansion contains all samples, and the gradient
+        // samples need to be matched with the selected power spectrum samples
+        let samples = Labels::new(["structure", "center"], &[[0, 1], [1, 0], [1, 3]]);
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            selected_samples: LabelsSelection::Subset(&samples),
+            ..Default::default()
+        };
+
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+        let (descriptor, _) = power_spectrum.compute_with_intermediates(&mut systems, options).unwrap();
+
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        let expected = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for (block, expected) in descriptor.blocks().iter().zip(expected.blocks()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            for parameter in ["positions", "cell"] {
+                let gradient = block.gradient(parameter).unwrap();
+                let expected = expected.gradient(parameter).unwrap();
+                assert_eq!(gradient.samples(), expected.samples());
+                assert_relative_eq!(
+                    gradient.values().to_array(), expected.values().to_array(),
+                    max_relative=1e-12, epsilon=1e-16
+                );
+            }
+        }
+    }
+
+    /// Check that the fused and non-fused calculations agree for `systems`
+    fn check_fused(systems: &mut [Box<dyn System>]) {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut fused_parameters = parameters();
+        fused_parameters.fused = true;
+        let mut fused = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            fused_parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+        let expected = calculator.compute(systems, options).unwrap();
+        let descriptor = fused.compute(systems, options).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            for parameter in ["positions", "cell"] {
+                let gradient = block.gradient(parameter).unwrap();
+                let expected = expected.gradient(parameter).unwrap();
+                assert_eq!(gradient.samples(), expected.samples());
+                assert_relative_eq!(
+                    gradient.values().to_array(), expected.values().to_array(),
+                    max_relative=1e-12, epsilon=1e-16
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fused() {
+        check_fused(&mut test_systems(&["water", "methane", "CH"]));
+    }
+
+    #[test]
+    fn fused_multiple_chunks() {
+        // more centers than FUSED_CENTERS_CHUNK, with pairs between atoms in
+        // different chunks
+        let system = test_system("methane").supercell([3, 3, 2]).unwrap();
+        assert!(system.size().unwrap() > FUSED_CENTERS_CHUNK);
+
+        check_fused(&mut [Box::new(system) as Box<dyn System>]);
+    }
+
+    #[test]
+    fn finite_differences_positions_fused_multiple_chunks() {
+        let mut parameters = parameters();
+        parameters.max_radial = 2;
+        parameters.max_angular = 2;
+        parameters.fused = true;
+        let calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        // the second chunk starts in the middle of a water molecule
+        let system = test_system("water").supercell([5, 5, 1]).unwrap();
+        assert!(system.size().unwrap() > FUSED_CENTERS_CHUNK);
+
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 5e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    #[test]
+    fn explicit_sum_over_m() {
+        // check the values and gradients computed with matrix multiplications
+        // against an explicit sum over `m`
+        let mut power_spectrum = SoapPowerSpectrum::new(parameters()).unwrap();
+
+        let mut systems = test_systems(&["water", "methane", "CH"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let (descriptor, spherical_expansion) = power_spectrum.compute_with_intermediates(
+            &mut systems, options
+        ).unwrap();
+
+        let spx_block = |l: LabelValue, center: LabelValue, neighbor: LabelValue| {
+            let mut key = LabelsBuilder::new(vec!["spherical_harmonics_l", "species_center", "species_neighbor"]);
+            key.add(&[l, center, neighbor]);
+            return spherical_expansion.block(&key.finish()).unwrap();
+        };
+
+        for (key, block) in descriptor.iter() {
+            let samples = block.samples();
+            let values = block.values().to_array();
+            let gradient = block.gradient("positions").unwrap();
+            let gradient_values = gradient.values().to_array();
+
+            for (property_i, &[l, n1, n2]) in block.properties().iter_fixed_size().enumerate() {
+                let spx_1 = spx_block(l, key[0], key[1]);
+                let spx_2 = spx_block(l, key[0], key[2]);
+                let spx_1_values = spx_1.values().to_array();
+                let spx_2_values = spx_2.values().to_array();
+                let spx_1_gradient = spx_1.gradient("positions").unwrap();
+                let spx_2_gradient = spx_2.gradient("positions").unwrap();
+
+                let property_1 = spx_1.properties().position(&[n1]).unwrap();
+                let property_2 = spx_2.properties().position(&[n2]).unwrap();
+
+                let mut factor = 1.0 / f64::sqrt((2 * l.usize() + 1) as f64);
+                if key[1] != key[2] {
+                    factor *= std::f64::consts::SQRT_2;
+                }
+
+                for (sample_i, sample) in samples.iter().enumerate() {
+                    let spx_sample_1 = spx_1.samples().position(sample).unwrap();
+                    let spx_sample_2 = spx_2.samples().position(sample).unwrap();
+
+                    let mut expected = 0.0;
+                    for m in 0..(2 * l.usize() + 1) {
+                        expected += spx_1_values[[spx_sample_1, m, property_1]] * spx_2_values[[spx_sample_2, m, property_2]];
+                    }
+                    assert_relative_eq!(
+                        values[[sample_i, property_i]], factor * expected,
+                        max_relative=1e-12, epsilon=1e-16
+                    );
+                }
+
+                for (gradient_i, &[sample_i, structure, atom]) in gradient.samples().iter_fixed_size().enumerate() {
+                    let sample = &samples[sample_i.usize()];
+                    let spx_sample_1 = spx_1.samples().position(sample).unwrap();
+                    let spx_sample_2 = spx_2.samples().position(sample).unwrap();
+
+                    let spx_gradient_1 = spx_1_gradient.samples().position(&[spx_sample_1.into(), structure, atom]);
+                    let spx_gradient_2 = spx_2_gradient.samples().position(&[spx_sample_2.into(), structure, atom]);
+
+                    for d in 0..3 {
+                        let mut expected = 0.0;
+                        for m in 0..(2 * l.usize() + 1) {
+                            if let Some(spx_gradient_1) = spx_gradient_1 {
+                                expected += spx_1_gradient.values().to_array()[[spx_gradient_1, d, m, property_1]]
+                                    * spx_2_values[[spx_sample_2, m, property_2]];
+                            }
+
+                            if let Some(spx_gradient_2) = spx_gradient_2 {
+                                expected += spx_1_values[[spx_sample_1, m, property_1]]
+                                    * spx_2_gradient.values().to_array()[[spx_gradient_2, d, m, property_2]];
+                            }
+                        }
+
+                        assert_relative_eq!(
+                            gradient_values[[gradient_i, d, property_i]], factor * expected,
+                            max_relative=1e-12, epsilon=1e-16
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     #[test]
     fn finite_differences_positions() {
         let calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
@@ -793,6 +2578,23 @@ mod tests {
         crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
     }
 
+    #[test]
+    fn finite_differences_optimal_displacement() {
+        let mut calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions::default().max_relative(5e-5);
+        let options = crate::calculators::tests_utils::optimal_displacement(&mut calculator, &system, options);
+
+        // the optimal displacement for central finite differences in double
+        // precision is around 1e-5/1e-6, far from both ends of the sweep
+        assert!(options.displacement <= 1e-4 && options.displacement >= 1e-8);
+
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
     #[test]
     fn finite_differences_cell() {
         let calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
@@ -808,6 +2610,21 @@ mod tests {
         crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
     }
 
+    #[test]
+    fn finite_differences_strain() {
+        let calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-5,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_strain(calculator, &system, options);
+    }
+
     #[test]
     fn compute_partial() {
         let calculator = Calculator::from(Box::new(SoapPowerSpectrum::new(
@@ -0,0 +1,287 @@
+use std::f64;
+
+use ndarray::{Array3, ArrayViewMut2};
+
+use crate::calculators::radial_basis::SphericalBesselRadialBasis;
+use crate::math::{gauss_legendre, spherical_bessel_first_kind, modified_spherical_bessel_scaled};
+use crate::Error;
+
+use super::SoapRadialIntegral;
+
+/// Parameters controlling the SOAP radial integral with spherical Bessel
+/// radial basis
+#[derive(Debug, Clone, Copy)]
+pub struct SoapRadialIntegralSphericalBesselParameters {
+    /// Number of radial components
+    pub max_radial: usize,
+    /// Number of angular components
+    pub max_angular: usize,
+    /// atomic density gaussian width
+    pub atomic_gaussian_width: f64,
+    /// cutoff radius
+    pub cutoff: f64,
+}
+
+impl SoapRadialIntegralSphericalBesselParameters {
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        if self.max_radial == 0 {
+            return Err(Error::InvalidParameter(
+                "max_radial must be at least 1 for spherical Bessel radial integral".into()
+            ));
+        }
+
+        if self.cutoff <= 0.0 || !self.cutoff.is_finite() {
+            return Err(Error::InvalidParameter(
+                "cutoff must be a positive number for spherical Bessel radial integral".into()
+            ));
+        }
+
+        if self.atomic_gaussian_width <= 0.0 || !self.atomic_gaussian_width.is_finite() {
+            return Err(Error::InvalidParameter(
+                "atomic_gaussian_width must be a positive number for spherical Bessel radial integral".into()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Implementation of the radial integral for spherical Bessel radial basis
+/// and gaussian atomic density.
+///
+/// There is no closed form for this integral, so it is evaluated with a
+/// Gauss-Legendre quadrature over `[0, cutoff]`. This is slow, and this
+/// implementation is meant to be used through `SoapRadialIntegralSpline`.
+#[derive(Debug, Clone)]
+pub struct SoapRadialIntegralSphericalBessel {
+    parameters: SoapRadialIntegralSphericalBesselParameters,
+    /// σ^2, with σ the atomic density gaussian width
+    atomic_gaussian_width_2: f64,
+    /// Quadrature nodes, in `[0, cutoff]`
+    nodes: Vec<f64>,
+    /// Quadrature weights, including the `r^2` factor from the integral
+    weights: Vec<f64>,
+    /// Radial basis functions evaluated on the quadrature nodes, with shape
+    /// `(max_angular + 1) x max_radial x n_nodes`
+    basis_values: Array3<f64>,
+}
+
+impl SoapRadialIntegralSphericalBessel {
+    pub fn new(parameters: SoapRadialIntegralSphericalBesselParameters) -> Result<SoapRadialIntegralSphericalBessel, Error> {
+        parameters.validate()?;
+
+        let basis = SphericalBesselRadialBasis {
+            max_radial: parameters.max_radial,
+            max_angular: parameters.max_angular,
+            cutoff: parameters.cutoff,
+        };
+        let zeros = basis.zeros();
+        let normalization = basis.normalization(&zeros);
+
+        // use enough points to sample the atomic gaussian density everywhere
+        let n_nodes = usize::max(64, (10.0 * parameters.cutoff / parameters.atomic_gaussian_width).ceil() as usize);
+        let (nodes, weights) = gauss_legendre(n_nodes);
+
+        // go from [-1, 1] to [0, cutoff]
+        let half_cutoff = 0.5 * parameters.cutoff;
+        let nodes = nodes.iter().map(|x| half_cutoff * (x + 1.0)).collect::<Vec<_>>();
+        let weights = nodes.iter().zip(&weights)
+            .map(|(r, w)| half_cutoff * w * r * r)
+            .collect::<Vec<_>>();
+
+        let shape = (parameters.max_angular + 1, parameters.max_radial, n_nodes);
+        let mut basis_values = Array3::from_elem(shape, 0.0);
+        for ((l, n, k), value) in basis_values.indexed_iter_mut() {
+            let x = zeros[[l, n]] * nodes[k] / parameters.cutoff;
+            *value = normalization[[l, n]] * spherical_bessel_first_kind(l, x);
+        }
+
+        return Ok(SoapRadialIntegralSphericalBessel {
+            parameters: parameters,
+            atomic_gaussian_width_2: parameters.atomic_gaussian_width * parameters.atomic_gaussian_width,
+            nodes: nodes,
+            weights: weights,
+            basis_values: basis_values,
+        });
+    }
+}
+
+impl SoapRadialIntegral for SoapRadialIntegralSphericalBessel {
+    #[time_graph::instrument(name = "SphericalBesselRadialIntegral::compute")]
+    fn compute(
+        &self,
+        distance: f64,
+        mut values: ArrayViewMut2<f64>,
+        mut gradients: Option<ArrayViewMut2<f64>>
+    ) {
+        let expected_shape = [self.parameters.max_angular + 1, self.parameters.max_radial];
+        assert_eq!(
+            values.shape(), expected_shape,
+            "wrong size for values array, expected [{}, {}] but got [{}, {}]",
+            expected_shape[0], expected_shape[1], values.shape()[0], values.shape()[1]
+        );
+
+        if let Some(ref gradients) = gradients {
+            assert_eq!(
+                gradients.shape(), expected_shape,
+                "wrong size for gradients array, expected [{}, {}] but got [{}, {}]",
+                expected_shape[0], expected_shape[1], gradients.shape()[0], gradients.shape()[1]
+            );
+        }
+
+        // 4π comes from the integration of the angular part, and
+        // 1/(πσ^2)^3/4 from the normalization of the atomic gaussian density
+        let global_factor = 4.0 * f64::consts::PI / (f64::consts::PI * self.atomic_gaussian_width_2).powf(0.75);
+        let sigma_2 = self.atomic_gaussian_width_2;
+
+        values.fill(0.0);
+        if let Some(ref mut gradients) = gradients {
+            gradients.fill(0.0);
+        }
+
+        // e^{-x} i_l(x) for l up to max_angular + 1, the last one is used
+        // for the gradients
+        let mut scaled_bessel = vec![0.0; self.parameters.max_angular + 2];
+        for (k, (&r, &weight)) in self.nodes.iter().zip(&self.weights).enumerate() {
+            let x = r * distance / sigma_2;
+            modified_spherical_bessel_scaled(x, &mut scaled_bessel);
+
+            // e^{-(r^2 + rij^2) / 2σ^2} i_l(x) = e^{-(r - rij)^2 / 2σ^2} e^{-x} i_l(x)
+            let delta = r - distance;
+            let factor = global_factor * weight * f64::exp(-0.5 * delta * delta / sigma_2);
+
+            for l in 0..=self.parameters.max_angular {
+                let value_factor = factor * scaled_bessel[l];
+                for n in 0..self.parameters.max_radial {
+                    values[[l, n]] += value_factor * self.basis_values[[l, n, k]];
+                }
+
+                if let Some(ref mut gradients) = gradients {
+                    // e^{-x} d i_l / dx = e^{-x} (i_{l + 1} + l / x i_l)
+                    let bessel_derivative = if x == 0.0 {
+                        if l == 1 { 1.0 / 3.0 } else { 0.0 }
+                    } else {
+                        scaled_bessel[l + 1] + l as f64 / x * scaled_bessel[l]
+                    };
+
+                    let gradient_factor = factor * (
+                        -distance / sigma_2 * scaled_bessel[l] + r / sigma_2 * bessel_derivative
+                    );
+                    for n in 0..self.parameters.max_radial {
+                        gradients[[l, n]] += gradient_factor * self.basis_values[[l, n, k]];
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::Array2;
+
+    use crate::calculators::radial_basis::SphericalBesselRadialBasis;
+    use crate::math::spherical_bessel_first_kind;
+
+    use super::super::{SoapRadialIntegral, SoapRadialIntegralSphericalBessel, SoapRadialIntegralSphericalBesselParameters};
+
+    #[test]
+    #[should_panic = "atomic_gaussian_width must be a positive number"]
+    fn zero_atomic_gaussian_width() {
+        SoapRadialIntegralSphericalBessel::new(SoapRadialIntegralSphericalBesselParameters {
+            max_radial: 6,
+            max_angular: 4,
+            cutoff: 3.0,
+            atomic_gaussian_width: 0.0,
+        }).unwrap();
+    }
+
+    #[test]
+    fn narrow_density() {
+        // for a very narrow gaussian density, the radial integral reduces to
+        // the radial basis evaluated at rij, multiplied by the integral of
+        // the density
+        let parameters = SoapRadialIntegralSphericalBesselParameters {
+            max_radial: 3,
+            max_angular: 3,
+            cutoff: 5.0,
+            atomic_gaussian_width: 0.02,
+        };
+        let bessel = SoapRadialIntegralSphericalBessel::new(parameters).unwrap();
+
+        let basis = SphericalBesselRadialBasis {
+            max_radial: parameters.max_radial,
+            max_angular: parameters.max_angular,
+            cutoff: parameters.cutoff,
+        };
+        let zeros = basis.zeros();
+        let normalization = basis.normalization(&zeros);
+
+        let sigma_2 = parameters.atomic_gaussian_width * parameters.atomic_gaussian_width;
+        let density_integral = (4.0 * std::f64::consts::PI * sigma_2).powf(0.75);
+
+        let rij = 2.3;
+        let mut values = Array2::from_elem((4, 3), 0.0);
+        bessel.compute(rij, values.view_mut(), None);
+
+        for l in 0..=parameters.max_angular {
+            for n in 0..parameters.max_radial {
+                let expected = density_integral * normalization[[l, n]]
+                    * spherical_bessel_first_kind(l, zeros[[l, n]] * rij / parameters.cutoff);
+                assert_relative_eq!(values[[l, n]], expected, epsilon=1e-6, max_relative=5e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn gradients_near_zero() {
+        let max_radial = 6;
+        let max_angular = 6;
+        let bessel = SoapRadialIntegralSphericalBessel::new(SoapRadialIntegralSphericalBesselParameters {
+            max_radial: max_radial,
+            max_angular: max_angular,
+            cutoff: 5.0,
+            atomic_gaussian_width: 0.5,
+        }).unwrap();
+
+        let shape = (max_angular + 1, max_radial);
+        let mut values = Array2::from_elem(shape, 0.0);
+        let mut gradients = Array2::from_elem(shape, 0.0);
+        let mut gradients_plus = Array2::from_elem(shape, 0.0);
+        bessel.compute(0.0, values.view_mut(), Some(gradients.view_mut()));
+        bessel.compute(1e-12, values.view_mut(), Some(gradients_plus.view_mut()));
+
+        assert_relative_eq!(
+            gradients, gradients_plus, epsilon=1e-11, max_relative=1e-6,
+        );
+    }
+
+    #[test]
+    fn finite_differences() {
+        let max_radial = 6;
+        let max_angular = 6;
+        let bessel = SoapRadialIntegralSphericalBessel::new(SoapRadialIntegralSphericalBesselParameters {
+            max_radial: max_radial,
+            max_angular: max_angular,
+            cutoff: 5.0,
+            atomic_gaussian_width: 0.5,
+        }).unwrap();
+
+        let rij = 3.4;
+        let delta = 1e-9;
+
+        let shape = (max_angular + 1, max_radial);
+        let mut values = Array2::from_elem(shape, 0.0);
+        let mut values_delta = Array2::from_elem(shape, 0.0);
+        let mut gradients = Array2::from_elem(shape, 0.0);
+        bessel.compute(rij, values.view_mut(), Some(gradients.view_mut()));
+        bessel.compute(rij + delta, values_delta.view_mut(), None);
+
+        let finite_differences = (&values_delta - &values) / delta;
+
+        assert_relative_eq!(
+            finite_differences, gradients, epsilon=1e-7, max_relative=1e-4
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use std::f64;
 
-use ndarray::{Array2, ArrayViewMut2};
+use ndarray::{Array1, Array2, ArrayViewMut2};
 
 use crate::calculators::radial_basis::GtoRadialBasis;
 use crate::math::{gamma, DoubleRegularized1F1};
@@ -188,6 +188,65 @@ impl SoapRadialIntegral for SoapRadialIntegralGto {
             gradients.assign(&gradients.dot(&self.gto_orthonormalization));
         }
     }
+
+    #[time_graph::instrument(name = "GtoRadialIntegral::compute_width_gradients")]
+    fn compute_width_gradients(&self, distance: f64, mut width_gradients: ArrayViewMut2<f64>) -> Result<(), Error> {
+        let expected_shape = [self.parameters.max_angular + 1, self.parameters.max_radial];
+        assert_eq!(
+            width_gradients.shape(), expected_shape,
+            "wrong size for width gradients array, expected [{}, {}] but got [{}, {}]",
+            expected_shape[0], expected_shape[1], width_gradients.shape()[0], width_gradients.shape()[1]
+        );
+
+        // The radial integral (before orthonormalization) is
+        //
+        //     I_nl = (2πc)^3/4 exp(-c rij^2) (c rij)^l (c + c_n)^(-a) G(z)
+        //
+        // with c = 1/2σ^2, a = (n + l + 3) / 2 and z = c^2 rij^2 / (c + c_n).
+        // We differentiate it with respect to c, and then use the chain rule
+        // to get the derivative with respect to σ.
+        let global_factor = (std::f64::consts::PI / self.atomic_gaussian_width_2).powf(0.75);
+
+        let c = self.atomic_gaussian_constant;
+        let c_rij = c * distance;
+        let exp_c_rij = f64::exp(-distance * c_rij);
+        let distance_2 = distance * distance;
+
+        // dc/dσ = -1/σ^3
+        let dc_dsigma = -1.0 / (self.parameters.atomic_gaussian_width * self.atomic_gaussian_width_2);
+
+        let mut g_values = Array1::from_elem(self.parameters.max_angular + 1, 0.0);
+        let mut g_gradients = Array1::from_elem(self.parameters.max_angular + 1, 0.0);
+        for n in 0..self.parameters.max_radial {
+            let gto_constant = self.gto_gaussian_constants[n];
+            // `global_factor * exp(-c rij^2) * (c * rij)^l`
+            let mut factor = global_factor * exp_c_rij;
+
+            let c_plus_gto = c + gto_constant;
+            let z = c_rij * c_rij / c_plus_gto;
+            let dz_dc = distance_2 * c * (c + 2.0 * gto_constant) / (c_plus_gto * c_plus_gto);
+
+            self.double_regularized_1f1.compute(z, n, g_values.view_mut(), Some(g_gradients.view_mut()));
+
+            for l in 0..(self.parameters.max_angular + 1) {
+                let n_l_3_over_2 = 0.5 * (n + l) as f64 + 1.5;
+                let c_dn = c_plus_gto.powf(-n_l_3_over_2);
+
+                // derivative of the log of everything except G w.r.t. c
+                let dlog_dc = (0.75 + l as f64) / c - distance_2 - n_l_3_over_2 / c_plus_gto;
+
+                width_gradients[[l, n]] = dc_dsigma * c_dn * factor * (
+                    g_values[l] * dlog_dc + g_gradients[l] * dz_dc
+                );
+
+                factor *= c_rij;
+            }
+        }
+
+        width_gradients.assign(&width_gradients.dot(&self.gto_orthonormalization));
+
+        return Ok(());
+    }
 }
 
 #[cfg(test)]
@@ -315,6 +374,41 @@ mod tests {
         );
     }
 
+    #[test]
+    fn width_finite_differences() {
+        let max_radial = 8;
+        let max_angular = 8;
+        let parameters = |atomic_gaussian_width| SoapRadialIntegralGtoParameters {
+            max_radial: max_radial,
+            max_angular: max_angular,
+            cutoff: 5.0,
+            atomic_gaussian_width: atomic_gaussian_width,
+        };
+
+        let width = 0.5;
+        let delta = 1e-7;
+        let gto = SoapRadialIntegralGto::new(parameters(width)).unwrap();
+        let gto_pos = SoapRadialIntegralGto::new(parameters(width + delta / 2.0)).unwrap();
+        let gto_neg = SoapRadialIntegralGto::new(parameters(width - delta / 2.0)).unwrap();
+
+        let shape = (max_angular + 1, max_radial);
+        let mut values_pos = Array2::from_elem(shape, 0.0);
+        let mut values_neg = Array2::from_elem(shape, 0.0);
+        let mut width_gradients = Array2::from_elem(shape, 0.0);
+
+        for rij in [0.0, 1.2, 3.4] {
+            gto.compute_width_gradients(rij, width_gradients.view_mut()).unwrap();
+            gto_pos.compute(rij, values_pos.view_mut(), None);
+            gto_neg.compute(rij, values_neg.view_mut(), None);
+
+            let finite_differences = (&values_pos - &values_neg) / delta;
+            assert_relative_eq!(
+                finite_differences, width_gradients,
+                epsilon=1e-6, max_relative=1e-5,
+            );
+        }
+    }
+
     #[test]
     fn finite_differences() {
         let max_radial = 8;
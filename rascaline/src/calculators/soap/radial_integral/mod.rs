@@ -49,11 +49,27 @@ pub trait SoapRadialIntegral: std::panic::RefUnwindSafe + Send {
     ///
     /// where $P_l$ is the l-th Legendre polynomial.
     fn compute(&self, rij: f64, values: ArrayViewMut2<f64>, gradients: Option<ArrayViewMut2<f64>>);
+
+    /// Compute the derivative of the radial integral with respect to the
+    /// atomic gaussian width $\sigma$ for a single `distance` between two
+    /// atoms, and store the resulting data in the `(max_angular + 1) x
+    /// max_radial` array `width_gradients`.
+    ///
+    /// The default implementation returns an error, radial integrals
+    /// supporting these gradients should override it.
+    fn compute_width_gradients(&self, _rij: f64, _width_gradients: ArrayViewMut2<f64>) -> Result<(), Error> {
+        return Err(Error::InvalidParameter(
+            "gradients with respect to the atomic gaussian width are not available for this radial integral".into()
+        ));
+    }
 }
 
 mod gto;
 pub use self::gto::{SoapRadialIntegralGto, SoapRadialIntegralGtoParameters};
 
+mod bessel;
+pub use self::bessel::{SoapRadialIntegralSphericalBessel, SoapRadialIntegralSphericalBesselParameters};
+
 mod spline;
 pub use self::spline::{SoapRadialIntegralSpline, SoapRadialIntegralSplineParameters};
 
@@ -75,11 +91,32 @@ pub struct SoapRadialIntegralCache {
     pub(crate) values: Array2<f64>,
     /// Cache for the radial integral gradient
     pub(crate) gradients: Array2<f64>,
+    /// Cache for the radial integral gradient with respect to the atomic
+    /// gaussian width
+    pub(crate) width_gradients: Array2<f64>,
 }
 
 impl SoapRadialIntegralCache {
     /// Create a new `RadialIntegralCache` for the given radial basis & parameters
     pub fn new(radial_basis: RadialBasis, parameters: SoapRadialIntegralParameters) -> Result<Self, Error> {
+        return SoapRadialIntegralCache::create(radial_basis, parameters, false);
+    }
+
+    /// Create a new `RadialIntegralCache` for the given radial basis &
+    /// parameters, which can also compute gradients with respect to the
+    /// atomic gaussian width with `compute_width_gradients`. This is only
+    /// available for the GTO radial basis.
+    pub fn with_width_gradients(radial_basis: RadialBasis, parameters: SoapRadialIntegralParameters) -> Result<Self, Error> {
+        return SoapRadialIntegralCache::create(radial_basis, parameters, true);
+    }
+
+    fn create(radial_basis: RadialBasis, parameters: SoapRadialIntegralParameters, width_gradients: bool) -> Result<Self, Error> {
+        if width_gradients && !matches!(radial_basis, RadialBasis::Gto { .. }) {
+            return Err(Error::InvalidParameter(
+                "gradients with respect to the atomic gaussian width are only available for the GTO radial basis".into()
+            ));
+        }
+
         let code = match radial_basis {
             RadialBasis::Gto {splined_radial_integral, spline_accuracy} => {
                 let parameters = SoapRadialIntegralGtoParameters {
@@ -97,14 +134,39 @@ impl SoapRadialIntegralCache {
                         cutoff: parameters.cutoff,
                     };
 
-                    Box::new(SoapRadialIntegralSpline::with_accuracy(
-                        parameters, spline_accuracy, gto
-                    )?)
+                    let mut spline = SoapRadialIntegralSpline::with_accuracy(
+                        parameters, spline_accuracy, gto.clone()
+                    )?;
+
+                    if width_gradients {
+                        spline.tabulate_width_gradients(parameters, spline_accuracy, &gto)?;
+                    }
+
+                    Box::new(spline)
                 } else {
                     Box::new(gto) as Box<dyn SoapRadialIntegral>
                 }
             }
 
+            RadialBasis::SphericalBessel {spline_accuracy} => {
+                let parameters = SoapRadialIntegralSphericalBesselParameters {
+                    max_radial: parameters.max_radial,
+                    max_angular: parameters.max_angular,
+                    atomic_gaussian_width: parameters.atomic_gaussian_width,
+                    cutoff: parameters.cutoff,
+                };
+                let bessel = SoapRadialIntegralSphericalBessel::new(parameters)?;
+
+                let parameters = SoapRadialIntegralSplineParameters {
+                    max_radial: parameters.max_radial,
+                    max_angular: parameters.max_angular,
+                    cutoff: parameters.cutoff,
+                };
+                Box::new(SoapRadialIntegralSpline::with_accuracy(
+                    parameters, spline_accuracy, bessel
+                )?)
+            }
+
             RadialBasis::TabulatedRadialIntegral {points} => {
                 let parameters = SoapRadialIntegralSplineParameters {
                     max_radial: parameters.max_radial,
@@ -120,8 +182,9 @@ impl SoapRadialIntegralCache {
         let shape = (parameters.max_angular + 1, parameters.max_radial);
         let values = Array2::from_elem(shape, 0.0);
         let gradients = Array2::from_elem(shape, 0.0);
+        let width_gradients = Array2::from_elem(shape, 0.0);
 
-        return Ok(SoapRadialIntegralCache { code, values, gradients });
+        return Ok(SoapRadialIntegralCache { code, values, gradients, width_gradients });
     }
 
     /// Run the calculation, the results are stored inside `self.values` and
@@ -141,4 +204,12 @@ impl SoapRadialIntegralCache {
             );
         }
     }
+
+    /// Compute the gradients with respect to the atomic gaussian width, the
+    /// results are stored inside `self.width_gradients`. This cache must have
+    /// been created with `with_width_gradients`, otherwise this function
+    /// returns an error.
+    pub fn compute_width_gradients(&mut self, distance: f64) -> Result<(), Error> {
+        return self.code.compute_width_gradients(distance, self.width_gradients.view_mut());
+    }
 }
@@ -14,8 +14,15 @@ use crate::Error;
 /// [splines-wiki]: https://en.wikipedia.org/wiki/Cubic_Hermite_spline
 pub struct SoapRadialIntegralSpline {
     spline: HermitCubicSpline<ndarray::Ix2>,
+    /// Spline for the gradients of the radial integral with respect to the
+    /// atomic gaussian width, if they were tabulated
+    width_spline: Option<HermitCubicSpline<ndarray::Ix2>>,
 }
 
+/// Displacement used to compute the derivative with respect to the distance
+/// of the width gradients when tabulating them
+const WIDTH_SPLINE_DELTA: f64 = 1e-6;
+
 /// Parameters for computing the radial integral using Hermit cubic splines
 #[derive(Debug, Clone, Copy)]
 pub struct SoapRadialIntegralSplineParameters {
@@ -58,13 +65,113 @@ impl SoapRadialIntegralSpline {
             },
         )?;
 
-        return Ok(SoapRadialIntegralSpline { spline });
+        return Ok(SoapRadialIntegralSpline { spline, width_spline: None });
     }
 
+    /// Also tabulate the gradients of `radial_integral` with respect to the
+    /// atomic gaussian width, with the given `accuracy`. After calling this
+    /// function, `compute_width_gradients` can be used on this spline.
+    ///
+    /// The Hermit spline needs the derivative of these width gradients with
+    /// respect to the distance, which are computed with finite differences.
+    pub fn tabulate_width_gradients(
+        &mut self,
+        parameters: SoapRadialIntegralSplineParameters,
+        accuracy: f64,
+        radial_integral: &impl SoapRadialIntegral,
+    ) -> Result<(), Error> {
+        let shape_tuple = (parameters.max_angular + 1, parameters.max_radial);
+
+        // the function given to the spline below can not return errors, so
+        // check that the radial integral supports width gradients beforehand
+        let mut width_gradients = Array2::from_elem(shape_tuple, 0.0);
+        radial_integral.compute_width_gradients(0.0, width_gradients.view_mut())?;
+
+        let parameters = SplineParameters {
+            start: 0.0,
+            stop: parameters.cutoff,
+            shape: vec![parameters.max_angular + 1, parameters.max_radial],
+        };
+
+        let width_spline = HermitCubicSpline::with_accuracy(
+            accuracy,
+            parameters,
+            |x| {
+                let mut width_gradients = Array2::from_elem(shape_tuple, 0.0);
+                radial_integral.compute_width_gradients(x, width_gradients.view_mut())
+                    .expect("the radial integral should support width gradients");
+
+                // use forward differences close to 0 to stay in the domain of
+                // the radial integral
+                let (before, after) = if x < WIDTH_SPLINE_DELTA {
+                    (x, x + WIDTH_SPLINE_DELTA)
+                } else {
+                    (x - WIDTH_SPLINE_DELTA, x + WIDTH_SPLINE_DELTA)
+                };
+
+                let mut width_gradients_before = Array2::from_elem(shape_tuple, 0.0);
+                let mut width_gradients_after = Array2::from_elem(shape_tuple, 0.0);
+                radial_integral.compute_width_gradients(before, width_gradients_before.view_mut())
+                    .expect("the radial integral should support width gradients");
+                radial_integral.compute_width_gradients(after, width_gradients_after.view_mut())
+                    .expect("the radial integral should support width gradients");
+
+                let derivative = (width_gradients_after - width_gradients_before) / (after - before);
+                (width_gradients, derivative)
+            },
+        )?;
+
+        self.width_spline = Some(width_spline);
+        return Ok(());
+    }
+
+    /// Create a new `SoapRadialIntegralSpline` from user-provided spline
+    /// points, containing the value and derivative of the radial integral.
+    ///
+    /// The points must cover the whole `[0, cutoff]` range, and contain values
+    /// and derivatives with a `(max_angular + 1) x max_radial` shape.
+    #[allow(clippy::float_cmp)]
     pub fn from_tabulated(
         parameters: SoapRadialIntegralSplineParameters,
-        spline_points: Vec<SplinePoint>
+        mut spline_points: Vec<SplinePoint>
     ) -> Result<SoapRadialIntegralSpline, Error> {
+        if spline_points.len() < 2 {
+            return Err(Error::InvalidParameter(format!(
+                "we need at least two points for a tabulated radial integral, got {}",
+                spline_points.len()
+            )));
+        }
+
+        if spline_points.iter().any(|point| !point.position.is_finite()) {
+            return Err(Error::InvalidParameter(
+                "all the positions in a tabulated radial integral must be finite".into()
+            ));
+        }
+
+        spline_points.sort_by(|a, b| {
+            a.position.partial_cmp(&b.position).expect("got NaN position")
+        });
+
+        let first = spline_points[0].position;
+        let last = spline_points[spline_points.len() - 1].position;
+        if first != 0.0 || last < parameters.cutoff {
+            return Err(Error::InvalidParameter(format!(
+                "the points in a tabulated radial integral must span [0, cutoff] \
+                (cutoff is {}), but they span [{}, {}]",
+                parameters.cutoff, first, last
+            )));
+        }
+
+        let expected_shape = [parameters.max_angular + 1, parameters.max_radial];
+        for point in &spline_points {
+            if point.values.shape() != expected_shape || point.derivatives.shape() != expected_shape {
+                return Err(Error::InvalidParameter(format!(
+                    "wrong shape for values ({:?}) or derivatives ({:?}) of the tabulated \
+                    radial integral at r={}, expected {:?}",
+                    point.values.shape(), point.derivatives.shape(), point.position, expected_shape
+                )));
+            }
+        }
 
         let spline_parameters = SplineParameters {
             start: 0.0,
@@ -84,7 +191,7 @@ impl SoapRadialIntegralSpline {
         }
 
         let spline = HermitCubicSpline::new(spline_parameters, new_spline_points);
-        return Ok(SoapRadialIntegralSpline{spline});
+        return Ok(SoapRadialIntegralSpline{spline, width_spline: None});
     }
 }
 
@@ -93,6 +200,14 @@ impl SoapRadialIntegral for SoapRadialIntegralSpline {
     fn compute(&self, x: f64, values: ArrayViewMut2<f64>, gradients: Option<ArrayViewMut2<f64>>) {
         self.spline.compute(x, values, gradients);
     }
+
+    fn compute_width_gradients(&self, x: f64, width_gradients: ArrayViewMut2<f64>) -> Result<(), Error> {
+        let width_spline = self.width_spline.as_ref().ok_or_else(|| Error::InvalidParameter(
+            "gradients with respect to the atomic gaussian width were not tabulated in this spline".into()
+        ))?;
+        width_spline.compute(x, width_gradients, None);
+        return Ok(());
+    }
 }
 
 #[cfg(test)]
@@ -123,6 +238,101 @@ mod tests {
         SoapRadialIntegralSpline::with_accuracy(parameters, 1e-10, gto).unwrap();
     }
 
+    #[test]
+    fn tabulated() {
+        use crate::calculators::radial_basis::JsonArray2;
+
+        let parameters = SoapRadialIntegralSplineParameters {
+            max_radial: 2,
+            max_angular: 1,
+            cutoff: 3.0,
+        };
+
+        // a linear function is interpolated exactly
+        let point = |position: f64| SplinePoint {
+            position: position,
+            values: JsonArray2(Array2::from_elem((2, 2), 2.0 * position)),
+            derivatives: JsonArray2(Array2::from_elem((2, 2), 2.0)),
+        };
+
+        let spline = SoapRadialIntegralSpline::from_tabulated(
+            parameters, vec![point(3.0), point(0.0), point(1.2)]
+        ).unwrap();
+
+        let mut values = Array2::from_elem((2, 2), 0.0);
+        let mut gradients = Array2::from_elem((2, 2), 0.0);
+        spline.compute(2.2, values.view_mut(), Some(gradients.view_mut()));
+        assert_relative_eq!(values, Array2::from_elem((2, 2), 4.4), max_relative=1e-12);
+        assert_relative_eq!(gradients, Array2::from_elem((2, 2), 2.0), max_relative=1e-12);
+
+        let error = SoapRadialIntegralSpline::from_tabulated(
+            parameters, vec![point(0.5), point(3.0)]
+        ).err().unwrap();
+        assert_eq!(error.to_string(),
+            "invalid parameter: the points in a tabulated radial integral must \
+            span [0, cutoff] (cutoff is 3), but they span [0.5, 3]"
+        );
+
+        let error = SoapRadialIntegralSpline::from_tabulated(
+            parameters, vec![point(0.0), point(2.5)]
+        ).err().unwrap();
+        assert_eq!(error.to_string(),
+            "invalid parameter: the points in a tabulated radial integral must \
+            span [0, cutoff] (cutoff is 3), but they span [0, 2.5]"
+        );
+
+        let error = SoapRadialIntegralSpline::from_tabulated(
+            parameters, vec![point(0.0)]
+        ).err().unwrap();
+        assert_eq!(error.to_string(),
+            "invalid parameter: we need at least two points for a tabulated radial integral, got 1"
+        );
+
+        let mut wrong_shape = point(3.0);
+        wrong_shape.values = JsonArray2(Array2::from_elem((3, 2), 0.0));
+        let error = SoapRadialIntegralSpline::from_tabulated(
+            parameters, vec![point(0.0), wrong_shape]
+        ).err().unwrap();
+        assert_eq!(error.to_string(),
+            "invalid parameter: wrong shape for values ([3, 2]) or derivatives ([2, 2]) \
+            of the tabulated radial integral at r=3, expected [2, 2]"
+        );
+    }
+
+    #[test]
+    fn width_gradients() {
+        let parameters = SoapRadialIntegralSplineParameters {
+            max_radial: 6,
+            max_angular: 6,
+            cutoff: 5.0,
+        };
+
+        let gto = SoapRadialIntegralGto::new(SoapRadialIntegralGtoParameters {
+            max_radial: parameters.max_radial,
+            max_angular: parameters.max_angular,
+            cutoff: parameters.cutoff,
+            atomic_gaussian_width: 0.5,
+        }).unwrap();
+
+        let shape = (parameters.max_angular + 1, parameters.max_radial);
+        let mut expected = Array2::from_elem(shape, 0.0);
+        let mut width_gradients = Array2::from_elem(shape, 0.0);
+
+        let mut spline = SoapRadialIntegralSpline::with_accuracy(parameters, 1e-8, gto.clone()).unwrap();
+        let error = spline.compute_width_gradients(1.3, width_gradients.view_mut()).unwrap_err();
+        assert_eq!(error.to_string(),
+            "invalid parameter: gradients with respect to the atomic gaussian width were not tabulated in this spline"
+        );
+
+        spline.tabulate_width_gradients(parameters, 1e-8, &gto).unwrap();
+
+        for rij in [0.0, 1.3, 3.4] {
+            gto.compute_width_gradients(rij, expected.view_mut()).unwrap();
+            spline.compute_width_gradients(rij, width_gradients.view_mut()).unwrap();
+            assert_relative_eq!(width_gradients, expected, epsilon=1e-6, max_relative=1e-5);
+        }
+    }
+
     #[test]
     fn finite_difference() {
         let max_radial = 8;
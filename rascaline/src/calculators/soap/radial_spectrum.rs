@@ -64,13 +64,21 @@ impl SoapRadialSpectrum {
     pub fn new(parameters: RadialSpectrumParameters) -> Result<SoapRadialSpectrum, Error> {
         let expansion_parameters = SphericalExpansionParameters {
             cutoff: parameters.cutoff,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
             max_radial: parameters.max_radial,
             max_angular: 0,
+            angular_channels: None,
             atomic_gaussian_width: parameters.atomic_gaussian_width,
             center_atom_weight: parameters.center_atom_weight,
             radial_basis: parameters.radial_basis.clone(),
-            cutoff_function: parameters.cutoff_function,
+            cutoff_function: parameters.cutoff_function.clone(),
             radial_scaling: parameters.radial_scaling,
+            species_occupations: Default::default(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
         };
 
         let spherical_expansion = SphericalExpansion::new(expansion_parameters)?;
@@ -129,6 +137,7 @@ impl CalculatorBase for SoapRadialSpectrum {
         let builder = CenterSingleNeighborsSpeciesKeys {
             cutoff: self.parameters.cutoff,
             self_pairs: true,
+            all_species: None,
         };
         return builder.keys(systems);
     }
@@ -150,6 +159,8 @@ impl CalculatorBase for SoapRadialSpectrum {
                 species_center: SpeciesFilter::Single(species_center.i32()),
                 species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                 self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             result.push(builder.samples(systems)?);
@@ -176,6 +187,8 @@ impl CalculatorBase for SoapRadialSpectrum {
                 species_center: SpeciesFilter::Single(species_center.i32()),
                 species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                 self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             gradient_samples.push(builder.gradients_for(systems, samples)?);
@@ -4,10 +4,10 @@ use ndarray::s;
 use rayon::prelude::*;
 
 use equistore::{LabelsBuilder, Labels, LabelValue, TensorBlockRefMut};
-use equistore::TensorMap;
+use equistore::{TensorMap, TensorBlock};
 
 use crate::{Error, System, Vector3D, Matrix3};
-use crate::systems::CellShape;
+use crate::systems::{CellShape, Pair};
 
 use crate::labels::{SamplesBuilder, SpeciesFilter, AtomCenteredSamples};
 use crate::labels::{KeysBuilder, CenterSingleNeighborsSpeciesKeys};
@@ -20,6 +20,11 @@ use super::spherical_expansion_pair::{GradientsOptions, PairContribution};
 use super::super::{split_tensor_map_by_system, array_mut_for_system};
 
 
+/// Number of pairs accumulated together in the same partial buffer when
+/// computing gradients with respect to the central atom position. This must
+/// not depend on the number of threads, to ensure reproducible results.
+const PAIRS_PER_CHUNK: usize = 256;
+
 /// The actual calculator used to compute SOAP spherical expansion coefficients
 #[derive(Debug)]
 pub struct SphericalExpansion {
@@ -27,6 +32,9 @@ pub struct SphericalExpansion {
     by_pair: SphericalExpansionByPair,
     /// Cache for (-1)^l values
     m_1_pow_l: Vec<f64>,
+    /// Velocities of the atoms in the systems for the current calculation,
+    /// used with velocity dependent weights
+    velocities: Option<Vec<Vec<Vector3D>>>,
 }
 
 impl SphericalExpansion {
@@ -39,9 +47,185 @@ impl SphericalExpansion {
         return Ok(SphericalExpansion {
             by_pair: SphericalExpansionByPair::new(parameters)?,
             m_1_pow_l,
+            velocities: None,
         });
     }
 
+    /// Compute separately the radial and angular parts of the spherical
+    /// expansion, for each pair of atoms in the `systems`.
+    ///
+    /// This returns two `TensorMap`, both with one block per
+    /// `spherical_harmonics_l` and the same samples: `structure`, `pair_id`,
+    /// `center` and `neighbor`. Each pair appears twice, once with each atom
+    /// as the center. The self contribution of the center is stored with a
+    /// `pair_id` of -1. The first `TensorMap` contains the radial part
+    /// `R_nl(r_ij)` (including the cutoff function, radial scaling and the
+    /// center atom weight), with `n` as properties. The second contains the
+    /// angular part `Y_lm(r_ij)`, with `spherical_harmonics_m` as component
+    /// and a single `_` property.
+    ///
+    /// The spherical expansion is recovered as
+    /// `<n l m | X_i> = \sum_j R_nl(r_ij) Y_lm(r_ij)`, summing over all
+    /// samples with the same center and with neighbors of the same species.
+    /// Species occupations are not applied to the separate outputs.
+    ///
+    /// The memory cost scales with the number of pairs instead of the number
+    /// of atoms: each pair (in both directions) stores `(max_angular + 1) *
+    /// (max_radial + max_angular + 1)` values. Since there are usually tens
+    /// of neighbors per atom, this can be significantly larger than the full
+    /// spherical expansion when `max_radial` is small.
+    ///
+    /// This function returns an error if the parameters of this calculator
+    /// include per-species cutoffs, an inner cutoff, species dependent weights
+    /// or velocity dependent weights, since the corresponding contributions
+    /// can not be split into radial and angular parts.
+    pub fn compute_radial_angular(&self, systems: &mut [Box<dyn System>]) -> Result<(TensorMap, TensorMap), Error> {
+        let parameters = self.by_pair.parameters();
+        let max_angular = parameters.max_angular;
+
+        if !parameters.cutoff_per_species.is_empty() {
+            return Err(Error::InvalidParameter(
+                "separate radial and angular parts are not available with per-species cutoffs".into()
+            ));
+        }
+
+        if parameters.inner_cutoff.is_some() {
+            return Err(Error::InvalidParameter(
+                "separate radial and angular parts are not available with an inner cutoff".into()
+            ));
+        }
+
+        if parameters.species_distance_weight.is_some() {
+            return Err(Error::InvalidParameter(
+                "separate radial and angular parts are not available with species dependent weights".into()
+            ));
+        }
+
+        if parameters.velocity_weight.is_some() {
+            return Err(Error::InvalidParameter(
+                "separate radial and angular parts are not available with velocity dependent weights".into()
+            ));
+        }
+
+        let mut samples = LabelsBuilder::new(vec!["structure", "pair_id", "center", "neighbor"]);
+        let mut radial = Vec::new();
+        let mut angular = Vec::new();
+
+        let (mut self_radial, self_angular) = self.by_pair.radial_angular_for_pair(0.0, Vector3D::new(0.0, 0.0, 1.0));
+        self_radial *= parameters.center_atom_weight;
+        // the self contribution is only non-zero for l=0
+        self_radial.slice_mut(s![1.., ..]).fill(0.0);
+
+        for (system_i, system) in systems.iter_mut().enumerate() {
+            system.compute_neighbors(parameters.cutoff)?;
+
+            for center_i in 0..system.size()? {
+                samples.add(&[system_i.into(), LabelValue::new(-1), center_i.into(), center_i.into()]);
+                radial.push(self_radial.clone());
+                angular.push(self_angular.clone());
+            }
+
+            for (pair_id, pair) in system.pairs()?.iter().enumerate() {
+                let (pair_radial, mut pair_angular) = self.by_pair.radial_angular_for_pair(
+                    pair.distance, pair.vector / pair.distance
+                );
+
+                samples.add(&[system_i, pair_id, pair.first, pair.second]);
+                radial.push(pair_radial.clone());
+                angular.push(pair_angular.clone());
+
+                if pair.first == pair.second {
+                    // see `accumulate_all_pairs`
+                    continue;
+                }
+
+                // Y_lm(-r) = (-1)^l Y_lm(r)
+                for (spherical_harmonics_l, values) in pair_angular.iter_mut().enumerate() {
+                    *values *= self.m_1_pow_l[spherical_harmonics_l];
+                }
+
+                samples.add(&[system_i, pair_id, pair.second, pair.first]);
+                radial.push(pair_radial);
+                angular.push(pair_angular);
+            }
+        }
+        let samples = samples.finish();
+
+        let mut keys = LabelsBuilder::new(vec!["spherical_harmonics_l"]);
+        let mut radial_blocks = Vec::new();
+        let mut angular_blocks = Vec::new();
+
+        let mut radial_properties = LabelsBuilder::new(vec!["n"]);
+        for n in 0..parameters.max_radial {
+            radial_properties.add(&[n]);
+        }
+        let radial_properties = radial_properties.finish();
+
+        for spherical_harmonics_l in 0..=max_angular {
+            keys.add(&[spherical_harmonics_l]);
+
+            let mut values = ndarray::Array2::from_elem((samples.count(), parameters.max_radial), 0.0);
+            for (sample_i, sample_radial) in radial.iter().enumerate() {
+                values.row_mut(sample_i).assign(&sample_radial.row(spherical_harmonics_l));
+            }
+            radial_blocks.push(TensorBlock::new(
+                values.into_dyn(),
+                &samples,
+                &[],
+                &radial_properties,
+            )?);
+
+            let mut component = LabelsBuilder::new(vec!["spherical_harmonics_m"]);
+            for m in -(spherical_harmonics_l as i32)..=(spherical_harmonics_l as i32) {
+                component.add(&[LabelValue::new(m)]);
+            }
+
+            let mut values = ndarray::Array3::from_elem((samples.count(), 2 * spherical_harmonics_l + 1, 1), 0.0);
+            for (sample_i, sample_angular) in angular.iter().enumerate() {
+                values.slice_mut(s![sample_i, .., 0]).assign(&sample_angular[spherical_harmonics_l]);
+            }
+            angular_blocks.push(TensorBlock::new(
+                values.into_dyn(),
+                &samples,
+                &[component.finish()],
+                &Labels::new(["_"], &[[0]]),
+            )?);
+        }
+        let keys = keys.finish();
+
+        return Ok((
+            TensorMap::new(keys.clone(), radial_blocks)?,
+            TensorMap::new(keys, angular_blocks)?,
+        ));
+    }
+
+    /// Compute only the self contribution to the spherical expansion, i.e. the
+    /// density of each center atom around itself, scaled by
+    /// `center_atom_weight`. Neighbors do not contribute to the result.
+    ///
+    /// The `descriptor` must have been created by this calculator for the
+    /// same `systems`, its values are overwritten with the self contribution.
+    /// The self contribution does not depend on the atomic positions or the
+    /// cell, so all gradients are set to zero.
+    ///
+    /// Subtracting this from the full spherical expansion gives the spherical
+    /// expansion computed with `center_atom_weight = 0`.
+    pub fn compute_self_only(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["spherical_harmonics_l", "species_center", "species_neighbor"]);
+
+        for (_, mut block) in descriptor.iter_mut() {
+            block.data_mut().values.to_array_mut().fill(0.0);
+
+            for parameter in ["positions", "cell"] {
+                if let Some(mut gradient) = block.gradient_mut(parameter) {
+                    gradient.data_mut().values.to_array_mut().fill(0.0);
+                }
+            }
+        }
+
+        return self.do_self_contributions(systems, descriptor);
+    }
+
     /// Accumulate the self contribution to the spherical expansion
     /// coefficients, i.e. the contribution arising from the density of the
     /// center atom around itself.
@@ -55,11 +239,18 @@ impl SphericalExpansion {
             let species_center = key[1];
             let species_neighbor = key[2];
 
-            if spherical_harmonics_l != 0 || species_center != species_neighbor {
+            if spherical_harmonics_l != 0 {
                 // center contribution is non-zero only for l=0
                 continue;
             }
 
+            let occupation = self.by_pair.parameters().occupation(species_center.i32(), species_neighbor.i32());
+            if occupation == 0.0 {
+                // the center does not contribute to the density of this
+                // neighbor species
+                continue;
+            }
+
             let block = block.data_mut();
             let array = block.values.to_array_mut();
 
@@ -83,7 +274,7 @@ impl SphericalExpansion {
                 }
 
                 for (property_i, &[n]) in block.properties.iter_fixed_size().enumerate() {
-                    array[[sample_i, 0, property_i]] += self_contribution.values[[0, n.usize()]];
+                    array[[sample_i, 0, property_i]] += occupation * self_contribution.values[[0, n.usize()]];
                 }
             }
         }
@@ -93,21 +284,28 @@ impl SphericalExpansion {
 
     /// For one system, compute the spherical expansion and corresponding
     /// gradients by summing over the pairs.
+    ///
+    /// The gradients with respect to positions are only computed for pairs
+    /// containing at least one of the `gradient_atoms`, other pairs only
+    /// contribute to the values (and cell gradients).
     #[allow(clippy::too_many_lines)]
     fn accumulate_all_pairs(
         &self,
         system: &dyn System,
+        velocities: Option<&[Vector3D]>,
         do_gradients: GradientsOptions,
         requested_centers: &BTreeSet<usize>,
+        gradient_atoms: &BTreeSet<usize>,
     ) -> Result<PairAccumulationResult, Error> {
         // pre-filter pairs to only include the ones containing at least one of
         // the requested atoms
         let pairs = system.pairs()?;
 
-        let pair_should_contribute = |pair: &&crate::systems::Pair| {
+        let pair_should_contribute = |pair: &&Pair| {
             requested_centers.contains(&pair.first) || requested_centers.contains(&pair.second)
         };
-        let pairs_count = pairs.iter().filter(pair_should_contribute).count();
+        let pairs = pairs.iter().filter(pair_should_contribute).collect::<Vec<_>>();
+        let pairs_count = pairs.len();
 
         let system_size = system.size()?;
         let species = system.species()?;
@@ -137,7 +335,23 @@ impl SphericalExpansion {
 
         let max_angular = self.by_pair.parameters().max_angular;
         let max_radial = self.by_pair.parameters().max_radial;
-        let mut contribution = PairContribution::new(max_radial, max_angular, do_gradients.either());
+        let mut gradients_contribution = PairContribution::new(max_radial, max_angular, do_gradients.either());
+        // pairs without any of the gradient atoms only need the values
+        let mut values_contribution = PairContribution::new(max_radial, max_angular, false);
+
+        // with a species dependent weight, the contribution of the j-i pair
+        // can not be obtained from the weighted contribution of the i-j pair,
+        // so we keep a copy of the unweighted one around.
+        let species_weighted = self.by_pair.parameters().species_distance_weight.is_some();
+        let mut gradients_unweighted = PairContribution::new(max_radial, max_angular, do_gradients.either());
+        let mut values_unweighted = PairContribution::new(max_radial, max_angular, false);
+
+        // with per-species cutoffs, the i-j and j-i pairs can have different
+        // cutoffs, and the contribution of the j-i pair is computed separately
+        let per_species_cutoff = !self.by_pair.parameters().cutoff_per_species.is_empty();
+        let reversed_separately = species_weighted || per_species_cutoff;
+
+        let velocity_weight = self.by_pair.parameters().velocity_weight;
 
         // total number of joined (l, m) indices
         let lm_shape = (max_angular + 1) * (max_angular + 1);
@@ -152,6 +366,12 @@ impl SphericalExpansion {
             } else {
                 None
             },
+            positions_gradients_by_pair_reversed: if do_gradients.positions && reversed_separately {
+                let shape = (pairs_count, 3, lm_shape, max_radial);
+                Some(ndarray::Array4::from_elem(shape, 0.0))
+            } else {
+                None
+            },
             positions_gradients_self: if do_gradients.positions {
                 let shape = (species_mapping.len(), requested_centers.len(), 3, lm_shape, max_radial);
                 Some(ndarray::Array5::from_elem(shape, 0.0))
@@ -169,13 +389,44 @@ impl SphericalExpansion {
             species_mapping,
             centers_mapping,
             pair_to_pair_ids: HashMap::new(),
+            pairs_first: pairs.iter().map(|pair| pair.first).collect(),
         };
 
-        for (pair_id, pair) in pairs.iter().filter(pair_should_contribute).enumerate() {
+        for (pair_id, pair) in pairs.iter().enumerate() {
             debug_assert!(requested_centers.contains(&pair.first) || requested_centers.contains(&pair.second));
 
+            let pair_gradients = GradientsOptions {
+                positions: do_gradients.positions && (gradient_atoms.contains(&pair.first) || gradient_atoms.contains(&pair.second)),
+                cell: do_gradients.cell,
+            };
+
+            let (mut contribution, mut unweighted) = if pair_gradients.either() {
+                (&mut gradients_contribution, &mut gradients_unweighted)
+            } else {
+                (&mut values_contribution, &mut values_unweighted)
+            };
+
             let direction = pair.vector / pair.distance;
-            self.by_pair.compute_for_pair(pair.distance, direction, do_gradients, &mut contribution);
+            let cutoff = self.by_pair.parameters().pair_cutoff(species[pair.first], species[pair.second]);
+            self.by_pair.compute_for_pair_with_cutoff(pair.distance, direction, cutoff, pair_gradients, contribution);
+
+            // the relative velocity along the bond is the same for the i-j and
+            // j-i pairs, so this weight also applies to the reversed pair
+            let velocity_factor = if let (Some(velocity_weight), Some(velocities)) = (velocity_weight, velocities) {
+                let relative_velocity = (velocities[pair.second] - velocities[pair.first]) * direction;
+                Some(velocity_weight.compute(relative_velocity))
+            } else {
+                None
+            };
+
+            if let Some(velocity_factor) = velocity_factor {
+                contribution.values *= velocity_factor;
+            }
+
+            if species_weighted {
+                unweighted.assign(contribution);
+                self.by_pair.apply_species_weight(contribution, species[pair.second], pair.distance, direction);
+            }
 
             let inverse_cell_pair_vector = Vector3D::new(
                 pair.vector[0] * inverse_cell[0][0] + pair.vector[1] * inverse_cell[1][0] + pair.vector[2] * inverse_cell[2][0],
@@ -183,6 +434,14 @@ impl SphericalExpansion {
                 pair.vector[0] * inverse_cell[0][2] + pair.vector[1] * inverse_cell[1][2] + pair.vector[2] * inverse_cell[2][2],
             );
 
+            // the gradients of this pair are also used for the self gradients
+            // of the second atom, so we need to store them even if the first
+            // atom is not one of the requested centers
+            if let (Some(contribution_gradients), Some(positions_gradients)) = (&contribution.gradients, &mut result.positions_gradients_by_pair) {
+                let gradients = &mut positions_gradients.slice_mut(s![pair_id, .., .., ..]);
+                gradients.assign(contribution_gradients);
+            }
+
             if let Some(mapped_center) = result.centers_mapping[pair.first] {
                 // add the pair contribution to the atomic environnement
                 // corresponding to the **first** atom in the pair
@@ -198,16 +457,6 @@ impl SphericalExpansion {
 
 
                 if let Some(ref contribution_gradients) = contribution.gradients {
-                    if let Some(ref mut positions_gradients) = result.positions_gradients_by_pair {
-                        let gradients = &mut positions_gradients.slice_mut(s![pair_id, .., .., ..]);
-                        gradients.assign(contribution_gradients);
-                    }
-
-                    if let Some(ref mut positions_gradients) = result.positions_gradients_self {
-                        let mut gradients = positions_gradients.slice_mut(s![species_neighbor_i, mapped_center, .., .., ..]);
-                        gradients -= contribution_gradients;
-                    }
-
                     if let Some(ref mut cell_gradients) = result.cell_gradients {
                         let mut cell_gradients = cell_gradients.slice_mut(
                             s![species_neighbor_i, mapped_center, .., .., .., ..]
@@ -257,7 +506,20 @@ impl SphericalExpansion {
                     .push(pair_id);
 
 
-                contribution.inverse_pair(&self.m_1_pow_l);
+                if per_species_cutoff {
+                    let cutoff = self.by_pair.parameters().pair_cutoff(species[pair.second], species[pair.first]);
+                    self.by_pair.compute_for_pair_with_cutoff(pair.distance, -direction, cutoff, pair_gradients, contribution);
+                    if let Some(velocity_factor) = velocity_factor {
+                        contribution.values *= velocity_factor;
+                    }
+                    self.by_pair.apply_species_weight(contribution, species[pair.first], pair.distance, -direction);
+                } else if species_weighted {
+                    std::mem::swap(&mut contribution, &mut unweighted);
+                    contribution.inverse_pair(&self.m_1_pow_l);
+                    self.by_pair.apply_species_weight(contribution, species[pair.first], pair.distance, -direction);
+                } else {
+                    contribution.inverse_pair(&self.m_1_pow_l);
+                }
 
                 let species_neighbor_i = result.species_mapping[&species[neighbor_i]];
 
@@ -266,11 +528,11 @@ impl SphericalExpansion {
 
                 if let Some(ref contribution_gradients) = contribution.gradients {
                     // we don't add second->first pair to positions_gradient_by_pair,
-                    // instead handling this in position_gradients_to_equistore
-
-                    if let Some(ref mut positions_gradients) = result.positions_gradients_self {
-                        let mut gradients = positions_gradients.slice_mut(s![species_neighbor_i, mapped_center, .., .., ..]);
-                        gradients -= contribution_gradients;
+                    // instead handling this in position_gradients_to_equistore.
+                    // This is only possible without species dependent weight.
+                    if let Some(ref mut positions_gradients) = result.positions_gradients_by_pair_reversed {
+                        let gradients = &mut positions_gradients.slice_mut(s![pair_id, .., .., ..]);
+                        gradients.assign(contribution_gradients);
                     }
 
                     if let Some(ref mut cell_gradients) = result.cell_gradients {
@@ -304,12 +566,101 @@ impl SphericalExpansion {
             }
         }
 
+        if do_gradients.positions {
+            self.accumulate_self_gradients(&pairs, species, &mut result);
+        }
+
         return Ok(result);
     }
 
+    /// Accumulate the gradients of the spherical expansion with respect to the
+    /// position of the central atom, from the gradients of each pair stored
+    /// in `result.positions_gradients_by_pair`.
+    ///
+    /// Pairs are split in chunks of fixed size, each chunk is accumulated in
+    /// a separate partial buffer (potentially on different threads), and the
+    /// partial buffers are then summed in the order of the chunks. Since
+    /// neither the chunks nor the summation order depend on the number of
+    /// threads, the results are bit-for-bit reproducible.
+    fn accumulate_self_gradients(&self, pairs: &[&Pair], species: &[i32], result: &mut PairAccumulationResult) {
+        let max_angular = self.by_pair.parameters().max_angular;
+
+        let by_pair = result.positions_gradients_by_pair.as_ref().expect("missing gradients by pair");
+        let by_pair_reversed = result.positions_gradients_by_pair_reversed.as_ref();
+        let species_mapping = &result.species_mapping;
+        let centers_mapping = &result.centers_mapping;
+
+        let partials = pairs.par_chunks(PAIRS_PER_CHUNK)
+            .enumerate()
+            .map(|(chunk_i, chunk)| {
+                let mut partial = BTreeMap::new();
+                for (i, pair) in chunk.iter().enumerate() {
+                    let pair_id = chunk_i * PAIRS_PER_CHUNK + i;
+                    let gradients = by_pair.slice(s![pair_id, .., .., ..]);
+
+                    if let Some(mapped_center) = centers_mapping[pair.first] {
+                        let species_neighbor_i = species_mapping[&species[pair.second]];
+                        let output = partial.entry((species_neighbor_i, mapped_center))
+                            .or_insert_with(|| ndarray::Array3::from_elem(gradients.raw_dim(), 0.0));
+                        *output -= &gradients;
+                    }
+
+                    if pair.first == pair.second {
+                        // see `accumulate_all_pairs`
+                        continue;
+                    }
+
+                    if let Some(mapped_center) = centers_mapping[pair.second] {
+                        let species_neighbor_i = species_mapping[&species[pair.first]];
+                        let output = partial.entry((species_neighbor_i, mapped_center))
+                            .or_insert_with(|| ndarray::Array3::from_elem(gradients.raw_dim(), 0.0));
+
+                        if let Some(by_pair_reversed) = by_pair_reversed {
+                            *output -= &by_pair_reversed.slice(s![pair_id, .., .., ..]);
+                        } else {
+                            // the gradients of the inverted pair get a -(-1)^l
+                            // factor (see `PairContribution::inverse_pair`),
+                            // and we are subtracting them
+                            let mut lm_index = 0;
+                            for spherical_harmonics_l in 0..=max_angular {
+                                let factor = self.m_1_pow_l[spherical_harmonics_l];
+                                for _m in 0..(2 * spherical_harmonics_l + 1) {
+                                    for spatial in 0..3 {
+                                        let mut output = output.slice_mut(s![spatial, lm_index, ..]);
+                                        output.scaled_add(factor, &gradients.slice(s![spatial, lm_index, ..]));
+                                    }
+                                    lm_index += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+                return partial;
+            })
+            .collect::<Vec<_>>();
+
+        let positions_gradients = result.positions_gradients_self.as_mut().expect("missing self gradients");
+        for partial in partials {
+            for ((species_neighbor_i, mapped_center), gradients) in partial {
+                let mut output = positions_gradients.slice_mut(s![species_neighbor_i, mapped_center, .., .., ..]);
+                output += &gradients;
+            }
+        }
+    }
+
+    /// Get the species contributing to the density of the `channel` neighbor
+    /// species, as indexes in the first dimension of the arrays in `result`,
+    /// together with the corresponding occupation.
+    fn channel_contributions(&self, channel: i32, result: &PairAccumulationResult) -> Vec<(usize, f64)> {
+        let parameters = self.by_pair.parameters();
+        return result.species_mapping.iter()
+            .map(|(&species, &species_i)| (species_i, parameters.occupation(species, channel)))
+            .filter(|&(_, occupation)| occupation != 0.0)
+            .collect();
+    }
+
     /// Move the pre-computed spherical expansion data to a single equistore
     /// block
-    #[allow(clippy::unused_self)]
     fn values_to_equistore(
         &self,
         key: &[LabelValue],
@@ -325,13 +676,12 @@ impl SphericalExpansion {
         let species_neighbor = key[2];
 
         let lm_start = spherical_harmonics_l * spherical_harmonics_l;
-        let species_neighbor_i = if let Some(s) = result.species_mapping.get(&species_neighbor.i32()) {
-            *s
-        } else {
+        let neighbor_contributions = self.channel_contributions(species_neighbor.i32(), result);
+        if neighbor_contributions.is_empty() {
             // this block does not correspond to actual species in the current
             // system
             return Ok(());
-        };
+        }
 
         let block = block.data_mut();
         let mut array = array_mut_for_system(block.values);
@@ -352,7 +702,9 @@ impl SphericalExpansion {
                     // mode.
                     unsafe {
                         let out = array.uget_mut([sample_i, m, property_i]);
-                        *out += *result.values.uget([species_neighbor_i, mapped_center, lm_start + m, n.usize()]);
+                        for &(species_neighbor_i, occupation) in &neighbor_contributions {
+                            *out += occupation * *result.values.uget([species_neighbor_i, mapped_center, lm_start + m, n.usize()]);
+                        }
                     }
                 }
             }
@@ -389,20 +741,19 @@ impl SphericalExpansion {
         };
 
         let positions_gradients_self = result.positions_gradients_self.as_ref().expect("missing self gradients");
+        let positions_gradients_by_pair_reversed = result.positions_gradients_by_pair_reversed.as_ref();
 
         let spherical_harmonics_l = key[0].usize();
         let species_center = key[1];
         let species_neighbor = key[2];
-        let species_neighbor_i = if let Some(s) = result.species_mapping.get(&species_neighbor.i32()) {
-            *s
-        } else {
+        let neighbor_contributions = self.channel_contributions(species_neighbor.i32(), result);
+        if neighbor_contributions.is_empty() {
             // this block does not correspond to actual species in the current
             // system
             return Ok(());
-        };
+        }
 
         let species = system.species()?;
-        let pairs = system.pairs()?;
         let system_size = system.size()?;
 
         let lm_start = spherical_harmonics_l * spherical_harmonics_l;
@@ -436,9 +787,11 @@ impl SphericalExpansion {
                             // SAFETY: same as above
                             unsafe {
                                 let out = array.uget_mut([grad_sample_i, spatial, m, property_i]);
-                                *out = *positions_gradients_self.uget(
-                                    [species_neighbor_i, mapped_center, spatial, lm_start + m, n.usize()]
-                                );
+                                for &(species_neighbor_i, occupation) in &neighbor_contributions {
+                                    *out += occupation * *positions_gradients_self.uget(
+                                        [species_neighbor_i, mapped_center, spatial, lm_start + m, n.usize()]
+                                    );
+                                }
                             }
                         }
                     }
@@ -446,17 +799,19 @@ impl SphericalExpansion {
             } else {
                 // gradient w.r.t. the position of a neighboring atom
                 let neighbor_i = neighbor_i.usize();
-                debug_assert!(species[neighbor_i] == species_neighbor);
+                let occupation = self.by_pair.parameters().occupation(species[neighbor_i], species_neighbor.i32());
+                debug_assert!(occupation != 0.0);
 
                 for &pair_id in &result.pair_to_pair_ids[&(center_i.usize(), neighbor_i)] {
-                    let pair = pairs[pair_id];
-                    let factor = if pair.first == center_i.usize() {
-                        debug_assert_eq!(pair.second, neighbor_i);
-                        1.0
+                    let (factor, gradients_by_pair) = if result.pairs_first[pair_id] == center_i.usize() {
+                        (occupation, positions_gradients_by_pair)
                     } else {
-                        debug_assert!(pair.second == center_i.usize());
-                        debug_assert_eq!(pair.first, neighbor_i);
-                        -m_1_pow_l
+                        debug_assert_eq!(result.pairs_first[pair_id], neighbor_i);
+                        if let Some(reversed) = positions_gradients_by_pair_reversed {
+                            (occupation, reversed)
+                        } else {
+                            (-m_1_pow_l * occupation, positions_gradients_by_pair)
+                        }
                     };
 
                     for spatial in 0..3 {
@@ -465,7 +820,7 @@ impl SphericalExpansion {
                                 // SAFETY: same as above
                                 unsafe {
                                     let out = array.uget_mut([grad_sample_i, spatial, m, property_i]);
-                                    *out += factor * *positions_gradients_by_pair.uget([pair_id, spatial, lm_start + m, n.usize()]);
+                                    *out += factor * *gradients_by_pair.uget([pair_id, spatial, lm_start + m, n.usize()]);
                                 }
                             }
                         }
@@ -478,10 +833,11 @@ impl SphericalExpansion {
     }
 
     /// Move the pre-computed spherical expansion gradients w.r.t. cell to
-    /// a single equistore block
-    #[allow(clippy::unused_self)]
+    /// a single equistore block, for the gradients w.r.t. `parameter` (either
+    /// `"cell"` or `"strain"`)
     fn cell_gradients_to_equistore(
         &self,
+        parameter: &str,
         key: &[LabelValue],
         block: &mut TensorBlockRefMut,
         system: &dyn System,
@@ -502,19 +858,29 @@ impl SphericalExpansion {
         let species_neighbor = key[2];
 
         let lm_start = spherical_harmonics_l * spherical_harmonics_l;
-        let species_neighbor_i = if let Some(s) = result.species_mapping.get(&species_neighbor.i32()) {
-            *s
-        } else {
+        let neighbor_contributions = self.channel_contributions(species_neighbor.i32(), result);
+        if neighbor_contributions.is_empty() {
             // this block does not correspond to actual species in the current
             // system
             return Ok(());
-        };
+        }
 
         let values_samples = block.samples();
-        let mut gradient = block.gradient_mut("cell").expect("missing cell gradients");
+        let mut gradient = match block.gradient_mut(parameter) {
+            Some(gradient) => gradient,
+            None => return Ok(()),
+        };
         let gradient = gradient.data_mut();
         let mut array = array_mut_for_system(gradient.values);
 
+        // gradients w.r.t. strain are obtained from the gradients w.r.t. cell
+        // as `dX/dε = dX/dh · h`
+        let transformation = if parameter == "strain" {
+            system.cell()?.matrix()
+        } else {
+            Matrix3::one()
+        };
+
         for (grad_sample_i, [sample_i]) in gradient.samples.iter_fixed_size().enumerate() {
             let center_i = values_samples[sample_i.usize()][1];
 
@@ -531,7 +897,12 @@ impl SphericalExpansion {
                             // SAFETY: same as above
                             unsafe {
                                 let out = array.uget_mut([grad_sample_i, spatial_1, spatial_2, m, property_i]);
-                                *out += *contributions.uget([species_neighbor_i, mapped_center, spatial_1, spatial_2, lm_start + m, n.usize()]);
+                                for &(species_neighbor_i, occupation) in &neighbor_contributions {
+                                    for spatial_3 in 0..3 {
+                                        let factor = transformation[spatial_3][spatial_2];
+                                        *out += occupation * factor * *contributions.uget([species_neighbor_i, mapped_center, spatial_1, spatial_3, lm_start + m, n.usize()]);
+                                    }
+                                }
                             }
                         }
                     }
@@ -543,6 +914,120 @@ impl SphericalExpansion {
     }
 }
 
+impl SphericalExpansion {
+    /// For one system, compute the gradients of the spherical expansion with
+    /// respect to the atomic gaussian width by summing over the pairs. The
+    /// result has the same shape as `PairAccumulationResult::values`, and
+    /// does not include the self contribution.
+    fn accumulate_width_gradients(&self, system: &dyn System, result: &PairAccumulationResult) -> Result<ndarray::Array4<f64>, Error> {
+        let species = system.species()?;
+        let parameters = self.by_pair.parameters();
+
+        let max_angular = parameters.max_angular;
+        let max_radial = parameters.max_radial;
+        let lm_shape = (max_angular + 1) * (max_angular + 1);
+
+        let mut width_gradients = ndarray::Array4::from_elem(result.values.raw_dim(), 0.0);
+        let mut pair_width_gradients = ndarray::Array2::from_elem((lm_shape, max_radial), 0.0);
+
+        for pair in system.pairs()? {
+            let direction = pair.vector / pair.distance;
+            for (reversed, center_i, neighbor_i) in [(false, pair.first, pair.second), (true, pair.second, pair.first)] {
+                if reversed && pair.first == pair.second {
+                    // pairs between an atom and its image only contribute once
+                    continue;
+                }
+                let direction = if reversed { -direction } else { direction };
+
+                let mapped_center = match result.centers_mapping[center_i] {
+                    Some(mapped_center) => mapped_center,
+                    None => continue,
+                };
+
+                let cutoff = parameters.pair_cutoff(species[center_i], species[neighbor_i]);
+                self.by_pair.width_gradients_for_pair(
+                    pair.distance, direction, cutoff, species[neighbor_i], &mut pair_width_gradients
+                )?;
+
+                let species_neighbor_i = result.species_mapping[&species[neighbor_i]];
+                let mut output = width_gradients.slice_mut(s![species_neighbor_i, mapped_center, .., ..]);
+                output += &pair_width_gradients;
+            }
+        }
+
+        return Ok(width_gradients);
+    }
+
+    /// Move the pre-computed gradients w.r.t. the atomic gaussian width to a
+    /// single equistore block, adding the self contribution for `L=0`.
+    fn width_gradients_to_equistore(
+        &self,
+        key: &[LabelValue],
+        block: &mut TensorBlockRefMut,
+        system: &dyn System,
+        result: &PairAccumulationResult,
+        width_gradients: &ndarray::Array4<f64>,
+        self_width_gradients: &ndarray::Array1<f64>,
+    ) -> Result<(), Error> {
+        let species = system.species()?;
+        let system_size = system.size()?;
+
+        let spherical_harmonics_l = key[0].usize();
+        let species_center = key[1];
+        let species_neighbor = key[2];
+
+        let lm_start = spherical_harmonics_l * spherical_harmonics_l;
+        let neighbor_contributions = self.channel_contributions(species_neighbor.i32(), result);
+        if neighbor_contributions.is_empty() {
+            // this block does not correspond to actual species in the current
+            // system
+            return Ok(());
+        }
+
+        let self_occupation = if spherical_harmonics_l == 0 {
+            self.by_pair.parameters().occupation(species_center.i32(), species_neighbor.i32())
+        } else {
+            0.0
+        };
+
+        let values_samples = block.samples();
+        let mut gradient = match block.gradient_mut("atomic_gaussian_width") {
+            Some(gradient) => gradient,
+            None => return Ok(()),
+        };
+        let gradient = gradient.data_mut();
+        let mut array = array_mut_for_system(gradient.values);
+
+        for (grad_sample_i, [sample_i]) in gradient.samples.iter_fixed_size().enumerate() {
+            let center_i = values_samples[sample_i.usize()][1];
+
+            // gradient samples should NOT contain entries for atoms that should
+            // not be part of this block, since they are not manually specified
+            // by the users
+            debug_assert!(center_i.usize() < system_size && species[center_i.usize()] == species_center);
+            let mapped_center = result.centers_mapping[center_i.usize()].expect("this center should be part of the mapping");
+
+            for m in 0..(2 * spherical_harmonics_l + 1) {
+                for (property_i, [n]) in gradient.properties.iter_fixed_size().enumerate() {
+                    // SAFETY: we are doing in-bounds access, and removing the
+                    // bounds checks is a significant speed-up for this code.
+                    // There is also a bounds check when running tests in debug
+                    // mode.
+                    unsafe {
+                        let out = array.uget_mut([grad_sample_i, m, property_i]);
+                        for &(species_neighbor_i, occupation) in &neighbor_contributions {
+                            *out += occupation * *width_gradients.uget([species_neighbor_i, mapped_center, lm_start + m, n.usize()]);
+                        }
+                        *out += self_occupation * *self_width_gradients.uget(n.usize());
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
 /// Result of `accumulate_all_pairs`, summing over all pairs in a system
 struct PairAccumulationResult {
     /// values of the spherical expansion
@@ -555,10 +1040,18 @@ struct PairAccumulationResult {
     ///
     /// the shape is [pair_id, spatial, lm_index, n]
     positions_gradients_by_pair: Option<ndarray::Array4<f64>>,
+    /// gradients w.r.t. positions associated with the reversed (second ->
+    /// first) direction of each pair. This is only used with a species
+    /// dependent weight, otherwise these gradients are obtained from
+    /// `positions_gradients_by_pair`.
+    ///
+    /// the shape is [pair_id, spatial, lm_index, n]
+    positions_gradients_by_pair_reversed: Option<ndarray::Array4<f64>>,
     /// gradient of spherical expansion w.r.t. the position of the central atom
     ///
-    /// this is separate from `positions_gradients_by_pair` because it can be
-    /// summed while computing each pair contributions.
+    /// this is separate from `positions_gradients_by_pair` because it sums
+    /// the contributions of all pairs around a given center (see
+    /// `accumulate_self_gradients`).
     ///
     /// the shape is [species_neighbor, mapped_center, spatial, lm_index, n]
     positions_gradients_self: Option<ndarray::Array5<f64>>,
@@ -577,58 +1070,263 @@ struct PairAccumulationResult {
     /// Two atoms can have more than one pair between them, so we need to be
     /// able store more than one pair id.
     pair_to_pair_ids: HashMap<(usize, usize), Vec<usize>>,
+    /// Index of the first atom of each pair used in the calculation, indexed
+    /// by pair_id. Pairs are filtered to only include the ones containing one
+    /// of the requested centers, so pair_id does not index the full list of
+    /// pairs of the system.
+    pairs_first: Vec<usize>,
 }
 
-impl CalculatorBase for SphericalExpansion {
-    fn name(&self) -> String {
-        "spherical expansion".into()
-    }
-
-    fn parameters(&self) -> String {
-        serde_json::to_string(self.by_pair.parameters()).expect("failed to serialize to JSON")
-    }
+/// Quadrature grid used to compute the spherical expansion by numerical
+/// integration, with the radial basis and spherical harmonics evaluated on all
+/// the grid points.
+#[cfg(any(test, feature = "reference"))]
+struct NumericalGrid {
+    /// positions of the grid points
+    points: Vec<Vector3D>,
+    /// quadrature weight associated with each grid point
+    weights: Vec<f64>,
+    /// radial basis functions evaluated on the grid, with shape (point, n)
+    radial: ndarray::Array2<f64>,
+    /// spherical harmonics evaluated on the grid, with shape (point, lm) where
+    /// the `lm` index is `l^2 + l + m`
+    spherical_harmonics: ndarray::Array2<f64>,
+}
 
-    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
-        let builder = CenterSingleNeighborsSpeciesKeys {
-            cutoff: self.by_pair.parameters().cutoff,
-            self_pairs: true,
+#[cfg(any(test, feature = "reference"))]
+impl NumericalGrid {
+    const N_RADIAL: usize = 64;
+    const N_THETA: usize = 32;
+    const N_PHI: usize = 64;
+
+    /// Create a grid extending up to `max_radius`, for a GTO radial basis
+    fn new(parameters: &SphericalExpansionParameters, max_radius: f64) -> NumericalGrid {
+        let basis = crate::calculators::radial_basis::GtoRadialBasis {
+            max_radial: parameters.max_radial,
+            cutoff: parameters.cutoff,
         };
-        let keys = builder.keys(systems)?;
+        let gaussian_widths = basis.gaussian_widths();
+        let orthonormalization = basis.orthonormalization_matrix();
+
+        let (radial_nodes, radial_weights) = crate::math::gauss_legendre(Self::N_RADIAL);
+        let (theta_nodes, theta_weights) = crate::math::gauss_legendre(Self::N_THETA);
+        let phi_weight = 2.0 * std::f64::consts::PI / Self::N_PHI as f64;
+
+        let n_points = Self::N_RADIAL * Self::N_THETA * Self::N_PHI;
+        let n_lm = (parameters.max_angular + 1) * (parameters.max_angular + 1);
+        let mut points = Vec::with_capacity(n_points);
+        let mut weights = Vec::with_capacity(n_points);
+        let mut radial = ndarray::Array2::zeros((n_points, parameters.max_radial));
+        let mut spherical_harmonics = ndarray::Array2::zeros((n_points, n_lm));
+
+        let mut sph_cache = crate::math::SphericalHarmonicsCache::new(parameters.max_angular);
+        for (&t, &radial_weight) in radial_nodes.iter().zip(&radial_weights) {
+            // map the Gauss-Legendre nodes from [-1, 1] to [0, max_radius]
+            let r = 0.5 * max_radius * (t + 1.0);
+            let radial_weight = 0.5 * max_radius * radial_weight * r * r;
+
+            let mut radial_values = vec![0.0; parameters.max_radial];
+            for (n, value) in radial_values.iter_mut().enumerate() {
+                for (k, &sigma) in gaussian_widths.iter().enumerate() {
+                    let gto = r.powi(k as i32) * f64::exp(-r * r / (2.0 * sigma * sigma));
+                    *value += orthonormalization[[n, k]] * gto;
+                }
+            }
 
-        let mut builder = LabelsBuilder::new(vec!["spherical_harmonics_l", "species_center", "species_neighbor"]);
-        for &[species_center, species_neighbor] in keys.iter_fixed_size() {
-            for spherical_harmonics_l in 0..=self.by_pair.parameters().max_angular {
-                builder.add(&[spherical_harmonics_l.into(), species_center, species_neighbor]);
+            for (&cos_theta, &theta_weight) in theta_nodes.iter().zip(&theta_weights) {
+                let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
+                for phi_i in 0..Self::N_PHI {
+                    let phi = phi_i as f64 * phi_weight;
+                    let direction = Vector3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+                    let point_i = points.len();
+                    points.push(direction * r);
+                    weights.push(radial_weight * theta_weight * phi_weight);
+
+                    for (n, &value) in radial_values.iter().enumerate() {
+                        radial[[point_i, n]] = value;
+                    }
+
+                    sph_cache.compute(direction, false);
+                    for l in 0..=(parameters.max_angular as isize) {
+                        for m in -l..=l {
+                            spherical_harmonics[[point_i, (l * l + l + m) as usize]] = sph_cache.values[[l, m]];
+                        }
+                    }
+                }
             }
         }
 
-        return Ok(builder.finish());
+        return NumericalGrid { points, weights, radial, spherical_harmonics };
     }
+}
 
-    fn samples_names(&self) -> Vec<&str> {
-        AtomCenteredSamples::samples_names()
-    }
+#[cfg(any(test, feature = "reference"))]
+impl SphericalExpansion {
+    /// Compute the spherical expansion by brute-force numerical integration of
+    /// the atomic density over space, instead of using the analytical radial
+    /// integral. This is very slow, and only intended to validate the fast
+    /// implementation in tests.
+    ///
+    /// The `descriptor` must have been created by this calculator for the
+    /// same `systems`, its values are overwritten with the numerical results.
+    /// Gradients are not computed. Only the GTO radial basis is supported,
+    /// without inner cutoff, per-species cutoffs, species occupations or
+    /// additional weights.
+    pub fn compute_numerical(&self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["spherical_harmonics_l", "species_center", "species_neighbor"]);
 
-    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
-        assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor"]);
+        let parameters = self.by_pair.parameters();
+        if !matches!(parameters.radial_basis, crate::calculators::radial_basis::RadialBasis::Gto { .. }) {
+            return Err(Error::InvalidParameter(
+                "numerical spherical expansion is only implemented for GTO radial basis".into()
+            ));
+        }
 
-        // only compute the samples once for each `species_center, species_neighbor`,
-        // and re-use the results across `spherical_harmonics_l`.
-        let mut samples_per_species = BTreeMap::new();
-        for [_, species_center, species_neighbor] in keys.iter_fixed_size() {
-            if samples_per_species.contains_key(&(species_center, species_neighbor)) {
-                continue;
-            }
+        if parameters.inner_cutoff.is_some() || !parameters.cutoff_per_species.is_empty()
+            || !parameters.species_occupations.is_empty()
+            || parameters.species_distance_weight.is_some() || parameters.velocity_weight.is_some() {
+            return Err(Error::InvalidParameter(
+                "numerical spherical expansion does not support inner cutoff, \
+                per-species cutoffs, species occupations or additional weights".into()
+            ));
+        }
 
-            let builder = AtomCenteredSamples {
-                cutoff: self.by_pair.parameters().cutoff,
-                species_center: SpeciesFilter::Single(species_center.i32()),
-                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
-                self_pairs: true,
-            };
+        let sigma = parameters.atomic_gaussian_width;
+        // the density of atoms inside the cutoff is negligible after 6 σ
+        let grid = NumericalGrid::new(parameters, parameters.cutoff + 6.0 * sigma);
 
-            samples_per_species.insert((species_center, species_neighbor), builder.samples(systems)?);
-        }
+        let density_normalization = (std::f64::consts::PI * sigma * sigma).powf(-0.75);
+        let scaling = |r: f64| {
+            parameters.cutoff_function.compute(r, parameters.cutoff) * parameters.radial_scaling.compute(r)
+        };
+
+        for (system_i, system) in systems.iter_mut().enumerate() {
+            system.compute_neighbors(parameters.cutoff)?;
+            let species = system.species()?;
+
+            // all the neighbors of each atom, as (species, vector)
+            let mut neighbors = vec![Vec::new(); species.len()];
+            for pair in system.pairs()? {
+                neighbors[pair.first].push((species[pair.second], pair.vector));
+                if pair.first != pair.second {
+                    neighbors[pair.second].push((species[pair.first], -pair.vector));
+                }
+            }
+
+            for (key, mut block) in descriptor.iter_mut() {
+                let l = key[0].usize();
+                let species_center = key[1].i32();
+                let species_neighbor = key[2].i32();
+
+                let block = block.data_mut();
+                let array = block.values.to_array_mut();
+                for (sample_i, &[structure, center]) in block.samples.iter_fixed_size().enumerate() {
+                    if structure.usize() != system_i {
+                        continue;
+                    }
+
+                    let center = center.usize();
+                    let mut density_centers = Vec::new();
+                    if species[center] == species_neighbor && species[center] == species_center {
+                        density_centers.push((parameters.center_atom_weight * scaling(0.0), Vector3D::zero()));
+                    }
+
+                    for &(neighbor_species, vector) in &neighbors[center] {
+                        if neighbor_species == species_neighbor {
+                            density_centers.push((scaling(vector.norm()), vector));
+                        }
+                    }
+
+                    let density = grid.points.iter().map(|&point| {
+                        density_centers.iter().map(|&(weight, position)| {
+                            let distance2 = (point - position).norm2();
+                            weight * density_normalization * f64::exp(-distance2 / (2.0 * sigma * sigma))
+                        }).sum::<f64>()
+                    }).collect::<Vec<_>>();
+
+                    for (property_i, &[n]) in block.properties.iter_fixed_size().enumerate() {
+                        for m in 0..(2 * l + 1) {
+                            let mut value = 0.0;
+                            for (point_i, &density) in density.iter().enumerate() {
+                                value += grid.weights[point_i] * density
+                                    * grid.radial[[point_i, n.usize()]]
+                                    * grid.spherical_harmonics[[point_i, l * l + m]];
+                            }
+                            array[[sample_i, m, property_i]] = value;
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+impl CalculatorBase for SphericalExpansion {
+    fn name(&self) -> String {
+        "spherical expansion".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(self.by_pair.parameters()).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        let builder = CenterSingleNeighborsSpeciesKeys {
+            cutoff: self.by_pair.parameters().cutoff,
+            self_pairs: true,
+            all_species: self.by_pair.parameters().all_species.clone(),
+        };
+        let keys = builder.keys(systems)?;
+
+        // neighbors with fractional occupations contribute to the density of
+        // multiple species
+        let mut all_species = BTreeSet::new();
+        for &[species_center, species_neighbor] in keys.iter_fixed_size() {
+            for channel in self.by_pair.parameters().species_channels(species_neighbor.i32()) {
+                all_species.insert((species_center, LabelValue::new(channel)));
+            }
+        }
+
+        let all_spherical_harmonics_l = self.by_pair.parameters().spherical_harmonics_l();
+        let mut builder = LabelsBuilder::new(vec!["spherical_harmonics_l", "species_center", "species_neighbor"]);
+        for (species_center, species_neighbor) in all_species {
+            for &spherical_harmonics_l in &all_spherical_harmonics_l {
+                builder.add(&[spherical_harmonics_l.into(), species_center, species_neighbor]);
+            }
+        }
+
+        return Ok(builder.finish());
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor"]);
+
+        // only compute the samples once for each `species_center, species_neighbor`,
+        // and re-use the results across `spherical_harmonics_l`.
+        let mut samples_per_species = BTreeMap::new();
+        for [_, species_center, species_neighbor] in keys.iter_fixed_size() {
+            if samples_per_species.contains_key(&(species_center, species_neighbor)) {
+                continue;
+            }
+
+            let builder = AtomCenteredSamples {
+                cutoff: self.by_pair.parameters().channel_cutoff(species_center.i32(), species_neighbor.i32()),
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: self.by_pair.parameters().channel_filter(species_neighbor.i32()),
+                self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: self.by_pair.parameters().inner_cutoff,
+            };
+
+            samples_per_species.insert((species_center, species_neighbor), builder.samples(systems)?);
+        }
 
         let mut result = Vec::new();
         for [_, species_center, species_neighbor] in keys.iter_fixed_size() {
@@ -646,10 +1344,21 @@ impl CalculatorBase for SphericalExpansion {
         match parameter {
             "positions" => true,
             "cell" => true,
+            "strain" => true,
+            "atomic_gaussian_width" => matches!(
+                self.by_pair.parameters().radial_basis,
+                crate::calculators::radial_basis::RadialBasis::Gto { .. }
+            ),
             _ => false,
         }
     }
 
+    fn supports_gradient_atoms_selection(&self) -> bool {
+        // positions gradients are computed by looping over the gradient
+        // samples present in the descriptor
+        return true;
+    }
+
     fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
         assert_eq!(keys.names(), ["spherical_harmonics_l", "species_center", "species_neighbor"]);
         assert_eq!(keys.count(), samples.len());
@@ -659,10 +1368,12 @@ impl CalculatorBase for SphericalExpansion {
             // TODO: we don't need to rebuild the gradient samples for different
             // spherical_harmonics_l
             let builder = AtomCenteredSamples {
-                cutoff: self.by_pair.parameters().cutoff,
+                cutoff: self.by_pair.parameters().channel_cutoff(species_center.i32(), species_neighbor.i32()),
                 species_center: SpeciesFilter::Single(species_center.i32()),
-                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                species_neighbor: self.by_pair.parameters().channel_filter(species_neighbor.i32()),
                 self_pairs: true,
+                selected_centers: None,
+                inner_cutoff: self.by_pair.parameters().inner_cutoff,
             };
 
             gradient_samples.push(builder.gradients_for(systems, samples)?);
@@ -719,16 +1430,42 @@ impl CalculatorBase for SphericalExpansion {
 
         let do_gradients = GradientsOptions {
             positions: descriptor.block_by_id(0).gradient("positions").is_some(),
-            cell: descriptor.block_by_id(0).gradient("cell").is_some(),
+            // strain gradients are computed from the cell gradients
+            cell: descriptor.block_by_id(0).gradient("cell").is_some()
+                || descriptor.block_by_id(0).gradient("strain").is_some(),
         };
+        let do_width_gradients = descriptor.block_by_id(0).gradient("atomic_gaussian_width").is_some();
+        if self.by_pair.parameters().velocity_weight.is_some() {
+            if do_gradients.either() || do_width_gradients {
+                return Err(Error::InvalidParameter(
+                    "gradients are not available with velocity dependent weights".into()
+                ));
+            }
+
+            if self.velocities.is_none() {
+                return Err(Error::InvalidParameter(
+                    "velocities must be given in the calculation options when using velocity dependent weights".into()
+                ));
+            }
+        }
+
+        self.by_pair.clear_spherical_harmonics_cache();
         self.do_self_contributions(systems, descriptor)?;
+        let self_width_gradients = if do_width_gradients {
+            Some(self.by_pair.self_contribution_width_gradients()?)
+        } else {
+            None
+        };
+
         let mut descriptors_by_system = split_tensor_map_by_system(descriptor, systems.len());
 
         systems.par_iter_mut()
             .zip_eq(&mut descriptors_by_system)
-            .try_for_each(|(system, descriptor)| {
+            .enumerate()
+            .try_for_each(|(system_i, (system, descriptor))| {
                 system.compute_neighbors(self.by_pair.parameters().cutoff)?;
                 let system = &**system;
+                let velocities = self.velocities.as_ref().map(|velocities| &*velocities[system_i]);
 
                 // we will only run the calculation on pairs where one of the
                 // atom is part of the requested samples
@@ -736,10 +1473,21 @@ impl CalculatorBase for SphericalExpansion {
                     block.samples().iter().map(|sample| sample[1].usize()).collect::<Vec<_>>()
                 }).collect::<BTreeSet<_>>();
 
+                // and we only compute the positions gradients of pairs where
+                // one of the atom is part of the gradient samples, which can
+                // be restricted by the user with `selected_gradient_atoms`
+                let gradient_atoms = descriptor.iter().flat_map(|(_, block)| {
+                    block.gradient("positions").map(|gradient| {
+                        gradient.samples().iter().map(|sample| sample[2].usize()).collect::<Vec<_>>()
+                    }).unwrap_or_default()
+                }).collect::<BTreeSet<_>>();
+
                 let accumulated = self.accumulate_all_pairs(
                     system,
+                    velocities,
                     do_gradients,
                     &requested_centers,
+                    &gradient_atoms,
                 )?;
 
                 // all pairs are done, copy the data into equistore, handling
@@ -747,7 +1495,15 @@ impl CalculatorBase for SphericalExpansion {
                 for (key, mut block) in descriptor.iter_mut() {
                     self.values_to_equistore(key, &mut block, system, &accumulated)?;
                     self.position_gradients_to_equistore(key, &mut block, system, &accumulated)?;
-                    self.cell_gradients_to_equistore(key, &mut block, system, &accumulated)?;
+                    self.cell_gradients_to_equistore("cell", key, &mut block, system, &accumulated)?;
+                    self.cell_gradients_to_equistore("strain", key, &mut block, system, &accumulated)?;
+                }
+
+                if let Some(ref self_width_gradients) = self_width_gradients {
+                    let width_gradients = self.accumulate_width_gradients(system, &accumulated)?;
+                    for (key, mut block) in descriptor.iter_mut() {
+                        self.width_gradients_to_equistore(key, &mut block, system, &accumulated, &width_gradients, self_width_gradients)?;
+                    }
                 }
 
                 Ok::<_, Error>(())
@@ -755,33 +1511,58 @@ impl CalculatorBase for SphericalExpansion {
 
         Ok(())
     }
+
+    fn set_velocities(&mut self, velocities: Option<&[Vec<Vector3D>]>) -> Result<(), Error> {
+        if velocities.is_some() && self.by_pair.parameters().velocity_weight.is_none() {
+            return Err(Error::InvalidParameter(
+                "velocities are only used with velocity dependent weights, \
+                but no velocity_weight was given".into()
+            ));
+        }
+
+        self.velocities = velocities.map(|velocities| velocities.to_vec());
+        return Ok(());
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use ndarray::ArrayD;
-    use equistore::{Labels, TensorBlock, EmptyArray, LabelsBuilder, TensorMap};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use approx::assert_relative_eq;
+    use ndarray::{ArrayD, Axis};
+    use equistore::{Labels, LabelValue, TensorBlock, EmptyArray, LabelsBuilder, TensorMap};
 
     use crate::systems::test_utils::{test_systems, test_system};
-    use crate::{Calculator, CalculationOptions, LabelsSelection};
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, CalculationOptions, LabelsSelection, System, Vector3D};
     use crate::calculators::CalculatorBase;
 
     use super::{SphericalExpansion, SphericalExpansionParameters};
-    use super::super::{CutoffFunction, RadialScaling};
+    use super::super::{CutoffFunction, RadialScaling, VelocityWeight};
     use crate::calculators::radial_basis::RadialBasis;
 
 
     fn parameters() -> SphericalExpansionParameters {
         SphericalExpansionParameters {
             cutoff: 3.5,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
             max_radial: 6,
             max_angular: 6,
+            angular_channels: None,
             atomic_gaussian_width: 0.3,
             center_atom_weight: 1.0,
             radial_basis: RadialBasis::splined_gto(1e-8),
             radial_scaling: RadialScaling::Willatt2018 { scale: 1.5, rate: 0.8, exponent: 2},
             cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            species_occupations: Default::default(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
         }
     }
 
@@ -813,6 +1594,233 @@ mod tests {
         // `rascaline/tests/spherical-expansion.rs`
     }
 
+    #[test]
+    fn angular_channels() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut selected = parameters();
+        selected.angular_channels = Some(vec![4, 0, 2]);
+        let mut selected = Calculator::from(Box::new(SphericalExpansion::new(
+            selected
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let reference = calculator.compute(&mut systems, options).unwrap();
+        let descriptor = selected.compute(&mut systems, options).unwrap();
+
+        assert_eq!(descriptor.keys().count() * 7, reference.keys().count() * 3);
+        for (key, block) in descriptor.iter() {
+            assert!([0, 2, 4].contains(&key[0].i32()));
+
+            let expected = reference.block_by_id(reference.keys().position(key).unwrap());
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+
+            let gradient = block.gradient("positions").unwrap();
+            let expected = expected.gradient("positions").unwrap();
+            assert_eq!(gradient.samples(), expected.samples());
+            assert_relative_eq!(
+                gradient.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-16
+            );
+        }
+    }
+
+    #[test]
+    fn cached_directions() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut cached = parameters();
+        cached.cached_directions = Some(64);
+        let mut cached = Calculator::from(Box::new(SphericalExpansion::new(
+            cached
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        // FCC crystal, where many pairs share the same direction
+        let mut system = SimpleSystem::new(UnitCell::cubic(3.6));
+        system.add_atom_fractional(1, Vector3D::new(0.0, 0.0, 0.0)).unwrap();
+        system.add_atom_fractional(1, Vector3D::new(0.5, 0.5, 0.0)).unwrap();
+        system.add_atom_fractional(1, Vector3D::new(0.5, 0.0, 0.5)).unwrap();
+        system.add_atom_fractional(1, Vector3D::new(0.0, 0.5, 0.5)).unwrap();
+        let system = system.supercell([2, 2, 2]).unwrap();
+
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+
+        let reference = calculator.compute(&mut systems, options).unwrap();
+        // run twice to also use the directions cached in the first calculation
+        for _ in 0..2 {
+            let descriptor = cached.compute(&mut systems, options).unwrap();
+
+            assert_eq!(descriptor.keys(), reference.keys());
+            for (block, expected) in descriptor.blocks().iter().zip(reference.blocks()) {
+                // the cached spherical harmonics are bitwise identical
+                assert_eq!(block.values().to_array(), expected.values().to_array());
+                for parameter in ["positions", "cell"] {
+                    let gradient = block.gradient(parameter).unwrap();
+                    let expected = expected.gradient(parameter).unwrap();
+                    assert_eq!(gradient.samples(), expected.samples());
+                    assert_eq!(gradient.values().to_array(), expected.values().to_array());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn analytical_and_splined_gto() {
+        let mut analytical = parameters();
+        analytical.radial_basis = RadialBasis::gto();
+        let mut analytical = Calculator::from(Box::new(SphericalExpansion::new(
+            analytical
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut splined = parameters();
+        splined.radial_basis = RadialBasis::splined_gto(1e-8);
+        let mut splined = Calculator::from(Box::new(SphericalExpansion::new(
+            splined
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let analytical = analytical.compute(&mut systems, options).unwrap();
+        let splined = splined.compute(&mut systems, options).unwrap();
+
+        assert_eq!(analytical.keys(), splined.keys());
+        for ((_, analytical), (_, splined)) in analytical.iter().zip(splined.iter()) {
+            assert_relative_eq!(
+                analytical.values().to_array(),
+                splined.values().to_array(),
+                max_relative=1e-6,
+                epsilon=1e-7,
+            );
+
+            assert_relative_eq!(
+                analytical.gradient("positions").unwrap().values().to_array(),
+                splined.gradient("positions").unwrap().values().to_array(),
+                max_relative=1e-5,
+                epsilon=1e-6,
+            );
+        }
+    }
+
+    #[test]
+    fn self_only() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut no_center = parameters();
+        no_center.center_atom_weight = 0.0;
+        let mut no_center = Calculator::from(Box::new(SphericalExpansion::new(
+            no_center
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let full = calculator.compute(&mut systems, options).unwrap();
+        let no_center = no_center.compute(&mut systems, options).unwrap();
+
+        let mut self_only = calculator.compute(&mut systems, options).unwrap();
+        SphericalExpansion::new(parameters()).unwrap()
+            .compute_self_only(&mut systems, &mut self_only)
+            .unwrap();
+
+        assert_eq!(full.keys(), self_only.keys());
+        assert_eq!(full.keys(), no_center.keys());
+        for (block_i, (key, full)) in full.iter().enumerate() {
+            let self_only = self_only.block_by_id(block_i);
+            let no_center = no_center.block_by_id(block_i);
+
+            if key[0].usize() != 0 {
+                assert!(self_only.values().to_array().iter().all(|&v| v == 0.0));
+            }
+
+            let difference = full.values().to_array() - self_only.values().to_array();
+            assert_relative_eq!(
+                &difference,
+                no_center.values().to_array(),
+                max_relative=1e-12,
+                epsilon=1e-14,
+            );
+
+            assert!(self_only.gradient("positions").unwrap().values().to_array().iter().all(|&v| v == 0.0));
+            assert_relative_eq!(
+                full.gradient("positions").unwrap().values().to_array(),
+                no_center.gradient("positions").unwrap().values().to_array(),
+                max_relative=1e-12,
+                epsilon=1e-14,
+            );
+        }
+    }
+
+    #[test]
+    fn numerical_integration() {
+        let parameters = SphericalExpansionParameters {
+            cutoff: 3.5,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
+            max_radial: 4,
+            max_angular: 3,
+            angular_channels: None,
+            atomic_gaussian_width: 0.5,
+            center_atom_weight: 1.0,
+            radial_basis: RadialBasis::gto(),
+            radial_scaling: RadialScaling::None {},
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            species_occupations: Default::default(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
+        };
+
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters.clone()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(8, Vector3D::new(0.4, 1.1, -0.7));
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+        let analytical = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let mut numerical = calculator.compute(&mut systems, Default::default()).unwrap();
+        SphericalExpansion::new(parameters).unwrap()
+            .compute_numerical(&mut systems, &mut numerical)
+            .unwrap();
+
+        assert_eq!(analytical.keys(), numerical.keys());
+        for ((_, analytical), (_, numerical)) in analytical.iter().zip(numerical.iter()) {
+            assert_relative_eq!(
+                analytical.values().to_array(),
+                numerical.values().to_array(),
+                max_relative=1e-3,
+                epsilon=1e-5,
+            );
+        }
+    }
+
     #[test]
     fn finite_differences_positions() {
         let calculator = Calculator::from(Box::new(SphericalExpansion::new(
@@ -829,18 +1837,776 @@ mod tests {
     }
 
     #[test]
-    fn finite_differences_cell() {
+    fn finite_differences_positions_selected_samples() {
         let calculator = Calculator::from(Box::new(SphericalExpansion::new(
             parameters()
         ).unwrap()) as Box<dyn CalculatorBase>);
 
+        // only the second atom of the 0-2 and 1-2 pairs is part of the
+        // selected samples
+        let samples = Labels::new(["structure", "center"], &[[0, 2]]);
+
         let system = test_system("water");
         let options = crate::calculators::tests_utils::FinalDifferenceOptions {
             displacement: 1e-6,
             max_relative: 1e-5,
             epsilon: 1e-16,
         };
-        crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
+        crate::calculators::tests_utils::finite_differences_positions_selected(calculator, &system, &samples, options);
+    }
+
+    #[test]
+    fn finite_differences_positions_spherical_bessel() {
+        let mut parameters = parameters();
+        parameters.radial_basis = RadialBasis::spherical_bessel(1e-8);
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_positions_custom_cutoff() {
+        let mut parameters = parameters();
+        let cutoff = parameters.cutoff;
+        parameters.cutoff_function = CutoffFunction::Custom(Arc::new(move |r: f64| {
+            if r >= cutoff {
+                (0.0, 0.0)
+            } else {
+                let x = std::f64::consts::PI * r / cutoff;
+                (0.5 * (1.0 + f64::cos(x)), -0.5 * std::f64::consts::PI / cutoff * f64::sin(x))
+            }
+        }));
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_positions_step_cutoff() {
+        let mut parameters = parameters();
+        // gradients are only correct as long as no atom is close to the cutoff
+        parameters.cutoff_function = CutoffFunction::Step {};
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_positions_polynomial_cutoff() {
+        let mut parameters = parameters();
+        // use a wide switching region so that all pairs in water are inside
+        parameters.cutoff_function = CutoffFunction::Polynomial { width: 3.0, exponent: 3 };
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_positions_tanh_cutoff() {
+        let mut parameters = parameters();
+        // use a wide switching region so that all pairs in water are inside
+        parameters.cutoff_function = CutoffFunction::Tanh { width: 3.0 };
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_cell() {
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_strain() {
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_strain(calculator, &system, options);
+    }
+
+    #[test]
+    fn finite_differences_atomic_gaussian_width() {
+        let calculator = |radial_basis: RadialBasis, atomic_gaussian_width: f64| {
+            let mut parameters = parameters();
+            parameters.radial_basis = radial_basis;
+            parameters.atomic_gaussian_width = atomic_gaussian_width;
+            Calculator::from(Box::new(
+                SphericalExpansion::new(parameters).unwrap()
+            ) as Box<dyn CalculatorBase>)
+        };
+
+        let mut systems = test_systems(&["water", "methane"]);
+
+        // use the analytical radial integral for the finite differences, the
+        // splines for different widths use different control points
+        let width = parameters().atomic_gaussian_width;
+        let delta = 1e-6;
+        let updated_pos = calculator(RadialBasis::gto(), width + delta / 2.0).compute(&mut systems, Default::default()).unwrap();
+        let updated_neg = calculator(RadialBasis::gto(), width - delta / 2.0).compute(&mut systems, Default::default()).unwrap();
+
+        let options = CalculationOptions {
+            gradients: &["atomic_gaussian_width"],
+            ..Default::default()
+        };
+
+        for radial_basis in [RadialBasis::gto(), RadialBasis::splined_gto(1e-8)] {
+            let reference = calculator(radial_basis, width).compute(&mut systems, options).unwrap();
+            assert_eq!(updated_pos.keys(), reference.keys());
+            assert_eq!(updated_neg.keys(), reference.keys());
+
+            for (block_i, (_, block)) in reference.iter().enumerate() {
+                let gradient = block.gradient("atomic_gaussian_width").unwrap();
+                assert_eq!(gradient.samples().count(), block.samples().count());
+
+                let value_pos = updated_pos.block_by_id(block_i).values().to_array().clone();
+                let value_neg = updated_neg.block_by_id(block_i).values().to_array().clone();
+                let finite_difference = (value_pos - value_neg) / delta;
+
+                assert_relative_eq!(
+                    finite_difference, gradient.values().to_array(),
+                    epsilon=1e-6, max_relative=1e-4,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn atomic_gaussian_width_gradients_unsupported() {
+        let mut parameters = parameters();
+        parameters.radial_basis = RadialBasis::spherical_bessel(1e-8);
+        let mut calculator = Calculator::from(Box::new(
+            SphericalExpansion::new(parameters).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water"]);
+        let options = CalculationOptions {
+            gradients: &["atomic_gaussian_width"],
+            ..Default::default()
+        };
+        let error = calculator.compute(&mut systems, options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: the spherical expansion calculator does not \
+            support gradients with respect to the atomic gaussian width"
+        );
+    }
+
+    #[test]
+    fn infinite_cell() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut system = test_system("water");
+        system.set_cell(UnitCell::infinite());
+        let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+        // a large enough cell is equivalent to an infinite one
+        let mut reference = test_system("water");
+        reference.set_cell(UnitCell::cubic(100.0));
+        let mut reference = vec![Box::new(reference) as Box<dyn System>];
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+        let reference = calculator.compute(&mut reference, options).unwrap();
+
+        assert_eq!(descriptor.keys(), reference.keys());
+        for ((_, block), (_, reference)) in descriptor.iter().zip(reference.iter()) {
+            assert_eq!(block.samples(), reference.samples());
+            assert_relative_eq!(block.values().to_array(), reference.values().to_array());
+
+            let gradient = block.gradient("positions").unwrap();
+            let reference = reference.gradient("positions").unwrap();
+            assert_eq!(gradient.samples(), reference.samples());
+            assert_relative_eq!(gradient.values().to_array(), reference.values().to_array());
+        }
+
+        // cell gradients are not defined for infinite cells
+        let options = CalculationOptions {
+            gradients: &["cell"],
+            ..Default::default()
+        };
+        let error = calculator.compute(&mut systems, options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: can not compute cell gradients for non periodic systems"
+        );
+    }
+
+    #[test]
+    fn finite_differences_logarithmic_scaling() {
+        let mut parameters = parameters();
+        parameters.radial_scaling = RadialScaling::Logarithmic { scale: 1.5, rate: 0.8, exponent: 2 };
+
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters.clone()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
+    }
+
+    #[test]
+    fn deterministic_gradients() {
+        // pseudo-random system with enough pairs to be split in multiple
+        // chunks when accumulating gradients
+        let mut system = SimpleSystem::new(UnitCell::cubic(6.0));
+        let mut state = 42_u64;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            return 6.0 * (state >> 11) as f64 / (1_u64 << 53) as f64;
+        };
+        for atom_i in 0..40 {
+            let species = if atom_i % 3 == 0 { 8 } else { 1 };
+            system.add_atom(species, Vector3D::new(next(), next(), next()));
+        }
+
+        let compute_gradients = |n_threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(n_threads).build().unwrap();
+            let system = system.clone();
+            pool.install(move || {
+                let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+                    parameters()
+                ).unwrap()) as Box<dyn CalculatorBase>);
+
+                let mut systems = vec![Box::new(system) as Box<dyn System>];
+                let options = CalculationOptions {
+                    gradients: &["positions"],
+                    ..Default::default()
+                };
+                let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+                descriptor.blocks().iter()
+                    .map(|block| block.gradient("positions").unwrap().values().to_array().clone())
+                    .collect::<Vec<ArrayD<f64>>>()
+            })
+        };
+
+        let reference = compute_gradients(1);
+        for n_threads in [2, 4] {
+            // the results must be bit-for-bit identical
+            assert_eq!(compute_gradients(n_threads), reference);
+        }
+    }
+
+    #[test]
+    fn radial_angular() {
+        let spherical_expansion = SphericalExpansion::new(parameters()).unwrap();
+        let mut systems = test_systems(&["water", "methane"]);
+        let (radial, angular) = spherical_expansion.compute_radial_angular(&mut systems).unwrap();
+
+        let mut calculator = Calculator::from(Box::new(spherical_expansion) as Box<dyn CalculatorBase>);
+        let expected = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        for (key, block) in expected.iter() {
+            let spherical_harmonics_l = key[0].usize();
+            let species_neighbor = key[2].i32();
+
+            let radial = radial.block_by_id(spherical_harmonics_l);
+            let radial_samples = radial.samples();
+            let radial = radial.values().to_array();
+
+            let angular = angular.block_by_id(spherical_harmonics_l);
+            let angular = angular.values().to_array();
+
+            let values = block.values().to_array();
+            for (sample_i, &[structure, center]) in block.samples().iter_fixed_size().enumerate() {
+                let species = systems[structure.usize()].species().unwrap();
+
+                let mut sum = ArrayD::from_elem(values.shape()[1..].to_vec(), 0.0);
+                for (pair_i, &[pair_structure, _, pair_center, neighbor]) in radial_samples.iter_fixed_size().enumerate() {
+                    if pair_structure != structure || pair_center != center || species[neighbor.usize()] != species_neighbor {
+                        continue;
+                    }
+
+                    for m in 0..(2 * spherical_harmonics_l + 1) {
+                        for n in 0..values.shape()[2] {
+                            sum[[m, n]] += radial[[pair_i, n]] * angular[[pair_i, m, 0]];
+                        }
+                    }
+                }
+
+                assert_relative_eq!(
+                    sum, values.index_axis(Axis(0), sample_i),
+                    max_relative=1e-12, epsilon=1e-14
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn radial_angular_unsupported_parameters() {
+        let mut systems = test_systems(&["water"]);
+
+        let unsupported = [
+            SphericalExpansionParameters {
+                inner_cutoff: Some(1.2),
+                ..parameters()
+            },
+            SphericalExpansionParameters {
+                species_distance_weight: Some(species_distance_weight()),
+                ..parameters()
+            },
+            SphericalExpansionParameters {
+                velocity_weight: Some(VelocityWeight::Gaussian { width: 0.5 }),
+                ..parameters()
+            },
+        ];
+
+        for parameters in unsupported {
+            let spherical_expansion = SphericalExpansion::new(parameters).unwrap();
+            let error = spherical_expansion.compute_radial_angular(&mut systems).unwrap_err();
+            assert!(matches!(error, crate::Error::InvalidParameter(_)));
+        }
+    }
+
+    fn alchemical_water() -> SimpleSystem {
+        let mut system = SimpleSystem::new(UnitCell::cubic(10.0));
+        system.add_atom(-42, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, 0.75545, -0.58895));
+        system.add_atom(7, Vector3D::new(0.0, -0.75545, -0.58895));
+        return system;
+    }
+
+    #[test]
+    fn species_occupations() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        let mut systems = test_systems(&["water"]);
+        let reference = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        // the atom with species 7 fully occupies the hydrogen channel
+        let mut occupations = BTreeMap::new();
+        occupations.insert(7, BTreeMap::from([(1, 1.0)]));
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                species_occupations: occupations,
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = vec![Box::new(alchemical_water()) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        assert_eq!(descriptor.keys().count(), 3 * reference.keys().count() / 2);
+        for (key, block) in reference.iter() {
+            let expected = block.values().to_array();
+            if key[1] == -42 {
+                let block = descriptor.block_by_id(descriptor.keys().position(key).unwrap());
+                assert_eq!(block.samples(), reference.block_by_id(0).samples());
+                assert_relative_eq!(block.values().to_array(), expected, max_relative=1e-12);
+            } else {
+                // the environments of the two hydrogen atoms are now in
+                // separate blocks
+                assert_eq!(key[1], 1);
+                let first_h = descriptor.block_by_id(descriptor.keys().position(key).unwrap());
+                let first_h = first_h.values().to_array();
+                assert_relative_eq!(
+                    first_h.index_axis(Axis(0), 0),
+                    expected.index_axis(Axis(0), 0),
+                    max_relative=1e-12
+                );
+
+                let second_h = descriptor.block_by_id(descriptor.keys().position(
+                    &[key[0], 7.into(), key[2]]
+                ).unwrap());
+                let second_h = second_h.values().to_array();
+                assert_relative_eq!(
+                    second_h.index_axis(Axis(0), 0),
+                    expected.index_axis(Axis(0), 1),
+                    max_relative=1e-12
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn finite_differences_positions_occupations() {
+        let mut occupations = BTreeMap::new();
+        occupations.insert(7, BTreeMap::from([(1, 0.3), (-42, 0.7)]));
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                species_occupations: occupations,
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = alchemical_water();
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+
+    fn species_distance_weight() -> super::super::SpeciesDistanceWeight {
+        super::super::SpeciesDistanceWeight {
+            weight: Arc::new(|species, r| 1.0 + 0.01 * species as f64 * f64::exp(-r)),
+            gradient: Arc::new(|species, r| -0.01 * species as f64 * f64::exp(-r)),
+        }
+    }
+
+    #[test]
+    fn finite_differences_species_distance_weight() {
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                species_distance_weight: Some(species_distance_weight()),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                species_distance_weight: Some(species_distance_weight()),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
+    }
+
+    #[test]
+    fn inner_cutoff() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                inner_cutoff: Some(1.2),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut reference = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(1.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, 2.5, 0.0));
+
+        // same system, with the second atom outside of the cutoff
+        let mut far_away = SimpleSystem::new(UnitCell::infinite());
+        far_away.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        far_away.add_atom(1, Vector3D::new(20.0, 0.0, 0.0));
+        far_away.add_atom(1, Vector3D::new(0.0, 2.5, 0.0));
+
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut [Box::new(system) as Box<dyn System>], options).unwrap();
+        let expected = reference.compute(&mut [Box::new(far_away) as Box<dyn System>], options).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            // the first atom only sees the third one
+            let center = [LabelValue::new(0), LabelValue::new(0)];
+            let sample_i = block.samples().position(&center).unwrap();
+            let expected_i = expected.samples().position(&center).unwrap();
+
+            let values = block.values().to_array();
+            let expected_values = expected.values().to_array();
+            assert_relative_eq!(
+                values.index_axis(Axis(0), sample_i),
+                expected_values.index_axis(Axis(0), expected_i),
+                max_relative=1e-12,
+            );
+
+            // and there are no gradients w.r.t. the excluded neighbor
+            let gradient = block.gradient("positions").unwrap();
+            let excluded = [sample_i.into(), LabelValue::new(0), LabelValue::new(1)];
+            assert!(gradient.samples().position(&excluded).is_none());
+        }
+    }
+
+    #[test]
+    fn invalid_inner_cutoff() {
+        let result = SphericalExpansion::new(SphericalExpansionParameters {
+            inner_cutoff: Some(4.0),
+            ..parameters()
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cutoff_per_species() {
+        let mut cutoff_per_species = BTreeMap::new();
+        cutoff_per_species.insert(1, BTreeMap::from([(1, 1.2)]));
+
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                cutoff_per_species: cutoff_per_species,
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut reference = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        // the H-H distance in water is 1.51, so hydrogen atoms only see the
+        // oxygen and themselves
+        let descriptor = calculator.compute(&mut test_systems(&["water"]), Default::default()).unwrap();
+        let expected = reference.compute(&mut test_systems(&["water"]), Default::default()).unwrap();
+
+        let mut single_hydrogen = SimpleSystem::new(UnitCell::cubic(10.0));
+        single_hydrogen.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        let only_self = reference.compute(&mut [Box::new(single_hydrogen) as Box<dyn System>], Default::default()).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((key, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+
+            let values = block.values().to_array();
+            if key[1] == 1 && key[2] == 1 {
+                let only_self = only_self.block_by_id(only_self.keys().position(key).unwrap());
+                let only_self = only_self.values().to_array();
+                for sample_i in 0..block.samples().count() {
+                    assert_relative_eq!(
+                        values.index_axis(Axis(0), sample_i),
+                        only_self.index_axis(Axis(0), 0),
+                        max_relative=1e-12,
+                    );
+                }
+            } else {
+                assert_relative_eq!(values, expected.values().to_array(), max_relative=1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn finite_differences_cutoff_per_species() {
+        // use different cutoffs for the H-O and O-H pairs, with all pairs
+        // inside the switching region of the cutoff function
+        let mut cutoff_per_species = BTreeMap::new();
+        cutoff_per_species.insert(1, BTreeMap::from([(-42, 1.0), (1, 1.8)]));
+        let parameters = SphericalExpansionParameters {
+            cutoff_per_species: cutoff_per_species,
+            ..parameters()
+        };
+
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters.clone()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+
+        let calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters
+        ).unwrap()) as Box<dyn CalculatorBase>);
+        crate::calculators::tests_utils::finite_differences_cell(calculator, &system, options);
+    }
+
+    #[test]
+    fn invalid_cutoff_per_species() {
+        let mut cutoff_per_species = BTreeMap::new();
+        cutoff_per_species.insert(1, BTreeMap::from([(8, 4.0)]));
+        let result = SphericalExpansion::new(SphericalExpansionParameters {
+            cutoff_per_species: cutoff_per_species,
+            ..parameters()
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn velocity_weight() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                velocity_weight: Some(VelocityWeight::Gaussian { width: 0.5 }),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut reference = Calculator::from(Box::new(SphericalExpansion::new(
+            parameters()
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water", "methane"]);
+        let expected = reference.compute(&mut systems, Default::default()).unwrap();
+
+        // all atoms moving together: zero relative velocity
+        let velocities = systems.iter()
+            .map(|system| vec![Vector3D::new(0.3, -1.2, 0.5); system.size().unwrap()])
+            .collect::<Vec<_>>();
+        let options = CalculationOptions {
+            velocities: Some(&velocities),
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(
+                block.values().to_array(), expected.values().to_array(),
+                max_relative=1e-12, epsilon=1e-14
+            );
+        }
+
+        // relative velocities reduce the neighbors contributions
+        let mut velocities = velocities;
+        velocities[0][1] = Vector3D::new(2.0, 0.0, 1.0);
+        let options = CalculationOptions {
+            velocities: Some(&velocities),
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut systems, options).unwrap();
+        let changed = descriptor.iter().zip(expected.iter()).any(|((_, block), (_, expected))| {
+            block.values().to_array() != expected.values().to_array()
+        });
+        assert!(changed);
+
+        // velocities are required, and gradients are not available
+        assert!(calculator.compute(&mut systems, Default::default()).is_err());
+        let options = CalculationOptions {
+            velocities: Some(&velocities),
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        assert!(calculator.compute(&mut systems, options).is_err());
+
+        // velocities without velocity dependent weight
+        let options = CalculationOptions {
+            velocities: Some(&velocities),
+            ..Default::default()
+        };
+        assert!(reference.compute(&mut systems, options).is_err());
+    }
+
+    #[test]
+    fn invalid_occupations() {
+        let mut occupations = BTreeMap::new();
+        occupations.insert(7, BTreeMap::from([(1, 0.3), (-42, 0.3)]));
+        let result = SphericalExpansion::new(SphericalExpansionParameters {
+            species_occupations: occupations,
+            ..parameters()
+        });
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "invalid parameter: occupations for atoms with species 7 must sum to 1, got 0.6"
+        );
+    }
+
+    #[test]
+    fn all_species() {
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                all_species: Some(vec![1, 6, -42]),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water"]);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        // there is no carbon in water, but the corresponding blocks are still
+        // present, and empty
+        assert_eq!(descriptor.keys().count(), 9 * (parameters().max_angular + 1));
+        for (key, block) in descriptor.iter() {
+            if key[1] == 6 || key[2] == 6 {
+                assert_eq!(block.samples().count(), 0);
+            } else {
+                assert_ne!(block.samples().count(), 0);
+            }
+        }
+
+        // all the species in the systems must be part of all_species
+        let mut calculator = Calculator::from(Box::new(SphericalExpansion::new(
+            SphericalExpansionParameters {
+                all_species: Some(vec![1, 6]),
+                ..parameters()
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+
+        let error = calculator.compute(&mut systems, Default::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: species -42 is present in the systems but not in `all_species`"
+        );
     }
 
     #[test]
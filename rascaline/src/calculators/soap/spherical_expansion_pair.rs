@@ -1,6 +1,8 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::collections::btree_map::Entry;
 use std::cell::RefCell;
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
 
 use ndarray::s;
 use thread_local::ThreadLocal;
@@ -11,6 +13,7 @@ use crate::{Error, System, Vector3D, Matrix3};
 use crate::systems::CellShape;
 
 use crate::math::SphericalHarmonicsCache;
+use crate::labels::SpeciesFilter;
 
 use super::super::CalculatorBase;
 use super::super::neighbor_list::FullNeighborList;
@@ -34,10 +37,34 @@ use super::radial_integral::SoapRadialIntegralParameters;
 pub struct SphericalExpansionParameters {
     /// Spherical cutoff to use for atomic environments
     pub cutoff: f64,
+    /// Hard inner cutoff: neighbors closer than this distance to the center
+    /// are completely excluded from the expansion, both for values and
+    /// gradients. Contrary to the smooth `cutoff_function` used at `cutoff`,
+    /// this makes the features (and any forces computed from them)
+    /// discontinuous when a neighbor crosses `inner_cutoff`.
+    #[serde(default)]
+    pub inner_cutoff: Option<f64>,
+    /// Cutoff radius to use for specific pairs of species instead of
+    /// `cutoff`. This maps the species of the center to the species of the
+    /// neighbor to the cutoff for this pair. All values must be positive and
+    /// smaller than `cutoff`, which is still used to build the neighbor list.
+    /// The `cutoff_function` is applied at the cutoff of each pair.
+    ///
+    /// The keys are determined with the global `cutoff`, while the samples
+    /// of [`SphericalExpansion`](super::SphericalExpansion) only contain
+    /// centers with neighbors inside the cutoff of the corresponding pair.
+    #[serde(default)]
+    pub cutoff_per_species: BTreeMap<i32, BTreeMap<i32, f64>>,
     /// Number of radial basis function to use in the expansion
     pub max_radial: usize,
     /// Number of spherical harmonics to use in the expansion
     pub max_angular: usize,
+    /// Only compute the expansion for these values of `spherical_harmonics_l`,
+    /// which must all be smaller or equal to `max_angular`. If this is not
+    /// given, all values from 0 to `max_angular` are used. The output only
+    /// contains blocks for the selected `spherical_harmonics_l`.
+    #[serde(default)]
+    pub angular_channels: Option<Vec<usize>>,
     /// Width of the atom-centered gaussian used to create the atomic density
     pub atomic_gaussian_width: f64,
     /// Weight of the central atom contribution to the
@@ -54,14 +81,240 @@ pub struct SphericalExpansionParameters {
     /// model
     #[serde(default)]
     pub radial_scaling: RadialScaling,
+    /// Fractional occupations of atomic species, used to describe alchemical
+    /// systems. This maps the species of atoms in the systems to an occupation
+    /// vector, i.e. to the weight of this atom in the density of each output
+    /// neighbor species. Occupations must be positive and sum to 1. Atoms with
+    /// a species not in this map fully occupy their own species channel.
+    ///
+    /// This is only used by [`SphericalExpansion`](super::SphericalExpansion),
+    /// the pair-by-pair expansion always uses integer species.
+    #[serde(default)]
+    pub species_occupations: BTreeMap<i32, BTreeMap<i32, f64>>,
+    /// Use this set of species to create the keys instead of the species
+    /// found in the systems. All the species in the systems must be part of
+    /// this set, and the blocks for species absent from the systems are
+    /// empty. This allows to get the same keys when computing the expansion
+    /// for different subsets of a dataset.
+    ///
+    /// This is only used by [`SphericalExpansion`](super::SphericalExpansion),
+    /// the pair-by-pair expansion always uses the species in the systems.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub all_species: Option<Vec<i32>>,
+    /// Maximal number of pair directions for which the spherical harmonics
+    /// are kept around in each thread, to be re-used by pairs with the exact
+    /// same direction. This is mainly useful for crystals, where the same
+    /// directions appear for many different pairs. The cache is disabled if
+    /// this is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_directions: Option<usize>,
+    /// Additional weight for the contribution of each neighbor, depending on
+    /// the species of the neighbor and its distance to the center. This can
+    /// not be set from JSON, only from Rust code.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub species_distance_weight: Option<SpeciesDistanceWeight>,
+    /// **Experimental**: additional weight for the contribution of each
+    /// neighbor, depending on the velocity of the neighbor relative to the
+    /// center along the bond. The velocities must be given in
+    /// [`CalculationOptions::velocities`](crate::CalculationOptions::velocities).
+    ///
+    /// This is only used by [`SphericalExpansion`](super::SphericalExpansion),
+    /// and gradients are not available when using it.
+    #[serde(default)]
+    pub velocity_weight: Option<VelocityWeight>,
+}
+
+/// Species and distance dependent weight for the neighbors contributions to
+/// the spherical expansion.
+///
+/// Both functions take the species of the neighbor and the distance between
+/// the center and the neighbor. The weight is applied on top of the cutoff
+/// function and radial scaling, and does not apply to the central atom
+/// contribution.
+///
+/// The functions are called through dynamic dispatch twice per pair and
+/// direction (once for the weight and once for the gradient), and prevent
+/// re-using the contribution of the `i-j` pair for the `j-i` pair when
+/// computing gradients. They should be cheap to evaluate, and only be used
+/// when required, since they make the calculation noticeably slower.
+#[derive(Clone)]
+pub struct SpeciesDistanceWeight {
+    /// weight of a neighbor with the given species at the given distance
+    pub weight: Arc<dyn Fn(i32, f64) -> f64 + Send + Sync + RefUnwindSafe>,
+    /// derivative of `weight` with respect to the distance
+    pub gradient: Arc<dyn Fn(i32, f64) -> f64 + Send + Sync + RefUnwindSafe>,
+}
+
+impl std::fmt::Debug for SpeciesDistanceWeight {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SpeciesDistanceWeight {{ .. }}")
+    }
+}
+
+/// **Experimental**: velocity dependent weight for the neighbors contributions
+/// to the spherical expansion.
+///
+/// The weight is a function of the relative velocity of the neighbor `j` with
+/// respect to the center `i`, projected along the bond: `v = (v_j - v_i) ·
+/// r_ij / |r_ij|`. This is the same for the `i-j` and `j-i` pairs. This is
+/// intended for non-equilibrium and transport descriptors, and might change
+/// or be removed in the future.
+#[derive(Debug, Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub enum VelocityWeight {
+    /// Gaussian weight `w(v) = exp(-v^2 / (2 width^2))`. Neighbors without
+    /// relative velocity have a weight of 1.
+    Gaussian {
+        width: f64,
+    },
+}
+
+impl VelocityWeight {
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            VelocityWeight::Gaussian { width } => {
+                if !(*width > 0.0 && width.is_finite()) {
+                    return Err(Error::InvalidParameter(format!(
+                        "expected positive width for gaussian velocity weight, got {}",
+                        width
+                    )));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Evaluate the weight for the relative `velocity` along the bond
+    pub fn compute(&self, velocity: f64) -> f64 {
+        match self {
+            VelocityWeight::Gaussian { width } => {
+                f64::exp(-velocity * velocity / (2.0 * width * width))
+            }
+        }
+    }
 }
 
 impl SphericalExpansionParameters {
+    /// Create a builder for `SphericalExpansionParameters`, using default
+    /// values for all optional parameters. The `cutoff`, `max_radial`,
+    /// `max_angular` and `atomic_gaussian_width` must be set before calling
+    /// [`SphericalExpansionParametersBuilder::build`].
+    pub fn builder() -> SphericalExpansionParametersBuilder {
+        SphericalExpansionParametersBuilder::default()
+    }
+
     /// Validate all the parameters
     pub fn validate(&self) -> Result<(), Error> {
+        if !(self.cutoff > 0.0 && self.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number, got {}", self.cutoff
+            )));
+        }
+
+        if self.max_radial == 0 {
+            return Err(Error::InvalidParameter(
+                "max_radial must be at least 1".into()
+            ));
+        }
+
+        if let Some(ref angular_channels) = self.angular_channels {
+            if angular_channels.is_empty() {
+                return Err(Error::InvalidParameter(
+                    "angular_channels must contain at least one value".into()
+                ));
+            }
+
+            for &spherical_harmonics_l in angular_channels {
+                if spherical_harmonics_l > self.max_angular {
+                    return Err(Error::InvalidParameter(format!(
+                        "angular_channels can only contain values up to max_angular ({}), got {}",
+                        self.max_angular, spherical_harmonics_l
+                    )));
+                }
+            }
+        }
+
+        if !(self.atomic_gaussian_width > 0.0 && self.atomic_gaussian_width.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "atomic_gaussian_width must be a positive number, got {}",
+                self.atomic_gaussian_width
+            )));
+        }
+
+        if !self.center_atom_weight.is_finite() {
+            return Err(Error::InvalidParameter(format!(
+                "center_atom_weight must be a finite number, got {}",
+                self.center_atom_weight
+            )));
+        }
+
         self.cutoff_function.validate()?;
+        if let Some(width) = self.cutoff_function.width() {
+            if width > self.cutoff {
+                return Err(Error::InvalidParameter(format!(
+                    "cutoff_function width ({}) must be smaller than the cutoff ({})",
+                    width, self.cutoff
+                )));
+            }
+        }
+
         self.radial_scaling.validate()?;
 
+        if let Some(velocity_weight) = self.velocity_weight {
+            velocity_weight.validate()?;
+        }
+
+        if let Some(inner_cutoff) = self.inner_cutoff {
+            if !inner_cutoff.is_finite() || inner_cutoff < 0.0 || inner_cutoff >= self.cutoff {
+                return Err(Error::InvalidParameter(format!(
+                    "inner cutoff must be a positive number smaller than the cutoff, got {}",
+                    inner_cutoff
+                )));
+            }
+        }
+
+        for (species_center, cutoffs) in &self.cutoff_per_species {
+            for (species_neighbor, &cutoff) in cutoffs {
+                if !cutoff.is_finite() || cutoff <= 0.0 || cutoff > self.cutoff {
+                    return Err(Error::InvalidParameter(format!(
+                        "cutoff for center species {} and neighbor species {} must \
+                        be a positive number smaller than the global cutoff, got {}",
+                        species_center, species_neighbor, cutoff
+                    )));
+                }
+
+                if self.inner_cutoff.map_or(false, |inner_cutoff| cutoff <= inner_cutoff) {
+                    return Err(Error::InvalidParameter(format!(
+                        "cutoff for center species {} and neighbor species {} must \
+                        be larger than the inner cutoff, got {}",
+                        species_center, species_neighbor, cutoff
+                    )));
+                }
+            }
+        }
+
+        for (species, occupations) in &self.species_occupations {
+            let mut total = 0.0;
+            for (channel, &occupation) in occupations {
+                if !occupation.is_finite() || occupation < 0.0 {
+                    return Err(Error::InvalidParameter(format!(
+                        "invalid occupation of species {} for atoms with species {}: \
+                        expected a positive number, got {}",
+                        channel, species, occupation
+                    )));
+                }
+                total += occupation;
+            }
+
+            if f64::abs(total - 1.0) > 1e-6 {
+                return Err(Error::InvalidParameter(format!(
+                    "occupations for atoms with species {} must sum to 1, got {}",
+                    species, total
+                )));
+            }
+        }
+
         // try constructing a radial integral
         SoapRadialIntegralCache::new(self.radial_basis.clone(), SoapRadialIntegralParameters {
             max_radial: self.max_radial,
@@ -72,6 +325,272 @@ impl SphericalExpansionParameters {
 
         return Ok(());
     }
+
+    /// Get the sorted list of `spherical_harmonics_l` for which the expansion
+    /// should be computed
+    pub(crate) fn spherical_harmonics_l(&self) -> Vec<usize> {
+        match self.angular_channels {
+            Some(ref angular_channels) => {
+                let angular_channels = angular_channels.iter().copied().collect::<BTreeSet<_>>();
+                angular_channels.into_iter().collect()
+            }
+            None => (0..=self.max_angular).collect(),
+        }
+    }
+
+    /// Get the cutoff radius for pairs between a center with species
+    /// `species_center` and a neighbor with species `species_neighbor`
+    pub fn pair_cutoff(&self, species_center: i32, species_neighbor: i32) -> f64 {
+        self.cutoff_per_species.get(&species_center)
+            .and_then(|cutoffs| cutoffs.get(&species_neighbor))
+            .copied()
+            .unwrap_or(self.cutoff)
+    }
+
+    /// Get the largest cutoff radius for pairs between a center with species
+    /// `species_center` and any atom contributing to the density associated
+    /// with the `channel` neighbor species.
+    pub(crate) fn channel_cutoff(&self, species_center: i32, channel: i32) -> f64 {
+        if self.cutoff_per_species.is_empty() {
+            return self.cutoff;
+        }
+
+        match self.channel_filter(channel) {
+            SpeciesFilter::Single(species) => self.pair_cutoff(species_center, species),
+            SpeciesFilter::OneOf(species) if !species.is_empty() => {
+                species.iter()
+                    .map(|&species_neighbor| self.pair_cutoff(species_center, species_neighbor))
+                    .fold(0.0, f64::max)
+            }
+            _ => self.cutoff,
+        }
+    }
+
+    /// Get the weight of an atom with the given `species` in the density
+    /// associated with the `channel` neighbor species.
+    pub(crate) fn occupation(&self, species: i32, channel: i32) -> f64 {
+        match self.species_occupations.get(&species) {
+            Some(occupations) => occupations.get(&channel).copied().unwrap_or(0.0),
+            None => if species == channel { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// Get the list of neighbor species channels an atom with the given
+    /// `species` contributes to
+    pub(crate) fn species_channels(&self, species: i32) -> Vec<i32> {
+        match self.species_occupations.get(&species) {
+            Some(occupations) => {
+                occupations.iter()
+                    .filter(|(_, occupation)| **occupation != 0.0)
+                    .map(|(&channel, _)| channel)
+                    .collect()
+            }
+            None => vec![species],
+        }
+    }
+
+    /// Get the filter matching all atomic species contributing to the density
+    /// associated with the `channel` neighbor species.
+    pub(crate) fn channel_filter(&self, channel: i32) -> SpeciesFilter {
+        if self.species_occupations.is_empty() {
+            return SpeciesFilter::Single(channel);
+        }
+
+        let mut species = Vec::new();
+        if !self.species_occupations.contains_key(&channel) {
+            species.push(channel);
+        }
+
+        for (&atom_species, occupations) in &self.species_occupations {
+            if occupations.get(&channel).map_or(false, |&occupation| occupation != 0.0) {
+                species.push(atom_species);
+            }
+        }
+
+        return SpeciesFilter::OneOf(species);
+    }
+}
+
+/// Builder for [`SphericalExpansionParameters`], created with
+/// [`SphericalExpansionParameters::builder`].
+///
+/// The optional parameters default to `center_atom_weight = 1.0`, no
+/// `radial_scaling`, a `ShiftedCosine` cutoff function with a width of 0.5,
+/// and a splined GTO radial basis.
+#[derive(Debug, Clone)]
+pub struct SphericalExpansionParametersBuilder {
+    cutoff: Option<f64>,
+    max_radial: Option<usize>,
+    max_angular: Option<usize>,
+    angular_channels: Option<Vec<usize>>,
+    atomic_gaussian_width: Option<f64>,
+    inner_cutoff: Option<f64>,
+    cutoff_per_species: BTreeMap<i32, BTreeMap<i32, f64>>,
+    center_atom_weight: f64,
+    radial_basis: RadialBasis,
+    cutoff_function: CutoffFunction,
+    radial_scaling: RadialScaling,
+    species_occupations: BTreeMap<i32, BTreeMap<i32, f64>>,
+    all_species: Option<Vec<i32>>,
+    cached_directions: Option<usize>,
+    species_distance_weight: Option<SpeciesDistanceWeight>,
+    velocity_weight: Option<VelocityWeight>,
+}
+
+impl Default for SphericalExpansionParametersBuilder {
+    fn default() -> SphericalExpansionParametersBuilder {
+        SphericalExpansionParametersBuilder {
+            cutoff: None,
+            max_radial: None,
+            max_angular: None,
+            angular_channels: None,
+            atomic_gaussian_width: None,
+            inner_cutoff: None,
+            cutoff_per_species: BTreeMap::new(),
+            center_atom_weight: 1.0,
+            radial_basis: RadialBasis::splined_gto(1e-8),
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            radial_scaling: RadialScaling::None {},
+            species_occupations: BTreeMap::new(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
+        }
+    }
+}
+
+impl SphericalExpansionParametersBuilder {
+    /// Set the spherical cutoff to use for atomic environments
+    pub fn cutoff(mut self, cutoff: f64) -> Self {
+        self.cutoff = Some(cutoff);
+        self
+    }
+
+    /// Set the number of radial basis functions
+    pub fn max_radial(mut self, max_radial: usize) -> Self {
+        self.max_radial = Some(max_radial);
+        self
+    }
+
+    /// Set the number of spherical harmonics
+    pub fn max_angular(mut self, max_angular: usize) -> Self {
+        self.max_angular = Some(max_angular);
+        self
+    }
+
+    /// Only compute the expansion for the given `spherical_harmonics_l`
+    pub fn angular_channels(mut self, angular_channels: Vec<usize>) -> Self {
+        self.angular_channels = Some(angular_channels);
+        self
+    }
+
+    /// Set the width of the atom-centered gaussian densities
+    pub fn atomic_gaussian_width(mut self, atomic_gaussian_width: f64) -> Self {
+        self.atomic_gaussian_width = Some(atomic_gaussian_width);
+        self
+    }
+
+    /// Set the hard inner cutoff
+    pub fn inner_cutoff(mut self, inner_cutoff: f64) -> Self {
+        self.inner_cutoff = Some(inner_cutoff);
+        self
+    }
+
+    /// Set the cutoff radius for pairs between a center with species
+    /// `species_center` and a neighbor with species `species_neighbor`
+    pub fn pair_cutoff(mut self, species_center: i32, species_neighbor: i32, cutoff: f64) -> Self {
+        self.cutoff_per_species.entry(species_center)
+            .or_default()
+            .insert(species_neighbor, cutoff);
+        self
+    }
+
+    /// Set the weight of the central atom contribution
+    pub fn center_atom_weight(mut self, center_atom_weight: f64) -> Self {
+        self.center_atom_weight = center_atom_weight;
+        self
+    }
+
+    /// Set the radial basis
+    pub fn radial_basis(mut self, radial_basis: RadialBasis) -> Self {
+        self.radial_basis = radial_basis;
+        self
+    }
+
+    /// Set the cutoff function
+    pub fn cutoff_function(mut self, cutoff_function: CutoffFunction) -> Self {
+        self.cutoff_function = cutoff_function;
+        self
+    }
+
+    /// Set the radial scaling
+    pub fn radial_scaling(mut self, radial_scaling: RadialScaling) -> Self {
+        self.radial_scaling = radial_scaling;
+        self
+    }
+
+    /// Set the fractional occupations of atoms with the given `species`
+    pub fn species_occupations(mut self, species: i32, occupations: BTreeMap<i32, f64>) -> Self {
+        self.species_occupations.insert(species, occupations);
+        self
+    }
+
+    /// Use the given set of species to create the keys, instead of the
+    /// species found in the systems
+    pub fn all_species(mut self, all_species: Vec<i32>) -> Self {
+        self.all_species = Some(all_species);
+        self
+    }
+
+    /// Keep the spherical harmonics for up to `cached_directions` pair
+    /// directions in each thread
+    pub fn cached_directions(mut self, cached_directions: usize) -> Self {
+        self.cached_directions = Some(cached_directions);
+        self
+    }
+
+    /// Set the species and distance dependent weight of the neighbors
+    pub fn species_distance_weight(mut self, weight: SpeciesDistanceWeight) -> Self {
+        self.species_distance_weight = Some(weight);
+        self
+    }
+
+    /// Set the velocity dependent weight of the neighbors
+    pub fn velocity_weight(mut self, weight: VelocityWeight) -> Self {
+        self.velocity_weight = Some(weight);
+        self
+    }
+
+    /// Create the parameters, checking that all required parameters are set
+    /// and that all parameters are valid.
+    pub fn build(self) -> Result<SphericalExpansionParameters, Error> {
+        let missing = |name: &str| Error::InvalidParameter(format!(
+            "missing required parameter '{}' for the spherical expansion", name
+        ));
+
+        let parameters = SphericalExpansionParameters {
+            cutoff: self.cutoff.ok_or_else(|| missing("cutoff"))?,
+            inner_cutoff: self.inner_cutoff,
+            cutoff_per_species: self.cutoff_per_species,
+            max_radial: self.max_radial.ok_or_else(|| missing("max_radial"))?,
+            max_angular: self.max_angular.ok_or_else(|| missing("max_angular"))?,
+            angular_channels: self.angular_channels,
+            atomic_gaussian_width: self.atomic_gaussian_width.ok_or_else(|| missing("atomic_gaussian_width"))?,
+            center_atom_weight: self.center_atom_weight,
+            radial_basis: self.radial_basis,
+            cutoff_function: self.cutoff_function,
+            radial_scaling: self.radial_scaling,
+            species_occupations: self.species_occupations,
+            all_species: self.all_species,
+            cached_directions: self.cached_directions,
+            species_distance_weight: self.species_distance_weight,
+            velocity_weight: self.velocity_weight,
+        };
+
+        parameters.validate()?;
+        return Ok(parameters);
+    }
 }
 
 /// The actual calculator used to compute spherical expansion pair-by-pair
@@ -83,6 +602,10 @@ pub struct SphericalExpansionByPair {
     /// implementation + cached allocation to compute the spherical harmonics
     /// for a single pair
     spherical_harmonics: ThreadLocal<RefCell<SphericalHarmonicsCache>>,
+    /// implementation + cached allocation to compute the gradients of the
+    /// radial integral with respect to the atomic gaussian width for a single
+    /// pair. This is only initialized when these gradients are requested.
+    width_radial_integral: ThreadLocal<RefCell<SoapRadialIntegralCache>>,
     /// Cache for (-1)^l values
     m_1_pow_l: Vec<f64>,
 }
@@ -131,6 +654,29 @@ impl PairContribution {
         }
     }
 
+    /// Copy the values and gradients from `other` into this contribution
+    pub fn assign(&mut self, other: &PairContribution) {
+        self.values.assign(&other.values);
+        if let (Some(gradients), Some(other)) = (&mut self.gradients, &other.gradients) {
+            gradients.assign(other);
+        }
+    }
+
+    /// Multiply this contribution by a distance-dependent `weight`, where
+    /// `weight_gradient` is the derivative of the weight with respect to the
+    /// distance, and `direction` the unit vector along the pair.
+    pub fn apply_weight(&mut self, weight: f64, weight_gradient: f64, direction: Vector3D) {
+        if let Some(ref mut gradients) = self.gradients {
+            for spatial in 0..3 {
+                let mut gradients = gradients.index_axis_mut(ndarray::Axis(0), spatial);
+                gradients *= weight;
+                gradients.scaled_add(weight_gradient * direction[spatial], &self.values);
+            }
+        }
+
+        self.values *= weight;
+    }
+
     /// Modify the values/gradients as required to construct the
     /// values/gradients associated with pair j -> i from pair i -> j.
     ///
@@ -183,6 +729,7 @@ impl SphericalExpansionByPair {
             parameters: parameters,
             radial_integral: ThreadLocal::new(),
             spherical_harmonics: ThreadLocal::new(),
+            width_radial_integral: ThreadLocal::new(),
             m_1_pow_l,
         })
     }
@@ -192,17 +739,28 @@ impl SphericalExpansionByPair {
         &self.parameters
     }
 
-    /// Compute the product of radial scaling & cutoff smoothing functions
-    fn scaling_functions(&self, r: f64) -> f64 {
-        let cutoff = self.parameters.cutoff_function.compute(r, self.parameters.cutoff);
+    /// Remove all directions cached by the spherical harmonics in all
+    /// threads. This should be called at the start of every calculation, so
+    /// the cache is filled with directions of the current systems.
+    pub(super) fn clear_spherical_harmonics_cache(&mut self) {
+        for spherical_harmonics in self.spherical_harmonics.iter_mut() {
+            spherical_harmonics.get_mut().clear_directions();
+        }
+    }
+
+    /// Compute the product of radial scaling & cutoff smoothing functions,
+    /// using the given cutoff radius
+    fn scaling_functions(&self, r: f64, cutoff: f64) -> f64 {
+        let cutoff = self.parameters.cutoff_function.compute(r, cutoff);
         let scaling = self.parameters.radial_scaling.compute(r);
         return cutoff * scaling;
     }
 
-    /// Compute the gradient of the product of radial scaling & cutoff smoothing functions
-    fn scaling_functions_gradient(&self, r: f64) -> f64 {
-        let cutoff = self.parameters.cutoff_function.compute(r, self.parameters.cutoff);
-        let cutoff_grad = self.parameters.cutoff_function.derivative(r, self.parameters.cutoff);
+    /// Compute the gradient of the product of radial scaling & cutoff
+    /// smoothing functions, using the given cutoff radius
+    fn scaling_functions_gradient(&self, r: f64, cutoff_radius: f64) -> f64 {
+        let cutoff = self.parameters.cutoff_function.compute(r, cutoff_radius);
+        let cutoff_grad = self.parameters.cutoff_function.derivative(r, cutoff_radius);
 
         let scaling = self.parameters.radial_scaling.compute(r);
         let scaling_grad = self.parameters.radial_scaling.derivative(r);
@@ -210,6 +768,22 @@ impl SphericalExpansionByPair {
         return cutoff_grad * scaling + cutoff * scaling_grad;
     }
 
+    /// Apply the species and distance dependent weight (if any) to the
+    /// `contribution` of a pair with a neighbor of species `species_neighbor`
+    pub(super) fn apply_species_weight(
+        &self,
+        contribution: &mut PairContribution,
+        species_neighbor: i32,
+        distance: f64,
+        direction: Vector3D,
+    ) {
+        if let Some(ref species_weight) = self.parameters.species_distance_weight {
+            let weight = (species_weight.weight)(species_neighbor, distance);
+            let gradient = (species_weight.gradient)(species_neighbor, distance);
+            contribution.apply_weight(weight, gradient, direction);
+        }
+    }
+
     /// Compute the self-contribution (contribution coming from an atom "seeing"
     /// it's own density). This is equivalent to a normal pair contribution,
     /// with a distance of 0.
@@ -235,7 +809,10 @@ impl SphericalExpansionByPair {
         }).borrow_mut();
 
         let mut spherical_harmonics = self.spherical_harmonics.get_or(|| {
-            RefCell::new(SphericalHarmonicsCache::new(self.parameters.max_angular))
+            RefCell::new(SphericalHarmonicsCache::with_directions_cache(
+                self.parameters.max_angular,
+                self.parameters.cached_directions.unwrap_or(0),
+            ))
         }).borrow_mut();
 
         // Compute the three factors that appear in the center contribution.
@@ -243,7 +820,7 @@ impl SphericalExpansionByPair {
         // case where the pair distance is zero.
         radial_integral.compute(0.0, false);
         spherical_harmonics.compute(Vector3D::new(0.0, 0.0, 1.0), false);
-        let f_scaling = self.scaling_functions(0.0);
+        let f_scaling = self.scaling_functions(0.0, self.parameters.cutoff);
 
         let factor = self.parameters.center_atom_weight
             * f_scaling
@@ -257,6 +834,103 @@ impl SphericalExpansionByPair {
         };
     }
 
+    /// Get the radial integral computing gradients with respect to the atomic
+    /// gaussian width for the current thread
+    fn width_radial_integral(&self) -> std::cell::RefMut<'_, SoapRadialIntegralCache> {
+        return self.width_radial_integral.get_or(|| {
+            let radial_integral = SoapRadialIntegralCache::with_width_gradients(
+                self.parameters.radial_basis.clone(),
+                SoapRadialIntegralParameters {
+                    max_radial: self.parameters.max_radial,
+                    max_angular: self.parameters.max_angular,
+                    atomic_gaussian_width: self.parameters.atomic_gaussian_width,
+                    cutoff: self.parameters.cutoff,
+                }
+            ).expect("invalid radial integral parameters");
+            return RefCell::new(radial_integral);
+        }).borrow_mut();
+    }
+
+    /// Compute the gradients of the self-contribution with respect to the
+    /// atomic gaussian width. As for the values, this is only non-zero for
+    /// `L=0`, and the result contains one entry for each `n`.
+    pub(super) fn self_contribution_width_gradients(&self) -> Result<ndarray::Array1<f64>, Error> {
+        let mut radial_integral = self.width_radial_integral();
+        let mut spherical_harmonics = self.spherical_harmonics.get_or(|| {
+            RefCell::new(SphericalHarmonicsCache::with_directions_cache(
+                self.parameters.max_angular,
+                self.parameters.cached_directions.unwrap_or(0),
+            ))
+        }).borrow_mut();
+
+        radial_integral.compute_width_gradients(0.0)?;
+        spherical_harmonics.compute(Vector3D::new(0.0, 0.0, 1.0), false);
+        let f_scaling = self.scaling_functions(0.0, self.parameters.cutoff);
+
+        let factor = self.parameters.center_atom_weight
+            * f_scaling
+            * spherical_harmonics.values[[0, 0]];
+
+        return Ok(factor * &radial_integral.width_gradients.row(0));
+    }
+
+    /// Compute the gradients with respect to the atomic gaussian width of the
+    /// contribution of a single pair, with a neighbor of species
+    /// `species_neighbor` and using the given `cutoff`. The output is stored
+    /// in `width_gradients`, with a shape of `(lm, n)`.
+    pub(super) fn width_gradients_for_pair(
+        &self,
+        distance: f64,
+        mut direction: Vector3D,
+        cutoff: f64,
+        species_neighbor: i32,
+        width_gradients: &mut ndarray::Array2<f64>,
+    ) -> Result<(), Error> {
+        if let Some(inner_cutoff) = self.parameters.inner_cutoff {
+            if distance < inner_cutoff {
+                width_gradients.fill(0.0);
+                return Ok(());
+            }
+        }
+
+        if distance < 1e-6 {
+            // see `compute_for_pair`
+            direction = Vector3D::new(0.0, 0.0, 1.0);
+        }
+
+        let mut radial_integral = self.width_radial_integral();
+        let mut spherical_harmonics = self.spherical_harmonics.get_or(|| {
+            RefCell::new(SphericalHarmonicsCache::with_directions_cache(
+                self.parameters.max_angular,
+                self.parameters.cached_directions.unwrap_or(0),
+            ))
+        }).borrow_mut();
+
+        radial_integral.compute_width_gradients(distance)?;
+        spherical_harmonics.compute(direction, false);
+
+        // the species dependent weight does not depend on the gaussian width
+        let mut f_scaling = self.scaling_functions(distance, cutoff);
+        if let Some(ref species_weight) = self.parameters.species_distance_weight {
+            f_scaling *= (species_weight.weight)(species_neighbor, distance);
+        }
+
+        let mut lm_index = 0;
+        for spherical_harmonics_l in 0..=self.parameters.max_angular {
+            let spherical_harmonics = spherical_harmonics.values.slice(spherical_harmonics_l as isize);
+            let radial_integral = radial_integral.width_gradients.slice(s![spherical_harmonics_l, ..]);
+
+            for sph_value in spherical_harmonics.iter() {
+                for (n, ri_value) in radial_integral.iter().enumerate() {
+                    width_gradients[[lm_index, n]] = f_scaling * sph_value * ri_value;
+                }
+                lm_index += 1;
+            }
+        }
+
+        return Ok(());
+    }
+
     /// Accumulate the self contribution to the spherical expansion
     /// coefficients.
     ///
@@ -319,14 +993,39 @@ impl SphericalExpansionByPair {
     /// expansion with `pair.second` as the center and `pair.first` as the
     /// neighbor.
     pub(super) fn compute_for_pair(
+        &self,
+        distance: f64,
+        direction: Vector3D,
+        do_gradients: GradientsOptions,
+        contribution: &mut PairContribution,
+    ) {
+        self.compute_for_pair_with_cutoff(distance, direction, self.parameters.cutoff, do_gradients, contribution);
+    }
+
+    /// Same as `compute_for_pair`, applying the cutoff function at the given
+    /// `cutoff` instead of the global cutoff. This is used for species pairs
+    /// with a separate cutoff in `cutoff_per_species`.
+    pub(super) fn compute_for_pair_with_cutoff(
         &self,
         distance: f64,
         mut direction: Vector3D,
+        cutoff: f64,
         do_gradients: GradientsOptions,
         contribution: &mut PairContribution,
     ) {
         debug_assert!(distance >= 0.0);
 
+        if let Some(inner_cutoff) = self.parameters.inner_cutoff {
+            if distance < inner_cutoff {
+                // neighbors inside the inner cutoff do not contribute
+                contribution.values.fill(0.0);
+                if let Some(ref mut gradients) = contribution.gradients {
+                    gradients.fill(0.0);
+                }
+                return;
+            }
+        }
+
         // Deal with the possibility that two atoms are at the same
         // position. While this is not usual, there is no reason to
         // prevent the calculation of spherical expansion. The user will
@@ -350,14 +1049,17 @@ impl SphericalExpansionByPair {
         }).borrow_mut();
 
         let mut spherical_harmonics = self.spherical_harmonics.get_or(|| {
-            RefCell::new(SphericalHarmonicsCache::new(self.parameters.max_angular))
+            RefCell::new(SphericalHarmonicsCache::with_directions_cache(
+                self.parameters.max_angular,
+                self.parameters.cached_directions.unwrap_or(0),
+            ))
         }).borrow_mut();
 
         radial_integral.compute(distance, do_gradients.either());
         spherical_harmonics.compute(direction, do_gradients.either());
 
-        let f_scaling = self.scaling_functions(distance);
-        let f_scaling_grad = self.scaling_functions_gradient(distance);
+        let f_scaling = self.scaling_functions(distance, cutoff);
+        let f_scaling_grad = self.scaling_functions_gradient(distance, cutoff);
 
         let mut lm_index = 0;
         let mut lm_index_grad = 0;
@@ -381,40 +1083,87 @@ impl SphericalExpansionByPair {
             }
 
             if let Some(ref mut gradient) = contribution.gradients {
-                let dr_d_spatial = direction;
-
-                for m in 0..(2 * spherical_harmonics_l + 1) {
-                    let sph_value = spherical_harmonics[m];
-                    let sph_grad_x = spherical_harmonics_grad[0][m];
-                    let sph_grad_y = spherical_harmonics_grad[1][m];
-                    let sph_grad_z = spherical_harmonics_grad[2][m];
-
-                    for n in 0..self.parameters.max_radial {
-                        let ri_value = radial_integral[n];
-                        let ri_grad = radial_integral_grad[n];
-
-                        gradient[[0, lm_index_grad, n]] =
-                            f_scaling_grad * dr_d_spatial[0] * ri_value * sph_value
-                            + f_scaling * ri_grad * dr_d_spatial[0] * sph_value
-                            + f_scaling * ri_value * sph_grad_x / distance;
-
-                        gradient[[1, lm_index_grad, n]] =
-                            f_scaling_grad * dr_d_spatial[1] * ri_value * sph_value
-                            + f_scaling * ri_grad * dr_d_spatial[1] * sph_value
-                            + f_scaling * ri_value * sph_grad_y / distance;
-
-                        gradient[[2, lm_index_grad, n]] =
-                            f_scaling_grad * dr_d_spatial[2] * ri_value * sph_value
-                            + f_scaling * ri_grad * dr_d_spatial[2] * sph_value
-                            + f_scaling * ri_value * sph_grad_z / distance;
+                let n_m = 2 * spherical_harmonics_l + 1;
+                for n in 0..self.parameters.max_radial {
+                    let ri_value = radial_integral[n];
+                    let ri_grad = radial_integral_grad[n];
+
+                    // the radial parts of the gradient only depend on n, and
+                    // are shared by all m for the current l
+                    let radial_factor = f_scaling_grad * ri_value + f_scaling * ri_grad;
+                    let angular_factor = f_scaling * ri_value / distance;
+
+                    for m in 0..n_m {
+                        let sph_value = radial_factor * spherical_harmonics[m];
+
+                        gradient[[0, lm_index_grad + m, n]] =
+                            sph_value * direction[0]
+                            + angular_factor * spherical_harmonics_grad[0][m];
+
+                        gradient[[1, lm_index_grad + m, n]] =
+                            sph_value * direction[1]
+                            + angular_factor * spherical_harmonics_grad[1][m];
+
+                        gradient[[2, lm_index_grad + m, n]] =
+                            sph_value * direction[2]
+                            + angular_factor * spherical_harmonics_grad[2][m];
                     }
-
-                    lm_index_grad += 1;
                 }
+
+                lm_index_grad += n_m;
             }
         }
     }
 
+    /// Compute separately the radial and angular parts of the contribution
+    /// of a single pair.
+    ///
+    /// The radial part includes the cutoff function and radial scaling, and
+    /// has shape `(l, n)`. The angular part contains the spherical harmonics
+    /// `Y_lm` for each `l`, for `m` from `-l` to `l`. The pair contribution
+    /// computed by `compute_for_pair` is the product of these two.
+    pub(super) fn radial_angular_for_pair(
+        &self,
+        distance: f64,
+        mut direction: Vector3D,
+    ) -> (ndarray::Array2<f64>, Vec<ndarray::Array1<f64>>) {
+        debug_assert!(distance >= 0.0);
+        if distance < 1e-6 {
+            // see `compute_for_pair`
+            direction = Vector3D::new(0.0, 0.0, 1.0);
+        }
+
+        let mut radial_integral = self.radial_integral.get_or(|| {
+            let radial_integral = SoapRadialIntegralCache::new(
+                self.parameters.radial_basis.clone(),
+                SoapRadialIntegralParameters {
+                    max_radial: self.parameters.max_radial,
+                    max_angular: self.parameters.max_angular,
+                    atomic_gaussian_width: self.parameters.atomic_gaussian_width,
+                    cutoff: self.parameters.cutoff,
+                }
+            ).expect("invalid parameters");
+            return RefCell::new(radial_integral);
+        }).borrow_mut();
+
+        let mut spherical_harmonics = self.spherical_harmonics.get_or(|| {
+            RefCell::new(SphericalHarmonicsCache::with_directions_cache(
+                self.parameters.max_angular,
+                self.parameters.cached_directions.unwrap_or(0),
+            ))
+        }).borrow_mut();
+
+        radial_integral.compute(distance, false);
+        spherical_harmonics.compute(direction, false);
+
+        let radial = self.scaling_functions(distance, self.parameters.cutoff) * &radial_integral.values;
+        let angular = (0..=self.parameters.max_angular)
+            .map(|l| spherical_harmonics.values.slice(l as isize).to_owned())
+            .collect();
+
+        return (radial, angular);
+    }
+
     /// Accumulate a single pair `contribution` in the right block.
     fn accumulate_in_block(
         spherical_harmonics_l: usize,
@@ -546,8 +1295,9 @@ impl CalculatorBase for SphericalExpansionByPair {
             "species_atom_2"
         ]);
 
+        let spherical_harmonics_l = self.parameters.spherical_harmonics_l();
         for (s1, s2) in all_species_pairs {
-            for l in 0..=self.parameters.max_angular {
+            for &l in &spherical_harmonics_l {
                 keys.add(&[l.into(), s1, s2]);
             }
         }
@@ -700,11 +1450,18 @@ impl CalculatorBase for SphericalExpansionByPair {
     fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
         assert_eq!(descriptor.keys().names(), ["spherical_harmonics_l", "species_atom_1", "species_atom_2"]);
 
+        if self.parameters.velocity_weight.is_some() {
+            return Err(Error::InvalidParameter(
+                "velocity dependent weights are not supported by the spherical expansion by pair".into()
+            ));
+        }
+
         let do_gradients = GradientsOptions {
             positions: descriptor.block_by_id(0).gradient("positions").is_some(),
             cell: descriptor.block_by_id(0).gradient("cell").is_some(),
         };
 
+        self.clear_spherical_harmonics_cache();
         self.do_self_contributions(systems, descriptor)?;
 
         let keys = descriptor.keys().clone();
@@ -712,6 +1469,9 @@ impl CalculatorBase for SphericalExpansionByPair {
         let max_angular = self.parameters.max_angular;
         let max_radial = self.parameters.max_radial;
         let mut contribution = PairContribution::new(max_radial, max_angular, do_gradients.either());
+        let species_weighted = self.parameters.species_distance_weight.is_some();
+        let mut unweighted = PairContribution::new(max_radial, max_angular, do_gradients.either());
+        let per_species_cutoff = !self.parameters.cutoff_per_species.is_empty();
 
         for (system_i, system) in systems.iter_mut().enumerate() {
             system.compute_neighbors(self.parameters.cutoff)?;
@@ -731,7 +1491,16 @@ impl CalculatorBase for SphericalExpansionByPair {
 
             for (pair_id, pair) in system.pairs()?.iter().enumerate() {
                 let direction = pair.vector / pair.distance;
-                self.compute_for_pair(pair.distance, direction, do_gradients, &mut contribution);
+                let species_first = species[pair.first];
+                let species_second = species[pair.second];
+
+                let cutoff = self.parameters.pair_cutoff(species_first, species_second);
+                self.compute_for_pair_with_cutoff(pair.distance, direction, cutoff, do_gradients, &mut contribution);
+
+                if species_weighted {
+                    unweighted.assign(&contribution);
+                    self.apply_species_weight(&mut contribution, species_second, pair.distance, direction);
+                }
 
                 let inverse_cell_pair_vector = Vector3D::new(
                     pair.vector[0] * inverse_cell[0][0] + pair.vector[1] * inverse_cell[1][0] + pair.vector[2] * inverse_cell[2][0],
@@ -739,8 +1508,6 @@ impl CalculatorBase for SphericalExpansionByPair {
                     pair.vector[0] * inverse_cell[0][2] + pair.vector[1] * inverse_cell[1][2] + pair.vector[2] * inverse_cell[2][2],
                 );
 
-                let species_first = species[pair.first];
-                let species_second = species[pair.second];
                 for spherical_harmonics_l in 0..=self.parameters.max_angular {
                     let block_i = keys.position(&[
                         spherical_harmonics_l.into(),
@@ -774,7 +1541,19 @@ impl CalculatorBase for SphericalExpansionByPair {
                     continue;
                 }
 
-                contribution.inverse_pair(&self.m_1_pow_l);
+                if per_species_cutoff {
+                    // the cutoff can be different for the reversed pair, so
+                    // we need to compute the contribution again
+                    let cutoff = self.parameters.pair_cutoff(species_second, species_first);
+                    self.compute_for_pair_with_cutoff(pair.distance, -direction, cutoff, do_gradients, &mut contribution);
+                    self.apply_species_weight(&mut contribution, species_first, pair.distance, -direction);
+                } else if species_weighted {
+                    std::mem::swap(&mut contribution, &mut unweighted);
+                    contribution.inverse_pair(&self.m_1_pow_l);
+                    self.apply_species_weight(&mut contribution, species_first, pair.distance, -direction);
+                } else {
+                    contribution.inverse_pair(&self.m_1_pow_l);
+                }
 
                 for spherical_harmonics_l in 0..=self.parameters.max_angular {
                     let block_i = keys.position(&[
@@ -828,16 +1607,140 @@ mod tests {
     fn parameters() -> SphericalExpansionParameters {
         SphericalExpansionParameters {
             cutoff: 3.5,
+            inner_cutoff: None,
+            cutoff_per_species: Default::default(),
             max_radial: 6,
             max_angular: 6,
+            angular_channels: None,
             atomic_gaussian_width: 0.3,
             center_atom_weight: 1.0,
             radial_basis: RadialBasis::splined_gto(1e-8),
             radial_scaling: RadialScaling::Willatt2018 { scale: 1.5, rate: 0.8, exponent: 2},
             cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            species_occupations: Default::default(),
+            all_species: None,
+            cached_directions: None,
+            species_distance_weight: None,
+            velocity_weight: None,
         }
     }
 
+    #[test]
+    fn invalid_parameters() {
+        let mut invalid = Vec::new();
+
+        let mut parameters = self::parameters();
+        parameters.cutoff = -3.5;
+        invalid.push((parameters, "cutoff"));
+
+        let mut parameters = self::parameters();
+        parameters.cutoff = f64::NAN;
+        invalid.push((parameters, "cutoff"));
+
+        let mut parameters = self::parameters();
+        parameters.max_radial = 0;
+        invalid.push((parameters, "max_radial"));
+
+        let mut parameters = self::parameters();
+        parameters.atomic_gaussian_width = 0.0;
+        invalid.push((parameters, "atomic_gaussian_width"));
+
+        let mut parameters = self::parameters();
+        parameters.atomic_gaussian_width = f64::NAN;
+        invalid.push((parameters, "atomic_gaussian_width"));
+
+        let mut parameters = self::parameters();
+        parameters.center_atom_weight = f64::INFINITY;
+        invalid.push((parameters, "center_atom_weight"));
+
+        let mut parameters = self::parameters();
+        parameters.cutoff_function = CutoffFunction::ShiftedCosine { width: 4.0 };
+        invalid.push((parameters, "cutoff_function width"));
+
+        let mut parameters = self::parameters();
+        parameters.cutoff_function = CutoffFunction::ShiftedCosine { width: f64::NAN };
+        invalid.push((parameters, "width for shifted cosine"));
+
+        let mut parameters = self::parameters();
+        parameters.angular_channels = Some(vec![]);
+        invalid.push((parameters, "angular_channels"));
+
+        let mut parameters = self::parameters();
+        parameters.angular_channels = Some(vec![0, 7]);
+        invalid.push((parameters, "angular_channels"));
+
+        let mut messages = Vec::new();
+        for (parameters, expected) in invalid {
+            let error = SphericalExpansion::new(parameters).unwrap_err();
+            assert!(matches!(error, crate::Error::InvalidParameter(_)));
+
+            let message = error.to_string();
+            assert!(message.contains(expected), "'{}' does not mention '{}'", message, expected);
+            assert!(!messages.contains(&message), "duplicated error message '{}'", message);
+            messages.push(message);
+        }
+
+        // negative weights for the central atom are allowed
+        let mut parameters = self::parameters();
+        parameters.center_atom_weight = -1.0;
+        SphericalExpansion::new(parameters).unwrap();
+    }
+
+    #[test]
+    fn builder() {
+        let built = SphericalExpansionParameters::builder()
+            .cutoff(3.5)
+            .max_radial(6)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .radial_scaling(RadialScaling::Willatt2018 { scale: 1.5, rate: 0.8, exponent: 2})
+            .build()
+            .unwrap();
+
+        let expected = parameters();
+        assert_eq!(
+            serde_json::to_value(&built).unwrap(),
+            serde_json::to_value(&expected).unwrap(),
+        );
+
+        let built = SphericalExpansionParameters::builder()
+            .cutoff(3.5)
+            .max_radial(6)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .build()
+            .unwrap();
+        assert_eq!(built.center_atom_weight, 1.0);
+        assert!(matches!(built.radial_scaling, RadialScaling::None {}));
+        assert!(matches!(built.cutoff_function, CutoffFunction::ShiftedCosine { width } if width == 0.5));
+
+        let error = SphericalExpansionParameters::builder()
+            .max_radial(6)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: missing required parameter 'cutoff' for the spherical expansion");
+
+        let error = SphericalExpansionParameters::builder()
+            .cutoff(-3.5)
+            .max_radial(6)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: cutoff must be a positive number, got -3.5");
+
+        let error = SphericalExpansionParameters::builder()
+            .cutoff(3.5)
+            .max_radial(0)
+            .max_angular(6)
+            .atomic_gaussian_width(0.3)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: max_radial must be at least 1");
+    }
+
     #[test]
     fn finite_differences_positions() {
         let calculator = Calculator::from(Box::new(SphericalExpansionByPair::new(
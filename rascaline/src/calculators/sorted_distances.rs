@@ -12,9 +12,10 @@ use crate::labels::{KeysBuilder, CenterSpeciesKeys, CenterSingleNeighborsSpecies
 /// Sorted distances vector representation of an atomic environment.
 ///
 /// Each atomic center is represented by a vector of distance to its neighbors
-/// within the spherical `cutoff`, sorted from smallest to largest. If there are
-/// less neighbors than `max_neighbors`, the remaining entries are filled with
-/// `cutoff` instead.
+/// within the spherical `cutoff`, sorted from smallest to largest. Centers with
+/// more than `max_neighbors` neighbors only keep the closest ones, and if there
+/// are less neighbors than `max_neighbors`, the remaining entries are filled
+/// with `pad_value` (or `cutoff` if `pad_value` is not given) instead.
 ///
 /// Separate species for neighbors are represented separately, meaning that the
 /// `max_neighbors` parameter only apply to a single species.
@@ -26,6 +27,10 @@ pub struct SortedDistances {
     max_neighbors: usize,
     /// Should separate neighbor species be represented separately?
     separate_neighbor_species: bool,
+    /// Value used to fill the distances vector of centers with less than
+    /// `max_neighbors` neighbors. If this is not given, `cutoff` is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pad_value: Option<f64>,
 }
 
 impl CalculatorBase for SortedDistances {
@@ -42,6 +47,7 @@ impl CalculatorBase for SortedDistances {
             let builder = CenterSingleNeighborsSpeciesKeys {
                 cutoff: self.cutoff,
                 self_pairs: false,
+                all_species: None,
             };
             return builder.keys(systems);
         }
@@ -63,6 +69,8 @@ impl CalculatorBase for SortedDistances {
                     species_center: SpeciesFilter::Single(species_center.i32()),
                     species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                     self_pairs: false,
+                    selected_centers: None,
+                    inner_cutoff: None,
                 };
 
                 samples.push(builder.samples(systems)?);
@@ -75,6 +83,8 @@ impl CalculatorBase for SortedDistances {
                     species_center: SpeciesFilter::Single(species_center.i32()),
                     species_neighbor: SpeciesFilter::Any,
                     self_pairs: false,
+                    selected_centers: None,
+                    inner_cutoff: None,
                 };
 
                 samples.push(builder.samples(systems)?);
@@ -156,7 +166,7 @@ impl CalculatorBase for SortedDistances {
                 // Sort, resize to limit to at most `self.max_neighbors` values
                 // and pad the distance vectors as needed
                 distances.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
-                distances.resize(self.max_neighbors, self.cutoff);
+                distances.resize(self.max_neighbors, self.pad_value.unwrap_or(self.cutoff));
 
                 for (property_i, [neighbor]) in block_data.properties.iter_fixed_size().enumerate() {
                     array[[sample_i, property_i]] = distances[neighbor.usize()];
@@ -184,7 +194,8 @@ mod tests {
         let calculator = Calculator::from(Box::new(SortedDistances{
             cutoff: 1.5,
             max_neighbors: 3,
-            separate_neighbor_species: false
+            separate_neighbor_species: false,
+            pad_value: None,
         }) as Box<dyn CalculatorBase>);
 
         assert_eq!(calculator.name(), "sorted distances vector");
@@ -196,7 +207,8 @@ mod tests {
         let mut calculator = Calculator::from(Box::new(SortedDistances {
             cutoff: 1.5,
             max_neighbors: 3,
-            separate_neighbor_species: false
+            separate_neighbor_species: false,
+            pad_value: None,
         }) as Box<dyn CalculatorBase>);
 
         let mut systems = test_systems(&["water"]);
@@ -215,12 +227,56 @@ mod tests {
         assert_eq!(values.slice(s![2, ..]), aview1(&[0.957897074324794, 1.5, 1.5]));
     }
 
+    #[test]
+    fn padding() {
+        let mut calculator = Calculator::from(Box::new(SortedDistances {
+            cutoff: 1.5,
+            max_neighbors: 4,
+            separate_neighbor_species: true,
+            pad_value: Some(-1.0),
+        }) as Box<dyn CalculatorBase>);
+
+        let mut systems = test_systems(&["water"]);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        // oxygen center, hydrogen neighbors
+        let block = descriptor.block(&Labels::new(["species_center", "species_neighbor"], &[[-42, 1]])).unwrap();
+        let values = block.values().to_array();
+        assert_eq!(values.shape(), [1, 4]);
+        assert_eq!(values.slice(s![0, ..]), aview1(&[0.957897074324794, 0.957897074324794, -1.0, -1.0]));
+
+        // hydrogen center, oxygen neighbor
+        let block = descriptor.block(&Labels::new(["species_center", "species_neighbor"], &[[1, -42]])).unwrap();
+        let values = block.values().to_array();
+        assert_eq!(values.shape(), [2, 4]);
+        assert_eq!(values.slice(s![0, ..]), aview1(&[0.957897074324794, -1.0, -1.0, -1.0]));
+        assert_eq!(values.slice(s![1, ..]), aview1(&[0.957897074324794, -1.0, -1.0, -1.0]));
+
+        // only keep the closest neighbor
+        let mut calculator = Calculator::from(Box::new(SortedDistances {
+            cutoff: 1.5,
+            max_neighbors: 1,
+            separate_neighbor_species: false,
+            pad_value: Some(-1.0),
+        }) as Box<dyn CalculatorBase>);
+
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        for (_, block) in descriptor.iter() {
+            let values = block.values().to_array();
+            assert_eq!(values.shape()[1], 1);
+            for &value in values {
+                assert_eq!(value, 0.957897074324794);
+            }
+        }
+    }
+
     #[test]
     fn compute_partial() {
         let calculator = Calculator::from(Box::new(SortedDistances{
             cutoff: 1.5,
             max_neighbors: 3,
             separate_neighbor_species: false,
+            pad_value: None,
         }) as Box<dyn CalculatorBase>);
 
         let mut systems = test_systems(&["water"]);
@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+
+use ndarray::Array3;
+
+use equistore::{Labels, LabelsBuilder, TensorMap};
+
+use super::CalculatorBase;
+use super::soap::third_order_invariant;
+
+use crate::{Error, System};
+use crate::math::SphericalHarmonicsCache;
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterSpeciesKeys};
+
+/// Parameters for the Steinhardt bond-order parameters calculator.
+///
+/// For each atom `i`, this calculator first computes the average of the
+/// spherical harmonics over all the `N` neighbors within the cutoff: `q_lm =
+/// 1/N \sum_j Y_lm(r_ij)`, and then the rotationally invariant
+///
+/// `Q_l = sqrt(4π/(2l + 1) \sum_m |q_lm|^2)`
+///
+/// If `include_w` is `true`, this calculator also computes the normalized
+/// third-order invariants
+///
+/// `W_l = \sum_{m1 m2 m3} (l l l; m1 m2 m3) q_lm1 q_lm2 q_lm3 / (\sum_m |q_lm|^2)^{3/2}`
+///
+/// where `(l l l; m1 m2 m3)` are Wigner 3j symbols.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct SteinhardtParameters {
+    /// Spherical cutoff used to find the neighbors of each atom
+    pub cutoff: f64,
+    /// Maximal angular degree `l` to compute
+    pub max_angular: usize,
+    /// Should we also compute the third-order `W_l` invariants?
+    #[serde(default)]
+    pub include_w: bool,
+}
+
+/// Calculator computing the Steinhardt bond-order parameters `Q_l` (and
+/// optionally `W_l`) of each atom.
+///
+/// The keys contains the central atom species, and the properties the angular
+/// degree `l`. When computing `W_l`, the properties also contain the order of
+/// the invariant: 2 for `Q_l`, and 3 for `W_l`.
+#[derive(Debug)]
+pub struct SteinhardtOrderParameters {
+    parameters: SteinhardtParameters,
+}
+
+impl SteinhardtOrderParameters {
+    pub fn new(parameters: SteinhardtParameters) -> Result<SteinhardtOrderParameters, Error> {
+        if !(parameters.cutoff > 0.0 && parameters.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number, got {}", parameters.cutoff
+            )));
+        }
+
+        return Ok(SteinhardtOrderParameters { parameters: parameters });
+    }
+
+    /// Compute the averaged spherical harmonics `q_lm` for all the given
+    /// samples in a single system. The output array has shape `(n_samples,
+    /// max_angular + 1, 2 * max_angular + 1)`, and `q_lm` is stored at
+    /// `[sample, l, max_angular + m]`.
+    fn averaged_spherical_harmonics(&self, system: &mut dyn System, centers: &BTreeMap<usize, usize>, q_lm: &mut Array3<f64>) -> Result<(), Error> {
+        let max_angular = self.parameters.max_angular;
+        let mut spherical_harmonics = SphericalHarmonicsCache::new(max_angular);
+
+        system.compute_neighbors(self.parameters.cutoff)?;
+
+        let mut add_neighbor = |sample_i: usize, direction| {
+            spherical_harmonics.compute(direction, false);
+            for l in 0..=max_angular {
+                let l_isize = l as isize;
+                for m in -l_isize..=l_isize {
+                    let m_i = (max_angular as isize + m) as usize;
+                    q_lm[[sample_i, l, m_i]] += spherical_harmonics.values[[l_isize, m]];
+                }
+            }
+        };
+
+        let mut n_neighbors = BTreeMap::new();
+        for pair in system.pairs()? {
+            let direction = pair.vector / pair.distance;
+
+            if let Some(&sample_i) = centers.get(&pair.first) {
+                add_neighbor(sample_i, direction);
+                *n_neighbors.entry(sample_i).or_insert(0_u32) += 1;
+            }
+
+            if pair.first == pair.second {
+                // the reversed pair is also part of the neighbor list
+                continue;
+            }
+
+            if let Some(&sample_i) = centers.get(&pair.second) {
+                add_neighbor(sample_i, -direction);
+                *n_neighbors.entry(sample_i).or_insert(0_u32) += 1;
+            }
+        }
+
+        for (sample_i, count) in n_neighbors {
+            let mut q_lm = q_lm.index_axis_mut(ndarray::Axis(0), sample_i);
+            q_lm /= f64::from(count);
+        }
+
+        return Ok(());
+    }
+}
+
+impl CalculatorBase for SteinhardtOrderParameters {
+    fn name(&self) -> String {
+        "Steinhardt bond-order parameters".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        return CenterSpeciesKeys.keys(systems);
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center"]);
+        let mut result = Vec::new();
+        for [species_center] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Any,
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn supports_gradient(&self, _parameter: &str) -> bool {
+        return false;
+    }
+
+    fn positions_gradient_samples(&self, _: &Labels, _: &[Labels], _: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        return Err(Error::InvalidParameter(
+            "the Steinhardt bond-order parameters calculator does not support gradients".into()
+        ));
+    }
+
+    fn components(&self, keys: &Labels) -> Vec<Vec<Labels>> {
+        return vec![Vec::new(); keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        if self.parameters.include_w {
+            vec!["order", "l"]
+        } else {
+            vec!["l"]
+        }
+    }
+
+    fn properties(&self, keys: &Labels) -> Vec<Labels> {
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        if self.parameters.include_w {
+            for order in [2, 3] {
+                for l in 0..=self.parameters.max_angular {
+                    properties.add(&[order, l]);
+                }
+            }
+        } else {
+            for l in 0..=self.parameters.max_angular {
+                properties.add(&[l]);
+            }
+        }
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "SteinhardtOrderParameters::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["species_center"]);
+        let max_angular = self.parameters.max_angular;
+
+        for (_, mut block) in descriptor.iter_mut() {
+            let samples = block.samples();
+            let properties = block.properties();
+
+            let mut q_lm = Array3::zeros((samples.count(), max_angular + 1, 2 * max_angular + 1));
+            for (system_i, system) in systems.iter_mut().enumerate() {
+                let mut centers = BTreeMap::new();
+                for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                    if structure.usize() == system_i {
+                        centers.insert(center.usize(), sample_i);
+                    }
+                }
+
+                if centers.is_empty() {
+                    continue;
+                }
+
+                self.averaged_spherical_harmonics(&mut **system, &centers, &mut q_lm)?;
+            }
+
+            let values = block.values_mut().to_array_mut();
+            for (property_i, property) in properties.iter().enumerate() {
+                let (order, l) = if self.parameters.include_w {
+                    (property[0].usize(), property[1].usize())
+                } else {
+                    (2, property[0].usize())
+                };
+
+                let start = max_angular - l;
+                let stop = max_angular + l;
+                for sample_i in 0..samples.count() {
+                    let q_l = q_lm.slice(ndarray::s![sample_i, l, start..=stop]);
+                    let norm2 = q_l.dot(&q_l);
+
+                    values[[sample_i, property_i]] = if order == 2 {
+                        f64::sqrt(4.0 * std::f64::consts::PI / (2 * l + 1) as f64 * norm2)
+                    } else if norm2 > 0.0 {
+                        third_order_invariant(l, q_l) / (norm2 * f64::sqrt(norm2))
+                    } else {
+                        0.0
+                    };
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use equistore::LabelValue;
+
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, Matrix3, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+
+    use super::{SteinhardtOrderParameters, SteinhardtParameters};
+
+    fn fcc(rotation: [Vector3D; 3]) -> SimpleSystem {
+        let a = 4.0;
+        let mut system = SimpleSystem::new(UnitCell::from(Matrix3::new([
+            [a * rotation[0][0], a * rotation[0][1], a * rotation[0][2]],
+            [a * rotation[1][0], a * rotation[1][1], a * rotation[1][2]],
+            [a * rotation[2][0], a * rotation[2][1], a * rotation[2][2]],
+        ])));
+
+        for [x, y, z] in [[0.0, 0.0, 0.0], [0.5, 0.5, 0.0], [0.5, 0.0, 0.5], [0.0, 0.5, 0.5]] {
+            system.add_atom(29, (rotation[0] * x + rotation[1] * y + rotation[2] * z) * a);
+        }
+
+        return system;
+    }
+
+    fn calculator(include_w: bool) -> Calculator {
+        return Calculator::from(Box::new(SteinhardtOrderParameters::new(
+            SteinhardtParameters {
+                // between the first and second neighbors shells
+                cutoff: 3.2,
+                max_angular: 6,
+                include_w: include_w,
+            }
+        ).unwrap()) as Box<dyn CalculatorBase>);
+    }
+
+    #[test]
+    fn fcc_values() {
+        let identity = [
+            Vector3D::new(1.0, 0.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            Vector3D::new(0.0, 0.0, 1.0),
+        ];
+        let mut systems = [Box::new(fcc(identity)) as Box<dyn System>];
+
+        let descriptor = calculator(true).compute(&mut systems, Default::default()).unwrap();
+        assert_eq!(descriptor.keys().count(), 1);
+
+        let block = descriptor.block_by_id(0);
+        assert_eq!(block.samples().count(), 4);
+
+        let properties = block.properties();
+        let position = |order: i32, l: i32| {
+            properties.position(&[LabelValue::new(order), LabelValue::new(l)]).unwrap()
+        };
+
+        let values = block.values().to_array();
+        for sample_i in 0..4 {
+            assert_relative_eq!(values[[sample_i, position(2, 0)]], 1.0, max_relative=1e-12);
+            assert_relative_eq!(values[[sample_i, position(2, 2)]], 0.0, epsilon=1e-12);
+            assert_relative_eq!(values[[sample_i, position(2, 4)]], 0.190941, max_relative=1e-5);
+            assert_relative_eq!(values[[sample_i, position(2, 6)]], 0.574524, max_relative=1e-5);
+
+            assert_relative_eq!(values[[sample_i, position(3, 4)]], -0.159317, max_relative=1e-5);
+            assert_relative_eq!(values[[sample_i, position(3, 6)]], -0.013161, max_relative=1e-4);
+        }
+    }
+
+    #[test]
+    fn rotation_invariance() {
+        let identity = [
+            Vector3D::new(1.0, 0.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            Vector3D::new(0.0, 0.0, 1.0),
+        ];
+
+        let (sin, cos) = f64::sin_cos(0.3);
+        let rotation = [
+            Vector3D::new(cos, sin, 0.0),
+            Vector3D::new(-sin * cos, cos * cos, sin),
+            Vector3D::new(sin * sin, -cos * sin, cos),
+        ];
+
+        let mut calculator = calculator(true);
+
+        let mut systems = [Box::new(fcc(identity)) as Box<dyn System>];
+        let reference = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let mut systems = [Box::new(fcc(rotation)) as Box<dyn System>];
+        let rotated = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        assert_relative_eq!(
+            reference.block_by_id(0).values().to_array(),
+            rotated.block_by_id(0).values().to_array(),
+            max_relative=1e-10,
+            epsilon=1e-12,
+        );
+    }
+
+    #[test]
+    fn properties() {
+        let mut calculator = calculator(false);
+        let mut systems = [Box::new(fcc([
+            Vector3D::new(1.0, 0.0, 0.0),
+            Vector3D::new(0.0, 1.0, 0.0),
+            Vector3D::new(0.0, 0.0, 1.0),
+        ])) as Box<dyn System>];
+
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        let block = descriptor.block_by_id(0);
+        assert_eq!(block.properties().names(), ["l"]);
+        assert_eq!(block.properties().count(), 7);
+    }
+}
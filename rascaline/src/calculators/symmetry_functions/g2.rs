@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+
+use equistore::{Labels, LabelsBuilder, TensorMap};
+
+use crate::calculators::CalculatorBase;
+use crate::calculators::soap::CutoffFunction;
+use crate::{Error, System, Vector3D};
+
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterSingleNeighborsSpeciesKeys};
+
+/// Parameters of a single G2 function
+#[derive(Debug, Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct G2Function {
+    /// Width parameter of the gaussian
+    pub eta: f64,
+    /// Center of the gaussian
+    pub rs: f64,
+}
+
+/// Parameters for the Behler-Parrinello G2 radial symmetry functions.
+///
+/// The G2 functions are defined as
+///
+/// `G2_i = \sum_j exp(-eta (r_ij - rs)^2) f_c(r_ij)`
+///
+/// where the sum runs over all neighbors `j` of a given species within the
+/// cutoff, and `f_c` is the cutoff function. Each `(eta, rs)` pair in
+/// `functions` gives one property, indexed by its position in the list.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct BehlerG2Parameters {
+    /// Spherical cutoff to use for atomic environments
+    pub cutoff: f64,
+    /// cutoff function `f_c` used to smooth the behavior around the cutoff
+    /// radius
+    pub cutoff_function: CutoffFunction,
+    /// list of `(eta, rs)` parameters for the G2 functions
+    pub functions: Vec<G2Function>,
+}
+
+/// Calculator implementing the Behler-Parrinello G2 radial symmetry functions
+#[derive(Debug)]
+pub struct BehlerG2 {
+    parameters: BehlerG2Parameters,
+}
+
+impl BehlerG2 {
+    pub fn new(parameters: BehlerG2Parameters) -> Result<BehlerG2, Error> {
+        if !(parameters.cutoff > 0.0 && parameters.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number, got {}", parameters.cutoff
+            )));
+        }
+
+        parameters.cutoff_function.validate()?;
+
+        if parameters.functions.is_empty() {
+            return Err(Error::InvalidParameter(
+                "expected at least one G2 function".into()
+            ));
+        }
+
+        for function in &parameters.functions {
+            if !(function.eta > 0.0 && function.eta.is_finite()) {
+                return Err(Error::InvalidParameter(format!(
+                    "eta must be a positive number for G2 functions, got {}", function.eta
+                )));
+            }
+
+            if !function.rs.is_finite() {
+                return Err(Error::InvalidParameter(format!(
+                    "rs must be a finite number for G2 functions, got {}", function.rs
+                )));
+            }
+        }
+
+        return Ok(BehlerG2 { parameters: parameters });
+    }
+
+    /// Get all the neighbors of `center` with the given `species_neighbor` as
+    /// `(neighbor, distance, direction)`, where `direction` is the unit vector
+    /// going from the center to the neighbor.
+    fn neighbors(&self, system: &mut dyn System, center: usize, species_neighbor: i32) -> Result<Vec<(usize, f64, Vector3D)>, Error> {
+        system.compute_neighbors(self.parameters.cutoff)?;
+        let species = system.species()?;
+
+        let mut neighbors = Vec::new();
+        for pair in system.pairs_containing(center)? {
+            let (neighbor, vector) = if pair.first == center {
+                (pair.second, pair.vector)
+            } else {
+                debug_assert_eq!(pair.second, center);
+                (pair.first, -pair.vector)
+            };
+
+            if species[neighbor] == species_neighbor {
+                neighbors.push((neighbor, pair.distance, vector / pair.distance));
+            }
+        }
+
+        return Ok(neighbors);
+    }
+}
+
+impl G2Function {
+    /// Evaluate this function and its derivative w.r.t. `r` for a single
+    /// neighbor at distance `r`
+    fn compute(&self, r: f64, cutoff: f64, cutoff_function: &CutoffFunction) -> (f64, f64) {
+        let gaussian = f64::exp(-self.eta * (r - self.rs) * (r - self.rs));
+        let gaussian_derivative = -2.0 * self.eta * (r - self.rs) * gaussian;
+
+        let fc = cutoff_function.compute(r, cutoff);
+        let fc_derivative = cutoff_function.derivative(r, cutoff);
+
+        return (gaussian * fc, gaussian_derivative * fc + gaussian * fc_derivative);
+    }
+}
+
+impl CalculatorBase for BehlerG2 {
+    fn name(&self) -> String {
+        "Behler-Parrinello G2".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        let builder = CenterSingleNeighborsSpeciesKeys {
+            cutoff: self.parameters.cutoff,
+            self_pairs: false,
+            all_species: None,
+        };
+        return builder.keys(systems);
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor"]);
+        let mut result = Vec::new();
+        for [species_center, species_neighbor] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn supports_gradient(&self, parameter: &str) -> bool {
+        match parameter {
+            "positions" => true,
+            _ => false,
+        }
+    }
+
+    fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor"]);
+        assert_eq!(keys.count(), samples.len());
+
+        let mut gradient_samples = Vec::new();
+        for ([species_center, species_neighbor], samples) in keys.iter_fixed_size().zip(samples) {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            gradient_samples.push(builder.gradients_for(systems, samples)?);
+        }
+
+        return Ok(gradient_samples);
+    }
+
+    fn components(&self, keys: &Labels) -> Vec<Vec<Labels>> {
+        return vec![Vec::new(); keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        vec!["function"]
+    }
+
+    fn properties(&self, keys: &Labels) -> Vec<Labels> {
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        for i in 0..self.parameters.functions.len() {
+            properties.add(&[i]);
+        }
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "BehlerG2::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["species_center", "species_neighbor"]);
+
+        let cutoff = self.parameters.cutoff;
+        let cutoff_function = &self.parameters.cutoff_function;
+
+        for (key, mut block) in descriptor.iter_mut() {
+            let species_neighbor = key[1].i32();
+
+            let samples = block.samples();
+            let functions = block.properties().iter_fixed_size()
+                .map(|&[function]| self.parameters.functions[function.usize()])
+                .collect::<Vec<_>>();
+
+            let mut all_neighbors = Vec::with_capacity(samples.count());
+            let values = block.values_mut().to_array_mut();
+            for (sample_i, &[structure, center]) in samples.iter_fixed_size().enumerate() {
+                let system = &mut *systems[structure.usize()];
+                let neighbors = self.neighbors(system, center.usize(), species_neighbor)?;
+
+                for &(_, distance, _) in &neighbors {
+                    for (property_i, function) in functions.iter().enumerate() {
+                        let (value, _) = function.compute(distance, cutoff, cutoff_function);
+                        values[[sample_i, property_i]] += value;
+                    }
+                }
+
+                all_neighbors.push(neighbors);
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("positions") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                let mut gradient_rows = BTreeMap::new();
+                for (grad_sample_i, &[sample_i, _, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    gradient_rows.insert((sample_i.usize(), atom.usize()), grad_sample_i);
+                }
+
+                for (sample_i, &[_, center]) in samples.iter_fixed_size().enumerate() {
+                    let center = center.usize();
+                    for &(neighbor, distance, direction) in &all_neighbors[sample_i] {
+                        if neighbor == center {
+                            // periodic image of the center, the gradients
+                            // w.r.t. the center and neighbor cancel out
+                            continue;
+                        }
+
+                        let center_row = gradient_rows[&(sample_i, center)];
+                        let neighbor_row = gradient_rows[&(sample_i, neighbor)];
+
+                        for (property_i, function) in functions.iter().enumerate() {
+                            let (_, derivative) = function.compute(distance, cutoff, cutoff_function);
+                            for d in 0..3 {
+                                array[[neighbor_row, d, property_i]] += derivative * direction[d];
+                                array[[center_row, d, property_i]] -= derivative * direction[d];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use equistore::LabelValue;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+    use crate::calculators::soap::CutoffFunction;
+
+    use super::{BehlerG2, BehlerG2Parameters, G2Function};
+
+    fn parameters() -> BehlerG2Parameters {
+        BehlerG2Parameters {
+            cutoff: 3.5,
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            functions: vec![
+                G2Function { eta: 0.5, rs: 0.0 },
+                G2Function { eta: 2.0, rs: 1.0 },
+                G2Function { eta: 1.0, rs: 2.5 },
+            ],
+        }
+    }
+
+    #[test]
+    fn values() {
+        let mut calculator = Calculator::from(Box::new(
+            BehlerG2::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 1.2));
+        system.add_atom(8, Vector3D::new(3.2, 0.0, 0.0));
+
+        let mut systems = [Box::new(system) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let block_i = descriptor.keys().position(&[LabelValue::new(1), LabelValue::new(8)]).unwrap();
+        let block = descriptor.block_by_id(block_i);
+        assert_eq!(block.samples().count(), 1);
+
+        let values = block.values().to_array();
+        let fc = CutoffFunction::ShiftedCosine { width: 0.5 };
+        for (property_i, function) in parameters().functions.iter().enumerate() {
+            let mut expected = 0.0;
+            for r in [1.2, 3.2] {
+                expected += f64::exp(-function.eta * (r - function.rs) * (r - function.rs)) * fc.compute(r, 3.5);
+            }
+            assert_relative_eq!(values[[0, property_i]], expected, max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let mut parameters = parameters();
+        parameters.functions.push(G2Function { eta: -1.0, rs: 0.0 });
+        assert!(BehlerG2::new(parameters).is_err());
+
+        let mut parameters = self::parameters();
+        parameters.functions.clear();
+        assert!(BehlerG2::new(parameters).is_err());
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let calculator = Calculator::from(Box::new(
+            BehlerG2::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-6,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+}
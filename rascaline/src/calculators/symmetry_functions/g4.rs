@@ -0,0 +1,536 @@
+use std::collections::BTreeMap;
+
+use ndarray::Array2;
+use rayon::prelude::*;
+
+use equistore::{Labels, LabelsBuilder, TensorMap};
+
+use crate::calculators::CalculatorBase;
+use crate::calculators::soap::CutoffFunction;
+use crate::{Error, System, Vector3D};
+
+use crate::labels::{SpeciesFilter, SamplesBuilder};
+use crate::labels::AtomCenteredSamples;
+use crate::labels::{KeysBuilder, CenterTwoNeighborsSpeciesKeys};
+
+/// Parameters of a single G4 function
+#[derive(Debug, Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct G4Function {
+    /// Width parameter of the gaussian
+    pub eta: f64,
+    /// Exponent controlling the angular resolution, must be at least 1
+    pub zeta: f64,
+    /// Sign of the cosine term, must be either 1 or -1
+    pub lambda: i32,
+}
+
+/// Parameters for the Behler-Parrinello G4 angular symmetry functions.
+///
+/// The G4 functions are defined as
+///
+/// `G4_i = 2^(1 - zeta) \sum_{j, k} (1 + lambda cos(theta_ijk))^zeta
+///         exp(-eta (r_ij^2 + r_ik^2 + r_jk^2)) f_c(r_ij) f_c(r_ik) f_c(r_jk)`
+///
+/// where the sum runs over all ordered pairs of neighbors `j` and `k` within
+/// the cutoff with the species given by the key, `theta_ijk` is the angle
+/// between the `i-j` and `i-k` vectors, and `f_c` is the cutoff function.
+/// Each `(eta, zeta, lambda)` triplet in `functions` gives one property,
+/// indexed by its position in the list.
+#[derive(Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct BehlerG4Parameters {
+    /// Spherical cutoff to use for atomic environments
+    pub cutoff: f64,
+    /// cutoff function `f_c` used to smooth the behavior around the cutoff
+    /// radius
+    pub cutoff_function: CutoffFunction,
+    /// list of `(eta, zeta, lambda)` parameters for the G4 functions
+    pub functions: Vec<G4Function>,
+    /// Should we include the degenerate triplets where both neighbors `j` and
+    /// `k` are the same atom (i.e. the same pair with the center) in the sum?
+    #[serde(default)]
+    pub self_pairs: bool,
+}
+
+/// Calculator implementing the Behler-Parrinello G4 angular symmetry functions
+#[derive(Debug)]
+pub struct BehlerG4 {
+    parameters: BehlerG4Parameters,
+}
+
+impl BehlerG4 {
+    pub fn new(parameters: BehlerG4Parameters) -> Result<BehlerG4, Error> {
+        if !(parameters.cutoff > 0.0 && parameters.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number, got {}", parameters.cutoff
+            )));
+        }
+
+        parameters.cutoff_function.validate()?;
+
+        if parameters.functions.is_empty() {
+            return Err(Error::InvalidParameter(
+                "expected at least one G4 function".into()
+            ));
+        }
+
+        for function in &parameters.functions {
+            if !(function.eta >= 0.0 && function.eta.is_finite()) {
+                return Err(Error::InvalidParameter(format!(
+                    "eta must be a non-negative number for G4 functions, got {}", function.eta
+                )));
+            }
+
+            if !(function.zeta >= 1.0 && function.zeta.is_finite()) {
+                return Err(Error::InvalidParameter(format!(
+                    "zeta must be a finite number larger than 1 for G4 functions, got {}", function.zeta
+                )));
+            }
+
+            if function.lambda != 1 && function.lambda != -1 {
+                return Err(Error::InvalidParameter(format!(
+                    "lambda must be either 1 or -1 for G4 functions, got {}", function.lambda
+                )));
+            }
+        }
+
+        return Ok(BehlerG4 { parameters: parameters });
+    }
+
+    /// Compute the contribution of all the triplets around `center` with
+    /// neighbors of the species in `species_neighbors`.
+    fn center_contribution(
+        &self,
+        system: &dyn System,
+        center: usize,
+        species_neighbors: (i32, i32),
+        functions: &[G4Function],
+        do_gradients: bool,
+    ) -> Result<CenterContribution, Error> {
+        let cutoff = self.parameters.cutoff;
+        let cutoff_function = &self.parameters.cutoff_function;
+        let species = system.species()?;
+
+        // all neighbors of the center, as `(neighbor, vector)` where `vector`
+        // goes from the center to the neighbor
+        let mut neighbors = Vec::new();
+        for pair in system.pairs_containing(center)? {
+            let (neighbor, vector) = if pair.first == center {
+                (pair.second, pair.vector)
+            } else {
+                debug_assert_eq!(pair.second, center);
+                (pair.first, -pair.vector)
+            };
+
+            if species[neighbor] == species_neighbors.0 || species[neighbor] == species_neighbors.1 {
+                neighbors.push((neighbor, vector));
+            }
+        }
+
+        let mut contribution = CenterContribution {
+            values: vec![0.0; functions.len()],
+            gradients: BTreeMap::new(),
+        };
+
+        for (j_i, &(j, r_ij)) in neighbors.iter().enumerate() {
+            for (k_i, &(k, r_ik)) in neighbors.iter().enumerate() {
+                let triplet_species = (species[j], species[k]);
+                if triplet_species != species_neighbors && triplet_species != (species_neighbors.1, species_neighbors.0) {
+                    continue;
+                }
+
+                if j_i == k_i && !self.parameters.self_pairs {
+                    continue;
+                }
+
+                for (property_i, function) in functions.iter().enumerate() {
+                    let (value, gradient_j, gradient_k) = function.compute(r_ij, r_ik, cutoff, cutoff_function);
+                    contribution.values[property_i] += value;
+
+                    if do_gradients {
+                        for (atom, gradient) in [(j, gradient_j), (k, gradient_k), (center, -(gradient_j + gradient_k))] {
+                            let atom_gradient = contribution.gradients.entry(atom)
+                                .or_insert_with(|| Array2::zeros((3, functions.len())));
+
+                            for d in 0..3 {
+                                atom_gradient[[d, property_i]] += gradient[d];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(contribution);
+    }
+}
+
+/// Contribution of all the triplets around a single center
+struct CenterContribution {
+    /// values of the functions
+    values: Vec<f64>,
+    /// gradients of the functions w.r.t. the position of each atom, stored as
+    /// `(direction, function)` arrays
+    gradients: BTreeMap<usize, Array2<f64>>,
+}
+
+impl G4Function {
+    /// Evaluate this function for a single triplet, where `r_ij` and `r_ik`
+    /// are the vectors going from the center to the two neighbors. This
+    /// returns the value of the function and its gradients w.r.t. `r_ij` and
+    /// `r_ik`.
+    fn compute(&self, r_ij: Vector3D, r_ik: Vector3D, cutoff: f64, cutoff_function: &CutoffFunction) -> (f64, Vector3D, Vector3D) {
+        let r_jk = r_ik - r_ij;
+        let (d_ij, d_ik, d_jk) = (r_ij.norm(), r_ik.norm(), r_jk.norm());
+
+        let cos_theta = (r_ij * r_ik) / (d_ij * d_ik);
+        // clamp to zero to prevent negative values coming from rounding errors
+        let angular = f64::max(1.0 + self.lambda as f64 * cos_theta, 0.0);
+        let angular_pow = angular.powf(self.zeta);
+        let angular_derivative = self.zeta * self.lambda as f64 * angular.powf(self.zeta - 1.0);
+
+        let gaussian = f64::exp(-self.eta * (d_ij * d_ij + d_ik * d_ik + d_jk * d_jk));
+
+        let fc_ij = cutoff_function.compute(d_ij, cutoff);
+        let fc_ik = cutoff_function.compute(d_ik, cutoff);
+        let fc_jk = cutoff_function.compute(d_jk, cutoff);
+        let fc = fc_ij * fc_ik * fc_jk;
+
+        let prefactor = f64::powf(2.0, 1.0 - self.zeta);
+        let value = prefactor * angular_pow * gaussian * fc;
+
+        // gradients of the different terms w.r.t. r_ij and r_ik
+        let cos_grad_ij = r_ik / (d_ij * d_ik) - r_ij * (cos_theta / (d_ij * d_ij));
+        let cos_grad_ik = r_ij / (d_ij * d_ik) - r_ik * (cos_theta / (d_ik * d_ik));
+
+        // d r_jk^2 / d r_ij = -2 r_jk, d r_jk^2 / d r_ik = 2 r_jk
+        let gaussian_grad_ij = (r_ij - r_jk) * (-2.0 * self.eta * gaussian);
+        let gaussian_grad_ik = (r_ik + r_jk) * (-2.0 * self.eta * gaussian);
+
+        let mut fc_grad_ij = r_ij * (cutoff_function.derivative(d_ij, cutoff) / d_ij * fc_ik * fc_jk);
+        let mut fc_grad_ik = r_ik * (cutoff_function.derivative(d_ik, cutoff) / d_ik * fc_ij * fc_jk);
+        if d_jk > 0.0 {
+            let fc_jk_derivative = cutoff_function.derivative(d_jk, cutoff) / d_jk * fc_ij * fc_ik;
+            fc_grad_ij -= r_jk * fc_jk_derivative;
+            fc_grad_ik += r_jk * fc_jk_derivative;
+        }
+
+        let gradient_ij = (
+            cos_grad_ij * (angular_derivative * gaussian * fc)
+            + gaussian_grad_ij * (angular_pow * fc)
+            + fc_grad_ij * (angular_pow * gaussian)
+        ) * prefactor;
+
+        let gradient_ik = (
+            cos_grad_ik * (angular_derivative * gaussian * fc)
+            + gaussian_grad_ik * (angular_pow * fc)
+            + fc_grad_ik * (angular_pow * gaussian)
+        ) * prefactor;
+
+        return (value, gradient_ij, gradient_ik);
+    }
+}
+
+impl CalculatorBase for BehlerG4 {
+    fn name(&self) -> String {
+        "Behler-Parrinello G4".into()
+    }
+
+    fn parameters(&self) -> String {
+        serde_json::to_string(&self.parameters).expect("failed to serialize to JSON")
+    }
+
+    fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        let builder = CenterTwoNeighborsSpeciesKeys {
+            cutoff: self.parameters.cutoff,
+            self_pairs: false,
+            symmetric: true,
+            all_species: None,
+        };
+        return builder.keys(systems);
+    }
+
+    fn samples_names(&self) -> Vec<&str> {
+        AtomCenteredSamples::samples_names()
+    }
+
+    fn samples(&self, keys: &Labels, systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
+        let mut result = Vec::new();
+        for [species_center, species_neighbor_1, species_neighbor_2] in keys.iter_fixed_size() {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                // we only want center with both neighbor species present
+                species_neighbor: SpeciesFilter::AllOf(
+                    [
+                        species_neighbor_1.i32(),
+                        species_neighbor_2.i32()
+                    ].iter().copied().collect()
+                ),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            result.push(builder.samples(systems)?);
+        }
+
+        return Ok(result);
+    }
+
+    fn supports_gradient(&self, parameter: &str) -> bool {
+        match parameter {
+            "positions" => true,
+            _ => false,
+        }
+    }
+
+    fn positions_gradient_samples(&self, keys: &Labels, samples: &[Labels], systems: &mut [Box<dyn System>]) -> Result<Vec<Labels>, Error> {
+        assert_eq!(keys.names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
+        assert_eq!(keys.count(), samples.len());
+
+        let mut gradient_samples = Vec::new();
+        for ([species_center, species_neighbor_1, species_neighbor_2], samples) in keys.iter_fixed_size().zip(samples) {
+            let builder = AtomCenteredSamples {
+                cutoff: self.parameters.cutoff,
+                species_center: SpeciesFilter::Single(species_center.i32()),
+                // gradients samples should contain either neighbor species
+                species_neighbor: SpeciesFilter::OneOf(vec![
+                    species_neighbor_1.i32(),
+                    species_neighbor_2.i32()
+                ]),
+                self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
+            };
+
+            gradient_samples.push(builder.gradients_for(systems, samples)?);
+        }
+
+        return Ok(gradient_samples);
+    }
+
+    fn components(&self, keys: &Labels) -> Vec<Vec<Labels>> {
+        return vec![Vec::new(); keys.count()];
+    }
+
+    fn properties_names(&self) -> Vec<&str> {
+        vec!["function"]
+    }
+
+    fn properties(&self, keys: &Labels) -> Vec<Labels> {
+        let mut properties = LabelsBuilder::new(self.properties_names());
+        for i in 0..self.parameters.functions.len() {
+            properties.add(&[i]);
+        }
+        let properties = properties.finish();
+
+        return vec![properties; keys.count()];
+    }
+
+    #[time_graph::instrument(name = "BehlerG4::compute")]
+    fn compute(&mut self, systems: &mut [Box<dyn System>], descriptor: &mut TensorMap) -> Result<(), Error> {
+        assert_eq!(descriptor.keys().names(), ["species_center", "species_neighbor_1", "species_neighbor_2"]);
+
+        for system in systems.iter_mut() {
+            system.compute_neighbors(self.parameters.cutoff)?;
+        }
+
+        let calculator = &*self;
+        let systems = &*systems;
+        for (key, mut block) in descriptor.iter_mut() {
+            let species_neighbors = (key[1].i32(), key[2].i32());
+
+            let samples = block.samples();
+            let functions = block.properties().iter_fixed_size()
+                .map(|&[function]| calculator.parameters.functions[function.usize()])
+                .collect::<Vec<_>>();
+
+            let do_gradients = block.gradient("positions").is_some();
+
+            // enumerating triplets is expensive, so we compute the
+            // contribution of all centers in parallel
+            let contributions = samples.par_iter()
+                .map(|sample| {
+                    let system = &*systems[sample[0].usize()];
+                    calculator.center_contribution(system, sample[1].usize(), species_neighbors, &functions, do_gradients)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let values = block.values_mut().to_array_mut();
+            for (sample_i, contribution) in contributions.iter().enumerate() {
+                for (property_i, &value) in contribution.values.iter().enumerate() {
+                    values[[sample_i, property_i]] = value;
+                }
+            }
+
+            if let Some(mut gradient) = block.gradient_mut("positions") {
+                let gradient = gradient.data_mut();
+                let array = gradient.values.to_array_mut();
+
+                let mut gradient_rows = BTreeMap::new();
+                for (grad_sample_i, &[sample_i, _, atom]) in gradient.samples.iter_fixed_size().enumerate() {
+                    gradient_rows.insert((sample_i.usize(), atom.usize()), grad_sample_i);
+                }
+
+                for (sample_i, contribution) in contributions.iter().enumerate() {
+                    for (&atom, atom_gradient) in &contribution.gradients {
+                        let row = gradient_rows[&(sample_i, atom)];
+                        for d in 0..3 {
+                            for property_i in 0..functions.len() {
+                                array[[row, d, property_i]] += atom_gradient[[d, property_i]];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use equistore::LabelValue;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{Calculator, System, Vector3D};
+    use crate::calculators::CalculatorBase;
+    use crate::calculators::soap::CutoffFunction;
+
+    use super::{BehlerG4, BehlerG4Parameters, G4Function};
+
+    fn parameters() -> BehlerG4Parameters {
+        BehlerG4Parameters {
+            cutoff: 3.5,
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            functions: vec![
+                G4Function { eta: 0.1, zeta: 1.0, lambda: 1 },
+                G4Function { eta: 0.3, zeta: 2.0, lambda: -1 },
+                G4Function { eta: 0.05, zeta: 4.5, lambda: 1 },
+            ],
+            self_pairs: false,
+        }
+    }
+
+    fn triplet_system() -> SimpleSystem {
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+        system.add_atom(1, Vector3D::new(0.0, 0.0, 1.1));
+        system.add_atom(1, Vector3D::new(0.9, 0.0, -0.4));
+        return system;
+    }
+
+    #[test]
+    fn values() {
+        let mut calculator = Calculator::from(Box::new(
+            BehlerG4::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut systems = [Box::new(triplet_system()) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let key = [LabelValue::new(8), LabelValue::new(1), LabelValue::new(1)];
+        let block = descriptor.block_by_id(descriptor.keys().position(&key).unwrap());
+        assert_eq!(block.samples().count(), 1);
+
+        let r_ij = Vector3D::new(0.0, 0.0, 1.1);
+        let r_ik = Vector3D::new(0.9, 0.0, -0.4);
+        let (d_ij, d_ik, d_jk) = (r_ij.norm(), r_ik.norm(), (r_ik - r_ij).norm());
+        let cos_theta = (r_ij * r_ik) / (d_ij * d_ik);
+
+        let fc = CutoffFunction::ShiftedCosine { width: 0.5 };
+        let cutoffs = fc.compute(d_ij, 3.5) * fc.compute(d_ik, 3.5) * fc.compute(d_jk, 3.5);
+
+        let values = block.values().to_array();
+        for (property_i, function) in parameters().functions.iter().enumerate() {
+            let angular = (1.0 + function.lambda as f64 * cos_theta).powf(function.zeta);
+            let gaussian = f64::exp(-function.eta * (d_ij * d_ij + d_ik * d_ik + d_jk * d_jk));
+
+            // the (j, k) and (k, j) triplets give the same contribution
+            let expected = 2.0 * f64::powf(2.0, 1.0 - function.zeta) * angular * gaussian * cutoffs;
+            assert_relative_eq!(values[[0, property_i]], expected, max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn self_pairs() {
+        let mut calculator = Calculator::from(Box::new(
+            BehlerG4::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+        let mut systems = [Box::new(triplet_system()) as Box<dyn System>];
+        let without = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let mut parameters = parameters();
+        parameters.self_pairs = true;
+        let mut calculator = Calculator::from(Box::new(
+            BehlerG4::new(parameters.clone()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+        let with = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let key = [LabelValue::new(8), LabelValue::new(1), LabelValue::new(1)];
+        let without = without.block_by_id(without.keys().position(&key).unwrap());
+        let with = with.block_by_id(with.keys().position(&key).unwrap());
+
+        let without = without.values().to_array();
+        let with = with.values().to_array();
+
+        let fc = CutoffFunction::ShiftedCosine { width: 0.5 };
+        for (property_i, function) in parameters.functions.iter().enumerate() {
+            // degenerate triplets have cos(theta) = 1 and r_jk = 0
+            let mut expected = 0.0;
+            for r in [1.1, f64::sqrt(0.9 * 0.9 + 0.4 * 0.4)] {
+                expected += f64::powf(2.0, 1.0 - function.zeta)
+                    * (1.0 + function.lambda as f64).powf(function.zeta)
+                    * f64::exp(-2.0 * function.eta * r * r)
+                    * fc.compute(r, 3.5) * fc.compute(r, 3.5);
+            }
+
+            assert_relative_eq!(with[[0, property_i]] - without[[0, property_i]], expected, max_relative=1e-12, epsilon=1e-15);
+        }
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let mut parameters = parameters();
+        parameters.functions.push(G4Function { eta: 0.1, zeta: 1.0, lambda: 2 });
+        assert!(BehlerG4::new(parameters).is_err());
+
+        let mut parameters = self::parameters();
+        parameters.functions.push(G4Function { eta: 0.1, zeta: 0.5, lambda: 1 });
+        assert!(BehlerG4::new(parameters).is_err());
+
+        let mut parameters = self::parameters();
+        parameters.functions.clear();
+        assert!(BehlerG4::new(parameters).is_err());
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let calculator = Calculator::from(Box::new(
+            BehlerG4::new(parameters()).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let system = test_system("methane");
+        let options = crate::calculators::tests_utils::FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-6,
+            epsilon: 1e-16,
+        };
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+
+        let mut parameters = parameters();
+        parameters.self_pairs = true;
+        let calculator = Calculator::from(Box::new(
+            BehlerG4::new(parameters).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let system = test_system("water");
+        crate::calculators::tests_utils::finite_differences_positions(calculator, &system, options);
+    }
+}
@@ -0,0 +1,11 @@
+//! Atom-centered symmetry functions, as introduced by Behler and Parrinello
+//! for neural network potentials.
+//!
+//! See [this paper](https://doi.org/10.1063/1.3553717) for more information
+//! on these functions.
+
+mod g2;
+pub use self::g2::{BehlerG2, BehlerG2Parameters, G2Function};
+
+mod g4;
+pub use self::g4::{BehlerG4, BehlerG4Parameters, G4Function};
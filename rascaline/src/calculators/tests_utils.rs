@@ -1,11 +1,27 @@
 use ndarray::Axis;
-use approx::{assert_relative_eq, assert_ulps_eq};
+use approx::assert_ulps_eq;
 
 use equistore::{Labels, TensorMap, LabelsBuilder};
 
 use crate::calculator::LabelsSelection;
 use crate::{CalculationOptions, Calculator};
-use crate::systems::{System, SimpleSystem, UnitCell};
+use crate::systems::{System, SimpleSystem};
+use crate::validation::{self, GradientMismatch};
+
+/// Create a SOAP calculator (either `"spherical_expansion"` or
+/// `"soap_power_spectrum"`) with small hyper-parameters, for use in tests
+/// that only need some descriptor with gradients.
+pub fn soap_calculator(name: &str) -> Calculator {
+    return Calculator::new(name, r#"{
+        "cutoff": 3.5,
+        "max_radial": 4,
+        "max_angular": 3,
+        "atomic_gaussian_width": 0.3,
+        "center_atom_weight": 1.0,
+        "radial_basis": {"Gto": {}},
+        "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+    }"#.into()).unwrap();
+}
 
 /// Check that computing a partial subset of features/samples works as intended
 /// for the given `calculator` and `systems`.
@@ -233,144 +249,81 @@ fn check_compute_partial_both(
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct FinalDifferenceOptions {
-    /// distance each atom will be displaced in each direction when computing
-    /// finite differences
-    pub displacement: f64,
-    /// Maximal relative error. 10 * displacement is a good starting point
-    pub max_relative: f64,
-    /// Threshold below which all values are considered zero. This should be
-    /// very small (1e-16) to prevent false positives (if all values & gradients
-    /// are below that threshold, tests will pass even with wrong gradients)
-    pub epsilon: f64,
+pub use crate::validation::FinalDifferenceOptions;
+
+/// Panic with a readable message if there are any gradients mismatches
+fn assert_no_mismatch(mismatches: &[GradientMismatch]) {
+    if !mismatches.is_empty() {
+        let mut message = format!("{} gradients do not match finite differences:", mismatches.len());
+        for mismatch in mismatches.iter().take(10) {
+            message += &format!("\n    {}", mismatch);
+        }
+        panic!("{}", message);
+    }
 }
 
 /// Check that analytical gradients with respect to positions agree with a
 /// finite difference calculation of the gradients.
 pub fn finite_differences_positions(mut calculator: Calculator, system: &SimpleSystem, options: FinalDifferenceOptions) {
-    let calculation_options = CalculationOptions {
-        gradients: &["positions"],
-        ..Default::default()
-    };
-    let reference = calculator.compute(&mut [Box::new(system.clone())], calculation_options).unwrap();
-
-    for atom_i in 0..system.size().unwrap() {
-        for spatial in 0..3 {
-            let mut system_pos = system.clone();
-            system_pos.positions_mut()[atom_i][spatial] += options.displacement / 2.0;
-            let updated_pos = calculator.compute(&mut [Box::new(system_pos)], Default::default()).unwrap();
-
-            let mut system_neg = system.clone();
-            system_neg.positions_mut()[atom_i][spatial] -= options.displacement / 2.0;
-            let updated_neg = calculator.compute(&mut [Box::new(system_neg)], Default::default()).unwrap();
-
-            assert_eq!(updated_pos.keys(), reference.keys());
-            assert_eq!(updated_neg.keys(), reference.keys());
-
-            for (block_i, (_, block)) in reference.iter().enumerate() {
-                let gradients = &block.gradient("positions").unwrap();
-                let block_pos = &updated_pos.block_by_id(block_i);
-                let block_neg = &updated_neg.block_by_id(block_i);
-
-                for (gradient_i, [sample_i, _, atom]) in gradients.samples().iter_fixed_size().enumerate() {
-                    if atom.usize() != atom_i {
-                        continue;
-                    }
-                    let sample_i = sample_i.usize();
-
-                    // check that the same sample is here in both descriptors
-                    assert_eq!(block_pos.samples()[sample_i], block.samples()[sample_i]);
-                    assert_eq!(block_neg.samples()[sample_i], block.samples()[sample_i]);
-
-                    let value_pos = block_pos.values().to_array().index_axis(Axis(0), sample_i);
-                    let value_neg = block_neg.values().to_array().index_axis(Axis(0), sample_i);
-                    let gradient = gradients.values().to_array().index_axis(Axis(0), gradient_i);
-                    let gradient = gradient.index_axis(Axis(0), spatial);
-
-                    assert_eq!(value_pos.shape(), gradient.shape());
-                    assert_eq!(value_neg.shape(), gradient.shape());
-
-                    let mut finite_difference = value_pos.to_owned().clone();
-                    finite_difference -= &value_neg;
-                    finite_difference /= options.displacement;
-
-                    assert_relative_eq!(
-                        finite_difference, gradient,
-                        epsilon=options.epsilon,
-                        max_relative=options.max_relative,
-                    );
-                }
-            }
+    let mismatches = validation::finite_differences_positions(&mut calculator, system, options).unwrap();
+    assert_no_mismatch(&mismatches);
+}
+
+/// Check that analytical gradients with respect to positions agree with a
+/// finite difference calculation of the gradients, when only computing the
+/// given `samples`.
+pub fn finite_differences_positions_selected(mut calculator: Calculator, system: &SimpleSystem, samples: &Labels, options: FinalDifferenceOptions) {
+    let selected_samples = LabelsSelection::Subset(samples);
+    let mismatches = validation::finite_differences_positions_selected(&mut calculator, system, selected_samples, options).unwrap();
+    assert_no_mismatch(&mismatches);
+}
+
+/// Candidate displacements for `optimal_displacement`
+const DISPLACEMENT_SWEEP: [f64; 9] = [1e-2, 1e-3, 1e-4, 1e-5, 1e-6, 1e-7, 1e-8, 1e-9, 1e-10];
+
+/// Get a copy of `options` with the displacement minimizing the relative
+/// error between finite differences and the analytical positions gradients of
+/// `calculator` for the given `system`.
+///
+/// Small displacements reduce the truncation error of finite differences but
+/// increase the round-off error, and the best compromise depends on the
+/// calculator. This tries all the displacements from `1e-2` to `1e-10` and
+/// picks the one giving the smallest maximal relative error.
+pub fn optimal_displacement(calculator: &mut Calculator, system: &SimpleSystem, mut options: FinalDifferenceOptions) -> FinalDifferenceOptions {
+    let mut best_error = f64::INFINITY;
+    for displacement in DISPLACEMENT_SWEEP {
+        // with a zero tolerance, all entries above epsilon are reported as
+        // mismatches, giving access to the full error
+        let sweep_options = options.displacement(displacement).max_relative(0.0);
+        let mismatches = validation::finite_differences_positions(calculator, system, sweep_options).unwrap();
+
+        let error = mismatches.iter()
+            .map(|mismatch| {
+                let scale = f64::max(f64::abs(mismatch.analytical), f64::abs(mismatch.finite_differences));
+                f64::abs(mismatch.analytical - mismatch.finite_differences) / scale
+            })
+            .fold(0.0, f64::max);
+
+        if error < best_error {
+            best_error = error;
+            options.displacement = displacement;
         }
     }
-}
 
+    return options;
+}
 
 /// Check that analytical gradients with respect to cell agree with a
 /// finite difference calculation of the gradients.
 pub fn finite_differences_cell(mut calculator: Calculator, system: &SimpleSystem, options: FinalDifferenceOptions) {
-    let calculation_options = CalculationOptions {
-        gradients: &["cell"],
-        ..Default::default()
-    };
-    let reference = calculator.compute(&mut [Box::new(system.clone())], calculation_options).unwrap();
-    let original_cell = system.cell().unwrap().matrix();
-    let original_cell_inverse = original_cell.inverse();
-
-    for spatial_1 in 0..3 {
-        for spatial_2 in 0..3 {
-            let mut deformed_cell = original_cell;
-            deformed_cell[spatial_1][spatial_2] += options.displacement / 2.0;
-
-            let mut system_pos = system.clone();
-            system_pos.set_cell(UnitCell::from(deformed_cell));
-            for position in system_pos.positions_mut() {
-                *position = deformed_cell * (original_cell_inverse * *position);
-            }
-            let updated_pos = calculator.compute(&mut [Box::new(system_pos)], Default::default()).unwrap();
-
-            deformed_cell[spatial_1][spatial_2] -= options.displacement;
-
-            let mut system_neg = system.clone();
-            system_neg.set_cell(UnitCell::from(deformed_cell));
-            for position in system_neg.positions_mut() {
-                *position = deformed_cell * (original_cell_inverse * *position);
-            }
-            let updated_neg = calculator.compute(&mut [Box::new(system_neg)], Default::default()).unwrap();
-
-            for (block_i, (_, block)) in reference.iter().enumerate() {
-                let gradients = &block.gradient("cell").unwrap();
-                let block_pos = &updated_pos.block_by_id(block_i);
-                let block_neg = &updated_neg.block_by_id(block_i);
-
-                for (gradient_i, [sample_i]) in gradients.samples().iter_fixed_size().enumerate() {
-                    let sample_i = sample_i.usize();
-
-                    // check that the same sample is here in both descriptors
-                    assert_eq!(block_pos.samples()[sample_i], block.samples()[sample_i]);
-                    assert_eq!(block_neg.samples()[sample_i], block.samples()[sample_i]);
-
-                    let value_pos = block_pos.values().to_array().index_axis(Axis(0), sample_i);
-                    let value_neg = block_neg.values().to_array().index_axis(Axis(0), sample_i);
-                    let gradient = gradients.values().to_array().index_axis(Axis(0), gradient_i);
-                    let gradient = gradient.index_axis(Axis(0), spatial_1);
-                    let gradient = gradient.index_axis(Axis(0), spatial_2);
-
-                    assert_eq!(value_pos.shape(), gradient.shape());
-                    assert_eq!(value_neg.shape(), gradient.shape());
-
-                    let mut finite_difference = value_pos.to_owned().clone();
-                    finite_difference -= &value_neg;
-                    finite_difference /= options.displacement;
+    let mismatches = validation::finite_differences_cell(&mut calculator, system, options).unwrap();
+    assert_no_mismatch(&mismatches);
+}
 
-                    assert_relative_eq!(
-                        finite_difference, gradient,
-                        epsilon=options.epsilon,
-                        max_relative=options.max_relative,
-                    );
-                }
-            }
-        }
-    }
+/// Check that analytical gradients with respect to strain agree with a finite
+/// difference calculation of the gradients, where the strain is applied to
+/// both the atomic positions and the cell vectors.
+pub fn finite_differences_strain(mut calculator: Calculator, system: &SimpleSystem, options: FinalDifferenceOptions) {
+    let mismatches = validation::finite_differences_strain(&mut calculator, system, options).unwrap();
+    assert_no_mismatch(&mismatches);
 }
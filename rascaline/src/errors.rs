@@ -25,6 +25,25 @@ pub enum Error {
     /// Error used for failed internal consistency check and panics, i.e. bugs
     /// in rascaline.
     Internal(String),
+    /// Another error, with additional context about what was happening when
+    /// it occurred. This is created with [`ErrorContext::context`]. Only the
+    /// context is included when displaying this error, the original error is
+    /// available through [`std::error::Error::source`].
+    Context {
+        context: String,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Get the innermost error, skipping all the [`Error::Context`] layers
+    pub fn root_cause(&self) -> &Error {
+        let mut error = self;
+        while let Error::Context { source, .. } = error {
+            error = source;
+        }
+        return error;
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -38,6 +57,7 @@ impl std::fmt::Display for Error {
             Error::BufferSize(e) => write!(f, "buffer is not big enough: {}", e),
             Error::External{status, message} => write!(f, "error from external code (status {}): {}", status, message),
             Error::Internal(e) => write!(f, "internal error (this is likely a bug, please report it): {}", e),
+            Error::Context { context, .. } => write!(f, "{}", context),
         }
     }
 }
@@ -53,6 +73,7 @@ impl std::error::Error for Error {
             Error::Equistore(e) => Some(e),
             Error::Json(e) => Some(e),
             Error::Utf8(e) => Some(e),
+            Error::Context { source, .. } => Some(&**source),
         }
     }
 }
@@ -90,3 +111,67 @@ impl From<Box<dyn std::any::Any + Send + 'static>> for Error {
         Error::Internal(message)
     }
 }
+
+/// Extension trait to add context to errors as they propagate, creating an
+/// [`Error::Context`].
+///
+/// ```
+/// # use rascaline::{Error, ErrorContext};
+/// fn read_cutoff(value: &str) -> Result<f64, Error> {
+///     return value.parse().map_err(|_| Error::InvalidParameter("not a number".into()));
+/// }
+///
+/// let error = read_cutoff("3.O").context("failed to read the cutoff").unwrap_err();
+/// assert_eq!(error.to_string(), "failed to read the cutoff");
+///
+/// let source = std::error::Error::source(&error).unwrap();
+/// assert_eq!(source.to_string(), "invalid parameter: not a number");
+/// ```
+pub trait ErrorContext<T> {
+    /// Wrap the error (if any) with the given `context`
+    fn context(self, context: impl Into<String>) -> Result<T, Error>;
+
+    /// Wrap the error (if any) with a context created by calling `context`,
+    /// which is only evaluated if there is an error
+    fn with_context<S: Into<String>>(self, context: impl FnOnce() -> S) -> Result<T, Error>;
+}
+
+impl<T> ErrorContext<T> for Result<T, Error> {
+    fn context(self, context: impl Into<String>) -> Result<T, Error> {
+        return self.map_err(|error| Error::Context {
+            context: context.into(),
+            source: Box::new(error),
+        });
+    }
+
+    fn with_context<S: Into<String>>(self, context: impl FnOnce() -> S) -> Result<T, Error> {
+        return self.map_err(|error| Error::Context {
+            context: context().into(),
+            source: Box::new(error),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::{Error, ErrorContext};
+
+    #[test]
+    fn context() {
+        let result: Result<(), Error> = Err(Error::InvalidParameter("bad cutoff".into()));
+        let error = result
+            .context("while creating the calculator")
+            .with_context(|| format!("while processing system {}", 3))
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "while processing system 3");
+
+        let source = error.source().unwrap();
+        assert_eq!(source.to_string(), "while creating the calculator");
+        assert_eq!(source.source().unwrap().to_string(), "invalid parameter: bad cutoff");
+
+        assert!(matches!(error.root_cause(), Error::InvalidParameter(_)));
+    }
+}
@@ -0,0 +1,6 @@
+//! Helpers to store the features computed with rascaline and check that they
+//! are still compatible with the current version of rascaline when loading
+//! them back.
+
+mod version;
+pub use self::version::{VersionedTensorMap, check_compatibility, FORMAT_VERSION};
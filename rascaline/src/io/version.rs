@@ -0,0 +1,127 @@
+use equistore::TensorMap;
+
+use crate::Error;
+
+/// Current version of the layout (keys, samples, components and properties
+/// names and ordering) of the features produced by rascaline. This is
+/// incremented every time the layout of the output changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Metadata saved next to the features data
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Metadata {
+    format_version: u32,
+}
+
+/// A `TensorMap` tagged with the version of the format used to produce it.
+///
+/// `TensorMap` does not have a place to store arbitrary metadata, so the
+/// version is stored separately. When saving features to disk, the output of
+/// [`VersionedTensorMap::metadata`] should be saved next to the data, and
+/// given back to [`VersionedTensorMap::from_metadata`] when loading the
+/// features.
+#[derive(Debug)]
+pub struct VersionedTensorMap {
+    /// the features
+    pub tensor: TensorMap,
+    /// version of the format used to create `tensor`
+    pub format_version: u32,
+}
+
+impl VersionedTensorMap {
+    /// Tag `tensor` with the current [`FORMAT_VERSION`]. This should be used
+    /// on features just produced by a calculator.
+    pub fn new(tensor: TensorMap) -> VersionedTensorMap {
+        return VersionedTensorMap {
+            tensor: tensor,
+            format_version: FORMAT_VERSION,
+        };
+    }
+
+    /// Get the metadata associated with this tensor, serialized to JSON.
+    pub fn metadata(&self) -> String {
+        let metadata = Metadata { format_version: self.format_version };
+        return serde_json::to_string(&metadata).expect("failed to serialize to JSON");
+    }
+
+    /// Create a `VersionedTensorMap` from a `tensor` loaded from disk and the
+    /// JSON `metadata` saved alongside it.
+    pub fn from_metadata(tensor: TensorMap, metadata: &str) -> Result<VersionedTensorMap, Error> {
+        let metadata = serde_json::from_str::<Metadata>(metadata)?;
+        return Ok(VersionedTensorMap {
+            tensor: tensor,
+            format_version: metadata.format_version,
+        });
+    }
+}
+
+/// Check that the features in `tensor` were produced with the
+/// `expected_version` of the format, returning an error otherwise.
+///
+/// This should be called when loading saved features, typically with
+/// `expected_version` set to [`FORMAT_VERSION`], to ensure that they can be
+/// used together with features computed by the current version of rascaline.
+pub fn check_compatibility(tensor: &VersionedTensorMap, expected_version: u32) -> Result<(), Error> {
+    if tensor.format_version == expected_version {
+        return Ok(());
+    }
+
+    let age = if tensor.format_version < expected_version {
+        "an older"
+    } else {
+        "a newer"
+    };
+
+    return Err(Error::InvalidParameter(format!(
+        "these features were produced with {} format (version {}) than \
+        the expected one (version {}), they need to be re-computed",
+        age, tensor.format_version, expected_version
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use equistore::{Labels, TensorBlock, TensorMap};
+    use ndarray::ArrayD;
+
+    use super::{VersionedTensorMap, check_compatibility, FORMAT_VERSION};
+
+    fn tensor() -> TensorMap {
+        let block = TensorBlock::new(
+            ArrayD::from_elem(vec![1, 1], 1.0),
+            &Labels::new(["structure", "center"], &[[0, 0]]),
+            &[],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap();
+        return TensorMap::new(Labels::single(), vec![block]).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let tensor = VersionedTensorMap::new(tensor());
+        assert_eq!(tensor.format_version, FORMAT_VERSION);
+
+        let metadata = tensor.metadata();
+        let loaded = VersionedTensorMap::from_metadata(tensor.tensor, &metadata).unwrap();
+        assert_eq!(loaded.format_version, FORMAT_VERSION);
+        check_compatibility(&loaded, FORMAT_VERSION).unwrap();
+    }
+
+    #[test]
+    fn mismatched_version() {
+        let metadata = format!(r#"{{"format_version": {}}}"#, FORMAT_VERSION + 1);
+        let loaded = VersionedTensorMap::from_metadata(tensor(), &metadata).unwrap();
+
+        let error = check_compatibility(&loaded, FORMAT_VERSION).unwrap_err();
+        assert!(error.to_string().contains("newer format"));
+
+        let loaded = VersionedTensorMap::from_metadata(tensor(), r#"{"format_version": 0}"#).unwrap();
+        let error = check_compatibility(&loaded, FORMAT_VERSION).unwrap_err();
+        assert!(error.to_string().contains("older format"));
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        assert!(VersionedTensorMap::from_metadata(tensor(), "{}").is_err());
+    }
+}
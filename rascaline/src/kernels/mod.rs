@@ -0,0 +1,8 @@
+//! Kernels functions operating on the descriptors produced by rascaline
+//! calculators.
+
+mod sparse;
+pub use self::sparse::project_sparse;
+
+mod structure;
+pub use self::structure::structure_kernel_gradient;
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+
+use ndarray::{Array2, ArrayView2};
+use equistore::{TensorMap, LabelValue, TensorBlockRef};
+
+use crate::Error;
+
+/// Get a 2D view of the values in this block, merging the components with
+/// the properties.
+pub(super) fn values_2d<'a>(block: &'a TensorBlockRef<'_>) -> ArrayView2<'a, f64> {
+    let values = block.values().to_array();
+    let n_samples = values.shape()[0];
+    let n_features: usize = values.shape().iter().skip(1).product();
+
+    return values.view()
+        .into_shape((n_samples, n_features))
+        .expect("values should be contiguous");
+}
+
+/// Get the position of each sample of all the blocks in `tensor`, with
+/// samples sorted in lexicographic order.
+pub(super) fn samples_positions(tensor: &TensorMap) -> BTreeMap<Vec<LabelValue>, usize> {
+    let mut all_samples = BTreeMap::new();
+    for (_, block) in tensor.iter() {
+        for sample in block.samples().iter() {
+            all_samples.insert(sample.to_vec(), 0);
+        }
+    }
+
+    for (i, position) in all_samples.values_mut().enumerate() {
+        *position = i;
+    }
+
+    return all_samples;
+}
+
+/// Compute the polynomial kernel between the samples in `features` and the
+/// samples in `inducing` (typically the inducing points of a sparse Gaussian
+/// process model).
+///
+/// The features of a single sample (usually an atom-centered environment) are
+/// split across multiple blocks, and the kernel is computed from the full
+/// feature vector, i.e. `K_ij = (Σ_b x_i^b · y_j^b)^ζ`, where the sum runs over
+/// all blocks present in both `features` and `inducing`. Components are
+/// considered as additional properties.
+///
+/// The rows of the returned `(n_samples, n_inducing)` matrix correspond to the
+/// unique samples of all blocks in `features`, in lexicographic order; and
+/// similarly the columns correspond to the unique samples in `inducing`.
+pub fn project_sparse(features: &TensorMap, inducing: &TensorMap, zeta: f64) -> Result<Array2<f64>, Error> {
+    if !(zeta > 0.0 && zeta.is_finite()) {
+        return Err(Error::InvalidParameter(format!(
+            "zeta must be a positive number for the polynomial kernel, got {}", zeta
+        )));
+    }
+
+    if features.keys().names() != inducing.keys().names() {
+        return Err(Error::InvalidParameter(format!(
+            "the keys names of the features ({}) do not match the keys names of the inducing points ({})",
+            features.keys().names().join(", "), inducing.keys().names().join(", ")
+        )));
+    }
+
+    let features_samples = samples_positions(features);
+    let inducing_samples = samples_positions(inducing);
+
+    let mut kernel = Array2::from_elem((features_samples.len(), inducing_samples.len()), 0.0);
+    for (key, block) in features.iter() {
+        let inducing_block = if let Some(block_i) = inducing.keys().position(key) {
+            inducing.block_by_id(block_i)
+        } else {
+            // these features do not contribute to the kernel
+            continue;
+        };
+
+        if block.properties() != inducing_block.properties() || block.components() != inducing_block.components() {
+            return Err(Error::InvalidParameter(format!(
+                "the properties or components of the features and inducing points do not match for key {:?}",
+                key
+            )));
+        }
+
+        let block_kernel = values_2d(&block).dot(&values_2d(&inducing_block).t());
+
+        let features_rows = block.samples().iter()
+            .map(|sample| features_samples[sample])
+            .collect::<Vec<_>>();
+
+        for (inducing_i, sample) in inducing_block.samples().iter().enumerate() {
+            let column = inducing_samples[sample];
+            for (features_i, &row) in features_rows.iter().enumerate() {
+                kernel[[row, column]] += block_kernel[[features_i, inducing_i]];
+            }
+        }
+    }
+
+    kernel.mapv_inplace(|k| k.powf(zeta));
+
+    return Ok(kernel);
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::array;
+
+    use equistore::{Labels, TensorBlock, TensorMap};
+
+    use super::project_sparse;
+
+    fn features() -> TensorMap {
+        let first = TensorBlock::new(
+            array![[1.0, 2.0], [3.0, -4.0], [2.0, 8.0]].into_dyn(),
+            &Labels::new(["structure", "center"], &[[0, 0], [0, 1], [1, 0]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1]]),
+        ).unwrap();
+
+        let second = TensorBlock::new(
+            array![[0.5, 1.5, 2.5], [-1.0, 4.0, 0.0]].into_dyn(),
+            &Labels::new(["structure", "center"], &[[0, 0], [1, 1]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1], [2]]),
+        ).unwrap();
+
+        let keys = Labels::new(["species_neighbor"], &[[1], [6]]);
+        return TensorMap::new(keys, vec![first, second]).unwrap();
+    }
+
+    #[test]
+    fn symmetric() {
+        let features = features();
+        let kernel = project_sparse(&features, &features, 2.0).unwrap();
+
+        assert_eq!(kernel.shape(), [4, 4]);
+        assert_relative_eq!(kernel, kernel.t(), max_relative=1e-12);
+
+        // sample (0, 0) has features in both blocks
+        let expected: f64 = 1.0 * 1.0 + 2.0 * 2.0 + 0.5 * 0.5 + 1.5 * 1.5 + 2.5 * 2.5;
+        assert_relative_eq!(kernel[[0, 0]], expected.powi(2), max_relative=1e-12);
+
+        // sample (0, 1) and (1, 1) do not share any block
+        assert_eq!(kernel[[1, 3]], 0.0);
+    }
+
+    #[test]
+    fn invalid_zeta() {
+        let features = features();
+        assert!(project_sparse(&features, &features, 0.0).is_err());
+        assert!(project_sparse(&features, &features, f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+
+use ndarray::{Array2, Array3, Axis};
+use equistore::{TensorMap, TensorBlock, Labels, LabelsBuilder, LabelValue};
+
+use crate::Error;
+
+use super::sparse::{values_2d, samples_positions};
+
+/// Compute the polynomial structure kernel between all structures in
+/// `features`, together with its gradients with respect to atomic positions.
+///
+/// The structure kernel is the sum of the polynomial kernel between all
+/// environments in the two structures: `K_AB = Σ_{i ∈ A} Σ_{j ∈ B} (x_i ·
+/// x_j)^ζ`, where the features of a single environment are split across
+/// multiple blocks (see [`super::project_sparse`]). The gradient with respect
+/// to the position of atom `k` is computed from the gradients of the features:
+///
+/// `∂K_AB / ∂r_k = Σ_{i ∈ A} Σ_{j ∈ B} ζ (x_i · x_j)^(ζ - 1) (∂x_i/∂r_k · x_j + x_i · ∂x_j/∂r_k)`
+///
+/// All blocks in `features` must contain gradients with respect to
+/// `"positions"`, and the first sample variable must be `"structure"`.
+///
+/// The returned `TensorMap` contains a single block, with one sample per
+/// structure and one property (`"other_structure"`) per structure, such that
+/// the values are `K_AB`. The `"positions"` gradients of this block contain
+/// the derivative of `K_AB` with respect to the positions of the atoms in
+/// structure `A`, for all structures `B`.
+///
+/// Since `(x_i · x_j)^(ζ - 1)` is singular for `ζ < 1` when `x_i · x_j` is
+/// zero, `zeta` must be larger or equal to 1.
+pub fn structure_kernel_gradient(features: &TensorMap, zeta: f64) -> Result<TensorMap, Error> {
+    if !(zeta >= 1.0 && zeta.is_finite()) {
+        return Err(Error::InvalidParameter(format!(
+            "zeta must be larger or equal to 1 for the structure kernel gradient, got {}", zeta
+        )));
+    }
+
+    let environments = samples_positions(features);
+
+    let mut structures = BTreeMap::new();
+    for sample in environments.keys() {
+        let n_structures = structures.len();
+        structures.entry(sample[0]).or_insert(n_structures);
+    }
+    let n_structures = structures.len();
+
+    let environment_structure = environments.keys()
+        .map(|sample| structures[&sample[0]])
+        .collect::<Vec<_>>();
+
+    // environment-environment dot products, summed over all blocks
+    let mut dot_products = Array2::from_elem((environments.len(), environments.len()), 0.0);
+    for (_, block) in features.iter() {
+        if block.samples().names().first() != Some(&"structure") {
+            return Err(Error::InvalidParameter(
+                "the first sample variable must be 'structure' to compute the structure kernel".into()
+            ));
+        }
+
+        let values = values_2d(&block);
+        let block_dot_products = values.dot(&values.t());
+
+        let rows = block.samples().iter()
+            .map(|sample| environments[sample])
+            .collect::<Vec<_>>();
+
+        for (i, &row_i) in rows.iter().enumerate() {
+            for (j, &row_j) in rows.iter().enumerate() {
+                dot_products[[row_i, row_j]] += block_dot_products[[i, j]];
+            }
+        }
+    }
+
+    let mut kernel = Array2::from_elem((n_structures, n_structures), 0.0);
+    for ((env_i, env_j), &dot) in dot_products.indexed_iter() {
+        kernel[[environment_structure[env_i], environment_structure[env_j]]] += dot.powf(zeta);
+    }
+
+    let weights = dot_products.mapv(|dot| zeta * dot.powf(zeta - 1.0));
+
+    // gradients of the kernel, indexed by (structure, atom). The arrays have
+    // shape (direction, other structure)
+    let mut gradients = BTreeMap::new();
+    for (key, block) in features.iter() {
+        let gradient = block.gradient("positions").ok_or_else(|| Error::InvalidParameter(format!(
+            "missing positions gradients for key {:?}, which are required to compute the kernel gradient", key
+        )))?;
+
+        let values = values_2d(&block);
+        let n_features = values.shape()[1];
+
+        let rows = block.samples().iter()
+            .map(|sample| environments[sample])
+            .collect::<Vec<_>>();
+
+        let gradient_values = gradient.values().to_array();
+        let n_gradient_samples = gradient_values.shape()[0];
+        let gradient_values = gradient_values.view()
+            .into_shape((n_gradient_samples, 3, n_features))
+            .expect("gradients should be contiguous");
+
+        for (gradient_i, &[sample, _, atom]) in gradient.samples().iter_fixed_size().enumerate() {
+            let env_i = rows[sample.usize()];
+            let structure_i = environment_structure[env_i];
+
+            // derivative of x_i · x_j for all environments j in this block,
+            // with shape (direction, j)
+            let dot_gradients = gradient_values.index_axis(Axis(0), gradient_i).dot(&values.t());
+
+            let output = gradients.entry((structure_i, atom))
+                .or_insert_with(|| Array2::from_elem((3, n_structures), 0.0));
+
+            for (j, &env_j) in rows.iter().enumerate() {
+                let weight = weights[[env_i, env_j]];
+                let structure_j = environment_structure[env_j];
+                for direction in 0..3 {
+                    output[[direction, structure_j]] += weight * dot_gradients[[direction, j]];
+                }
+            }
+        }
+    }
+
+    // when both environments are in the same structure, the atom positions
+    // also contribute through x_j, with the same value by symmetry
+    for (&(structure_i, _), output) in &mut gradients {
+        output.column_mut(structure_i).mapv_inplace(|g| 2.0 * g);
+    }
+
+    let mut samples = LabelsBuilder::new(vec!["structure"]);
+    let mut properties = LabelsBuilder::new(vec!["other_structure"]);
+    for &structure in structures.keys() {
+        samples.add(&[structure]);
+        properties.add(&[structure]);
+    }
+    let samples = samples.finish();
+    let properties = properties.finish();
+
+    let structure_labels = structures.keys().copied().collect::<Vec<LabelValue>>();
+    let mut gradient_samples = LabelsBuilder::new(vec!["sample", "structure", "atom"]);
+    let mut gradient_values = Array3::from_elem((gradients.len(), 3, n_structures), 0.0);
+    for (gradient_i, ((structure_i, atom), output)) in gradients.into_iter().enumerate() {
+        gradient_samples.add(&[structure_i.into(), structure_labels[structure_i], atom]);
+        gradient_values.index_axis_mut(Axis(0), gradient_i).assign(&output);
+    }
+
+    let mut block = TensorBlock::new(kernel.into_dyn(), &samples, &[], &properties)?;
+    block.add_gradient("positions", TensorBlock::new(
+        gradient_values.into_dyn(),
+        &gradient_samples.finish(),
+        &[Labels::new(["direction"], &[[0], [1], [2]])],
+        &properties,
+    )?)?;
+
+    return Ok(TensorMap::new(Labels::single(), vec![block])?);
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::Axis;
+
+    use crate::systems::test_utils::test_system;
+    use crate::{CalculationOptions, System};
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::structure_kernel_gradient;
+
+    #[test]
+    fn finite_differences() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let zeta = 2.0;
+
+        let systems = vec![test_system("water"), test_system("methane")];
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+
+        let mut boxed = systems.iter().cloned().map(|s| Box::new(s) as Box<dyn System>).collect::<Vec<_>>();
+        let features = calculator.compute(&mut boxed, options).unwrap();
+        let kernel = structure_kernel_gradient(&features, zeta).unwrap();
+
+        let block = kernel.block_by_id(0);
+        let gradient = block.gradient("positions").unwrap();
+        let gradient_values = gradient.values().to_array();
+
+        let displacement = 1e-6;
+        for (gradient_i, &[sample, structure, atom]) in gradient.samples().iter_fixed_size().enumerate() {
+            assert_eq!(sample.usize(), structure.usize());
+
+            for direction in 0..3 {
+                let mut kernel_at = |delta: f64| {
+                    let mut displaced = systems.clone();
+                    displaced[structure.usize()].positions_mut()[atom.usize()][direction] += delta;
+
+                    let mut displaced = displaced.into_iter().map(|s| Box::new(s) as Box<dyn System>).collect::<Vec<_>>();
+                    let features = calculator.compute(&mut displaced, options).unwrap();
+                    let kernel = structure_kernel_gradient(&features, zeta).unwrap();
+                    let values = kernel.block_by_id(0).values().to_array().clone();
+                    return values.index_axis(Axis(0), sample.usize()).to_owned();
+                };
+
+                let finite_difference = (kernel_at(displacement / 2.0) - kernel_at(-displacement / 2.0)) / displacement;
+                let expected = gradient_values.index_axis(Axis(0), gradient_i).index_axis(Axis(0), direction).to_owned();
+
+                assert_relative_eq!(finite_difference, expected, epsilon=1e-6, max_relative=1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_zeta() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let mut systems = vec![Box::new(test_system("water")) as Box<dyn System>];
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let features = calculator.compute(&mut systems, options).unwrap();
+
+        assert!(structure_kernel_gradient(&features, 0.5).is_err());
+        assert!(structure_kernel_gradient(&features, f64::NAN).is_err());
+
+        // gradients are required
+        let features = calculator.compute(&mut systems, Default::default()).unwrap();
+        assert!(structure_kernel_gradient(&features, 2.0).is_err());
+    }
+}
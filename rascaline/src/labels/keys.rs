@@ -11,6 +11,23 @@ pub trait KeysBuilder {
     fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error>;
 }
 
+/// Get the full set of species from the user-provided `all_species`, checking
+/// that all the species in `systems` are part of it
+fn check_all_species(all_species: &[i32], systems: &mut [Box<dyn System>]) -> Result<BTreeSet<i32>, Error> {
+    let all_species = all_species.iter().copied().collect::<BTreeSet<_>>();
+    for system in systems {
+        for species in system.species()? {
+            if !all_species.contains(species) {
+                return Err(Error::InvalidParameter(format!(
+                    "species {} is present in the systems but not in `all_species`", species
+                )));
+            }
+        }
+    }
+
+    return Ok(all_species);
+}
+
 /// Compute a set of keys with a single variable, the central atom species.
 pub struct CenterSpeciesKeys;
 
@@ -58,17 +75,37 @@ impl KeysBuilder for AllSpeciesPairsKeys {
 
 /// Compute a set of keys with two variables: the central atom species and a
 /// single neighbor atom species within a cutoff around the central atom.
+///
+/// If `all_species` is given, the keys contain all possible pairs of these
+/// species, regardless of the species actually present in the systems.
 pub struct CenterSingleNeighborsSpeciesKeys {
     /// Spherical cutoff to use when searching for neighbors around an atom
     pub cutoff: f64,
     /// Should we consider an atom to be it's own neighbor or not?
     pub self_pairs: bool,
+    /// If this is not `None`, use this set of species to create the keys
+    /// instead of the species found in the systems. This allows to get the
+    /// same keys for different sets of systems.
+    pub all_species: Option<Vec<i32>>,
 }
 
 impl KeysBuilder for CenterSingleNeighborsSpeciesKeys {
     fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
         assert!(self.cutoff > 0.0 && self.cutoff.is_finite());
 
+        if let Some(ref all_species) = self.all_species {
+            let all_species = check_all_species(all_species, systems)?;
+
+            let mut keys = LabelsBuilder::new(vec!["species_center", "species_neighbor"]);
+            for &center in &all_species {
+                for &neighbor in &all_species {
+                    keys.add(&[center, neighbor]);
+                }
+            }
+
+            return Ok(keys.finish());
+        }
+
         let mut all_species_pairs = BTreeSet::new();
         for system in systems {
             system.compute_neighbors(self.cutoff)?;
@@ -98,6 +135,9 @@ impl KeysBuilder for CenterSingleNeighborsSpeciesKeys {
 
 /// Compute a set of keys with three variables: the central atom species and two
 /// neighbor atom species.
+///
+/// If `all_species` is given, the keys contain all possible combinations of
+/// these species, regardless of the species actually present in the systems.
 pub struct CenterTwoNeighborsSpeciesKeys {
     /// Spherical cutoff to use when searching for neighbors around an atom
     pub cutoff: f64,
@@ -105,12 +145,35 @@ pub struct CenterTwoNeighborsSpeciesKeys {
     pub self_pairs: bool,
     /// Are neighbor atoms keys symmetric with respect to exchange or not?
     pub symmetric: bool,
+    /// If this is not `None`, use this set of species to create the keys
+    /// instead of the species found in the systems. This allows to get the
+    /// same keys for different sets of systems.
+    pub all_species: Option<Vec<i32>>,
 }
 
 impl KeysBuilder for CenterTwoNeighborsSpeciesKeys {
     fn keys(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
         assert!(self.cutoff > 0.0 && self.cutoff.is_finite());
 
+        if let Some(ref all_species) = self.all_species {
+            let all_species = check_all_species(all_species, systems)?;
+
+            let mut keys_builder = LabelsBuilder::new(vec!["species_center", "species_neighbor_1", "species_neighbor_2"]);
+            for &species_center in &all_species {
+                for &species_neighbor_1 in &all_species {
+                    for &species_neighbor_2 in &all_species {
+                        if self.symmetric && species_neighbor_2 < species_neighbor_1 {
+                            continue;
+                        }
+
+                        keys_builder.add(&[species_center, species_neighbor_1, species_neighbor_2]);
+                    }
+                }
+            }
+
+            return Ok(keys_builder.finish());
+        }
+
         let mut keys = BTreeSet::new();
         for system in systems {
             system.compute_neighbors(self.cutoff)?;
@@ -157,3 +220,58 @@ impl KeysBuilder for CenterTwoNeighborsSpeciesKeys {
         return Ok(keys_builder.finish());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::test_utils::test_systems;
+
+    #[test]
+    fn center_species() {
+        let mut systems = test_systems(&["water", "methane"]);
+        let keys = CenterSpeciesKeys.keys(&mut systems).unwrap();
+        assert_eq!(keys, Labels::new(["species_center"], &[[-42], [1], [6]]));
+
+        // species are only included once, even if they are in multiple systems
+        let mut systems = test_systems(&["methane", "CH", "methane"]);
+        let keys = CenterSpeciesKeys.keys(&mut systems).unwrap();
+        assert_eq!(keys, Labels::new(["species_center"], &[[1], [6]]));
+    }
+
+    #[test]
+    fn all_species() {
+        let mut systems = test_systems(&["CH"]);
+        let builder = CenterSingleNeighborsSpeciesKeys {
+            cutoff: 2.0,
+            self_pairs: false,
+            all_species: Some(vec![8, 1, 6]),
+        };
+        let keys = builder.keys(&mut systems).unwrap();
+        assert_eq!(keys, Labels::new(
+            ["species_center", "species_neighbor"],
+            &[[1, 1], [1, 6], [1, 8], [6, 1], [6, 6], [6, 8], [8, 1], [8, 6], [8, 8]],
+        ));
+
+        let builder = CenterTwoNeighborsSpeciesKeys {
+            cutoff: 2.0,
+            self_pairs: false,
+            symmetric: true,
+            all_species: Some(vec![1, 6]),
+        };
+        let keys = builder.keys(&mut systems).unwrap();
+        assert_eq!(keys, Labels::new(
+            ["species_center", "species_neighbor_1", "species_neighbor_2"],
+            &[[1, 1, 1], [1, 1, 6], [1, 6, 6], [6, 1, 1], [6, 1, 6], [6, 6, 6]],
+        ));
+
+        // all the species in the systems must be part of `all_species`
+        let mut systems = test_systems(&["water"]);
+        let builder = CenterSingleNeighborsSpeciesKeys {
+            cutoff: 2.0,
+            self_pairs: false,
+            all_species: Some(vec![1, 8]),
+        };
+        let error = builder.keys(&mut systems).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: species -42 is present in the systems but not in `all_species`");
+    }
+}
@@ -3,6 +3,7 @@ mod samples;
 pub use self::samples::{SpeciesFilter, SamplesBuilder};
 pub use self::samples::AtomCenteredSamples;
 pub use self::samples::LongRangeSamplesPerAtom;
+pub use self::samples::StructureSamples;
 
 mod keys;
 pub use self::keys::KeysBuilder;
@@ -12,6 +12,15 @@ use super::{SamplesBuilder, SpeciesFilter};
 ///
 /// Positions gradient samples include all atoms within a spherical cutoff,
 /// optionally filtering on the neighbor atom species.
+///
+/// The samples can also be restricted to a subset of the atoms with
+/// `selected_centers`. This only applies to the central atoms: the gradients
+/// still include all neighbors, including the ones which are not selected as
+/// centers.
+///
+/// Setting `inner_cutoff` only considers neighbors in the spherical shell
+/// between `inner_cutoff` and `cutoff`, ignoring pairs closer than
+/// `inner_cutoff` for both the neighbor species filters and the gradients.
 pub struct AtomCenteredSamples {
     /// spherical cutoff radius used to construct the atom-centered environments
     pub cutoff: f64,
@@ -21,6 +30,51 @@ pub struct AtomCenteredSamples {
     pub species_neighbor: SpeciesFilter,
     /// Should the central atom be considered it's own neighbor?
     pub self_pairs: bool,
+    /// If this is not `None`, only include samples for the given `(structure,
+    /// center)` pairs, in addition to the species filters above
+    pub selected_centers: Option<Vec<(usize, usize)>>,
+    /// If this is not `None`, ignore all pairs with a distance smaller than
+    /// this inner cutoff radius
+    pub inner_cutoff: Option<f64>,
+}
+
+impl AtomCenteredSamples {
+    /// Get the set of selected centers for the system at index `system`, or
+    /// `None` if all centers are selected
+    fn selected_centers(&self, system: usize) -> Option<BTreeSet<usize>> {
+        self.selected_centers.as_ref().map(|selected| {
+            selected.iter()
+                .filter(|&&(structure, _)| structure == system)
+                .map(|&(_, center)| center)
+                .collect()
+        })
+    }
+
+    /// Check that the cutoff and inner cutoff of this builder make sense
+    fn check_cutoffs(&self) -> Result<(), Error> {
+        if !(self.cutoff > 0.0 && self.cutoff.is_finite()) {
+            return Err(Error::InvalidParameter(format!(
+                "cutoff must be a positive number for AtomCenteredSamples, got {}", self.cutoff
+            )));
+        }
+
+        if let Some(inner_cutoff) = self.inner_cutoff {
+            if !(inner_cutoff >= 0.0 && inner_cutoff < self.cutoff) {
+                return Err(Error::InvalidParameter(format!(
+                    "inner cutoff must be positive and smaller than the cutoff for AtomCenteredSamples, got {}",
+                    inner_cutoff
+                )));
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Should a pair at the given `distance` be ignored because it is inside
+    /// the inner cutoff?
+    fn inside_inner_cutoff(&self, distance: f64) -> bool {
+        self.inner_cutoff.map_or(false, |inner_cutoff| distance < inner_cutoff)
+    }
 }
 
 impl SamplesBuilder for AtomCenteredSamples {
@@ -29,16 +83,21 @@ impl SamplesBuilder for AtomCenteredSamples {
     }
 
     fn samples(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
-        assert!(self.cutoff > 0.0 && self.cutoff.is_finite(), "cutoff must be positive for AtomCenteredSamples");
+        self.check_cutoffs()?;
         let mut builder = LabelsBuilder::new(Self::samples_names());
         for (system_i, system) in systems.iter_mut().enumerate() {
             system.compute_neighbors(self.cutoff)?;
             let species = system.species()?;
 
+            let selected_centers = self.selected_centers(system_i);
+            let is_selected = |center: usize| {
+                selected_centers.as_ref().map_or(true, |selected| selected.contains(&center))
+            };
+
             match &self.species_neighbor {
                 SpeciesFilter::Any => {
                     for (center_i, &species_center) in species.iter().enumerate() {
-                        if self.species_center.matches(species_center) {
+                        if self.species_center.matches(species_center) && is_selected(center_i) {
                             builder.add(&[system_i, center_i]);
                         }
                     }
@@ -46,8 +105,12 @@ impl SamplesBuilder for AtomCenteredSamples {
                 SpeciesFilter::AllOf(requested_species) => {
                     let mut neighbor_species = BTreeSet::new();
                     for (center_i, &species_center) in species.iter().enumerate() {
-                        if self.species_center.matches(species_center) {
+                        if self.species_center.matches(species_center) && is_selected(center_i) {
                             for pair in system.pairs_containing(center_i)? {
+                                if self.inside_inner_cutoff(pair.distance) {
+                                    continue;
+                                }
+
                                 let neighbor = if pair.first == center_i {
                                     pair.second
                                 } else {
@@ -71,12 +134,16 @@ impl SamplesBuilder for AtomCenteredSamples {
                 selection => {
                     let mut matching_centers = BTreeSet::new();
                     for (center_i, &species_center) in species.iter().enumerate() {
-                        if self.species_center.matches(species_center) {
+                        if self.species_center.matches(species_center) && is_selected(center_i) {
                             if self.self_pairs && selection.matches(species_center) {
                                 matching_centers.insert(center_i);
                             }
 
                             for pair in system.pairs_containing(center_i)? {
+                                if self.inside_inner_cutoff(pair.distance) {
+                                    continue;
+                                }
+
                                 let neighbor = if pair.first == center_i {
                                     pair.second
                                 } else {
@@ -101,7 +168,7 @@ impl SamplesBuilder for AtomCenteredSamples {
     }
 
     fn gradients_for(&self, systems: &mut [Box<dyn System>], samples: &Labels) -> Result<Labels, Error> {
-        assert!(self.cutoff > 0.0 && self.cutoff.is_finite(), "cutoff must be positive for AtomCenteredSamples");
+        self.check_cutoffs()?;
         assert_eq!(samples.names(), ["structure", "center"]);
         let mut builder = LabelsBuilder::new(vec!["sample", "structure", "atom"]);
 
@@ -125,6 +192,10 @@ impl SamplesBuilder for AtomCenteredSamples {
             }
 
             for pair in system.pairs_containing(center_i)? {
+                if self.inside_inner_cutoff(pair.distance) {
+                    continue;
+                }
+
                 let neighbor_i = if pair.first == center_i {
                     pair.second
                 } else {
@@ -162,6 +233,8 @@ mod tests {
             species_center: SpeciesFilter::Any,
             species_neighbor: SpeciesFilter::Any,
             self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
         };
 
         let samples = builder.samples(&mut systems).unwrap();
@@ -193,6 +266,8 @@ mod tests {
             species_center: SpeciesFilter::Single(1),
             species_neighbor: SpeciesFilter::Any,
             self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
         };
 
         let samples = builder.samples(&mut systems).unwrap();
@@ -222,6 +297,8 @@ mod tests {
             species_center: SpeciesFilter::Any,
             species_neighbor: SpeciesFilter::Single(1),
             self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
         };
 
         let samples = builder.samples(&mut systems).unwrap();
@@ -249,6 +326,8 @@ mod tests {
             species_center: SpeciesFilter::Any,
             species_neighbor: SpeciesFilter::OneOf(vec![1, 6]),
             self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
         };
 
         let gradient_samples = builder.gradients_for(&mut systems, &samples).unwrap();
@@ -266,6 +345,159 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn exclude_species_neighbor() {
+        let mut systems = test_systems(&["CH", "water"]);
+        let builder = AtomCenteredSamples {
+            cutoff: 2.0,
+            species_center: SpeciesFilter::Any,
+            species_neighbor: SpeciesFilter::NoneOf([1].into_iter().collect()),
+            self_pairs: false,
+            selected_centers: None,
+            inner_cutoff: None,
+        };
+
+        // only centers with a non-hydrogen neighbor are included
+        let samples = builder.samples(&mut systems).unwrap();
+        assert_eq!(samples, Labels::new(
+            ["structure", "center"],
+            &[[0, 1], [1, 1], [1, 2]],
+        ));
+
+        let gradient_samples = builder.gradients_for(&mut systems, &samples).unwrap();
+        assert_eq!(gradient_samples, Labels::new(
+            ["sample", "structure", "atom"],
+            &[
+                // gradients of H in CH w.r.t. C and itself
+                [0, 0, 0], [0, 0, 1],
+                // gradients of H atoms in water w.r.t. O and themselves
+                [1, 1, 0], [1, 1, 1],
+                [2, 1, 0], [2, 1, 2],
+            ],
+        ));
+
+        // combined with a filter on the center species
+        let builder = AtomCenteredSamples {
+            cutoff: 2.0,
+            species_center: SpeciesFilter::NoneOf([1].into_iter().collect()),
+            species_neighbor: SpeciesFilter::NoneOf([1].into_iter().collect()),
+            self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
+        };
+
+        let samples = builder.samples(&mut systems).unwrap();
+        assert_eq!(samples, Labels::new(["structure", "center"], &[[0, 0], [1, 0]]));
+
+        let gradient_samples = builder.gradients_for(&mut systems, &samples).unwrap();
+        assert_eq!(gradient_samples, Labels::new(
+            ["sample", "structure", "atom"],
+            &[[0, 0, 0], [1, 1, 0]],
+        ));
+    }
+
+    #[test]
+    fn selected_centers() {
+        let mut systems = test_systems(&["CH", "water"]);
+        let builder = AtomCenteredSamples {
+            cutoff: 2.0,
+            species_center: SpeciesFilter::Any,
+            species_neighbor: SpeciesFilter::Any,
+            self_pairs: true,
+            selected_centers: Some(vec![(1, 2), (0, 1), (1, 0), (3, 0)]),
+            inner_cutoff: None,
+        };
+
+        let samples = builder.samples(&mut systems).unwrap();
+        assert_eq!(samples, Labels::new(
+            ["structure", "center"],
+            &[[0, 1], [1, 0], [1, 2]],
+        ));
+
+        // gradients still include the atoms which are not selected as centers
+        let gradient_samples = builder.gradients_for(&mut systems, &samples).unwrap();
+        assert_eq!(gradient_samples, Labels::new(
+            ["sample", "structure", "atom"],
+            &[
+                [0, 0, 0], [0, 0, 1],
+                [1, 1, 0], [1, 1, 1], [1, 1, 2],
+                [2, 1, 0], [2, 1, 1], [2, 1, 2],
+            ],
+        ));
+
+        // selection is combined with the species filters
+        let builder = AtomCenteredSamples {
+            cutoff: 2.0,
+            species_center: SpeciesFilter::Single(1),
+            species_neighbor: SpeciesFilter::Single(-42),
+            self_pairs: true,
+            selected_centers: Some(vec![(0, 0), (0, 1), (1, 0), (1, 1)]),
+            inner_cutoff: None,
+        };
+
+        let samples = builder.samples(&mut systems).unwrap();
+        assert_eq!(samples, Labels::new(["structure", "center"], &[[1, 1]]));
+    }
+
+    #[test]
+    fn inner_cutoff() {
+        let mut systems = test_systems(&["CH", "water"]);
+        // the O-H distance in water is 0.9579, the H-H distance is 1.51 and
+        // the C-H distance in CH is 1.2
+        let builder = AtomCenteredSamples {
+            cutoff: 2.0,
+            species_center: SpeciesFilter::Any,
+            species_neighbor: SpeciesFilter::Single(1),
+            self_pairs: false,
+            selected_centers: None,
+            inner_cutoff: Some(1.0),
+        };
+
+        let samples = builder.samples(&mut systems).unwrap();
+        assert_eq!(samples, Labels::new(
+            ["structure", "center"],
+            &[[0, 0], [1, 1], [1, 2]],
+        ));
+
+        let gradient_samples = builder.gradients_for(&mut systems, &samples).unwrap();
+        assert_eq!(gradient_samples, Labels::new(
+            ["sample", "structure", "atom"],
+            &[
+                // C-H pair in CH
+                [0, 0, 0], [0, 0, 1],
+                // H-H pair in water, the O-H pairs are excluded
+                [1, 1, 1], [1, 1, 2],
+                [2, 1, 1], [2, 1, 2],
+            ],
+        ));
+    }
+
+    #[test]
+    fn invalid_inner_cutoff() {
+        let mut systems = test_systems(&["water"]);
+        let builder = AtomCenteredSamples {
+            cutoff: 2.0,
+            species_center: SpeciesFilter::Any,
+            species_neighbor: SpeciesFilter::Any,
+            self_pairs: false,
+            selected_centers: None,
+            inner_cutoff: Some(3.0),
+        };
+
+        let error = builder.samples(&mut systems).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: inner cutoff must be positive and smaller than the cutoff for AtomCenteredSamples, got 3"
+        );
+
+        let samples = Labels::new(["structure", "center"], &[[0, 0]]);
+        let error = builder.gradients_for(&mut systems, &samples).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: inner cutoff must be positive and smaller than the cutoff for AtomCenteredSamples, got 3"
+        );
+    }
+
     #[test]
     fn partial_gradients() {
         let samples = Labels::new(["structure", "center"], &[
@@ -280,6 +512,8 @@ mod tests {
             species_center: SpeciesFilter::Any,
             species_neighbor: SpeciesFilter::Single(-42),
             self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
         };
 
         let gradients = builder.gradients_for(&mut systems, &samples).unwrap();
@@ -293,6 +527,8 @@ mod tests {
             species_center: SpeciesFilter::Any,
             species_neighbor: SpeciesFilter::Single(1),
             self_pairs: true,
+            selected_centers: None,
+            inner_cutoff: None,
         };
         let gradients = builder.gradients_for(&mut systems, &samples).unwrap();
         assert_eq!(gradients, Labels::new(
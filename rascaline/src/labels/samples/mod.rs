@@ -15,6 +15,8 @@ pub enum SpeciesFilter {
     /// All of the given atoms species must be present. This can only be used
     /// for neighbor species selection.
     AllOf(BTreeSet<i32>),
+    /// Any atomic species except the given ones is fine
+    NoneOf(BTreeSet<i32>),
 }
 
 impl SpeciesFilter {
@@ -24,6 +26,7 @@ impl SpeciesFilter {
             SpeciesFilter::Any => true,
             SpeciesFilter::Single(selected) => species == *selected,
             SpeciesFilter::OneOf(selected) => selected.contains(&species),
+            SpeciesFilter::NoneOf(excluded) => !excluded.contains(&species),
             SpeciesFilter::AllOf(_) => panic!("internal error: can not call `matches` on a `SpeciesFilter::AllOf`"),
         }
     }
@@ -57,3 +60,6 @@ pub use self::atom_centered::AtomCenteredSamples;
 
 mod long_range;
 pub use self::long_range::LongRangeSamplesPerAtom;
+
+mod structure;
+pub use self::structure::StructureSamples;
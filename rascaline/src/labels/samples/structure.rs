@@ -0,0 +1,76 @@
+use equistore::{Labels, LabelsBuilder};
+
+use crate::{Error, System};
+use super::SamplesBuilder;
+
+/// Samples builder for per-structure representations, with one sample for
+/// each system.
+///
+/// The gradients samples contain all the atoms in the corresponding system.
+pub struct StructureSamples;
+
+impl SamplesBuilder for StructureSamples {
+    fn samples_names() -> Vec<&'static str> {
+        vec!["structure"]
+    }
+
+    fn samples(&self, systems: &mut [Box<dyn System>]) -> Result<Labels, Error> {
+        let mut builder = LabelsBuilder::new(Self::samples_names());
+        for system_i in 0..systems.len() {
+            builder.add(&[system_i]);
+        }
+
+        return Ok(builder.finish());
+    }
+
+    fn gradients_for(&self, systems: &mut [Box<dyn System>], samples: &Labels) -> Result<Labels, Error> {
+        assert_eq!(samples.names(), ["structure"]);
+        let mut builder = LabelsBuilder::new(vec!["sample", "structure", "atom"]);
+
+        for (sample_i, [structure_i]) in samples.iter_fixed_size().enumerate() {
+            let structure_i = structure_i.usize();
+            for atom_i in 0..systems[structure_i].size()? {
+                builder.add(&[sample_i, structure_i, atom_i]);
+            }
+        }
+
+        return Ok(builder.finish());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::systems::test_utils::test_systems;
+
+    #[test]
+    fn all_samples() {
+        let mut systems = test_systems(&["CH", "water"]);
+
+        let samples = StructureSamples.samples(&mut systems).unwrap();
+        assert_eq!(samples, Labels::new(["structure"], &[[0], [1]]));
+
+        let gradient_samples = StructureSamples.gradients_for(&mut systems, &samples).unwrap();
+        assert_eq!(gradient_samples, Labels::new(
+            ["sample", "structure", "atom"],
+            &[
+                // gradients of atoms in CH
+                [0, 0, 0], [0, 0, 1],
+                // gradients of atoms in water
+                [1, 1, 0], [1, 1, 1], [1, 1, 2],
+            ]
+        ));
+    }
+
+    #[test]
+    fn partial_gradients() {
+        let mut systems = test_systems(&["CH", "water"]);
+
+        let samples = Labels::new(["structure"], &[[1]]);
+        let gradient_samples = StructureSamples.gradients_for(&mut systems, &samples).unwrap();
+        assert_eq!(gradient_samples, Labels::new(
+            ["sample", "structure", "atom"],
+            &[[0, 1, 0], [0, 1, 1], [0, 1, 2]]
+        ));
+    }
+}
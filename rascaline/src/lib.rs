@@ -21,7 +21,7 @@ pub use types::*;
 pub mod math;
 
 mod errors;
-pub use self::errors::Error;
+pub use self::errors::{Error, ErrorContext};
 
 pub mod systems;
 pub use self::systems::{System, SimpleSystem};
@@ -29,10 +29,25 @@ pub use self::systems::{System, SimpleSystem};
 pub mod labels;
 
 mod calculator;
-pub use self::calculator::{Calculator, CalculationOptions, LabelsSelection};
+pub use self::calculator::{Calculator, CalculationOptions, CellInference, LabelsSelection, PostProcess, ProgressCallback};
+pub use self::calculator::{SinglePrecisionTensorMap, SinglePrecisionBlock};
 
 pub mod calculators;
 
+pub mod utils;
+
+pub mod kernels;
+
+pub mod analysis;
+
+pub mod io;
+
+pub mod profiling;
+
+// the validation module is always used by the tests of the calculators
+#[cfg(any(test, feature = "validation"))]
+pub mod validation;
+
 // only try to build the tutorials in test mode
 #[cfg(test)]
 mod tutorials;
@@ -0,0 +1,63 @@
+/// Get the nodes and weights of the `n`-points Gauss-Legendre quadrature on
+/// the `[-1, 1]` interval.
+///
+/// The nodes are found with Newton's method, starting from the usual
+/// asymptotic approximation of the roots of the Legendre polynomial `P_n`.
+pub fn gauss_legendre(n: usize) -> (Vec<f64>, Vec<f64>) {
+    assert!(n > 0, "Gauss-Legendre quadrature needs at least one point");
+
+    let mut nodes = Vec::with_capacity(n);
+    let mut weights = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut x = f64::cos(std::f64::consts::PI * (i as f64 + 0.75) / (n as f64 + 0.5));
+        let mut derivative;
+        loop {
+            let (p_n, p_n_minus_1) = legendre(n, x);
+            derivative = n as f64 * (x * p_n - p_n_minus_1) / (x * x - 1.0);
+
+            let dx = p_n / derivative;
+            x -= dx;
+            if dx.abs() < 1e-15 {
+                break;
+            }
+        }
+
+        nodes.push(x);
+        weights.push(2.0 / ((1.0 - x * x) * derivative * derivative));
+    }
+
+    return (nodes, weights);
+}
+
+/// Evaluate the Legendre polynomials `P_n(x)` and `P_{n - 1}(x)`
+fn legendre(n: usize, x: f64) -> (f64, f64) {
+    debug_assert!(n > 0);
+    let mut p_k = x;
+    let mut p_k_minus_1 = 1.0;
+    for k in 2..=n {
+        let k = k as f64;
+        let p_k_plus_1 = ((2.0 * k - 1.0) * x * p_k - (k - 1.0) * p_k_minus_1) / k;
+        p_k_minus_1 = p_k;
+        p_k = p_k_plus_1;
+    }
+
+    return (p_k, p_k_minus_1);
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use super::gauss_legendre;
+
+    #[test]
+    fn polynomials() {
+        let (nodes, weights) = gauss_legendre(5);
+        assert_relative_eq!(weights.iter().sum::<f64>(), 2.0, max_relative=1e-14);
+
+        // a n-points quadrature is exact for polynomials up to degree 2n - 1
+        let integral = nodes.iter().zip(&weights)
+            .map(|(&x, &w)| w * (x.powi(8) + 3.0 * x.powi(3)))
+            .sum::<f64>();
+        assert_relative_eq!(integral, 2.0 / 9.0, max_relative=1e-13);
+    }
+}
@@ -16,12 +16,18 @@ pub use self::erf::{erf, erfc};
 mod gamma;
 pub(crate) use self::gamma::{gamma, ln_gamma, digamma};
 
+mod gauss_legendre;
+pub(crate) use self::gauss_legendre::gauss_legendre;
+
 mod hyp1f1;
 pub(crate) use self::hyp1f1::hyp1f1;
 
 mod hyp2f1;
 pub (crate) use self::hyp2f1::hyp2f1;
 
+mod spherical_bessel;
+pub(crate) use self::spherical_bessel::{spherical_bessel_first_kind, modified_spherical_bessel_scaled};
+
 mod splines;
 pub(crate) use self::splines::{HermitSplinePoint, HermitCubicSpline, SplineParameters};
 
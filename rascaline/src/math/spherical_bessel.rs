@@ -0,0 +1,178 @@
+/// Threshold used to rescale the values in downward recurrences and avoid
+/// overflows
+const RESCALE_THRESHOLD: f64 = 1e250;
+
+/// Compute the spherical Bessel function of the first kind `j_l(x)`.
+///
+/// Upward recurrence is used when `x > l`, where it is stable; and downward
+/// recurrence (Miller's algorithm) otherwise.
+pub fn spherical_bessel_first_kind(l: usize, x: f64) -> f64 {
+    if x == 0.0 {
+        return if l == 0 { 1.0 } else { 0.0 };
+    }
+
+    let j_0 = x.sin() / x;
+    let j_1 = x.sin() / (x * x) - x.cos() / x;
+    if l == 0 {
+        return j_0;
+    } else if l == 1 && x > 1.0 {
+        // the closed form suffers from cancellations for small x
+        return j_1;
+    }
+
+    if x > l as f64 {
+        let mut previous = j_0;
+        let mut current = j_1;
+        for k in 1..l {
+            let next = (2 * k + 1) as f64 / x * current - previous;
+            previous = current;
+            current = next;
+        }
+        return current;
+    }
+
+    // downward recurrence, starting well above l
+    let start = l + 30 + x as usize;
+    let mut next = 0.0;
+    let mut current = 1.0;
+    let mut value = 0.0;
+    let mut k_0 = 0.0;
+    let mut k_1 = 0.0;
+    for k in (1..=start).rev() {
+        // compute j_{k - 1} from j_k and j_{k + 1}
+        let previous = (2 * k + 1) as f64 / x * current - next;
+        next = current;
+        current = previous;
+
+        if k - 1 == l {
+            value = current;
+        } else if k - 1 == 1 {
+            k_1 = current;
+        } else if k - 1 == 0 {
+            k_0 = current;
+        }
+
+        if current.abs() > RESCALE_THRESHOLD {
+            current /= RESCALE_THRESHOLD;
+            next /= RESCALE_THRESHOLD;
+            value /= RESCALE_THRESHOLD;
+            k_1 /= RESCALE_THRESHOLD;
+        }
+    }
+
+    // normalize with whichever of j_0 and j_1 is further away from zero
+    if j_0.abs() > j_1.abs() {
+        return value * j_0 / k_0;
+    } else {
+        return value * j_1 / k_1;
+    }
+}
+
+/// Compute the exponentially scaled modified spherical Bessel functions of the
+/// first kind `e^{-x} i_l(x)` for all `l` from 0 to `values.len() - 1`, and
+/// store them in `values`. `x` must be positive.
+///
+/// The scaling removes the exponential growth of `i_l(x)`, allowing to
+/// evaluate it for large values of `x`.
+pub fn modified_spherical_bessel_scaled(x: f64, values: &mut [f64]) {
+    assert!(x >= 0.0, "x must be positive in modified_spherical_bessel_scaled");
+    if values.is_empty() {
+        return;
+    }
+
+    values.fill(0.0);
+    if x == 0.0 {
+        values[0] = 1.0;
+        return;
+    }
+
+    let max_l = values.len() - 1;
+    // i_l(x) / i_0(x) behaves like exp(-l^2 / 2x) for large x, start the
+    // downward recurrence high enough for this ratio to be negligible
+    let start = max_l + 30 + (10.0 * x.sqrt()) as usize;
+
+    let mut next = 0.0;
+    let mut current = 1.0;
+    for k in (1..=start).rev() {
+        // compute i_{k - 1} from i_k and i_{k + 1}
+        let previous = next + (2 * k + 1) as f64 / x * current;
+        next = current;
+        current = previous;
+
+        if k - 1 <= max_l {
+            values[k - 1] = current;
+        }
+
+        if current.abs() > RESCALE_THRESHOLD {
+            current /= RESCALE_THRESHOLD;
+            next /= RESCALE_THRESHOLD;
+            for value in values.iter_mut() {
+                *value /= RESCALE_THRESHOLD;
+            }
+        }
+    }
+
+    // e^{-x} i_0(x) = (1 - e^{-2x}) / 2x
+    let scaled_i_0 = -f64::exp_m1(-2.0 * x) / (2.0 * x);
+    let normalization = scaled_i_0 / values[0];
+    for value in values.iter_mut() {
+        *value *= normalization;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use super::*;
+
+    #[test]
+    fn first_kind() {
+        // reference values from scipy.special.spherical_jn
+        assert_relative_eq!(spherical_bessel_first_kind(0, 1.5), 0.6649966577360363, max_relative=1e-12);
+        assert_relative_eq!(spherical_bessel_first_kind(1, 1.5), 0.39617297071222224, max_relative=1e-12);
+        assert_relative_eq!(spherical_bessel_first_kind(1, 1e-3), 3.333333000000012e-4, max_relative=1e-12);
+
+        for (l, x) in [(2, 0.3), (2, 3.7), (5, 1.2), (5, 12.5), (8, 4.0)] {
+            // check the recurrence relation
+            let expected = (2 * l - 1) as f64 / x * spherical_bessel_first_kind(l - 1, x)
+                - spherical_bessel_first_kind(l - 2, x);
+            assert_relative_eq!(spherical_bessel_first_kind(l, x), expected, max_relative=1e-8);
+        }
+
+        // small x behavior: j_l(x) ~ x^l / (2l + 1)!!
+        let x = 1e-3;
+        assert_relative_eq!(spherical_bessel_first_kind(3, x), x * x * x / 105.0, max_relative=1e-6);
+
+        assert_eq!(spherical_bessel_first_kind(0, 0.0), 1.0);
+        assert_eq!(spherical_bessel_first_kind(3, 0.0), 0.0);
+    }
+
+    #[test]
+    fn modified_scaled() {
+        let mut values = [0.0; 3];
+        modified_spherical_bessel_scaled(1.0, &mut values);
+
+        let i_0 = f64::sinh(1.0);
+        let i_1 = f64::cosh(1.0) - f64::sinh(1.0);
+        let i_2 = i_0 - 3.0 * i_1;
+        assert_relative_eq!(values[0], f64::exp(-1.0) * i_0, max_relative=1e-12);
+        assert_relative_eq!(values[1], f64::exp(-1.0) * i_1, max_relative=1e-12);
+        assert_relative_eq!(values[2], f64::exp(-1.0) * i_2, max_relative=1e-10);
+
+        // large x, where i_l(x) e^{-x} ~ 1 / 2x
+        let mut values = [0.0; 4];
+        modified_spherical_bessel_scaled(500.0, &mut values);
+        for l in 0..4 {
+            let expected = 1.0 / 1000.0 * f64::exp(-(l * (l + 1)) as f64 / 1000.0);
+            assert_relative_eq!(values[l], expected, max_relative=1e-5);
+        }
+
+        // small x, where i_l(x) ~ x^l / (2l + 1)!!
+        let mut values = [0.0; 4];
+        modified_spherical_bessel_scaled(1e-4, &mut values);
+        assert_relative_eq!(values[3], 1e-12 / 105.0, max_relative=1e-6);
+
+        modified_spherical_bessel_scaled(0.0, &mut values);
+        assert_eq!(values, [1.0, 0.0, 0.0, 0.0]);
+    }
+}
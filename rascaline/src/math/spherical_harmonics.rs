@@ -1,5 +1,6 @@
 #![allow(clippy::range_plus_one)]
 
+use std::collections::HashMap;
 use std::f64;
 use std::f64::consts::SQRT_2;
 
@@ -424,8 +425,21 @@ impl SphericalHarmonics {
 }
 
 
+/// Spherical harmonics values (and optionally gradients) for a single
+/// direction, stored in the directions cache of [`SphericalHarmonicsCache`]
+struct CachedDirection {
+    values: SphericalHarmonicsArray,
+    gradients: Option<[SphericalHarmonicsArray; 3]>,
+}
+
 /// Store together the spherical harmonics implementation and cached allocation
 /// for values/gradients.
+///
+/// This can optionally keep the spherical harmonics computed for previously
+/// seen directions, which is useful for highly symmetric systems (e.g. perfect
+/// crystals) where the same directions appear for many different pairs.
+/// Directions are looked up using the exact bit pattern of their components,
+/// so cached results are identical to the ones computed from scratch.
 pub(crate) struct SphericalHarmonicsCache {
     /// Implementation of the spherical harmonics
     code: SphericalHarmonics,
@@ -433,11 +447,29 @@ pub(crate) struct SphericalHarmonicsCache {
     pub(crate) values: SphericalHarmonicsArray,
     /// Cache for the spherical harmonics gradients (one value each for x/y/z)
     pub(crate) gradients: [SphericalHarmonicsArray; 3],
+    /// Maximal number of directions to store in `directions`, or 0 to disable
+    /// the directions cache
+    max_directions: usize,
+    /// Map from the bits of the directions components to their position in
+    /// `directions`
+    directions_index: HashMap<[u64; 3], usize>,
+    /// Values and gradients for previously computed directions
+    directions: Vec<CachedDirection>,
 }
 
 impl SphericalHarmonicsCache {
     /// Create a new `SphericalHarmonicsCache` for the given `max_angular` parameter
     pub(crate) fn new(max_angular: usize) -> SphericalHarmonicsCache {
+        return SphericalHarmonicsCache::with_directions_cache(max_angular, 0);
+    }
+
+    /// Create a new `SphericalHarmonicsCache` for the given `max_angular`
+    /// parameter, keeping the values and gradients for up to `max_directions`
+    /// different directions.
+    ///
+    /// Once the cache is full, new directions are computed but not stored
+    /// anymore, until [`SphericalHarmonicsCache::clear_directions`] is called.
+    pub(crate) fn with_directions_cache(max_angular: usize, max_directions: usize) -> SphericalHarmonicsCache {
         let code = SphericalHarmonics::new(max_angular);
         let values = SphericalHarmonicsArray::new(max_angular);
         let gradients = [
@@ -446,12 +478,74 @@ impl SphericalHarmonicsCache {
             SphericalHarmonicsArray::new(max_angular)
         ];
 
-        return SphericalHarmonicsCache { code, values, gradients };
+        return SphericalHarmonicsCache {
+            code,
+            values,
+            gradients,
+            max_directions,
+            directions_index: HashMap::new(),
+            directions: Vec::new(),
+        };
+    }
+
+    /// Remove all directions from the directions cache
+    pub(crate) fn clear_directions(&mut self) {
+        self.directions_index.clear();
+        self.directions.clear();
     }
 
     /// Run the calculation, the results are stored inside `self.values` and
     /// `self.gradients`
     pub(crate) fn compute(&mut self, direction: Vector3D, gradient: bool) {
+        if self.max_directions == 0 {
+            self.compute_direction(direction, gradient);
+            return;
+        }
+
+        let key = [
+            direction[0].to_bits(),
+            direction[1].to_bits(),
+            direction[2].to_bits(),
+        ];
+
+        let index = self.directions_index.get(&key).copied();
+        if let Some(index) = index {
+            let cached = &self.directions[index];
+            match (gradient, &cached.gradients) {
+                (false, _) => {
+                    self.values.data.copy_from_slice(&cached.values.data);
+                    return;
+                }
+                (true, Some(gradients)) => {
+                    self.values.data.copy_from_slice(&cached.values.data);
+                    for (gradient, cached) in self.gradients.iter_mut().zip(gradients) {
+                        gradient.data.copy_from_slice(&cached.data);
+                    }
+                    return;
+                }
+                // we need to compute the gradients for this direction
+                (true, None) => {}
+            }
+        }
+
+        self.compute_direction(direction, gradient);
+
+        let cached = CachedDirection {
+            values: self.values.clone(),
+            gradients: if gradient { Some(self.gradients.clone()) } else { None },
+        };
+
+        if let Some(index) = index {
+            self.directions[index] = cached;
+        } else if self.directions.len() < self.max_directions {
+            self.directions_index.insert(key, self.directions.len());
+            self.directions.push(cached);
+        }
+    }
+
+    /// Compute the spherical harmonics for the given direction, without
+    /// using the directions cache
+    fn compute_direction(&mut self, direction: Vector3D, gradient: bool) {
         if gradient {
             self.code.compute(
                 direction,
@@ -465,7 +559,6 @@ impl SphericalHarmonicsCache {
                 None,
             );
         }
-
     }
 }
 
@@ -587,6 +680,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn directions_cache() {
+        let max_angular = 8;
+        let mut reference = SphericalHarmonicsCache::new(max_angular);
+        let mut cached = SphericalHarmonicsCache::with_directions_cache(max_angular, 2);
+
+        let mut directions = [
+            Vector3D::new(1.0, 1.0, 1.0),
+            Vector3D::new(1.0, -3.0, 9.0),
+            Vector3D::new(-452.0, 825.0, 22.0),
+        ];
+        for d in &mut directions {
+            *d /= d.norm();
+        }
+
+        // first without gradients, then with gradients for the same
+        // directions, then without gradients again
+        for gradient in [false, true, false] {
+            for _ in 0..2 {
+                for &direction in &directions {
+                    reference.compute(direction, gradient);
+                    cached.compute(direction, gradient);
+
+                    assert_eq!(cached.values.data, reference.values.data);
+                    if gradient {
+                        for spatial in 0..3 {
+                            assert_eq!(cached.gradients[spatial].data, reference.gradients[spatial].data);
+                        }
+                    }
+                }
+            }
+        }
+
+        // only the first two directions are stored
+        assert_eq!(cached.directions.len(), 2);
+        assert!(cached.directions.iter().all(|d| d.gradients.is_some()));
+
+        cached.clear_directions();
+        assert!(cached.directions.is_empty());
+
+        // directions are only shared if they are bitwise identical
+        let direction = directions[0];
+        let mut shifted = direction;
+        shifted[0] = f64::from_bits(shifted[0].to_bits() + 1);
+
+        cached.compute(direction, false);
+        cached.compute(shifted, false);
+        reference.compute(shifted, false);
+        assert_eq!(cached.directions.len(), 2);
+        assert_eq!(cached.values.data, reference.values.data);
+    }
+
     mod bad {
         use super::super::{SphericalHarmonics, SphericalHarmonicsArray};
         use crate::Vector3D;
@@ -0,0 +1,71 @@
+//! Access to the profiling data collected during calculations.
+//!
+//! Rascaline uses [`time_graph`](https://docs.rs/time-graph/) to record the
+//! time spent inside the most important functions. The construction of
+//! neighbor lists is recorded under [`NEIGHBORS_LIST`], separately from the
+//! actual calculation of the representation, recorded under [`COMPUTE`].
+//!
+//! ```no_run
+//! rascaline::profiling::enable(true);
+//! rascaline::profiling::clear();
+//!
+//! // run some calculations here
+//!
+//! let timings = rascaline::profiling::get();
+//! if let Some(timing) = timings.get(rascaline::profiling::NEIGHBORS_LIST) {
+//!     println!("spent {:?} building neighbor lists", timing.elapsed);
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Name of the timing entry corresponding to the construction of neighbor
+/// lists
+pub const NEIGHBORS_LIST: &str = "NeighborsList::new";
+
+/// Name of the timing entry corresponding to the calculation of the
+/// representation, once the neighbor lists and the output metadata are
+/// available
+pub const COMPUTE: &str = "Calculator::compute";
+
+/// Timing information collected for a single function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Total time spent inside this function
+    pub elapsed: Duration,
+    /// Number of times this function was called
+    pub called: u32,
+}
+
+/// Enable or disable profiling data collection. By default, data collection
+/// is disabled.
+pub fn enable(enabled: bool) {
+    time_graph::enable_data_collection(enabled);
+}
+
+/// Clear all the profiling data collected so far
+pub fn clear() {
+    time_graph::clear_collected_data();
+}
+
+/// Get the timing information collected so far, indexed by function name.
+///
+/// If the same function was called from multiple places, the corresponding
+/// timings are summed together.
+pub fn get() -> BTreeMap<&'static str, Timing> {
+    let graph = time_graph::get_full_graph();
+
+    let mut timings = BTreeMap::new();
+    for span in graph.spans() {
+        let timing = timings.entry(span.callsite.name()).or_insert(Timing {
+            elapsed: Duration::ZERO,
+            called: 0,
+        });
+
+        timing.elapsed += span.elapsed;
+        timing.called += span.called;
+    }
+
+    return timings;
+}
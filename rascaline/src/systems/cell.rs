@@ -96,18 +96,41 @@ impl UnitCell {
     }
 
     /// Create a triclinic unit cell, with side lengths `a, b, c` and angles
-    /// `alpha, beta, gamma`.
+    /// `alpha, beta, gamma` (in degrees), as typically found in
+    /// crystallographic data.
+    ///
+    /// The first cell vector is aligned with the x axis, and the second one is
+    /// in the xy plane, giving a lower triangular cell matrix where each row is
+    /// a cell vector. If all angles are 90°, the resulting cell is
+    /// orthorhombic.
     pub fn triclinic(a: f64, b: f64, c: f64, alpha: f64, beta: f64, gamma: f64) -> UnitCell {
         assert!(a > 0.0 && b > 0.0 && c > 0.0, "Cell lengths must be positive");
+        for angle in [alpha, beta, gamma] {
+            assert!(angle > 0.0 && angle < 180.0, "Cell angles must be between 0 and 180 degrees");
+        }
+
+        let is_right_angle = |angle: f64| f64::abs(angle - 90.0) < 1e-12;
+        if is_right_angle(alpha) && is_right_angle(beta) && is_right_angle(gamma) {
+            return UnitCell::orthorhombic(a, b, c);
+        }
+
         let cos_alpha = alpha.to_radians().cos();
         let cos_beta = beta.to_radians().cos();
         let (sin_gamma, cos_gamma) = gamma.to_radians().sin_cos();
 
+        // the angles must be compatible with each other for the last cell
+        // vector to exist
+        let c_y = (cos_alpha - cos_beta * cos_gamma) / sin_gamma;
+        assert!(
+            1.0 - cos_beta * cos_beta - c_y * c_y > 0.0,
+            "Cell angles do not describe a valid unit cell"
+        );
+
         let b_x = b * cos_gamma;
         let b_y = b * sin_gamma;
 
         let c_x = c * cos_beta;
-        let c_y = c * (cos_alpha - cos_beta * cos_gamma) / sin_gamma;
+        let c_y = c * c_y;
         let c_z = f64::sqrt(c * c - c_y * c_y - c_x * c_x);
 
         return UnitCell::from(Matrix3::new([
@@ -117,6 +140,13 @@ impl UnitCell {
         ]));
     }
 
+    /// Create an hexagonal unit cell, with side lengths `a, a, c` and angles
+    /// `90°, 90°, 120°`. The first cell vector is aligned with the x axis, the
+    /// second is `(-a/2, a √3/2, 0)` and the third is aligned with the z axis.
+    pub fn hexagonal(a: f64, c: f64) -> UnitCell {
+        return UnitCell::triclinic(a, a, c, 90.0, 90.0, 120.0);
+    }
+
     /// Get the cell shape
     pub fn shape(&self) -> CellShape {
         self.shape
@@ -336,6 +366,56 @@ mod tests {
         assert_relative_eq!(cell.volume(), 55.410529, epsilon = 1e-6);
     }
 
+    #[test]
+    fn triclinic_right_angles() {
+        let cell = UnitCell::triclinic(3.0, 4.0, 5.0, 90.0, 90.0, 90.0);
+        assert_eq!(cell.shape(), CellShape::Orthorhombic);
+        assert_eq!(cell, UnitCell::orthorhombic(3.0, 4.0, 5.0));
+    }
+
+    #[test]
+    fn triclinic_matrix() {
+        // the cell matrix is lower triangular
+        let cell = UnitCell::triclinic(3.0, 4.0, 5.0, 80.0, 90.0, 110.0);
+        let matrix = cell.matrix();
+        assert_eq!(matrix[0][1], 0.0);
+        assert_eq!(matrix[0][2], 0.0);
+        assert_eq!(matrix[1][2], 0.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cell angles must be between 0 and 180 degrees")]
+    fn negative_angle_triclinic() {
+        UnitCell::triclinic(3.0, 4.0, 5.0, -20.0, 90.0, 90.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cell angles do not describe a valid unit cell")]
+    fn invalid_angles_triclinic() {
+        UnitCell::triclinic(3.0, 4.0, 5.0, 20.0, 20.0, 120.0);
+    }
+
+    #[test]
+    fn hexagonal() {
+        let cell = UnitCell::hexagonal(2.5, 4.0);
+        assert_eq!(cell.shape(), CellShape::Triclinic);
+
+        assert_relative_eq!(cell.a(), 2.5, epsilon = 1e-12);
+        assert_relative_eq!(cell.b(), 2.5, epsilon = 1e-12);
+        assert_relative_eq!(cell.c(), 4.0, epsilon = 1e-12);
+
+        assert_relative_eq!(cell.alpha(), 90.0, epsilon = 1e-12);
+        assert_relative_eq!(cell.beta(), 90.0, epsilon = 1e-12);
+        assert_relative_eq!(cell.gamma(), 120.0, epsilon = 1e-12);
+
+        let matrix = cell.matrix();
+        assert_relative_eq!(Vector3D::from(matrix[0]), Vector3D::new(2.5, 0.0, 0.0), epsilon = 1e-12);
+        assert_relative_eq!(Vector3D::from(matrix[1]), Vector3D::new(-1.25, 2.5 * f64::sqrt(3.0) / 2.0, 0.0), epsilon = 1e-12);
+        assert_relative_eq!(Vector3D::from(matrix[2]), Vector3D::new(0.0, 0.0, 4.0), epsilon = 1e-12);
+
+        assert_relative_eq!(cell.volume(), f64::sqrt(3.0) / 2.0 * 2.5 * 2.5 * 4.0, epsilon = 1e-12);
+    }
+
     #[test]
     fn distances_between_faces() {
         let ortho = UnitCell::orthorhombic(3.0, 4.0, 5.0);
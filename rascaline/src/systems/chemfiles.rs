@@ -1,6 +1,7 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 
-use super::SimpleSystem;
+use super::{System, SimpleSystem};
 use crate::Error;
 
 #[cfg(feature = "chemfiles")]
@@ -15,32 +16,175 @@ impl From<chemfiles::Error> for Error {
 ///
 /// This function can read all [formats supported by
 /// chemfiles](https://chemfiles.org/chemfiles/latest/formats.html).
+///
+/// Atoms without a known atomic number are assigned a species starting at
+/// 120, with one species for each different atomic type.
 #[cfg(feature = "chemfiles")]
-#[allow(clippy::needless_range_loop)]
 pub fn read_from_file(path: impl AsRef<Path>) -> Result<Vec<SimpleSystem>, Error> {
     use std::collections::HashMap;
-    use crate::Matrix3;
-    use crate::systems::UnitCell;
-
-    let mut systems = Vec::new();
-
-    let mut trajectory = chemfiles::Trajectory::open(path, 'r')?;
-    let mut frame = chemfiles::Frame::new();
 
     let mut assigned_species = HashMap::new();
-    let mut get_species = |atom: chemfiles::AtomRef| {
+    let get_species = |atom: chemfiles::AtomRef| {
         let atomic_number = atom.atomic_number();
         if atomic_number == 0 {
             // use number assigned from the the atomic type, starting at 120
             // since that's larger than the number of elements in the periodic
             // table
             let new_species = 120 + assigned_species.len() as i32;
-            *assigned_species.entry(atom.atomic_type()).or_insert(new_species)
+            Ok(*assigned_species.entry(atom.atomic_type()).or_insert(new_species))
         } else {
-            atomic_number as i32
+            Ok(atomic_number as i32)
         }
     };
 
+    return read_with_species(path, get_species);
+}
+
+/// Read all structures in the file at the given `path` using
+/// [chemfiles](https://chemfiles.org/), and convert them to `SimpleSystem`s.
+///
+/// This function can read all [formats supported by
+/// chemfiles](https://chemfiles.org/chemfiles/latest/formats.html).
+#[cfg(not(feature = "chemfiles"))]
+pub fn read_from_file(_: impl AsRef<Path>) -> Result<Vec<SimpleSystem>, Error> {
+    Err(Error::Chemfiles(
+        "read_from_file is only available with the chemfiles feature enabled".into()
+    ))
+}
+
+/// Mapping from the atomic names and types used in files to the integer
+/// species used by rascaline, used by [`read_systems`].
+///
+/// Atoms are first looked up by name and then by type in the explicitly
+/// mapped names. If neither is found, the atomic number of the atom (as
+/// determined by chemfiles) is used when enabled, and then the catch-all
+/// species if one was given. This allows to either collapse different names
+/// for the same element (e.g. `"OW"` and `"O"`) into a single species, or to
+/// distinguish them:
+///
+/// ```
+/// # use rascaline::systems::SpeciesMapping;
+/// // use atomic numbers, except for "OW" which is a different species
+/// let mapping = SpeciesMapping::default().insert("OW", 108);
+///
+/// // only use the explicitly given names, mapping everything else to 0
+/// let mapping = SpeciesMapping::new()
+///     .atomic_numbers(false)
+///     .insert("OW", 8)
+///     .insert("HW", 1)
+///     .catch_all(0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeciesMapping {
+    /// explicit mapping from names to species
+    names: BTreeMap<String, i32>,
+    /// should we use the atomic number for atoms without an explicit mapping?
+    atomic_numbers: bool,
+    /// species to use for atoms not matching any of the above
+    catch_all: Option<i32>,
+}
+
+impl Default for SpeciesMapping {
+    /// Get the default mapping, using the atomic numbers as species
+    fn default() -> SpeciesMapping {
+        SpeciesMapping {
+            names: BTreeMap::new(),
+            atomic_numbers: true,
+            catch_all: None,
+        }
+    }
+}
+
+impl SpeciesMapping {
+    /// Create a new mapping, using the atomic numbers as species. This is the
+    /// same as `SpeciesMapping::default()`.
+    pub fn new() -> SpeciesMapping {
+        SpeciesMapping::default()
+    }
+
+    /// Map atoms with the given `name` (either atomic name or atomic type) to
+    /// the given `species`
+    pub fn insert(mut self, name: impl Into<String>, species: i32) -> SpeciesMapping {
+        self.names.insert(name.into(), species);
+        return self;
+    }
+
+    /// Set whether the atomic number should be used as the species of atoms
+    /// without an explicit mapping
+    pub fn atomic_numbers(mut self, atomic_numbers: bool) -> SpeciesMapping {
+        self.atomic_numbers = atomic_numbers;
+        return self;
+    }
+
+    /// Use the given `species` for all the atoms not matching any other rule
+    /// in this mapping
+    pub fn catch_all(mut self, species: i32) -> SpeciesMapping {
+        self.catch_all = Some(species);
+        return self;
+    }
+
+    /// Get the species for an atom with the given `name`, `atomic_type` and
+    /// `atomic_number` (0 if unknown)
+    #[cfg(feature = "chemfiles")]
+    fn species(&self, name: &str, atomic_type: &str, atomic_number: u64) -> Result<i32, Error> {
+        if let Some(&species) = self.names.get(name).or_else(|| self.names.get(atomic_type)) {
+            return Ok(species);
+        }
+
+        if self.atomic_numbers && atomic_number != 0 {
+            return Ok(atomic_number as i32);
+        }
+
+        return self.catch_all.ok_or_else(|| Error::InvalidParameter(format!(
+            "no species for atom with name '{}' and type '{}' in the species mapping",
+            name, atomic_type
+        )));
+    }
+}
+
+/// Read all the frames in the trajectory file at the given `path` using
+/// [chemfiles](https://chemfiles.org/), ready to be used in a calculation.
+///
+/// The species of the atoms are determined from their names with the given
+/// `mapping`, see [`SpeciesMapping`] for more information. Contrary to
+/// [`read_from_file`], this function returns an error for atoms without a
+/// species in the mapping. Frames without a unit cell are converted to
+/// systems with an infinite cell.
+#[cfg(feature = "chemfiles")]
+pub fn read_systems(path: impl AsRef<Path>, mapping: &SpeciesMapping) -> Result<Vec<Box<dyn System>>, Error> {
+    let get_species = |atom: chemfiles::AtomRef| {
+        mapping.species(&atom.name(), &atom.atomic_type(), atom.atomic_number())
+    };
+
+    let systems = read_with_species(path, get_species)?;
+    return Ok(systems.into_iter().map(|system| Box::new(system) as Box<dyn System>).collect());
+}
+
+/// Read all the frames in the trajectory file at the given `path` using
+/// [chemfiles](https://chemfiles.org/), ready to be used in a calculation.
+#[cfg(not(feature = "chemfiles"))]
+pub fn read_systems(_: impl AsRef<Path>, _: &SpeciesMapping) -> Result<Vec<Box<dyn System>>, Error> {
+    Err(Error::Chemfiles(
+        "read_systems is only available with the chemfiles feature enabled".into()
+    ))
+}
+
+/// Read all the frames in the file at `path` and convert them to
+/// `SimpleSystem`, using `get_species` to get the species of each atom.
+#[cfg(feature = "chemfiles")]
+#[allow(clippy::needless_range_loop)]
+fn read_with_species(
+    path: impl AsRef<Path>,
+    mut get_species: impl FnMut(chemfiles::AtomRef) -> Result<i32, Error>,
+) -> Result<Vec<SimpleSystem>, Error> {
+    use crate::Matrix3;
+    use crate::systems::UnitCell;
+
+    let mut systems = Vec::new();
+
+    let mut trajectory = chemfiles::Trajectory::open(path, 'r')?;
+    let mut frame = chemfiles::Frame::new();
+
     for _ in 0..trajectory.nsteps() {
         trajectory.read(&mut frame)?;
 
@@ -56,7 +200,7 @@ pub fn read_from_file(path: impl AsRef<Path>) -> Result<Vec<SimpleSystem>, Error
         let mut system = SimpleSystem::new(cell);
         for i in 0..frame.size() {
             let atom = frame.atom(i);
-            system.add_atom(get_species(atom), positions[i].into());
+            system.add_atom(get_species(atom)?, positions[i].into());
         }
 
         systems.push(system);
@@ -65,18 +209,6 @@ pub fn read_from_file(path: impl AsRef<Path>) -> Result<Vec<SimpleSystem>, Error
     return Ok(systems);
 }
 
-/// Read all structures in the file at the given `path` using
-/// [chemfiles](https://chemfiles.org/), and convert them to `SimpleSystem`s.
-///
-/// This function can read all [formats supported by
-/// chemfiles](https://chemfiles.org/chemfiles/latest/formats.html).
-#[cfg(not(feature = "chemfiles"))]
-pub fn read_from_file(_: impl AsRef<Path>) -> Result<Vec<SimpleSystem>, Error> {
-    Err(Error::Chemfiles(
-        "read_from_file is only available with the chemfiles feature enabled".into()
-    ))
-}
-
 #[cfg(all(test, feature = "chemfiles"))]
 mod tests {
     use std::path::PathBuf;
@@ -85,6 +217,27 @@ mod tests {
     use crate::{System, Vector3D};
     use super::*;
 
+    /// Temporary file with the given content, removed when dropped (even if
+    /// the test fails before the end)
+    struct TemporaryFile {
+        path: PathBuf,
+    }
+
+    impl TemporaryFile {
+        fn new(name: &str, content: &str) -> TemporaryFile {
+            let mut path = std::env::temp_dir();
+            path.push(format!("rascaline-{}-{}.xyz", name, std::process::id()));
+            std::fs::write(&path, content).expect("failed to write temporary file");
+            return TemporaryFile { path: path };
+        }
+    }
+
+    impl Drop for TemporaryFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
     #[test]
     fn read() -> Result<(), Box<dyn std::error::Error>> {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -119,4 +272,64 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn read_systems_xyz() -> Result<(), Box<dyn std::error::Error>> {
+        let file = TemporaryFile::new("read-systems", "\
+3
+Lattice=\"10.0 0.0 0.0 0.0 11.0 0.0 0.0 0.0 12.0\" Properties=species:S:1:pos:R:3
+O 0.0 0.0 0.0
+H 0.0 0.75 -0.58
+H 0.0 -0.75 -0.58
+2
+
+C 1.0 2.0 3.0
+H 1.0 3.2 3.0
+");
+
+        let systems = read_systems(&file.path, &SpeciesMapping::default())?;
+
+        assert_eq!(systems.len(), 2);
+        assert_eq!(systems[0].species()?, [8, 1, 1]);
+        assert_relative_eq!(systems[0].positions()?[1], Vector3D::new(0.0, 0.75, -0.58));
+        assert_eq!(systems[0].cell()?.matrix()[1], [0.0, 11.0, 0.0]);
+
+        assert_eq!(systems[1].species()?, [6, 1]);
+        assert_relative_eq!(systems[1].positions()?[0], Vector3D::new(1.0, 2.0, 3.0));
+        assert_eq!(systems[1].cell()?.shape(), crate::systems::CellShape::Infinite);
+
+        Ok(())
+    }
+
+    #[test]
+    fn species_mapping() -> Result<(), Box<dyn std::error::Error>> {
+        let file = TemporaryFile::new("species-mapping", "4\n\nO 0.0 0.0 0.0\nOW 1.0 0.0 0.0\nH 2.0 0.0 0.0\nZz 3.0 0.0 0.0\n");
+
+        let read = |mapping: &SpeciesMapping| {
+            read_systems(&file.path, mapping).map(|systems| systems[0].species().unwrap().to_vec())
+        };
+
+        // unknown names are an error by default
+        let error = read(&SpeciesMapping::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: no species for atom with name 'Zz' and type 'Zz' in the species mapping"
+        );
+
+        // collapse O and OW into the same species
+        let mapping = SpeciesMapping::default().insert("OW", 8).insert("Zz", 0);
+        assert_eq!(read(&mapping)?, [8, 8, 1, 0]);
+
+        // distinguish O and OW
+        let mapping = SpeciesMapping::default().insert("OW", 108).catch_all(-1);
+        assert_eq!(read(&mapping)?, [8, 108, 1, -1]);
+
+        // only use explicit names
+        let mapping = SpeciesMapping::new().atomic_numbers(false).insert("H", 1).catch_all(42);
+        assert_eq!(read(&mapping)?, [42, 42, 1, 42]);
+
+        assert_eq!(SpeciesMapping::new(), SpeciesMapping::default());
+
+        Ok(())
+    }
 }
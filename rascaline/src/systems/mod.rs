@@ -6,11 +6,17 @@ pub use self::cell::{UnitCell, CellShape};
 mod neighbors;
 pub use self::neighbors::NeighborsList;
 
+mod voronoi;
+pub use self::voronoi::VoronoiNeighbors;
+
 mod simple_system;
 pub use self::simple_system::SimpleSystem;
 
+mod soa_system;
+pub use self::soa_system::SoaSystem;
+
 mod chemfiles;
-pub use self::chemfiles::read_from_file;
+pub use self::chemfiles::{read_from_file, read_systems, SpeciesMapping};
 
 #[cfg(test)]
 pub(crate) mod test_utils;
@@ -30,6 +36,11 @@ pub struct Pair {
     /// vector from the first atom to the second atom, wrapped inside the unit
     /// cell as required
     pub vector: Vector3D,
+    /// number of shifts along each of the unit cell vectors for this pair. The
+    /// `vector` can be reconstructed as `positions[second] - positions[first] +
+    /// cell_shift[0] * a + cell_shift[1] * b + cell_shift[2] * c`, where `a`,
+    /// `b` and `c` are the unit cell vectors.
+    pub cell_shift: [i32; 3],
 }
 
 /// A `System` deals with the storage of atoms and related information, as well
@@ -52,18 +63,81 @@ pub trait System: Send + Sync {
     /// of all atoms in the system.
     fn positions(&self) -> Result<&[Vector3D], Error>;
 
+    /// Get the charges of all atoms in this system, if they are available.
+    /// The returned slice should have the same size as the system, and the
+    /// charges should be expressed in units of the elementary charge.
+    ///
+    /// The default implementation returns `None`.
+    fn charges(&self) -> Option<&[f64]> {
+        return None;
+    }
+
     /// Compute the neighbor list according to the given cutoff, and store it
     /// for later access with `pairs` or `pairs_around`.
     fn compute_neighbors(&mut self, cutoff: f64) -> Result<(), Error>;
 
+    /// Use the given `pairs` as the neighbor list for the given `cutoff`,
+    /// instead of computing it. All calculators call `compute_neighbors`
+    /// before using the pairs, and later calls to `compute_neighbors` with
+    /// exactly the same `cutoff` must use these `pairs` instead of
+    /// re-computing the neighbor list.
+    ///
+    /// This allows to re-use a neighbor list computed by an external code,
+    /// for example when running molecular dynamics with a Verlet list that is
+    /// only rebuilt every few steps. The `pairs` must contain the distances
+    /// and vectors for the current positions, and can either be a "half"
+    /// neighbor list following the same rules as [`System::pairs`], or a
+    /// "full" neighbor list following the same rules as
+    /// [`System::pairs_full`]. Since all calculators use half neighbor lists,
+    /// full neighbor lists are converted automatically. Implementations should
+    /// check the pairs, and return an error if they do not follow these rules.
+    ///
+    /// **Warning**: the system can not check that the pairs are still
+    /// correct. If the list was built with a skin distance and is not
+    /// rebuilt after the atoms moved by more than half the skin, pairs
+    /// entering the cutoff sphere will be missing and the results of the
+    /// calculation will be wrong. Calculations using
+    /// [`CalculationOptions::use_native_system`](crate::CalculationOptions::use_native_system)
+    /// or cell inference copy the system, and re-compute the neighbor list.
+    ///
+    /// The default implementation returns an error.
+    fn set_neighbors(&mut self, cutoff: f64, pairs: Vec<Pair>) -> Result<(), Error> {
+        let _ = (cutoff, pairs);
+        return Err(Error::InvalidParameter(
+            "this system does not support setting the neighbor list".into()
+        ));
+    }
+
     /// Get the list of pairs in this system. This list of pair should only
     /// contain each pair once (and not twice as `i-j` and `j-i`), should not
     /// contain self pairs (`i-i`); and should only contains pairs where the
     /// distance between atoms is actually bellow the cutoff passed in the last
     /// call to `compute_neighbors`. This function is only valid to call after a
     /// call to `compute_neighbors`.
+    ///
+    /// All the calculators in rascaline use this "half" neighbor list, and
+    /// create the `j-i` pair from the `i-j` pair when needed. Returning both
+    /// directions here would count each pair twice in the features. Use
+    /// [`System::pairs_full`] to get both directions.
     fn pairs(&self) -> Result<&[Pair], Error>;
 
+    /// Get the list of pairs in this system as a "full" neighbor list,
+    /// containing each pair twice, once as `i-j` and once as `j-i` (with the
+    /// opposite vector). Pairs between an atom and one of its periodic images
+    /// are already present in both directions in the half list, and are only
+    /// included once. The pairs are sorted by `first` and then by `second`
+    /// atom index.
+    ///
+    /// The default implementation builds the full list from [`System::pairs`].
+    /// None of the calculators in rascaline call this function; the
+    /// `NeighborList` calculator with `full_neighbor_list = true` builds the
+    /// same full list from [`System::pairs`] while computing the features.
+    fn pairs_full(&self) -> Result<Vec<Pair>, Error> {
+        let mut pairs = self::neighbors::half_to_full_pairs(self.pairs()?);
+        pairs.sort_by_key(|pair| (pair.first, pair.second));
+        return Ok(pairs);
+    }
+
     /// Get the list of pairs in this system which include the atom at index
     /// `center`. The same restrictions on the list of pairs as `System::pairs`
     /// applies, with the additional condition that the pair `i-j` should be
@@ -71,3 +145,49 @@ pub trait System: Send + Sync {
     /// `pairs_containing(j)`.
     fn pairs_containing(&self, center: usize) -> Result<&[Pair], Error>;
 }
+
+/// Compute the list of pairs in `system` with the given spherical `cutoff`.
+///
+/// This is the same list of pairs that calculators use (see [`System::pairs`]
+/// for the exact content), and is mainly intended to inspect and debug the
+/// neighbor list of a system. The neighbor list stored in the `system` is
+/// updated to use this `cutoff`.
+pub fn compute_pairs(system: &mut dyn System, cutoff: f64) -> Result<Vec<Pair>, Error> {
+    if !(cutoff > 0.0 && cutoff.is_finite()) {
+        return Err(Error::InvalidParameter(format!(
+            "cutoff must be a positive finite number, got {}", cutoff
+        )));
+    }
+
+    system.compute_neighbors(cutoff)?;
+    return Ok(system.pairs()?.to_vec());
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+    use super::test_utils::test_system;
+
+    #[test]
+    fn compute_pairs() {
+        let mut system = test_system("water");
+
+        let pairs = super::compute_pairs(&mut system, 1.0).unwrap();
+        assert_eq!(pairs.len(), 2);
+        for pair in &pairs {
+            assert_eq!(pair.first, 0);
+            assert_relative_eq!(pair.distance, 0.957897074324794, max_relative=1e-12);
+            assert_relative_eq!(pair.vector.norm(), pair.distance, max_relative=1e-12);
+        }
+
+        // the neighbor list of the system is updated
+        let pairs = super::compute_pairs(&mut system, 2.0).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(system.pairs().unwrap().len(), 3);
+
+        let error = super::compute_pairs(&mut system, -1.0).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: cutoff must be a positive finite number, got -1");
+    }
+}
@@ -1,13 +1,21 @@
+use std::collections::HashSet;
+
 use log::warn;
 use ndarray::Array3;
+use rayon::prelude::*;
 
-use crate::{Matrix3, Vector3D};
+use crate::{Error, Matrix3, Vector3D};
 use super::{UnitCell, Pair};
 
 /// Maximal number of cells, we need to use this to prevent having too many
 /// cells with a small unit cell and a large cutoff
 const MAX_NUMBER_OF_CELLS: f64 = 1e5;
 
+/// Minimal number of atoms for which the neighbor list is constructed in
+/// parallel. Below this, the overhead of spawning tasks is larger than the
+/// actual work.
+const PARALLEL_NEIGHBORS_MIN_ATOMS: usize = 2048;
+
 /// A cell shift represents the displacement along cell axis between the actual
 /// position of an atom and a periodic image of this atom.
 ///
@@ -47,6 +55,11 @@ impl std::ops::Index<usize> for CellShift {
 }
 
 impl CellShift {
+    /// Get the integer shifts along each cell vector as an array
+    pub fn to_array(self) -> [i32; 3] {
+        [self[0] as i32, self[1] as i32, self[2] as i32]
+    }
+
     /// Compute the shift vector in cartesian coordinates, using the given cell
     /// matrix (stored in row major order).
     pub fn cartesian(&self, cell: &Matrix3) -> Vector3D {
@@ -130,11 +143,16 @@ impl CellList {
         }
 
         // number of cells to search in each direction to make sure all possible
-        // pairs below the cutoff are accounted for.
+        // pairs below the cutoff are accounted for. This uses the distance
+        // between faces of the cell (i.e. the inverse of the reciprocal lattice
+        // spacing), which is smaller than the cell vectors length for
+        // triclinic cells. When the cutoff is larger than the distance between
+        // faces, we need to look at multiple periodic images, and the number
+        // of cells to search must be rounded up to include all of them.
         let mut n_search = [
-            f64::trunc(cutoff * n_cells[0] / distances_between_faces[0]) as isize,
-            f64::trunc(cutoff * n_cells[1] / distances_between_faces[1]) as isize,
-            f64::trunc(cutoff * n_cells[2] / distances_between_faces[2]) as isize,
+            f64::ceil(cutoff * n_cells[0] / distances_between_faces[0]) as isize,
+            f64::ceil(cutoff * n_cells[1] / distances_between_faces[1]) as isize,
+            f64::ceil(cutoff * n_cells[2] / distances_between_faces[2]) as isize,
         ];
 
         let n_cells = [
@@ -165,6 +183,34 @@ impl CellList {
     /// Add a single atom to the cell list at the given `position`. The atom is
     /// uniquely identified by its `index`.
     pub fn add_atom(&mut self, index: usize, position: Vector3D) {
+        let (shift, cell_index) = self.locate(position);
+        self.cells[cell_index].push(AtomData {
+            index: index,
+            shift: shift,
+        });
+    }
+
+    /// Add all atoms at the given `positions` to the cell list, using the
+    /// position in the slice as index. The cell containing each atom is
+    /// computed in parallel, and the atoms are then added in order, which
+    /// gives the same cell list as calling [`CellList::add_atom`] for each
+    /// atom in turn.
+    pub fn par_add_atoms(&mut self, positions: &[Vector3D]) {
+        let locations = positions.par_iter()
+            .map(|&position| self.locate(position))
+            .collect::<Vec<_>>();
+
+        for (index, (shift, cell_index)) in locations.into_iter().enumerate() {
+            self.cells[cell_index].push(AtomData {
+                index: index,
+                shift: shift,
+            });
+        }
+    }
+
+    /// Find the cell containing an atom at the given `position`, and the
+    /// shift from this position to the image of the atom inside the unit cell
+    fn locate(&self, position: Vector3D) -> (CellShift, [usize; 3]) {
         let fractional = if self.unit_cell.is_infinite() {
             position
         } else {
@@ -194,10 +240,7 @@ impl CellList {
             divmod_vec(cell_index, n_cells)
         };
 
-        self.cells[cell_index].push(AtomData {
-            index: index,
-            shift: CellShift(shift),
-        });
+        return (CellShift(shift), cell_index);
     }
 
     /// Get the list of candidate pair. Some pairs might be separated by more
@@ -216,7 +259,37 @@ impl CellList {
     /// and another pair between atoms 33-64 at 4.8 Å.
     pub fn pairs(&self) -> Vec<CellPair> {
         let mut pairs = Vec::new();
+        // for each cell in the cell list
+        for (cell_index, current_cell) in self.cells.indexed_iter() {
+            self.cell_pairs(cell_index, current_cell, &mut pairs);
+        }
+        return pairs;
+    }
+
+    /// Parallel version of [`CellList::pairs`]. The pairs for each cell are
+    /// computed in parallel and then merged, producing exactly the same pairs
+    /// in the same order as the serial version.
+    pub fn par_pairs(&self) -> Vec<CellPair> {
+        let cells = self.cells.indexed_iter().collect::<Vec<_>>();
+        let pairs_by_cell = cells.par_iter()
+            .map(|&(cell_index, current_cell)| {
+                let mut pairs = Vec::new();
+                self.cell_pairs(cell_index, current_cell, &mut pairs);
+                pairs
+            })
+            .collect::<Vec<_>>();
+
+        return pairs_by_cell.concat();
+    }
 
+    /// Add all candidate pairs involving the atoms in `current_cell` (which
+    /// is at `cell_index` in the cell list) as the first atom to `pairs`
+    fn cell_pairs(
+        &self,
+        cell_index: (usize, usize, usize),
+        current_cell: &[AtomData],
+        pairs: &mut Vec<CellPair>,
+    ) {
         let n_cells = self.cells.shape();
         let n_cells = [n_cells[0], n_cells[1], n_cells[2]];
 
@@ -224,59 +297,54 @@ impl CellList {
         let search_y = -self.n_search[1]..=self.n_search[1];
         let search_z = -self.n_search[2]..=self.n_search[2];
 
-        // for each cell in the cell list
-        for ((cell_i_x, cell_i_y, cell_i_z), current_cell) in self.cells.indexed_iter() {
-            // look through each neighboring cell
-            for delta_x in search_x.clone() {
-                for delta_y in search_y.clone() {
-                    for delta_z in search_z.clone() {
-                        let cell_i = [
-                            cell_i_x as isize + delta_x,
-                            cell_i_y as isize + delta_y,
-                            cell_i_z as isize + delta_z,
-                        ];
-
-                        // shift vector from one cell to the other and index of
-                        // the neighboring cell
-                        let (cell_shift, neighbor_cell_i) = divmod_vec(cell_i, n_cells);
-
-                        for atom_i in current_cell {
-                            for atom_j in &self.cells[neighbor_cell_i] {
-                                // create a half neighbor list
-                                if atom_i.index > atom_j.index {
-                                    continue;
-                                }
-
-                                let shift = CellShift(cell_shift) + atom_i.shift - atom_j.shift;
-                                let shift_is_zero = shift[0] == 0 && shift[1] == 0 && shift[2] == 0;
-
-                                if atom_i.index == atom_j.index && shift_is_zero {
-                                    // only create pair with the same atom twice
-                                    // if the pair spans more than one unit cell
-                                    continue;
-                                }
-
-                                if self.unit_cell.is_infinite() && !shift_is_zero {
-                                    // do not create pairs crossing the periodic
-                                    // boundaries in an infinite cell
-                                    continue;
-                                }
-
-                                pairs.push(CellPair {
-                                    first: atom_i.index,
-                                    second: atom_j.index,
-                                    shift: shift,
-                                });
+        let (cell_i_x, cell_i_y, cell_i_z) = cell_index;
+        // look through each neighboring cell
+        for delta_x in search_x {
+            for delta_y in search_y.clone() {
+                for delta_z in search_z.clone() {
+                    let cell_i = [
+                        cell_i_x as isize + delta_x,
+                        cell_i_y as isize + delta_y,
+                        cell_i_z as isize + delta_z,
+                    ];
+
+                    // shift vector from one cell to the other and index of
+                    // the neighboring cell
+                    let (cell_shift, neighbor_cell_i) = divmod_vec(cell_i, n_cells);
+
+                    for atom_i in current_cell {
+                        for atom_j in &self.cells[neighbor_cell_i] {
+                            // create a half neighbor list
+                            if atom_i.index > atom_j.index {
+                                continue;
                             }
-                        } // loop over atoms in current neighbor cells
 
-                    }
-                }
-            } // loop over neighboring cells
+                            let shift = CellShift(cell_shift) + atom_i.shift - atom_j.shift;
+                            let shift_is_zero = shift[0] == 0 && shift[1] == 0 && shift[2] == 0;
 
-        }
+                            if atom_i.index == atom_j.index && shift_is_zero {
+                                // only create pair with the same atom twice
+                                // if the pair spans more than one unit cell
+                                continue;
+                            }
 
-        return pairs;
+                            if self.unit_cell.is_infinite() && !shift_is_zero {
+                                // do not create pairs crossing the periodic
+                                // boundaries in an infinite cell
+                                continue;
+                            }
+
+                            pairs.push(CellPair {
+                                first: atom_i.index,
+                                second: atom_j.index,
+                                shift: shift,
+                            });
+                        }
+                    } // loop over atoms in current neighbor cells
+
+                }
+            }
+        } // loop over neighboring cells
     }
 }
 
@@ -303,7 +371,110 @@ fn divmod_vec(a: [isize; 3], b: [usize; 3]) -> ([isize; 3], [usize; 3]) {
     return ([qx, qy, qz], [rx, ry, rz]);
 }
 
+/// Create the neighbor list corresponding to `pairs` given to
+/// `System::set_neighbors`, for a system with `n_atoms` atoms and the given
+/// `cutoff`.
+///
+/// The `pairs` can either be a half neighbor list or a full neighbor list,
+/// which is detected from the presence of pairs where the first atom has a
+/// larger index than the second. Full neighbor lists are converted to the half
+/// neighbor lists used by all calculators.
+pub(super) fn neighbors_from_pairs(n_atoms: usize, cutoff: f64, pairs: Vec<Pair>) -> Result<NeighborsList, Error> {
+    let n_reversed = pairs.iter().filter(|pair| pair.first > pair.second).count();
+    if n_reversed == 0 {
+        check_pairs(n_atoms, cutoff, &pairs)?;
+        return Ok(NeighborsList::from_pairs(n_atoms, cutoff, pairs, false));
+    }
+
+    let n_direct = pairs.iter().filter(|pair| pair.first < pair.second).count();
+    if n_direct != n_reversed {
+        return Err(Error::InvalidParameter(format!(
+            "invalid full neighbor list: got {} pairs where the first atom \
+            has the smallest index, and {} pairs where it has the largest \
+            index; both directions must be present for all pairs",
+            n_direct, n_reversed
+        )));
+    }
+
+    let (half, reversed): (Vec<_>, Vec<_>) = pairs.into_iter().partition(|pair| pair.first <= pair.second);
+    check_pairs(n_atoms, cutoff, &half)?;
+
+    let all_half = half.iter()
+        .map(|pair| (pair.first, pair.second, pair.cell_shift))
+        .collect::<HashSet<_>>();
+
+    for pair in &reversed {
+        let shift = pair.cell_shift;
+        if !all_half.contains(&(pair.second, pair.first, [-shift[0], -shift[1], -shift[2]])) {
+            return Err(Error::InvalidParameter(format!(
+                "invalid full neighbor list: missing the pair between atoms \
+                {} and {} corresponding to the pair between atoms {} and {}",
+                pair.second, pair.first, pair.first, pair.second
+            )));
+        }
+    }
+
+    return Ok(NeighborsList::from_pairs(n_atoms, cutoff, half, false));
+}
+
+/// Check that `pairs` given to `System::set_neighbors` are valid for a system
+/// with `n_atoms` atoms and the given `cutoff`
+fn check_pairs(n_atoms: usize, cutoff: f64, pairs: &[Pair]) -> Result<(), Error> {
+    if !(cutoff > 0.0 && cutoff.is_finite()) {
+        return Err(Error::InvalidParameter(format!(
+            "cutoff must be a positive finite number, got {}", cutoff
+        )));
+    }
+
+    for pair in pairs {
+        if pair.first >= n_atoms || pair.second >= n_atoms {
+            return Err(Error::InvalidParameter(format!(
+                "invalid pair between atoms {} and {} in a system with {} atoms",
+                pair.first, pair.second, n_atoms
+            )));
+        }
+
+        if pair.first == pair.second && pair.cell_shift == [0, 0, 0] {
+            return Err(Error::InvalidParameter(format!(
+                "invalid pair between atom {} and itself: self pairs are only \
+                allowed between an atom and one of its periodic images",
+                pair.first
+            )));
+        }
+
+        if !pair.distance.is_finite() || pair.distance > cutoff {
+            return Err(Error::InvalidParameter(format!(
+                "invalid distance for the pair between atoms {} and {}: \
+                expected a finite distance below the cutoff ({}), got {}",
+                pair.first, pair.second, cutoff, pair.distance
+            )));
+        }
+
+        if f64::abs(pair.distance - pair.vector.norm()) > 1e-6 {
+            return Err(Error::InvalidParameter(format!(
+                "invalid pair between atoms {} and {}: the distance ({}) does \
+                not match the norm of the vector ({})",
+                pair.first, pair.second, pair.distance, pair.vector.norm()
+            )));
+        }
+    }
+
+    return Ok(());
+}
+
 /// A neighbor list implementation usable with any system
+///
+/// The neighbor list can either be a half list, containing each pair `i-j`
+/// only once; or a full list, containing both `i-j` and `j-i`. Pairs between
+/// an atom and one of its periodic images are included once for each image in
+/// both cases. In a half list, `pairs_by_center[i]` contains all the pairs
+/// where `i` is either the first or the second atom, while in a full list it
+/// only contains the pairs where `i` is the first atom.
+///
+/// All calculators expect half lists. Full lists given to
+/// [`System::set_neighbors`](super::System::set_neighbors) are converted
+/// automatically, and [`NeighborsList::to_half`] and [`NeighborsList::to_full`]
+/// can be used to convert between the two conventions.
 #[derive(Clone, Debug)]
 pub struct NeighborsList {
     /// the cutoff used to create this neighbor list
@@ -312,15 +483,38 @@ pub struct NeighborsList {
     pub pairs: Vec<Pair>,
     /// all pairs in the system, classified by associated center
     pub pairs_by_center: Vec<Vec<Pair>>,
+    /// is this a full neighbor list, or a half neighbor list?
+    pub full: bool,
 }
 
 impl NeighborsList {
-    #[time_graph::instrument(name = "NeighborsList")]
+    /// Compute the neighbor list for atoms at the given `positions` in the
+    /// given `unit_cell`, including all pairs below `cutoff`.
+    ///
+    /// The `cutoff` can be larger than half the distance between the faces of
+    /// the cell: the minimum image convention is not used, and all periodic
+    /// images within the cutoff are included, possibly giving multiple pairs
+    /// between the same two atoms (or between an atom and its own images).
+    ///
+    /// For large systems, the neighbor list is computed in parallel. This
+    /// gives exactly the same list as the serial construction.
+    #[time_graph::instrument(name = "NeighborsList::new")]
     pub fn new(positions: &[Vector3D], unit_cell: UnitCell, cutoff: f64) -> NeighborsList {
+        let parallel = positions.len() >= PARALLEL_NEIGHBORS_MIN_ATOMS;
+        return NeighborsList::with_parallel(positions, unit_cell, cutoff, parallel);
+    }
+
+    /// Same as [`NeighborsList::new`], explicitly choosing to construct the
+    /// neighbor list in `parallel` (using rayon) or serially.
+    pub fn with_parallel(positions: &[Vector3D], unit_cell: UnitCell, cutoff: f64, parallel: bool) -> NeighborsList {
         let mut cell_list = CellList::new(unit_cell, cutoff);
 
-        for (index, &position) in positions.iter().enumerate() {
-            cell_list.add_atom(index, position);
+        if parallel {
+            cell_list.par_add_atoms(positions);
+        } else {
+            for (index, &position) in positions.iter().enumerate() {
+                cell_list.add_atom(index, position);
+            }
         }
 
         let cell_matrix = unit_cell.matrix();
@@ -328,10 +522,7 @@ impl NeighborsList {
 
         // the cell list creates too many pairs, we only need to keep the one where
         // the distance is actually below the cutoff
-        let mut pairs = Vec::new();
-        let mut pairs_by_center = vec![Vec::new(); positions.len()];
-
-        for pair in cell_list.pairs() {
+        let filter_pair = |pair: &CellPair| {
             let mut vector = positions[pair.second] - positions[pair.first];
             vector += pair.shift.cartesian(&cell_matrix);
 
@@ -344,37 +535,118 @@ impl NeighborsList {
                     );
                 }
 
-                let pair = Pair {
+                return Some(Pair {
                     first: pair.first,
                     second: pair.second,
                     distance: distance2.sqrt(),
                     vector: vector,
-                };
-
-                pairs.push(pair);
-                pairs_by_center[pair.first].push(pair);
-                pairs_by_center[pair.second].push(pair);
+                    cell_shift: pair.shift.to_array(),
+                });
             }
+
+            return None;
+        };
+
+        let pairs = if parallel {
+            // `collect` keeps the pairs in the same order as the serial version
+            cell_list.par_pairs().par_iter().filter_map(filter_pair).collect()
+        } else {
+            cell_list.pairs().iter().filter_map(filter_pair).collect()
+        };
+
+        return NeighborsList::from_pairs(positions.len(), cutoff, pairs, false);
+    }
+
+    /// Create a neighbor list for a system containing `n_atoms` atoms from an
+    /// already computed list of `pairs`, for example coming from an external
+    /// code. If `full` is `true`, the `pairs` should contain both `i-j` and
+    /// `j-i`, and the list is converted to a half neighbor list.
+    pub fn from_pairs(n_atoms: usize, cutoff: f64, pairs: Vec<Pair>, full: bool) -> NeighborsList {
+        let list = NeighborsList::with_pairs(n_atoms, cutoff, pairs, full);
+        if full {
+            return list.to_half();
+        }
+        return list;
+    }
+
+    /// Get the full version of this neighbor list, containing both `i-j` and
+    /// `j-i` for all pairs. If this list is already a full list, this returns
+    /// a copy of it.
+    pub fn to_full(&self) -> NeighborsList {
+        if self.full {
+            return self.clone();
+        }
+
+        let pairs = half_to_full_pairs(&self.pairs);
+        return NeighborsList::with_pairs(self.pairs_by_center.len(), self.cutoff, pairs, true);
+    }
+
+    /// Get the half version of this neighbor list, containing each pair `i-j`
+    /// only once. If this list is already a half list, this returns a copy of
+    /// it.
+    pub fn to_half(&self) -> NeighborsList {
+        if !self.full {
+            return self.clone();
         }
 
+        let pairs = self.pairs.iter()
+            .filter(|pair| pair.first <= pair.second)
+            .copied()
+            .collect();
+
+        return NeighborsList::with_pairs(self.pairs_by_center.len(), self.cutoff, pairs, false);
+    }
+
+    /// Create a neighbor list with the given `pairs`, sorting them and
+    /// classifying them by center according to the `full` convention
+    fn with_pairs(n_atoms: usize, cutoff: f64, mut pairs: Vec<Pair>, full: bool) -> NeighborsList {
         // sort the pairs to make sure the final output of rascaline is ordered
         // naturally
         pairs.sort_unstable_by_key(|pair| (pair.first, pair.second));
-        for pairs in &mut pairs_by_center {
-            pairs.sort_unstable_by_key(|pair| (pair.first, pair.second));
+
+        // since `pairs` is sorted, the pairs for each center are also sorted
+        let mut pairs_by_center = vec![Vec::new(); n_atoms];
+        for pair in &pairs {
+            pairs_by_center[pair.first].push(*pair);
+            if !full {
+                pairs_by_center[pair.second].push(*pair);
+            }
         }
 
         return NeighborsList {
             cutoff: cutoff,
             pairs: pairs,
             pairs_by_center: pairs_by_center,
+            full: full,
         };
     }
 }
 
+/// Convert a list of `pairs` following the half neighbor list convention to
+/// the full neighbor list convention, adding the `j-i` pair for each `i-j`
+/// pair. The pairs are not sorted.
+pub(crate) fn half_to_full_pairs(pairs: &[Pair]) -> Vec<Pair> {
+    let mut full = Vec::with_capacity(2 * pairs.len());
+    for pair in pairs {
+        full.push(*pair);
+        if pair.first != pair.second {
+            // pairs between an atom and its own images are already
+            // included in both directions
+            full.push(Pair {
+                first: pair.second,
+                second: pair.first,
+                distance: pair.distance,
+                vector: -pair.vector,
+                cell_shift: [-pair.cell_shift[0], -pair.cell_shift[1], -pair.cell_shift[2]],
+            });
+        }
+    }
+    return full;
+}
+
 #[cfg(test)]
 mod tests {
-    use approx::assert_ulps_eq;
+    use approx::{assert_relative_eq, assert_ulps_eq};
 
     use crate::Matrix3;
 
@@ -413,6 +685,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn infinite_cell_water() {
+        let positions = [
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.0, 0.75545, -0.58895),
+            Vector3D::new(0.0, -0.75545, -0.58895),
+        ];
+
+        // a cubic cell much larger than the cutoff does not contain any pair
+        // with periodic images, and should be equivalent to an infinite cell
+        for cutoff in [1.0, 2.5, 8.0] {
+            let infinite = NeighborsList::new(&positions, UnitCell::infinite(), cutoff);
+            let large_box = NeighborsList::new(&positions, UnitCell::cubic(100.0), cutoff);
+
+            assert_eq!(infinite.pairs.len(), large_box.pairs.len());
+            for (pair, reference) in infinite.pairs.iter().zip(&large_box.pairs) {
+                assert_eq!(pair.first, reference.first);
+                assert_eq!(pair.second, reference.second);
+                assert_eq!(pair.cell_shift, [0, 0, 0]);
+                assert_ulps_eq!(pair.distance, reference.distance);
+            }
+        }
+    }
+
     #[test]
     fn fcc_cell() {
         let cell = UnitCell::from(Matrix3::from([
@@ -447,6 +743,173 @@ mod tests {
         }
     }
 
+    /// Compute all pairs below the cutoff by looping over enough periodic
+    /// images, returning sorted `(first, second, distance)`
+    fn brute_force_pairs(positions: &[Vector3D], cell: UnitCell, cutoff: f64) -> Vec<(usize, usize, f64)> {
+        let matrix = cell.matrix();
+        let faces = cell.distances_between_faces();
+        let n_images = [
+            f64::ceil(cutoff / faces[0]) as isize + 1,
+            f64::ceil(cutoff / faces[1]) as isize + 1,
+            f64::ceil(cutoff / faces[2]) as isize + 1,
+        ];
+
+        let mut pairs = Vec::new();
+        for first in 0..positions.len() {
+            for second in first..positions.len() {
+                for a in -n_images[0]..=n_images[0] {
+                    for b in -n_images[1]..=n_images[1] {
+                        for c in -n_images[2]..=n_images[2] {
+                            if first == second && a == 0 && b == 0 && c == 0 {
+                                continue;
+                            }
+
+                            let shift = CellShift([a, b, c]).cartesian(&matrix);
+                            let distance = (positions[second] - positions[first] + shift).norm();
+                            if distance < cutoff {
+                                pairs.push((first, second, distance));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        pairs.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.partial_cmp(&b.2).expect("got NaN distance")));
+        return pairs;
+    }
+
+    fn check_against_brute_force(positions: &[Vector3D], cell: UnitCell, cutoff: f64) {
+        let neighbors = NeighborsList::new(positions, cell, cutoff);
+        let mut pairs = neighbors.pairs.iter()
+            .map(|pair| (pair.first, pair.second, pair.distance))
+            .collect::<Vec<_>>();
+        pairs.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)).then(a.2.partial_cmp(&b.2).expect("got NaN distance")));
+
+        let expected = brute_force_pairs(positions, cell, cutoff);
+        assert_eq!(pairs.len(), expected.len(), "wrong number of pairs with cutoff={}", cutoff);
+        for (pair, expected) in pairs.iter().zip(&expected) {
+            assert_eq!(pair.0, expected.0);
+            assert_eq!(pair.1, expected.1);
+            assert_relative_eq!(pair.2, expected.2, max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn cutoff_larger_than_cell() {
+        // simple cubic lattice with a single atom, and a cutoff much larger
+        // than half the cell size
+        let positions = [Vector3D::new(0.3, 0.2, 0.1)];
+        let neighbors = NeighborsList::new(&positions, UnitCell::cubic(1.0), 2.5);
+
+        // there are 80 lattice points (excluding the origin) within 2.5 of the
+        // origin, and pairs with periodic images are included for each image
+        assert_eq!(neighbors.pairs.len(), 80);
+        for pair in &neighbors.pairs {
+            assert_eq!(pair.first, 0);
+            assert_eq!(pair.second, 0);
+            assert_ne!(pair.cell_shift, [0, 0, 0]);
+            assert!(pair.distance < 2.5);
+        }
+
+        // same thing with multiple atoms in a small triclinic cell
+        let cell = UnitCell::triclinic(1.5, 1.7, 1.6, 80.0, 95.0, 110.0);
+        let positions = [
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.5, 0.6, 0.2),
+            Vector3D::new(1.1, 0.1, 0.9),
+        ];
+        check_against_brute_force(&positions, cell, 3.4);
+    }
+
+    #[test]
+    fn cell_shifts() {
+        let cell = UnitCell::from(Matrix3::new([
+            [4.0, 0.0, 0.0],
+            [1.8, 3.6, 0.0],
+            [-1.2, 0.9, 3.3],
+        ]));
+        let matrix = cell.matrix();
+
+        // all positions are wrapped inside the unit cell
+        let positions = [
+            Vector3D::new(0.1, 0.2, 0.3),
+            Vector3D::new(3.5, 3.1, 0.4),
+            Vector3D::new(1.2, 1.7, 2.9),
+        ];
+
+        let neighbors = NeighborsList::new(&positions, cell, 4.5);
+        assert!(neighbors.pairs.iter().any(|pair| pair.cell_shift != [0, 0, 0]));
+
+        let full = neighbors.to_full();
+        for pair in neighbors.pairs.iter().chain(&full.pairs) {
+            let shift = CellShift([
+                pair.cell_shift[0] as isize,
+                pair.cell_shift[1] as isize,
+                pair.cell_shift[2] as isize,
+            ]);
+            let vector = positions[pair.second] - positions[pair.first] + shift.cartesian(&matrix);
+
+            assert_relative_eq!(vector, pair.vector, max_relative=1e-12);
+            assert_relative_eq!(vector.norm(), pair.distance, max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn triclinic_water_box() {
+        let cell = UnitCell::from(Matrix3::new([
+            [4.0, 0.0, 0.0],
+            [1.8, 3.6, 0.0],
+            [-1.2, 0.9, 3.3],
+        ]));
+
+        let water = [
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.0, 0.75545, -0.58895),
+            Vector3D::new(0.0, -0.75545, -0.58895),
+        ];
+        let centers = [
+            Vector3D::new(0.5, 0.6, 1.2),
+            Vector3D::new(2.4, 1.9, 0.7),
+            Vector3D::new(1.1, 3.0, 2.6),
+            Vector3D::new(3.3, 0.8, 2.2),
+        ];
+
+        let mut positions = Vec::new();
+        for &center in &centers {
+            for &atom in &water {
+                positions.push(center + atom);
+            }
+        }
+
+        // the last two cutoffs are larger than the distance between some of
+        // the cell faces (3.27, 3.47 and 3.3 A)
+        for cutoff in [3.0, 4.5, 6.0] {
+            check_against_brute_force(&positions, cell, cutoff);
+        }
+    }
+
+    #[test]
+    fn skewed_cell() {
+        // very skewed cell, with distances between faces of 1.46, 1.49 and
+        // 2.9 A, requiring multiple periodic images
+        let cell = UnitCell::from(Matrix3::new([
+            [3.0, 0.0, 0.0],
+            [2.7, 1.5, 0.0],
+            [0.5, 0.4, 2.9],
+        ]));
+        let positions = [
+            Vector3D::new(0.1, 0.2, 0.3),
+            Vector3D::new(1.2, 0.9, 0.4),
+            Vector3D::new(2.9, 1.3, 2.7),
+            Vector3D::new(0.6, 0.1, 2.5),
+        ];
+
+        for cutoff in [2.5, 3.0, 4.0, 5.1] {
+            check_against_brute_force(&positions, cell, cutoff);
+        }
+    }
+
     #[test]
     fn large_cell_small_cutoff() {
         let cell = UnitCell::cubic(54.0);
@@ -476,4 +939,101 @@ mod tests {
             assert_ulps_eq!(pair.distance, 2.0);
         }
     }
+
+    #[test]
+    fn full_half_conversion() {
+        let cell = UnitCell::cubic(3.0);
+        let positions = [
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(1.2, 0.3, 0.0),
+            Vector3D::new(0.5, 1.8, 2.4),
+        ];
+
+        let half = NeighborsList::new(&positions, cell, 3.2);
+        assert!(!half.full);
+
+        let self_images = half.pairs.iter().filter(|pair| pair.first == pair.second).count();
+        assert!(self_images > 0);
+
+        let full = half.to_full();
+        assert!(full.full);
+        assert_eq!(full.pairs.len(), 2 * half.pairs.len() - self_images);
+
+        for (center, pairs) in full.pairs_by_center.iter().enumerate() {
+            for pair in pairs {
+                assert_eq!(pair.first, center);
+            }
+        }
+
+        for pair in &half.pairs {
+            let reversed = full.pairs.iter().any(|other| {
+                other.first == pair.second && other.second == pair.first
+                && (other.vector + pair.vector).norm() < 1e-12
+            });
+            assert!(reversed);
+        }
+
+        let converted = full.to_half();
+        assert!(!converted.full);
+        assert_eq!(converted.pairs.len(), half.pairs.len());
+        for pair in &half.pairs {
+            let found = converted.pairs.iter().any(|other| {
+                other.first == pair.first && other.second == pair.second
+                && (other.vector - pair.vector).norm() < 1e-12
+            });
+            assert!(found);
+        }
+
+        for (pairs, expected) in converted.pairs_by_center.iter().zip(&half.pairs_by_center) {
+            assert_eq!(pairs.len(), expected.len());
+        }
+
+        let from_full = NeighborsList::from_pairs(positions.len(), 3.2, full.pairs.clone(), true);
+        assert!(!from_full.full);
+        assert_eq!(from_full.pairs.len(), half.pairs.len());
+    }
+
+    #[test]
+    fn parallel_construction() {
+        // deterministic pseudo-random positions in a triclinic cell
+        let mut state = 42_u64;
+        let mut random = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1_u64 << 53) as f64
+        };
+
+        let cell = UnitCell::from(Matrix3::new([
+            [18.0, 0.0, 0.0],
+            [3.2, 16.5, 0.0],
+            [-2.1, 1.7, 17.3],
+        ]));
+
+        let mut positions = Vec::new();
+        for _ in 0..600 {
+            let fractional = Vector3D::new(1.4 * random() - 0.2, random(), random());
+            positions.push(cell.cartesian(fractional));
+        }
+
+        for (cell, cutoff) in [(cell, 3.5), (cell, 7.1), (UnitCell::infinite(), 4.2)] {
+            let serial = NeighborsList::with_parallel(&positions, cell, cutoff, false);
+            let parallel = NeighborsList::with_parallel(&positions, cell, cutoff, true);
+
+            assert!(!serial.pairs.is_empty());
+            assert_eq!(serial.pairs.len(), parallel.pairs.len());
+            for (a, b) in serial.pairs.iter().zip(&parallel.pairs) {
+                assert_eq!(a.first, b.first);
+                assert_eq!(a.second, b.second);
+                assert_eq!(a.distance, b.distance);
+                assert_eq!(a.vector, b.vector);
+            }
+
+            for (a, b) in serial.pairs_by_center.iter().zip(&parallel.pairs_by_center) {
+                assert_eq!(a.len(), b.len());
+                for (a, b) in a.iter().zip(b) {
+                    assert_eq!((a.first, a.second), (b.first, b.second));
+                    assert_eq!(a.vector, b.vector);
+                }
+            }
+        }
+    }
 }
@@ -2,15 +2,32 @@ use crate::Error;
 
 use super::{UnitCell, System, Vector3D, Pair};
 
-use super::neighbors::NeighborsList;
+use super::neighbors::{NeighborsList, neighbors_from_pairs};
+
+/// Maximal number of neighbor lists (with different cutoffs) kept in the cache
+/// of a `SimpleSystem`
+const MAX_CACHED_NEIGHBORS: usize = 4;
 
 /// A simple implementation of `System` to use when no other is available
+///
+/// Neighbor lists are cached by cutoff, so running multiple calculators with
+/// different cutoffs on the same system does not re-compute the neighbor list
+/// each time. When the cache is full, the least recently used neighbor list is
+/// evicted. The cache is cleared whenever the positions or the cell change.
 #[derive(Clone, Debug)]
 pub struct SimpleSystem {
     pub(crate) cell: UnitCell,
     species: Vec<i32>,
     positions: Vec<Vector3D>,
-    neighbors: Option<NeighborsList>,
+    /// Charges of the atoms, if any atom was added with a charge
+    charges: Option<Vec<f64>>,
+    /// Cache of neighbor lists computed with different cutoffs, from the
+    /// least recently used to the most recently used one
+    neighbors: Vec<NeighborsList>,
+    /// Index in `neighbors` of the list used by `pairs` and
+    /// `pairs_containing`, corresponding to the last call to
+    /// `compute_neighbors`
+    current_neighbors: Option<usize>,
 }
 
 impl SimpleSystem {
@@ -20,27 +37,170 @@ impl SimpleSystem {
             cell: cell,
             species: Vec::new(),
             positions: Vec::new(),
-            neighbors: None,
+            charges: None,
+            neighbors: Vec::new(),
+            current_neighbors: None,
         }
     }
 
     /// Add an atom with the given species and position to this system
     pub fn add_atom(&mut self, species: i32, position: Vector3D) {
+        self.invalidate_neighbors();
         self.species.push(species);
         self.positions.push(position);
+        if let Some(ref mut charges) = self.charges {
+            charges.push(0.0);
+        }
+    }
+
+    /// Add an atom with the given species, position and `charge` to this
+    /// system. Atoms added without a charge (with [`SimpleSystem::add_atom`])
+    /// have a charge of 0.
+    pub fn add_atom_with_charge(&mut self, species: i32, position: Vector3D, charge: f64) {
+        let n_atoms = self.species.len();
+        self.add_atom(species, position);
+
+        let charges = self.charges.get_or_insert_with(|| vec![0.0; n_atoms + 1]);
+        charges[n_atoms] = charge;
+    }
+
+    /// Remove all cached neighbor lists
+    fn invalidate_neighbors(&mut self) {
+        self.neighbors.clear();
+        self.current_neighbors = None;
+    }
+
+    /// Get the neighbor list corresponding to the last call to
+    /// `compute_neighbors`
+    fn current_neighbors(&self) -> Result<&NeighborsList, Error> {
+        let current = self.current_neighbors.ok_or_else(|| Error::Internal(
+            "neighbor list is not initialized".into()
+        ))?;
+        return Ok(&self.neighbors[current]);
+    }
+
+    /// Add an atom with the given species and `fractional` position (i.e.
+    /// position expressed in the basis of the cell vectors) to this system.
+    ///
+    /// The position is converted to Cartesian coordinates as `r = Hᵀ s`, where
+    /// `H` is the cell matrix (with one cell vector per row) and `s` the
+    /// fractional position.
+    ///
+    /// This function returns an error if the cell of this system is infinite.
+    pub fn add_atom_fractional(&mut self, species: i32, fractional: Vector3D) -> Result<(), Error> {
+        if self.cell.is_infinite() {
+            return Err(Error::InvalidParameter(
+                "can not use fractional positions with an infinite cell".into()
+            ));
+        }
+
+        self.add_atom(species, self.cell.cartesian(fractional));
+        return Ok(());
+    }
+
+    /// If this system has an infinite cell, replace it by an orthorhombic
+    /// bounding box containing all atoms, with `padding` empty space on each
+    /// side. Atoms are translated to be inside the new cell. Systems which
+    /// already have a periodic cell are left untouched.
+    ///
+    /// The closest periodic image of any atom is at least `2 * padding` away
+    /// from all other atoms.
+    pub fn infer_bounding_box(&mut self, padding: f64) -> Result<(), Error> {
+        if !(padding.is_finite() && padding > 0.0) {
+            return Err(Error::InvalidParameter(format!(
+                "bounding box padding must be a positive number, got {}", padding
+            )));
+        }
+
+        if !self.cell.is_infinite() {
+            return Ok(());
+        }
+
+        let mut min = Vector3D::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vector3D::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for position in &self.positions {
+            for d in 0..3 {
+                min[d] = f64::min(min[d], position[d]);
+                max[d] = f64::max(max[d], position[d]);
+            }
+        }
+
+        if self.positions.is_empty() {
+            min = Vector3D::zero();
+            max = Vector3D::zero();
+        }
+
+        let shift = Vector3D::new(padding, padding, padding) - min;
+        for position in &mut self.positions {
+            *position += shift;
+        }
+
+        let lengths = max - min;
+        self.invalidate_neighbors();
+        self.cell = UnitCell::orthorhombic(
+            lengths[0] + 2.0 * padding,
+            lengths[1] + 2.0 * padding,
+            lengths[2] + 2.0 * padding,
+        );
+
+        return Ok(());
+    }
+
+    /// Create a supercell of this system, repeating it `repetitions[i]` times
+    /// along the `i`-th cell vector.
+    ///
+    /// The atoms of the original system are the first atoms of the supercell,
+    /// followed by the atoms of each periodic image in turn.
+    pub fn supercell(&self, repetitions: [usize; 3]) -> Result<SimpleSystem, Error> {
+        if self.cell.is_infinite() {
+            return Err(Error::InvalidParameter(
+                "can not create a supercell of a system with an infinite cell".into()
+            ));
+        }
+
+        if repetitions.iter().any(|&n| n == 0) {
+            return Err(Error::InvalidParameter(format!(
+                "supercell repetitions must be at least 1, got {:?}", repetitions
+            )));
+        }
+
+        let mut matrix = self.cell.matrix();
+        for (row, &n) in matrix.iter_mut().zip(&repetitions) {
+            for value in row {
+                *value *= n as f64;
+            }
+        }
+
+        let mut supercell = SimpleSystem::new(UnitCell::from(matrix));
+        for a in 0..repetitions[0] {
+            for b in 0..repetitions[1] {
+                for c in 0..repetitions[2] {
+                    let shift = self.cell.cartesian(Vector3D::new(a as f64, b as f64, c as f64));
+                    for (i, (&species, &position)) in self.species.iter().zip(&self.positions).enumerate() {
+                        if let Some(ref charges) = self.charges {
+                            supercell.add_atom_with_charge(species, position + shift, charges[i]);
+                        } else {
+                            supercell.add_atom(species, position + shift);
+                        }
+                    }
+                }
+            }
+        }
+
+        return Ok(supercell);
     }
 
     #[cfg(test)]
     pub(crate) fn positions_mut(&mut self) -> &mut [Vector3D] {
-        // any position access invalidates the neighbor list
-        self.neighbors = None;
+        // any position access invalidates the neighbor lists
+        self.invalidate_neighbors();
         return &mut self.positions;
     }
 
     #[cfg(test)]
     pub(crate) fn set_cell(&mut self, cell: UnitCell) {
-        // cell change invalidate the neighbor list
-        self.neighbors = None;
+        // cell change invalidate the neighbor lists
+        self.invalidate_neighbors();
         self.cell = cell;
     }
 }
@@ -62,31 +222,52 @@ impl System for SimpleSystem {
         Ok(self.cell)
     }
 
+    fn charges(&self) -> Option<&[f64]> {
+        self.charges.as_deref()
+    }
+
     #[allow(clippy::float_cmp)]
     fn compute_neighbors(&mut self, cutoff: f64) -> Result<(), Error> {
         // re-use already computed NL is possible
-        if let Some(ref nl) = self.neighbors {
-            if nl.cutoff == cutoff {
-                return Ok(());
-            }
+        if let Some(index) = self.neighbors.iter().position(|nl| nl.cutoff == cutoff) {
+            // move the list to the end, marking it as the most recently used
+            let neighbors = self.neighbors.remove(index);
+            self.neighbors.push(neighbors);
+            self.current_neighbors = Some(self.neighbors.len() - 1);
+            return Ok(());
+        }
+
+        if self.neighbors.len() == MAX_CACHED_NEIGHBORS {
+            // evict the least recently used neighbor list
+            self.neighbors.remove(0);
         }
 
-        self.neighbors = Some(NeighborsList::new(self.positions()?, self.cell()?, cutoff));
+        self.neighbors.push(NeighborsList::new(self.positions()?, self.cell()?, cutoff));
+        self.current_neighbors = Some(self.neighbors.len() - 1);
+        Ok(())
+    }
+
+    #[allow(clippy::float_cmp)]
+    fn set_neighbors(&mut self, cutoff: f64, pairs: Vec<Pair>) -> Result<(), Error> {
+        let neighbors = neighbors_from_pairs(self.species.len(), cutoff, pairs)?;
+
+        // replace any list with the same cutoff in the cache
+        self.neighbors.retain(|nl| nl.cutoff != cutoff);
+        if self.neighbors.len() == MAX_CACHED_NEIGHBORS {
+            self.neighbors.remove(0);
+        }
+
+        self.neighbors.push(neighbors);
+        self.current_neighbors = Some(self.neighbors.len() - 1);
         Ok(())
     }
 
     fn pairs(&self) -> Result<&[Pair], Error> {
-        let neighbors = self.neighbors.as_ref().ok_or_else(|| Error::Internal(
-            "neighbor list is not initialized".into()
-        ))?;
-        Ok(&neighbors.pairs)
+        Ok(&self.current_neighbors()?.pairs)
     }
 
     fn pairs_containing(&self, center: usize) -> Result<&[Pair], Error> {
-        let neighbors = self.neighbors.as_ref().ok_or_else(|| Error::Internal(
-            "neighbor list is not initialized".into()
-        ))?;
-        Ok(&neighbors.pairs_by_center[center])
+        Ok(&self.current_neighbors()?.pairs_by_center[center])
     }
 }
 
@@ -98,6 +279,7 @@ impl std::convert::TryFrom<&dyn System> for SimpleSystem {
         for (&species, &position) in system.species()?.iter().zip(system.positions()?) {
             new.add_atom(species, position);
         }
+        new.charges = system.charges().map(|charges| charges.to_vec());
         return Ok(new);
     }
 }
@@ -124,4 +306,228 @@ mod tests {
             Vector3D::new(5.0, 3.0, 4.0),
         ]);
     }
+
+    #[test]
+    fn add_atoms_fractional() {
+        let mut system = SimpleSystem::new(UnitCell::orthorhombic(2.0, 3.0, 4.0));
+        system.add_atom_fractional(3, Vector3D::new(0.5, 0.5, 0.5)).unwrap();
+        assert_eq!(system.positions().unwrap(), &[Vector3D::new(1.0, 1.5, 2.0)]);
+
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        let error = system.add_atom_fractional(3, Vector3D::new(0.5, 0.5, 0.5)).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: can not use fractional positions with an infinite cell");
+    }
+
+    #[test]
+    fn charges() {
+        let mut system = SimpleSystem::new(UnitCell::cubic(10.0));
+        system.add_atom(3, Vector3D::new(2.0, 3.0, 4.0));
+        assert!(system.charges().is_none());
+
+        system.add_atom_with_charge(8, Vector3D::new(1.0, 3.0, 4.0), -0.8);
+        system.add_atom(1, Vector3D::new(5.0, 3.0, 4.0));
+        system.add_atom_with_charge(1, Vector3D::new(5.0, 3.0, 5.0), 0.4);
+        assert_eq!(system.charges().unwrap(), &[0.0, -0.8, 0.0, 0.4]);
+
+        let supercell = system.supercell([2, 1, 1]).unwrap();
+        assert_eq!(supercell.charges().unwrap(), &[0.0, -0.8, 0.0, 0.4, 0.0, -0.8, 0.0, 0.4]);
+
+        let copy = SimpleSystem::try_from(&system as &dyn System).unwrap();
+        assert_eq!(copy.charges(), system.charges());
+    }
+
+    #[test]
+    fn supercell() {
+        let mut system = SimpleSystem::new(UnitCell::orthorhombic(2.0, 3.0, 4.0));
+        system.add_atom(3, Vector3D::new(0.5, 1.0, 1.5));
+        system.add_atom(1, Vector3D::new(1.0, 2.0, 3.0));
+
+        let supercell = system.supercell([2, 1, 2]).unwrap();
+        assert_eq!(supercell.cell().unwrap().matrix(), UnitCell::orthorhombic(4.0, 3.0, 8.0).matrix());
+        assert_eq!(supercell.species().unwrap(), &[3, 1, 3, 1, 3, 1, 3, 1]);
+        assert_eq!(&supercell.positions().unwrap()[..4], &[
+            Vector3D::new(0.5, 1.0, 1.5),
+            Vector3D::new(1.0, 2.0, 3.0),
+            Vector3D::new(0.5, 1.0, 5.5),
+            Vector3D::new(1.0, 2.0, 7.0),
+        ]);
+        assert_eq!(supercell.positions().unwrap()[7], Vector3D::new(3.0, 2.0, 7.0));
+
+        assert!(system.supercell([2, 0, 1]).is_err());
+        assert!(SimpleSystem::new(UnitCell::infinite()).supercell([2, 2, 2]).is_err());
+    }
+
+    #[test]
+    fn bounding_box() {
+        let mut system = SimpleSystem::new(UnitCell::infinite());
+        system.add_atom(3, Vector3D::new(2.0, -3.0, 4.0));
+        system.add_atom(1, Vector3D::new(1.0, 3.0, 4.0));
+
+        system.infer_bounding_box(2.5).unwrap();
+        assert_eq!(system.cell().unwrap(), UnitCell::orthorhombic(6.0, 11.0, 5.0));
+        assert_eq!(system.positions().unwrap(), &[
+            Vector3D::new(3.5, 2.5, 2.5),
+            Vector3D::new(2.5, 8.5, 2.5),
+        ]);
+
+        // periodic systems are not modified
+        let mut system = SimpleSystem::new(UnitCell::cubic(10.0));
+        system.add_atom(3, Vector3D::new(2.0, -3.0, 4.0));
+        system.infer_bounding_box(2.5).unwrap();
+        assert_eq!(system.cell().unwrap(), UnitCell::cubic(10.0));
+        assert_eq!(system.positions().unwrap(), &[Vector3D::new(2.0, -3.0, 4.0)]);
+
+        assert!(system.infer_bounding_box(-1.0).is_err());
+        assert!(system.infer_bounding_box(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn neighbors_cache() {
+        let mut system = crate::systems::test_utils::test_system("methane");
+        assert!(system.pairs().is_err());
+
+        system.compute_neighbors(3.5).unwrap();
+        let pairs_3_5 = system.pairs().unwrap().as_ptr();
+        let n_pairs_3_5 = system.pairs().unwrap().len();
+
+        system.compute_neighbors(5.0).unwrap();
+        assert_ne!(system.pairs().unwrap().len(), n_pairs_3_5);
+
+        // the list for 3.5 is still in the cache and not re-computed
+        system.compute_neighbors(3.5).unwrap();
+        assert_eq!(system.pairs().unwrap().as_ptr(), pairs_3_5);
+        assert_eq!(system.pairs().unwrap().len(), n_pairs_3_5);
+        assert_eq!(system.neighbors.len(), 2);
+
+        // the least recently used list is evicted when the cache is full.
+        // 3.5 was used after 5.0, so 5.0 is evicted first
+        for cutoff in [2.0, 2.5, 3.0] {
+            system.compute_neighbors(cutoff).unwrap();
+        }
+        assert_eq!(system.neighbors.len(), MAX_CACHED_NEIGHBORS);
+        assert!(system.neighbors.iter().all(|nl| nl.cutoff != 5.0));
+
+        system.compute_neighbors(3.5).unwrap();
+        assert_eq!(system.pairs().unwrap().as_ptr(), pairs_3_5);
+
+        // using 2.0 again means 2.5 is now the least recently used list
+        system.compute_neighbors(2.0).unwrap();
+        system.compute_neighbors(4.0).unwrap();
+        assert_eq!(system.neighbors.len(), MAX_CACHED_NEIGHBORS);
+        assert!(system.neighbors.iter().all(|nl| nl.cutoff != 2.5));
+        assert!(system.neighbors.iter().any(|nl| nl.cutoff == 2.0));
+        assert!(system.neighbors.iter().any(|nl| nl.cutoff == 3.5));
+
+        // changing the positions clears the cache
+        system.positions_mut()[0] += Vector3D::new(0.1, 0.0, 0.0);
+        assert!(system.neighbors.is_empty());
+        assert!(system.pairs().is_err());
+    }
+
+    #[test]
+    fn full_neighbors_list() {
+        let mut system = crate::systems::test_utils::test_system("water");
+        // cutoff larger than the cell, the atoms see their own images
+        system.compute_neighbors(10.5).unwrap();
+        let half = system.pairs().unwrap().to_vec();
+        let full = system.pairs_full().unwrap();
+        let self_images = half.iter().filter(|pair| pair.first == pair.second).count();
+        assert!(self_images > 0);
+        assert_eq!(full.len(), 2 * half.len() - self_images);
+
+        system.compute_neighbors(3.0).unwrap();
+        let half = system.pairs().unwrap().to_vec();
+        let full = system.pairs_full().unwrap();
+        assert!(!half.is_empty());
+        assert_eq!(full.len(), 2 * half.len());
+
+        for pair in &half {
+            assert!(full.iter().any(|p| p.first == pair.first && p.second == pair.second && p.vector == pair.vector));
+            assert!(full.iter().any(|p| p.first == pair.second && p.second == pair.first && p.vector == -pair.vector));
+        }
+
+        for window in full.windows(2) {
+            assert!((window[0].first, window[0].second) <= (window[1].first, window[1].second));
+        }
+    }
+
+    #[test]
+    fn set_neighbors() {
+        let mut system = crate::systems::test_utils::test_system("water");
+        let mut calculator = crate::Calculator::new(
+            "neighbor_list",
+            r#"{"cutoff": 3.0, "full_neighbor_list": false, "self_pairs": false}"#.into()
+        ).unwrap();
+
+        let mut systems = vec![Box::new(system.clone()) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        let n_pairs = descriptor.iter().map(|(_, block)| block.samples().count()).sum::<usize>();
+        assert_eq!(n_pairs, 3);
+
+        // only keep the first pair
+        system.compute_neighbors(3.0).unwrap();
+        let pairs = system.pairs().unwrap()[..1].to_vec();
+        system.set_neighbors(3.0, pairs).unwrap();
+
+        // the calculator uses the injected list
+        let mut systems = vec![Box::new(system.clone()) as Box<dyn System>];
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        let n_pairs = descriptor.iter().map(|(_, block)| block.samples().count()).sum::<usize>();
+        assert_eq!(n_pairs, 1);
+
+        // other cutoffs still compute the neighbor list
+        system.compute_neighbors(2.0).unwrap();
+        assert_eq!(system.pairs().unwrap().len(), 3);
+        system.compute_neighbors(3.0).unwrap();
+        assert_eq!(system.pairs().unwrap().len(), 1);
+
+        // changing the positions invalidates the list
+        system.positions_mut()[0][0] += 0.1;
+        system.compute_neighbors(3.0).unwrap();
+        assert_eq!(system.pairs().unwrap().len(), 3);
+
+        let pairs = system.pairs().unwrap().to_vec();
+        let mut invalid = pairs.clone();
+        invalid[0].first = 0;
+        invalid[0].second = 3;
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: invalid pair between atoms 0 and 3 in a system with 3 atoms");
+
+        let mut invalid = pairs.clone();
+        invalid[0].first = 2;
+        invalid[0].second = 1;
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: invalid full neighbor list: got 2 pairs where the first atom has the smallest index, and 1 pairs where it has the largest index; both directions must be present for all pairs");
+
+        let mut invalid = crate::systems::neighbors::half_to_full_pairs(&pairs);
+        for pair in &mut invalid {
+            if pair.first == 2 && pair.second == 1 {
+                pair.cell_shift = [1, 0, 0];
+            }
+        }
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: invalid full neighbor list: missing the pair between atoms 1 and 2 corresponding to the pair between atoms 2 and 1");
+
+        let mut invalid = pairs.clone();
+        invalid[0].first = 1;
+        invalid[0].second = 1;
+        invalid[0].cell_shift = [0, 0, 0];
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert_eq!(error.to_string(), "invalid parameter: invalid pair between atom 1 and itself: self pairs are only allowed between an atom and one of its periodic images");
+
+        let mut invalid = pairs.clone();
+        invalid[0].distance = f64::NAN;
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert!(error.to_string().starts_with("invalid parameter: invalid distance for the pair between atoms"));
+
+        let mut invalid = pairs.clone();
+        invalid[0].distance = 3.5;
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert!(error.to_string().starts_with("invalid parameter: invalid distance for the pair between atoms"));
+
+        let mut invalid = pairs;
+        invalid[0].distance *= 0.5;
+        let error = system.set_neighbors(3.0, invalid).unwrap_err();
+        assert!(error.to_string().contains("does not match the norm of the vector"));
+    }
 }
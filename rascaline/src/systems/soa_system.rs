@@ -0,0 +1,293 @@
+use log::warn;
+
+use crate::Error;
+
+use super::{UnitCell, System, Vector3D, Pair};
+use super::neighbors::{CellList, NeighborsList, neighbors_from_pairs};
+
+/// An implementation of `System` storing the atomic positions in a
+/// structure-of-arrays layout, with separate arrays for the `x`, `y` and `z`
+/// coordinates.
+///
+/// The neighbor search uses the same cell list as [`SimpleSystem`], but
+/// computes the distances for all candidate pairs in a single pass over
+/// contiguous arrays, which the compiler can vectorize. This produces exactly
+/// the same pairs (in the same order) as [`SimpleSystem`].
+///
+/// The `System` trait requires access to the positions as `&[Vector3D]`, so
+/// this type also keeps an array-of-structures copy of the positions.
+///
+/// The `neighbors` benchmark (`cargo bench --bench neighbors`) compares the
+/// neighbor list construction of this type and [`SimpleSystem`], both for
+/// small systems and for a large supercell.
+///
+/// [`SimpleSystem`]: super::SimpleSystem
+#[derive(Clone, Debug)]
+pub struct SoaSystem {
+    cell: UnitCell,
+    species: Vec<i32>,
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    zs: Vec<f64>,
+    positions: Vec<Vector3D>,
+    neighbors: Option<NeighborsList>,
+}
+
+impl SoaSystem {
+    /// Create a new empty system with the given unit cell
+    pub fn new(cell: UnitCell) -> SoaSystem {
+        SoaSystem {
+            cell: cell,
+            species: Vec::new(),
+            xs: Vec::new(),
+            ys: Vec::new(),
+            zs: Vec::new(),
+            positions: Vec::new(),
+            neighbors: None,
+        }
+    }
+
+    /// Add an atom with the given species and position to this system
+    pub fn add_atom(&mut self, species: i32, position: Vector3D) {
+        self.species.push(species);
+        self.xs.push(position[0]);
+        self.ys.push(position[1]);
+        self.zs.push(position[2]);
+        self.positions.push(position);
+        self.neighbors = None;
+    }
+
+    /// Get the `x` coordinates of all atoms in this system
+    pub fn xs(&self) -> &[f64] {
+        &self.xs
+    }
+
+    /// Get the `y` coordinates of all atoms in this system
+    pub fn ys(&self) -> &[f64] {
+        &self.ys
+    }
+
+    /// Get the `z` coordinates of all atoms in this system
+    pub fn zs(&self) -> &[f64] {
+        &self.zs
+    }
+
+    /// Compute the neighbor list with the given `cutoff`, using the
+    /// structure-of-arrays layout for the distance computations.
+    #[time_graph::instrument(name = "SoaSystem::neighbors")]
+    fn soa_neighbors(&self, cutoff: f64) -> NeighborsList {
+        let mut cell_list = CellList::new(self.cell, cutoff);
+        for (index, &position) in self.positions.iter().enumerate() {
+            cell_list.add_atom(index, position);
+        }
+        let candidates = cell_list.pairs();
+
+        // gather the vectors between candidate pairs in separate arrays
+        let cell_matrix = self.cell.matrix();
+        let mut dx = Vec::with_capacity(candidates.len());
+        let mut dy = Vec::with_capacity(candidates.len());
+        let mut dz = Vec::with_capacity(candidates.len());
+        for pair in &candidates {
+            let shift = pair.shift.cartesian(&cell_matrix);
+            dx.push(self.xs[pair.second] - self.xs[pair.first] + shift[0]);
+            dy.push(self.ys[pair.second] - self.ys[pair.first] + shift[1]);
+            dz.push(self.zs[pair.second] - self.zs[pair.first] + shift[2]);
+        }
+
+        // this loop only touches contiguous arrays and can be vectorized
+        let distances2 = dx.iter().zip(&dy).zip(&dz)
+            .map(|((x, y), z)| x * x + y * y + z * z)
+            .collect::<Vec<_>>();
+
+        let cutoff2 = cutoff * cutoff;
+        let mut pairs = Vec::new();
+        for (i, pair) in candidates.iter().enumerate() {
+            let distance2 = distances2[i];
+            if distance2 < cutoff2 {
+                if distance2 < 1e-3 {
+                    warn!(
+                        "atoms {} and {} are very close to one another ({} A)",
+                        pair.first, pair.second, distance2.sqrt()
+                    );
+                }
+
+                pairs.push(Pair {
+                    first: pair.first,
+                    second: pair.second,
+                    distance: distance2.sqrt(),
+                    vector: Vector3D::new(dx[i], dy[i], dz[i]),
+                    cell_shift: pair.shift.to_array(),
+                });
+            }
+        }
+
+        return NeighborsList::from_pairs(self.species.len(), cutoff, pairs, false);
+    }
+}
+
+impl System for SoaSystem {
+    fn size(&self) -> Result<usize, Error> {
+        Ok(self.species.len())
+    }
+
+    fn positions(&self) -> Result<&[Vector3D], Error> {
+        Ok(&self.positions)
+    }
+
+    fn species(&self) -> Result<&[i32], Error> {
+        Ok(&self.species)
+    }
+
+    fn cell(&self) -> Result<UnitCell, Error> {
+        Ok(self.cell)
+    }
+
+    #[allow(clippy::float_cmp)]
+    fn compute_neighbors(&mut self, cutoff: f64) -> Result<(), Error> {
+        // re-use already computed NL is possible
+        if let Some(ref nl) = self.neighbors {
+            if nl.cutoff == cutoff {
+                return Ok(());
+            }
+        }
+
+        self.neighbors = Some(self.soa_neighbors(cutoff));
+        Ok(())
+    }
+
+    fn set_neighbors(&mut self, cutoff: f64, pairs: Vec<Pair>) -> Result<(), Error> {
+        self.neighbors = Some(neighbors_from_pairs(self.species.len(), cutoff, pairs)?);
+        Ok(())
+    }
+
+    fn pairs(&self) -> Result<&[Pair], Error> {
+        let neighbors = self.neighbors.as_ref().ok_or_else(|| Error::Internal(
+            "neighbor list is not initialized".into()
+        ))?;
+        Ok(&neighbors.pairs)
+    }
+
+    fn pairs_containing(&self, center: usize) -> Result<&[Pair], Error> {
+        let neighbors = self.neighbors.as_ref().ok_or_else(|| Error::Internal(
+            "neighbor list is not initialized".into()
+        ))?;
+        Ok(&neighbors.pairs_by_center[center])
+    }
+}
+
+impl std::convert::TryFrom<&dyn System> for SoaSystem {
+    type Error = Error;
+
+    fn try_from(system: &dyn System) -> Result<SoaSystem, Error> {
+        let mut new = SoaSystem::new(system.cell()?);
+        for (&species, &position) in system.species()?.iter().zip(system.positions()?) {
+            new.add_atom(species, position);
+        }
+        return Ok(new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use approx::assert_relative_eq;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::SimpleSystem;
+    use crate::{Calculator, Matrix3};
+    use crate::calculators::CalculatorBase;
+    use crate::calculators::{SoapPowerSpectrum, PowerSpectrumParameters, RadialBasis};
+    use crate::calculators::soap::{CutoffFunction, RadialScaling};
+
+    use super::*;
+
+    fn systems() -> Vec<SimpleSystem> {
+        let mut triclinic = SimpleSystem::new(UnitCell::from(Matrix3::new([
+            [4.2, 0.0, 0.0],
+            [1.1, 3.9, 0.0],
+            [-0.7, 0.4, 4.5],
+        ])));
+        triclinic.add_atom(14, Vector3D::new(0.1, 0.2, 0.3));
+        triclinic.add_atom(14, Vector3D::new(1.9, 2.1, 2.2));
+        triclinic.add_atom(8, Vector3D::new(3.5, -0.4, 4.1));
+        triclinic.add_atom(8, Vector3D::new(2.2, 3.3, 1.0));
+
+        let mut molecule = SimpleSystem::new(UnitCell::infinite());
+        molecule.add_atom(6, Vector3D::new(0.0, 0.0, 0.0));
+        molecule.add_atom(1, Vector3D::new(0.6, 0.8, 0.1));
+        molecule.add_atom(1, Vector3D::new(-0.9, 0.2, 0.4));
+        molecule.add_atom(8, Vector3D::new(0.3, -1.3, -0.2));
+
+        return vec![test_system("methane"), test_system("water"), triclinic, molecule];
+    }
+
+    #[test]
+    fn same_pairs() {
+        for mut simple in systems() {
+            let mut soa = SoaSystem::try_from(&simple as &dyn System).unwrap();
+            assert_eq!(soa.xs().len(), simple.size().unwrap());
+
+            for cutoff in [2.1, 3.5, 6.2] {
+                simple.compute_neighbors(cutoff).unwrap();
+                soa.compute_neighbors(cutoff).unwrap();
+
+                let simple_pairs = simple.pairs().unwrap();
+                let soa_pairs = soa.pairs().unwrap();
+                assert_eq!(simple_pairs.len(), soa_pairs.len());
+                for (a, b) in simple_pairs.iter().zip(soa_pairs) {
+                    assert_eq!(a.first, b.first);
+                    assert_eq!(a.second, b.second);
+                    assert_eq!(a.distance, b.distance);
+                    assert_eq!(a.vector, b.vector);
+                }
+
+                for center in 0..simple.size().unwrap() {
+                    assert_eq!(
+                        simple.pairs_containing(center).unwrap().len(),
+                        soa.pairs_containing(center).unwrap().len(),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_features() {
+        let parameters = PowerSpectrumParameters {
+            cutoff: 3.5,
+            max_radial: 4,
+            max_angular: 3,
+            atomic_gaussian_width: 0.3,
+            center_atom_weight: 1.0,
+            radial_basis: RadialBasis::splined_gto(1e-8),
+            radial_scaling: RadialScaling::None {},
+            cutoff_function: CutoffFunction::ShiftedCosine { width: 0.5 },
+            fused: false,
+            all_species: None,
+        };
+        let mut calculator = Calculator::from(Box::new(
+            SoapPowerSpectrum::new(parameters).unwrap()
+        ) as Box<dyn CalculatorBase>);
+
+        let mut simple = systems().into_iter()
+            .map(|system| Box::new(system) as Box<dyn System>)
+            .collect::<Vec<_>>();
+        let mut soa = systems().into_iter()
+            .map(|system| Box::new(SoaSystem::try_from(&system as &dyn System).unwrap()) as Box<dyn System>)
+            .collect::<Vec<_>>();
+
+        let simple = calculator.compute(&mut simple, Default::default()).unwrap();
+        let soa = calculator.compute(&mut soa, Default::default()).unwrap();
+
+        assert_eq!(simple.keys(), soa.keys());
+        for ((_, simple), (_, soa)) in simple.iter().zip(soa.iter()) {
+            assert_eq!(simple.samples(), soa.samples());
+            assert_relative_eq!(
+                simple.values().to_array(),
+                soa.values().to_array(),
+                max_relative=1e-14,
+            );
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use crate::Vector3D;
+
+use super::{UnitCell, Pair};
+use super::neighbors::NeighborsList;
+
+/// A neighbor list where two atoms are neighbors if their Voronoi cells share
+/// a face, instead of being closer than a given cutoff.
+///
+/// This uses the same "half" neighbor list convention as [`NeighborsList`]:
+/// each pair `i-j` is only included once, and `pairs_by_center[i]` contains
+/// all the pairs where `i` is either the first or the second atom. Pairs
+/// between an atom and its own periodic images (for small unit cells) are
+/// included once for each pair of opposite faces.
+///
+/// The area of the face shared by the two atoms in each pair is available in
+/// `face_areas`, and can be used to weight the neighbors.
+#[derive(Clone, Debug)]
+pub struct VoronoiNeighbors {
+    /// all pairs in the system
+    pub pairs: Vec<Pair>,
+    /// all pairs in the system, classified by associated center
+    pub pairs_by_center: Vec<Vec<Pair>>,
+    /// area of the Voronoi face shared by the atoms in each pair, in the same
+    /// order as `pairs`
+    pub face_areas: Vec<f64>,
+}
+
+impl VoronoiNeighbors {
+    /// Compute the Voronoi neighbors of atoms at the given `positions`, in
+    /// the given `unit_cell`.
+    ///
+    /// For infinite unit cells, the Voronoi cells of atoms at the surface of
+    /// the system extend to infinity, and only the faces shared with other
+    /// atoms are included.
+    #[time_graph::instrument(name = "VoronoiNeighbors::new")]
+    pub fn new(positions: &[Vector3D], unit_cell: UnitCell) -> VoronoiNeighbors {
+        if positions.is_empty() {
+            return VoronoiNeighbors {
+                pairs: Vec::new(),
+                pairs_by_center: Vec::new(),
+                face_areas: Vec::new(),
+            };
+        }
+
+        if unit_cell.is_infinite() {
+            // use all other atoms as candidate neighbors
+            let mut min = positions[0];
+            let mut max = positions[0];
+            for position in positions {
+                for d in 0..3 {
+                    min[d] = f64::min(min[d], position[d]);
+                    max[d] = f64::max(max[d], position[d]);
+                }
+            }
+            let radius = (max - min).norm() + 1.0;
+
+            let candidates = candidate_neighbors(positions, unit_cell, radius);
+            let cells = voronoi_cells(&candidates, radius);
+            return VoronoiNeighbors::from_cells(&candidates, &cells);
+        }
+
+        // start with a radius of the order of the distance between atoms, and
+        // increase it until all the atoms which could contribute to any
+        // Voronoi cell are included in the candidates
+        let mut radius = 2.0 * f64::cbrt(unit_cell.volume() / positions.len() as f64);
+        loop {
+            let candidates = candidate_neighbors(positions, unit_cell, radius);
+            let cells = voronoi_cells(&candidates, radius);
+
+            let max_radius = cells.iter()
+                .map(VoronoiCell::max_radius)
+                .fold(0.0, f64::max);
+
+            // the bisector plane with an atom further than `2 * max_radius`
+            // can not cut any of the cells
+            if 2.0 * max_radius < radius {
+                return VoronoiNeighbors::from_cells(&candidates, &cells);
+            }
+
+            // the cells can only shrink when adding more candidates, so this
+            // radius is enough to include all the relevant neighbors, as long
+            // as the cells are no longer bounded by the initial box
+            radius = f64::max(2.0 * radius, 2.0 * max_radius * (1.0 + 1e-6));
+        }
+    }
+
+    /// Extract the pairs following the half neighbor list convention from the
+    /// Voronoi `cells` of all atoms
+    fn from_cells(candidates: &[Vec<Candidate>], cells: &[VoronoiCell]) -> VoronoiNeighbors {
+        let mut pairs = Vec::new();
+        for (center, cell) in cells.iter().enumerate() {
+            for face in &cell.faces {
+                let candidate = match face.neighbor {
+                    Some(index) => candidates[center][index],
+                    // face of the initial bounding box
+                    None => continue,
+                };
+
+                let vector = candidate.vector;
+                let keep = if center == candidate.neighbor {
+                    // pairs with a periodic image of the same atom appear as
+                    // two opposite faces, only keep one of them
+                    (vector[0], vector[1], vector[2]) > (-vector[0], -vector[1], -vector[2])
+                } else {
+                    center < candidate.neighbor
+                };
+
+                if keep {
+                    let pair = Pair {
+                        first: center,
+                        second: candidate.neighbor,
+                        distance: vector.norm(),
+                        vector: vector,
+                        cell_shift: candidate.cell_shift,
+                    };
+                    pairs.push((pair, face.area()));
+                }
+            }
+        }
+
+        pairs.sort_by_key(|(pair, _)| (pair.first, pair.second));
+
+        let mut pairs_by_center = vec![Vec::new(); cells.len()];
+        for (pair, _) in &pairs {
+            pairs_by_center[pair.first].push(*pair);
+            pairs_by_center[pair.second].push(*pair);
+        }
+
+        let (pairs, face_areas) = pairs.into_iter().unzip();
+        return VoronoiNeighbors {
+            pairs: pairs,
+            pairs_by_center: pairs_by_center,
+            face_areas: face_areas,
+        };
+    }
+}
+
+/// Potential neighbor of an atom when building its Voronoi cell
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    /// index of the neighbor atom
+    neighbor: usize,
+    /// vector from the center to the neighbor atom
+    vector: Vector3D,
+    /// cell shift associated with `vector`
+    cell_shift: [i32; 3],
+}
+
+/// Get all the candidate neighbors closer than `radius` for each atom, sorted
+/// by distance
+fn candidate_neighbors(positions: &[Vector3D], unit_cell: UnitCell, radius: f64) -> Vec<Vec<Candidate>> {
+    let neighbors = NeighborsList::new(positions, unit_cell, radius);
+
+    let mut candidates = vec![Vec::new(); positions.len()];
+    for pair in &neighbors.pairs {
+        if pair.distance < 1e-12 {
+            // the Voronoi cell is not defined for overlapping atoms
+            continue;
+        }
+
+        candidates[pair.first].push(Candidate {
+            neighbor: pair.second,
+            vector: pair.vector,
+            cell_shift: pair.cell_shift,
+        });
+        candidates[pair.second].push(Candidate {
+            neighbor: pair.first,
+            vector: -pair.vector,
+            cell_shift: [-pair.cell_shift[0], -pair.cell_shift[1], -pair.cell_shift[2]],
+        });
+    }
+
+    for candidates in &mut candidates {
+        candidates.sort_by(|a, b| {
+            a.vector.norm2().partial_cmp(&b.vector.norm2()).expect("got NaN distance")
+        });
+    }
+
+    return candidates;
+}
+
+/// Compute the Voronoi cells of all atoms, starting from a cube with the
+/// given `half_size` around each atom
+fn voronoi_cells(candidates: &[Vec<Candidate>], half_size: f64) -> Vec<VoronoiCell> {
+    return candidates.iter().map(|candidates| {
+        let mut cell = VoronoiCell::cube(half_size);
+        let mut max_radius = cell.max_radius();
+        for (index, candidate) in candidates.iter().enumerate() {
+            if candidate.vector.norm() > 2.0 * max_radius {
+                // candidates are sorted by distance, none of the remaining
+                // ones can cut the cell
+                break;
+            }
+
+            if cell.clip(index, candidate.vector) {
+                max_radius = cell.max_radius();
+            }
+        }
+        cell
+    }).collect();
+}
+
+/// Face of a Voronoi cell
+#[derive(Debug, Clone)]
+struct Face {
+    /// index of the candidate neighbor defining this face, `None` for faces
+    /// of the initial bounding box
+    neighbor: Option<usize>,
+    /// vertices of the face, in order around the face
+    vertices: Vec<Vector3D>,
+}
+
+impl Face {
+    /// Get the area of this (convex) face
+    fn area(&self) -> f64 {
+        let origin = self.vertices[0];
+        let mut sum = Vector3D::zero();
+        for window in self.vertices[1..].windows(2) {
+            sum += (window[0] - origin) ^ (window[1] - origin);
+        }
+        return 0.5 * sum.norm();
+    }
+}
+
+/// Voronoi cell of a single atom, represented as a convex polyhedron centered
+/// on the atom
+#[derive(Debug, Clone)]
+struct VoronoiCell {
+    faces: Vec<Face>,
+}
+
+impl VoronoiCell {
+    /// Create a cube centered on the origin with the given `half_size`
+    fn cube(half_size: f64) -> VoronoiCell {
+        let h = half_size;
+        let faces = [
+            [[h, -h, -h], [h, h, -h], [h, h, h], [h, -h, h]],
+            [[-h, -h, -h], [-h, -h, h], [-h, h, h], [-h, h, -h]],
+            [[-h, h, -h], [-h, h, h], [h, h, h], [h, h, -h]],
+            [[-h, -h, -h], [h, -h, -h], [h, -h, h], [-h, -h, h]],
+            [[-h, -h, h], [h, -h, h], [h, h, h], [-h, h, h]],
+            [[-h, -h, -h], [-h, h, -h], [h, h, -h], [h, -h, -h]],
+        ];
+
+        let faces = faces.iter().map(|vertices| Face {
+            neighbor: None,
+            vertices: vertices.iter().map(|&v| Vector3D::from(v)).collect(),
+        }).collect();
+
+        return VoronoiCell { faces };
+    }
+
+    /// Get the largest distance between the center of the cell and any of
+    /// its vertices
+    fn max_radius(&self) -> f64 {
+        return self.faces.iter()
+            .flat_map(|face| &face.vertices)
+            .map(|vertex| vertex.norm())
+            .fold(0.0, f64::max);
+    }
+
+    /// Cut this cell with the bisector plane between the center and a
+    /// neighbor at `vector`, keeping the half-space containing the center.
+    ///
+    /// Returns `true` if the cell was modified.
+    fn clip(&mut self, neighbor: usize, vector: Vector3D) -> bool {
+        // points are inside if `x·vector <= offset`
+        let offset = 0.5 * vector.norm2();
+        let distance_to_plane = |point: Vector3D| point * vector - offset;
+
+        // vertices closer than this to the plane are considered to be on the
+        // plane. This prevents the creation of faces with zero area when the
+        // plane only touches the cell.
+        let epsilon = 1e-10 * offset;
+        let length_epsilon = 1e-8 * vector.norm();
+
+        let max_distance = self.faces.iter()
+            .flat_map(|face| &face.vertices)
+            .map(|&vertex| distance_to_plane(vertex))
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        if max_distance <= epsilon {
+            return false;
+        }
+
+        let mut new_face = Vec::new();
+        let mut faces = Vec::with_capacity(self.faces.len() + 1);
+        for face in &self.faces {
+            let mut vertices = Vec::new();
+            let n_vertices = face.vertices.len();
+            for k in 0..n_vertices {
+                let current = face.vertices[k];
+                let next = face.vertices[(k + 1) % n_vertices];
+
+                let current_distance = distance_to_plane(current);
+                let next_distance = distance_to_plane(next);
+
+                let current_inside = current_distance <= epsilon;
+                if current_inside {
+                    push_unique(&mut vertices, current, length_epsilon);
+                    if current_distance >= -epsilon {
+                        push_unique(&mut new_face, current, length_epsilon);
+                    }
+                }
+
+                if current_inside != (next_distance <= epsilon) {
+                    // this edge crosses the plane
+                    let t = current_distance / (current_distance - next_distance);
+                    let point = current + f64::clamp(t, 0.0, 1.0) * (next - current);
+                    push_unique(&mut vertices, point, length_epsilon);
+                    push_unique(&mut new_face, point, length_epsilon);
+                }
+            }
+
+            if vertices.len() >= 3 {
+                faces.push(Face {
+                    neighbor: face.neighbor,
+                    vertices: vertices,
+                });
+            }
+        }
+
+        if new_face.len() >= 3 {
+            sort_around_center(&mut new_face, vector);
+            faces.push(Face {
+                neighbor: Some(neighbor),
+                vertices: new_face,
+            });
+        }
+
+        self.faces = faces;
+        return true;
+    }
+}
+
+/// Add `point` to `points`, unless it is closer than `epsilon` to one of the
+/// points already there
+fn push_unique(points: &mut Vec<Vector3D>, point: Vector3D, epsilon: f64) {
+    if points.iter().all(|other| (*other - point).norm() > epsilon) {
+        points.push(point);
+    }
+}
+
+/// Sort the `points`, which are all in a plane with the given `normal`,
+/// according to their angle around their barycenter.
+fn sort_around_center(points: &mut [Vector3D], normal: Vector3D) {
+    let mut center = Vector3D::zero();
+    for &point in points.iter() {
+        center += point;
+    }
+    center /= points.len() as f64;
+
+    // create a basis of the plane
+    let normal = normal.normalized();
+    let reference = if normal[0].abs() < 0.9 {
+        Vector3D::new(1.0, 0.0, 0.0)
+    } else {
+        Vector3D::new(0.0, 1.0, 0.0)
+    };
+    let u = (normal ^ reference).normalized();
+    let v = normal ^ u;
+
+    let angle = |point: &Vector3D| {
+        let delta = *point - center;
+        f64::atan2(delta * v, delta * u)
+    };
+
+    points.sort_by(|a, b| angle(a).partial_cmp(&angle(b)).expect("got NaN angle"));
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use crate::Matrix3;
+
+    use super::*;
+
+    fn fcc_conventional(a: f64) -> (Vec<Vector3D>, UnitCell) {
+        let cell = UnitCell::cubic(a);
+        let positions = vec![
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.0, a / 2.0, a / 2.0),
+            Vector3D::new(a / 2.0, 0.0, a / 2.0),
+            Vector3D::new(a / 2.0, a / 2.0, 0.0),
+        ];
+        return (positions, cell);
+    }
+
+    #[test]
+    fn fcc() {
+        let a = 3.6;
+        let (positions, cell) = fcc_conventional(a);
+        let voronoi = VoronoiNeighbors::new(&positions, cell);
+
+        // 12 neighbors for 4 atoms, with each pair counted once
+        assert_eq!(voronoi.pairs.len(), 4 * 12 / 2);
+        for pairs in &voronoi.pairs_by_center {
+            assert_eq!(pairs.len(), 12);
+        }
+
+        // the Voronoi cells are rhombic dodecahedra
+        for (pair, &area) in voronoi.pairs.iter().zip(&voronoi.face_areas) {
+            assert_relative_eq!(pair.distance, a / f64::sqrt(2.0), max_relative=1e-12);
+            assert_relative_eq!(area, a * a / (4.0 * f64::sqrt(2.0)), max_relative=1e-10);
+        }
+
+        // FCC primitive cell, all the neighbors are periodic images of the
+        // same atom
+        let primitive = UnitCell::from(Matrix3::new([
+            [0.0, a / 2.0, a / 2.0],
+            [a / 2.0, 0.0, a / 2.0],
+            [a / 2.0, a / 2.0, 0.0],
+        ]));
+        let voronoi = VoronoiNeighbors::new(&[Vector3D::new(0.3, 0.2, 0.1)], primitive);
+        assert_eq!(voronoi.pairs.len(), 6);
+        assert_eq!(voronoi.pairs_by_center[0].len(), 12);
+        for pair in &voronoi.pairs {
+            assert_eq!(pair.first, 0);
+            assert_eq!(pair.second, 0);
+            assert_relative_eq!(pair.distance, a / f64::sqrt(2.0), max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn simple_cubic() {
+        let voronoi = VoronoiNeighbors::new(&[Vector3D::new(0.0, 0.0, 0.0)], UnitCell::cubic(2.5));
+        assert_eq!(voronoi.pairs.len(), 3);
+        assert_eq!(voronoi.pairs_by_center[0].len(), 6);
+        for &area in &voronoi.face_areas {
+            assert_relative_eq!(area, 2.5 * 2.5, max_relative=1e-10);
+        }
+    }
+
+    #[test]
+    fn total_volume() {
+        let cell = UnitCell::from(Matrix3::new([
+            [6.0, 0.0, 0.0],
+            [1.3, 5.5, 0.0],
+            [-0.8, 0.6, 5.8],
+        ]));
+        let positions = [
+            Vector3D::new(0.1, 0.2, 0.3),
+            Vector3D::new(2.9, 1.1, 0.4),
+            Vector3D::new(1.7, 3.8, 2.2),
+            Vector3D::new(4.4, 4.1, 4.9),
+            Vector3D::new(0.6, 2.5, 3.9),
+            Vector3D::new(3.8, 0.3, 2.7),
+        ];
+
+        let voronoi = VoronoiNeighbors::new(&positions, cell);
+        for pairs in &voronoi.pairs_by_center {
+            assert!(pairs.len() >= 4);
+        }
+
+        // each face is the base of two pyramids, one in each Voronoi cell,
+        // with a height of `distance / 2`
+        let volume = voronoi.pairs.iter().zip(&voronoi.face_areas)
+            .map(|(pair, area)| area * pair.distance / 3.0)
+            .sum::<f64>();
+        assert_relative_eq!(volume, cell.volume(), max_relative=1e-10);
+    }
+
+    #[test]
+    fn molecule() {
+        // linear molecule, the middle atom is the neighbor of both ends
+        let positions = [
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(1.0, 0.0, 0.0),
+            Vector3D::new(2.0, 0.0, 0.0),
+        ];
+
+        let voronoi = VoronoiNeighbors::new(&positions, UnitCell::infinite());
+        assert_eq!(voronoi.pairs.len(), 2);
+        assert_eq!((voronoi.pairs[0].first, voronoi.pairs[0].second), (0, 1));
+        assert_eq!((voronoi.pairs[1].first, voronoi.pairs[1].second), (1, 2));
+    }
+}
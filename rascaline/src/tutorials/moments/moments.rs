@@ -25,6 +25,7 @@ impl CalculatorBase for GeometricMoments {
         let builder = CenterSingleNeighborsSpeciesKeys {
             cutoff: self.cutoff,
             self_pairs: false,
+            all_species: None,
         };
         return builder.keys(systems);
     }
@@ -43,6 +44,8 @@ impl CalculatorBase for GeometricMoments {
                 species_center: SpeciesFilter::Single(species_center.i32()),
                 species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                 self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             samples.push(builder.samples(systems)?);
@@ -69,6 +72,8 @@ impl CalculatorBase for GeometricMoments {
                 species_center: SpeciesFilter::Single(center_species.i32()),
                 species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                 self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             gradient_samples.push(builder.gradients_for(systems, samples_for_key)?);
@@ -34,6 +34,7 @@ impl CalculatorBase for GeometricMoments {
             // self pairs would have a distance of 0 and would not contribute
             // anything meaningful to a GeometricMoments representation
             self_pairs: false,
+            all_species: None,
         };
         return builder.keys(systems);
     }
@@ -57,6 +58,8 @@ impl CalculatorBase for GeometricMoments {
                 // defined by the spherical `cutoff`.
                 species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                 self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             samples.push(builder.samples(systems)?);
@@ -90,6 +93,8 @@ impl CalculatorBase for GeometricMoments {
                 // gradients in the current block).
                 species_neighbor: SpeciesFilter::Single(species_neighbor.i32()),
                 self_pairs: false,
+                selected_centers: None,
+                inner_cutoff: None,
             };
 
             gradient_samples.push(builder.gradients_for(systems, samples_for_key)?);
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use ndarray::{Axis, ArrayD, IxDyn};
+use equistore::{TensorMap, TensorBlock, TensorBlockRef, LabelsBuilder, LabelValue};
+
+use crate::Error;
+use crate::calculator::ALL_GRADIENT_PARAMETERS;
+
+/// How to combine the per-center samples of a descriptor into a single sample
+/// per structure, see [`aggregate_centers`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Sum the features of all centers in a structure
+    Sum,
+    /// Average the features of all centers in a structure
+    Mean,
+}
+
+/// Combine all the samples with the same `structure` in each block of the
+/// `descriptor`, producing a global descriptor with one sample per structure.
+///
+/// All the variables other than `structure` in the samples (typically
+/// `center`) are removed. The aggregation is done separately for each block:
+/// with [`Aggregation::Mean`], the features are divided by the number of
+/// samples for the structure in the corresponding block (e.g. the number of
+/// centers of a given species for SOAP descriptors).
+///
+/// Gradients are aggregated in the same way as the values: the gradient of
+/// the new sample with respect to a given atom (or the cell) is the sum (or
+/// the mean) of the gradients of the original samples with respect to the
+/// same atom. All gradient samples are kept, so forces acting on each atom
+/// can still be recovered from the aggregated descriptor.
+pub fn aggregate_centers(descriptor: &TensorMap, aggregation: Aggregation) -> Result<TensorMap, Error> {
+    let mut blocks = Vec::new();
+    for (_, block) in descriptor.iter() {
+        blocks.push(aggregate_block(&block, aggregation)?);
+    }
+
+    return Ok(TensorMap::new(descriptor.keys().clone(), blocks)?);
+}
+
+fn aggregate_block(block: &TensorBlockRef, aggregation: Aggregation) -> Result<TensorBlock, Error> {
+    let samples = block.samples();
+    let names = samples.names();
+    let structure = names.iter().position(|&name| name == "structure").ok_or_else(|| Error::InvalidParameter(format!(
+        "expected 'structure' in the samples names, got [{}]", names.join(", ")
+    )))?;
+
+    // position of the new sample for each old sample
+    let mut new_samples = LabelsBuilder::new(vec!["structure"]);
+    let mut structures = HashMap::new();
+    let mut counts = Vec::new();
+    let mut mapping = Vec::with_capacity(samples.count());
+    for sample in samples.iter() {
+        let new_sample_i = *structures.entry(sample[structure]).or_insert_with(|| {
+            new_samples.add(&[sample[structure]]);
+            counts.push(0);
+            counts.len() - 1
+        });
+        counts[new_sample_i] += 1;
+        mapping.push(new_sample_i);
+    }
+    let new_samples = new_samples.finish();
+
+    let factors = counts.iter().map(|&count| match aggregation {
+        Aggregation::Sum => 1.0,
+        Aggregation::Mean => 1.0 / count as f64,
+    }).collect::<Vec<_>>();
+
+    let values = block.values().to_array();
+    let new_values = accumulate(values, new_samples.count(), |sample_i| mapping[sample_i], |new_sample_i| factors[new_sample_i]);
+
+    let mut new_block = TensorBlock::new(
+        new_values,
+        &new_samples,
+        &block.components(),
+        &block.properties(),
+    )?;
+
+    for parameter in ALL_GRADIENT_PARAMETERS {
+        let gradient = match block.gradient(parameter) {
+            Some(gradient) => gradient,
+            None => continue,
+        };
+
+        // the first variable of the gradient samples is the index of the
+        // corresponding sample in the values, the other variables are kept
+        let gradient_samples = gradient.samples();
+        let mut new_gradient_samples = LabelsBuilder::new(gradient_samples.names());
+        let mut new_gradient_positions = HashMap::new();
+        let mut gradient_mapping = Vec::with_capacity(gradient_samples.count());
+        let mut gradient_factors = Vec::new();
+        for gradient_sample in gradient_samples.iter() {
+            let new_sample_i = mapping[gradient_sample[0].usize()];
+            let mut new_gradient_sample = gradient_sample.to_vec();
+            new_gradient_sample[0] = LabelValue::from(new_sample_i);
+
+            let new_gradient_sample_i = *new_gradient_positions.entry(new_gradient_sample.clone()).or_insert_with(|| {
+                new_gradient_samples.add(&new_gradient_sample);
+                gradient_factors.push(factors[new_sample_i]);
+                gradient_factors.len() - 1
+            });
+            gradient_mapping.push(new_gradient_sample_i);
+        }
+        let new_gradient_samples = new_gradient_samples.finish();
+
+        let new_gradient_values = accumulate(
+            gradient.values().to_array(),
+            new_gradient_samples.count(),
+            |sample_i| gradient_mapping[sample_i],
+            |new_sample_i| gradient_factors[new_sample_i],
+        );
+
+        new_block.add_gradient(parameter, TensorBlock::new(
+            new_gradient_values,
+            &new_gradient_samples,
+            &gradient.components(),
+            &gradient.properties(),
+        )?)?;
+    }
+
+    return Ok(new_block);
+}
+
+/// Sum the rows of `values` into a new array with `n_samples` rows, using
+/// `mapping` to get the new row of each old row, and multiply each new row
+/// by the corresponding `factor`.
+fn accumulate(
+    values: &ArrayD<f64>,
+    n_samples: usize,
+    mapping: impl Fn(usize) -> usize,
+    factor: impl Fn(usize) -> f64,
+) -> ArrayD<f64> {
+    let mut shape = values.shape().to_vec();
+    shape[0] = n_samples;
+
+    let mut new_values = ArrayD::zeros(IxDyn(&shape));
+    for (sample_i, row) in values.axis_iter(Axis(0)).enumerate() {
+        new_values.index_axis_mut(Axis(0), mapping(sample_i)).scaled_add(1.0, &row);
+    }
+
+    for (new_sample_i, mut row) in new_values.axis_iter_mut(Axis(0)).enumerate() {
+        row *= factor(new_sample_i);
+    }
+
+    return new_values;
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::Axis;
+
+    use crate::systems::test_utils::test_systems;
+    use crate::CalculationOptions;
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::{aggregate_centers, Aggregation};
+
+    #[test]
+    fn sum_and_mean() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+        let descriptor = calculator.compute(&mut test_systems(&["water", "methane"]), options).unwrap();
+
+        let sum = aggregate_centers(&descriptor, Aggregation::Sum).unwrap();
+        let mean = aggregate_centers(&descriptor, Aggregation::Mean).unwrap();
+        assert_eq!(sum.keys(), descriptor.keys());
+
+        for ((_, block), ((_, sum), (_, mean))) in descriptor.iter().zip(sum.iter().zip(mean.iter())) {
+            assert_eq!(sum.samples().names(), ["structure"]);
+            assert_eq!(sum.properties(), block.properties());
+
+            let values = block.values().to_array();
+            let sum_values = sum.values().to_array();
+            let mean_values = mean.values().to_array();
+            for (new_sample_i, new_sample) in sum.samples().iter().enumerate() {
+                let mut expected = ndarray::Array::zeros(values.index_axis(Axis(0), 0).raw_dim());
+                let mut count = 0;
+                for (sample, row) in block.samples().iter().zip(values.axis_iter(Axis(0))) {
+                    if sample[0] == new_sample[0] {
+                        expected += &row;
+                        count += 1;
+                    }
+                }
+
+                assert_relative_eq!(sum_values.index_axis(Axis(0), new_sample_i), expected, max_relative=1e-12);
+                assert_relative_eq!(
+                    mean_values.index_axis(Axis(0), new_sample_i),
+                    expected / count as f64,
+                    max_relative=1e-12,
+                );
+            }
+
+            // the gradients w.r.t. each atom are summed over all centers
+            let gradient = block.gradient("positions").unwrap();
+            let sum_gradient = sum.gradient("positions").unwrap();
+            assert_eq!(sum_gradient.samples().names(), ["sample", "structure", "atom"]);
+
+            let gradient_values = gradient.values().to_array();
+            let sum_gradient_values = sum_gradient.values().to_array();
+            for (new_gradient_i, new_gradient_sample) in sum_gradient.samples().iter().enumerate() {
+                let mut expected = ndarray::Array::zeros(gradient_values.index_axis(Axis(0), 0).raw_dim());
+                for (gradient_sample, row) in gradient.samples().iter().zip(gradient_values.axis_iter(Axis(0))) {
+                    let structure = block.samples()[gradient_sample[0].usize()][0];
+                    if structure == new_gradient_sample[1] && gradient_sample[2] == new_gradient_sample[2] {
+                        expected += &row;
+                    }
+                }
+
+                assert_relative_eq!(
+                    sum_gradient_values.index_axis(Axis(0), new_gradient_i),
+                    expected,
+                    max_relative=1e-12,
+                );
+            }
+
+            // one cell gradient sample per structure
+            let sum_gradient = sum.gradient("cell").unwrap();
+            assert_eq!(sum_gradient.samples().count(), sum.samples().count());
+        }
+    }
+
+    #[test]
+    fn calculation_option() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let mut systems = test_systems(&["water", "methane"]);
+        let descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        let expected = aggregate_centers(&descriptor, Aggregation::Mean).unwrap();
+
+        let options = CalculationOptions {
+            aggregate: Some(Aggregation::Mean),
+            ..Default::default()
+        };
+        let aggregated = calculator.compute(&mut systems, options).unwrap();
+
+        assert_eq!(aggregated.keys(), expected.keys());
+        for ((_, block), (_, expected)) in aggregated.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_relative_eq!(block.values().to_array(), expected.values().to_array(), max_relative=1e-12);
+        }
+    }
+}
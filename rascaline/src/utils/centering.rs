@@ -0,0 +1,159 @@
+use ndarray::{ArrayD, Axis};
+use equistore::{TensorMap, TensorBlock, Labels};
+
+use crate::Error;
+
+/// Compute the mean of the features over all samples, for each block in
+/// `tensor`.
+///
+/// The returned `TensorMap` has the same keys as `tensor`, and each block
+/// contains a single sample with the mean of all the samples in the
+/// corresponding block of `tensor`. Components and properties are kept as-is.
+/// Blocks without any sample have a mean of zero.
+pub fn mean_features(tensor: &TensorMap) -> TensorMap {
+    let mut blocks = Vec::new();
+    for (_, block) in tensor.iter() {
+        let block = block.data();
+        let values = block.values.as_array();
+
+        let mean = match values.mean_axis(Axis(0)) {
+            Some(mean) => mean.insert_axis(Axis(0)),
+            None => {
+                let mut shape = values.shape().to_vec();
+                shape[0] = 1;
+                ArrayD::from_elem(shape, 0.0)
+            }
+        };
+
+        blocks.push(TensorBlock::new(
+            mean,
+            &Labels::single(),
+            &block.components,
+            &block.properties,
+        ).expect("invalid TensorBlock"));
+    }
+
+    return TensorMap::new(tensor.keys().clone(), blocks).expect("invalid TensorMap");
+}
+
+/// Center the features in `tensor` by subtracting the per-property `mean`,
+/// typically computed with [`mean_features`] over a full dataset.
+///
+/// Every block in `tensor` must have a corresponding block in `mean` with the
+/// same components and properties. Since the mean is a constant, gradients
+/// are left unchanged.
+pub fn center_features(tensor: &mut TensorMap, mean: &TensorMap) -> Result<(), Error> {
+    if tensor.keys().names() != mean.keys().names() {
+        return Err(Error::InvalidParameter(format!(
+            "the keys names of the mean ({}) do not match the keys names of the tensor ({})",
+            mean.keys().names().join(", "), tensor.keys().names().join(", ")
+        )));
+    }
+
+    for (key, mut block) in tensor.iter_mut() {
+        let mean_block = if let Some(block_i) = mean.keys().position(key) {
+            mean.block_by_id(block_i)
+        } else {
+            return Err(Error::InvalidParameter(format!(
+                "missing block for key {:?} in the mean", key
+            )));
+        };
+        let mean_block = mean_block.data();
+
+        let block = block.data_mut();
+        if *block.properties != *mean_block.properties || *block.components != *mean_block.components {
+            return Err(Error::InvalidParameter(format!(
+                "the properties or components of the mean for key {:?} do not match the ones in the tensor",
+                key
+            )));
+        }
+
+        let mean_values = mean_block.values.as_array();
+        if mean_values.shape()[0] != 1 {
+            return Err(Error::InvalidParameter(format!(
+                "expected a single sample in the mean for key {:?}, got {}",
+                key, mean_values.shape()[0]
+            )));
+        }
+
+        let values = block.values.to_array_mut();
+        *values -= mean_values;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::{Axis, array};
+
+    use equistore::{Labels, TensorBlock, TensorMap};
+
+    use super::{mean_features, center_features};
+
+    fn tensor() -> TensorMap {
+        let first = TensorBlock::new(
+            array![[1.0, 2.0], [3.0, -4.0], [2.0, 8.0]].into_dyn(),
+            &Labels::new(["structure", "center"], &[[0, 0], [0, 1], [1, 0]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1]]),
+        ).unwrap();
+
+        let second = TensorBlock::new(
+            array![[[0.5], [1.5], [2.5]], [[-1.0], [4.0], [0.0]]].into_dyn(),
+            &Labels::new(["structure", "center"], &[[0, 2], [1, 1]]),
+            &[Labels::new(["spherical_harmonics_m"], &[[-1], [0], [1]])],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap();
+
+        let keys = Labels::new(["species_center"], &[[1], [6]]);
+        return TensorMap::new(keys, vec![first, second]).unwrap();
+    }
+
+    #[test]
+    fn mean() {
+        let mean = mean_features(&tensor());
+        assert_eq!(mean.keys().count(), 2);
+
+        let block = mean.block_by_id(0);
+        assert_eq!(block.samples(), Labels::single());
+        assert_relative_eq!(block.values().to_array(), &array![[2.0, 2.0]].into_dyn());
+
+        let block = mean.block_by_id(1);
+        assert_relative_eq!(block.values().to_array(), &array![[[-0.25], [2.75], [1.25]]].into_dyn());
+    }
+
+    #[test]
+    fn centering() {
+        let mut tensor = tensor();
+        let mean = mean_features(&tensor);
+        center_features(&mut tensor, &mean).unwrap();
+
+        for (_, block) in tensor.iter() {
+            let values = block.values().to_array();
+            let mean = values.mean_axis(Axis(0)).unwrap();
+            assert_relative_eq!(mean, ndarray::ArrayD::from_elem(mean.shape(), 0.0), epsilon=1e-14);
+        }
+    }
+
+    #[test]
+    fn mismatched_properties() {
+        let mut tensor = tensor();
+        let block = TensorBlock::new(
+            array![[1.0]].into_dyn(),
+            &Labels::single(),
+            &[],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap();
+        let empty = TensorBlock::new(
+            array![[[1.0], [1.0], [1.0]]].into_dyn(),
+            &Labels::single(),
+            &[Labels::new(["spherical_harmonics_m"], &[[-1], [0], [1]])],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap();
+        let mean = TensorMap::new(tensor.keys().clone(), vec![block, empty]).unwrap();
+
+        assert!(center_features(&mut tensor, &mean).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use ndarray::{Array2, Axis};
+use equistore::{TensorMap, TensorBlockRef, Labels, LabelsBuilder, LabelValue};
+
+use crate::Error;
+
+/// Convert the `descriptor` to a single dense `(samples, features)` matrix.
+///
+/// All the keys are moved to the features dimension, together with the
+/// components and properties of each block. The samples of the matrix are the
+/// union of the samples of all blocks, in order of first appearance; and the
+/// entries corresponding to samples missing from some blocks are filled with
+/// zeros. Gradients are ignored.
+///
+/// This function returns the dense matrix, the labels associated with the
+/// rows (with the same names as the blocks samples) and the labels associated
+/// with the columns, with the keys names followed by the components names and
+/// the properties names.
+///
+/// Keys which should not become features (for example `species_center` for
+/// SOAP) should be moved to the samples with `TensorMap::keys_to_samples`
+/// before calling this function.
+pub fn to_dense_array(descriptor: &TensorMap) -> Result<(Array2<f64>, Labels, Labels), Error> {
+    if descriptor.keys().count() == 0 {
+        return Err(Error::InvalidParameter(
+            "can not convert a descriptor without any block to a dense array".into()
+        ));
+    }
+
+    let first = descriptor.block_by_id(0);
+    let first_samples = first.samples();
+    let samples_names = first_samples.names();
+    let features_names = features_names(descriptor.keys(), &first);
+
+    let mut samples = LabelsBuilder::new(samples_names.clone());
+    let mut samples_positions = HashMap::new();
+    let mut features = LabelsBuilder::new(features_names.iter().map(|name| &**name).collect());
+    for (key, block) in descriptor.iter() {
+        if block.samples().names() != samples_names || features_names(descriptor.keys(), &block) != features_names {
+            return Err(Error::InvalidParameter(format!(
+                "can not convert to a dense array: the block for key [{}] has \
+                different samples, components or properties names than the first block",
+                key.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+            )));
+        }
+
+        for sample in block.samples().iter() {
+            let sample = sample.to_vec();
+            if !samples_positions.contains_key(&sample) {
+                samples_positions.insert(sample.clone(), samples_positions.len());
+                samples.add(&sample);
+            }
+        }
+
+        for feature in block_features(key, &block.components(), &block.properties()) {
+            features.add(&feature);
+        }
+    }
+
+    let samples = samples.finish();
+    let features = features.finish();
+
+    let mut array = Array2::zeros((samples.count(), features.count()));
+    let mut first_feature = 0;
+    for (_, block) in descriptor.iter() {
+        let block_samples = block.samples();
+        let values = block.values().to_array();
+        let n_samples = values.shape()[0];
+        let n_features = values.len() / usize::max(n_samples, 1);
+        let values = values.view().into_shape((n_samples, n_features)).expect("non contiguous values array");
+
+        for (sample, row) in block_samples.iter().zip(values.axis_iter(Axis(0))) {
+            let sample_i = samples_positions[sample];
+            array.index_axis_mut(Axis(0), sample_i)
+                .slice_mut(ndarray::s![first_feature..first_feature + n_features])
+                .assign(&row);
+        }
+
+        first_feature += n_features;
+    }
+
+    return Ok((array, samples, features));
+}
+
+/// Get the names of the features of a `block`: the `keys` names followed by
+/// the components and properties names.
+fn features_names(keys: &Labels, block: &TensorBlockRef) -> Vec<String> {
+    let mut names = keys.names().iter().map(|&name| name.to_owned()).collect::<Vec<_>>();
+    for component in block.components() {
+        names.extend(component.names().iter().map(|&name| name.to_owned()));
+    }
+    names.extend(block.properties().names().iter().map(|&name| name.to_owned()));
+    return names;
+}
+
+/// Get all the features of a block, i.e. the combination of the `key` with
+/// all the `components` and `properties` entries, in the same order as the
+/// values of the block.
+fn block_features(key: &[LabelValue], components: &[Labels], properties: &Labels) -> Vec<Vec<LabelValue>> {
+    let mut features = vec![key.to_vec()];
+    for labels in components.iter().chain(std::iter::once(properties)) {
+        let mut new_features = Vec::with_capacity(features.len() * labels.count());
+        for feature in &features {
+            for entry in labels.iter() {
+                let mut feature = feature.clone();
+                feature.extend_from_slice(entry);
+                new_features.push(feature);
+            }
+        }
+        features = new_features;
+    }
+
+    return features;
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Axis;
+
+    use crate::systems::test_utils::test_systems;
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::to_dense_array;
+
+    #[test]
+    fn dense_power_spectrum() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+
+        let descriptor = calculator.compute(&mut test_systems(&["water", "CH"]), Default::default()).unwrap();
+        let (array, samples, features) = to_dense_array(&descriptor).unwrap();
+
+        assert_eq!(samples.names(), ["structure", "center"]);
+        assert_eq!(samples.count(), 5);
+        assert_eq!(features.names(), ["species_center", "species_neighbor_1", "species_neighbor_2", "l", "n1", "n2"]);
+        assert_eq!(features.count(), descriptor.keys().count() * 4 * 4 * 4);
+        assert_eq!(array.shape(), [samples.count(), features.count()]);
+
+        for (key, block) in descriptor.iter() {
+            let values = block.values().to_array();
+            for (sample, row) in block.samples().iter().zip(values.axis_iter(Axis(0))) {
+                let sample_i = samples.position(sample).unwrap();
+                for (property, &value) in block.properties().iter().zip(row) {
+                    let mut feature = key.to_vec();
+                    feature.extend_from_slice(property);
+                    let feature_i = features.position(&feature).unwrap();
+                    assert_eq!(array[[sample_i, feature_i]], value);
+                }
+            }
+        }
+
+        // samples are zero for the features of other center species
+        for (sample_i, sample) in samples.iter().enumerate() {
+            let center_species = if sample[0].i32() == 0 {
+                if sample[1].i32() == 0 { -42 } else { 1 }
+            } else if sample[1].i32() == 0 { 6 } else { 1 };
+
+            for (feature_i, feature) in features.iter().enumerate() {
+                if feature[0].i32() != center_species {
+                    assert_eq!(array[[sample_i, feature_i]], 0.0);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use ndarray::Axis;
+use equistore::TensorMap;
+
+use crate::{Error, System};
+
+/// Transform the gradients with respect to atomic positions in `descriptor`
+/// (computed for the given `systems`) from Cartesian to fractional
+/// coordinates.
+///
+/// With `H` the cell matrix (containing one cell vector per row), Cartesian
+/// positions are related to fractional positions by `r = Hᵀ s`. The Jacobian
+/// of this transformation is `∂r_α / ∂s_k = H_kα`, and the gradients with
+/// respect to fractional positions are then
+///
+/// `∂X / ∂s_k = Σ_α H_kα ∂X / ∂r_α`
+///
+/// This function returns an error if any of the systems does not have a
+/// periodic cell. Blocks without gradients with respect to positions are left
+/// untouched.
+pub fn positions_gradients_to_fractional(descriptor: &mut TensorMap, systems: &mut [Box<dyn System>]) -> Result<(), Error> {
+    let mut cells = Vec::with_capacity(systems.len());
+    for system in systems.iter() {
+        let cell = system.cell()?;
+        if cell.is_infinite() {
+            return Err(Error::InvalidParameter(
+                "can not compute fractional gradients for non periodic systems".into()
+            ));
+        }
+        cells.push(cell.matrix());
+    }
+
+    for (_, mut block) in descriptor.iter_mut() {
+        if let Some(mut gradient) = block.gradient_mut("positions") {
+            let gradient = gradient.data_mut();
+            let array = gradient.values.to_array_mut();
+
+            for (grad_sample_i, &[_, structure, _]) in gradient.samples.iter_fixed_size().enumerate() {
+                let cell = cells[structure.usize()];
+
+                let mut sample_gradient = array.index_axis_mut(Axis(0), grad_sample_i);
+                let cartesian = sample_gradient.to_owned();
+                for k in 0..3 {
+                    let mut fractional = sample_gradient.index_axis_mut(Axis(0), k);
+                    fractional.fill(0.0);
+                    for alpha in 0..3 {
+                        fractional.scaled_add(cell[k][alpha], &cartesian.index_axis(Axis(0), alpha));
+                    }
+                }
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::{assert_relative_eq, assert_ulps_eq};
+    use ndarray::Axis;
+
+    use crate::systems::{SimpleSystem, UnitCell};
+    use crate::{CalculationOptions, System, Vector3D};
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::positions_gradients_to_fractional;
+
+    fn cell() -> UnitCell {
+        UnitCell::triclinic(5.0, 5.5, 6.0, 80.0, 95.0, 105.0)
+    }
+
+    fn fractional_positions() -> Vec<(i32, Vector3D)> {
+        vec![
+            (8, Vector3D::new(0.1, 0.2, 0.3)),
+            (1, Vector3D::new(0.25, 0.15, 0.35)),
+            (1, Vector3D::new(0.05, 0.32, 0.28)),
+        ]
+    }
+
+    fn system(positions: &[(i32, Vector3D)]) -> SimpleSystem {
+        let mut system = SimpleSystem::new(cell());
+        for &(species, fractional) in positions {
+            system.add_atom_fractional(species, fractional).unwrap();
+        }
+        return system;
+    }
+
+    #[test]
+    fn same_values() {
+        let mut cartesian = SimpleSystem::new(cell());
+        for (species, fractional) in fractional_positions() {
+            cartesian.add_atom(species, cell().cartesian(fractional));
+        }
+
+        let mut calculator = soap_calculator("spherical_expansion");
+        let expected = calculator.compute(&mut [Box::new(cartesian) as Box<dyn System>], Default::default()).unwrap();
+
+        let fractional = system(&fractional_positions());
+        let descriptor = calculator.compute(&mut [Box::new(fractional) as Box<dyn System>], Default::default()).unwrap();
+
+        assert_eq!(descriptor.keys(), expected.keys());
+        for ((_, block), (_, expected)) in descriptor.iter().zip(expected.iter()) {
+            assert_eq!(block.samples(), expected.samples());
+            assert_ulps_eq!(block.values().to_array(), expected.values().to_array());
+        }
+    }
+
+    #[test]
+    fn finite_differences() {
+        let mut calculator = soap_calculator("spherical_expansion");
+        let positions = fractional_positions();
+
+        let mut systems = vec![Box::new(system(&positions)) as Box<dyn System>];
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let mut reference = calculator.compute(&mut systems, options).unwrap();
+        positions_gradients_to_fractional(&mut reference, &mut systems).unwrap();
+
+        let displacement = 1e-6;
+        for atom_i in 0..positions.len() {
+            for k in 0..3 {
+                let mut positions_pos = positions.clone();
+                positions_pos[atom_i].1[k] += displacement / 2.0;
+                let updated_pos = calculator.compute(
+                    &mut [Box::new(system(&positions_pos)) as Box<dyn System>], Default::default()
+                ).unwrap();
+
+                let mut positions_neg = positions.clone();
+                positions_neg[atom_i].1[k] -= displacement / 2.0;
+                let updated_neg = calculator.compute(
+                    &mut [Box::new(system(&positions_neg)) as Box<dyn System>], Default::default()
+                ).unwrap();
+
+                for (block_i, (_, block)) in reference.iter().enumerate() {
+                    let gradients = block.gradient("positions").unwrap();
+                    let block_pos = updated_pos.block_by_id(block_i);
+                    let block_neg = updated_neg.block_by_id(block_i);
+
+                    for (gradient_i, [sample_i, _, atom]) in gradients.samples().iter_fixed_size().enumerate() {
+                        if atom.usize() != atom_i {
+                            continue;
+                        }
+                        let sample_i = sample_i.usize();
+
+                        let value_pos = block_pos.values().to_array().index_axis(Axis(0), sample_i).to_owned();
+                        let value_neg = block_neg.values().to_array().index_axis(Axis(0), sample_i).to_owned();
+                        let finite_difference = (value_pos - value_neg) / displacement;
+
+                        let gradient = gradients.values().to_array().index_axis(Axis(0), gradient_i).to_owned();
+                        let gradient = gradient.index_axis(Axis(0), k);
+
+                        assert_relative_eq!(finite_difference, gradient, epsilon=1e-9, max_relative=1e-5);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use ndarray::{Array2, Axis};
+use equistore::{TensorMap, LabelValue};
+
+/// Compute the covariance between properties for each block of `tensor`.
+///
+/// The values in each block are flattened to a `(n_rows, n_properties)`
+/// matrix, where every sample (and every entry in the components, if the block
+/// has any) is considered as a separate observation. The covariance is then
+/// computed as `(X - X̄)ᵀ (X - X̄) / (n_rows - 1)`, where `X̄` is the mean of the
+/// properties over all rows. Blocks with less than two rows get a covariance
+/// filled with zeros.
+///
+/// The returned map associates the values of each key to the corresponding
+/// `n_properties x n_properties` covariance matrix.
+pub fn property_gram(tensor: &TensorMap) -> HashMap<Vec<LabelValue>, Array2<f64>> {
+    let mut result = HashMap::new();
+    for (key, block) in tensor.iter() {
+        let values = block.values().to_array();
+
+        let n_properties = values.shape()[values.ndim() - 1];
+        let n_rows = if n_properties == 0 {
+            0
+        } else {
+            values.len() / n_properties
+        };
+
+        let values = values.to_shape((n_rows, n_properties)).expect("failed to reshape values");
+
+        let mut gram = Array2::from_elem((n_properties, n_properties), 0.0);
+        if n_rows > 1 {
+            let mean = values.mean_axis(Axis(0)).expect("empty array");
+            let centered = &values - &mean;
+
+            gram = centered.t().dot(&centered);
+            gram /= (n_rows - 1) as f64;
+        }
+
+        result.insert(key.to_vec(), gram);
+    }
+
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::{ArrayD, array};
+
+    use equistore::{Labels, TensorBlock, TensorMap, LabelValue};
+
+    use super::property_gram;
+
+    #[test]
+    fn covariance() {
+        let values = array![
+            [1.0, 2.0, -1.0],
+            [3.0, 0.5, 4.0],
+            [-2.0, 1.5, 0.0],
+            [0.0, 3.0, 2.5],
+        ];
+
+        let block = TensorBlock::new(
+            values.clone().into_dyn(),
+            &Labels::new(["structure", "center"], &[[0, 0], [0, 1], [0, 2], [1, 0]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1], [2]]),
+        ).unwrap();
+
+        let empty = TensorBlock::new(
+            ArrayD::from_elem(vec![1, 2], 3.0),
+            &Labels::new(["structure", "center"], &[[0, 3]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1]]),
+        ).unwrap();
+
+        let keys = Labels::new(["species_center"], &[[1], [6]]);
+        let tensor = TensorMap::new(keys, vec![block, empty]).unwrap();
+
+        let gram = property_gram(&tensor);
+        assert_eq!(gram.len(), 2);
+
+        let n_samples = values.nrows();
+        let mut expected = ndarray::Array2::from_elem((3, 3), 0.0);
+        for i in 0..3 {
+            for j in 0..3 {
+                let mean_i = values.column(i).sum() / n_samples as f64;
+                let mean_j = values.column(j).sum() / n_samples as f64;
+                for sample in 0..n_samples {
+                    expected[[i, j]] += (values[[sample, i]] - mean_i) * (values[[sample, j]] - mean_j);
+                }
+                expected[[i, j]] /= (n_samples - 1) as f64;
+            }
+        }
+
+        let actual = &gram[&vec![LabelValue::new(1)]];
+        assert_relative_eq!(actual, &expected, max_relative=1e-12);
+
+        // a single sample gives a zero covariance
+        let actual = &gram[&vec![LabelValue::new(6)]];
+        assert_eq!(actual, &ndarray::Array2::from_elem((2, 2), 0.0));
+    }
+}
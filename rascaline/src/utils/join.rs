@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+
+use ndarray::{Axis, ArrayD};
+use equistore::{TensorMap, TensorBlock, TensorBlockRef, Labels, LabelsBuilder, LabelValue};
+
+use crate::Error;
+use crate::calculator::ALL_GRADIENT_PARAMETERS;
+
+/// Join multiple `tensors` (for example computed on separate batches of
+/// systems) into a single `TensorMap`, by concatenating the blocks along the
+/// samples axis.
+///
+/// The keys of the result are the union of the keys of all `tensors`. Blocks
+/// with the same key must have the same components, properties and gradients
+/// in all the `tensors`.
+///
+/// If `structures_count` is given, it should contain the number of systems
+/// used to compute each of the `tensors`, and the `structure` samples (in both
+/// values and gradients) of each tensor are shifted by the number of systems
+/// used for all the previous tensors. This should be used when each tensor was
+/// computed on a separate list of systems, starting from structure 0. If
+/// `structures_count` is `None`, the samples are used as-is, which is
+/// appropriate when they already refer to a global structure index (e.g. with
+/// [`crate::Calculator::compute_streaming`]).
+///
+/// This function returns an error if the same sample appears in multiple
+/// tensors after the optional renumbering.
+pub fn join_descriptors(tensors: &[TensorMap], structures_count: Option<&[usize]>) -> Result<TensorMap, Error> {
+    if tensors.is_empty() {
+        return Err(Error::InvalidParameter("can not join an empty list of descriptors".into()));
+    }
+
+    let keys_names = tensors[0].keys().names();
+    for tensor in tensors {
+        if tensor.keys().names() != keys_names {
+            return Err(Error::InvalidParameter(format!(
+                "can not join descriptors with different keys names: [{}] and [{}]",
+                keys_names.join(", "), tensor.keys().names().join(", ")
+            )));
+        }
+    }
+
+    let offsets = if let Some(structures_count) = structures_count {
+        if structures_count.len() != tensors.len() {
+            return Err(Error::InvalidParameter(format!(
+                "expected {} entries in structures_count, got {}",
+                tensors.len(), structures_count.len()
+            )));
+        }
+
+        let mut offsets = Vec::with_capacity(tensors.len());
+        let mut n_structures = 0;
+        for (tensor, &count) in tensors.iter().zip(structures_count) {
+            check_structures(tensor, count)?;
+            offsets.push(n_structures);
+            n_structures += count;
+        }
+        offsets
+    } else {
+        vec![0; tensors.len()]
+    };
+
+    // keys of the result, in order of first appearance
+    let mut all_keys = LabelsBuilder::new(keys_names);
+    let mut seen_keys = HashSet::new();
+    for tensor in tensors {
+        for key in tensor.keys().iter() {
+            if seen_keys.insert(key.to_vec()) {
+                all_keys.add(key);
+            }
+        }
+    }
+    let all_keys = all_keys.finish();
+
+    let mut blocks = Vec::new();
+    for key in all_keys.iter() {
+        let mut parts = Vec::new();
+        for (tensor, &offset) in tensors.iter().zip(&offsets) {
+            if let Some(block_i) = tensor.keys().position(key) {
+                parts.push((tensor.block_by_id(block_i), offset));
+            }
+        }
+
+        blocks.push(join_blocks(&parts)?);
+    }
+
+    return Ok(TensorMap::new(all_keys, blocks)?);
+}
+
+/// Join the blocks in `parts` along the samples, shifting the `structure`
+/// samples of each block by the associated offset.
+fn join_blocks(parts: &[(TensorBlockRef, usize)]) -> Result<TensorBlock, Error> {
+    let (first, _) = &parts[0];
+    for (block, _) in parts {
+        check_same_metadata(first, block, "values")?;
+    }
+
+    let samples = join_samples(parts.iter().map(|(block, offset)| (block.samples(), *offset, None)))?;
+    let values = parts.iter().map(|(block, _)| block.values().to_array().view()).collect::<Vec<_>>();
+
+    let mut new_block = TensorBlock::new(
+        concatenate(&values),
+        &samples,
+        &first.components(),
+        &first.properties(),
+    )?;
+
+    for parameter in ALL_GRADIENT_PARAMETERS {
+        let first_gradient = first.gradient(parameter);
+        let mut gradients = Vec::new();
+        let mut samples_offset = 0;
+        for (block, offset) in parts {
+            match (&first_gradient, block.gradient(parameter)) {
+                (Some(first_gradient), Some(gradient)) => {
+                    check_same_metadata(first_gradient, &gradient, parameter)?;
+                    gradients.push((gradient, *offset, samples_offset));
+                }
+                (None, None) => {}
+                _ => {
+                    return Err(Error::InvalidParameter(format!(
+                        "can not join descriptors with and without {} gradients", parameter
+                    )));
+                }
+            }
+            samples_offset += block.samples().count();
+        }
+
+        if let Some(first_gradient) = first_gradient {
+            let gradient_samples = join_samples(gradients.iter().map(|(gradient, offset, samples_offset)| {
+                (gradient.samples(), *offset, Some(*samples_offset))
+            }))?;
+            let values = gradients.iter().map(|(gradient, _, _)| gradient.values().to_array().view()).collect::<Vec<_>>();
+
+            new_block.add_gradient(parameter, TensorBlock::new(
+                concatenate(&values),
+                &gradient_samples,
+                &first_gradient.components(),
+                &first_gradient.properties(),
+            )?)?;
+        }
+    }
+
+    return Ok(new_block);
+}
+
+/// Concatenate all the `samples`, shifting the `structure` variable by the
+/// given offset, and the `sample` variable (for gradients) by the given
+/// samples offset.
+fn join_samples(samples: impl Iterator<Item = (Labels, usize, Option<usize>)>) -> Result<Labels, Error> {
+    let mut builder = None;
+    let mut seen = HashSet::new();
+    for (samples, offset, samples_offset) in samples {
+        let names = samples.names();
+        let structure = names.iter().position(|&name| name == "structure");
+        let builder = builder.get_or_insert_with(|| LabelsBuilder::new(names.clone()));
+
+        for entry in samples.iter() {
+            let mut entry = entry.to_vec();
+            if let Some(structure) = structure {
+                entry[structure] = LabelValue::new(entry[structure].i32() + offset as i32);
+            }
+            if let Some(samples_offset) = samples_offset {
+                entry[0] = LabelValue::new(entry[0].i32() + samples_offset as i32);
+            }
+
+            if !seen.insert(entry.iter().map(|v| v.i32()).collect::<Vec<_>>()) {
+                return Err(Error::InvalidParameter(format!(
+                    "can not join descriptors: the sample [{}] is present multiple times",
+                    entry.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+                )));
+            }
+            builder.add(&entry);
+        }
+    }
+
+    let builder = builder.expect("there should be at least one set of samples");
+    return Ok(builder.finish());
+}
+
+/// Check that `block` has the same components and properties as `reference`
+fn check_same_metadata(reference: &TensorBlockRef, block: &TensorBlockRef, what: &str) -> Result<(), Error> {
+    if reference.samples().names() != block.samples().names() {
+        return Err(Error::InvalidParameter(format!(
+            "can not join descriptors with different samples names for {}", what
+        )));
+    }
+
+    if reference.components() != block.components() {
+        return Err(Error::InvalidParameter(format!(
+            "can not join descriptors with different components for {}", what
+        )));
+    }
+
+    if reference.properties() != block.properties() {
+        return Err(Error::InvalidParameter(format!(
+            "can not join descriptors with different properties for {}", what
+        )));
+    }
+
+    return Ok(());
+}
+
+/// Check that all the `structure` samples in `tensor` are smaller than the
+/// number of structures `count` used to compute it.
+fn check_structures(tensor: &TensorMap, count: usize) -> Result<(), Error> {
+    for (_, block) in tensor.iter() {
+        let samples = block.samples();
+        let names = samples.names();
+        let structure = names.iter().position(|&name| name == "structure").ok_or_else(|| Error::InvalidParameter(format!(
+            "expected 'structure' in the samples names, got [{}]", names.join(", ")
+        )))?;
+
+        for sample in samples.iter() {
+            if sample[structure].i32() < 0 || sample[structure].usize() >= count {
+                return Err(Error::InvalidParameter(format!(
+                    "can not join descriptors: structure {} is out of bounds for a descriptor computed on {} structures",
+                    sample[structure], count
+                )));
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+fn concatenate(arrays: &[ndarray::ArrayViewD<f64>]) -> ArrayD<f64> {
+    return ndarray::concatenate(Axis(0), arrays).expect("arrays should have compatible shapes");
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Axis;
+    use equistore::LabelValue;
+
+    use crate::systems::test_utils::test_systems;
+    use crate::{CalculationOptions, LabelsSelection};
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::join_descriptors;
+
+    #[test]
+    fn join() {
+        let mut calculator = soap_calculator("spherical_expansion");
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+
+        let expected = calculator.compute(&mut test_systems(&["water", "CH"]), options).unwrap();
+        let water = calculator.compute(&mut test_systems(&["water"]), options).unwrap();
+        let ch = calculator.compute(&mut test_systems(&["CH"]), options).unwrap();
+
+        let joined = join_descriptors(&[water.try_clone().unwrap(), ch.try_clone().unwrap()], Some(&[1, 1])).unwrap();
+        assert_eq!(joined.keys().count(), expected.keys().count());
+
+        for (key, block) in expected.iter() {
+            let joined = joined.block_by_id(joined.keys().position(key).unwrap());
+            assert_eq!(joined.samples().count(), block.samples().count());
+            assert_eq!(joined.properties(), block.properties());
+
+            let values = block.values().to_array();
+            let joined_values = joined.values().to_array();
+            for (sample_i, sample) in block.samples().iter().enumerate() {
+                let joined_i = joined.samples().position(sample).unwrap();
+                assert_eq!(
+                    joined_values.index_axis(Axis(0), joined_i),
+                    values.index_axis(Axis(0), sample_i),
+                );
+            }
+
+            for parameter in ["positions", "cell"] {
+                let gradient = block.gradient(parameter).unwrap();
+                let joined_gradient = joined.gradient(parameter).unwrap();
+                assert_eq!(joined_gradient.samples().count(), gradient.samples().count());
+
+                let values = gradient.values().to_array();
+                let joined_values = joined_gradient.values().to_array();
+                for (gradient_i, gradient_sample) in gradient.samples().iter().enumerate() {
+                    let sample = &block.samples()[gradient_sample[0].usize()];
+                    let joined_sample_i = joined.samples().position(sample).unwrap();
+
+                    let mut joined_sample = gradient_sample.to_vec();
+                    joined_sample[0] = LabelValue::from(joined_sample_i);
+                    let joined_i = joined_gradient.samples().position(&joined_sample).unwrap();
+
+                    assert_eq!(
+                        joined_values.index_axis(Axis(0), joined_i),
+                        values.index_axis(Axis(0), gradient_i),
+                    );
+                }
+            }
+        }
+
+        // without offsetting the structures, the samples are duplicated
+        let error = join_descriptors(&[water.try_clone().unwrap(), water], None).unwrap_err();
+        assert!(error.to_string().contains("is present multiple times"));
+    }
+
+    #[test]
+    fn join_trailing_empty_structures() {
+        let mut calculator = soap_calculator("spherical_expansion");
+
+        // only the first structure has samples in this descriptor
+        let samples = equistore::Labels::new(["structure"], &[[0]]);
+        let options = CalculationOptions {
+            selected_samples: LabelsSelection::Subset(&samples),
+            ..Default::default()
+        };
+        let first = calculator.compute(&mut test_systems(&["water", "CH"]), options).unwrap();
+        let second = calculator.compute(&mut test_systems(&["water"]), Default::default()).unwrap();
+
+        let joined = join_descriptors(&[first, second], Some(&[2, 1])).unwrap();
+        for (_, block) in joined.iter() {
+            for sample in block.samples().iter() {
+                assert_ne!(sample[0].i32(), 1);
+            }
+        }
+
+        let block = joined.block_by_id(0);
+        assert!(block.samples().iter().any(|sample| sample[0].i32() == 2));
+
+        let water = calculator.compute(&mut test_systems(&["water"]), Default::default()).unwrap();
+        let error = join_descriptors(&[water], Some(&[1, 1])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: expected 1 entries in structures_count, got 2"
+        );
+    }
+
+    #[test]
+    fn mismatched_properties() {
+        let mut calculator = soap_calculator("spherical_expansion");
+        let water = calculator.compute(&mut test_systems(&["water"]), Default::default()).unwrap();
+
+        let properties = equistore::Labels::new(["n"], &[[0], [2]]);
+        let options = CalculationOptions {
+            selected_properties: LabelsSelection::Subset(&properties),
+            ..Default::default()
+        };
+        let partial = calculator.compute(&mut test_systems(&["water"]), options).unwrap();
+
+        let error = join_descriptors(&[water, partial], Some(&[1, 1])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid parameter: can not join descriptors with different properties for values"
+        );
+    }
+}
@@ -0,0 +1,35 @@
+//! Helper functions to analyze and manipulate the descriptors produced by
+//! rascaline calculators.
+
+mod gram;
+pub use self::gram::property_gram;
+
+mod centering;
+pub use self::centering::{mean_features, center_features};
+
+mod fractional;
+pub use self::fractional::positions_gradients_to_fractional;
+
+mod samples;
+pub use self::samples::check_unique_samples;
+
+mod split;
+pub use self::split::split_by_structure;
+
+mod join;
+pub use self::join::join_descriptors;
+
+mod dense;
+pub use self::dense::to_dense_array;
+
+mod normalize;
+pub use self::normalize::normalize_samples;
+
+mod region;
+pub use self::region::centers_in_region;
+
+mod projection;
+pub use self::projection::project;
+
+mod aggregate;
+pub use self::aggregate::{Aggregation, aggregate_centers};
@@ -0,0 +1,338 @@
+use std::collections::{BTreeSet, HashMap};
+
+use ndarray::Axis;
+use equistore::{TensorMap, TensorBlock, LabelsBuilder, LabelValue};
+
+use crate::Error;
+use crate::calculator::ALL_GRADIENT_PARAMETERS;
+
+/// Normalize each sample of the `descriptor` to unit L2 norm, in-place.
+///
+/// The features of a given sample (e.g. a given center in a given structure)
+/// are usually split across multiple blocks, for example one block for each
+/// pair of neighbor species in the SOAP power spectrum. The norm is computed
+/// over the concatenation of all the features associated with a sample in all
+/// blocks, where samples in different blocks are identified by their
+/// labels. Samples with a norm of zero are left unchanged.
+///
+/// Gradients are updated consistently with the values: for a sample with
+/// features $x$ and norm $\|x\|$, the gradients of the normalized features
+/// are
+///
+/// $$ \frac{\partial}{\partial r} \frac{x}{\|x\|}
+///     = \frac{1}{\|x\|} \frac{\partial x}{\partial r}
+///     - \frac{x}{\|x\|^3} \left(x \cdot \frac{\partial x}{\partial r}\right) $$
+///
+/// Since the norm depends on the features in all blocks, the normalized
+/// features in one block can have non-zero gradients even where the block
+/// did not contain any gradient entry. The gradient samples of each block are
+/// extended to contain the gradient samples of all the blocks sharing the
+/// same values sample, and the new entries are filled accordingly.
+#[allow(clippy::float_cmp, clippy::too_many_lines)]
+pub fn normalize_samples(descriptor: &mut TensorMap) -> Result<(), Error> {
+    // squared norm of each sample, accumulated over all blocks
+    let mut squared_norms = HashMap::<Vec<i32>, f64>::new();
+    // dot product between the values and the gradients of each gradient
+    // sample, accumulated over all blocks. The key contains the values sample
+    // followed by the remaining entries of the gradient sample.
+    let mut gradients_dots = HashMap::<(&str, Vec<i32>), Vec<f64>>::new();
+    // union of the gradient samples (without the sample index) associated
+    // with each values sample in all blocks
+    let mut gradients_entries = HashMap::<(&str, Vec<i32>), BTreeSet<Vec<i32>>>::new();
+
+    for (_, block) in descriptor.iter() {
+        let samples = block.samples();
+        let values = block.values().to_array();
+        let values = as_samples_features(values);
+
+        for (sample, row) in samples.iter().zip(values.axis_iter(Axis(0))) {
+            let squared_norm = squared_norms.entry(label_key(sample)).or_insert(0.0);
+            *squared_norm += row.dot(&row);
+        }
+
+        for parameter in ALL_GRADIENT_PARAMETERS {
+            let gradient = match block.gradient(parameter) {
+                Some(gradient) => gradient,
+                None => continue,
+            };
+
+            let gradient_samples = gradient.samples();
+            let gradient_values = gradient.values().to_array();
+            let gradient_values = as_gradient_features(gradient_values, values.shape()[1]);
+
+            for (gradient_sample, gradient_row) in gradient_samples.iter().zip(gradient_values.axis_iter(Axis(0))) {
+                let sample = &samples[gradient_sample[0].usize()];
+                gradients_entries.entry((parameter, label_key(sample)))
+                    .or_insert_with(BTreeSet::new)
+                    .insert(label_key(&gradient_sample[1..]));
+
+                let row = values.index_axis(Axis(0), gradient_sample[0].usize());
+                let dots = gradients_dots
+                    .entry((parameter, gradient_key(sample, gradient_sample)))
+                    .or_insert_with(|| vec![0.0; gradient_row.shape()[0]]);
+
+                for (dot, gradient_row) in dots.iter_mut().zip(gradient_row.axis_iter(Axis(0))) {
+                    *dot += row.dot(&gradient_row);
+                }
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for (_, block) in descriptor.iter() {
+        let samples = block.samples();
+        let norms = samples.iter()
+            .map(|sample| f64::sqrt(squared_norms[&label_key(sample)]))
+            .collect::<Vec<_>>();
+
+        let values = block.values().to_array();
+        let n_features = as_samples_features(values).shape()[1];
+
+        let mut normalized = values.clone();
+        {
+            let mut normalized = normalized.view_mut().into_shape(
+                (norms.len(), n_features)
+            ).expect("non contiguous values array");
+            for (mut row, &norm) in normalized.axis_iter_mut(Axis(0)).zip(&norms) {
+                if norm != 0.0 {
+                    row /= norm;
+                }
+            }
+        }
+
+        let mut new_block = TensorBlock::new(normalized, &samples, &block.components(), &block.properties())?;
+
+        let values = as_samples_features(values);
+        for parameter in ALL_GRADIENT_PARAMETERS {
+            let gradient = match block.gradient(parameter) {
+                Some(gradient) => gradient,
+                None => continue,
+            };
+
+            // all the gradient samples for this block, with the position of
+            // the corresponding entry in the existing gradients (if any)
+            let existing = gradient.samples().iter()
+                .enumerate()
+                .map(|(gradient_i, gradient_sample)| (label_key(gradient_sample), gradient_i))
+                .collect::<HashMap<_, _>>();
+
+            let mut gradient_samples = LabelsBuilder::new(gradient.samples().names());
+            let mut existing_rows = Vec::new();
+            for (sample_i, sample) in samples.iter().enumerate() {
+                let entries = match gradients_entries.get(&(parameter, label_key(sample))) {
+                    Some(entries) => entries,
+                    None => continue,
+                };
+
+                for entry in entries {
+                    let mut gradient_sample = vec![LabelValue::from(sample_i)];
+                    gradient_sample.extend(entry.iter().map(|&v| LabelValue::new(v)));
+
+                    existing_rows.push(existing.get(&label_key(&gradient_sample)).copied());
+                    gradient_samples.add(&gradient_sample);
+                }
+            }
+            let gradient_samples = gradient_samples.finish();
+
+            let gradient_values = as_gradient_features(gradient.values().to_array(), n_features);
+            let n_gradient_components = gradient_values.shape()[1];
+
+            let mut new_gradient = ndarray::Array3::from_elem(
+                (existing_rows.len(), n_gradient_components, n_features), 0.0
+            );
+            for ((gradient_sample, existing_i), mut new_row) in gradient_samples.iter().zip(existing_rows).zip(new_gradient.axis_iter_mut(Axis(0))) {
+                if let Some(existing_i) = existing_i {
+                    new_row.assign(&gradient_values.index_axis(Axis(0), existing_i));
+                }
+
+                let sample_i = gradient_sample[0].usize();
+                let norm = norms[sample_i];
+                if norm == 0.0 {
+                    continue;
+                }
+
+                let row = values.index_axis(Axis(0), sample_i);
+                let dots = &gradients_dots[&(parameter, gradient_key(&samples[sample_i], gradient_sample))];
+                for (&dot, mut new_row) in dots.iter().zip(new_row.axis_iter_mut(Axis(0))) {
+                    new_row /= norm;
+                    new_row.scaled_add(-dot / (norm * norm * norm), &row);
+                }
+            }
+
+            let mut shape = gradient.values().to_array().shape().to_vec();
+            shape[0] = gradient_samples.count();
+            let new_gradient = new_gradient.into_shape(shape).expect("invalid gradient shape");
+
+            new_block.add_gradient(parameter, TensorBlock::new(
+                new_gradient,
+                &gradient_samples,
+                &gradient.components(),
+                &gradient.properties(),
+            )?)?;
+        }
+
+        blocks.push(new_block);
+    }
+
+    *descriptor = TensorMap::new(descriptor.keys().clone(), blocks)?;
+
+    return Ok(());
+}
+
+/// Get a hashable representation of a single entry in some `Labels`
+fn label_key(entry: &[equistore::LabelValue]) -> Vec<i32> {
+    entry.iter().map(|v| v.i32()).collect()
+}
+
+/// Get the key used to accumulate dot products for a given gradient sample,
+/// replacing the index of the sample with the corresponding values sample.
+fn gradient_key(sample: &[equistore::LabelValue], gradient_sample: &[equistore::LabelValue]) -> Vec<i32> {
+    let mut key = label_key(sample);
+    key.extend(gradient_sample[1..].iter().map(|v| v.i32()));
+    return key;
+}
+
+/// Reshape the values of a block to `(samples, features)`, merging components
+/// and properties together.
+fn as_samples_features(values: &ndarray::ArrayD<f64>) -> ndarray::ArrayView2<'_, f64> {
+    let n_samples = values.shape()[0];
+    let n_features = values.len() / usize::max(n_samples, 1);
+    return values.view().into_shape((n_samples, n_features)).expect("non contiguous values array");
+}
+
+/// Reshape the values of a gradient block to `(gradient samples, gradient
+/// components, features)`.
+fn as_gradient_features(values: &ndarray::ArrayD<f64>, n_features: usize) -> ndarray::ArrayView3<'_, f64> {
+    let shape = values.shape();
+    let n_gradient_components = shape.iter().skip(1).product::<usize>() / usize::max(n_features, 1);
+    return values.view().into_shape((shape[0], n_gradient_components, n_features)).expect("non contiguous gradient array");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use approx::assert_relative_eq;
+    use ndarray::Axis;
+
+    use crate::systems::test_utils::{test_system, test_systems};
+    use crate::{CalculationOptions, System};
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::normalize_samples;
+
+    #[test]
+    fn unit_norm() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let mut systems = vec![Box::new(test_system("water")) as Box<dyn System>];
+        let mut descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+        normalize_samples(&mut descriptor).unwrap();
+
+        // all samples are the (structure, center) pairs
+        let mut squared_norms = BTreeMap::new();
+        for (_, block) in descriptor.iter() {
+            let values = block.values().to_array();
+            for (sample, row) in block.samples().iter().zip(values.axis_iter(Axis(0))) {
+                let sample = (sample[0].i32(), sample[1].i32());
+                *squared_norms.entry(sample).or_insert(0.0) += row.iter().map(|v| v * v).sum::<f64>();
+            }
+        }
+
+        assert_eq!(squared_norms.len(), 3);
+        for squared_norm in squared_norms.values() {
+            assert_relative_eq!(*squared_norm, 1.0, max_relative=1e-12);
+        }
+    }
+
+    #[test]
+    fn gradient_samples_union() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+        let mut descriptor = calculator.compute(&mut test_systems(&["water", "methane"]), options).unwrap();
+        normalize_samples(&mut descriptor).unwrap();
+
+        // all blocks containing a given sample have the same gradient samples
+        // for this sample after normalization
+        let mut all_atoms = BTreeMap::new();
+        for (_, block) in descriptor.iter() {
+            let samples = block.samples();
+            let mut atoms = vec![BTreeSet::new(); samples.count()];
+            for gradient_sample in block.gradient("positions").unwrap().samples().iter() {
+                atoms[gradient_sample[0].usize()].insert(gradient_sample[2].i32());
+            }
+
+            for (sample, atoms) in samples.iter().zip(atoms) {
+                let sample = (sample[0].i32(), sample[1].i32());
+                let expected = all_atoms.entry(sample).or_insert_with(|| atoms.clone());
+                assert_eq!(*expected, atoms);
+            }
+        }
+    }
+
+    #[test]
+    fn finite_differences_positions() {
+        let mut calculator = soap_calculator("soap_power_spectrum");
+        let system = test_system("water");
+        let options = CalculationOptions {
+            gradients: &["positions"],
+            ..Default::default()
+        };
+
+        let mut systems = vec![Box::new(system.clone()) as Box<dyn System>];
+        let mut reference = calculator.compute(&mut systems, options).unwrap();
+        normalize_samples(&mut reference).unwrap();
+
+        let displacement = 1e-6;
+        for atom_i in 0..system.size().unwrap() {
+            for spatial in 0..3 {
+                let mut compute_displaced = |delta: f64| {
+                    let mut system = system.clone();
+                    system.positions_mut()[atom_i][spatial] += delta;
+
+                    let mut systems = vec![Box::new(system) as Box<dyn System>];
+                    let mut descriptor = calculator.compute(&mut systems, Default::default()).unwrap();
+                    normalize_samples(&mut descriptor).unwrap();
+                    descriptor
+                };
+
+                let positive = compute_displaced(displacement / 2.0);
+                let negative = compute_displaced(-displacement / 2.0);
+
+                for (block_i, (_, block)) in reference.iter().enumerate() {
+                    let gradient = block.gradient("positions").unwrap();
+                    let gradient_values = gradient.values().to_array();
+
+                    let positive = positive.block_by_id(block_i).values().to_array().clone();
+                    let negative = negative.block_by_id(block_i).values().to_array().clone();
+
+                    // samples without a gradient entry for this atom should
+                    // have zero gradients
+                    for sample_i in 0..block.samples().count() {
+                        let finite_differences = (
+                            &positive.index_axis(Axis(0), sample_i) - &negative.index_axis(Axis(0), sample_i)
+                        ) / displacement;
+
+                        let gradient_i = gradient.samples().iter().position(|gradient_sample| {
+                            gradient_sample[0].usize() == sample_i && gradient_sample[2].usize() == atom_i
+                        });
+
+                        let analytical = if let Some(gradient_i) = gradient_i {
+                            let analytical = gradient_values.index_axis(Axis(0), gradient_i);
+                            analytical.index_axis(Axis(0), spatial).to_owned()
+                        } else {
+                            ndarray::ArrayD::zeros(finite_differences.shape())
+                        };
+
+                        assert_relative_eq!(
+                            finite_differences, analytical,
+                            epsilon=1e-6, max_relative=1e-5,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use ndarray::{ArrayD, ArrayView2, Ix2};
+use equistore::{TensorMap, TensorBlock};
+
+use crate::Error;
+use crate::calculator::ALL_GRADIENT_PARAMETERS;
+
+/// Project the properties of each block in `tensor` using the corresponding
+/// block in `projections`, e.g. to apply a dimensionality reduction computed
+/// with PCA.
+///
+/// For every key in `tensor`, `projections` must contain a block with the
+/// same key, without components, where the samples are the properties of
+/// the `tensor` block and the properties are the new (projected) properties.
+/// The values of this block are the projection matrix `P`, and the features
+/// `X` are transformed to `X P`. Gradients are transformed in the same way.
+pub fn project(tensor: &TensorMap, projections: &TensorMap) -> Result<TensorMap, Error> {
+    if tensor.keys().names() != projections.keys().names() {
+        return Err(Error::InvalidParameter(format!(
+            "the keys names of the projections ({}) do not match the keys names of the tensor ({})",
+            projections.keys().names().join(", "), tensor.keys().names().join(", ")
+        )));
+    }
+
+    let mut blocks = Vec::new();
+    for (key, block) in tensor.iter() {
+        let projection = if let Some(block_i) = projections.keys().position(key) {
+            projections.block_by_id(block_i)
+        } else {
+            return Err(Error::InvalidParameter(format!(
+                "missing projection for key {:?}", key
+            )));
+        };
+
+        if !projection.components().is_empty() {
+            return Err(Error::InvalidParameter(format!(
+                "the projection for key {:?} should not have components", key
+            )));
+        }
+
+        if projection.samples() != block.properties() {
+            return Err(Error::InvalidParameter(format!(
+                "the samples of the projection for key {:?} do not match the properties of the tensor",
+                key
+            )));
+        }
+
+        let matrix = projection.values().to_array();
+        let matrix = matrix.view().into_dimensionality::<Ix2>().expect("invalid projection matrix");
+        let new_properties = projection.properties();
+
+        let mut new_block = TensorBlock::new(
+            project_array(block.values().to_array(), matrix),
+            &block.samples(),
+            &block.components(),
+            &new_properties,
+        )?;
+
+        for parameter in ALL_GRADIENT_PARAMETERS {
+            if let Some(gradient) = block.gradient(parameter) {
+                new_block.add_gradient(parameter, TensorBlock::new(
+                    project_array(gradient.values().to_array(), matrix),
+                    &gradient.samples(),
+                    &gradient.components(),
+                    &new_properties,
+                )?)?;
+            }
+        }
+
+        blocks.push(new_block);
+    }
+
+    return Ok(TensorMap::new(tensor.keys().clone(), blocks)?);
+}
+
+/// Multiply the last dimension of `array` with `matrix`
+fn project_array(array: &ArrayD<f64>, matrix: ArrayView2<f64>) -> ArrayD<f64> {
+    let mut shape = array.shape().to_vec();
+    let n_properties = shape[shape.len() - 1];
+    let n_rows = if n_properties == 0 {
+        0
+    } else {
+        array.len() / n_properties
+    };
+
+    let array = array.to_shape((n_rows, n_properties)).expect("failed to reshape values");
+    let projected = array.dot(&matrix);
+
+    let last = shape.len() - 1;
+    shape[last] = matrix.ncols();
+    return projected.into_shape(shape).expect("failed to reshape projected values");
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::{ArrayD, Axis, array};
+
+    use equistore::{Labels, TensorBlock, TensorMap};
+
+    use super::project;
+
+    fn tensor() -> TensorMap {
+        let mut block = TensorBlock::new(
+            array![[1.0, 2.0, -1.0], [3.0, 0.5, 4.0]].into_dyn(),
+            &Labels::new(["structure", "center"], &[[0, 0], [0, 1]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1], [2]]),
+        ).unwrap();
+
+        block.add_gradient("positions", TensorBlock::new(
+            array![
+                [[0.5, 1.0, 0.0], [-1.0, 2.0, 3.0], [0.0, 0.0, 1.5]],
+                [[2.0, -0.5, 1.0], [1.0, 1.0, 1.0], [0.25, -3.0, 0.0]],
+            ].into_dyn(),
+            &Labels::new(["sample", "structure", "atom"], &[[0, 0, 1], [1, 0, 0]]),
+            &[Labels::new(["direction"], &[[0], [1], [2]])],
+            &Labels::new(["n"], &[[0], [1], [2]]),
+        ).unwrap()).unwrap();
+
+        return TensorMap::new(Labels::new(["species_center"], &[[1]]), vec![block]).unwrap();
+    }
+
+    fn projection(matrix: ArrayD<f64>, properties: &Labels) -> TensorMap {
+        let block = TensorBlock::new(
+            matrix,
+            &Labels::new(["n"], &[[0], [1], [2]]),
+            &[],
+            properties,
+        ).unwrap();
+        return TensorMap::new(Labels::new(["species_center"], &[[1]]), vec![block]).unwrap();
+    }
+
+    #[test]
+    fn identity() {
+        let tensor = tensor();
+        let identity = projection(
+            ndarray::Array2::eye(3).into_dyn(),
+            &Labels::new(["n"], &[[0], [1], [2]]),
+        );
+
+        let projected = project(&tensor, &identity).unwrap();
+        let block = projected.block_by_id(0);
+        let expected = tensor.block_by_id(0);
+
+        assert_eq!(block.properties(), expected.properties());
+        assert_relative_eq!(block.values().to_array(), expected.values().to_array());
+
+        let gradient = block.gradient("positions").unwrap();
+        let expected = expected.gradient("positions").unwrap();
+        assert_eq!(gradient.samples(), expected.samples());
+        assert_relative_eq!(gradient.values().to_array(), expected.values().to_array());
+    }
+
+    #[test]
+    fn random_projection() {
+        let tensor = tensor();
+        let matrix = array![[0.3, -1.2], [0.7, 0.1], [-0.4, 2.5]];
+        let projections = projection(matrix.clone().into_dyn(), &Labels::new(["pca"], &[[0], [1]]));
+
+        let projected = project(&tensor, &projections).unwrap();
+        let block = projected.block_by_id(0);
+        let original = tensor.block_by_id(0);
+
+        assert_eq!(block.properties(), Labels::new(["pca"], &[[0], [1]]));
+
+        let values = original.values().to_array().view().into_dimensionality::<ndarray::Ix2>().unwrap().dot(&matrix);
+        assert_relative_eq!(block.values().to_array(), &values.into_dyn(), max_relative=1e-14);
+
+        let gradient = block.gradient("positions").unwrap();
+        let gradient = gradient.values().to_array();
+        let original = original.gradient("positions").unwrap();
+        let original = original.values().to_array();
+        assert_eq!(gradient.shape(), [2, 3, 2]);
+        for sample in 0..2 {
+            let expected = original.index_axis(Axis(0), sample)
+                .into_dimensionality::<ndarray::Ix2>().unwrap()
+                .dot(&matrix);
+            assert_relative_eq!(
+                gradient.index_axis(Axis(0), sample).into_dimensionality::<ndarray::Ix2>().unwrap(),
+                expected,
+                max_relative=1e-14,
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_properties() {
+        let tensor = tensor();
+
+        // wrong samples in the projection
+        let block = TensorBlock::new(
+            ndarray::Array2::eye(2).into_dyn(),
+            &Labels::new(["n"], &[[0], [1]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1]]),
+        ).unwrap();
+        let wrong = TensorMap::new(Labels::new(["species_center"], &[[1]]), vec![block]).unwrap();
+        assert!(project(&tensor, &wrong).is_err());
+
+        // missing key
+        let block = TensorBlock::new(
+            ndarray::Array2::eye(3).into_dyn(),
+            &Labels::new(["n"], &[[0], [1], [2]]),
+            &[],
+            &Labels::new(["n"], &[[0], [1], [2]]),
+        ).unwrap();
+        let other_keys = TensorMap::new(Labels::new(["species_center"], &[[6]]), vec![block]).unwrap();
+        assert!(project(&tensor, &other_keys).is_err());
+    }
+}
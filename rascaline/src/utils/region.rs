@@ -0,0 +1,101 @@
+use equistore::{Labels, LabelsBuilder, LabelValue};
+
+use crate::{Error, System, Vector3D};
+use crate::systems::UnitCell;
+
+/// Get the `(structure, center)` samples corresponding to all atoms inside a
+/// parallelepiped region of space, defined by its `origin` and the cell
+/// vectors of `region`.
+///
+/// The returned labels can be used with [`crate::LabelsSelection::Subset`] to
+/// compute features only for atoms inside a sub-cell of a larger periodic
+/// system (for example to use the atoms of the QM region as centers in QM/MM
+/// setups). The neighbor search still uses the full system with periodic
+/// boundary conditions, so the features of the selected atoms are the same
+/// as the corresponding features in the full calculation.
+///
+/// An atom is inside the region if its fractional coordinates with respect to
+/// the `region` cell (after subtracting `origin` to the position) are in
+/// `[0, 1)`. Positions are used as given by the systems, without wrapping
+/// them inside the unit cell of the system first.
+pub fn centers_in_region(
+    systems: &[Box<dyn System>],
+    origin: Vector3D,
+    region: UnitCell,
+) -> Result<Labels, Error> {
+    if region.is_infinite() {
+        return Err(Error::InvalidParameter(
+            "the region used to select centers can not be infinite".into()
+        ));
+    }
+
+    let mut samples = LabelsBuilder::new(vec!["structure", "center"]);
+    for (structure_i, system) in systems.iter().enumerate() {
+        for (center_i, &position) in system.positions()?.iter().enumerate() {
+            let fractional = region.fractional(position - origin);
+            if (0..3).all(|k| 0.0 <= fractional[k] && fractional[k] < 1.0) {
+                samples.add(&[LabelValue::from(structure_i), LabelValue::from(center_i)]);
+            }
+        }
+    }
+
+    return Ok(samples.finish());
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use ndarray::Axis;
+
+    use crate::systems::test_utils::test_system;
+    use crate::systems::UnitCell;
+    use crate::{CalculationOptions, LabelsSelection, System, Vector3D};
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::centers_in_region;
+
+    #[test]
+    fn region() {
+        let methane = test_system("methane");
+        let supercell = methane.supercell([2, 2, 2]).unwrap();
+        let mut systems = vec![Box::new(supercell) as Box<dyn System>];
+
+        // the atoms of the original methane cell are all between 3.5 and 8.5
+        // along all axes, and all the periodic images are outside this region
+        let samples = centers_in_region(&systems, Vector3D::new(3.5, 3.5, 3.5), UnitCell::cubic(5.0)).unwrap();
+        assert_eq!(samples.count(), 5);
+        for (center_i, sample) in samples.iter().enumerate() {
+            assert_eq!(sample[0].usize(), 0);
+            assert_eq!(sample[1].usize(), center_i);
+        }
+
+        let mut calculator = soap_calculator("spherical_expansion");
+        let full = calculator.compute(&mut systems, Default::default()).unwrap();
+
+        let options = CalculationOptions {
+            selected_samples: LabelsSelection::Subset(&samples),
+            ..Default::default()
+        };
+        let selected = calculator.compute(&mut systems, options).unwrap();
+
+        let mut methane = vec![Box::new(methane) as Box<dyn System>];
+        let primitive = calculator.compute(&mut methane, Default::default()).unwrap();
+
+        assert_eq!(selected.keys(), full.keys());
+        assert_eq!(selected.keys(), primitive.keys());
+        for (((_, selected), (_, full)), (_, primitive)) in selected.iter().zip(full.iter()).zip(primitive.iter()) {
+            let selected_samples = selected.samples();
+            let full_samples = full.samples();
+            assert_eq!(selected_samples, primitive.samples());
+
+            let positions = selected_samples.iter()
+                .map(|sample| full_samples.position(sample).expect("missing sample"))
+                .collect::<Vec<_>>();
+            let expected = full.values().to_array().select(Axis(0), &positions);
+
+            let values = selected.values().to_array();
+            assert_relative_eq!(values, expected, max_relative=1e-12);
+            assert_relative_eq!(values, primitive.values().to_array(), max_relative=1e-10, epsilon=1e-14);
+        }
+    }
+}
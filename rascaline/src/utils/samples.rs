@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use equistore::{TensorMap, LabelValue};
+
+use crate::Error;
+
+/// Check that the `structure` and `center` samples in `tensor` uniquely
+/// identify atoms.
+///
+/// The `center` index is only unique inside a given structure, so when
+/// merging `TensorMap` computed separately (e.g. joining them along samples),
+/// the `structure` index must be updated to refer to the merged set of
+/// systems. This function checks that no `(structure, center)` sample is
+/// duplicated inside a block, and if the keys contain `species_center`, that
+/// the same `(structure, center)` is not associated with multiple central
+/// species, which happens when the structures indexes of different systems
+/// collide.
+pub fn check_unique_samples(tensor: &TensorMap) -> Result<(), Error> {
+    let species_center_position = tensor.keys().names().iter().position(|&name| name == "species_center");
+
+    let mut species_by_sample: BTreeMap<(LabelValue, LabelValue), LabelValue> = BTreeMap::new();
+    for (key, block) in tensor.iter() {
+        let samples = block.samples();
+        let names = samples.names();
+
+        let structure_position = names.iter().position(|&name| name == "structure");
+        let center_position = names.iter().position(|&name| name == "center");
+        let (structure_position, center_position) = match (structure_position, center_position) {
+            (Some(structure), Some(center)) => (structure, center),
+            _ => {
+                return Err(Error::InvalidParameter(format!(
+                    "expected 'structure' and 'center' in the samples names, got [{}]",
+                    names.join(", ")
+                )));
+            }
+        };
+
+        let mut seen = BTreeSet::new();
+        for sample in samples.iter() {
+            let atom = (sample[structure_position], sample[center_position]);
+            if !seen.insert(atom) {
+                return Err(Error::InvalidParameter(format!(
+                    "duplicated sample (structure={}, center={}) in block for key {:?}",
+                    atom.0.i32(), atom.1.i32(), key
+                )));
+            }
+
+            if let Some(species_position) = species_center_position {
+                let species_center = key[species_position];
+                let previous = *species_by_sample.entry(atom).or_insert(species_center);
+                if previous != species_center {
+                    return Err(Error::InvalidParameter(format!(
+                        "sample (structure={}, center={}) is associated with \
+                        multiple central species ({} and {}), structure indexes \
+                        are likely not unique across merged systems",
+                        atom.0.i32(), atom.1.i32(), previous.i32(), species_center.i32()
+                    )));
+                }
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::ArrayD;
+    use equistore::{Labels, TensorBlock, TensorMap};
+
+    use super::check_unique_samples;
+
+    fn block(samples: &[[i32; 2]]) -> TensorBlock {
+        TensorBlock::new(
+            ArrayD::from_elem(vec![samples.len(), 1], 0.0),
+            &Labels::new(["structure", "center"], samples),
+            &[],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap()
+    }
+
+    #[test]
+    fn joined_systems() {
+        // first system is water (O, H, H), second is CH, with correctly
+        // updated structure indexes
+        let keys = Labels::new(["species_center"], &[[1], [6], [8]]);
+        let tensor = TensorMap::new(keys, vec![
+            block(&[[0, 1], [0, 2], [1, 1]]),
+            block(&[[1, 0]]),
+            block(&[[0, 0]]),
+        ]).unwrap();
+        assert!(check_unique_samples(&tensor).is_ok());
+
+        // the structure index of CH was not updated, and collides with water
+        let keys = Labels::new(["species_center"], &[[1], [6], [8]]);
+        let tensor = TensorMap::new(keys, vec![
+            block(&[[0, 1], [0, 2]]),
+            block(&[[0, 0]]),
+            block(&[[0, 0]]),
+        ]).unwrap();
+        assert!(check_unique_samples(&tensor).is_err());
+    }
+
+    #[test]
+    fn missing_samples_names() {
+        let tensor = TensorMap::new(Labels::single(), vec![TensorBlock::new(
+            ArrayD::from_elem(vec![1, 1], 0.0),
+            &Labels::new(["structure"], &[[0]]),
+            &[],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap()]).unwrap();
+        assert!(check_unique_samples(&tensor).is_err());
+    }
+}
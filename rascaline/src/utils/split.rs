@@ -0,0 +1,166 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ndarray::Axis;
+use equistore::{TensorMap, TensorBlock, LabelsBuilder, LabelValue};
+
+use crate::Error;
+use crate::calculator::ALL_GRADIENT_PARAMETERS;
+
+/// Split a `tensor` containing multiple structures into one `TensorMap` per
+/// structure.
+///
+/// The returned `TensorMap` are sorted by the value of the `structure`
+/// samples, and the `structure` samples (in both values and gradients) are
+/// renumbered to 0. All the returned `TensorMap` have the same keys as
+/// `tensor`, blocks without any sample for a given structure are kept empty.
+pub fn split_by_structure(tensor: &TensorMap) -> Result<Vec<TensorMap>, Error> {
+    let mut structures = BTreeSet::new();
+    for (_, block) in tensor.iter() {
+        let samples = block.samples();
+        let structure_position = structure_position(&samples.names())?;
+        for sample in samples.iter() {
+            structures.insert(sample[structure_position]);
+        }
+    }
+
+    let mut blocks_by_structure = (0..structures.len()).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (_, block) in tensor.iter() {
+        let samples = block.samples();
+        let structure_position = structure_position(&samples.names())?;
+        let values = block.values().to_array();
+
+        for (structure_i, &structure) in structures.iter().enumerate() {
+            let mut new_samples = LabelsBuilder::new(samples.names());
+            let mut new_sample_index = BTreeMap::new();
+            let mut selected = Vec::new();
+            for (sample_i, sample) in samples.iter().enumerate() {
+                if sample[structure_position] == structure {
+                    let mut new_sample = sample.to_vec();
+                    new_sample[structure_position] = LabelValue::new(0);
+                    new_samples.add(&new_sample);
+
+                    new_sample_index.insert(sample_i, selected.len());
+                    selected.push(sample_i);
+                }
+            }
+
+            let mut new_block = TensorBlock::new(
+                values.select(Axis(0), &selected),
+                &new_samples.finish(),
+                &block.components(),
+                &block.properties(),
+            )?;
+
+            for parameter in ALL_GRADIENT_PARAMETERS {
+                if let Some(gradient) = block.gradient(parameter) {
+                    let gradient_samples = gradient.samples();
+                    let gradient_names = gradient_samples.names();
+                    let gradient_structure = gradient_names.iter().position(|&name| name == "structure");
+
+                    let mut new_samples = LabelsBuilder::new(gradient_names.clone());
+                    let mut selected = Vec::new();
+                    for (gradient_sample_i, gradient_sample) in gradient_samples.iter().enumerate() {
+                        if let Some(&new_sample_i) = new_sample_index.get(&gradient_sample[0].usize()) {
+                            let mut new_sample = gradient_sample.to_vec();
+                            new_sample[0] = new_sample_i.into();
+                            if let Some(position) = gradient_structure {
+                                new_sample[position] = LabelValue::new(0);
+                            }
+                            new_samples.add(&new_sample);
+                            selected.push(gradient_sample_i);
+                        }
+                    }
+
+                    new_block.add_gradient(parameter, TensorBlock::new(
+                        gradient.values().to_array().select(Axis(0), &selected),
+                        &new_samples.finish(),
+                        &gradient.components(),
+                        &gradient.properties(),
+                    )?)?;
+                }
+            }
+
+            blocks_by_structure[structure_i].push(new_block);
+        }
+    }
+
+    let mut result = Vec::new();
+    for blocks in blocks_by_structure {
+        result.push(TensorMap::new(tensor.keys().clone(), blocks)?);
+    }
+
+    return Ok(result);
+}
+
+fn structure_position(names: &[&str]) -> Result<usize, Error> {
+    return names.iter().position(|&name| name == "structure").ok_or_else(|| Error::InvalidParameter(format!(
+        "expected 'structure' in the samples names, got [{}]", names.join(", ")
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_ulps_eq;
+    use ndarray::ArrayD;
+    use equistore::{Labels, TensorBlock, TensorMap};
+
+    use crate::systems::test_utils::test_systems;
+    use crate::CalculationOptions;
+    use crate::calculators::tests_utils::soap_calculator;
+
+    use super::split_by_structure;
+
+    #[test]
+    fn round_trip() {
+        let mut calculator = soap_calculator("spherical_expansion");
+        let options = CalculationOptions {
+            gradients: &["positions", "cell"],
+            ..Default::default()
+        };
+
+        let mut systems = test_systems(&["water", "CH"]);
+        let joined = calculator.compute(&mut systems, options).unwrap();
+
+        let split = split_by_structure(&joined).unwrap();
+        assert_eq!(split.len(), 2);
+
+        for (name, tensor) in ["water", "CH"].into_iter().zip(&split) {
+            assert_eq!(tensor.keys(), joined.keys());
+
+            let mut systems = test_systems(&[name]);
+            let expected = calculator.compute(&mut systems, options).unwrap();
+
+            for (key, block) in tensor.iter() {
+                let expected = match expected.keys().position(key) {
+                    Some(block_i) => expected.block_by_id(block_i),
+                    None => {
+                        // this block only exists for the other system
+                        assert_eq!(block.samples().count(), 0);
+                        continue;
+                    }
+                };
+
+                assert_eq!(block.samples(), expected.samples());
+                assert_ulps_eq!(block.values().to_array(), expected.values().to_array());
+
+                for parameter in ["positions", "cell"] {
+                    let gradient = block.gradient(parameter).unwrap();
+                    let expected = expected.gradient(parameter).unwrap();
+                    assert_eq!(gradient.samples(), expected.samples());
+                    assert_ulps_eq!(gradient.values().to_array(), expected.values().to_array());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn missing_structure() {
+        let tensor = TensorMap::new(Labels::single(), vec![TensorBlock::new(
+            ArrayD::from_elem(vec![1, 1], 0.0),
+            &Labels::new(["center"], &[[0]]),
+            &[],
+            &Labels::new(["n"], &[[0]]),
+        ).unwrap()]).unwrap();
+        assert!(split_by_structure(&tensor).is_err());
+    }
+}
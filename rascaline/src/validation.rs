@@ -0,0 +1,427 @@
+//! Check the gradients computed by a calculator against finite differences.
+//!
+//! These functions are intended for people implementing their own
+//! [`CalculatorBase`](crate::calculators::CalculatorBase), and are only
+//! available when the `validation` feature is enabled.
+//!
+//! ```
+//! use rascaline::{Calculator, SimpleSystem, Vector3D};
+//! use rascaline::systems::UnitCell;
+//! use rascaline::validation::{FinalDifferenceOptions, finite_differences_positions};
+//!
+//! let mut calculator = Calculator::new("soap_power_spectrum", r#"{
+//!     "cutoff": 3.5,
+//!     "max_radial": 4,
+//!     "max_angular": 3,
+//!     "atomic_gaussian_width": 0.3,
+//!     "center_atom_weight": 1.0,
+//!     "radial_basis": {"Gto": {}},
+//!     "cutoff_function": {"ShiftedCosine": {"width": 0.5}}
+//! }"#.into())?;
+//!
+//! let mut system = SimpleSystem::new(UnitCell::infinite());
+//! system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+//! system.add_atom(1, Vector3D::new(0.0, 0.75, -0.5));
+//! system.add_atom(1, Vector3D::new(0.0, -0.75, -0.5));
+//!
+//! let options = FinalDifferenceOptions::default().max_relative(1e-4);
+//! let mismatches = finite_differences_positions(&mut calculator, &system, options)?;
+//! assert!(mismatches.is_empty());
+//! # Ok::<(), rascaline::Error>(())
+//! ```
+
+use std::convert::TryFrom;
+
+use ndarray::{ArrayD, ArrayViewD, Axis};
+use approx::relative_eq;
+
+use equistore::TensorMap;
+
+use crate::{Calculator, CalculationOptions, LabelsSelection, Error};
+use crate::systems::{System, SimpleSystem, UnitCell};
+
+/// Options for the finite differences checks of gradients.
+///
+/// The default values should work for most calculators, and can be changed
+/// with the corresponding builder functions:
+///
+/// ```
+/// # use rascaline::validation::FinalDifferenceOptions;
+/// let options = FinalDifferenceOptions::default()
+///     .displacement(1e-5)
+///     .max_relative(1e-4);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct FinalDifferenceOptions {
+    /// distance each atom will be displaced in each direction when computing
+    /// finite differences
+    pub displacement: f64,
+    /// Maximal relative error. 10 * displacement is a good starting point
+    pub max_relative: f64,
+    /// Threshold below which all values are considered zero. This should be
+    /// very small (1e-16) to prevent false positives (if all values & gradients
+    /// are below that threshold, tests will pass even with wrong gradients)
+    pub epsilon: f64,
+}
+
+impl Default for FinalDifferenceOptions {
+    fn default() -> FinalDifferenceOptions {
+        FinalDifferenceOptions {
+            displacement: 1e-6,
+            max_relative: 1e-5,
+            epsilon: 1e-16,
+        }
+    }
+}
+
+impl FinalDifferenceOptions {
+    /// Set the displacement used for finite differences
+    pub fn displacement(mut self, displacement: f64) -> FinalDifferenceOptions {
+        self.displacement = displacement;
+        return self;
+    }
+
+    /// Set the maximal relative error between finite differences and
+    /// analytical gradients
+    pub fn max_relative(mut self, max_relative: f64) -> FinalDifferenceOptions {
+        self.max_relative = max_relative;
+        return self;
+    }
+
+    /// Set the threshold below which all values are considered zero
+    pub fn epsilon(mut self, epsilon: f64) -> FinalDifferenceOptions {
+        self.epsilon = epsilon;
+        return self;
+    }
+}
+
+/// A single gradient entry where the analytical gradient and the finite
+/// differences estimate disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradientMismatch {
+    /// Index of the block containing this entry in the descriptor
+    pub block: usize,
+    /// Index of the values sample containing this entry in the block
+    pub sample: usize,
+    /// Direction of the displacement: the displaced atom and cartesian
+    /// direction for positions gradients, or the indexes of the deformed entry
+    /// in the cell matrix for cell and strain gradients
+    pub displacement: [usize; 2],
+    /// Index of this entry in the flattened components and properties of the
+    /// gradient sample
+    pub index: usize,
+    /// Value of the analytical gradient
+    pub analytical: f64,
+    /// Value of the gradient estimated with finite differences
+    pub finite_differences: f64,
+}
+
+impl std::fmt::Display for GradientMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,
+            "block {}, sample {}, displacement {:?}, index {}: analytical gradient is {} but finite differences give {}",
+            self.block, self.sample, self.displacement, self.index,
+            self.analytical, self.finite_differences,
+        )
+    }
+}
+
+/// Check that the analytical gradients of `calculator` with respect to
+/// positions agree with a finite difference calculation of the gradients for
+/// the given `system`.
+///
+/// All atoms are displaced, and the gradients of samples without a gradient
+/// entry for the displaced atom are taken to be zero.
+///
+/// This returns all the gradient entries where the two disagree, or an error
+/// if any of the calculations failed.
+pub fn finite_differences_positions(
+    calculator: &mut Calculator,
+    system: &dyn System,
+    options: FinalDifferenceOptions,
+) -> Result<Vec<GradientMismatch>, Error> {
+    return finite_differences_positions_selected(calculator, system, LabelsSelection::All, options);
+}
+
+/// Same as [`finite_differences_positions`], only computing the samples
+/// matching `selected_samples`.
+pub(crate) fn finite_differences_positions_selected(
+    calculator: &mut Calculator,
+    system: &dyn System,
+    selected_samples: LabelsSelection,
+    options: FinalDifferenceOptions,
+) -> Result<Vec<GradientMismatch>, Error> {
+    let system = SimpleSystem::try_from(system)?;
+
+    let calculation_options = CalculationOptions {
+        gradients: &["positions"],
+        selected_samples: selected_samples,
+        ..Default::default()
+    };
+    let reference = calculator.compute(&mut [Box::new(system.clone())], calculation_options)?;
+
+    let calculation_options = CalculationOptions {
+        selected_samples: selected_samples,
+        ..Default::default()
+    };
+
+    let mut mismatches = Vec::new();
+    for atom_i in 0..system.size()? {
+        for spatial in 0..3 {
+            let mut system_pos = system.clone();
+            system_pos.positions_mut()[atom_i][spatial] += options.displacement / 2.0;
+            let updated_pos = calculator.compute(&mut [Box::new(system_pos)], calculation_options)?;
+
+            let mut system_neg = system.clone();
+            system_neg.positions_mut()[atom_i][spatial] -= options.displacement / 2.0;
+            let updated_neg = calculator.compute(&mut [Box::new(system_neg)], calculation_options)?;
+
+            check_same_keys(&reference, &updated_pos)?;
+            check_same_keys(&reference, &updated_neg)?;
+
+            for (block_i, (_, block)) in reference.iter().enumerate() {
+                let gradients = &block.gradient("positions").expect("missing positions gradients");
+                let block_pos = &updated_pos.block_by_id(block_i);
+                let block_neg = &updated_neg.block_by_id(block_i);
+
+                // check that the same samples are here in all descriptors
+                if block_pos.samples() != block.samples() || block_neg.samples() != block.samples() {
+                    return Err(Error::InvalidParameter(
+                        "the samples changed when displacing the system, can not use finite differences".into()
+                    ));
+                }
+
+                // find the gradient entries with respect to the displaced
+                // atom, samples without such entry have zero gradients
+                let mut gradient_rows = vec![None; block.samples().count()];
+                for (gradient_i, [sample_i, _, atom]) in gradients.samples().iter_fixed_size().enumerate() {
+                    if atom.usize() == atom_i {
+                        gradient_rows[sample_i.usize()] = Some(gradient_i);
+                    }
+                }
+
+                let values_pos = block_pos.values().to_array();
+                let values_neg = block_neg.values().to_array();
+                let gradients_values = gradients.values().to_array();
+                for (sample_i, gradient_i) in gradient_rows.into_iter().enumerate() {
+                    let value_pos = values_pos.index_axis(Axis(0), sample_i);
+                    let value_neg = values_neg.index_axis(Axis(0), sample_i);
+                    let finite_difference = finite_difference(value_pos, value_neg, options.displacement);
+
+                    let gradient = if let Some(gradient_i) = gradient_i {
+                        let gradient = gradients_values.index_axis(Axis(0), gradient_i);
+                        gradient.index_axis(Axis(0), spatial).to_owned()
+                    } else {
+                        ArrayD::zeros(finite_difference.shape())
+                    };
+
+                    compare(&mut mismatches, finite_difference.view(), gradient.view(), options, GradientMismatch {
+                        block: block_i,
+                        sample: sample_i,
+                        displacement: [atom_i, spatial],
+                        index: 0,
+                        analytical: 0.0,
+                        finite_differences: 0.0,
+                    })?;
+                }
+            }
+        }
+    }
+
+    return Ok(mismatches);
+}
+
+/// Check that the analytical gradients of `calculator` with respect to the
+/// cell agree with a finite difference calculation of the gradients for the
+/// given `system`. Atoms are kept at the same fractional position when
+/// deforming the cell.
+///
+/// This returns all the gradient entries where the two disagree, or an error
+/// if any of the calculations failed.
+pub fn finite_differences_cell(
+    calculator: &mut Calculator,
+    system: &dyn System,
+    options: FinalDifferenceOptions,
+) -> Result<Vec<GradientMismatch>, Error> {
+    let system = SimpleSystem::try_from(system)?;
+
+    let calculation_options = CalculationOptions {
+        gradients: &["cell"],
+        ..Default::default()
+    };
+    let reference = calculator.compute(&mut [Box::new(system.clone())], calculation_options)?;
+    let original_cell = system.cell()?.matrix();
+    let original_cell_inverse = original_cell.inverse();
+
+    let deformed = |delta: f64, spatial_1: usize, spatial_2: usize| {
+        let mut cell = original_cell;
+        cell[spatial_1][spatial_2] += delta;
+
+        let mut deformed = system.clone();
+        deformed.set_cell(UnitCell::from(cell));
+        for position in deformed.positions_mut() {
+            *position = cell * (original_cell_inverse * *position);
+        }
+        return deformed;
+    };
+
+    return finite_differences_deformation(calculator, &reference, "cell", options, deformed);
+}
+
+/// Check that the analytical gradients of `calculator` with respect to strain
+/// agree with a finite difference calculation of the gradients for the given
+/// `system`, where the strain is applied to both the atomic positions and the
+/// cell vectors.
+///
+/// This returns all the gradient entries where the two disagree, or an error
+/// if any of the calculations failed.
+pub fn finite_differences_strain(
+    calculator: &mut Calculator,
+    system: &dyn System,
+    options: FinalDifferenceOptions,
+) -> Result<Vec<GradientMismatch>, Error> {
+    let system = SimpleSystem::try_from(system)?;
+
+    let calculation_options = CalculationOptions {
+        gradients: &["strain"],
+        ..Default::default()
+    };
+    let reference = calculator.compute(&mut [Box::new(system.clone())], calculation_options)?;
+    let original_cell = system.cell()?.matrix();
+
+    // apply a strain `ε` with a single non-zero entry `ε[spatial_1][spatial_2]`
+    let strained = |strain: f64, spatial_1: usize, spatial_2: usize| {
+        let mut cell = original_cell;
+        for vector in 0..3 {
+            cell[vector][spatial_1] += strain * original_cell[vector][spatial_2];
+        }
+
+        let mut strained = system.clone();
+        strained.set_cell(UnitCell::from(cell));
+        for position in strained.positions_mut() {
+            let shift = strain * position[spatial_2];
+            position[spatial_1] += shift;
+        }
+        return strained;
+    };
+
+    return finite_differences_deformation(calculator, &reference, "strain", options, strained);
+}
+
+/// Shared implementation of finite differences for gradients with respect to
+/// a 3x3 deformation of the system (cell or strain). `deformed(delta, i, j)`
+/// should create a new system where the entry `[i][j]` of the deformation is
+/// changed by `delta`.
+fn finite_differences_deformation(
+    calculator: &mut Calculator,
+    reference: &TensorMap,
+    parameter: &str,
+    options: FinalDifferenceOptions,
+    deformed: impl Fn(f64, usize, usize) -> SimpleSystem,
+) -> Result<Vec<GradientMismatch>, Error> {
+    let mut mismatches = Vec::new();
+    for spatial_1 in 0..3 {
+        for spatial_2 in 0..3 {
+            let system_pos = deformed(options.displacement / 2.0, spatial_1, spatial_2);
+            let updated_pos = calculator.compute(&mut [Box::new(system_pos)], Default::default())?;
+
+            let system_neg = deformed(-options.displacement / 2.0, spatial_1, spatial_2);
+            let updated_neg = calculator.compute(&mut [Box::new(system_neg)], Default::default())?;
+
+            check_same_keys(reference, &updated_pos)?;
+            check_same_keys(reference, &updated_neg)?;
+
+            for (block_i, (_, block)) in reference.iter().enumerate() {
+                let gradients = &block.gradient(parameter).expect("missing gradients");
+                let block_pos = &updated_pos.block_by_id(block_i);
+                let block_neg = &updated_neg.block_by_id(block_i);
+
+                for (gradient_i, [sample_i]) in gradients.samples().iter_fixed_size().enumerate() {
+                    let sample_i = sample_i.usize();
+
+                    // check that the same sample is here in both descriptors
+                    if block_pos.samples()[sample_i] != block.samples()[sample_i] ||
+                       block_neg.samples()[sample_i] != block.samples()[sample_i] {
+                        return Err(Error::InvalidParameter(
+                            "the samples changed when displacing the system, can not use finite differences".into()
+                        ));
+                    }
+
+                    let value_pos = block_pos.values().to_array().index_axis(Axis(0), sample_i);
+                    let value_neg = block_neg.values().to_array().index_axis(Axis(0), sample_i);
+                    let gradient = gradients.values().to_array().index_axis(Axis(0), gradient_i);
+                    let gradient = gradient.index_axis(Axis(0), spatial_1);
+                    let gradient = gradient.index_axis(Axis(0), spatial_2);
+
+                    let finite_difference = finite_difference(value_pos, value_neg, options.displacement);
+                    compare(&mut mismatches, finite_difference.view(), gradient, options, GradientMismatch {
+                        block: block_i,
+                        sample: sample_i,
+                        displacement: [spatial_1, spatial_2],
+                        index: 0,
+                        analytical: 0.0,
+                        finite_differences: 0.0,
+                    })?;
+                }
+            }
+        }
+    }
+
+    return Ok(mismatches);
+}
+
+/// Check that the displaced descriptor contains the same keys as the
+/// reference descriptor, so we can compare them with finite differences.
+fn check_same_keys(reference: &TensorMap, displaced: &TensorMap) -> Result<(), Error> {
+    if reference.keys() != displaced.keys() {
+        return Err(Error::InvalidParameter(
+            "the keys changed when displacing the system, can not use finite differences".into()
+        ));
+    }
+
+    return Ok(());
+}
+
+fn finite_difference(value_pos: ArrayViewD<f64>, value_neg: ArrayViewD<f64>, displacement: f64) -> ArrayD<f64> {
+    let mut finite_difference = value_pos.to_owned();
+    finite_difference -= &value_neg;
+    finite_difference /= displacement;
+    return finite_difference;
+}
+
+/// Compare `finite_difference` and `gradient` element-wise, adding all the
+/// entries that disagree to `mismatches`. `template` is used to fill the
+/// fields of the mismatches that do not depend on the entry.
+fn compare(
+    mismatches: &mut Vec<GradientMismatch>,
+    finite_difference: ArrayViewD<f64>,
+    gradient: ArrayViewD<f64>,
+    options: FinalDifferenceOptions,
+    template: GradientMismatch,
+) -> Result<(), Error> {
+    if finite_difference.shape() != gradient.shape() {
+        return Err(Error::InvalidParameter(format!(
+            "the gradients have shape {:?}, but the values have shape {:?}",
+            gradient.shape(), finite_difference.shape()
+        )));
+    }
+
+    for (index, (&finite_differences, &analytical)) in finite_difference.iter().zip(&gradient).enumerate() {
+        let agree = relative_eq!(
+            finite_differences, analytical,
+            epsilon=options.epsilon,
+            max_relative=options.max_relative
+        );
+
+        if !agree {
+            mismatches.push(GradientMismatch {
+                index: index,
+                analytical: analytical,
+                finite_differences: finite_differences,
+                ..template.clone()
+            });
+        }
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,75 @@
+use approx::assert_relative_eq;
+
+use rascaline::systems::{SimpleSystem, UnitCell};
+use rascaline::{Calculator, CalculationOptions, System, Vector3D};
+
+fn system() -> SimpleSystem {
+    let mut system = SimpleSystem::new(UnitCell::cubic(4.0));
+    system.add_atom(8, Vector3D::new(0.0, 0.0, 0.0));
+    system.add_atom(1, Vector3D::new(0.9, 0.4, 0.1));
+    system.add_atom(1, Vector3D::new(-0.3, 0.8, 0.6));
+    system.add_atom(6, Vector3D::new(2.1, 1.7, 2.5));
+    return system;
+}
+
+#[test]
+fn symmetrized_full_list() {
+    let parameters = r#"{
+        "cutoff": 3.5,
+        "max_radial": 4,
+        "max_angular": 3,
+        "atomic_gaussian_width": 0.3,
+        "center_atom_weight": 1.0,
+        "radial_basis": {
+            "Gto": {}
+        },
+        "cutoff_function": {
+            "ShiftedCosine": {"width": 0.5}
+        }
+    }"#;
+    let mut calculator = Calculator::new("spherical_expansion", parameters.to_owned()).unwrap();
+
+    let options = CalculationOptions {
+        gradients: &["positions", "cell"],
+        ..Default::default()
+    };
+
+    let mut native = vec![Box::new(system()) as Box<dyn System>];
+    let native = calculator.compute(&mut native, options).unwrap();
+
+    // emulate an external code which only provides full neighbor lists
+    let mut full_list = system();
+    full_list.compute_neighbors(3.5).unwrap();
+    let full_pairs = full_list.pairs_full().unwrap();
+    assert!(full_pairs.iter().any(|pair| pair.first > pair.second));
+
+    let mut full_list = system();
+    full_list.set_neighbors(3.5, full_pairs).unwrap();
+
+    let mut converted = vec![Box::new(full_list) as Box<dyn System>];
+    let converted = calculator.compute(&mut converted, options).unwrap();
+
+    assert_eq!(native.keys(), converted.keys());
+    for ((_, native), (_, converted)) in native.iter().zip(converted.iter()) {
+        assert_eq!(native.samples(), converted.samples());
+        assert_relative_eq!(
+            native.values().to_array(),
+            converted.values().to_array(),
+            max_relative=1e-12,
+            epsilon=1e-14,
+        );
+
+        for parameter in ["positions", "cell"] {
+            let native = native.gradient(parameter).unwrap();
+            let converted = converted.gradient(parameter).unwrap();
+
+            assert_eq!(native.samples(), converted.samples());
+            assert_relative_eq!(
+                native.values().to_array(),
+                converted.values().to_array(),
+                max_relative=1e-12,
+                epsilon=1e-14,
+            );
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use rascaline::systems::{System, SimpleSystem, UnitCell};
+use rascaline::{Vector3D, Calculator};
+
+#[test]
+fn neighbors_list_and_compute_timings() {
+    let mut system = SimpleSystem::new(UnitCell::cubic(10.0));
+    system.add_atom(8, Vector3D::new(1.0, 1.0, 1.0));
+    system.add_atom(1, Vector3D::new(1.8, 1.5, 1.0));
+    system.add_atom(1, Vector3D::new(0.4, 1.7, 1.2));
+
+    let mut systems = vec![Box::new(system) as Box<dyn System>];
+
+    let parameters = r#"{
+        "cutoff": 3.0,
+        "max_radial": 4,
+        "max_angular": 3,
+        "atomic_gaussian_width": 0.3,
+        "center_atom_weight": 1.0,
+        "radial_basis": {
+            "Gto": {}
+        },
+        "cutoff_function": {
+            "ShiftedCosine": {"width": 0.5}
+        }
+    }"#;
+    let mut calculator = Calculator::new("spherical_expansion", parameters.to_owned()).unwrap();
+
+    rascaline::profiling::enable(true);
+    rascaline::profiling::clear();
+
+    calculator.compute(&mut systems, Default::default()).unwrap();
+
+    let timings = rascaline::profiling::get();
+
+    let neighbors_list = timings.get(rascaline::profiling::NEIGHBORS_LIST).expect("missing neighbors list timing");
+    assert!(neighbors_list.called > 0);
+    assert!(neighbors_list.elapsed.as_nanos() > 0);
+
+    let compute = timings.get(rascaline::profiling::COMPUTE).expect("missing compute timing");
+    assert!(compute.called > 0);
+    assert!(compute.elapsed.as_nanos() > 0);
+}